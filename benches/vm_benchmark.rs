@@ -4,7 +4,10 @@ extern crate criterion;
 use bytes::Bytes;
 #[cfg(has_asm)]
 use ckb_vm::machine::{aot::AotCompilingMachine, asm::AsmMachine};
-use ckb_vm::{run, SparseMemory};
+use ckb_vm::{
+    machine::trace::TRACE_ITEM_LENGTH, memory::FLAG_EXECUTABLE, run, CoreMachine,
+    DefaultCoreMachine, DefaultMachine, Memory, SparseMemory, TraceMachine, WXorXMemory,
+};
 use criterion::Criterion;
 use std::fs::File;
 use std::io::Read;
@@ -26,6 +29,87 @@ fn interpret_benchmark(c: &mut Criterion) {
     });
 }
 
+// A loop storing to a data page 1000 times, which exits with the exit
+// syscall afterwards.
+const STORE_LOOP_PROGRAM: [u32; 9] = [
+    0x00000513, // li a0, 0
+    0x3e800593, // li a1, 1000
+    0x00002637, // lui a2, 0x2
+    0x00a63023, // sd a0, 0(a2)
+    0x00150513, // addi a0, a0, 1
+    0xfff58593, // addi a1, a1, -1
+    0xfe059ae3, // bnez a1, -12
+    0x05d00893, // li a7, 93
+    0x00000073, // ecall
+];
+
+// Self modifying code only drops cached traces when code is overwritten,
+// so stores to data pages should cost about the same with it allowed.
+fn store_benchmark(c: &mut Criterion) {
+    let code: Vec<u8> = STORE_LOOP_PROGRAM
+        .iter()
+        .flat_map(|word| word.to_le_bytes().to_vec())
+        .collect();
+    let code = Bytes::from(code);
+    for (name, self_modifying) in [
+        ("interpret store loop", false),
+        ("interpret store loop with self modifying code", true),
+    ] {
+        c.bench_function(name, |b| {
+            b.iter(|| {
+                let mut machine = TraceMachine::new(DefaultMachine::<
+                    DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>,
+                >::default());
+                machine
+                    .memory_mut()
+                    .set_allow_self_modifying_code(self_modifying);
+                machine
+                    .memory_mut()
+                    .init_pages(0x1000, 0x1000, FLAG_EXECUTABLE, Some(code.clone()), 0)
+                    .unwrap();
+                machine.set_pc(0x1000);
+                machine.run().unwrap()
+            });
+        });
+    }
+}
+
+// Small trace caches make hot blocks alias to the same slots, which a set
+// associative cache should cope with better than the direct mapped one.
+fn trace_cache_benchmark(c: &mut Criterion) {
+    let mut file = File::open("benches/data/secp256k1_bench").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+
+    let buffer = Bytes::from(buffer);
+    let args: Vec<Bytes> = vec!["secp256k1_bench",
+                                  "033f8cf9c4d51a33206a6c1c6b27d2cc5129daa19dbd1fc148d395284f6b26411f",
+                                  "304402203679d909f43f073c7c1dcf8468a485090589079ee834e6eed92fea9b09b06a2402201e46f1075afa18f306715e7db87493e7b7e779569aa13c64ab3d09980b3560a3",
+                                  "foo",
+                                  "bar"].into_iter().map(|a| a.into()).collect();
+    for (trace_size, ways) in [(8192, 1), (8192, 4), (256, 1), (256, 4)] {
+        let name = format!(
+            "trace secp256k1_bench with {} trace items, {} way",
+            trace_size, ways
+        );
+        c.bench_function(&name, |b| {
+            b.iter(|| {
+                let mut machine =
+                    TraceMachine::with_capacity(
+                        DefaultMachine::<
+                            DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>,
+                        >::default(),
+                        trace_size,
+                        TRACE_ITEM_LENGTH,
+                    );
+                machine.set_trace_ways(ways);
+                machine.load_program(&buffer, &args[..]).unwrap();
+                machine.run().unwrap()
+            });
+        });
+    }
+}
+
 #[cfg(has_asm)]
 fn asm_benchmark(c: &mut Criterion) {
     c.bench_function("interpret secp256k1_bench via assembly", |b| {
@@ -91,12 +175,19 @@ fn aot_compiling_benchmark(c: &mut Criterion) {
 }
 
 #[cfg(not(has_asm))]
-criterion_group!(benches, interpret_benchmark,);
+criterion_group!(
+    benches,
+    interpret_benchmark,
+    store_benchmark,
+    trace_cache_benchmark
+);
 
 #[cfg(has_asm)]
 criterion_group!(
     benches,
     interpret_benchmark,
+    store_benchmark,
+    trace_cache_benchmark,
     asm_benchmark,
     aot_benchmark,
     aot_compiling_benchmark
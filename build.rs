@@ -11,6 +11,17 @@ fn main() {
     let is_windows = target_family == "windows";
     let is_unix = target_family == "unix";
     let can_enable_asm = (target_pointer_width == "64") && (is_windows || is_unix);
+    let target_arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap();
+
+    // cfgs set below, declared even when they end up unset
+    println!("cargo:rustc-check-cfg=cfg(has_asm)");
+    println!("cargo:rustc-check-cfg=cfg(has_jit)");
+
+    // The JIT tier emits x86-64 code following the System V calling
+    // convention, on other platforms TraceMachine stays interpreted.
+    if cfg!(feature = "jit") && target_arch == "x86_64" && is_unix {
+        println!("cargo:rustc-cfg=has_jit")
+    }
 
     if cfg!(feature = "asm") && (!can_enable_asm) {
         panic!("asm feature can only be enabled on 64-bit Linux, macOS and Windows platforms!");
@@ -1,7 +1,8 @@
 use crate::{
     instructions::Instruction, RISCV_GENERAL_REGISTER_NUMBER, RISCV_MAX_MEMORY, RISCV_PAGES,
 };
-use std::alloc::{alloc_zeroed, Layout};
+use alloc::alloc::{alloc_zeroed, Layout};
+use alloc::boxed::Box;
 
 // The number of trace items to keep
 pub const TRACE_SIZE: usize = 8192;
@@ -14,6 +15,7 @@ pub const RET_DYNAMIC_JUMP: u8 = 4;
 pub const RET_MAX_CYCLES_EXCEEDED: u8 = 5;
 pub const RET_OUT_OF_BOUND: u8 = 6;
 pub const RET_INVALID_PERMISSION: u8 = 7;
+pub const RET_FENCEI: u8 = 8;
 
 #[inline(always)]
 pub fn calculate_slot(addr: u64) -> usize {
@@ -65,7 +67,12 @@ impl AsmCoreMachine {
             let raw_allocation = alloc_zeroed(layout) as *mut AsmCoreMachine;
             Box::from_raw(raw_allocation)
         };
-        machine.max_cycles = max_cycles;
+        // A max cycles value of 0 means there is no limit on cycles.
+        machine.max_cycles = if max_cycles > 0 {
+            max_cycles
+        } else {
+            u64::max_value()
+        };
         machine
     }
 }
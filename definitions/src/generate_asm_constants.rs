@@ -1,7 +1,8 @@
 use ckb_vm_definitions::{
     asm::{
         AsmCoreMachine, Trace, RET_DECODE_TRACE, RET_DYNAMIC_JUMP, RET_EBREAK, RET_ECALL,
-        RET_INVALID_PERMISSION, RET_MAX_CYCLES_EXCEEDED, RET_OUT_OF_BOUND, TRACE_ITEM_LENGTH,
+        RET_FENCEI, RET_INVALID_PERMISSION, RET_MAX_CYCLES_EXCEEDED, RET_OUT_OF_BOUND,
+        TRACE_ITEM_LENGTH,
     },
     instructions::{Instruction, INSTRUCTION_OPCODE_NAMES},
     memory::{FLAG_EXECUTABLE, FLAG_FREEZED, FLAG_WRITABLE, FLAG_WXORX_BIT},
@@ -44,6 +45,7 @@ fn main() {
         "#define CKB_VM_ASM_RET_INVALID_PERMISSION {}",
         RET_INVALID_PERMISSION
     );
+    println!("#define CKB_VM_ASM_RET_FENCEI {}", RET_FENCEI);
     println!();
 
     println!("#define CKB_VM_ASM_REGISTER_SP {}", SP);
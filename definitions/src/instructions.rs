@@ -128,11 +128,167 @@ pub const OP_RVC_XOR: InstructionOpcode = 102;
 pub const OP_CUSTOM_LOAD_IMM: InstructionOpcode = 103;
 pub const OP_ADDI: InstructionOpcode = 104;
 pub const OP_CUSTOM_TRACE_END: InstructionOpcode = 105;
+// B extension(Zba, Zbb, Zbc and Zbs) instructions
+pub const OP_ADDUW: InstructionOpcode = 106;
+pub const OP_ANDN: InstructionOpcode = 107;
+pub const OP_BCLR: InstructionOpcode = 108;
+pub const OP_BCLRI: InstructionOpcode = 109;
+pub const OP_BEXT: InstructionOpcode = 110;
+pub const OP_BEXTI: InstructionOpcode = 111;
+pub const OP_BINV: InstructionOpcode = 112;
+pub const OP_BINVI: InstructionOpcode = 113;
+pub const OP_BSET: InstructionOpcode = 114;
+pub const OP_BSETI: InstructionOpcode = 115;
+pub const OP_CLMUL: InstructionOpcode = 116;
+pub const OP_CLMULH: InstructionOpcode = 117;
+pub const OP_CLMULR: InstructionOpcode = 118;
+pub const OP_CLZ: InstructionOpcode = 119;
+pub const OP_CLZW: InstructionOpcode = 120;
+pub const OP_CPOP: InstructionOpcode = 121;
+pub const OP_CPOPW: InstructionOpcode = 122;
+pub const OP_CTZ: InstructionOpcode = 123;
+pub const OP_CTZW: InstructionOpcode = 124;
+pub const OP_MAX: InstructionOpcode = 125;
+pub const OP_MAXU: InstructionOpcode = 126;
+pub const OP_MIN: InstructionOpcode = 127;
+pub const OP_MINU: InstructionOpcode = 128;
+pub const OP_ORCB: InstructionOpcode = 129;
+pub const OP_ORN: InstructionOpcode = 130;
+pub const OP_REV8: InstructionOpcode = 131;
+pub const OP_ROL: InstructionOpcode = 132;
+pub const OP_ROLW: InstructionOpcode = 133;
+pub const OP_ROR: InstructionOpcode = 134;
+pub const OP_RORI: InstructionOpcode = 135;
+pub const OP_RORIW: InstructionOpcode = 136;
+pub const OP_RORW: InstructionOpcode = 137;
+pub const OP_SEXTB: InstructionOpcode = 138;
+pub const OP_SEXTH: InstructionOpcode = 139;
+pub const OP_SH1ADD: InstructionOpcode = 140;
+pub const OP_SH1ADDUW: InstructionOpcode = 141;
+pub const OP_SH2ADD: InstructionOpcode = 142;
+pub const OP_SH2ADDUW: InstructionOpcode = 143;
+pub const OP_SH3ADD: InstructionOpcode = 144;
+pub const OP_SH3ADDUW: InstructionOpcode = 145;
+pub const OP_SLLIUW: InstructionOpcode = 146;
+pub const OP_XNOR: InstructionOpcode = 147;
+pub const OP_ZEXTH: InstructionOpcode = 148;
+// F extension instructions
+pub const OP_FLW: InstructionOpcode = 149;
+pub const OP_FSW: InstructionOpcode = 150;
+pub const OP_FMADDS: InstructionOpcode = 151;
+pub const OP_FMSUBS: InstructionOpcode = 152;
+pub const OP_FNMSUBS: InstructionOpcode = 153;
+pub const OP_FNMADDS: InstructionOpcode = 154;
+pub const OP_FADDS: InstructionOpcode = 155;
+pub const OP_FSUBS: InstructionOpcode = 156;
+pub const OP_FMULS: InstructionOpcode = 157;
+pub const OP_FDIVS: InstructionOpcode = 158;
+pub const OP_FSQRTS: InstructionOpcode = 159;
+pub const OP_FSGNJS: InstructionOpcode = 160;
+pub const OP_FSGNJNS: InstructionOpcode = 161;
+pub const OP_FSGNJXS: InstructionOpcode = 162;
+pub const OP_FMINS: InstructionOpcode = 163;
+pub const OP_FMAXS: InstructionOpcode = 164;
+pub const OP_FCVTWS: InstructionOpcode = 165;
+pub const OP_FCVTWUS: InstructionOpcode = 166;
+pub const OP_FMVXW: InstructionOpcode = 167;
+pub const OP_FEQS: InstructionOpcode = 168;
+pub const OP_FLTS: InstructionOpcode = 169;
+pub const OP_FLES: InstructionOpcode = 170;
+pub const OP_FCLASSS: InstructionOpcode = 171;
+pub const OP_FCVTSW: InstructionOpcode = 172;
+pub const OP_FCVTSWU: InstructionOpcode = 173;
+pub const OP_FMVWX: InstructionOpcode = 174;
+pub const OP_FCVTLS: InstructionOpcode = 175;
+pub const OP_FCVTLUS: InstructionOpcode = 176;
+pub const OP_FCVTSL: InstructionOpcode = 177;
+pub const OP_FCVTSLU: InstructionOpcode = 178;
+// D extension instructions
+pub const OP_FLD: InstructionOpcode = 179;
+pub const OP_FSD: InstructionOpcode = 180;
+pub const OP_FMADDD: InstructionOpcode = 181;
+pub const OP_FMSUBD: InstructionOpcode = 182;
+pub const OP_FNMSUBD: InstructionOpcode = 183;
+pub const OP_FNMADDD: InstructionOpcode = 184;
+pub const OP_FADDD: InstructionOpcode = 185;
+pub const OP_FSUBD: InstructionOpcode = 186;
+pub const OP_FMULD: InstructionOpcode = 187;
+pub const OP_FDIVD: InstructionOpcode = 188;
+pub const OP_FSQRTD: InstructionOpcode = 189;
+pub const OP_FSGNJD: InstructionOpcode = 190;
+pub const OP_FSGNJND: InstructionOpcode = 191;
+pub const OP_FSGNJXD: InstructionOpcode = 192;
+pub const OP_FMIND: InstructionOpcode = 193;
+pub const OP_FMAXD: InstructionOpcode = 194;
+pub const OP_FCVTSD: InstructionOpcode = 195;
+pub const OP_FCVTDS: InstructionOpcode = 196;
+pub const OP_FEQD: InstructionOpcode = 197;
+pub const OP_FLTD: InstructionOpcode = 198;
+pub const OP_FLED: InstructionOpcode = 199;
+pub const OP_FCLASSD: InstructionOpcode = 200;
+pub const OP_FCVTWD: InstructionOpcode = 201;
+pub const OP_FCVTWUD: InstructionOpcode = 202;
+pub const OP_FCVTDW: InstructionOpcode = 203;
+pub const OP_FCVTDWU: InstructionOpcode = 204;
+pub const OP_FCVTLD: InstructionOpcode = 205;
+pub const OP_FCVTLUD: InstructionOpcode = 206;
+pub const OP_FMVXD: InstructionOpcode = 207;
+pub const OP_FCVTDL: InstructionOpcode = 208;
+pub const OP_FCVTDLU: InstructionOpcode = 209;
+pub const OP_FMVDX: InstructionOpcode = 210;
+// Zicsr instructions, only floating point CSRs are accessible
+pub const OP_CSRRW: InstructionOpcode = 211;
+pub const OP_CSRRS: InstructionOpcode = 212;
+pub const OP_CSRRC: InstructionOpcode = 213;
+pub const OP_CSRRWI: InstructionOpcode = 214;
+pub const OP_CSRRSI: InstructionOpcode = 215;
+pub const OP_CSRRCI: InstructionOpcode = 216;
+// Compressed floating point loads and stores
+pub const OP_RVC_FLD: InstructionOpcode = 217;
+pub const OP_RVC_FSD: InstructionOpcode = 218;
+pub const OP_RVC_FLDSP: InstructionOpcode = 219;
+pub const OP_RVC_FSDSP: InstructionOpcode = 220;
+// A extension instructions, D variants are only available on RV64
+pub const OP_LR_W: InstructionOpcode = 221;
+pub const OP_SC_W: InstructionOpcode = 222;
+pub const OP_AMOSWAP_W: InstructionOpcode = 223;
+pub const OP_AMOADD_W: InstructionOpcode = 224;
+pub const OP_AMOXOR_W: InstructionOpcode = 225;
+pub const OP_AMOAND_W: InstructionOpcode = 226;
+pub const OP_AMOOR_W: InstructionOpcode = 227;
+pub const OP_AMOMIN_W: InstructionOpcode = 228;
+pub const OP_AMOMAX_W: InstructionOpcode = 229;
+pub const OP_AMOMINU_W: InstructionOpcode = 230;
+pub const OP_AMOMAXU_W: InstructionOpcode = 231;
+pub const OP_LR_D: InstructionOpcode = 232;
+pub const OP_SC_D: InstructionOpcode = 233;
+pub const OP_AMOSWAP_D: InstructionOpcode = 234;
+pub const OP_AMOADD_D: InstructionOpcode = 235;
+pub const OP_AMOXOR_D: InstructionOpcode = 236;
+pub const OP_AMOAND_D: InstructionOpcode = 237;
+pub const OP_AMOOR_D: InstructionOpcode = 238;
+pub const OP_AMOMIN_D: InstructionOpcode = 239;
+pub const OP_AMOMAX_D: InstructionOpcode = 240;
+pub const OP_AMOMINU_D: InstructionOpcode = 241;
+pub const OP_AMOMAXU_D: InstructionOpcode = 242;
+// V extension subset, see instructions/v.rs for how operands are packed
+pub const OP_VSETVLI: InstructionOpcode = 243;
+pub const OP_VSETIVLI: InstructionOpcode = 244;
+pub const OP_VSETVL: InstructionOpcode = 245;
+pub const OP_VLE: InstructionOpcode = 246;
+pub const OP_VSE: InstructionOpcode = 247;
+pub const OP_VADD: InstructionOpcode = 248;
+pub const OP_VSUB: InstructionOpcode = 249;
+pub const OP_VMUL: InstructionOpcode = 250;
+pub const OP_VAND: InstructionOpcode = 251;
+pub const OP_VOR: InstructionOpcode = 252;
+pub const OP_VXOR: InstructionOpcode = 253;
+pub const OP_VMV: InstructionOpcode = 254;
+// Instructions decoded by factories supplied by embedders, see
+// instructions/custom.rs in ckb-vm
+pub const OP_CUSTOM: InstructionOpcode = 255;
 
-// Maximum opcode for instructions consuming 4 bytes. Any opcode
-// larger than this one is treated as RVC instructions(which consume
-// 2 bytes)
-pub const MAXIMUM_OPCODE: InstructionOpcode = OP_CUSTOM_TRACE_END;
+pub const MAXIMUM_OPCODE: InstructionOpcode = OP_CUSTOM;
 
 pub const MINIMAL_RVC_OPCODE: InstructionOpcode = OP_RVC_ADD;
 pub const MAXIMUM_RVC_OPCODE: InstructionOpcode = OP_RVC_XOR;
@@ -161,4 +317,27 @@ pub const INSTRUCTION_OPCODE_NAMES: [&str; MAXIMUM_OPCODE as usize + 1] = [
     "RVC_SLLI", "RVC_SLLI64", "RVC_SRAI", "RVC_SRAI64", "RVC_SRLI", "RVC_SRLI64",
     "RVC_SUB", "RVC_SUBW", "RVC_SW", "RVC_SWSP", "RVC_XOR",
     "CUSTOM_LOAD_IMM", "ADDI", "CUSTOM_TRACE_END",
+    "ADDUW", "ANDN", "BCLR", "BCLRI", "BEXT", "BEXTI", "BINV", "BINVI", "BSET", "BSETI",
+    "CLMUL", "CLMULH", "CLMULR", "CLZ", "CLZW", "CPOP", "CPOPW", "CTZ", "CTZW",
+    "MAX", "MAXU", "MIN", "MINU", "ORCB", "ORN", "REV8",
+    "ROL", "ROLW", "ROR", "RORI", "RORIW", "RORW", "SEXTB", "SEXTH",
+    "SH1ADD", "SH1ADDUW", "SH2ADD", "SH2ADDUW", "SH3ADD", "SH3ADDUW", "SLLIUW",
+    "XNOR", "ZEXTH",
+    "FLW", "FSW", "FMADDS", "FMSUBS", "FNMSUBS", "FNMADDS", "FADDS", "FSUBS",
+    "FMULS", "FDIVS", "FSQRTS", "FSGNJS", "FSGNJNS", "FSGNJXS", "FMINS", "FMAXS",
+    "FCVTWS", "FCVTWUS", "FMVXW", "FEQS", "FLTS", "FLES", "FCLASSS", "FCVTSW",
+    "FCVTSWU", "FMVWX", "FCVTLS", "FCVTLUS", "FCVTSL", "FCVTSLU",
+    "FLD", "FSD", "FMADDD", "FMSUBD", "FNMSUBD", "FNMADDD", "FADDD", "FSUBD",
+    "FMULD", "FDIVD", "FSQRTD", "FSGNJD", "FSGNJND", "FSGNJXD", "FMIND", "FMAXD",
+    "FCVTSD", "FCVTDS", "FEQD", "FLTD", "FLED", "FCLASSD", "FCVTWD", "FCVTWUD",
+    "FCVTDW", "FCVTDWU", "FCVTLD", "FCVTLUD", "FMVXD", "FCVTDL", "FCVTDLU", "FMVDX",
+    "CSRRW", "CSRRS", "CSRRC", "CSRRWI", "CSRRSI", "CSRRCI",
+    "RVC_FLD", "RVC_FSD", "RVC_FLDSP", "RVC_FSDSP",
+    "LR_W", "SC_W", "AMOSWAP_W", "AMOADD_W", "AMOXOR_W", "AMOAND_W",
+    "AMOOR_W", "AMOMIN_W", "AMOMAX_W", "AMOMINU_W", "AMOMAXU_W",
+    "LR_D", "SC_D", "AMOSWAP_D", "AMOADD_D", "AMOXOR_D", "AMOAND_D",
+    "AMOOR_D", "AMOMIN_D", "AMOMAX_D", "AMOMINU_D", "AMOMAXU_D",
+    "VSETVLI", "VSETIVLI", "VSETVL", "VLE", "VSE",
+    "VADD", "VSUB", "VMUL", "VAND", "VOR", "VXOR", "VMV",
+    "CUSTOM",
 ];
@@ -0,0 +1,88 @@
+#ifndef CKB_VM_H
+#define CKB_VM_H
+
+/*
+ * C API of CKB VM, available when the crate is built with the ffi feature.
+ * See src/ffi.rs for details on each function.
+ */
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define CKB_VM_OK 0
+#define CKB_VM_ERROR_INVALID_ARGUMENT -1
+#define CKB_VM_ERROR_PARSE -2
+#define CKB_VM_ERROR_UNALIGNED -3
+#define CKB_VM_ERROR_OUT_OF_BOUND -4
+#define CKB_VM_ERROR_INVALID_CYCLES -5
+#define CKB_VM_ERROR_CYCLES_EXCEEDED -6
+#define CKB_VM_ERROR_INVALID_INSTRUCTION -7
+#define CKB_VM_ERROR_INVALID_ECALL -8
+#define CKB_VM_ERROR_INVALID_ELF_BITS -9
+#define CKB_VM_ERROR_INVALID_OP -10
+#define CKB_VM_ERROR_IO -11
+#define CKB_VM_ERROR_DYNASM -12
+#define CKB_VM_ERROR_ASM -13
+#define CKB_VM_ERROR_LIMIT_REACHED -14
+#define CKB_VM_ERROR_INVALID_PERMISSION -15
+#define CKB_VM_ERROR_MEMORY_PROTECTION -16
+#define CKB_VM_ERROR_INVALID_MEMORY_SIZE -17
+#define CKB_VM_ERROR_MEMORY_SIZE_EXCEEDED -18
+#define CKB_VM_ERROR_WATCHPOINT -19
+#define CKB_VM_ERROR_INVALID_SNAPSHOT -20
+#define CKB_VM_ERROR_UNEXPECTED -21
+#define CKB_VM_ERROR_UNIMPLEMENTED -22
+#define CKB_VM_ERROR_TIMEOUT -23
+#define CKB_VM_ERROR_WRITE_TO_EXECUTABLE_MEMORY -24
+#define CKB_VM_ERROR_INSTRUCTION_LIMIT_EXCEEDED -25
+#define CKB_VM_ERROR_STACK_OVERFLOW -26
+#define CKB_VM_ERROR_ELF_SEGMENT -27
+#define CKB_VM_ERROR_PRELOAD_OVERLAP -28
+#define CKB_VM_ERROR_WOULD_BLOCK -29
+#define CKB_VM_ERROR_INVALID_HEAP_ACCESS -30
+#define CKB_VM_ERROR_DEADLINE -31
+#define CKB_VM_ERROR_REPLAY_DIVERGENCE -32
+#define CKB_VM_ERROR_CALL_EXITED -33
+#define CKB_VM_ERROR_UNKNOWN_SYMBOL -34
+#define CKB_VM_ERROR_CALL_DEPTH_EXCEEDED -35
+#define CKB_VM_ERROR_INTERRUPTED -36
+#define CKB_VM_ERROR_PAGE_FAULT -37
+#define CKB_VM_ERROR_UNSUPPORTED_ISA -38
+
+typedef struct ckb_vm_machine ckb_vm_machine;
+/* Machine state passed to syscall callbacks */
+typedef struct ckb_vm_core ckb_vm_core;
+
+/* Returns 0 on success, any other value fails the syscall */
+typedef int32_t (*ckb_vm_syscall_callback)(void *data, ckb_vm_core *core);
+
+ckb_vm_machine *ckb_vm_create(uint64_t max_cycles);
+void ckb_vm_destroy(ckb_vm_machine *machine);
+
+int32_t ckb_vm_load_program(ckb_vm_machine *machine, const uint8_t *program,
+                            size_t program_len, size_t argc,
+                            const uint8_t *const *args,
+                            const size_t *arg_lens);
+int32_t ckb_vm_run(ckb_vm_machine *machine, int8_t *exit_code);
+uint64_t ckb_vm_cycles(const ckb_vm_machine *machine);
+
+int32_t ckb_vm_register_syscall(ckb_vm_machine *machine, uint64_t number,
+                                ckb_vm_syscall_callback callback, void *data);
+
+int32_t ckb_vm_get_register(const ckb_vm_core *core, size_t index,
+                            uint64_t *value);
+int32_t ckb_vm_set_register(ckb_vm_core *core, size_t index, uint64_t value);
+int32_t ckb_vm_load_memory(ckb_vm_core *core, uint64_t addr, uint8_t *buffer,
+                           size_t len);
+int32_t ckb_vm_store_memory(ckb_vm_core *core, uint64_t addr,
+                            const uint8_t *buffer, size_t len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* CKB_VM_H */
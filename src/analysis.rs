@@ -0,0 +1,297 @@
+// Static checks on programs before they are run, so hosts can reject
+// scripts a machine cannot execute with a clear message instead of having
+// them fail halfway through a run.
+use super::decoder::Decoder;
+use super::instructions::{
+    extract_opcode, instruction_length, Instruction, Itype, Register, Stype, Utype,
+};
+use super::machine::{convert_flags, elf_bits};
+use super::memory::FLAG_EXECUTABLE;
+use super::Error;
+use ckb_vm_definitions::instructions as insts;
+
+use alloc::{collections::BTreeSet, vec, vec::Vec};
+use bytes::Bytes;
+use core::fmt;
+use goblin::elf::program_header::PT_LOAD;
+use goblin::elf::section_header::{SHF_EXECINSTR, SHT_NOBITS};
+use goblin::elf::Elf;
+
+/// What scan_program found out about a program.
+#[derive(Debug, PartialEq, Clone, Eq, Default)]
+pub struct Report {
+    pub entry: u64,
+    // Instructions reached from the entry through direct control flow
+    pub instructions: u64,
+    // Address and raw bits of code reached from the entry that the decoder
+    // rejects, compressed instructions only use the lower 16 bits. Running
+    // the program down to any of them fails.
+    pub unsupported: Vec<(u64, u32)>,
+    // Executable ranges as (start, end) that direct control flow from the
+    // entry never reaches, only indirect jumps such as calls through
+    // function pointers can get there. Padding between functions shows up
+    // here as well.
+    pub indirect_only: Vec<(u64, u64)>,
+    // Same as unsupported for indirect_only ranges, which are swept
+    // linearly. Entries might be data embedded in code rather than
+    // instructions.
+    pub unsupported_indirect: Vec<(u64, u32)>,
+}
+
+impl Report {
+    // Tells if every instruction direct control flow can reach is supported
+    pub fn is_supported(&self) -> bool {
+        self.unsupported.is_empty()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "entry {:#x}, {} reachable instructions",
+            self.entry, self.instructions
+        )?;
+        for (address, bits) in &self.unsupported {
+            write!(f, "\nunsupported instruction {:#x} at {:#x}", bits, address)?;
+        }
+        for (address, bits) in &self.unsupported_indirect {
+            write!(
+                f,
+                "\nunsupported instruction {:#x} at {:#x}, only reachable by indirect jumps",
+                bits, address
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// Executable code of a program as (address, code) pairs
+struct Code<'a> {
+    segments: Vec<(u64, &'a [u8])>,
+}
+
+impl<'a> Code<'a> {
+    // Raw bits and length of the instruction at address, None outside of
+    // executable segments. Truncated instructions have a length of 0.
+    fn fetch(&self, address: u64) -> Option<(u32, usize)> {
+        let (start, code) = self
+            .segments
+            .iter()
+            .find(|(start, code)| address >= *start && address - start < code.len() as u64)?;
+        let code = &code[(address - start) as usize..];
+        if code.len() < 2 {
+            return Some((u32::from(code[0]), 0));
+        }
+        let low = u32::from(u16::from_le_bytes([code[0], code[1]]));
+        if low & 0x3 != 0x3 {
+            return Some((low, 2));
+        }
+        if code.len() < 4 {
+            return Some((low, 0));
+        }
+        Some((
+            low | (u32::from(u16::from_le_bytes([code[2], code[3]])) << 16),
+            4,
+        ))
+    }
+}
+
+// Code ranges as (address, file offset, size), executable sections when
+// the program has any, so headers and constants sharing the executable
+// segment are left out, or executable segments otherwise.
+fn code_ranges(elf: &Elf) -> Result<Vec<(u64, u64, u64)>, Error> {
+    let sections: Vec<(u64, u64, u64)> = elf
+        .section_headers
+        .iter()
+        .filter(|header| {
+            header.sh_flags & u64::from(SHF_EXECINSTR) != 0 && header.sh_type != SHT_NOBITS
+        })
+        .map(|header| (header.sh_addr, header.sh_offset, header.sh_size))
+        .collect();
+    if !sections.is_empty() {
+        return Ok(sections);
+    }
+    let mut segments = Vec::new();
+    for program_header in &elf.program_headers {
+        if program_header.p_type == PT_LOAD
+            && convert_flags(program_header.p_flags)? & FLAG_EXECUTABLE != 0
+        {
+            segments.push((
+                program_header.p_vaddr,
+                program_header.p_offset,
+                program_header.p_filesz,
+            ));
+        }
+    }
+    Ok(segments)
+}
+
+// Decodes the executable code of program with decoder, which should be the
+// decoder machines run the program with. Code is followed from the entry
+// through fall through, branches and direct jumps, including far calls
+// built from auipc and jalr. Indirect jumps are assumed to return to the
+// next instruction when they link. Position independent programs are
+// analyzed at the addresses they are linked to.
+pub fn scan_program<R: Register>(program: &Bytes, decoder: &Decoder) -> Result<Report, Error> {
+    let elf = Elf::parse(program).map_err(|_e| Error::ParseError)?;
+    let bits = elf_bits(&elf.header).ok_or(Error::InvalidElfBits)?;
+    if bits != R::BITS {
+        return Err(Error::InvalidElfBits);
+    }
+    let mut code = Code {
+        segments: Vec::new(),
+    };
+    for (address, offset, size) in code_ranges(&elf)? {
+        let start = offset as usize;
+        let end = offset.checked_add(size).ok_or(Error::OutOfBound)? as usize;
+        if start > end || end > program.len() {
+            return Err(Error::OutOfBound);
+        }
+        if start < end {
+            code.segments.push((address, &program[start..end]));
+        }
+    }
+    code.segments.sort_by_key(|(start, _)| *start);
+
+    let mask = if R::BITS == 32 {
+        u64::from(u32::MAX)
+    } else {
+        u64::MAX
+    };
+    let mut report = Report {
+        entry: elf.header.e_entry,
+        ..Report::default()
+    };
+    // Start and end of each instruction reached
+    let mut reached: BTreeSet<(u64, u64)> = BTreeSet::new();
+    let mut visited: BTreeSet<u64> = BTreeSet::new();
+    // Address to visit and the register set by an auipc right before it
+    let mut pending: Vec<(u64, Option<(usize, u64)>)> = Vec::new();
+    pending.push((report.entry, None));
+    while let Some((address, auipc)) = pending.pop() {
+        if !visited.insert(address) {
+            continue;
+        }
+        let (bits, length) = match code.fetch(address) {
+            Some(fetched) => fetched,
+            None => continue,
+        };
+        let instruction = match decoder.decode_raw(bits) {
+            Ok(instruction)
+                if length > 0 && usize::from(instruction_length(instruction)) == length =>
+            {
+                instruction
+            }
+            _ => {
+                report.unsupported.push((address, bits));
+                continue;
+            }
+        };
+        report.instructions += 1;
+        reached.insert((address, address + length as u64));
+        let next = address.wrapping_add(length as u64) & mask;
+        let relative = |offset: i32| address.wrapping_add(offset as i64 as u64) & mask;
+        for (target, auipc) in successors(instruction, next, auipc, relative, mask) {
+            pending.push((target, auipc));
+        }
+    }
+    report.unsupported.sort_unstable();
+
+    // Ranges of executable code left out, merged from the reached
+    // instructions in address order
+    for (start, segment) in &code.segments {
+        let end = start + segment.len() as u64;
+        let mut current = *start;
+        for (reached_start, reached_end) in reached.range((*start, 0)..(end, 0)) {
+            if *reached_start > current {
+                report.indirect_only.push((current, *reached_start));
+            }
+            current = current.max(*reached_end);
+        }
+        if current < end {
+            report.indirect_only.push((current, end));
+        }
+    }
+    for (start, end) in &report.indirect_only {
+        let mut address = *start;
+        while address < *end {
+            match code.fetch(address) {
+                Some((bits, length)) if length > 0 && address + length as u64 <= *end => {
+                    match decoder.decode_raw(bits) {
+                        Ok(instruction)
+                            if usize::from(instruction_length(instruction)) == length =>
+                        {
+                            address += length as u64;
+                        }
+                        _ => {
+                            report.unsupported_indirect.push((address, bits));
+                            address += 2;
+                        }
+                    }
+                }
+                _ => break,
+            }
+        }
+    }
+    Ok(report)
+}
+
+// Addresses direct control flow continues at after instruction, together
+// with the register an auipc instruction has just set.
+fn successors<F: Fn(i32) -> u64>(
+    instruction: Instruction,
+    next: u64,
+    auipc: Option<(usize, u64)>,
+    relative: F,
+    mask: u64,
+) -> Vec<(u64, Option<(usize, u64)>)> {
+    match extract_opcode(instruction) {
+        insts::OP_AUIPC => {
+            let i = Utype(instruction);
+            vec![(next, Some((i.rd(), relative(i.immediate_s()))))]
+        }
+        insts::OP_JAL => {
+            let i = Utype(instruction);
+            let target = (relative(i.immediate_s()), None);
+            if i.rd() != 0 {
+                vec![target, (next, None)]
+            } else {
+                vec![target]
+            }
+        }
+        insts::OP_RVC_J => vec![(relative(Utype(instruction).immediate_s()), None)],
+        insts::OP_RVC_JAL => vec![
+            (relative(Utype(instruction).immediate_s()), None),
+            (next, None),
+        ],
+        insts::OP_BEQ
+        | insts::OP_BNE
+        | insts::OP_BLT
+        | insts::OP_BGE
+        | insts::OP_BLTU
+        | insts::OP_BGEU
+        | insts::OP_RVC_BEQZ
+        | insts::OP_RVC_BNEZ => vec![
+            (relative(Stype(instruction).immediate_s()), None),
+            (next, None),
+        ],
+        insts::OP_JALR => {
+            let i = Itype(instruction);
+            let mut result = Vec::new();
+            if let Some((rd, value)) = auipc {
+                if rd == i.rs1() && rd != 0 {
+                    let target = value.wrapping_add(i.immediate_s() as i64 as u64) & mask & !1;
+                    result.push((target, None));
+                }
+            }
+            if i.rd() != 0 {
+                result.push((next, None));
+            }
+            result
+        }
+        insts::OP_RVC_JALR => vec![(next, None)],
+        insts::OP_RVC_JR => vec![],
+        _ => vec![(next, None)],
+    }
+}
@@ -0,0 +1,214 @@
+// Runs many independent programs on a pool of threads. Each program gets a
+// fresh DefaultMachine created on the worker thread running it, so memory
+// implementations do not need to be Send. Besides the limits of individual
+// machines, a batch can be bounded by a total cycle budget shared by all
+// machines and by a wall-clock timeout.
+use super::{
+    decoder::build_imac_decoder,
+    instructions::Instruction,
+    machine::{
+        DefaultCoreMachine, DefaultMachine, DefaultMachineBuilder, RunResult, SupportMachine,
+    },
+    memory::Memory,
+    Error, Register,
+};
+use bytes::Bytes;
+use std::cmp::min;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Machines take cycles from the shared budget in slices of this size, so
+// the budget is enforced exactly without locking for every instruction.
+const CYCLES_PER_SLICE: u64 = 1 << 20;
+// Number of instructions executed between checks of the timeout.
+const STEPS_PER_SLICE: usize = 1 << 12;
+
+/// Settings of a single machine in a batch.
+#[derive(Clone, Default)]
+pub struct MachineConfig {
+    pub args: Vec<Bytes>,
+    // Like DefaultMachineBuilder::max_cycles, 0 means no limit.
+    pub max_cycles: u64,
+    // Instructions are free when no cycle function is given, see
+    // cycle_model for the available presets.
+    pub instruction_cycle_func: Option<fn(Instruction) -> u64>,
+}
+
+/// Limits applying to a whole batch.
+#[derive(Clone, Default)]
+pub struct BatchConfig {
+    // Number of worker threads, 0 uses the available parallelism of the
+    // host.
+    pub threads: usize,
+    // Total cycles all machines can consume, 0 means no limit. Machines
+    // running out of the budget, as well as those that have not started
+    // yet, stop with ExitReason::CyclesExceeded.
+    pub max_cycles: u64,
+    // Machines still running when the timeout expires, as well as those
+    // that have not started yet, stop with Error::Timeout.
+    pub timeout: Option<Duration>,
+}
+
+// Cycles left in the budget shared by the machines of a batch, None means
+// the budget is unlimited.
+struct CyclePool {
+    remaining: Option<Mutex<u64>>,
+}
+
+impl CyclePool {
+    fn new(max_cycles: u64) -> Self {
+        Self {
+            remaining: if max_cycles > 0 {
+                Some(Mutex::new(max_cycles))
+            } else {
+                None
+            },
+        }
+    }
+
+    // Takes up to cycles from the pool, returns the cycles granted.
+    fn reserve(&self, cycles: u64) -> u64 {
+        match &self.remaining {
+            Some(remaining) => {
+                let mut remaining = remaining.lock().expect("cycle pool lock");
+                let granted = min(*remaining, cycles);
+                *remaining -= granted;
+                granted
+            }
+            None => cycles,
+        }
+    }
+
+    fn release(&self, cycles: u64) {
+        if let Some(remaining) = &self.remaining {
+            *remaining.lock().expect("cycle pool lock") += cycles;
+        }
+    }
+}
+
+fn execute<Inner: SupportMachine>(
+    machine: &mut DefaultMachine<Inner>,
+    max_cycles: u64,
+    pool: &CyclePool,
+    deadline: Option<Instant>,
+) -> Result<i8, Error> {
+    let decoder = build_imac_decoder::<Inner::REG>();
+    machine.set_running(true);
+    while machine.running() {
+        if deadline.map(|d| Instant::now() >= d).unwrap_or(false) {
+            return Err(Error::Timeout);
+        }
+        let cycles = machine.cycles();
+        let granted = pool.reserve(CYCLES_PER_SLICE);
+        let mut limit = cycles.saturating_add(granted);
+        if max_cycles > 0 {
+            limit = min(limit, max_cycles);
+        }
+        if limit == 0 {
+            // A max cycles value of 0 would lift the limit instead
+            pool.release(granted);
+            return Err(Error::CyclesExceeded);
+        }
+        machine.set_max_cycles(limit);
+        let mut result = Ok(());
+        for _ in 0..STEPS_PER_SLICE {
+            result = machine.step(&decoder).map(|_| ());
+            if !machine.running() || result.is_err() {
+                break;
+            }
+        }
+        let used = machine.cycles() - cycles;
+        pool.release(granted - min(used, granted));
+        match result {
+            // Running out of the current slice is fine as long as neither
+            // the machine limit nor the shared budget has been reached.
+            // An instruction costing more than a whole slice also stops
+            // here, since no progress can be made.
+            Err(Error::CyclesExceeded)
+                if limit != max_cycles && granted == CYCLES_PER_SLICE && used > 0 => {}
+            Err(e) => return Err(e),
+            Ok(()) => {}
+        }
+    }
+    Ok(machine.exit_code())
+}
+
+fn run_one<R: Register, M: Memory<R> + Default>(
+    program: &Bytes,
+    config: &MachineConfig,
+    pool: &CyclePool,
+    deadline: Option<Instant>,
+) -> RunResult {
+    let mut builder = DefaultMachineBuilder::new(DefaultCoreMachine::<R, M>::default());
+    if let Some(instruction_cycle_func) = config.instruction_cycle_func {
+        builder = builder.instruction_cycle_func(Box::new(instruction_cycle_func));
+    }
+    let mut machine = builder.build();
+    let result = machine
+        .load_program(program, &config.args)
+        .and_then(|_| execute(&mut machine, config.max_cycles, pool, deadline));
+    machine.set_max_cycles(config.max_cycles);
+    RunResult::new(&machine, result)
+}
+
+// Runs each program with the config at the same index and returns their
+// results in the same order. A single config can also be given to use it
+// for all programs.
+pub fn run_all<R, M>(
+    programs: &[Bytes],
+    configs: &[MachineConfig],
+    batch: &BatchConfig,
+) -> Vec<RunResult>
+where
+    R: Register + 'static,
+    M: Memory<R> + Default + 'static,
+{
+    assert!(
+        configs.len() == programs.len() || configs.len() == 1,
+        "one config per program is required"
+    );
+    let deadline = batch.timeout.map(|timeout| Instant::now() + timeout);
+    let threads = if batch.threads > 0 {
+        batch.threads
+    } else {
+        thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+    };
+    let programs: Arc<Vec<Bytes>> = Arc::new(programs.to_vec());
+    let configs: Arc<Vec<MachineConfig>> = Arc::new(configs.to_vec());
+    let pool = Arc::new(CyclePool::new(batch.max_cycles));
+    let next = Arc::new(AtomicUsize::new(0));
+    let results = Arc::new(Mutex::new(vec![None; programs.len()]));
+
+    let workers: Vec<_> = (0..min(threads, programs.len()))
+        .map(|_| {
+            let programs = Arc::clone(&programs);
+            let configs = Arc::clone(&configs);
+            let pool = Arc::clone(&pool);
+            let next = Arc::clone(&next);
+            let results = Arc::clone(&results);
+            thread::spawn(move || loop {
+                let index = next.fetch_add(1, Ordering::SeqCst);
+                if index >= programs.len() {
+                    break;
+                }
+                let config = &configs[min(index, configs.len() - 1)];
+                let result = run_one::<R, M>(&programs[index], config, &pool, deadline);
+                results.lock().expect("results lock")[index] = Some(result);
+            })
+        })
+        .collect();
+    for worker in workers {
+        if let Err(e) = worker.join() {
+            std::panic::resume_unwind(e);
+        }
+    }
+    let mut results = results.lock().expect("results lock");
+    results
+        .iter_mut()
+        .map(|result| result.take().expect("all programs are run"))
+        .collect()
+}
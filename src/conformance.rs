@@ -0,0 +1,180 @@
+// Harness running binaries built from the official riscv-tests suites, so
+// instruction set extensions can be validated against them. Those tests
+// target bare metal machines: they are linked at 0x80000000, set up the
+// machine with CSR instructions before jumping to the test body via mret,
+// and report their result either through the exit syscall or by writing to
+// the tohost symbol. The harness loads them with their addresses shifted by
+// a base, treats privileged instructions as no-ops and recognizes both
+// reporting protocols.
+use super::{
+    decoder::Decoder,
+    instructions::{i, Instruction},
+    machine::{
+        CoreMachine, DefaultCoreMachine, DefaultMachine, DefaultMachineBuilder, SupportMachine,
+    },
+    memory::{round_page_down, round_page_up, sparse::SparseMemory, Memory},
+    registers::GP,
+    Error, Register, WatchpointKind,
+};
+use bytes::Bytes;
+use goblin::elf::program_header::PT_LOAD;
+use goblin::elf::Elf;
+
+// Default address riscv-tests are linked at.
+pub const DEFAULT_BASE: u64 = 0x8000_0000;
+// Instructions a test can execute before it is considered hanging.
+pub const DEFAULT_MAX_STEPS: u64 = 1_000_000;
+
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub enum TestOutcome {
+    Pass,
+    // Number of the failing test case, TESTNUM in riscv-tests
+    Fail(u64),
+}
+
+impl TestOutcome {
+    // Both protocols encode a pass as 1, and a failure of test case n as
+    // (n << 1) | 1.
+    fn from_code(code: u64) -> Self {
+        if code == 1 {
+            TestOutcome::Pass
+        } else {
+            TestOutcome::Fail(code >> 1)
+        }
+    }
+}
+
+const MRET: u32 = 0x3020_0073;
+const SRET: u32 = 0x1020_0073;
+const WFI: u32 = 0x1050_0073;
+
+// Decodes CSR accesses, mret, sret, wfi and sfence.vma as no-ops. The
+// machine has no privileged state, so the environment setup riscv-tests
+// perform before running the test body has nothing to do.
+fn privileged_factory(instruction_bits: u32) -> Option<Instruction> {
+    if instruction_bits & 0x7F != 0b_1110011 {
+        return None;
+    }
+    let csr = (instruction_bits >> 12) & 0x7 != 0;
+    let sfence_vma = instruction_bits & 0xFE00_7FFF == 0x1200_0073;
+    if csr || sfence_vma || [MRET, SRET, WFI].contains(&instruction_bits) {
+        Some(i::nop())
+    } else {
+        None
+    }
+}
+
+pub struct ConformanceRunner {
+    decoder: Decoder,
+    base: u64,
+    max_steps: u64,
+}
+
+impl ConformanceRunner {
+    // The decoder picks the extensions to test, such as the one returned by
+    // build_imacb_decoder for B extension tests.
+    pub fn new(mut decoder: Decoder) -> Self {
+        decoder.add_instruction_factory(privileged_factory);
+        Self {
+            decoder,
+            base: DEFAULT_BASE,
+            max_steps: DEFAULT_MAX_STEPS,
+        }
+    }
+
+    // Address subtracted from all addresses in the ELF file so the test fits
+    // in the address space of the machine.
+    pub fn base(mut self, base: u64) -> Self {
+        self.base = base;
+        self
+    }
+
+    pub fn max_steps(mut self, max_steps: u64) -> Self {
+        self.max_steps = max_steps;
+        self
+    }
+
+    // Runs a single test binary, errors are only returned when the test
+    // cannot be loaded or crashes the machine before reporting a result.
+    pub fn run<R: Register>(&self, program: &Bytes) -> Result<TestOutcome, Error> {
+        let mut machine =
+            DefaultMachineBuilder::<DefaultCoreMachine<R, SparseMemory<R>>>::default()
+                .instruction_cycle_func(Box::new(|_| 1))
+                .max_cycles(self.max_steps)
+                .build();
+        let tohost = self.load(&mut machine, program)?;
+        if let Some(tohost) = tohost {
+            machine
+                .memory_mut()
+                .add_watchpoint(tohost, 8, WatchpointKind::Write)?;
+        }
+        machine.set_running(true);
+        while machine.running() {
+            match machine.step(&self.decoder) {
+                Ok(_) => (),
+                Err(Error::Watchpoint { addr, .. }) if Some(addr) == tohost => {
+                    // Lets the store through, then reads what is reported.
+                    machine
+                        .memory_mut()
+                        .remove_watchpoint(addr, 8, WatchpointKind::Write)?;
+                    machine.step(&self.decoder)?;
+                    let code = machine.memory_mut().load64(&R::from_u64(addr))?.to_u64();
+                    return Ok(TestOutcome::from_code(code));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        // Tests report through the exit syscall with TESTNUM in gp
+        Ok(TestOutcome::from_code(machine.registers()[GP].to_u64()))
+    }
+
+    // Loads the test and returns the shifted address of tohost, if the test
+    // has one.
+    fn load<R: Register>(
+        &self,
+        machine: &mut DefaultMachine<DefaultCoreMachine<R, SparseMemory<R>>>,
+        program: &Bytes,
+    ) -> Result<Option<u64>, Error> {
+        let elf = Elf::parse(program).map_err(|_e| Error::ParseError)?;
+        let bits = if elf.is_64 { 64 } else { 32 };
+        if bits != R::BITS {
+            return Err(Error::InvalidElfBits);
+        }
+        for program_header in &elf.program_headers {
+            if program_header.p_type != PT_LOAD {
+                continue;
+            }
+            let vaddr = program_header
+                .p_vaddr
+                .checked_sub(self.base)
+                .ok_or(Error::OutOfBound)?;
+            let aligned_start = round_page_down(vaddr);
+            let padding_start = vaddr - aligned_start;
+            let size = round_page_up(program_header.p_memsz + padding_start);
+            let slice_start = program_header.p_offset as usize;
+            let slice_end = slice_start + program_header.p_filesz as usize;
+            if slice_end > program.len() {
+                return Err(Error::OutOfBound);
+            }
+            machine.memory_mut().init_pages(
+                aligned_start,
+                size,
+                0,
+                Some(program.slice(slice_start, slice_end)),
+                padding_start,
+            )?;
+        }
+        let entry = elf
+            .header
+            .e_entry
+            .checked_sub(self.base)
+            .ok_or(Error::OutOfBound)?;
+        machine.set_pc(R::from_u64(entry));
+        let tohost = elf
+            .syms
+            .iter()
+            .find(|sym| elf.strtab.get_unsafe(sym.st_name) == Some("tohost"))
+            .map(|sym| sym.st_value.wrapping_sub(self.base));
+        Ok(tohost)
+    }
+}
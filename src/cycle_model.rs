@@ -0,0 +1,175 @@
+// Built-in cycle models that can be passed to
+// DefaultMachineBuilder::instruction_cycle_func. Cycles are part of
+// consensus, so a released model must never change: when costs need to be
+// adjusted, a new versioned model is added instead.
+use super::instructions::{extract_opcode, insts, Instruction};
+
+// Cycle model used by CKB since its launch in 2019. Memory accesses and
+// control flow transfers are charged more than plain ALU operations, while
+// ecall and ebreak are the most expensive since they leave the VM.
+// Instructions not listed here, including extensions added later, cost 1
+// cycle.
+pub fn ckb2019(i: Instruction) -> u64 {
+    match extract_opcode(i) {
+        insts::OP_JALR => 3,
+        insts::OP_LD => 2,
+        insts::OP_LW => 3,
+        insts::OP_LH => 3,
+        insts::OP_LB => 3,
+        insts::OP_LWU => 3,
+        insts::OP_LHU => 3,
+        insts::OP_LBU => 3,
+        insts::OP_SB => 3,
+        insts::OP_SH => 3,
+        insts::OP_SW => 3,
+        insts::OP_SD => 2,
+        insts::OP_BEQ => 3,
+        insts::OP_BGE => 3,
+        insts::OP_BGEU => 3,
+        insts::OP_BLT => 3,
+        insts::OP_BLTU => 3,
+        insts::OP_BNE => 3,
+        insts::OP_EBREAK => 500,
+        insts::OP_ECALL => 500,
+        insts::OP_JAL => 3,
+        insts::OP_MUL => 5,
+        insts::OP_MULW => 5,
+        insts::OP_MULH => 5,
+        insts::OP_MULHU => 5,
+        insts::OP_MULHSU => 5,
+        insts::OP_DIV => 32,
+        insts::OP_DIVW => 32,
+        insts::OP_DIVU => 32,
+        insts::OP_DIVUW => 32,
+        insts::OP_REM => 32,
+        insts::OP_REMW => 32,
+        insts::OP_REMU => 32,
+        insts::OP_REMUW => 32,
+        insts::OP_RVC_LW => 3,
+        insts::OP_RVC_LD => 2,
+        insts::OP_RVC_SW => 3,
+        insts::OP_RVC_SD => 2,
+        insts::OP_RVC_LWSP => 3,
+        insts::OP_RVC_LDSP => 2,
+        insts::OP_RVC_SWSP => 3,
+        insts::OP_RVC_SDSP => 2,
+        insts::OP_RVC_BEQZ => 3,
+        insts::OP_RVC_BNEZ => 3,
+        insts::OP_RVC_JAL => 3,
+        insts::OP_RVC_J => 3,
+        insts::OP_RVC_JR => 3,
+        insts::OP_RVC_JALR => 3,
+        insts::OP_RVC_EBREAK => 500,
+        _ => 1,
+    }
+}
+
+// Cycle model introduced together with the B extension in 2021. IMAC
+// instructions keep their costs from ckb2019, carry-less multiplications
+// are charged like integer multiplications.
+pub fn ckb2021(i: Instruction) -> u64 {
+    match extract_opcode(i) {
+        insts::OP_CLMUL => 5,
+        insts::OP_CLMULH => 5,
+        insts::OP_CLMULR => 5,
+        _ => ckb2019(i),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::instructions::{blank_instruction, InstructionOpcode};
+    use super::*;
+    use ckb_vm_definitions::instructions::MAXIMUM_OPCODE;
+
+    fn assert_costs(model: fn(Instruction) -> u64, costs: &[(InstructionOpcode, u64)]) {
+        for op in 0..=MAXIMUM_OPCODE {
+            let expected = costs
+                .iter()
+                .find(|(o, _)| *o == op)
+                .map(|(_, cost)| *cost)
+                .unwrap_or(1);
+            assert_eq!(
+                model(blank_instruction(op)),
+                expected,
+                "cost of {}",
+                insts::INSTRUCTION_OPCODE_NAMES[op as usize]
+            );
+        }
+    }
+
+    const CKB2019_COSTS: [(InstructionOpcode, u64); 49] = [
+        (insts::OP_JALR, 3),
+        (insts::OP_LD, 2),
+        (insts::OP_LW, 3),
+        (insts::OP_LH, 3),
+        (insts::OP_LB, 3),
+        (insts::OP_LWU, 3),
+        (insts::OP_LHU, 3),
+        (insts::OP_LBU, 3),
+        (insts::OP_SB, 3),
+        (insts::OP_SH, 3),
+        (insts::OP_SW, 3),
+        (insts::OP_SD, 2),
+        (insts::OP_BEQ, 3),
+        (insts::OP_BGE, 3),
+        (insts::OP_BGEU, 3),
+        (insts::OP_BLT, 3),
+        (insts::OP_BLTU, 3),
+        (insts::OP_BNE, 3),
+        (insts::OP_EBREAK, 500),
+        (insts::OP_ECALL, 500),
+        (insts::OP_JAL, 3),
+        (insts::OP_MUL, 5),
+        (insts::OP_MULW, 5),
+        (insts::OP_MULH, 5),
+        (insts::OP_MULHU, 5),
+        (insts::OP_MULHSU, 5),
+        (insts::OP_DIV, 32),
+        (insts::OP_DIVW, 32),
+        (insts::OP_DIVU, 32),
+        (insts::OP_DIVUW, 32),
+        (insts::OP_REM, 32),
+        (insts::OP_REMW, 32),
+        (insts::OP_REMU, 32),
+        (insts::OP_REMUW, 32),
+        (insts::OP_RVC_LW, 3),
+        (insts::OP_RVC_LD, 2),
+        (insts::OP_RVC_SW, 3),
+        (insts::OP_RVC_SD, 2),
+        (insts::OP_RVC_LWSP, 3),
+        (insts::OP_RVC_LDSP, 2),
+        (insts::OP_RVC_SWSP, 3),
+        (insts::OP_RVC_SDSP, 2),
+        (insts::OP_RVC_BEQZ, 3),
+        (insts::OP_RVC_BNEZ, 3),
+        (insts::OP_RVC_JAL, 3),
+        (insts::OP_RVC_J, 3),
+        (insts::OP_RVC_JR, 3),
+        (insts::OP_RVC_JALR, 3),
+        (insts::OP_RVC_EBREAK, 500),
+    ];
+
+    #[test]
+    fn test_ckb2019_costs() {
+        assert_costs(ckb2019, &CKB2019_COSTS);
+    }
+
+    #[test]
+    fn test_ckb2021_costs() {
+        let mut costs = CKB2019_COSTS.to_vec();
+        costs.push((insts::OP_CLMUL, 5));
+        costs.push((insts::OP_CLMULH, 5));
+        costs.push((insts::OP_CLMULR, 5));
+        assert_costs(ckb2021, &costs);
+    }
+
+    #[test]
+    fn test_costs_only_depend_on_opcode() {
+        let add = blank_instruction(insts::OP_ADD) | (0xFFFF_FFFF << 8);
+        let ecall = blank_instruction(insts::OP_ECALL) | (0xFFFF_FFFF << 8);
+        assert_eq!(ckb2019(add), 1);
+        assert_eq!(ckb2019(ecall), 500);
+        assert_eq!(ckb2021(ecall), 500);
+    }
+}
@@ -1,7 +1,18 @@
-use super::instructions::{i, m, rvc, Instruction, InstructionFactory, Register};
+#[cfg(feature = "fd")]
+use super::instructions::fd;
+#[cfg(feature = "v-ext")]
+use super::instructions::v;
+use super::instructions::{
+    a, b, custom, i, m, rvc, zicntr, Instruction, InstructionFactory, Register,
+};
 use super::memory::Memory;
 use super::Error;
 
+use alloc::vec::Vec;
+
+/// Decodes instruction bits by asking each factory in the order they were
+/// added, the first one recognizing the bits wins. Embedders can add their
+/// own factories, see instructions::custom for vendor instructions.
 #[derive(Default)]
 pub struct Decoder {
     factories: Vec<InstructionFactory>,
@@ -46,7 +57,7 @@ impl Decoder {
     // Also, due to RISC-V encoding behavior, it's totally okay when we cast a 16-bit
     // RVC instruction into a 32-bit instruction, the meaning of the instruction stays
     // unchanged in the cast conversion.
-    fn decode_bits<R: Register, M: Memory<R>>(
+    pub(crate) fn decode_bits<R: Register, M: Memory<R>>(
         &self,
         memory: &mut M,
         pc: u64,
@@ -64,6 +75,12 @@ impl Decoder {
         pc: u64,
     ) -> Result<Instruction, Error> {
         let instruction_bits = self.decode_bits(memory, pc)?;
+        self.decode_raw(instruction_bits)
+    }
+
+    // Decodes instruction bits already fetched, RVC instructions only use
+    // the lower 16 bits.
+    pub fn decode_raw(&self, instruction_bits: u32) -> Result<Instruction, Error> {
         for factory in &self.factories {
             if let Some(instruction) = factory(instruction_bits) {
                 return Ok(instruction);
@@ -80,3 +97,55 @@ pub fn build_imac_decoder<R: Register>() -> Decoder {
     decoder.add_instruction_factory(m::factory::<R>);
     decoder
 }
+
+// A extension is opt-in as well, since programs using it were rejected by
+// the IMAC decoder before. The asm machine does not support it.
+pub fn build_imac_atomic_decoder<R: Register>() -> Decoder {
+    let mut decoder = build_imac_decoder::<R>();
+    decoder.add_instruction_factory(a::factory::<R>);
+    decoder
+}
+
+// Counter CSRs are opt-in too, since programs reading them were rejected
+// by the IMAC decoder before. The asm machine does not support them, see
+// DefaultMachineBuilder::fixed_counters for running them under consensus.
+pub fn build_imac_counter_decoder<R: Register>() -> Decoder {
+    let mut decoder = build_imac_decoder::<R>();
+    decoder.add_instruction_factory(zicntr::factory::<R>);
+    decoder
+}
+
+// Custom-0 and custom-1 opcode spaces are opt-in as well, machines must
+// register handlers for custom::CUSTOM_0_ID and custom::CUSTOM_1_ID to
+// execute them, see DefaultMachineBuilder::custom_instruction.
+pub fn build_imac_custom_decoder<R: Register>() -> Decoder {
+    let mut decoder = build_imac_decoder::<R>();
+    decoder.add_instruction_factory(custom::factory::<R>);
+    decoder
+}
+
+// B extension is opt-in, use this decoder instead of the IMAC one to run
+// programs compiled for it.
+pub fn build_imacb_decoder<R: Register>() -> Decoder {
+    let mut decoder = build_imac_decoder::<R>();
+    decoder.add_instruction_factory(b::factory::<R>);
+    decoder
+}
+
+// F and D extensions are only available with the fd feature, machines must
+// provide a floating point register file to execute them.
+#[cfg(feature = "fd")]
+pub fn build_imacfd_decoder<R: Register>() -> Decoder {
+    let mut decoder = build_imac_decoder::<R>();
+    decoder.add_instruction_factory(fd::factory::<R>);
+    decoder
+}
+
+// V extension subset is only available with the v-ext feature, machines
+// must provide a vector register file to execute it.
+#[cfg(feature = "v-ext")]
+pub fn build_imacv_decoder<R: Register>() -> Decoder {
+    let mut decoder = build_imac_decoder::<R>();
+    decoder.add_instruction_factory(v::factory::<R>);
+    decoder
+}
@@ -0,0 +1,99 @@
+// Disassembles raw code without setting up a machine, so tooling can show
+// what a program is about to execute.
+use super::decoder::Decoder;
+#[cfg(feature = "fd")]
+use super::instructions::fd;
+#[cfg(feature = "v-ext")]
+use super::instructions::v;
+use super::instructions::{
+    a, b, custom, disassemble as disassemble_instruction, i, m, rvc, zicntr, Register,
+};
+
+use alloc::{format, string::String, vec::Vec};
+
+// Decoder accepting every extension the crate implements, so code is shown
+// as is even when the machine running it would reject some instructions.
+pub fn build_disassembler_decoder<R: Register>() -> Decoder {
+    let mut decoder = Decoder::default();
+    decoder.add_instruction_factory(rvc::factory::<R>);
+    decoder.add_instruction_factory(i::factory::<R>);
+    decoder.add_instruction_factory(m::factory::<R>);
+    decoder.add_instruction_factory(a::factory::<R>);
+    decoder.add_instruction_factory(b::factory::<R>);
+    decoder.add_instruction_factory(zicntr::factory::<R>);
+    decoder.add_instruction_factory(custom::factory::<R>);
+    #[cfg(feature = "fd")]
+    decoder.add_instruction_factory(fd::factory::<R>);
+    #[cfg(feature = "v-ext")]
+    decoder.add_instruction_factory(v::factory::<R>);
+    decoder
+}
+
+// Disassembles RV64 code loaded at base_addr, returning the address and
+// text of each instruction.
+pub fn disassemble(bytes: &[u8], base_addr: u64) -> Vec<(u64, String)> {
+    disassemble_with_decoder(&build_disassembler_decoder::<u64>(), bytes, base_addr)
+}
+
+// Same as disassemble but with a custom decoder, for RV32 code or to only
+// recognize the extensions of a given machine. Bits that cannot be decoded
+// are shown as .half or .word directives and a trailing odd byte as .byte,
+// so the whole input is always covered.
+pub fn disassemble_with_decoder(
+    decoder: &Decoder,
+    bytes: &[u8],
+    base_addr: u64,
+) -> Vec<(u64, String)> {
+    let mut result = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let addr = base_addr.wrapping_add(offset as u64);
+        if offset + 2 > bytes.len() {
+            result.push((addr, format!(".byte {:#04x}", bytes[offset])));
+            break;
+        }
+        let low = u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+        let (bits, length) = if low & 0x3 == 0x3 {
+            if offset + 4 > bytes.len() {
+                result.push((addr, format!(".half {:#06x}", low)));
+                offset += 2;
+                continue;
+            }
+            let high = u16::from_le_bytes([bytes[offset + 2], bytes[offset + 3]]);
+            (u32::from(low) | (u32::from(high) << 16), 4)
+        } else {
+            (u32::from(low), 2)
+        };
+        let text = match decoder.decode_raw(bits) {
+            Ok(instruction) => disassemble_instruction(instruction),
+            Err(_) if length == 2 => format!(".half {:#06x}", bits),
+            Err(_) => format!(".word {:#010x}", bits),
+        };
+        result.push((addr, text));
+        offset += length;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disassemble() {
+        let code = [
+            0x13, 0x85, 0x45, 0x00, // addi a0, a1, 4
+            0x05, 0x05, // c.addi a0, 1
+            0x2f, 0xa5, 0xc5, 0x00, // amoadd.w a0, a2, (a1)
+            0xff, 0xff, 0xff, 0xff, // invalid
+            0x73, // truncated
+        ];
+        let result = disassemble(&code, 0x1000);
+        let addrs: Vec<u64> = result.iter().map(|(addr, _)| *addr).collect();
+        assert_eq!(addrs, [0x1000, 0x1004, 0x1006, 0x100a, 0x100e]);
+        assert_eq!(result[0].1, "addi a0, a1, 4");
+        assert_eq!(result[2].1, "amoadd.w a0, a2, (a1)");
+        assert_eq!(result[3].1, ".word 0xffffffff");
+        assert_eq!(result[4].1, ".byte 0x73");
+    }
+}
@@ -1,7 +1,15 @@
+use crate::instructions::{disassemble, Instruction};
+use core::fmt;
+#[cfg(feature = "std")]
 use std::error::Error as StdError;
+#[cfg(feature = "std")]
 use std::io::{Error as IOError, ErrorKind};
 
+// Variants may be added in future versions, so matches outside of the crate
+// need a wildcard arm. Context of errors raised while running a program is
+// reported separately as a Fault.
 #[derive(Debug, PartialEq, Clone, Copy, Eq, Display)]
+#[non_exhaustive]
 pub enum Error {
     #[display(fmt = "parse error")]
     ParseError,
@@ -9,8 +17,10 @@ pub enum Error {
     Unaligned,
     #[display(fmt = "out of bound access")]
     OutOfBound,
-    #[display(fmt = "max cycles exceeded")]
+    #[display(fmt = "invalid cycles")]
     InvalidCycles,
+    #[display(fmt = "max cycles exceeded")]
+    CyclesExceeded,
     #[display(fmt = "invalid instruction {}", "_0")]
     InvalidInstruction(u32),
     #[display(fmt = "invalid syscall {}", "_0")]
@@ -19,6 +29,7 @@ pub enum Error {
     InvalidElfBits,
     #[display(fmt = "invalid operand {}", "_0")]
     InvalidOp(u8),
+    #[cfg(feature = "std")]
     #[display(fmt = "I/O error: {:?}", "_0")]
     IO(ErrorKind),
     #[display(fmt = "dynasm error {}", "_0")]
@@ -29,14 +40,147 @@ pub enum Error {
     LimitReached,
     #[display(fmt = "invalid permission")] // FIXME: Distinguish which permission
     InvalidPermission,
+    #[display(fmt = "memory protection violation")]
+    MemoryProtection,
+    #[display(fmt = "invalid memory size {}", "_0")]
+    InvalidMemorySize(u64),
+    #[display(fmt = "address {:#x} exceeds memory size {:#x}", "_0", "_1")]
+    MemorySizeExceeded(u64, u64),
+    #[display(fmt = "watchpoint at {:#x} triggered by pc {:#x}", "addr", "pc")]
+    Watchpoint { addr: u64, pc: u64 },
+    #[display(fmt = "invalid heap access at {:#x} by pc {:#x}", "addr", "pc")]
+    InvalidHeapAccess { addr: u64, pc: u64 },
+    #[display(fmt = "execution timed out")]
+    Timeout,
+    #[display(fmt = "write to executable memory")]
+    WriteToExecutableMemory,
+    #[display(fmt = "max instructions exceeded")]
+    InstructionLimitExceeded,
+    #[display(fmt = "stack overflow")]
+    StackOverflow,
+    #[display(fmt = "preloaded data at {:#x} overlaps other data", "_0")]
+    PreloadOverlap(u64),
+    #[display(fmt = "invalid ELF segment {}: {}", "index", "reason")]
+    ElfSegment {
+        index: usize,
+        reason: ElfSegmentError,
+    },
+    #[display(fmt = "invalid snapshot")]
+    InvalidSnapshot,
+    // Raised by syscalls that cannot complete yet, such as receiving from
+    // an empty channel. run_resumable yields instead, and the ecall is
+    // executed again on resume.
+    #[display(fmt = "syscall would block")]
+    WouldBlock,
+    // Wall-clock deadline of run_with_deadline passed, unlike Timeout the
+    // machine is left in a resumable state.
+    #[display(fmt = "deadline exceeded")]
+    Deadline,
+    // The machine left the recorded instruction stream, see
+    // DefaultMachine::replay
+    #[display(
+        fmt = "replay diverged at instruction {} recorded at pc {:#x}",
+        "index",
+        "pc"
+    )]
+    ReplayDivergence { index: u64, pc: u64 },
+    // The program exited with the given code before the function called by
+    // DefaultMachine::call returned
+    #[display(fmt = "program exited with {} during call", "_0")]
+    CallExited(i8),
+    #[display(fmt = "unknown symbol")]
+    UnknownSymbol,
+    #[display(fmt = "max call depth exceeded")]
+    CallDepthExceeded,
+    // Stop requested through an InterruptHandle, the machine is left in a
+    // resumable state.
+    #[display(fmt = "interrupted")]
+    Interrupted,
+    // Virtual address that could not be translated, see Sv39Memory
+    #[display(fmt = "page fault at {:#x} by pc {:#x}", "addr", "pc")]
+    PageFault { addr: u64, pc: u64 },
+    // Register width and ISA_* extensions the ELF header of a program
+    // requires, which the machine was not built for, see MachineIsa
+    #[display(
+        fmt = "program requires rv{} with extensions {:#x}",
+        "xlen",
+        "extensions"
+    )]
+    UnsupportedIsa { xlen: u8, extensions: u32 },
     #[display(fmt = "unexpected error")]
     Unexpected,
     #[display(fmt = "unimplemented")]
     Unimplemented,
 }
 
+/// Why a PT_LOAD segment was rejected, see `Error::ElfSegment`.
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Display)]
+#[non_exhaustive]
+pub enum ElfSegmentError {
+    #[display(fmt = "file size exceeds memory size")]
+    FileSizeExceedsMemorySize,
+    #[display(fmt = "alignment is not a power of 2")]
+    InvalidAlignment,
+    #[display(fmt = "address and file offset differ modulo alignment")]
+    Misaligned,
+    // Segments are loaded with page granularity, so sharing a page counts
+    // as overlapping too.
+    #[display(fmt = "overlaps segment {}", "_0")]
+    Overlap(usize),
+}
+
+impl Error {
+    // Memory reports watchpoints without knowing which instruction made the
+    // access, machines use this to record the pc of that instruction.
+    pub(crate) fn with_pc(self, pc: u64) -> Self {
+        match self {
+            Error::Watchpoint { addr, .. } => Error::Watchpoint { addr, pc },
+            Error::InvalidHeapAccess { addr, .. } => Error::InvalidHeapAccess { addr, pc },
+            Error::PageFault { addr, .. } => Error::PageFault { addr, pc },
+            e => e,
+        }
+    }
+}
+
+/// An error raised while running a program together with the machine state
+/// at the time, see `DefaultMachine::last_fault`.
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub struct Fault {
+    pub error: Error,
+    // Address of the instruction being fetched or executed
+    pub pc: u64,
+    // None when the instruction could not be fetched or decoded
+    pub instruction: Option<Instruction>,
+    // Memory range accessed by the instruction for loads, stores and atomic
+    // instructions, or the fetched address when decoding fails.
+    pub addr: Option<u64>,
+    pub size: Option<u64>,
+    // Cycles consumed when the error was raised
+    pub cycles: u64,
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at pc {:#x}", self.error, self.pc)?;
+        if let Some(instruction) = self.instruction {
+            write!(f, " ({})", disassemble(instruction))?;
+        }
+        match (self.addr, self.size) {
+            (Some(addr), Some(size)) => write!(f, " accessing {} bytes at {:#x}", size, addr)?,
+            (Some(addr), None) => write!(f, " accessing {:#x}", addr)?,
+            _ => (),
+        }
+        write!(f, " after {} cycles", self.cycles)
+    }
+}
+
+#[cfg(feature = "std")]
 impl StdError for Error {}
 
+#[cfg(feature = "std")]
+impl StdError for Fault {}
+
+#[cfg(feature = "std")]
 impl From<IOError> for Error {
     fn from(error: IOError) -> Self {
         Error::IO(error.kind())
@@ -0,0 +1,318 @@
+// C bindings covering the whole lifecycle of a machine, so hosts written in
+// other languages can embed the VM without generating bindings. Machines
+// are handed out as opaque pointers, and every fallible function returns
+// one of the CKB_VM_* codes below. include/ckb_vm.h declares the same API
+// for C, a linkable library can be built with:
+//
+//     cargo rustc --release --features ffi --crate-type cdylib
+//
+// Pointers passed in must either be null, which is reported as
+// CKB_VM_ERROR_INVALID_ARGUMENT, or valid for the given lengths. Handles can
+// not be shared between threads without external locking.
+#![allow(clippy::missing_safety_doc)]
+
+use super::{
+    cycle_model, machine::trace::TraceMachine, CoreMachine, DefaultCoreMachine,
+    DefaultMachineBuilder, Error, Memory, SparseMemory, SupportMachine, WXorXMemory,
+    RISCV_GENERAL_REGISTER_NUMBER,
+};
+use bytes::Bytes;
+use std::os::raw::c_void;
+use std::ptr;
+use std::slice;
+
+// Error codes are part of the C API, existing values must never change.
+pub const CKB_VM_OK: i32 = 0;
+pub const CKB_VM_ERROR_INVALID_ARGUMENT: i32 = -1;
+pub const CKB_VM_ERROR_PARSE: i32 = -2;
+pub const CKB_VM_ERROR_UNALIGNED: i32 = -3;
+pub const CKB_VM_ERROR_OUT_OF_BOUND: i32 = -4;
+pub const CKB_VM_ERROR_INVALID_CYCLES: i32 = -5;
+pub const CKB_VM_ERROR_CYCLES_EXCEEDED: i32 = -6;
+pub const CKB_VM_ERROR_INVALID_INSTRUCTION: i32 = -7;
+pub const CKB_VM_ERROR_INVALID_ECALL: i32 = -8;
+pub const CKB_VM_ERROR_INVALID_ELF_BITS: i32 = -9;
+pub const CKB_VM_ERROR_INVALID_OP: i32 = -10;
+pub const CKB_VM_ERROR_IO: i32 = -11;
+pub const CKB_VM_ERROR_DYNASM: i32 = -12;
+pub const CKB_VM_ERROR_ASM: i32 = -13;
+pub const CKB_VM_ERROR_LIMIT_REACHED: i32 = -14;
+pub const CKB_VM_ERROR_INVALID_PERMISSION: i32 = -15;
+pub const CKB_VM_ERROR_MEMORY_PROTECTION: i32 = -16;
+pub const CKB_VM_ERROR_INVALID_MEMORY_SIZE: i32 = -17;
+pub const CKB_VM_ERROR_MEMORY_SIZE_EXCEEDED: i32 = -18;
+pub const CKB_VM_ERROR_WATCHPOINT: i32 = -19;
+pub const CKB_VM_ERROR_INVALID_SNAPSHOT: i32 = -20;
+pub const CKB_VM_ERROR_UNEXPECTED: i32 = -21;
+pub const CKB_VM_ERROR_UNIMPLEMENTED: i32 = -22;
+pub const CKB_VM_ERROR_TIMEOUT: i32 = -23;
+pub const CKB_VM_ERROR_WRITE_TO_EXECUTABLE_MEMORY: i32 = -24;
+pub const CKB_VM_ERROR_INSTRUCTION_LIMIT_EXCEEDED: i32 = -25;
+pub const CKB_VM_ERROR_STACK_OVERFLOW: i32 = -26;
+pub const CKB_VM_ERROR_ELF_SEGMENT: i32 = -27;
+pub const CKB_VM_ERROR_PRELOAD_OVERLAP: i32 = -28;
+pub const CKB_VM_ERROR_WOULD_BLOCK: i32 = -29;
+pub const CKB_VM_ERROR_INVALID_HEAP_ACCESS: i32 = -30;
+pub const CKB_VM_ERROR_DEADLINE: i32 = -31;
+pub const CKB_VM_ERROR_REPLAY_DIVERGENCE: i32 = -32;
+pub const CKB_VM_ERROR_CALL_EXITED: i32 = -33;
+pub const CKB_VM_ERROR_UNKNOWN_SYMBOL: i32 = -34;
+pub const CKB_VM_ERROR_CALL_DEPTH_EXCEEDED: i32 = -35;
+pub const CKB_VM_ERROR_INTERRUPTED: i32 = -36;
+pub const CKB_VM_ERROR_PAGE_FAULT: i32 = -37;
+pub const CKB_VM_ERROR_UNSUPPORTED_ISA: i32 = -38;
+
+pub fn error_code(error: Error) -> i32 {
+    match error {
+        Error::ParseError => CKB_VM_ERROR_PARSE,
+        Error::Unaligned => CKB_VM_ERROR_UNALIGNED,
+        Error::OutOfBound => CKB_VM_ERROR_OUT_OF_BOUND,
+        Error::InvalidCycles => CKB_VM_ERROR_INVALID_CYCLES,
+        Error::CyclesExceeded => CKB_VM_ERROR_CYCLES_EXCEEDED,
+        Error::InvalidInstruction(_) => CKB_VM_ERROR_INVALID_INSTRUCTION,
+        Error::InvalidEcall(_) => CKB_VM_ERROR_INVALID_ECALL,
+        Error::InvalidElfBits => CKB_VM_ERROR_INVALID_ELF_BITS,
+        Error::InvalidOp(_) => CKB_VM_ERROR_INVALID_OP,
+        Error::IO(_) => CKB_VM_ERROR_IO,
+        Error::Dynasm(_) => CKB_VM_ERROR_DYNASM,
+        Error::Asm(_) => CKB_VM_ERROR_ASM,
+        Error::LimitReached => CKB_VM_ERROR_LIMIT_REACHED,
+        Error::InvalidPermission => CKB_VM_ERROR_INVALID_PERMISSION,
+        Error::MemoryProtection => CKB_VM_ERROR_MEMORY_PROTECTION,
+        Error::InvalidMemorySize(_) => CKB_VM_ERROR_INVALID_MEMORY_SIZE,
+        Error::MemorySizeExceeded(_, _) => CKB_VM_ERROR_MEMORY_SIZE_EXCEEDED,
+        Error::Watchpoint { .. } => CKB_VM_ERROR_WATCHPOINT,
+        Error::InvalidSnapshot => CKB_VM_ERROR_INVALID_SNAPSHOT,
+        Error::Unexpected => CKB_VM_ERROR_UNEXPECTED,
+        Error::Unimplemented => CKB_VM_ERROR_UNIMPLEMENTED,
+        Error::Timeout => CKB_VM_ERROR_TIMEOUT,
+        Error::WriteToExecutableMemory => CKB_VM_ERROR_WRITE_TO_EXECUTABLE_MEMORY,
+        Error::InstructionLimitExceeded => CKB_VM_ERROR_INSTRUCTION_LIMIT_EXCEEDED,
+        Error::StackOverflow => CKB_VM_ERROR_STACK_OVERFLOW,
+        Error::ElfSegment { .. } => CKB_VM_ERROR_ELF_SEGMENT,
+        Error::PreloadOverlap(_) => CKB_VM_ERROR_PRELOAD_OVERLAP,
+        Error::WouldBlock => CKB_VM_ERROR_WOULD_BLOCK,
+        Error::InvalidHeapAccess { .. } => CKB_VM_ERROR_INVALID_HEAP_ACCESS,
+        Error::Deadline => CKB_VM_ERROR_DEADLINE,
+        Error::ReplayDivergence { .. } => CKB_VM_ERROR_REPLAY_DIVERGENCE,
+        Error::CallExited(_) => CKB_VM_ERROR_CALL_EXITED,
+        Error::UnknownSymbol => CKB_VM_ERROR_UNKNOWN_SYMBOL,
+        Error::CallDepthExceeded => CKB_VM_ERROR_CALL_DEPTH_EXCEEDED,
+        Error::Interrupted => CKB_VM_ERROR_INTERRUPTED,
+        Error::PageFault { .. } => CKB_VM_ERROR_PAGE_FAULT,
+        Error::UnsupportedIsa { .. } => CKB_VM_ERROR_UNSUPPORTED_ISA,
+    }
+}
+
+fn to_code(result: Result<(), Error>) -> i32 {
+    match result {
+        Ok(()) => CKB_VM_OK,
+        Err(e) => error_code(e),
+    }
+}
+
+// Machine state visible to syscall callbacks, exposed as ckb_vm_core in C.
+pub type FfiCoreMachine = DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>;
+
+// Syscall callbacks receive the data pointer given at registration, and
+// return 0 when the syscall succeeds. Any other value stops execution with
+// CKB_VM_ERROR_INVALID_ECALL.
+pub type FfiSyscallCallback = extern "C" fn(data: *mut c_void, core: *mut FfiCoreMachine) -> i32;
+
+// Exposed as ckb_vm_machine in C.
+pub struct FfiMachine {
+    machine: TraceMachine<'static, FfiCoreMachine>,
+}
+
+// Creates a 64-bit IMC machine charging cycles with cycle_model::ckb2019, a
+// max_cycles value of 0 means there is no limit. The returned handle must be
+// released with ckb_vm_destroy.
+#[no_mangle]
+pub extern "C" fn ckb_vm_create(max_cycles: u64) -> *mut FfiMachine {
+    let core = FfiCoreMachine::new_with_max_cycles(max_cycles);
+    let machine = DefaultMachineBuilder::new(core)
+        .instruction_cycle_func(Box::new(cycle_model::ckb2019))
+        .build();
+    Box::into_raw(Box::new(FfiMachine {
+        machine: TraceMachine::new(machine),
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ckb_vm_destroy(machine: *mut FfiMachine) {
+    if !machine.is_null() {
+        drop(Box::from_raw(machine));
+    }
+}
+
+// Loads an ELF program, args holds argc pointers to arguments whose lengths
+// are stored in arg_lens. Both can be null when argc is 0.
+#[no_mangle]
+pub unsafe extern "C" fn ckb_vm_load_program(
+    machine: *mut FfiMachine,
+    program: *const u8,
+    program_len: usize,
+    argc: usize,
+    args: *const *const u8,
+    arg_lens: *const usize,
+) -> i32 {
+    let machine = match machine.as_mut() {
+        Some(machine) => machine,
+        None => return CKB_VM_ERROR_INVALID_ARGUMENT,
+    };
+    if program.is_null() || (argc > 0 && (args.is_null() || arg_lens.is_null())) {
+        return CKB_VM_ERROR_INVALID_ARGUMENT;
+    }
+    let mut program_args = Vec::with_capacity(argc);
+    for i in 0..argc {
+        let arg = *args.add(i);
+        let len = *arg_lens.add(i);
+        if arg.is_null() && len > 0 {
+            return CKB_VM_ERROR_INVALID_ARGUMENT;
+        }
+        let arg = if len > 0 {
+            slice::from_raw_parts(arg, len)
+        } else {
+            &[]
+        };
+        program_args.push(Bytes::from(arg));
+    }
+    let program = Bytes::from(slice::from_raw_parts(program, program_len));
+    to_code(
+        machine
+            .machine
+            .load_program(&program, &program_args)
+            .map(|_| ()),
+    )
+}
+
+// Runs the loaded program until it exits, the exit code is written to
+// exit_code when running succeeds. exit_code can be null if not needed.
+#[no_mangle]
+pub unsafe extern "C" fn ckb_vm_run(machine: *mut FfiMachine, exit_code: *mut i8) -> i32 {
+    let machine = match machine.as_mut() {
+        Some(machine) => machine,
+        None => return CKB_VM_ERROR_INVALID_ARGUMENT,
+    };
+    match machine.machine.run() {
+        Ok(code) => {
+            if !exit_code.is_null() {
+                ptr::write(exit_code, code);
+            }
+            CKB_VM_OK
+        }
+        Err(e) => error_code(e),
+    }
+}
+
+// Returns 0 for a null handle.
+#[no_mangle]
+pub unsafe extern "C" fn ckb_vm_cycles(machine: *const FfiMachine) -> u64 {
+    match machine.as_ref() {
+        Some(machine) => SupportMachine::cycles(&machine.machine.machine),
+        None => 0,
+    }
+}
+
+// Registers a callback for a syscall number, replacing any callback
+// registered earlier for the same number. Syscall 93 is always handled by
+// the VM as exit and can not be registered.
+#[no_mangle]
+pub unsafe extern "C" fn ckb_vm_register_syscall(
+    machine: *mut FfiMachine,
+    number: u64,
+    callback: Option<FfiSyscallCallback>,
+    data: *mut c_void,
+) -> i32 {
+    let (machine, callback) = match (machine.as_mut(), callback) {
+        (Some(machine), Some(callback)) => (machine, callback),
+        _ => return CKB_VM_ERROR_INVALID_ARGUMENT,
+    };
+    if number == 93 {
+        return CKB_VM_ERROR_INVALID_ARGUMENT;
+    }
+    machine.machine.machine.syscall_registry_mut().register(
+        number,
+        Box::new(move |core: &mut FfiCoreMachine| {
+            if callback(data, core) == 0 {
+                Ok(())
+            } else {
+                Err(Error::InvalidEcall(number))
+            }
+        }),
+    );
+    CKB_VM_OK
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ckb_vm_get_register(
+    core: *const FfiCoreMachine,
+    index: usize,
+    value: *mut u64,
+) -> i32 {
+    match core.as_ref() {
+        Some(core) if index < RISCV_GENERAL_REGISTER_NUMBER && !value.is_null() => {
+            ptr::write(value, core.registers()[index]);
+            CKB_VM_OK
+        }
+        _ => CKB_VM_ERROR_INVALID_ARGUMENT,
+    }
+}
+
+// Writes to register 0 are ignored like in RISC-V.
+#[no_mangle]
+pub unsafe extern "C" fn ckb_vm_set_register(
+    core: *mut FfiCoreMachine,
+    index: usize,
+    value: u64,
+) -> i32 {
+    match core.as_mut() {
+        Some(core) if index < RISCV_GENERAL_REGISTER_NUMBER => {
+            if index != 0 {
+                core.set_register(index, value);
+            }
+            CKB_VM_OK
+        }
+        _ => CKB_VM_ERROR_INVALID_ARGUMENT,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ckb_vm_load_memory(
+    core: *mut FfiCoreMachine,
+    addr: u64,
+    buffer: *mut u8,
+    len: usize,
+) -> i32 {
+    let core = match core.as_mut() {
+        Some(core) if !buffer.is_null() || len == 0 => core,
+        _ => return CKB_VM_ERROR_INVALID_ARGUMENT,
+    };
+    match core.memory_mut().load_bytes(addr, len as u64) {
+        Ok(data) => {
+            ptr::copy_nonoverlapping(data.as_ptr(), buffer, len);
+            CKB_VM_OK
+        }
+        Err(e) => error_code(e),
+    }
+}
+
+// Stores are subject to the same W^X permission checks as the program.
+#[no_mangle]
+pub unsafe extern "C" fn ckb_vm_store_memory(
+    core: *mut FfiCoreMachine,
+    addr: u64,
+    buffer: *const u8,
+    len: usize,
+) -> i32 {
+    let core = match core.as_mut() {
+        Some(core) if !buffer.is_null() || len == 0 => core,
+        _ => return CKB_VM_ERROR_INVALID_ARGUMENT,
+    };
+    let data = if len > 0 {
+        slice::from_raw_parts(buffer, len)
+    } else {
+        &[]
+    };
+    to_code(core.memory_mut().store_bytes(addr, data))
+}
@@ -0,0 +1,557 @@
+use super::{
+    decoder::{build_imac_decoder, Decoder},
+    machine::{CoreMachine, DefaultMachine, SupportMachine},
+    memory::Memory,
+    Error, Register, RISCV_GENERAL_REGISTER_NUMBER,
+};
+use std::collections::HashSet;
+use std::io::{Cursor, ErrorKind, Read, Write};
+
+// Register number GDB uses for pc on RISC-V, following the 32 general
+// purpose registers.
+const PC_REGISTER: usize = RISCV_GENERAL_REGISTER_NUMBER;
+
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+const SIGXCPU: u8 = 24;
+
+/// Transport backed by in-memory buffers, incoming data is read from
+/// `input` while everything sent by the stub is appended to `output`.
+#[derive(Default)]
+pub struct MemoryTransport {
+    pub input: Cursor<Vec<u8>>,
+    pub output: Vec<u8>,
+}
+
+impl MemoryTransport {
+    pub fn new(input: Vec<u8>) -> Self {
+        Self {
+            input: Cursor::new(input),
+            output: Vec::new(),
+        }
+    }
+}
+
+impl Read for MemoryTransport {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl Write for MemoryTransport {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Stub speaking the GDB remote serial protocol, it drives a DefaultMachine
+/// according to the commands received from the transport, which can be any
+/// type implementing both Read and Write such as TcpStream. The program
+/// should already be loaded in the machine before serving.
+pub struct GdbStub<'a, 'b, Inner, T> {
+    machine: &'b mut DefaultMachine<'a, Inner>,
+    transport: T,
+    decoder: Decoder,
+    breakpoints: HashSet<u64>,
+    last_packet: Vec<u8>,
+}
+
+enum Action {
+    Reply(Vec<u8>),
+    Stop,
+}
+
+impl<'a, 'b, Inner: SupportMachine, T: Read + Write> GdbStub<'a, 'b, Inner, T> {
+    pub fn new(machine: &'b mut DefaultMachine<'a, Inner>, transport: T) -> Self {
+        Self::with_decoder(machine, transport, build_imac_decoder::<Inner::REG>())
+    }
+
+    pub fn with_decoder(
+        machine: &'b mut DefaultMachine<'a, Inner>,
+        transport: T,
+        decoder: Decoder,
+    ) -> Self {
+        Self {
+            machine,
+            transport,
+            decoder,
+            breakpoints: HashSet::new(),
+            last_packet: Vec::new(),
+        }
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    pub fn breakpoints(&self) -> &HashSet<u64> {
+        &self.breakpoints
+    }
+
+    // Serves requests until the debugger detaches, kills the program or
+    // closes the connection.
+    pub fn serve(&mut self) -> Result<(), Error> {
+        while let Some(packet) = self.read_packet()? {
+            match self.handle(&packet)? {
+                Action::Reply(reply) => self.send_packet(&reply)?,
+                Action::Stop => break,
+            }
+        }
+        Ok(())
+    }
+
+    fn read_byte(&mut self) -> Result<Option<u8>, Error> {
+        let mut buf = [0u8; 1];
+        loop {
+            match self.transport.read(&mut buf) {
+                Ok(0) => return Ok(None),
+                Ok(_) => return Ok(Some(buf[0])),
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    // Reads the next valid packet, acknowledging it. Acks from the other
+    // side are skipped, a NAK leads to retransmission of the last packet.
+    fn read_packet(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(b'$') => (),
+                Some(b'-') => {
+                    let packet = self.last_packet.clone();
+                    self.transport.write_all(&packet)?;
+                    continue;
+                }
+                Some(_) => continue,
+            }
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(b) => data.push(b),
+                }
+            }
+            let mut checksum = [0u8; 2];
+            for c in checksum.iter_mut() {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(b) => *c = b,
+                }
+            }
+            if parse_hex(&checksum) == Some(u64::from(packet_checksum(&data))) {
+                self.transport.write_all(b"+")?;
+                return Ok(Some(data));
+            }
+            self.transport.write_all(b"-")?;
+        }
+    }
+
+    fn send_packet(&mut self, data: &[u8]) -> Result<(), Error> {
+        let mut packet = Vec::with_capacity(data.len() + 4);
+        packet.push(b'$');
+        packet.extend_from_slice(data);
+        packet.extend_from_slice(format!("#{:02x}", packet_checksum(data)).as_bytes());
+        self.transport.write_all(&packet)?;
+        self.transport.flush()?;
+        self.last_packet = packet;
+        Ok(())
+    }
+
+    fn handle(&mut self, packet: &[u8]) -> Result<Action, Error> {
+        let (command, args) = match packet.split_first() {
+            Some((command, args)) => (*command, args),
+            None => return Ok(Action::Reply(vec![])),
+        };
+        let reply = match command {
+            b'?' => stop_reply(SIGTRAP),
+            b'g' => self.read_registers(),
+            b'G' => self.write_registers(args),
+            b'p' => self.read_register(args),
+            b'P' => self.write_register(args),
+            b'm' => self.read_memory(args),
+            b'M' => self.write_memory(args),
+            b'Z' | b'z' => self.update_breakpoint(command == b'Z', args),
+            b's' => {
+                self.resume_at(args);
+                self.step()
+            }
+            b'c' => {
+                self.resume_at(args);
+                self.cont()
+            }
+            b'H' => b"OK".to_vec(),
+            b'q' if args == b"Attached" => b"1".to_vec(),
+            b'q' if args.starts_with(b"Supported") => b"PacketSize=4000".to_vec(),
+            b'k' => return Ok(Action::Stop),
+            b'D' => {
+                self.send_packet(b"OK")?;
+                return Ok(Action::Stop);
+            }
+            _ => vec![],
+        };
+        Ok(Action::Reply(reply))
+    }
+
+    fn register_value(&self, index: usize) -> u64 {
+        if index == PC_REGISTER {
+            self.machine.pc().to_u64()
+        } else {
+            self.machine.registers()[index].to_u64()
+        }
+    }
+
+    fn set_register_value(&mut self, index: usize, value: u64) {
+        if index == PC_REGISTER {
+            self.machine.set_pc(Inner::REG::from_u64(value));
+        } else if index > 0 {
+            self.machine
+                .set_register(index, Inner::REG::from_u64(value));
+        }
+    }
+
+    fn register_bytes(&self) -> usize {
+        Inner::REG::BITS as usize / 8
+    }
+
+    fn read_registers(&self) -> Vec<u8> {
+        let mut reply = Vec::new();
+        for i in 0..=PC_REGISTER {
+            encode_le(&mut reply, self.register_value(i), self.register_bytes());
+        }
+        reply
+    }
+
+    fn write_registers(&mut self, args: &[u8]) -> Vec<u8> {
+        let chunks = args.chunks_exact(self.register_bytes() * 2);
+        if !chunks.remainder().is_empty() {
+            return error_reply();
+        }
+        let mut values = Vec::new();
+        for chunk in chunks.take(PC_REGISTER + 1) {
+            match decode_le(chunk) {
+                Some(value) => values.push(value),
+                None => return error_reply(),
+            }
+        }
+        for (i, value) in values.into_iter().enumerate() {
+            self.set_register_value(i, value);
+        }
+        b"OK".to_vec()
+    }
+
+    fn read_register(&self, args: &[u8]) -> Vec<u8> {
+        match parse_hex(args) {
+            Some(index) if index as usize <= PC_REGISTER => {
+                let mut reply = Vec::new();
+                encode_le(
+                    &mut reply,
+                    self.register_value(index as usize),
+                    self.register_bytes(),
+                );
+                reply
+            }
+            _ => error_reply(),
+        }
+    }
+
+    fn write_register(&mut self, args: &[u8]) -> Vec<u8> {
+        let mut parts = args.splitn(2, |b| *b == b'=');
+        let index = parts.next().and_then(parse_hex);
+        let value = parts.next().and_then(decode_le);
+        match (index, value) {
+            (Some(index), Some(value)) if index as usize <= PC_REGISTER => {
+                self.set_register_value(index as usize, value);
+                b"OK".to_vec()
+            }
+            _ => error_reply(),
+        }
+    }
+
+    fn read_memory(&mut self, args: &[u8]) -> Vec<u8> {
+        let (addr, size) = match parse_address_and_size(args) {
+            Some(result) => result,
+            None => return error_reply(),
+        };
+        match self.machine.memory_mut().load_bytes(addr, size) {
+            Ok(bytes) => encode_hex(&bytes),
+            Err(_) => error_reply(),
+        }
+    }
+
+    fn write_memory(&mut self, args: &[u8]) -> Vec<u8> {
+        let mut parts = args.splitn(2, |b| *b == b':');
+        let header = parts.next().and_then(parse_address_and_size);
+        let data = parts.next().and_then(decode_hex);
+        match (header, data) {
+            (Some((addr, size)), Some(data)) if data.len() as u64 == size => {
+                match self.machine.memory_mut().store_bytes(addr, &data) {
+                    Ok(()) => b"OK".to_vec(),
+                    Err(_) => error_reply(),
+                }
+            }
+            _ => error_reply(),
+        }
+    }
+
+    // Only software and hardware execution breakpoints are supported, they
+    // are kept in the stub so guest memory is never patched.
+    fn update_breakpoint(&mut self, insert: bool, args: &[u8]) -> Vec<u8> {
+        let mut parts = args.split(|b| *b == b',');
+        let kind = parts.next();
+        let addr = parts.next().and_then(parse_hex);
+        match (kind, addr) {
+            (Some(b"0"), Some(addr)) | (Some(b"1"), Some(addr)) => {
+                if insert {
+                    self.breakpoints.insert(addr);
+                } else {
+                    self.breakpoints.remove(&addr);
+                }
+                b"OK".to_vec()
+            }
+            _ => vec![],
+        }
+    }
+
+    fn resume_at(&mut self, args: &[u8]) {
+        if let Some(addr) = parse_hex(args) {
+            self.machine.set_pc(Inner::REG::from_u64(addr));
+        }
+    }
+
+    // Executes one instruction, returns the reply to send if execution has
+    // to stop afterwards for reasons other than a breakpoint.
+    fn execute_one(&mut self) -> Option<Vec<u8>> {
+        self.machine.set_running(true);
+        if let Err(e) = self.machine.step(&self.decoder) {
+            let signal = match e {
+                Error::InvalidInstruction(_) | Error::InvalidOp(_) => SIGILL,
+                Error::CyclesExceeded => SIGXCPU,
+                Error::Watchpoint { .. } => SIGTRAP,
+                _ => SIGSEGV,
+            };
+            return Some(stop_reply(signal));
+        }
+        if !self.machine.running() {
+            return Some(format!("W{:02x}", self.machine.exit_code() as u8).into_bytes());
+        }
+        None
+    }
+
+    fn step(&mut self) -> Vec<u8> {
+        self.execute_one().unwrap_or_else(|| stop_reply(SIGTRAP))
+    }
+
+    fn cont(&mut self) -> Vec<u8> {
+        loop {
+            if let Some(reply) = self.execute_one() {
+                return reply;
+            }
+            if self.breakpoints.contains(&self.machine.pc().to_u64()) {
+                return stop_reply(SIGTRAP);
+            }
+        }
+    }
+}
+
+fn packet_checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+fn stop_reply(signal: u8) -> Vec<u8> {
+    format!("S{:02x}", signal).into_bytes()
+}
+
+fn error_reply() -> Vec<u8> {
+    b"E01".to_vec()
+}
+
+fn parse_hex(data: &[u8]) -> Option<u64> {
+    if data.is_empty() || data.len() > 16 {
+        return None;
+    }
+    let s = std::str::from_utf8(data).ok()?;
+    u64::from_str_radix(s, 16).ok()
+}
+
+fn parse_address_and_size(data: &[u8]) -> Option<(u64, u64)> {
+    let mut parts = data.splitn(2, |b| *b == b',');
+    let addr = parts.next().and_then(parse_hex)?;
+    let size = parts.next().and_then(parse_hex)?;
+    Some((addr, size))
+}
+
+fn encode_hex(data: &[u8]) -> Vec<u8> {
+    data.iter()
+        .flat_map(|b| format!("{:02x}", b).into_bytes())
+        .collect()
+}
+
+fn decode_hex(data: &[u8]) -> Option<Vec<u8>> {
+    let chunks = data.chunks_exact(2);
+    if !chunks.remainder().is_empty() {
+        return None;
+    }
+    chunks.map(|c| parse_hex(c).map(|b| b as u8)).collect()
+}
+
+// Register values are transferred in target byte order, which is little
+// endian on RISC-V.
+fn encode_le(buffer: &mut Vec<u8>, value: u64, bytes: usize) {
+    buffer.extend(encode_hex(&value.to_le_bytes()[..bytes]));
+}
+
+fn decode_le(data: &[u8]) -> Option<u64> {
+    let bytes = decode_hex(data)?;
+    if bytes.len() > 8 {
+        return None;
+    }
+    Some(
+        bytes
+            .iter()
+            .rev()
+            .fold(0u64, |value, b| (value << 8) | u64::from(*b)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{machine::DefaultCoreMachine, SparseMemory};
+    use bytes::Bytes;
+    use std::fs::File;
+
+    fn packet(data: &str) -> Vec<u8> {
+        format!("${}#{:02x}", data, packet_checksum(data.as_bytes())).into_bytes()
+    }
+
+    // Splits everything sent by the stub into packet payloads, dropping acks
+    fn replies(output: &[u8]) -> Vec<String> {
+        let output = String::from_utf8(output.to_vec()).unwrap();
+        output
+            .split('$')
+            .skip(1)
+            .map(|p| p.split('#').next().unwrap().to_string())
+            .collect()
+    }
+
+    fn serve(
+        machine: &mut DefaultMachine<DefaultCoreMachine<u64, SparseMemory<u64>>>,
+        packets: &[&str],
+    ) -> Vec<String> {
+        let input: Vec<u8> = packets.iter().flat_map(|p| packet(p)).collect();
+        let mut stub = GdbStub::new(machine, MemoryTransport::new(input));
+        stub.serve().unwrap();
+        replies(&stub.transport().output)
+    }
+
+    fn load_simple() -> DefaultMachine<'static, DefaultCoreMachine<u64, SparseMemory<u64>>> {
+        let mut file = File::open("tests/programs/simple64").unwrap();
+        let mut buffer = Vec::new();
+        file.read_to_end(&mut buffer).unwrap();
+        let mut machine = DefaultMachine::default();
+        machine
+            .load_program(&Bytes::from(buffer), &vec!["simple".into()])
+            .unwrap();
+        machine
+    }
+
+    #[test]
+    fn test_packet_framing() {
+        let mut machine = load_simple();
+        let mut input = b"+$?#00".to_vec();
+        input.extend(packet("?"));
+        let mut stub = GdbStub::new(&mut machine, MemoryTransport::new(input));
+        stub.serve().unwrap();
+        // Corrupted packet is rejected with a NAK
+        assert_eq!(
+            String::from_utf8(stub.transport().output.clone()).unwrap(),
+            "-+$S05#b8"
+        );
+    }
+
+    #[test]
+    fn test_registers_and_memory() {
+        let mut machine = load_simple();
+        let pc = *machine.pc();
+        let replies = serve(
+            &mut machine,
+            &[
+                "qSupported:swbreak+",
+                "g",
+                "p20",
+                "P5=efbeadde00000000",
+                "p5",
+                "P0=0100000000000000",
+                "p0",
+                "M1000,4:01020304",
+                "m1000,4",
+                "m1001,2",
+                "m7fffffffff,2",
+                "k",
+            ],
+        );
+        let pc_hex = String::from_utf8(encode_hex(&pc.to_le_bytes())).unwrap();
+        assert_eq!(replies[0], "PacketSize=4000");
+        assert_eq!(replies[1].len(), 33 * 16);
+        assert!(replies[1].ends_with(&pc_hex));
+        assert_eq!(replies[2], pc_hex);
+        assert_eq!(replies[3], "OK");
+        assert_eq!(replies[4], "efbeadde00000000");
+        assert_eq!(replies[5], "OK");
+        assert_eq!(replies[6], "0000000000000000");
+        assert_eq!(replies[7], "OK");
+        assert_eq!(replies[8], "01020304");
+        assert_eq!(replies[9], "0203");
+        assert_eq!(replies[10], "E01");
+        assert_eq!(machine.registers()[5], 0xdead_beef);
+    }
+
+    #[test]
+    fn test_step_breakpoint_and_continue() {
+        let mut machine = load_simple();
+        let entry = *machine.pc();
+        let mut first = load_simple();
+        first.set_running(true);
+        first.step(&build_imac_decoder::<u64>()).unwrap();
+        let second = *first.pc();
+        first.step(&build_imac_decoder::<u64>()).unwrap();
+        let third = *first.pc();
+
+        let replies = serve(
+            &mut machine,
+            &[
+                "s",
+                "p20",
+                &format!("Z0,{:x},2", third),
+                "c",
+                "p20",
+                &format!("z0,{:x},2", third),
+                "c",
+                "D",
+            ],
+        );
+        assert_ne!(entry, second);
+        assert_eq!(replies[0], "S05");
+        assert_eq!(
+            replies[1],
+            String::from_utf8(encode_hex(&second.to_le_bytes())).unwrap()
+        );
+        assert_eq!(replies[2], "OK");
+        assert_eq!(replies[3], "S05");
+        assert_eq!(
+            replies[4],
+            String::from_utf8(encode_hex(&third.to_le_bytes())).unwrap()
+        );
+        assert_eq!(replies[5], "OK");
+        assert_eq!(replies[6], "W00");
+        assert_eq!(replies[7], "OK");
+    }
+}
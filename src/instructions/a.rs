@@ -0,0 +1,304 @@
+use super::super::{machine::Machine, memory::Memory, Error};
+use super::register::Register;
+use super::utils::{funct3, opcode, rd, rs1, rs2, update_register};
+use super::{extract_opcode, Instruction, Rtype};
+use ckb_vm_definitions::instructions as insts;
+
+/// Reservation made by LR instructions. The reservation set is exactly the
+/// word or double word loaded, SC only succeeds when the reservation still
+/// covers the same address and size. Any SC, successful or not, releases
+/// the reservation.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Reservation(Option<(u64, u8)>);
+
+impl Reservation {
+    pub fn reserve(&mut self, address: u64, size: u8) {
+        self.0 = Some((address, size));
+    }
+
+    pub fn clear(&mut self) {
+        self.0 = None;
+    }
+
+    pub fn address(&self) -> Option<u64> {
+        self.0.map(|(address, _)| address)
+    }
+
+    fn take(&mut self, address: u64, size: u8) -> bool {
+        self.0.take() == Some((address, size))
+    }
+}
+
+// Decodes instructions of the A extension. The aq and rl bits are dropped
+// since there is only a single hart, all memory accesses are already
+// performed in program order.
+pub fn factory<R: Register>(instruction_bits: u32) -> Option<Instruction> {
+    let bit_length = R::BITS;
+    if bit_length != 32 && bit_length != 64 && bit_length != 128 {
+        return None;
+    }
+    if opcode(instruction_bits) != 0b_0101111 {
+        return None;
+    }
+    // 128 bit registers run the RV64 instruction set
+    let rv64 = bit_length >= 64;
+    let funct5_value = instruction_bits >> 27;
+    let inst_opt = match (funct5_value, funct3(instruction_bits)) {
+        (0b_00010, 0b_010) if rs2(instruction_bits) == 0 => Some(insts::OP_LR_W),
+        (0b_00011, 0b_010) => Some(insts::OP_SC_W),
+        (0b_00001, 0b_010) => Some(insts::OP_AMOSWAP_W),
+        (0b_00000, 0b_010) => Some(insts::OP_AMOADD_W),
+        (0b_00100, 0b_010) => Some(insts::OP_AMOXOR_W),
+        (0b_01100, 0b_010) => Some(insts::OP_AMOAND_W),
+        (0b_01000, 0b_010) => Some(insts::OP_AMOOR_W),
+        (0b_10000, 0b_010) => Some(insts::OP_AMOMIN_W),
+        (0b_10100, 0b_010) => Some(insts::OP_AMOMAX_W),
+        (0b_11000, 0b_010) => Some(insts::OP_AMOMINU_W),
+        (0b_11100, 0b_010) => Some(insts::OP_AMOMAXU_W),
+        (0b_00010, 0b_011) if rv64 && rs2(instruction_bits) == 0 => Some(insts::OP_LR_D),
+        (0b_00011, 0b_011) if rv64 => Some(insts::OP_SC_D),
+        (0b_00001, 0b_011) if rv64 => Some(insts::OP_AMOSWAP_D),
+        (0b_00000, 0b_011) if rv64 => Some(insts::OP_AMOADD_D),
+        (0b_00100, 0b_011) if rv64 => Some(insts::OP_AMOXOR_D),
+        (0b_01100, 0b_011) if rv64 => Some(insts::OP_AMOAND_D),
+        (0b_01000, 0b_011) if rv64 => Some(insts::OP_AMOOR_D),
+        (0b_10000, 0b_011) if rv64 => Some(insts::OP_AMOMIN_D),
+        (0b_10100, 0b_011) if rv64 => Some(insts::OP_AMOMAX_D),
+        (0b_11000, 0b_011) if rv64 => Some(insts::OP_AMOMINU_D),
+        (0b_11100, 0b_011) if rv64 => Some(insts::OP_AMOMAXU_D),
+        _ => None,
+    };
+    inst_opt.map(|inst| {
+        Rtype::new(
+            inst,
+            rd(instruction_bits),
+            rs1(instruction_bits),
+            rs2(instruction_bits),
+        )
+        .0
+    })
+}
+
+fn load<Mac: Machine>(machine: &mut Mac, address: &Mac::REG, size: u8) -> Result<Mac::REG, Error> {
+    if size == 4 {
+        let value = machine.memory_mut().load32(address)?;
+        Ok(value.sign_extend(&Mac::REG::from_u8(32)))
+    } else {
+        machine.memory_mut().load64(address)
+    }
+}
+
+fn store<Mac: Machine>(
+    machine: &mut Mac,
+    address: &Mac::REG,
+    value: &Mac::REG,
+    size: u8,
+) -> Result<(), Error> {
+    if size == 4 {
+        machine.memory_mut().store32(address, value)
+    } else {
+        machine.memory_mut().store64(address, value)
+    }
+}
+
+pub fn execute<Mac: Machine>(inst: Instruction, machine: &mut Mac) -> Result<(), Error> {
+    let op = extract_opcode(inst);
+    let i = Rtype(inst);
+    let size = if op <= insts::OP_AMOMAXU_W { 4 } else { 8 };
+    let address = machine.registers()[i.rs1()].clone();
+    // Atomic memory accesses must be naturally aligned
+    if address.to_u64() & (u64::from(size) - 1) != 0 {
+        return Err(Error::Unaligned);
+    }
+    let rs2_value = machine.registers()[i.rs2()].clone();
+    match op {
+        insts::OP_LR_W | insts::OP_LR_D => {
+            let value = load(machine, &address, size)?;
+            machine
+                .reservation_mut()
+                .ok_or(Error::InvalidOp(op))?
+                .reserve(address.to_u64(), size);
+            update_register(machine, i.rd(), value);
+        }
+        insts::OP_SC_W | insts::OP_SC_D => {
+            let reserved = machine
+                .reservation_mut()
+                .ok_or(Error::InvalidOp(op))?
+                .take(address.to_u64(), size);
+            if reserved {
+                store(machine, &address, &rs2_value, size)?;
+            }
+            update_register(machine, i.rd(), Mac::REG::from_u8(u8::from(!reserved)));
+        }
+        _ => {
+            let value = load(machine, &address, size)?;
+            // W variants operate on the sign extended lower 32 bits, which
+            // keeps signed and unsigned comparisons correct on RV64.
+            let operand = if size == 4 {
+                rs2_value.sign_extend(&Mac::REG::from_u8(32))
+            } else {
+                rs2_value
+            };
+            let result = match op {
+                insts::OP_AMOSWAP_W | insts::OP_AMOSWAP_D => operand,
+                insts::OP_AMOADD_W | insts::OP_AMOADD_D => value.overflowing_add(&operand),
+                insts::OP_AMOXOR_W | insts::OP_AMOXOR_D => value.clone() ^ operand,
+                insts::OP_AMOAND_W | insts::OP_AMOAND_D => value.clone() & operand,
+                insts::OP_AMOOR_W | insts::OP_AMOOR_D => value.clone() | operand,
+                insts::OP_AMOMIN_W | insts::OP_AMOMIN_D => {
+                    value.lt_s(&operand).cond(&value, &operand)
+                }
+                insts::OP_AMOMAX_W | insts::OP_AMOMAX_D => {
+                    value.lt_s(&operand).cond(&operand, &value)
+                }
+                insts::OP_AMOMINU_W | insts::OP_AMOMINU_D => {
+                    value.lt(&operand).cond(&value, &operand)
+                }
+                insts::OP_AMOMAXU_W | insts::OP_AMOMAXU_D => {
+                    value.lt(&operand).cond(&operand, &value)
+                }
+                _ => return Err(Error::InvalidOp(op)),
+            };
+            store(machine, &address, &result, size)?;
+            update_register(machine, i.rd(), value);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::{
+        machine::{DefaultCoreMachine, DefaultMachine, DefaultMachineBuilder},
+        memory::sparse::SparseMemory,
+    };
+    use super::*;
+    use crate::CoreMachine;
+
+    type TestMachine<'a, R> = DefaultMachine<'a, DefaultCoreMachine<R, SparseMemory<R>>>;
+
+    // Encodes an A extension instruction with aq and rl both set, which
+    // should not affect the result.
+    fn encode(funct5: u32, funct3: u32, rd: u32, rs1: u32, rs2: u32) -> u32 {
+        (funct5 << 27)
+            | (0b11 << 25)
+            | (rs2 << 20)
+            | (rs1 << 15)
+            | (funct3 << 12)
+            | (rd << 7)
+            | 0b_0101111
+    }
+
+    fn run<'a, R: Register>(machine: &mut TestMachine<'a, R>, instruction_bits: u32) {
+        let inst = factory::<R>(instruction_bits).expect("decoding");
+        execute(inst, machine).expect("execution");
+    }
+
+    #[test]
+    fn test_decode() {
+        let lr_w = factory::<u64>(encode(0b_00010, 0b_010, 5, 6, 0)).unwrap();
+        assert_eq!(extract_opcode(lr_w), insts::OP_LR_W);
+        assert_eq!(Rtype(lr_w).rd(), 5);
+        assert_eq!(Rtype(lr_w).rs1(), 6);
+        let amomaxu_d = factory::<u64>(encode(0b_11100, 0b_011, 1, 2, 3)).unwrap();
+        assert_eq!(extract_opcode(amomaxu_d), insts::OP_AMOMAXU_D);
+        assert_eq!(Rtype(amomaxu_d).rs2(), 3);
+        // LR requires rs2 to be zero
+        assert!(factory::<u64>(encode(0b_00010, 0b_010, 5, 6, 1)).is_none());
+        // Double word variants are only available on RV64
+        assert!(factory::<u32>(encode(0b_00000, 0b_011, 1, 2, 3)).is_none());
+        assert!(factory::<u32>(encode(0b_00000, 0b_010, 1, 2, 3)).is_some());
+        assert!(factory::<u64>(encode(0b_00101, 0b_010, 1, 2, 3)).is_none());
+    }
+
+    #[test]
+    fn test_amo() {
+        let mut machine =
+            DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default().build();
+        machine.set_register(1, 0x100);
+        let cases = [
+            (0b_00001, 0xFFFF_FFFF_8000_0000, 0x8000_0000u64),
+            (0b_00000, 0xFFFF_FFFF_0000_0002, 0x7FFF_FFFF),
+            (0b_00100, 0x0F0F_0F0F, 0x70F0_F0F2),
+            (0b_01100, 0x0F0F_0F0F, 0x0F0F_0F0D),
+            (0b_01000, 0xF000_0000, 0xFFFF_FFFD),
+            (0b_10000, 0xFFFF_FFFF_FFFF_FFFF, 0xFFFF_FFFF),
+            (0b_10100, 0x1, 0x7FFF_FFFD),
+            (0b_11000, 0x1, 0x1),
+            (0b_11100, 0xFFFF_FFFF_FFFF_FFFF, 0xFFFF_FFFF),
+        ];
+        for (funct5, operand, expected) in cases.iter() {
+            machine.memory_mut().store32(&0x100, &0x7FFF_FFFD).unwrap();
+            machine.set_register(2, *operand);
+            run(&mut machine, encode(*funct5, 0b_010, 3, 1, 2));
+            // rd receives the original value, sign extended
+            assert_eq!(machine.registers()[3], 0x7FFF_FFFD);
+            assert_eq!(
+                machine.memory_mut().load32(&0x100).unwrap(),
+                *expected,
+                "funct5 {:05b}",
+                funct5
+            );
+        }
+
+        machine
+            .memory_mut()
+            .store64(&0x100, &0x8000_0000_0000_0000)
+            .unwrap();
+        machine.set_register(2, 1);
+        run(&mut machine, encode(0b_10000, 0b_011, 3, 1, 2));
+        assert_eq!(machine.registers()[3], 0x8000_0000_0000_0000);
+        assert_eq!(
+            machine.memory_mut().load64(&0x100).unwrap(),
+            0x8000_0000_0000_0000
+        );
+        run(&mut machine, encode(0b_11000, 0b_011, 3, 1, 2));
+        assert_eq!(machine.memory_mut().load64(&0x100).unwrap(), 1);
+        // rd and rs2 can be the same register
+        machine.set_register(2, 5);
+        run(&mut machine, encode(0b_00000, 0b_011, 2, 1, 2));
+        assert_eq!(machine.registers()[2], 1);
+        assert_eq!(machine.memory_mut().load64(&0x100).unwrap(), 6);
+    }
+
+    #[test]
+    fn test_lr_sc() {
+        let mut machine =
+            DefaultMachineBuilder::<DefaultCoreMachine<u32, SparseMemory<u32>>>::default().build();
+        machine.memory_mut().store32(&0x200, &0x8000_0001).unwrap();
+        machine.set_register(1, 0x200);
+        machine.set_register(2, 7);
+
+        // SC without a reservation fails and leaves memory untouched
+        run(&mut machine, encode(0b_00011, 0b_010, 3, 1, 2));
+        assert_eq!(machine.registers()[3], 1);
+        assert_eq!(machine.memory_mut().load32(&0x200).unwrap(), 0x8000_0001);
+
+        run(&mut machine, encode(0b_00010, 0b_010, 3, 1, 0));
+        assert_eq!(machine.registers()[3], 0x8000_0001);
+        assert_eq!(machine.reservation().address(), Some(0x200));
+        run(&mut machine, encode(0b_00011, 0b_010, 3, 1, 2));
+        assert_eq!(machine.registers()[3], 0);
+        assert_eq!(machine.memory_mut().load32(&0x200).unwrap(), 7);
+        assert_eq!(machine.reservation().address(), None);
+
+        // The reservation is released by SC to a different address
+        run(&mut machine, encode(0b_00010, 0b_010, 3, 1, 0));
+        machine.set_register(4, 0x204);
+        run(&mut machine, encode(0b_00011, 0b_010, 3, 4, 2));
+        assert_eq!(machine.registers()[3], 1);
+        run(&mut machine, encode(0b_00011, 0b_010, 3, 1, 2));
+        assert_eq!(machine.registers()[3], 1);
+    }
+
+    #[test]
+    fn test_misaligned_access() {
+        let mut machine =
+            DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default().build();
+        machine.set_register(1, 0x104);
+        let inst = factory::<u64>(encode(0b_00010, 0b_011, 3, 1, 0)).unwrap();
+        assert_eq!(execute(inst, &mut machine), Err(Error::Unaligned));
+        let inst = factory::<u64>(encode(0b_00000, 0b_010, 3, 1, 0)).unwrap();
+        assert!(execute(inst, &mut machine).is_ok());
+    }
+}
@@ -0,0 +1,295 @@
+use super::register::Register;
+use super::utils::{funct3, funct7, itype_immediate, opcode, rd, rs1, rs2};
+use super::{Instruction, Itype, Rtype};
+use ckb_vm_definitions::instructions as insts;
+
+// Decodes instructions of the B extension, which consists of the Zba, Zbb,
+// Zbc and Zbs sub extensions. Instructions with only one source register
+// are encoded in R-type with rs2 set to 0.
+pub fn factory<R: Register>(instruction_bits: u32) -> Option<Instruction> {
+    let bit_length = R::BITS;
+    if bit_length != 32 && bit_length != 64 && bit_length != 128 {
+        return None;
+    }
+    // 128 bit registers run the RV64 instruction set
+    let rv64 = bit_length >= 64;
+    let funct3_value = funct3(instruction_bits);
+    let funct7_value = funct7(instruction_bits);
+    let funct12_value = instruction_bits >> 20;
+    let unary = |inst| Rtype::new(inst, rd(instruction_bits), rs1(instruction_bits), 0).0;
+    let binary = |inst| {
+        Rtype::new(
+            inst,
+            rd(instruction_bits),
+            rs1(instruction_bits),
+            rs2(instruction_bits),
+        )
+        .0
+    };
+    let shift = |inst, shift_mask: i32| {
+        Itype::new_s(
+            inst,
+            rd(instruction_bits),
+            rs1(instruction_bits),
+            itype_immediate(instruction_bits) & shift_mask,
+        )
+        .0
+    };
+    match opcode(instruction_bits) {
+        0b_0110011 => {
+            let inst_opt = match (funct7_value, funct3_value) {
+                (0b_0100000, 0b_111) => Some(insts::OP_ANDN),
+                (0b_0100000, 0b_110) => Some(insts::OP_ORN),
+                (0b_0100000, 0b_100) => Some(insts::OP_XNOR),
+                (0b_0010000, 0b_010) => Some(insts::OP_SH1ADD),
+                (0b_0010000, 0b_100) => Some(insts::OP_SH2ADD),
+                (0b_0010000, 0b_110) => Some(insts::OP_SH3ADD),
+                (0b_0000101, 0b_001) => Some(insts::OP_CLMUL),
+                (0b_0000101, 0b_010) => Some(insts::OP_CLMULR),
+                (0b_0000101, 0b_011) => Some(insts::OP_CLMULH),
+                (0b_0000101, 0b_100) => Some(insts::OP_MIN),
+                (0b_0000101, 0b_101) => Some(insts::OP_MINU),
+                (0b_0000101, 0b_110) => Some(insts::OP_MAX),
+                (0b_0000101, 0b_111) => Some(insts::OP_MAXU),
+                (0b_0110000, 0b_001) => Some(insts::OP_ROL),
+                (0b_0110000, 0b_101) => Some(insts::OP_ROR),
+                (0b_0100100, 0b_001) => Some(insts::OP_BCLR),
+                (0b_0100100, 0b_101) => Some(insts::OP_BEXT),
+                (0b_0110100, 0b_001) => Some(insts::OP_BINV),
+                (0b_0010100, 0b_001) => Some(insts::OP_BSET),
+                (0b_0000100, 0b_100) if !rv64 && rs2(instruction_bits) == 0 => {
+                    return Some(unary(insts::OP_ZEXTH));
+                }
+                _ => None,
+            };
+            inst_opt.map(binary)
+        }
+        0b_0111011 if rv64 => {
+            let inst_opt = match (funct7_value, funct3_value) {
+                (0b_0000100, 0b_000) => Some(insts::OP_ADDUW),
+                (0b_0010000, 0b_010) => Some(insts::OP_SH1ADDUW),
+                (0b_0010000, 0b_100) => Some(insts::OP_SH2ADDUW),
+                (0b_0010000, 0b_110) => Some(insts::OP_SH3ADDUW),
+                (0b_0110000, 0b_001) => Some(insts::OP_ROLW),
+                (0b_0110000, 0b_101) => Some(insts::OP_RORW),
+                (0b_0000100, 0b_100) if rs2(instruction_bits) == 0 => {
+                    return Some(unary(insts::OP_ZEXTH));
+                }
+                _ => None,
+            };
+            inst_opt.map(binary)
+        }
+        0b_0010011 => {
+            let unary_opt = match (funct3_value, funct12_value) {
+                (0b_001, 0x600) => Some(insts::OP_CLZ),
+                (0b_001, 0x601) => Some(insts::OP_CTZ),
+                (0b_001, 0x602) => Some(insts::OP_CPOP),
+                (0b_001, 0x604) => Some(insts::OP_SEXTB),
+                (0b_001, 0x605) => Some(insts::OP_SEXTH),
+                (0b_101, 0x287) => Some(insts::OP_ORCB),
+                (0b_101, 0x698) if !rv64 => Some(insts::OP_REV8),
+                (0b_101, 0x6B8) if rv64 => Some(insts::OP_REV8),
+                _ => None,
+            };
+            if unary_opt.is_some() {
+                return unary_opt.map(unary);
+            }
+            // On RV32, bit 5 of the shift amount is reserved
+            if !rv64 && funct7_value & 1 != 0 {
+                return None;
+            }
+            let inst_opt = match (funct3_value, funct7_value >> 1) {
+                (0b_001, 0b_010010) => Some(insts::OP_BCLRI),
+                (0b_001, 0b_001010) => Some(insts::OP_BSETI),
+                (0b_001, 0b_011010) => Some(insts::OP_BINVI),
+                (0b_101, 0b_010010) => Some(insts::OP_BEXTI),
+                (0b_101, 0b_011000) => Some(insts::OP_RORI),
+                _ => None,
+            };
+            inst_opt.map(|inst| shift(inst, i32::from(R::SHIFT_MASK)))
+        }
+        0b_0011011 if rv64 => match (funct3_value, funct12_value) {
+            (0b_001, 0x600) => Some(unary(insts::OP_CLZW)),
+            (0b_001, 0x601) => Some(unary(insts::OP_CTZW)),
+            (0b_001, 0x602) => Some(unary(insts::OP_CPOPW)),
+            (0b_001, _) if funct7_value >> 1 == 0b_000010 => Some(shift(insts::OP_SLLIUW, 0x3F)),
+            (0b_101, _) if funct7_value == 0b_0110000 => Some(shift(insts::OP_RORIW, 0x1F)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::execute;
+    use super::*;
+    use crate::machine::{CoreMachine, DefaultCoreMachine, DefaultMachine};
+    use crate::SparseMemory;
+
+    // Encodes an instruction with rd = a0, rs1 = a1 and rs2 = a2
+    fn encode(funct7: u32, rs2: u32, funct3: u32, opcode: u32) -> u32 {
+        (funct7 << 25) | (rs2 << 20) | (11 << 15) | (funct3 << 12) | (10 << 7) | opcode
+    }
+
+    fn run<R: Register>(bits: u32, a1: R, a2: R) -> R {
+        let mut machine = DefaultMachine::<DefaultCoreMachine<R, SparseMemory<R>>>::default();
+        machine.set_register(11, a1);
+        machine.set_register(12, a2);
+        let instruction = factory::<R>(bits).expect("decoding");
+        execute(instruction, &mut machine).unwrap();
+        machine.registers()[10].clone()
+    }
+
+    fn run64(bits: u32, a1: u64, a2: u64) -> u64 {
+        run::<u64>(bits, a1, a2)
+    }
+
+    #[test]
+    fn test_decode_is_xlen_specific() {
+        let zext_h32 = encode(0b_0000100, 0, 0b_100, 0b_0110011);
+        let zext_h64 = encode(0b_0000100, 0, 0b_100, 0b_0111011);
+        let rev8_32 = encode(0b_0110100, 0b_11000, 0b_101, 0b_0010011);
+        let rev8_64 = encode(0b_0110101, 0b_11000, 0b_101, 0b_0010011);
+        let bseti_32 = encode(0b_0010101, 0, 0b_001, 0b_0010011);
+        assert!(factory::<u32>(zext_h32).is_some());
+        assert!(factory::<u64>(zext_h32).is_none());
+        assert!(factory::<u32>(zext_h64).is_none());
+        assert!(factory::<u64>(zext_h64).is_some());
+        assert!(factory::<u32>(rev8_32).is_some());
+        assert!(factory::<u32>(rev8_64).is_none());
+        assert!(factory::<u64>(rev8_64).is_some());
+        assert!(factory::<u32>(bseti_32).is_none());
+        assert!(factory::<u64>(bseti_32).is_some());
+        // Plain RV64I instructions are left to other factories
+        assert!(factory::<u64>(encode(0, 12, 0b_000, 0b_0110011)).is_none());
+        assert!(factory::<u64>(encode(0, 3, 0b_001, 0b_0010011)).is_none());
+    }
+
+    #[test]
+    fn test_zba() {
+        let x = 0xffff_ffff_8000_0001u64;
+        assert_eq!(
+            run64(encode(0b_0000100, 12, 0b_000, 0b_0111011), x, 1),
+            0x8000_0002
+        );
+        assert_eq!(run64(encode(0b_0010000, 12, 0b_010, 0b_0110011), 3, 4), 10);
+        assert_eq!(run64(encode(0b_0010000, 12, 0b_100, 0b_0110011), 3, 4), 16);
+        assert_eq!(run64(encode(0b_0010000, 12, 0b_110, 0b_0110011), 3, 4), 28);
+        assert_eq!(
+            run64(encode(0b_0010000, 12, 0b_110, 0b_0111011), x, 0),
+            0x4_0000_0008
+        );
+        // slli.uw a0, a1, 4
+        assert_eq!(
+            run64(encode(0b_0000100, 4, 0b_001, 0b_0011011), x, 0),
+            0x8_0000_0010
+        );
+    }
+
+    #[test]
+    fn test_zbb() {
+        let op = 0b_0110011;
+        assert_eq!(
+            run64(encode(0b_0100000, 12, 0b_111, op), 0b_1100, 0b_1010),
+            0b_0100
+        );
+        assert_eq!(run64(encode(0b_0100000, 12, 0b_110, op), 0, 1), !1);
+        assert_eq!(run64(encode(0b_0100000, 12, 0b_100, op), 5, 3), !6);
+        assert_eq!(run64(encode(0b_0000101, 12, 0b_100, op), !0, 1), !0);
+        assert_eq!(run64(encode(0b_0000101, 12, 0b_101, op), !0, 1), 1);
+        assert_eq!(run64(encode(0b_0000101, 12, 0b_110, op), !0, 1), 1);
+        assert_eq!(run64(encode(0b_0000101, 12, 0b_111, op), !0, 1), !0);
+        assert_eq!(run64(encode(0b_0110000, 12, 0b_001, op), 1 << 63, 1), 1);
+        assert_eq!(run64(encode(0b_0110000, 12, 0b_101, op), 1, 65), 1 << 63);
+        // rolw / rorw / roriw sign extend 32-bit results
+        assert_eq!(
+            run64(encode(0b_0110000, 12, 0b_001, 0b_0111011), 0x4000_0000, 1),
+            0xffff_ffff_8000_0000
+        );
+        assert_eq!(run64(encode(0b_0110000, 12, 0b_101, 0b_0111011), 1, 32), 1);
+        assert_eq!(
+            run64(encode(0b_0110000, 4, 0b_101, 0b_0011011), 0x10, 0),
+            0x1
+        );
+        assert_eq!(run64(encode(0b_0110000, 4, 0b_101, 0b_0010011), 0x10, 0), 1);
+
+        let unary =
+            |rs2, funct3, opcode, value| run64(encode(0b_0110000, rs2, funct3, opcode), value, 0);
+        assert_eq!(unary(0, 0b_001, 0b_0010011, 1), 63);
+        assert_eq!(unary(0, 0b_001, 0b_0010011, 0), 64);
+        assert_eq!(unary(1, 0b_001, 0b_0010011, 0), 64);
+        assert_eq!(unary(1, 0b_001, 0b_0010011, 8), 3);
+        assert_eq!(unary(2, 0b_001, 0b_0010011, 0xff), 8);
+        assert_eq!(unary(4, 0b_001, 0b_0010011, 0x80), !0x7f);
+        assert_eq!(unary(5, 0b_001, 0b_0010011, 0x7fff), 0x7fff);
+        assert_eq!(unary(0, 0b_001, 0b_0011011, 0x1_0000_0001), 31);
+        assert_eq!(unary(1, 0b_001, 0b_0011011, 1 << 40), 32);
+        assert_eq!(unary(2, 0b_001, 0b_0011011, !0), 32);
+        assert_eq!(
+            run64(encode(0b_0000100, 0, 0b_100, 0b_0111011), 0x1234_5678, 0),
+            0x5678
+        );
+        assert_eq!(
+            run64(
+                encode(0b_0010100, 0b_00111, 0b_101, 0b_0010011),
+                0x0100_2000_0003,
+                0
+            ),
+            0xff00_ff00_00ff
+        );
+        assert_eq!(
+            run64(
+                encode(0b_0110101, 0b_11000, 0b_101, 0b_0010011),
+                0x0102_0304_0506_0708,
+                0
+            ),
+            0x0807_0605_0403_0201
+        );
+        assert_eq!(
+            run::<u32>(
+                encode(0b_0110100, 0b_11000, 0b_101, 0b_0010011),
+                0x0102_0304,
+                0
+            ),
+            0x0403_0201
+        );
+    }
+
+    #[test]
+    fn test_zbc() {
+        let op = 0b_0110011;
+        let x = 0x8000_0000_0000_0003u64;
+        let y = 0x8000_0000_0000_0005u64;
+        assert_eq!(run64(encode(0b_0000101, 12, 0b_001, op), x, y), 0xf);
+        assert_eq!(
+            run64(encode(0b_0000101, 12, 0b_011, op), x, y),
+            0x4000_0000_0000_0003
+        );
+        assert_eq!(
+            run64(encode(0b_0000101, 12, 0b_010, op), x, y),
+            0x8000_0000_0000_0006
+        );
+        assert_eq!(
+            run::<u32>(encode(0b_0000101, 12, 0b_011, op), 0x8000_0003, 0x8000_0005),
+            0x4000_0003
+        );
+    }
+
+    #[test]
+    fn test_zbs() {
+        let op = 0b_0110011;
+        let opi = 0b_0010011;
+        assert_eq!(
+            run64(encode(0b_0100100, 12, 0b_001, op), 0xff, 64 + 3),
+            0xf7
+        );
+        assert_eq!(run64(encode(0b_0100100, 12, 0b_101, op), 0x10, 4), 1);
+        assert_eq!(run64(encode(0b_0110100, 12, 0b_001, op), 0x10, 4), 0);
+        assert_eq!(run64(encode(0b_0010100, 12, 0b_001, op), 0, 63), 1 << 63);
+        assert_eq!(run64(encode(0b_0100101, 3, 0b_001, opi), !0, 0), !(1 << 35));
+        assert_eq!(run64(encode(0b_0100100, 3, 0b_101, opi), 8, 0), 1);
+        assert_eq!(run64(encode(0b_0110100, 3, 0b_001, opi), 8, 0), 0);
+        assert_eq!(run64(encode(0b_0010100, 3, 0b_001, opi), 0, 0), 8);
+    }
+}
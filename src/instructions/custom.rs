@@ -0,0 +1,93 @@
+use super::{
+    super::{machine::Machine, Error},
+    extract_opcode, insts, Instruction, Register, Utype,
+};
+
+// Major opcodes RISC-V reserves for custom extensions
+pub const CUSTOM_0: u32 = 0b000_1011;
+pub const CUSTOM_1: u32 = 0b010_1011;
+
+// Ids factory gives to instructions of each custom opcode space, ids
+// picked by other factories should not collide with them.
+pub const CUSTOM_0_ID: u8 = 0;
+pub const CUSTOM_1_ID: u8 = 1;
+
+// Instructions decoded by factories supplied by embedders all share
+// OP_CUSTOM. The factory picks an id telling which handler registered on
+// the machine executes the instruction, the raw bits are kept so the
+// handler can extract its operands. Custom instructions are 32 bits long
+// and end basic blocks, so handlers are free to change pc.
+//
+// A factory for the custom-0 opcode space looks like:
+//
+//     fn factory(bits: u32) -> Option<Instruction> {
+//         if bits & 0x7f == 0x0b {
+//             Some(custom::pack(MY_ID, bits))
+//         } else {
+//             None
+//         }
+//     }
+pub fn pack(id: u8, bits: u32) -> Instruction {
+    Utype::new(insts::OP_CUSTOM, id as usize, bits).0
+}
+
+// Handler id of a custom instruction, see pack
+pub fn id(inst: Instruction) -> u8 {
+    Utype(inst).rd() as u8
+}
+
+// Raw bits a custom instruction was decoded from, see pack
+pub fn bits(inst: Instruction) -> u32 {
+    Utype(inst).immediate()
+}
+
+pub fn is_custom(inst: Instruction) -> bool {
+    extract_opcode(inst) == insts::OP_CUSTOM
+}
+
+// Decodes the whole custom-0 and custom-1 opcode spaces, leaving the
+// meaning of the remaining bits to handlers registered with CUSTOM_0_ID and
+// CUSTOM_1_ID, e.g. to accelerate hashing in the host.
+pub fn factory<R: Register>(instruction_bits: u32) -> Option<Instruction> {
+    match instruction_bits & 0x7f {
+        CUSTOM_0 => Some(pack(CUSTOM_0_ID, instruction_bits)),
+        CUSTOM_1 => Some(pack(CUSTOM_1_ID, instruction_bits)),
+        _ => None,
+    }
+}
+
+pub fn execute<Mac: Machine>(
+    inst: Instruction,
+    machine: &mut Mac,
+) -> Result<Option<Mac::REG>, Error> {
+    machine.execute_custom(id(inst), bits(inst))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pack() {
+        let inst = pack(7, 0x8000_000b);
+        assert!(is_custom(inst));
+        assert_eq!(id(inst), 7);
+        assert_eq!(bits(inst), 0x8000_000b);
+        assert!(!is_custom(insts::OP_ADD as Instruction));
+    }
+
+    #[test]
+    fn test_factory() {
+        assert_eq!(
+            factory::<u64>(0x00b5060b),
+            Some(pack(CUSTOM_0_ID, 0x00b5060b))
+        );
+        assert_eq!(
+            factory::<u64>(0x00b5062b),
+            Some(pack(CUSTOM_1_ID, 0x00b5062b))
+        );
+        // custom-2 and custom-3 overlap with RV128 encodings
+        assert_eq!(factory::<u64>(0x00b5065b), None);
+        assert_eq!(factory::<u64>(0x00b50633), None);
+    }
+}
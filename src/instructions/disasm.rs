@@ -0,0 +1,827 @@
+use super::super::decoder::Decoder;
+use super::super::memory::Memory;
+use super::super::registers::REGISTER_ABI_NAMES;
+use super::{
+    extract_opcode, instruction_length, Instruction, InstructionOpcode, Itype, R4type, Register,
+    Rtype, Stype, Utype, INSTRUCTION_OPCODE_NAMES,
+};
+use crate::Error;
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use ckb_vm_definitions::instructions as insts;
+
+fn register_name(index: usize) -> &'static str {
+    REGISTER_ABI_NAMES[index % REGISTER_ABI_NAMES.len()]
+}
+
+#[rustfmt::skip]
+const FLOAT_REGISTER_ABI_NAMES: [&str; 32] = [
+    "ft0", "ft1", "ft2", "ft3", "ft4", "ft5", "ft6", "ft7",
+    "fs0", "fs1", "fa0", "fa1", "fa2", "fa3", "fa4", "fa5",
+    "fa6", "fa7", "fs2", "fs3", "fs4", "fs5", "fs6", "fs7",
+    "fs8", "fs9", "fs10", "fs11", "ft8", "ft9", "ft10", "ft11",
+];
+
+fn float_register_name(index: usize) -> &'static str {
+    FLOAT_REGISTER_ABI_NAMES[index % FLOAT_REGISTER_ABI_NAMES.len()]
+}
+
+// Static rounding modes are appended as an extra operand, the dynamic one is
+// omitted like assemblers do.
+fn rounding_mode(rm: u8) -> &'static str {
+    match rm {
+        0 => ", rne",
+        1 => ", rtz",
+        2 => ", rdn",
+        3 => ", rup",
+        4 => ", rmm",
+        _ => "",
+    }
+}
+
+fn vector_register_name(index: usize) -> String {
+    format!("v{}", index % 32)
+}
+
+// vtype immediates are shown the way assemblers accept them, such as
+// "e32, m1, ta, ma", reserved encodings are shown as raw numbers.
+fn vtype_name(vtype: u32) -> String {
+    let lmul = match vtype & 0b_111 {
+        0b_000 => "m1",
+        0b_001 => "m2",
+        0b_010 => "m4",
+        0b_011 => "m8",
+        0b_101 => "mf8",
+        0b_110 => "mf4",
+        0b_111 => "mf2",
+        _ => return format!("{:#x}", vtype),
+    };
+    let vsew = (vtype >> 3) & 0b_111;
+    if vtype >> 8 != 0 || vsew > 3 {
+        return format!("{:#x}", vtype);
+    }
+    format!(
+        "e{}, {}, {}, {}",
+        8 << vsew,
+        lmul,
+        if vtype & 0x40 != 0 { "ta" } else { "tu" },
+        if vtype & 0x80 != 0 { "ma" } else { "mu" }
+    )
+}
+
+fn csr_name(csr: u32) -> String {
+    match csr {
+        0x001 => "fflags".to_string(),
+        0x002 => "frm".to_string(),
+        0x003 => "fcsr".to_string(),
+        0xC00 => "cycle".to_string(),
+        0xC01 => "time".to_string(),
+        0xC02 => "instret".to_string(),
+        0xC80 => "cycleh".to_string(),
+        0xC81 => "timeh".to_string(),
+        0xC82 => "instreth".to_string(),
+        _ => format!("{:#x}", csr),
+    }
+}
+
+// Floating point opcode names carry the operand formats as suffixes, such
+// as FCVTWUS for fcvt.wu.s or FMADDD for fmadd.d.
+fn float_mnemonic(name: &str) -> String {
+    let name = name.to_lowercase();
+    let (prefix, formats) = if name.starts_with("fcvt") {
+        name.split_at(4)
+    } else if name.starts_with("fmv") {
+        name.split_at(3)
+    } else {
+        name.split_at(name.len() - 1)
+    };
+    let mut result = prefix.to_string();
+    let mut chars = formats.chars().peekable();
+    while let Some(c) = chars.next() {
+        result.push('.');
+        result.push(c);
+        if chars.peek() == Some(&'u') {
+            result.push(chars.next().unwrap());
+        }
+    }
+    result
+}
+
+// Mnemonics are derived from opcode names, RVC instructions use the
+// standard `c.` prefix.
+fn mnemonic(op: InstructionOpcode) -> String {
+    match INSTRUCTION_OPCODE_NAMES.get(op as usize) {
+        Some(_) if op == insts::OP_FENCEI => "fence.i".to_string(),
+        Some(_) if op == insts::OP_ADDUW => "add.uw".to_string(),
+        Some(_) if op == insts::OP_SH1ADDUW => "sh1add.uw".to_string(),
+        Some(_) if op == insts::OP_SH2ADDUW => "sh2add.uw".to_string(),
+        Some(_) if op == insts::OP_SH3ADDUW => "sh3add.uw".to_string(),
+        Some(_) if op == insts::OP_SLLIUW => "slli.uw".to_string(),
+        Some(_) if op == insts::OP_ORCB => "orc.b".to_string(),
+        Some(_) if op == insts::OP_SEXTB => "sext.b".to_string(),
+        Some(_) if op == insts::OP_SEXTH => "sext.h".to_string(),
+        Some(_) if op == insts::OP_ZEXTH => "zext.h".to_string(),
+        Some(name)
+            if (insts::OP_FMADDS..=insts::OP_FMVDX).contains(&op)
+                && op != insts::OP_FLD
+                && op != insts::OP_FSD =>
+        {
+            float_mnemonic(name)
+        }
+        Some(name) if (insts::OP_LR_W..=insts::OP_AMOMAXU_D).contains(&op) => {
+            name.to_lowercase().replace('_', ".")
+        }
+        Some(name) if name.starts_with("RVC_") => format!("c.{}", name[4..].to_lowercase()),
+        Some(name) => name.to_lowercase(),
+        None => format!("unknown.{}", op),
+    }
+}
+
+// FENCE predecessor and successor sets are rendered in the same IORW
+// notation used by assemblers.
+fn fence_set(bits: usize) -> String {
+    let set: String = ['i', 'o', 'r', 'w']
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| bits & (0b1000 >> i) != 0)
+        .map(|(_, c)| *c)
+        .collect();
+    if set.is_empty() {
+        "0".to_string()
+    } else {
+        set
+    }
+}
+
+// Renders a decoded instruction as RISC-V assembly text. Branch and jump
+// targets are shown as offsets relative to the instruction's own address,
+// since the decoded instruction contains no pc information.
+pub fn disassemble(inst: Instruction) -> String {
+    let op = extract_opcode(inst);
+    let name = mnemonic(op);
+    match op {
+        insts::OP_ADD
+        | insts::OP_ADDW
+        | insts::OP_AND
+        | insts::OP_DIV
+        | insts::OP_DIVU
+        | insts::OP_DIVUW
+        | insts::OP_DIVW
+        | insts::OP_MUL
+        | insts::OP_MULH
+        | insts::OP_MULHSU
+        | insts::OP_MULHU
+        | insts::OP_MULW
+        | insts::OP_OR
+        | insts::OP_REM
+        | insts::OP_REMU
+        | insts::OP_REMUW
+        | insts::OP_REMW
+        | insts::OP_SLL
+        | insts::OP_SLLW
+        | insts::OP_SLT
+        | insts::OP_SLTU
+        | insts::OP_SRA
+        | insts::OP_SRAW
+        | insts::OP_SRL
+        | insts::OP_SRLW
+        | insts::OP_SUB
+        | insts::OP_SUBW
+        | insts::OP_XOR
+        | insts::OP_ADDUW
+        | insts::OP_ANDN
+        | insts::OP_BCLR
+        | insts::OP_BEXT
+        | insts::OP_BINV
+        | insts::OP_BSET
+        | insts::OP_CLMUL
+        | insts::OP_CLMULH
+        | insts::OP_CLMULR
+        | insts::OP_MAX
+        | insts::OP_MAXU
+        | insts::OP_MIN
+        | insts::OP_MINU
+        | insts::OP_ORN
+        | insts::OP_ROL
+        | insts::OP_ROLW
+        | insts::OP_ROR
+        | insts::OP_RORW
+        | insts::OP_SH1ADD
+        | insts::OP_SH1ADDUW
+        | insts::OP_SH2ADD
+        | insts::OP_SH2ADDUW
+        | insts::OP_SH3ADD
+        | insts::OP_SH3ADDUW
+        | insts::OP_XNOR => {
+            let i = Rtype(inst);
+            format!(
+                "{} {}, {}, {}",
+                name,
+                register_name(i.rd()),
+                register_name(i.rs1()),
+                register_name(i.rs2())
+            )
+        }
+        insts::OP_ADDI
+        | insts::OP_ADDIW
+        | insts::OP_ANDI
+        | insts::OP_ORI
+        | insts::OP_XORI
+        | insts::OP_SLTI
+        | insts::OP_SLTIU
+        | insts::OP_SLLI
+        | insts::OP_SRLI
+        | insts::OP_SRAI
+        | insts::OP_SLLIW
+        | insts::OP_SRLIW
+        | insts::OP_SRAIW
+        | insts::OP_BCLRI
+        | insts::OP_BEXTI
+        | insts::OP_BINVI
+        | insts::OP_BSETI
+        | insts::OP_RORI
+        | insts::OP_RORIW
+        | insts::OP_SLLIUW => {
+            let i = Itype(inst);
+            format!(
+                "{} {}, {}, {}",
+                name,
+                register_name(i.rd()),
+                register_name(i.rs1()),
+                i.immediate_s()
+            )
+        }
+        insts::OP_CLZ
+        | insts::OP_CLZW
+        | insts::OP_CPOP
+        | insts::OP_CPOPW
+        | insts::OP_CTZ
+        | insts::OP_CTZW
+        | insts::OP_ORCB
+        | insts::OP_REV8
+        | insts::OP_SEXTB
+        | insts::OP_SEXTH
+        | insts::OP_ZEXTH => {
+            let i = Rtype(inst);
+            format!(
+                "{} {}, {}",
+                name,
+                register_name(i.rd()),
+                register_name(i.rs1())
+            )
+        }
+        insts::OP_LB
+        | insts::OP_LBU
+        | insts::OP_LD
+        | insts::OP_LH
+        | insts::OP_LHU
+        | insts::OP_LW
+        | insts::OP_LWU
+        | insts::OP_JALR
+        | insts::OP_RVC_LW
+        | insts::OP_RVC_LD => {
+            let i = Itype(inst);
+            format!(
+                "{} {}, {}({})",
+                name,
+                register_name(i.rd()),
+                i.immediate_s(),
+                register_name(i.rs1())
+            )
+        }
+        insts::OP_SB
+        | insts::OP_SH
+        | insts::OP_SW
+        | insts::OP_SD
+        | insts::OP_RVC_SW
+        | insts::OP_RVC_SD => {
+            let i = Stype(inst);
+            format!(
+                "{} {}, {}({})",
+                name,
+                register_name(i.rs2()),
+                i.immediate_s(),
+                register_name(i.rs1())
+            )
+        }
+        insts::OP_BEQ
+        | insts::OP_BNE
+        | insts::OP_BLT
+        | insts::OP_BGE
+        | insts::OP_BLTU
+        | insts::OP_BGEU => {
+            let i = Stype(inst);
+            format!(
+                "{} {}, {}, {}",
+                name,
+                register_name(i.rs1()),
+                register_name(i.rs2()),
+                i.immediate_s()
+            )
+        }
+        insts::OP_LUI | insts::OP_AUIPC | insts::OP_RVC_LUI => {
+            let i = Utype(inst);
+            format!(
+                "{} {}, {:#x}",
+                name,
+                register_name(i.rd()),
+                i.immediate() >> 12
+            )
+        }
+        insts::OP_JAL | insts::OP_RVC_LI | insts::OP_CUSTOM_LOAD_IMM => {
+            let i = Utype(inst);
+            format!("{} {}, {}", name, register_name(i.rd()), i.immediate_s())
+        }
+        insts::OP_FENCE => {
+            let i = Rtype(inst);
+            format!("{} {}, {}", name, fence_set(i.rs1()), fence_set(i.rs2()))
+        }
+        insts::OP_RVC_ADD
+        | insts::OP_RVC_SUB
+        | insts::OP_RVC_XOR
+        | insts::OP_RVC_OR
+        | insts::OP_RVC_AND
+        | insts::OP_RVC_SUBW
+        | insts::OP_RVC_ADDW
+        | insts::OP_RVC_MV => {
+            let i = Rtype(inst);
+            format!(
+                "{} {}, {}",
+                name,
+                register_name(i.rd()),
+                register_name(i.rs2())
+            )
+        }
+        insts::OP_RVC_ADDI
+        | insts::OP_RVC_ANDI
+        | insts::OP_RVC_ADDIW
+        | insts::OP_RVC_SLLI
+        | insts::OP_RVC_SRLI
+        | insts::OP_RVC_SRAI => {
+            let i = Itype(inst);
+            format!("{} {}, {}", name, register_name(i.rd()), i.immediate_s())
+        }
+        insts::OP_RVC_ADDI4SPN => {
+            let i = Utype(inst);
+            format!("{} {}, sp, {}", name, register_name(i.rd()), i.immediate())
+        }
+        insts::OP_RVC_ADDI16SP => {
+            let i = Itype(inst);
+            format!("{} sp, {}", name, i.immediate_s())
+        }
+        insts::OP_RVC_LWSP | insts::OP_RVC_LDSP => {
+            let i = Utype(inst);
+            format!("{} {}, {}(sp)", name, register_name(i.rd()), i.immediate())
+        }
+        insts::OP_RVC_SWSP | insts::OP_RVC_SDSP => {
+            let i = Stype(inst);
+            format!("{} {}, {}(sp)", name, register_name(i.rs2()), i.immediate())
+        }
+        insts::OP_RVC_BEQZ | insts::OP_RVC_BNEZ => {
+            let i = Stype(inst);
+            format!("{} {}, {}", name, register_name(i.rs1()), i.immediate_s())
+        }
+        insts::OP_RVC_J | insts::OP_RVC_JAL => {
+            let i = Utype(inst);
+            format!("{} {}", name, i.immediate_s())
+        }
+        insts::OP_RVC_JR | insts::OP_RVC_JALR => {
+            let i = Stype(inst);
+            format!("{} {}", name, register_name(i.rs1()))
+        }
+        insts::OP_FLW | insts::OP_FLD | insts::OP_RVC_FLD => {
+            let i = Itype(inst);
+            format!(
+                "{} {}, {}({})",
+                name,
+                float_register_name(i.rd()),
+                i.immediate_s(),
+                register_name(i.rs1())
+            )
+        }
+        insts::OP_FSW | insts::OP_FSD | insts::OP_RVC_FSD => {
+            let i = Stype(inst);
+            format!(
+                "{} {}, {}({})",
+                name,
+                float_register_name(i.rs2()),
+                i.immediate_s(),
+                register_name(i.rs1())
+            )
+        }
+        insts::OP_RVC_FLDSP => {
+            let i = Utype(inst);
+            format!(
+                "{} {}, {}(sp)",
+                name,
+                float_register_name(i.rd()),
+                i.immediate()
+            )
+        }
+        insts::OP_RVC_FSDSP => {
+            let i = Stype(inst);
+            format!(
+                "{} {}, {}(sp)",
+                name,
+                float_register_name(i.rs2()),
+                i.immediate()
+            )
+        }
+        insts::OP_FADDS
+        | insts::OP_FSUBS
+        | insts::OP_FMULS
+        | insts::OP_FDIVS
+        | insts::OP_FADDD
+        | insts::OP_FSUBD
+        | insts::OP_FMULD
+        | insts::OP_FDIVD => {
+            let i = R4type(inst);
+            format!(
+                "{} {}, {}, {}{}",
+                name,
+                float_register_name(i.rd()),
+                float_register_name(i.rs1()),
+                float_register_name(i.rs2()),
+                rounding_mode(i.rm())
+            )
+        }
+        insts::OP_FSGNJS
+        | insts::OP_FSGNJNS
+        | insts::OP_FSGNJXS
+        | insts::OP_FMINS
+        | insts::OP_FMAXS
+        | insts::OP_FSGNJD
+        | insts::OP_FSGNJND
+        | insts::OP_FSGNJXD
+        | insts::OP_FMIND
+        | insts::OP_FMAXD => {
+            let i = R4type(inst);
+            format!(
+                "{} {}, {}, {}",
+                name,
+                float_register_name(i.rd()),
+                float_register_name(i.rs1()),
+                float_register_name(i.rs2())
+            )
+        }
+        insts::OP_FMADDS
+        | insts::OP_FMSUBS
+        | insts::OP_FNMSUBS
+        | insts::OP_FNMADDS
+        | insts::OP_FMADDD
+        | insts::OP_FMSUBD
+        | insts::OP_FNMSUBD
+        | insts::OP_FNMADDD => {
+            let i = R4type(inst);
+            format!(
+                "{} {}, {}, {}, {}{}",
+                name,
+                float_register_name(i.rd()),
+                float_register_name(i.rs1()),
+                float_register_name(i.rs2()),
+                float_register_name(i.rs3()),
+                rounding_mode(i.rm())
+            )
+        }
+        insts::OP_FSQRTS | insts::OP_FSQRTD | insts::OP_FCVTSD | insts::OP_FCVTDS => {
+            let i = R4type(inst);
+            format!(
+                "{} {}, {}{}",
+                name,
+                float_register_name(i.rd()),
+                float_register_name(i.rs1()),
+                rounding_mode(i.rm())
+            )
+        }
+        insts::OP_FCVTWS
+        | insts::OP_FCVTWUS
+        | insts::OP_FCVTLS
+        | insts::OP_FCVTLUS
+        | insts::OP_FCVTWD
+        | insts::OP_FCVTWUD
+        | insts::OP_FCVTLD
+        | insts::OP_FCVTLUD => {
+            let i = R4type(inst);
+            format!(
+                "{} {}, {}{}",
+                name,
+                register_name(i.rd()),
+                float_register_name(i.rs1()),
+                rounding_mode(i.rm())
+            )
+        }
+        insts::OP_FCVTSW
+        | insts::OP_FCVTSWU
+        | insts::OP_FCVTSL
+        | insts::OP_FCVTSLU
+        | insts::OP_FCVTDW
+        | insts::OP_FCVTDWU
+        | insts::OP_FCVTDL
+        | insts::OP_FCVTDLU => {
+            let i = R4type(inst);
+            format!(
+                "{} {}, {}{}",
+                name,
+                float_register_name(i.rd()),
+                register_name(i.rs1()),
+                rounding_mode(i.rm())
+            )
+        }
+        insts::OP_FMVXW | insts::OP_FMVXD | insts::OP_FCLASSS | insts::OP_FCLASSD => {
+            let i = R4type(inst);
+            format!(
+                "{} {}, {}",
+                name,
+                register_name(i.rd()),
+                float_register_name(i.rs1())
+            )
+        }
+        insts::OP_FMVWX | insts::OP_FMVDX => {
+            let i = R4type(inst);
+            format!(
+                "{} {}, {}",
+                name,
+                float_register_name(i.rd()),
+                register_name(i.rs1())
+            )
+        }
+        insts::OP_FEQS
+        | insts::OP_FLTS
+        | insts::OP_FLES
+        | insts::OP_FEQD
+        | insts::OP_FLTD
+        | insts::OP_FLED => {
+            let i = R4type(inst);
+            format!(
+                "{} {}, {}, {}",
+                name,
+                register_name(i.rd()),
+                float_register_name(i.rs1()),
+                float_register_name(i.rs2())
+            )
+        }
+        insts::OP_CSRRW | insts::OP_CSRRS | insts::OP_CSRRC => {
+            let i = Itype(inst);
+            format!(
+                "{} {}, {}, {}",
+                name,
+                register_name(i.rd()),
+                csr_name(i.immediate()),
+                register_name(i.rs1())
+            )
+        }
+        insts::OP_CSRRWI | insts::OP_CSRRSI | insts::OP_CSRRCI => {
+            let i = Itype(inst);
+            format!(
+                "{} {}, {}, {}",
+                name,
+                register_name(i.rd()),
+                csr_name(i.immediate()),
+                i.rs1()
+            )
+        }
+        insts::OP_LR_W | insts::OP_LR_D => {
+            let i = Rtype(inst);
+            format!(
+                "{} {}, ({})",
+                name,
+                register_name(i.rd()),
+                register_name(i.rs1())
+            )
+        }
+        insts::OP_SC_W..=insts::OP_AMOMAXU_W | insts::OP_SC_D..=insts::OP_AMOMAXU_D => {
+            let i = Rtype(inst);
+            format!(
+                "{} {}, {}, ({})",
+                name,
+                register_name(i.rd()),
+                register_name(i.rs2()),
+                register_name(i.rs1())
+            )
+        }
+        insts::OP_VSETVLI | insts::OP_VSETIVLI => {
+            let i = Itype(inst);
+            let avl = if op == insts::OP_VSETVLI {
+                register_name(i.rs1()).to_string()
+            } else {
+                i.rs1().to_string()
+            };
+            format!(
+                "{} {}, {}, {}",
+                name,
+                register_name(i.rd()),
+                avl,
+                vtype_name(i.immediate())
+            )
+        }
+        insts::OP_VSETVL => {
+            let i = Rtype(inst);
+            format!(
+                "{} {}, {}, {}",
+                name,
+                register_name(i.rd()),
+                register_name(i.rs1()),
+                register_name(i.rs2())
+            )
+        }
+        insts::OP_VLE | insts::OP_VSE => {
+            let i = Itype(inst);
+            format!(
+                "{}{}.v {}, ({})",
+                name,
+                i.immediate(),
+                vector_register_name(i.rd()),
+                register_name(i.rs1())
+            )
+        }
+        insts::OP_VADD..=insts::OP_VMV => {
+            // rm holds the operand form, see FORM_VV, FORM_VX and FORM_VI
+            // in the v module
+            let i = R4type(inst);
+            let (suffix, operand) = match i.rm() {
+                0 => ("v", vector_register_name(i.rs1())),
+                1 => ("x", register_name(i.rs1()).to_string()),
+                _ => ("i", (i.rs1() as u8 as i8).to_string()),
+            };
+            if op == insts::OP_VMV {
+                format!(
+                    "{}.v.{} {}, {}",
+                    name,
+                    suffix,
+                    vector_register_name(i.rd()),
+                    operand
+                )
+            } else {
+                format!(
+                    "{}.v{} {}, {}, {}",
+                    name,
+                    suffix,
+                    vector_register_name(i.rd()),
+                    vector_register_name(i.rs2()),
+                    operand
+                )
+            }
+        }
+        insts::OP_CUSTOM => format!(
+            "{}.{} {:#010x}",
+            name,
+            super::custom::id(inst),
+            super::custom::bits(inst)
+        ),
+        _ => name,
+    }
+}
+
+// Decodes and disassembles instructions starting from pc, stopping either
+// after count instructions or at the end of current basic block, which is
+// the same unit TraceMachine caches.
+pub fn disassemble_block<R: Register, M: Memory<R>>(
+    decoder: &Decoder,
+    memory: &mut M,
+    pc: u64,
+    count: usize,
+) -> Result<Vec<(u64, String)>, Error> {
+    let mut result = Vec::with_capacity(count);
+    let mut current_pc = pc;
+    while result.len() < count {
+        let instruction = decoder.decode(memory, current_pc)?;
+        result.push((current_pc, disassemble(instruction)));
+        if super::is_basic_block_end_instruction(instruction) {
+            break;
+        }
+        current_pc += u64::from(instruction_length(instruction));
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{a, b, i, m, rvc};
+    use super::*;
+    use crate::decoder::build_imac_decoder;
+    use crate::memory::{FLAG_EXECUTABLE, FLAG_FREEZED};
+    use crate::{SparseMemory, WXorXMemory};
+    use bytes::Bytes;
+
+    fn assert_disassemble(factory: fn(u32) -> Option<Instruction>, bits: u32, text: &str) {
+        let instruction = factory(bits).expect("decoding");
+        assert_eq!(disassemble(instruction), text);
+    }
+
+    #[test]
+    fn test_disassemble_i() {
+        let f = i::factory::<u64>;
+        assert_disassemble(f, 0x0045_8513, "addi a0, a1, 4");
+        assert_disassemble(f, 0x0002_80e7, "jalr ra, 0(t0)");
+        assert_disassemble(f, 0xff81_2503, "lw a0, -8(sp)");
+        assert_disassemble(f, 0x0011_3423, "sd ra, 8(sp)");
+        assert_disassemble(f, 0xfe05_0ee3, "beq a0, zero, -4");
+        assert_disassemble(f, 0x1234_5537, "lui a0, 0x12345");
+        assert_disassemble(f, 0x0000_0073, "ecall");
+        assert_disassemble(f, 0x0000_100f, "fence.i");
+        assert_disassemble(f, 0x0ff0_000f, "fence iorw, iorw");
+    }
+
+    #[test]
+    fn test_disassemble_m() {
+        assert_disassemble(m::factory::<u64>, 0x02c5_8533, "mul a0, a1, a2");
+    }
+
+    #[test]
+    fn test_disassemble_a() {
+        let f = a::factory::<u64>;
+        assert_disassemble(f, 0x1005_a52f, "lr.w a0, (a1)");
+        assert_disassemble(f, 0x18c5_b52f, "sc.d a0, a2, (a1)");
+        assert_disassemble(f, 0x00c5_a52f, "amoadd.w a0, a2, (a1)");
+        assert_disassemble(f, 0xe6c5_b52f, "amomaxu.d a0, a2, (a1)");
+    }
+
+    #[test]
+    fn test_disassemble_zicntr() {
+        let f = super::super::zicntr::factory::<u64>;
+        assert_disassemble(f, 0xc000_2573, "csrrs a0, cycle, zero");
+        assert_disassemble(f, 0xc020_65f3, "csrrsi a1, instret, 0");
+    }
+
+    #[test]
+    fn test_disassemble_b() {
+        let f = b::factory::<u64>;
+        assert_disassemble(f, 0x40c5_f533, "andn a0, a1, a2");
+        assert_disassemble(f, 0x08c5_853b, "add.uw a0, a1, a2");
+        assert_disassemble(f, 0x6005_9513, "clz a0, a1");
+        assert_disassemble(f, 0x6b85_d513, "rev8 a0, a1");
+        assert_disassemble(f, 0x2875_d513, "orc.b a0, a1");
+        assert_disassemble(f, 0x4855_9513, "bclri a0, a1, 5");
+    }
+
+    #[cfg(feature = "fd")]
+    #[test]
+    fn test_disassemble_fd() {
+        let f = super::super::fd::factory::<u64>;
+        assert_disassemble(f, 0x02c5_f553, "fadd.d fa0, fa1, fa2");
+        assert_disassemble(f, 0x68c5_8543, "fmadd.s fa0, fa1, fa2, fa3, rne");
+        assert_disassemble(f, 0xc005_9553, "fcvt.w.s a0, fa1, rtz");
+        assert_disassemble(f, 0xc015_9553, "fcvt.wu.s a0, fa1, rtz");
+        assert_disassemble(f, 0xe005_8553, "fmv.x.w a0, fa1");
+        assert_disassemble(f, 0x0085_b507, "fld fa0, 8(a1)");
+        assert_disassemble(f, 0x0010_2573, "csrrs a0, fflags, zero");
+        assert_disassemble(f, 0x2522, "c.fldsp fa0, 8(sp)");
+    }
+
+    #[cfg(feature = "v-ext")]
+    #[test]
+    fn test_disassemble_v() {
+        let f = super::super::v::factory::<u64>;
+        assert_disassemble(f, 0x0d05_f557, "vsetvli a0, a1, e32, m1, ta, ma");
+        assert_disassemble(f, 0xcd02_7557, "vsetivli a0, 4, e32, m1, ta, ma");
+        assert_disassemble(f, 0x0205_6087, "vle32.v v1, (a0)");
+        assert_disassemble(f, 0x0221_80d7, "vadd.vv v1, v2, v3");
+        assert_disassemble(f, 0x9625_60d7, "vmul.vx v1, v2, a0");
+        assert_disassemble(f, 0x5e0e_b257, "vmv.v.i v4, -3");
+    }
+
+    #[test]
+    fn test_disassemble_rvc() {
+        let f = rvc::factory::<u64>;
+        assert_disassemble(f, 0x0505, "c.addi a0, 1");
+        assert_disassemble(f, 0x4501, "c.li a0, 0");
+        assert_disassemble(f, 0x852e, "c.mv a0, a1");
+        assert_disassemble(f, 0x8082, "c.jr ra");
+        assert_disassemble(f, 0xe406, "c.sdsp ra, 8(sp)");
+    }
+
+    #[test]
+    fn test_disassemble_block() {
+        let code: Vec<u8> = vec![
+            0x13, 0x85, 0x45, 0x00, // addi a0, a1, 4
+            0x05, 0x05, // c.addi a0, 1
+            0x82, 0x80, // c.jr ra
+            0x73, 0x00, 0x00, 0x00, // ecall
+        ];
+        let mut memory = WXorXMemory::<u64, SparseMemory<u64>>::default();
+        memory
+            .init_pages(
+                0,
+                4096,
+                FLAG_EXECUTABLE | FLAG_FREEZED,
+                Some(Bytes::from(code)),
+                0,
+            )
+            .unwrap();
+        let decoder = build_imac_decoder::<u64>();
+        let result = disassemble_block(&decoder, &mut memory, 0, 16).unwrap();
+        assert_eq!(
+            result,
+            vec![
+                (0, "addi a0, a1, 4".to_string()),
+                (4, "c.addi a0, 1".to_string()),
+                (6, "c.jr ra".to_string()),
+            ]
+        );
+        let result = disassemble_block(&decoder, &mut memory, 0, 1).unwrap();
+        assert_eq!(result, vec![(0, "addi a0, a1, 4".to_string())]);
+    }
+}
@@ -340,7 +340,10 @@ pub fn execute<Mac: Machine>(inst: Instruction, machine: &mut Mac) -> Result<(),
             machine.ebreak()?;
             None
         }
-        insts::OP_FENCEI => None,
+        insts::OP_FENCEI => {
+            machine.fence_i();
+            None
+        }
         insts::OP_FENCE => None,
         insts::OP_JAL => {
             let i = Utype(inst);
@@ -658,6 +661,354 @@ pub fn execute<Mac: Machine>(inst: Instruction, machine: &mut Mac) -> Result<(),
             update_register(machine, i.rd(), value);
             None
         }
+        insts::OP_ADDUW => {
+            let i = Rtype(inst);
+            let rs1_value = &machine.registers()[i.rs1()];
+            let rs2_value = &machine.registers()[i.rs2()];
+            let value = rs1_value
+                .zero_extend(&Mac::REG::from_u8(32))
+                .overflowing_add(rs2_value);
+            update_register(machine, i.rd(), value);
+            None
+        }
+        insts::OP_ANDN => {
+            let i = Rtype(inst);
+            let rs1_value = machine.registers()[i.rs1()].clone();
+            let rs2_value = machine.registers()[i.rs2()].clone();
+            update_register(machine, i.rd(), rs1_value & !rs2_value);
+            None
+        }
+        insts::OP_ORN => {
+            let i = Rtype(inst);
+            let rs1_value = machine.registers()[i.rs1()].clone();
+            let rs2_value = machine.registers()[i.rs2()].clone();
+            update_register(machine, i.rd(), rs1_value | !rs2_value);
+            None
+        }
+        insts::OP_XNOR => {
+            let i = Rtype(inst);
+            let rs1_value = machine.registers()[i.rs1()].clone();
+            let rs2_value = machine.registers()[i.rs2()].clone();
+            update_register(machine, i.rd(), !(rs1_value ^ rs2_value));
+            None
+        }
+        insts::OP_BCLR => {
+            let i = Rtype(inst);
+            let shift_value =
+                machine.registers()[i.rs2()].clone() & Mac::REG::from_u8(Mac::REG::SHIFT_MASK);
+            let value = machine.registers()[i.rs1()].clone() & !(Mac::REG::one() << shift_value);
+            update_register(machine, i.rd(), value);
+            None
+        }
+        insts::OP_BCLRI => {
+            let i = Itype(inst);
+            let shift_value = Mac::REG::from_u32(i.immediate());
+            let value = machine.registers()[i.rs1()].clone() & !(Mac::REG::one() << shift_value);
+            update_register(machine, i.rd(), value);
+            None
+        }
+        insts::OP_BEXT => {
+            let i = Rtype(inst);
+            let shift_value =
+                machine.registers()[i.rs2()].clone() & Mac::REG::from_u8(Mac::REG::SHIFT_MASK);
+            let value = (machine.registers()[i.rs1()].clone() >> shift_value) & Mac::REG::one();
+            update_register(machine, i.rd(), value);
+            None
+        }
+        insts::OP_BEXTI => {
+            let i = Itype(inst);
+            let shift_value = Mac::REG::from_u32(i.immediate());
+            let value = (machine.registers()[i.rs1()].clone() >> shift_value) & Mac::REG::one();
+            update_register(machine, i.rd(), value);
+            None
+        }
+        insts::OP_BINV => {
+            let i = Rtype(inst);
+            let shift_value =
+                machine.registers()[i.rs2()].clone() & Mac::REG::from_u8(Mac::REG::SHIFT_MASK);
+            let value = machine.registers()[i.rs1()].clone() ^ (Mac::REG::one() << shift_value);
+            update_register(machine, i.rd(), value);
+            None
+        }
+        insts::OP_BINVI => {
+            let i = Itype(inst);
+            let shift_value = Mac::REG::from_u32(i.immediate());
+            let value = machine.registers()[i.rs1()].clone() ^ (Mac::REG::one() << shift_value);
+            update_register(machine, i.rd(), value);
+            None
+        }
+        insts::OP_BSET => {
+            let i = Rtype(inst);
+            let shift_value =
+                machine.registers()[i.rs2()].clone() & Mac::REG::from_u8(Mac::REG::SHIFT_MASK);
+            let value = machine.registers()[i.rs1()].clone() | (Mac::REG::one() << shift_value);
+            update_register(machine, i.rd(), value);
+            None
+        }
+        insts::OP_BSETI => {
+            let i = Itype(inst);
+            let shift_value = Mac::REG::from_u32(i.immediate());
+            let value = machine.registers()[i.rs1()].clone() | (Mac::REG::one() << shift_value);
+            update_register(machine, i.rd(), value);
+            None
+        }
+        insts::OP_CLMUL => {
+            let i = Rtype(inst);
+            let rs1_value = &machine.registers()[i.rs1()];
+            let rs2_value = &machine.registers()[i.rs2()];
+            let value = rs1_value.clmul(rs2_value);
+            update_register(machine, i.rd(), value);
+            None
+        }
+        insts::OP_CLMULH => {
+            let i = Rtype(inst);
+            let rs1_value = &machine.registers()[i.rs1()];
+            let rs2_value = &machine.registers()[i.rs2()];
+            let value = rs1_value.clmulh(rs2_value);
+            update_register(machine, i.rd(), value);
+            None
+        }
+        insts::OP_CLMULR => {
+            let i = Rtype(inst);
+            let rs1_value = &machine.registers()[i.rs1()];
+            let rs2_value = &machine.registers()[i.rs2()];
+            let value = rs1_value.clmulr(rs2_value);
+            update_register(machine, i.rd(), value);
+            None
+        }
+        insts::OP_CLZ => {
+            let i = Rtype(inst);
+            let value = machine.registers()[i.rs1()].clz();
+            update_register(machine, i.rd(), value);
+            None
+        }
+        insts::OP_CLZW => {
+            let i = Rtype(inst);
+            let value = machine.registers()[i.rs1()]
+                .zero_extend(&Mac::REG::from_u8(32))
+                .clz()
+                .overflowing_sub(&Mac::REG::from_u8(32));
+            update_register(machine, i.rd(), value);
+            None
+        }
+        insts::OP_CPOP => {
+            let i = Rtype(inst);
+            let value = machine.registers()[i.rs1()].cpop();
+            update_register(machine, i.rd(), value);
+            None
+        }
+        insts::OP_CPOPW => {
+            let i = Rtype(inst);
+            let value = machine.registers()[i.rs1()]
+                .zero_extend(&Mac::REG::from_u8(32))
+                .cpop();
+            update_register(machine, i.rd(), value);
+            None
+        }
+        insts::OP_CTZ => {
+            let i = Rtype(inst);
+            let value = machine.registers()[i.rs1()].ctz();
+            update_register(machine, i.rd(), value);
+            None
+        }
+        insts::OP_CTZW => {
+            let i = Rtype(inst);
+            let value = (machine.registers()[i.rs1()].clone()
+                | (Mac::REG::one() << Mac::REG::from_u8(32)))
+            .ctz();
+            update_register(machine, i.rd(), value);
+            None
+        }
+        insts::OP_MAX => {
+            let i = Rtype(inst);
+            let rs1_value = &machine.registers()[i.rs1()];
+            let rs2_value = &machine.registers()[i.rs2()];
+            let value = rs1_value.lt_s(rs2_value).cond(rs2_value, rs1_value);
+            update_register(machine, i.rd(), value);
+            None
+        }
+        insts::OP_MAXU => {
+            let i = Rtype(inst);
+            let rs1_value = &machine.registers()[i.rs1()];
+            let rs2_value = &machine.registers()[i.rs2()];
+            let value = rs1_value.lt(rs2_value).cond(rs2_value, rs1_value);
+            update_register(machine, i.rd(), value);
+            None
+        }
+        insts::OP_MIN => {
+            let i = Rtype(inst);
+            let rs1_value = &machine.registers()[i.rs1()];
+            let rs2_value = &machine.registers()[i.rs2()];
+            let value = rs1_value.lt_s(rs2_value).cond(rs1_value, rs2_value);
+            update_register(machine, i.rd(), value);
+            None
+        }
+        insts::OP_MINU => {
+            let i = Rtype(inst);
+            let rs1_value = &machine.registers()[i.rs1()];
+            let rs2_value = &machine.registers()[i.rs2()];
+            let value = rs1_value.lt(rs2_value).cond(rs1_value, rs2_value);
+            update_register(machine, i.rd(), value);
+            None
+        }
+        insts::OP_ORCB => {
+            let i = Rtype(inst);
+            let value = machine.registers()[i.rs1()].orcb();
+            update_register(machine, i.rd(), value);
+            None
+        }
+        insts::OP_REV8 => {
+            let i = Rtype(inst);
+            let value = machine.registers()[i.rs1()].rev8();
+            update_register(machine, i.rd(), value);
+            None
+        }
+        insts::OP_ROL => {
+            let i = Rtype(inst);
+            let rs1_value = &machine.registers()[i.rs1()];
+            let rs2_value = &machine.registers()[i.rs2()];
+            let value = rs1_value.rol(rs2_value);
+            update_register(machine, i.rd(), value);
+            None
+        }
+        insts::OP_ROLW => {
+            let i = Rtype(inst);
+            let rs1_value = machine.registers()[i.rs1()].zero_extend(&Mac::REG::from_u8(32));
+            let shift_value = machine.registers()[i.rs2()].clone() & Mac::REG::from_u8(0x1F);
+            let reverse_shift_value =
+                Mac::REG::from_u8(32).overflowing_sub(&shift_value) & Mac::REG::from_u8(0x1F);
+            let value = (rs1_value.clone() << shift_value) | (rs1_value >> reverse_shift_value);
+            update_register(machine, i.rd(), value.sign_extend(&Mac::REG::from_u8(32)));
+            None
+        }
+        insts::OP_ROR => {
+            let i = Rtype(inst);
+            let rs1_value = &machine.registers()[i.rs1()];
+            let rs2_value = &machine.registers()[i.rs2()];
+            let value = rs1_value.ror(rs2_value);
+            update_register(machine, i.rd(), value);
+            None
+        }
+        insts::OP_RORI => {
+            let i = Itype(inst);
+            let value = machine.registers()[i.rs1()].ror(&Mac::REG::from_u32(i.immediate()));
+            update_register(machine, i.rd(), value);
+            None
+        }
+        insts::OP_RORIW => {
+            let i = Itype(inst);
+            let rs1_value = machine.registers()[i.rs1()].zero_extend(&Mac::REG::from_u8(32));
+            let shift_value = Mac::REG::from_u32(i.immediate());
+            let reverse_shift_value = Mac::REG::from_u32((32 - i.immediate()) & 0x1F);
+            let value = (rs1_value.clone() >> shift_value) | (rs1_value << reverse_shift_value);
+            update_register(machine, i.rd(), value.sign_extend(&Mac::REG::from_u8(32)));
+            None
+        }
+        insts::OP_RORW => {
+            let i = Rtype(inst);
+            let rs1_value = machine.registers()[i.rs1()].zero_extend(&Mac::REG::from_u8(32));
+            let shift_value = machine.registers()[i.rs2()].clone() & Mac::REG::from_u8(0x1F);
+            let reverse_shift_value =
+                Mac::REG::from_u8(32).overflowing_sub(&shift_value) & Mac::REG::from_u8(0x1F);
+            let value = (rs1_value.clone() >> shift_value) | (rs1_value << reverse_shift_value);
+            update_register(machine, i.rd(), value.sign_extend(&Mac::REG::from_u8(32)));
+            None
+        }
+        insts::OP_SEXTB => {
+            let i = Rtype(inst);
+            let value = machine.registers()[i.rs1()].sign_extend(&Mac::REG::from_u8(8));
+            update_register(machine, i.rd(), value);
+            None
+        }
+        insts::OP_SEXTH => {
+            let i = Rtype(inst);
+            let value = machine.registers()[i.rs1()].sign_extend(&Mac::REG::from_u8(16));
+            update_register(machine, i.rd(), value);
+            None
+        }
+        insts::OP_SH1ADD => {
+            let i = Rtype(inst);
+            let rs1_value = machine.registers()[i.rs1()].clone();
+            let rs2_value = &machine.registers()[i.rs2()];
+            let value = (rs1_value << Mac::REG::from_u8(1)).overflowing_add(rs2_value);
+            update_register(machine, i.rd(), value);
+            None
+        }
+        insts::OP_SH1ADDUW => {
+            let i = Rtype(inst);
+            let rs1_value = machine.registers()[i.rs1()].zero_extend(&Mac::REG::from_u8(32));
+            let rs2_value = &machine.registers()[i.rs2()];
+            let value = (rs1_value << Mac::REG::from_u8(1)).overflowing_add(rs2_value);
+            update_register(machine, i.rd(), value);
+            None
+        }
+        insts::OP_SH2ADD => {
+            let i = Rtype(inst);
+            let rs1_value = machine.registers()[i.rs1()].clone();
+            let rs2_value = &machine.registers()[i.rs2()];
+            let value = (rs1_value << Mac::REG::from_u8(2)).overflowing_add(rs2_value);
+            update_register(machine, i.rd(), value);
+            None
+        }
+        insts::OP_SH2ADDUW => {
+            let i = Rtype(inst);
+            let rs1_value = machine.registers()[i.rs1()].zero_extend(&Mac::REG::from_u8(32));
+            let rs2_value = &machine.registers()[i.rs2()];
+            let value = (rs1_value << Mac::REG::from_u8(2)).overflowing_add(rs2_value);
+            update_register(machine, i.rd(), value);
+            None
+        }
+        insts::OP_SH3ADD => {
+            let i = Rtype(inst);
+            let rs1_value = machine.registers()[i.rs1()].clone();
+            let rs2_value = &machine.registers()[i.rs2()];
+            let value = (rs1_value << Mac::REG::from_u8(3)).overflowing_add(rs2_value);
+            update_register(machine, i.rd(), value);
+            None
+        }
+        insts::OP_SH3ADDUW => {
+            let i = Rtype(inst);
+            let rs1_value = machine.registers()[i.rs1()].zero_extend(&Mac::REG::from_u8(32));
+            let rs2_value = &machine.registers()[i.rs2()];
+            let value = (rs1_value << Mac::REG::from_u8(3)).overflowing_add(rs2_value);
+            update_register(machine, i.rd(), value);
+            None
+        }
+        insts::OP_SLLIUW => {
+            let i = Itype(inst);
+            let rs1_value = machine.registers()[i.rs1()].zero_extend(&Mac::REG::from_u8(32));
+            let value = rs1_value << Mac::REG::from_u32(i.immediate());
+            update_register(machine, i.rd(), value);
+            None
+        }
+        insts::OP_ZEXTH => {
+            let i = Rtype(inst);
+            let value = machine.registers()[i.rs1()].zero_extend(&Mac::REG::from_u8(16));
+            update_register(machine, i.rd(), value);
+            None
+        }
+        insts::OP_LR_W..=insts::OP_AMOMAXU_D => {
+            super::a::execute(inst, machine)?;
+            None
+        }
+        // Counters share the CSR instructions with the F and D extensions
+        insts::OP_CSRRW..=insts::OP_CSRRCI
+            if super::zicntr::is_counter(Itype(inst).immediate()) =>
+        {
+            super::zicntr::execute(inst, machine)?;
+            None
+        }
+        #[cfg(feature = "fd")]
+        insts::OP_FLW..=insts::OP_RVC_FSDSP => {
+            super::fd::execute(inst, machine)?;
+            None
+        }
+        #[cfg(feature = "v-ext")]
+        insts::OP_VSETVLI..=insts::OP_VMV => {
+            super::v::execute(inst, machine)?;
+            None
+        }
+        insts::OP_CUSTOM => super::custom::execute(inst, machine)?,
         _ => return Err(Error::InvalidOp(op as u8)),
     };
     let default_instruction_size = instruction_length(inst);
@@ -0,0 +1,754 @@
+use super::super::{machine::Machine, memory::Memory, registers::SP, Error};
+use super::register::Register;
+use super::rvc::{
+    c_rs2, compact_register_number, fld_uimmediate, fldsp_uimmediate, fsdsp_uimmediate,
+};
+use super::softfloat::{self, Format, F32, F64, RM_DYN, RM_RMM};
+use super::utils::{
+    funct3, funct7, itype_immediate, opcode, rd, rs1, rs2, stype_immediate, update_register, x,
+};
+use super::{extract_opcode, Instruction, Itype, R4type, Stype, Utype};
+use ckb_vm_definitions::instructions as insts;
+use serde::{Deserialize, Serialize};
+
+pub const CSR_FFLAGS: u32 = 0x001;
+pub const CSR_FRM: u32 = 0x002;
+pub const CSR_FCSR: u32 = 0x003;
+
+const NAN_BOX: u64 = 0xFFFF_FFFF_0000_0000;
+
+// Floating point register file shared by the F and D extensions. Each
+// register is 64 bits wide, single precision values are NaN-boxed in it.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FloatRegisters {
+    registers: [u64; 32],
+    fcsr: u32,
+}
+
+impl FloatRegisters {
+    pub fn registers(&self) -> &[u64] {
+        &self.registers
+    }
+
+    pub fn set_register(&mut self, idx: usize, value: u64) {
+        self.registers[idx] = value;
+    }
+
+    // fcsr holds the accrued exception flags in bits 4:0 and the dynamic
+    // rounding mode in bits 7:5, all other bits are hardwired to zero.
+    pub fn fcsr(&self) -> u32 {
+        self.fcsr
+    }
+
+    pub fn set_fcsr(&mut self, fcsr: u32) {
+        self.fcsr = fcsr & 0xFF;
+    }
+
+    pub fn fflags(&self) -> u32 {
+        self.fcsr & 0x1F
+    }
+
+    pub fn frm(&self) -> u32 {
+        (self.fcsr >> 5) & 0x7
+    }
+
+    fn accrue(&mut self, flags: u8) {
+        self.fcsr |= u32::from(flags);
+    }
+
+    fn read_csr(&self, csr: u32) -> u32 {
+        match csr {
+            CSR_FFLAGS => self.fflags(),
+            CSR_FRM => self.frm(),
+            _ => self.fcsr,
+        }
+    }
+
+    fn write_csr(&mut self, csr: u32, value: u32) {
+        match csr {
+            CSR_FFLAGS => self.set_fcsr((self.fcsr & !0x1F) | (value & 0x1F)),
+            CSR_FRM => self.set_fcsr((self.fcsr & 0x1F) | ((value & 0x7) << 5)),
+            _ => self.set_fcsr(value),
+        }
+    }
+
+    // A single precision value that is not properly NaN-boxed reads as the
+    // canonical NaN.
+    fn f32(&self, idx: usize) -> u64 {
+        let value = self.registers[idx];
+        if value & NAN_BOX == NAN_BOX {
+            value & 0xFFFF_FFFF
+        } else {
+            F32.canonical_nan()
+        }
+    }
+
+    fn f64(&self, idx: usize) -> u64 {
+        self.registers[idx]
+    }
+
+    fn set(&mut self, fmt: Format, idx: usize, value: u64) {
+        self.registers[idx] = if fmt == F32 { NAN_BOX | value } else { value };
+    }
+
+    fn get(&self, fmt: Format, idx: usize) -> u64 {
+        if fmt == F32 {
+            self.f32(idx)
+        } else {
+            self.f64(idx)
+        }
+    }
+}
+
+fn valid_rounding_mode(rm: u32) -> bool {
+    rm <= u32::from(RM_RMM) || rm == u32::from(RM_DYN)
+}
+
+// Decodes instructions of the F and D extensions, together with the CSR
+// instructions accessing fflags, frm and fcsr. Other CSRs are not
+// available in CKB VM, so CSR instructions touching them are still invalid.
+pub fn factory<R: Register>(instruction_bits: u32) -> Option<Instruction> {
+    let bit_length = R::BITS;
+    if bit_length != 32 && bit_length != 64 && bit_length != 128 {
+        return None;
+    }
+    // 128 bit registers run the RV64 instruction set
+    let rv64 = bit_length >= 64;
+    if instruction_bits & 0b_11 != 0b_11 {
+        return compressed_factory(instruction_bits);
+    }
+    let funct3_value = funct3(instruction_bits);
+    let funct7_value = funct7(instruction_bits);
+    let rs2_value = rs2(instruction_bits);
+    let rounded = |inst, rs3| {
+        if !valid_rounding_mode(funct3_value) {
+            return None;
+        }
+        Some(
+            R4type::new(
+                inst,
+                rd(instruction_bits),
+                funct3_value as u8,
+                rs1(instruction_bits),
+                rs2_value,
+                rs3,
+            )
+            .0,
+        )
+    };
+    let exact = |inst| {
+        Some(
+            R4type::new(
+                inst,
+                rd(instruction_bits),
+                0,
+                rs1(instruction_bits),
+                rs2_value,
+                0,
+            )
+            .0,
+        )
+    };
+    match opcode(instruction_bits) {
+        0b_0000111 => {
+            let inst = match funct3_value {
+                0b_010 => insts::OP_FLW,
+                0b_011 => insts::OP_FLD,
+                _ => return None,
+            };
+            Some(
+                Itype::new_s(
+                    inst,
+                    rd(instruction_bits),
+                    rs1(instruction_bits),
+                    itype_immediate(instruction_bits),
+                )
+                .0,
+            )
+        }
+        0b_0100111 => {
+            let inst = match funct3_value {
+                0b_010 => insts::OP_FSW,
+                0b_011 => insts::OP_FSD,
+                _ => return None,
+            };
+            Some(
+                Stype::new_s(
+                    inst,
+                    stype_immediate(instruction_bits),
+                    rs1(instruction_bits),
+                    rs2_value,
+                )
+                .0,
+            )
+        }
+        0b_1000011 | 0b_1000111 | 0b_1001011 | 0b_1001111 => {
+            let inst = match (opcode(instruction_bits), funct7_value & 0b_11) {
+                (0b_1000011, 0b_00) => insts::OP_FMADDS,
+                (0b_1000111, 0b_00) => insts::OP_FMSUBS,
+                (0b_1001011, 0b_00) => insts::OP_FNMSUBS,
+                (0b_1001111, 0b_00) => insts::OP_FNMADDS,
+                (0b_1000011, 0b_01) => insts::OP_FMADDD,
+                (0b_1000111, 0b_01) => insts::OP_FMSUBD,
+                (0b_1001011, 0b_01) => insts::OP_FNMSUBD,
+                (0b_1001111, 0b_01) => insts::OP_FNMADDD,
+                _ => return None,
+            };
+            rounded(inst, x(instruction_bits, 27, 5, 0) as usize)
+        }
+        0b_1010011 => match (funct7_value, rs2_value, funct3_value) {
+            (0b_0000000, _, _) => rounded(insts::OP_FADDS, 0),
+            (0b_0000100, _, _) => rounded(insts::OP_FSUBS, 0),
+            (0b_0001000, _, _) => rounded(insts::OP_FMULS, 0),
+            (0b_0001100, _, _) => rounded(insts::OP_FDIVS, 0),
+            (0b_0101100, 0, _) => rounded(insts::OP_FSQRTS, 0),
+            (0b_0010000, _, 0b_000) => exact(insts::OP_FSGNJS),
+            (0b_0010000, _, 0b_001) => exact(insts::OP_FSGNJNS),
+            (0b_0010000, _, 0b_010) => exact(insts::OP_FSGNJXS),
+            (0b_0010100, _, 0b_000) => exact(insts::OP_FMINS),
+            (0b_0010100, _, 0b_001) => exact(insts::OP_FMAXS),
+            (0b_1100000, 0, _) => rounded(insts::OP_FCVTWS, 0),
+            (0b_1100000, 1, _) => rounded(insts::OP_FCVTWUS, 0),
+            (0b_1100000, 2, _) if rv64 => rounded(insts::OP_FCVTLS, 0),
+            (0b_1100000, 3, _) if rv64 => rounded(insts::OP_FCVTLUS, 0),
+            (0b_1110000, 0, 0b_000) => exact(insts::OP_FMVXW),
+            (0b_1110000, 0, 0b_001) => exact(insts::OP_FCLASSS),
+            (0b_1010000, _, 0b_010) => exact(insts::OP_FEQS),
+            (0b_1010000, _, 0b_001) => exact(insts::OP_FLTS),
+            (0b_1010000, _, 0b_000) => exact(insts::OP_FLES),
+            (0b_1101000, 0, _) => rounded(insts::OP_FCVTSW, 0),
+            (0b_1101000, 1, _) => rounded(insts::OP_FCVTSWU, 0),
+            (0b_1101000, 2, _) if rv64 => rounded(insts::OP_FCVTSL, 0),
+            (0b_1101000, 3, _) if rv64 => rounded(insts::OP_FCVTSLU, 0),
+            (0b_1111000, 0, 0b_000) => exact(insts::OP_FMVWX),
+            (0b_0000001, _, _) => rounded(insts::OP_FADDD, 0),
+            (0b_0000101, _, _) => rounded(insts::OP_FSUBD, 0),
+            (0b_0001001, _, _) => rounded(insts::OP_FMULD, 0),
+            (0b_0001101, _, _) => rounded(insts::OP_FDIVD, 0),
+            (0b_0101101, 0, _) => rounded(insts::OP_FSQRTD, 0),
+            (0b_0010001, _, 0b_000) => exact(insts::OP_FSGNJD),
+            (0b_0010001, _, 0b_001) => exact(insts::OP_FSGNJND),
+            (0b_0010001, _, 0b_010) => exact(insts::OP_FSGNJXD),
+            (0b_0010101, _, 0b_000) => exact(insts::OP_FMIND),
+            (0b_0010101, _, 0b_001) => exact(insts::OP_FMAXD),
+            (0b_0100000, 1, _) => rounded(insts::OP_FCVTSD, 0),
+            (0b_0100001, 0, _) => rounded(insts::OP_FCVTDS, 0),
+            (0b_1010001, _, 0b_010) => exact(insts::OP_FEQD),
+            (0b_1010001, _, 0b_001) => exact(insts::OP_FLTD),
+            (0b_1010001, _, 0b_000) => exact(insts::OP_FLED),
+            (0b_1110001, 0, 0b_001) => exact(insts::OP_FCLASSD),
+            (0b_1100001, 0, _) => rounded(insts::OP_FCVTWD, 0),
+            (0b_1100001, 1, _) => rounded(insts::OP_FCVTWUD, 0),
+            (0b_1100001, 2, _) if rv64 => rounded(insts::OP_FCVTLD, 0),
+            (0b_1100001, 3, _) if rv64 => rounded(insts::OP_FCVTLUD, 0),
+            (0b_1101001, 0, _) => rounded(insts::OP_FCVTDW, 0),
+            (0b_1101001, 1, _) => rounded(insts::OP_FCVTDWU, 0),
+            (0b_1101001, 2, _) if rv64 => rounded(insts::OP_FCVTDL, 0),
+            (0b_1101001, 3, _) if rv64 => rounded(insts::OP_FCVTDLU, 0),
+            (0b_1110001, 0, 0b_000) if rv64 => exact(insts::OP_FMVXD),
+            (0b_1111001, 0, 0b_000) if rv64 => exact(insts::OP_FMVDX),
+            _ => None,
+        },
+        0b_1110011 => {
+            let csr = instruction_bits >> 20;
+            if csr != CSR_FFLAGS && csr != CSR_FRM && csr != CSR_FCSR {
+                return None;
+            }
+            let inst = match funct3_value {
+                0b_001 => insts::OP_CSRRW,
+                0b_010 => insts::OP_CSRRS,
+                0b_011 => insts::OP_CSRRC,
+                0b_101 => insts::OP_CSRRWI,
+                0b_110 => insts::OP_CSRRSI,
+                0b_111 => insts::OP_CSRRCI,
+                _ => return None,
+            };
+            Some(Itype::new(inst, rd(instruction_bits), rs1(instruction_bits), csr).0)
+        }
+        _ => None,
+    }
+}
+
+// Compressed double precision loads and stores, they share the immediate
+// encoding of their integer counterparts. The single precision forms only
+// exist on RV32 and are not supported.
+fn compressed_factory(instruction_bits: u32) -> Option<Instruction> {
+    match instruction_bits & 0b_111_00000000000_11 {
+        0b_001_00000000000_00 => Some(
+            Itype::new(
+                insts::OP_RVC_FLD,
+                compact_register_number(instruction_bits, 2),
+                compact_register_number(instruction_bits, 7),
+                fld_uimmediate(instruction_bits),
+            )
+            .0,
+        ),
+        0b_101_00000000000_00 => Some(
+            Stype::new(
+                insts::OP_RVC_FSD,
+                fld_uimmediate(instruction_bits),
+                compact_register_number(instruction_bits, 7),
+                compact_register_number(instruction_bits, 2),
+            )
+            .0,
+        ),
+        0b_001_00000000000_10 => Some(
+            Utype::new(
+                insts::OP_RVC_FLDSP,
+                rd(instruction_bits),
+                fldsp_uimmediate(instruction_bits),
+            )
+            .0,
+        ),
+        0b_101_00000000000_10 => Some(
+            Stype::new(
+                insts::OP_RVC_FSDSP,
+                fsdsp_uimmediate(instruction_bits),
+                0,
+                c_rs2(instruction_bits),
+            )
+            .0,
+        ),
+        _ => None,
+    }
+}
+
+fn float_registers<Mac: Machine>(machine: &mut Mac, op: u8) -> Result<&mut FloatRegisters, Error> {
+    machine.float_registers_mut().ok_or(Error::InvalidOp(op))
+}
+
+// 64 bit memory accesses are split into 2 halves on RV32, where a register
+// cannot hold the whole value.
+fn load64<Mac: Machine>(machine: &mut Mac, address: &Mac::REG) -> Result<u64, Error> {
+    if Mac::REG::BITS >= 64 {
+        return Ok(machine.memory_mut().load64(address)?.to_u64());
+    }
+    let high_address = address.overflowing_add(&Mac::REG::from_u8(4));
+    let low = machine.memory_mut().load32(address)?.to_u64();
+    let high = machine.memory_mut().load32(&high_address)?.to_u64();
+    Ok(low | (high << 32))
+}
+
+fn store64<Mac: Machine>(machine: &mut Mac, address: &Mac::REG, value: u64) -> Result<(), Error> {
+    if Mac::REG::BITS >= 64 {
+        return machine
+            .memory_mut()
+            .store64(address, &Mac::REG::from_u64(value));
+    }
+    let high_address = address.overflowing_add(&Mac::REG::from_u8(4));
+    machine
+        .memory_mut()
+        .store32(address, &Mac::REG::from_u64(value & 0xFFFF_FFFF))?;
+    machine
+        .memory_mut()
+        .store32(&high_address, &Mac::REG::from_u64(value >> 32))
+}
+
+fn sign_extend32(value: u64) -> u64 {
+    value as i32 as i64 as u64
+}
+
+fn execute_load_store<Mac: Machine>(inst: Instruction, machine: &mut Mac) -> Result<(), Error> {
+    let op = extract_opcode(inst);
+    match op {
+        insts::OP_FLW | insts::OP_FLD | insts::OP_RVC_FLD | insts::OP_RVC_FLDSP => {
+            let (rd, address) = if op == insts::OP_RVC_FLDSP {
+                let i = Utype(inst);
+                let offset = Mac::REG::from_i32(i.immediate_s());
+                (i.rd(), machine.registers()[SP].overflowing_add(&offset))
+            } else {
+                let i = Itype(inst);
+                let offset = Mac::REG::from_i32(i.immediate_s());
+                (
+                    i.rd(),
+                    machine.registers()[i.rs1()].overflowing_add(&offset),
+                )
+            };
+            let value = if op == insts::OP_FLW {
+                NAN_BOX | machine.memory_mut().load32(&address)?.to_u64()
+            } else {
+                load64(machine, &address)?
+            };
+            float_registers(machine, op)?.set_register(rd, value);
+        }
+        _ => {
+            let i = Stype(inst);
+            let base = if op == insts::OP_RVC_FSDSP {
+                SP
+            } else {
+                i.rs1()
+            };
+            let offset = Mac::REG::from_i32(i.immediate_s());
+            let address = machine.registers()[base].overflowing_add(&offset);
+            let value = float_registers(machine, op)?.registers()[i.rs2()];
+            if op == insts::OP_FSW {
+                machine
+                    .memory_mut()
+                    .store32(&address, &Mac::REG::from_u64(value & 0xFFFF_FFFF))?;
+            } else {
+                store64(machine, &address, value)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn execute_csr<Mac: Machine>(inst: Instruction, machine: &mut Mac) -> Result<(), Error> {
+    let op = extract_opcode(inst);
+    let i = Itype(inst);
+    let csr = i.immediate();
+    let source = match op {
+        insts::OP_CSRRW | insts::OP_CSRRS | insts::OP_CSRRC => {
+            machine.registers()[i.rs1()].to_u32()
+        }
+        _ => i.rs1() as u32,
+    };
+    let registers = float_registers(machine, op)?;
+    let old_value = registers.read_csr(csr);
+    // Per RISC-V spec, CSRRS and CSRRC with x0 or a zero immediate as
+    // source only read the CSR.
+    let new_value = match op {
+        insts::OP_CSRRW | insts::OP_CSRRWI => Some(source),
+        insts::OP_CSRRS | insts::OP_CSRRSI if i.rs1() != 0 => Some(old_value | source),
+        insts::OP_CSRRC | insts::OP_CSRRCI if i.rs1() != 0 => Some(old_value & !source),
+        _ => None,
+    };
+    if let Some(new_value) = new_value {
+        registers.write_csr(csr, new_value);
+    }
+    update_register(machine, i.rd(), Mac::REG::from_u32(old_value));
+    Ok(())
+}
+
+// Executes an instruction produced by the factory in this module.
+pub fn execute<Mac: Machine>(inst: Instruction, machine: &mut Mac) -> Result<(), Error> {
+    let op = extract_opcode(inst);
+    match op {
+        insts::OP_FLW
+        | insts::OP_FLD
+        | insts::OP_RVC_FLD
+        | insts::OP_RVC_FLDSP
+        | insts::OP_FSW
+        | insts::OP_FSD
+        | insts::OP_RVC_FSD
+        | insts::OP_RVC_FSDSP => return execute_load_store(inst, machine),
+        insts::OP_CSRRW
+        | insts::OP_CSRRS
+        | insts::OP_CSRRC
+        | insts::OP_CSRRWI
+        | insts::OP_CSRRSI
+        | insts::OP_CSRRCI => return execute_csr(inst, machine),
+        _ => (),
+    }
+    let i = R4type(inst);
+    let (rd, rs1, rs2, rs3) = (i.rd(), i.rs1(), i.rs2(), i.rs3());
+    let integer = machine.registers()[rs1].to_u64();
+    let registers = float_registers(machine, op)?;
+    let rm = if i.rm() == RM_DYN {
+        registers.frm() as u8
+    } else {
+        i.rm()
+    };
+    if !valid_rounding_mode(u32::from(rm)) {
+        return Err(Error::InvalidOp(op));
+    }
+    let fmt = match op {
+        insts::OP_FMADDS..=insts::OP_FCVTSLU => F32,
+        _ => F64,
+    };
+    let (a, b, c) = (
+        registers.get(fmt, rs1),
+        registers.get(fmt, rs2),
+        registers.get(fmt, rs3),
+    );
+    // Instructions writing to an integer register
+    let integer_result = match op {
+        insts::OP_FCVTWS | insts::OP_FCVTWD => {
+            let (value, flags) = softfloat::to_int(fmt, a, rm, true, 32);
+            Some((sign_extend32(value), flags))
+        }
+        insts::OP_FCVTWUS | insts::OP_FCVTWUD => {
+            let (value, flags) = softfloat::to_int(fmt, a, rm, false, 32);
+            Some((sign_extend32(value), flags))
+        }
+        insts::OP_FCVTLS | insts::OP_FCVTLD => Some(softfloat::to_int(fmt, a, rm, true, 64)),
+        insts::OP_FCVTLUS | insts::OP_FCVTLUD => Some(softfloat::to_int(fmt, a, rm, false, 64)),
+        insts::OP_FMVXW => Some((sign_extend32(registers.registers()[rs1]), 0)),
+        insts::OP_FMVXD => Some((registers.registers()[rs1], 0)),
+        insts::OP_FCLASSS | insts::OP_FCLASSD => Some((softfloat::classify(fmt, a), 0)),
+        insts::OP_FEQS | insts::OP_FEQD => Some(softfloat::eq(fmt, a, b)),
+        insts::OP_FLTS | insts::OP_FLTD => Some(softfloat::lt(fmt, a, b)),
+        insts::OP_FLES | insts::OP_FLED => Some(softfloat::le(fmt, a, b)),
+        _ => None,
+    };
+    if let Some((value, flags)) = integer_result {
+        registers.accrue(flags);
+        update_register(machine, rd, Mac::REG::from_u64(value));
+        return Ok(());
+    }
+    // Instructions writing to a floating point register, conversions
+    // between formats write in the format opposite to their source.
+    let (result_fmt, (value, flags)) = match op {
+        insts::OP_FADDS | insts::OP_FADDD => (fmt, softfloat::add(fmt, a, b, rm)),
+        insts::OP_FSUBS | insts::OP_FSUBD => (fmt, softfloat::sub(fmt, a, b, rm)),
+        insts::OP_FMULS | insts::OP_FMULD => (fmt, softfloat::mul(fmt, a, b, rm)),
+        insts::OP_FDIVS | insts::OP_FDIVD => (fmt, softfloat::div(fmt, a, b, rm)),
+        insts::OP_FSQRTS | insts::OP_FSQRTD => (fmt, softfloat::sqrt(fmt, a, rm)),
+        insts::OP_FMADDS | insts::OP_FMADDD => (fmt, softfloat::fma(fmt, a, b, c, rm)),
+        insts::OP_FMSUBS | insts::OP_FMSUBD => (fmt, softfloat::fma(fmt, a, b, fmt.negate(c), rm)),
+        insts::OP_FNMSUBS | insts::OP_FNMSUBD => {
+            (fmt, softfloat::fma(fmt, fmt.negate(a), b, c, rm))
+        }
+        insts::OP_FNMADDS | insts::OP_FNMADDD => {
+            let (a, c) = (fmt.negate(a), fmt.negate(c));
+            (fmt, softfloat::fma(fmt, a, b, c, rm))
+        }
+        insts::OP_FSGNJS | insts::OP_FSGNJD => (fmt, (softfloat::copy_sign(fmt, a, b), 0)),
+        insts::OP_FSGNJNS | insts::OP_FSGNJND => {
+            (fmt, (softfloat::copy_sign(fmt, a, fmt.negate(b)), 0))
+        }
+        insts::OP_FSGNJXS | insts::OP_FSGNJXD => (fmt, (softfloat::xor_sign(fmt, a, b), 0)),
+        insts::OP_FMINS | insts::OP_FMIND => (fmt, softfloat::min_max(fmt, a, b, false)),
+        insts::OP_FMAXS | insts::OP_FMAXD => (fmt, softfloat::min_max(fmt, a, b, true)),
+        insts::OP_FCVTSD => (F32, softfloat::convert(F64, F32, a, rm)),
+        insts::OP_FCVTDS => {
+            let a = registers.f32(rs1);
+            (F64, softfloat::convert(F32, F64, a, rm))
+        }
+        insts::OP_FCVTSW | insts::OP_FCVTDW => {
+            (fmt, softfloat::from_int(fmt, integer, true, 32, rm))
+        }
+        insts::OP_FCVTSWU | insts::OP_FCVTDWU => {
+            (fmt, softfloat::from_int(fmt, integer, false, 32, rm))
+        }
+        insts::OP_FCVTSL | insts::OP_FCVTDL => {
+            (fmt, softfloat::from_int(fmt, integer, true, 64, rm))
+        }
+        insts::OP_FCVTSLU | insts::OP_FCVTDLU => {
+            (fmt, softfloat::from_int(fmt, integer, false, 64, rm))
+        }
+        insts::OP_FMVWX => (F32, (integer & 0xFFFF_FFFF, 0)),
+        insts::OP_FMVDX => (F64, (integer, 0)),
+        _ => return Err(Error::InvalidOp(op)),
+    };
+    registers.accrue(flags);
+    registers.set(result_fmt, rd, value);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::execute;
+    use super::*;
+    use crate::machine::{CoreMachine, DefaultCoreMachine, DefaultMachine};
+    use crate::SparseMemory;
+
+    type TestMachine<'a, R> = DefaultMachine<'a, DefaultCoreMachine<R, SparseMemory<R>>>;
+
+    // Encodes an OP-FP instruction with rd = a0/fa0, rs1 = a1/fa1 and
+    // rs2 = fa2
+    fn encode(funct7: u32, rs2: u32, rm: u32) -> u32 {
+        (funct7 << 25) | (rs2 << 20) | (11 << 15) | (rm << 12) | (10 << 7) | 0b_1010011
+    }
+
+    fn run<R: Register>(machine: &mut TestMachine<R>, bits: u32) -> Result<(), Error> {
+        let instruction = factory::<R>(bits).expect("decoding");
+        execute(instruction, machine)
+    }
+
+    fn boxed(value: f32) -> u64 {
+        NAN_BOX | u64::from(value.to_bits())
+    }
+
+    fn registers_mut<'a, R: Register>(
+        machine: &'a mut TestMachine<'_, R>,
+    ) -> &'a mut FloatRegisters {
+        machine.float_registers_mut().unwrap()
+    }
+
+    #[test]
+    fn test_decode() {
+        // fadd.d with reserved rounding modes
+        assert!(factory::<u64>(encode(0b_0000001, 12, 0b_101)).is_none());
+        assert!(factory::<u64>(encode(0b_0000001, 12, 0b_110)).is_none());
+        assert!(factory::<u64>(encode(0b_0000001, 12, 0b_111)).is_some());
+        // fcvt.l.s and fmv.x.d are only available on RV64
+        assert!(factory::<u32>(encode(0b_1100000, 2, 0)).is_none());
+        assert!(factory::<u64>(encode(0b_1100000, 2, 0)).is_some());
+        assert!(factory::<u32>(encode(0b_1110001, 0, 0)).is_none());
+        assert!(factory::<u64>(encode(0b_1110001, 0, 0)).is_some());
+        // Only floating point CSRs can be accessed, csrrs a0, cycle, zero
+        // stays invalid
+        assert!(factory::<u64>(0x0010_2573).is_some());
+        assert!(factory::<u64>(0xC000_2573).is_none());
+        // Plain RV64I and RVC instructions are left to other factories
+        assert!(factory::<u64>(0x0045_8513).is_none());
+        assert!(factory::<u64>(0x0073).is_none());
+        assert!(factory::<u64>(0x4188).is_none());
+        assert!(factory::<u64>(0x2588).is_some());
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let mut machine = TestMachine::<u64>::default();
+        registers_mut(&mut machine).set_register(11, 1.5f64.to_bits());
+        registers_mut(&mut machine).set_register(12, 2.25f64.to_bits());
+        registers_mut(&mut machine).set_register(13, 0.5f64.to_bits());
+        run(&mut machine, encode(0b_0000001, 12, 0b_111)).unwrap();
+        assert_eq!(machine.float_registers().registers()[10], 3.75f64.to_bits());
+        // fmadd.d fa0, fa1, fa2, fa3
+        let fmadd = (13 << 27) | (1 << 25) | (12 << 20) | (11 << 15) | (10 << 7) | 0b_1000011;
+        run(&mut machine, fmadd).unwrap();
+        assert_eq!(
+            machine.float_registers().registers()[10],
+            3.875f64.to_bits()
+        );
+        // fnmsub.d fa0, fa1, fa2, fa3
+        run(&mut machine, fmadd | 0b_1000).unwrap();
+        assert_eq!(
+            machine.float_registers().registers()[10],
+            (-2.875f64).to_bits()
+        );
+        assert_eq!(machine.float_registers().fflags(), 0);
+        // fdiv.s fa0, fa1, fa2
+        registers_mut(&mut machine).set_register(11, boxed(1.0));
+        registers_mut(&mut machine).set_register(12, boxed(0.0));
+        run(&mut machine, encode(0b_0001100, 12, 0b_000)).unwrap();
+        assert_eq!(
+            machine.float_registers().registers()[10],
+            boxed(f32::INFINITY)
+        );
+        assert_eq!(
+            machine.float_registers().fflags(),
+            u32::from(softfloat::FLAG_DZ)
+        );
+    }
+
+    #[test]
+    fn test_rounding_modes() {
+        let mut machine = TestMachine::<u64>::default();
+        registers_mut(&mut machine).set_register(11, (-2.5f64).to_bits());
+        // fcvt.w.d a0, fa1
+        let fcvt_w_d = |rm| encode(0b_1100001, 0, rm);
+        let expected = [-2i64, -2, -3, -2, -3];
+        for (rm, expected) in expected.iter().enumerate() {
+            run(&mut machine, fcvt_w_d(rm as u32)).unwrap();
+            assert_eq!(machine.registers()[10], *expected as u64);
+        }
+        // Dynamic rounding mode is taken from frm
+        registers_mut(&mut machine).set_fcsr(3 << 5);
+        run(&mut machine, fcvt_w_d(0b_111)).unwrap();
+        assert_eq!(machine.registers()[10], (-2i64) as u64);
+        assert_eq!(
+            machine.float_registers().fflags(),
+            u32::from(softfloat::FLAG_NX)
+        );
+        // An invalid frm makes instructions using it illegal
+        registers_mut(&mut machine).set_fcsr(5 << 5);
+        assert_eq!(
+            run(&mut machine, fcvt_w_d(0b_111)),
+            Err(Error::InvalidOp(insts::OP_FCVTWD))
+        );
+        // fcvt.wu.d results are sign extended on RV64
+        registers_mut(&mut machine).set_register(11, 4e9f64.to_bits());
+        run(&mut machine, encode(0b_1100001, 1, 0)).unwrap();
+        assert_eq!(
+            machine.registers()[10],
+            4_000_000_000u32 as i32 as i64 as u64
+        );
+    }
+
+    #[test]
+    fn test_nan_boxing() {
+        let mut machine = TestMachine::<u64>::default();
+        // fadd.s fa0, fa1, fa2 with an improperly boxed fa1
+        registers_mut(&mut machine).set_register(11, u64::from(1.0f32.to_bits()));
+        registers_mut(&mut machine).set_register(12, boxed(1.0));
+        run(&mut machine, encode(0b_0000000, 12, 0)).unwrap();
+        assert_eq!(
+            machine.float_registers().registers()[10],
+            NAN_BOX | F32.canonical_nan()
+        );
+        // fmv.w.x fa0, a1 boxes the value, fmv.x.w a0, fa1 sign extends it
+        machine.set_register(11, 0xFFFF_FFFF_BF80_0000);
+        run(&mut machine, encode(0b_1111000, 0, 0)).unwrap();
+        assert_eq!(machine.float_registers().registers()[10], boxed(-1.0));
+        registers_mut(&mut machine).set_register(11, 0xBF80_0000);
+        run(&mut machine, encode(0b_1110000, 0, 0)).unwrap();
+        assert_eq!(machine.registers()[10], 0xFFFF_FFFF_BF80_0000);
+        // fcvt.d.s fa0, fa1 reads the canonical NaN from unboxed values
+        run(&mut machine, encode(0b_0100001, 0, 0)).unwrap();
+        assert_eq!(
+            machine.float_registers().registers()[10],
+            F64.canonical_nan()
+        );
+    }
+
+    #[test]
+    fn test_csr() {
+        let mut machine = TestMachine::<u64>::default();
+        // csrrw a0, fcsr, a1
+        machine.set_register(11, 0x1FF);
+        run(&mut machine, 0x0035_9573).unwrap();
+        assert_eq!(machine.registers()[10], 0);
+        assert_eq!(machine.float_registers().fcsr(), 0xFF);
+        // csrrs a0, fflags, zero only reads
+        run(&mut machine, 0x0010_2573).unwrap();
+        assert_eq!(machine.registers()[10], 0x1F);
+        assert_eq!(machine.float_registers().fcsr(), 0xFF);
+        // csrrci a0, fflags, 0x11
+        run(&mut machine, 0x0018_F573).unwrap();
+        assert_eq!(machine.registers()[10], 0x1F);
+        assert_eq!(machine.float_registers().fflags(), 0x0E);
+        // csrrwi a0, frm, 1
+        run(&mut machine, 0x0020_D573).unwrap();
+        assert_eq!(machine.registers()[10], 7);
+        assert_eq!(machine.float_registers().fcsr(), 0x2E);
+    }
+
+    fn run_load_store<R: Register>() {
+        let mut machine = TestMachine::<R>::default();
+        machine.set_register(2, R::from_u64(0x2000));
+        machine.set_register(11, R::from_u64(0x1000));
+        registers_mut(&mut machine).set_register(10, 0x0123_4567_89AB_CDEF);
+        // fsd fa0, 16(a1) and fld fa1, 16(a1)
+        run(&mut machine, 0x00A5_B827).unwrap();
+        run(&mut machine, 0x0105_B587).unwrap();
+        assert_eq!(
+            machine.float_registers().registers()[11],
+            0x0123_4567_89AB_CDEF
+        );
+        // fsw fa0, 16(a1) and flw fa1, 16(a1)
+        run(&mut machine, 0x00A5_A827).unwrap();
+        run(&mut machine, 0x0105_A587).unwrap();
+        assert_eq!(
+            machine.float_registers().registers()[11],
+            NAN_BOX | 0x89AB_CDEF
+        );
+        // c.fsdsp fa0, 8(sp) and c.fld fa0, 8(a1) on a copy
+        run(&mut machine, 0xA42A).unwrap();
+        let value = machine.memory_mut().load32(&R::from_u64(0x2008)).unwrap();
+        assert_eq!(value.to_u64(), 0x89AB_CDEF);
+        let value = machine.memory_mut().load32(&R::from_u64(0x200C)).unwrap();
+        assert_eq!(value.to_u64(), 0x0123_4567);
+        machine.set_register(11, R::from_u64(0x2000));
+        registers_mut(&mut machine).set_register(10, 0);
+        run(&mut machine, 0x2588).unwrap();
+        assert_eq!(
+            machine.float_registers().registers()[10],
+            0x0123_4567_89AB_CDEF
+        );
+        // c.fldsp fa1, 8(sp)
+        run(&mut machine, 0x25A2).unwrap();
+        assert_eq!(
+            machine.float_registers().registers()[11],
+            0x0123_4567_89AB_CDEF
+        );
+    }
+
+    #[test]
+    fn test_load_store() {
+        run_load_store::<u32>();
+        run_load_store::<u64>();
+    }
+}
@@ -31,10 +31,11 @@ impl FenceType {
 
 pub fn factory<R: Register>(instruction_bits: u32) -> Option<Instruction> {
     let bit_length = R::BITS;
-    if bit_length != 32 && bit_length != 64 {
+    if bit_length != 32 && bit_length != 64 && bit_length != 128 {
         return None;
     }
-    let rv64 = bit_length == 64;
+    // 128 bit registers run the RV64 instruction set
+    let rv64 = bit_length >= 64;
     match opcode(instruction_bits) {
         0b_0110111 => Some(
             Utype::new_s(
@@ -110,7 +111,13 @@ pub fn factory<R: Register>(instruction_bits: u32) -> Option<Instruction> {
                 0b_111 => Some(insts::OP_ANDI),
                 // I-type special ALU instructions
                 0b_001 | 0b_101 => {
-                    let top6_value = funct7(instruction_bits) >> 1;
+                    let funct7_value = funct7(instruction_bits);
+                    // shamt[5] is only valid on RV64, RV32 encodings with it
+                    // set are reserved.
+                    if !rv64 && funct7_value & 1 != 0 {
+                        return None;
+                    }
+                    let top6_value = funct7_value >> 1;
                     let inst_opt = match (funct3_value, top6_value) {
                         (0b_001, 0b_000000) => Some(insts::OP_SLLI),
                         (0b_101, 0b_000000) => Some(insts::OP_SRLI),
@@ -5,10 +5,11 @@ use ckb_vm_definitions::instructions as insts;
 
 pub fn factory<R: Register>(instruction_bits: u32) -> Option<Instruction> {
     let bit_length = R::BITS;
-    if bit_length != 32 && bit_length != 64 {
+    if bit_length != 32 && bit_length != 64 && bit_length != 128 {
         return None;
     }
-    let rv64 = bit_length == 64;
+    // 128 bit registers run the RV64 instruction set
+    let rv64 = bit_length >= 64;
     if funct7(instruction_bits) != 0b_0000001 {
         return None;
     }
@@ -1,20 +1,36 @@
 mod common;
+mod disasm;
 mod execute;
 mod register;
+mod tagged;
 mod utils;
 
+pub mod a;
 pub mod ast;
+pub mod b;
+pub mod custom;
+#[cfg(feature = "fd")]
+pub mod fd;
 pub mod i;
 pub mod m;
 pub mod rvc;
+#[cfg(feature = "fd")]
+mod softfloat;
+#[cfg(feature = "v-ext")]
+pub mod v;
+pub mod zicntr;
 
 pub use self::register::Register;
+use super::registers::{RA, SP, T0};
 use super::Error;
 pub use ckb_vm_definitions::instructions::{
     self as insts, Instruction, InstructionOpcode, INSTRUCTION_OPCODE_NAMES, MAXIMUM_RVC_OPCODE,
     MINIMAL_RVC_OPCODE,
 };
+pub use disasm::{disassemble, disassemble_block};
 pub use execute::execute;
+pub use rvc::decode_rvc;
+pub use tagged::{unpack, TaggedInstruction};
 
 type RegisterIndex = usize;
 type Immediate = i32;
@@ -197,6 +213,56 @@ impl Utype {
     }
 }
 
+// R4-type is used by floating point instructions, it carries the rounding
+// mode and a third source register used by fused multiply-add instructions.
+// Instructions with less operands leave the unused fields as 0.
+#[derive(Debug, Clone, Copy)]
+pub struct R4type(pub Instruction);
+
+impl R4type {
+    pub fn new(
+        op: InstructionOpcode,
+        rd: RegisterIndex,
+        rm: u8,
+        rs1: RegisterIndex,
+        rs2: RegisterIndex,
+        rs3: RegisterIndex,
+    ) -> Self {
+        R4type(
+            u64::from(op)
+                | (u64::from(rd as u8) << 8)
+                | (u64::from(rm) << 16)
+                | (u64::from(rs1 as u8) << 32)
+                | (u64::from(rs2 as u8) << 40)
+                | (u64::from(rs3 as u8) << 48),
+        )
+    }
+
+    pub fn op(self) -> InstructionOpcode {
+        self.0 as InstructionOpcode
+    }
+
+    pub fn rd(self) -> RegisterIndex {
+        (self.0 >> 8) as u8 as RegisterIndex
+    }
+
+    pub fn rm(self) -> u8 {
+        (self.0 >> 16) as u8
+    }
+
+    pub fn rs1(self) -> RegisterIndex {
+        (self.0 >> 32) as u8 as RegisterIndex
+    }
+
+    pub fn rs2(self) -> RegisterIndex {
+        (self.0 >> 40) as u8 as RegisterIndex
+    }
+
+    pub fn rs3(self) -> RegisterIndex {
+        (self.0 >> 48) as u8 as RegisterIndex
+    }
+}
+
 pub fn is_basic_block_end_instruction(i: Instruction) -> bool {
     match extract_opcode(i) {
         insts::OP_AUIPC => true,
@@ -209,6 +275,7 @@ pub fn is_basic_block_end_instruction(i: Instruction) -> bool {
         insts::OP_BGEU => true,
         insts::OP_ECALL => true,
         insts::OP_EBREAK => true,
+        insts::OP_FENCEI => true,
         insts::OP_JAL => true,
         insts::OP_RVC_EBREAK => true,
         insts::OP_RVC_BEQZ => true,
@@ -217,14 +284,127 @@ pub fn is_basic_block_end_instruction(i: Instruction) -> bool {
         insts::OP_RVC_JAL => true,
         insts::OP_RVC_JALR => true,
         insts::OP_RVC_JR => true,
+        insts::OP_CUSTOM => true,
         _ => false,
     }
 }
 
+// Change of call depth caused by a jump, 1 for calls, -1 for returns and 0
+// otherwise. This follows the return address stack hints of the RISC-V
+// spec where ra and t0 are link registers, swapping coroutines through
+// both counts as a return followed by a call.
+pub fn call_depth_change(i: Instruction) -> i8 {
+    let is_link = |r: RegisterIndex| r == RA || r == T0;
+    let jalr = |rd: RegisterIndex, rs1: RegisterIndex| match (is_link(rd), is_link(rs1)) {
+        (true, true) if rd != rs1 => 0,
+        (true, _) => 1,
+        (false, true) => -1,
+        (false, false) => 0,
+    };
+    match extract_opcode(i) {
+        insts::OP_JAL => is_link(Utype(i).rd()) as i8,
+        insts::OP_JALR => jalr(Itype(i).rd(), Itype(i).rs1()),
+        insts::OP_RVC_JAL => 1,
+        insts::OP_RVC_JALR => jalr(RA, Stype(i).rs1()),
+        insts::OP_RVC_JR => jalr(0, Stype(i).rs1()),
+        _ => 0,
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub enum MemoryOp {
+    Load,
+    Store,
+    // Atomic instructions, including LR and SC
+    Atomic,
+}
+
+// Kind and size of the memory access done by load, store and atomic
+// instructions, other instructions return None.
+pub fn memory_op(i: Instruction) -> Option<(MemoryOp, u64)> {
+    let op = extract_opcode(i);
+    let kind = match op {
+        insts::OP_SB
+        | insts::OP_SH
+        | insts::OP_SW
+        | insts::OP_SD
+        | insts::OP_RVC_SW
+        | insts::OP_RVC_SWSP
+        | insts::OP_RVC_SD
+        | insts::OP_RVC_SDSP
+        | insts::OP_FSW
+        | insts::OP_FSD
+        | insts::OP_RVC_FSD
+        | insts::OP_RVC_FSDSP => MemoryOp::Store,
+        insts::OP_LR_W..=insts::OP_AMOMAXU_W | insts::OP_LR_D..=insts::OP_AMOMAXU_D => {
+            MemoryOp::Atomic
+        }
+        _ => MemoryOp::Load,
+    };
+    let size = match op {
+        insts::OP_LB | insts::OP_LBU | insts::OP_SB => 1,
+        insts::OP_LH | insts::OP_LHU | insts::OP_SH => 2,
+        insts::OP_LW
+        | insts::OP_LWU
+        | insts::OP_SW
+        | insts::OP_RVC_LW
+        | insts::OP_RVC_SW
+        | insts::OP_RVC_LWSP
+        | insts::OP_RVC_SWSP
+        | insts::OP_FLW
+        | insts::OP_FSW
+        | insts::OP_LR_W..=insts::OP_AMOMAXU_W => 4,
+        insts::OP_LD
+        | insts::OP_SD
+        | insts::OP_RVC_LD
+        | insts::OP_RVC_SD
+        | insts::OP_RVC_LDSP
+        | insts::OP_RVC_SDSP
+        | insts::OP_FLD
+        | insts::OP_FSD
+        | insts::OP_RVC_FLD
+        | insts::OP_RVC_FSD
+        | insts::OP_RVC_FLDSP
+        | insts::OP_RVC_FSDSP
+        | insts::OP_LR_D..=insts::OP_AMOMAXU_D => 8,
+        _ => return None,
+    };
+    Some((kind, size))
+}
+
+// Address and size of the memory accessed by load, store and atomic
+// instructions, computed from register values before the instruction is
+// executed. Other instructions return None.
+pub fn memory_access<R: Register>(i: Instruction, registers: &[R]) -> Option<(u64, u64)> {
+    let (_, size) = memory_op(i)?;
+    let op = extract_opcode(i);
+    let tagged = unpack(i);
+    let base = match op {
+        insts::OP_RVC_LWSP
+        | insts::OP_RVC_SWSP
+        | insts::OP_RVC_LDSP
+        | insts::OP_RVC_SDSP
+        | insts::OP_RVC_FLDSP
+        | insts::OP_RVC_FSDSP => SP,
+        _ => tagged.rs1()?,
+    };
+    let offset = tagged.imm().unwrap_or(0) as i64 as u64;
+    let addr = registers.get(base)?.to_u64().wrapping_add(offset);
+    // Addresses wrap around at the register width
+    let addr = if R::BITS == 32 {
+        addr as u32 as u64
+    } else {
+        addr
+    };
+    Some((addr, size))
+}
+
 #[inline(always)]
 pub fn instruction_length(i: Instruction) -> u8 {
     let o = extract_opcode(i);
-    if o >= MINIMAL_RVC_OPCODE && o <= MAXIMUM_RVC_OPCODE {
+    if (MINIMAL_RVC_OPCODE..=MAXIMUM_RVC_OPCODE).contains(&o)
+        || (insts::OP_RVC_FLD..=insts::OP_RVC_FSDSP).contains(&o)
+    {
         2
     } else {
         4
@@ -234,10 +414,28 @@ pub fn instruction_length(i: Instruction) -> u8 {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::mem::size_of;
+    use core::mem::size_of;
 
     #[test]
     fn test_instruction_op_should_fit_in_byte() {
         assert_eq!(1, size_of::<InstructionOpcode>());
     }
+
+    #[test]
+    fn test_call_depth_change() {
+        let jal = |rd| Utype::new(insts::OP_JAL, rd, 16).0;
+        let jalr = |rd, rs1| Itype::new(insts::OP_JALR, rd, rs1, 0).0;
+        assert_eq!(call_depth_change(jal(RA)), 1);
+        assert_eq!(call_depth_change(jal(0)), 0);
+        assert_eq!(call_depth_change(jalr(RA, 6)), 1);
+        assert_eq!(call_depth_change(jalr(0, RA)), -1);
+        assert_eq!(call_depth_change(jalr(0, 6)), 0);
+        assert_eq!(call_depth_change(jalr(RA, RA)), 1);
+        assert_eq!(call_depth_change(jalr(RA, T0)), 0);
+        let jr = Stype::new(insts::OP_RVC_JR, 0, RA, 0).0;
+        assert_eq!(call_depth_change(jr), -1);
+        let c_jalr = Stype::new(insts::OP_RVC_JALR, 0, T0, 0).0;
+        assert_eq!(call_depth_change(c_jalr), 0);
+        assert_eq!(call_depth_change(blank_instruction(insts::OP_ECALL)), 0);
+    }
 }
@@ -1,6 +1,6 @@
-use std::cmp::min;
-use std::fmt::Display;
-use std::ops::{BitAnd, BitOr, BitXor, Not, Shl, Shr};
+use core::cmp::min;
+use core::fmt::Display;
+use core::ops::{BitAnd, BitOr, BitXor, Not, Shl, Shr};
 
 pub trait Register:
     Sized
@@ -104,6 +104,108 @@ pub trait Register:
     fn ge_s(&self, other: &Self) -> Self {
         self.lt_s(other).logical_not()
     }
+
+    // Methods below are used by the B extension. The default versions are
+    // built purely on top of the required operations above, so they work for
+    // any register type. Native integer types are expected to override them
+    // with faster versions.
+    fn rol(&self, rhs: &Self) -> Self {
+        let shift = rhs.clone() & Self::from_u8(Self::SHIFT_MASK);
+        let reverse =
+            Self::from_u8(Self::BITS).overflowing_sub(&shift) & Self::from_u8(Self::SHIFT_MASK);
+        (self.clone() << shift) | (self.clone() >> reverse)
+    }
+
+    fn ror(&self, rhs: &Self) -> Self {
+        let shift = rhs.clone() & Self::from_u8(Self::SHIFT_MASK);
+        let reverse =
+            Self::from_u8(Self::BITS).overflowing_sub(&shift) & Self::from_u8(Self::SHIFT_MASK);
+        (self.clone() >> shift) | (self.clone() << reverse)
+    }
+
+    // Count leading zeros
+    fn clz(&self) -> Self {
+        let mut result = Self::from_u8(Self::BITS);
+        for i in 0..Self::BITS {
+            let bit = (self.clone() >> Self::from_u8(i)) & Self::one();
+            result = bit.cond(&Self::from_u8(Self::BITS - 1 - i), &result);
+        }
+        result
+    }
+
+    // Count trailing zeros
+    fn ctz(&self) -> Self {
+        let mut result = Self::from_u8(Self::BITS);
+        for i in (0..Self::BITS).rev() {
+            let bit = (self.clone() >> Self::from_u8(i)) & Self::one();
+            result = bit.cond(&Self::from_u8(i), &result);
+        }
+        result
+    }
+
+    // Count set bits
+    fn cpop(&self) -> Self {
+        let mut result = Self::zero();
+        for i in 0..Self::BITS {
+            let bit = (self.clone() >> Self::from_u8(i)) & Self::one();
+            result = result.overflowing_add(&bit);
+        }
+        result
+    }
+
+    // Each non-zero byte is set to 0xFF, each zero byte stays zero
+    fn orcb(&self) -> Self {
+        let mut result = Self::zero();
+        for i in (0..Self::BITS).step_by(8) {
+            let byte = (self.clone() >> Self::from_u8(i)) & Self::from_u8(0xFF);
+            let mask = Self::from_u8(0xFF) << Self::from_u8(i);
+            result = result | byte.eq(&Self::zero()).cond(&Self::zero(), &mask);
+        }
+        result
+    }
+
+    // Reverse byte order
+    fn rev8(&self) -> Self {
+        let mut result = Self::zero();
+        for i in (0..Self::BITS).step_by(8) {
+            let byte = (self.clone() >> Self::from_u8(i)) & Self::from_u8(0xFF);
+            result = result | (byte << Self::from_u8(Self::BITS - 8 - i));
+        }
+        result
+    }
+
+    // Carry-less multiplication, clmul returns the lower half of the full
+    // product, clmulh returns the upper half, while clmulr returns bits
+    // from 2*BITS-2 to BITS-1.
+    fn clmul(&self, rhs: &Self) -> Self {
+        let mut result = Self::zero();
+        for i in 0..Self::BITS {
+            let bit = (rhs.clone() >> Self::from_u8(i)) & Self::one();
+            let value = result.clone() ^ (self.clone() << Self::from_u8(i));
+            result = bit.cond(&value, &result);
+        }
+        result
+    }
+
+    fn clmulh(&self, rhs: &Self) -> Self {
+        let mut result = Self::zero();
+        for i in 1..Self::BITS {
+            let bit = (rhs.clone() >> Self::from_u8(i)) & Self::one();
+            let value = result.clone() ^ (self.clone() >> Self::from_u8(Self::BITS - i));
+            result = bit.cond(&value, &result);
+        }
+        result
+    }
+
+    fn clmulr(&self, rhs: &Self) -> Self {
+        let mut result = Self::zero();
+        for i in 0..Self::BITS {
+            let bit = (rhs.clone() >> Self::from_u8(i)) & Self::one();
+            let value = result.clone() ^ (self.clone() >> Self::from_u8(Self::BITS - i - 1));
+            result = bit.cond(&value, &result);
+        }
+        result
+    }
 }
 
 impl Register for u32 {
@@ -246,6 +348,70 @@ impl Register for u32 {
         (((*self << (32 - start_bit)) as i32) >> (32 - start_bit)) as u32
     }
 
+    fn rol(&self, rhs: &u32) -> u32 {
+        self.rotate_left(rhs & u32::from(Self::SHIFT_MASK))
+    }
+
+    fn ror(&self, rhs: &u32) -> u32 {
+        self.rotate_right(rhs & u32::from(Self::SHIFT_MASK))
+    }
+
+    fn clz(&self) -> u32 {
+        self.leading_zeros()
+    }
+
+    fn ctz(&self) -> u32 {
+        self.trailing_zeros()
+    }
+
+    fn cpop(&self) -> u32 {
+        self.count_ones()
+    }
+
+    fn orcb(&self) -> u32 {
+        let mut result = 0;
+        for i in (0..32).step_by(8) {
+            if (*self >> i) & 0xFF != 0 {
+                result |= 0xFF << i;
+            }
+        }
+        result
+    }
+
+    fn rev8(&self) -> u32 {
+        self.swap_bytes()
+    }
+
+    fn clmul(&self, rhs: &u32) -> u32 {
+        let mut result = 0;
+        for i in 0..32 {
+            if (*rhs >> i) & 1 != 0 {
+                result ^= *self << i;
+            }
+        }
+        result
+    }
+
+    fn clmulh(&self, rhs: &u32) -> u32 {
+        let mut result = 0;
+        for i in 1..32 {
+            if (*rhs >> i) & 1 != 0 {
+                result ^= *self >> (32 - i);
+            }
+        }
+        result
+    }
+
+    fn clmulr(&self, rhs: &u32) -> u32 {
+        let mut result = 0;
+        for i in 0..32 {
+            if (*rhs >> i) & 1 != 0 {
+                result ^= *self >> (32 - i - 1);
+            }
+        }
+        result
+    }
+
     fn to_i8(&self) -> i8 {
         *self as i8
     }
@@ -451,6 +617,70 @@ impl Register for u64 {
         (((*self << (64 - start_bit)) as i64) >> (64 - start_bit)) as u64
     }
 
+    fn rol(&self, rhs: &u64) -> u64 {
+        self.rotate_left((rhs & u64::from(Self::SHIFT_MASK)) as u32)
+    }
+
+    fn ror(&self, rhs: &u64) -> u64 {
+        self.rotate_right((rhs & u64::from(Self::SHIFT_MASK)) as u32)
+    }
+
+    fn clz(&self) -> u64 {
+        u64::from(self.leading_zeros())
+    }
+
+    fn ctz(&self) -> u64 {
+        u64::from(self.trailing_zeros())
+    }
+
+    fn cpop(&self) -> u64 {
+        u64::from(self.count_ones())
+    }
+
+    fn orcb(&self) -> u64 {
+        let mut result = 0;
+        for i in (0..64).step_by(8) {
+            if (*self >> i) & 0xFF != 0 {
+                result |= 0xFF << i;
+            }
+        }
+        result
+    }
+
+    fn rev8(&self) -> u64 {
+        self.swap_bytes()
+    }
+
+    fn clmul(&self, rhs: &u64) -> u64 {
+        let mut result = 0;
+        for i in 0..64 {
+            if (*rhs >> i) & 1 != 0 {
+                result ^= *self << i;
+            }
+        }
+        result
+    }
+
+    fn clmulh(&self, rhs: &u64) -> u64 {
+        let mut result = 0;
+        for i in 1..64 {
+            if (*rhs >> i) & 1 != 0 {
+                result ^= *self >> (64 - i);
+            }
+        }
+        result
+    }
+
+    fn clmulr(&self, rhs: &u64) -> u64 {
+        let mut result = 0;
+        for i in 0..64 {
+            if (*rhs >> i) & 1 != 0 {
+                result ^= *self >> (64 - i - 1);
+            }
+        }
+        result
+    }
+
     fn to_i8(&self) -> i8 {
         *self as i8
     }
@@ -515,3 +745,254 @@ impl Register for u64 {
         v
     }
 }
+
+// Full 256 bit product of 2 unsigned 128 bit values as (high, low) halves.
+fn widening_mul_u128(a: u128, b: u128) -> (u128, u128) {
+    const MASK: u128 = u64::MAX as u128;
+    let (a_high, a_low) = (a >> 64, a & MASK);
+    let (b_high, b_low) = (b >> 64, b & MASK);
+    let low_low = a_low * b_low;
+    let low_high = a_low * b_high;
+    let high_low = a_high * b_low;
+    let high_high = a_high * b_high;
+    let middle = (low_low >> 64) + (low_high & MASK) + (high_low & MASK);
+    let low = (low_low & MASK) | (middle << 64);
+    let high = high_high + (low_high >> 64) + (high_low >> 64) + (middle >> 64);
+    (high, low)
+}
+
+// 128 bit registers let machines experiment with wider register storage,
+// such as RV128. Decoders treat them as RV64 machines with wider registers.
+impl Register for u128 {
+    const BITS: u8 = 128;
+    const SHIFT_MASK: u8 = 0x7F;
+
+    fn zero() -> u128 {
+        0
+    }
+
+    fn one() -> u128 {
+        1
+    }
+
+    fn min_value() -> u128 {
+        u128::MIN
+    }
+
+    fn max_value() -> u128 {
+        u128::MAX
+    }
+
+    fn eq(&self, other: &u128) -> u128 {
+        (self == other).into()
+    }
+
+    fn lt(&self, other: &u128) -> u128 {
+        (self < other).into()
+    }
+
+    fn lt_s(&self, other: &u128) -> u128 {
+        ((*self as i128) < (*other as i128)).into()
+    }
+
+    fn logical_not(&self) -> u128 {
+        (*self != Self::one()).into()
+    }
+
+    fn cond(&self, true_value: &u128, false_value: &u128) -> u128 {
+        if *self == Self::one() {
+            *true_value
+        } else {
+            *false_value
+        }
+    }
+
+    fn overflowing_add(&self, rhs: &u128) -> u128 {
+        (*self).overflowing_add(*rhs).0
+    }
+
+    fn overflowing_sub(&self, rhs: &u128) -> u128 {
+        (*self).overflowing_sub(*rhs).0
+    }
+
+    fn overflowing_mul(&self, rhs: &u128) -> u128 {
+        (*self).overflowing_mul(*rhs).0
+    }
+
+    fn overflowing_div(&self, rhs: &u128) -> u128 {
+        if *rhs == 0 {
+            u128::MAX
+        } else {
+            (*self).overflowing_div(*rhs).0
+        }
+    }
+
+    fn overflowing_rem(&self, rhs: &u128) -> u128 {
+        if *rhs == 0 {
+            *self
+        } else {
+            (*self).overflowing_rem(*rhs).0
+        }
+    }
+
+    fn overflowing_div_signed(&self, rhs: &u128) -> u128 {
+        if *rhs == 0 {
+            (-1i128) as u128
+        } else {
+            let (v, o) = (*self as i128).overflowing_div(*rhs as i128);
+            if o {
+                // -2**(L-1) implemented using (-1) << (L - 1)
+                ((-1i128) as u128) << (Self::BITS - 1)
+            } else {
+                v as u128
+            }
+        }
+    }
+
+    fn overflowing_rem_signed(&self, rhs: &u128) -> u128 {
+        if *rhs == 0 {
+            *self
+        } else {
+            let (v, o) = (*self as i128).overflowing_rem(*rhs as i128);
+            if o {
+                0
+            } else {
+                v as u128
+            }
+        }
+    }
+
+    // There is no wider native type, signed variants correct the unsigned
+    // product by subtracting the other operand for each negative one.
+    fn overflowing_mul_high_signed(&self, rhs: &u128) -> u128 {
+        let (mut high, _) = widening_mul_u128(*self, *rhs);
+        if (*self as i128) < 0 {
+            high = high.wrapping_sub(*rhs);
+        }
+        if (*rhs as i128) < 0 {
+            high = high.wrapping_sub(*self);
+        }
+        high
+    }
+
+    fn overflowing_mul_high_unsigned(&self, rhs: &u128) -> u128 {
+        widening_mul_u128(*self, *rhs).0
+    }
+
+    fn overflowing_mul_high_signed_unsigned(&self, rhs: &u128) -> u128 {
+        let (high, _) = widening_mul_u128(*self, *rhs);
+        if (*self as i128) < 0 {
+            high.wrapping_sub(*rhs)
+        } else {
+            high
+        }
+    }
+
+    fn signed_shl(&self, rhs: &u128) -> u128 {
+        (*self as i128).shl(*rhs) as u128
+    }
+
+    fn signed_shr(&self, rhs: &u128) -> u128 {
+        (*self as i128).shr(*rhs) as u128
+    }
+
+    fn zero_extend(&self, start_bit: &u128) -> u128 {
+        let start_bit = min(*start_bit, 128);
+        debug_assert!(start_bit > 0);
+        (*self << (128 - start_bit)) >> (128 - start_bit)
+    }
+
+    fn sign_extend(&self, start_bit: &u128) -> u128 {
+        let start_bit = min(*start_bit, 128);
+        debug_assert!(start_bit > 0);
+        (((*self << (128 - start_bit)) as i128) >> (128 - start_bit)) as u128
+    }
+
+    fn rol(&self, rhs: &u128) -> u128 {
+        self.rotate_left((rhs & u128::from(Self::SHIFT_MASK)) as u32)
+    }
+
+    fn ror(&self, rhs: &u128) -> u128 {
+        self.rotate_right((rhs & u128::from(Self::SHIFT_MASK)) as u32)
+    }
+
+    fn clz(&self) -> u128 {
+        u128::from(self.leading_zeros())
+    }
+
+    fn ctz(&self) -> u128 {
+        u128::from(self.trailing_zeros())
+    }
+
+    fn cpop(&self) -> u128 {
+        u128::from(self.count_ones())
+    }
+
+    fn rev8(&self) -> u128 {
+        self.swap_bytes()
+    }
+
+    fn to_i8(&self) -> i8 {
+        *self as i8
+    }
+
+    fn to_i16(&self) -> i16 {
+        *self as i16
+    }
+
+    fn to_i32(&self) -> i32 {
+        *self as i32
+    }
+
+    fn to_i64(&self) -> i64 {
+        *self as i64
+    }
+
+    fn to_u8(&self) -> u8 {
+        *self as u8
+    }
+
+    fn to_u16(&self) -> u16 {
+        *self as u16
+    }
+
+    fn to_u32(&self) -> u32 {
+        *self as u32
+    }
+
+    fn to_u64(&self) -> u64 {
+        *self as u64
+    }
+
+    fn from_i8(v: i8) -> u128 {
+        i128::from(v) as u128
+    }
+
+    fn from_i16(v: i16) -> u128 {
+        i128::from(v) as u128
+    }
+
+    fn from_i32(v: i32) -> u128 {
+        i128::from(v) as u128
+    }
+
+    fn from_i64(v: i64) -> u128 {
+        i128::from(v) as u128
+    }
+
+    fn from_u8(v: u8) -> u128 {
+        u128::from(v)
+    }
+
+    fn from_u16(v: u16) -> u128 {
+        u128::from(v)
+    }
+
+    fn from_u32(v: u32) -> u128 {
+        u128::from(v)
+    }
+
+    fn from_u64(v: u64) -> u128 {
+        u128::from(v)
+    }
+}
@@ -6,7 +6,7 @@ use ckb_vm_definitions::instructions as insts;
 
 // Notice the location of rs2 in RVC encoding is different from full encoding
 #[inline(always)]
-fn c_rs2(instruction_bits: u32) -> usize {
+pub(super) fn c_rs2(instruction_bits: u32) -> usize {
     x(instruction_bits, 2, 5, 0) as usize
 }
 
@@ -15,7 +15,7 @@ fn c_rs2(instruction_bits: u32) -> usize {
 // used registers x8 - x15. In other words, a number of 0 extracted here means
 // x8, 1 means x9, etc.
 #[inline(always)]
-fn compact_register_number(instruction_bits: u32, least_bit: usize) -> usize {
+pub(super) fn compact_register_number(instruction_bits: u32, least_bit: usize) -> usize {
     x(instruction_bits, least_bit, 3, 0) as usize + 8
 }
 
@@ -45,7 +45,7 @@ fn j_immediate(instruction_bits: u32) -> i32 {
 
 // [12:10] => uimm[5:3]
 // [6:5]   => uimm[7:6]
-fn fld_uimmediate(instruction_bits: u32) -> u32 {
+pub(super) fn fld_uimmediate(instruction_bits: u32) -> u32 {
     x(instruction_bits, 10, 3, 3) | x(instruction_bits, 5, 2, 6)
 }
 
@@ -63,12 +63,12 @@ fn lwsp_uimmediate(instruction_bits: u32) -> u32 {
 
 // [12]  => uimm[5]
 // [6:2] => uimm[4:3|8:6]
-fn fldsp_uimmediate(instruction_bits: u32) -> u32 {
+pub(super) fn fldsp_uimmediate(instruction_bits: u32) -> u32 {
     x(instruction_bits, 5, 2, 3) | x(instruction_bits, 12, 1, 5) | x(instruction_bits, 2, 3, 6)
 }
 
 // [12:7] => uimm[5:3|8:6]
-fn fsdsp_uimmediate(instruction_bits: u32) -> u32 {
+pub(super) fn fsdsp_uimmediate(instruction_bits: u32) -> u32 {
     x(instruction_bits, 10, 3, 3) | x(instruction_bits, 7, 3, 6)
 }
 
@@ -88,13 +88,20 @@ fn b_immediate(instruction_bits: u32) -> i32 {
 }
 
 #[allow(clippy::cognitive_complexity)]
+// Decodes a single compressed instruction, None is returned for invalid
+// encodings as well as for the first half of a 32-bit instruction.
+pub fn decode_rvc<R: Register>(instruction_bits: u16) -> Option<Instruction> {
+    factory::<R>(u32::from(instruction_bits))
+}
+
 pub fn factory<R: Register>(instruction_bits: u32) -> Option<Instruction> {
     let bit_length = R::BITS;
-    if bit_length != 32 && bit_length != 64 {
+    if bit_length != 32 && bit_length != 64 && bit_length != 128 {
         return None;
     }
     let rv32 = bit_length == 32;
-    let rv64 = bit_length == 64;
+    // 128 bit registers run the RV64 instruction set
+    let rv64 = bit_length >= 64;
     match instruction_bits & 0b_111_00000000000_11 {
         // == Quadrant 0
         0b_000_00000000000_00 => {
@@ -0,0 +1,874 @@
+// A small software implementation of IEEE 754 binary32 and binary64
+// arithmetic. Host floating point is never used here: results, rounding and
+// exception flags must be identical on every platform, so all operations are
+// carried out on integers. Each operation returns the raw bits of the result
+// together with the exception flags it raised.
+
+pub const RM_RNE: u8 = 0;
+pub const RM_RTZ: u8 = 1;
+pub const RM_RDN: u8 = 2;
+pub const RM_RUP: u8 = 3;
+pub const RM_RMM: u8 = 4;
+pub const RM_DYN: u8 = 7;
+
+pub const FLAG_NX: u8 = 0x01;
+pub const FLAG_UF: u8 = 0x02;
+pub const FLAG_OF: u8 = 0x04;
+pub const FLAG_DZ: u8 = 0x08;
+pub const FLAG_NV: u8 = 0x10;
+
+pub type Result = (u64, u8);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Format {
+    exp_bits: u32,
+    frac_bits: u32,
+}
+
+pub const F32: Format = Format {
+    exp_bits: 8,
+    frac_bits: 23,
+};
+
+pub const F64: Format = Format {
+    exp_bits: 11,
+    frac_bits: 52,
+};
+
+impl Format {
+    fn bias(self) -> i32 {
+        (1 << (self.exp_bits - 1)) - 1
+    }
+
+    fn max_exp(self) -> u64 {
+        (1 << self.exp_bits) - 1
+    }
+
+    fn sign_bit(self) -> u64 {
+        1 << (self.exp_bits + self.frac_bits)
+    }
+
+    fn frac_mask(self) -> u64 {
+        (1 << self.frac_bits) - 1
+    }
+
+    // Exponent of the least significant bit of subnormal numbers.
+    fn min_lsb_exp(self) -> i32 {
+        1 - self.bias() - self.frac_bits as i32
+    }
+
+    fn sign(self, sign: bool) -> u64 {
+        if sign {
+            self.sign_bit()
+        } else {
+            0
+        }
+    }
+
+    pub fn canonical_nan(self) -> u64 {
+        (self.max_exp() << self.frac_bits) | (1 << (self.frac_bits - 1))
+    }
+
+    pub fn zero(self, sign: bool) -> u64 {
+        self.sign(sign)
+    }
+
+    pub fn infinity(self, sign: bool) -> u64 {
+        self.sign(sign) | (self.max_exp() << self.frac_bits)
+    }
+
+    fn max_finite(self, sign: bool) -> u64 {
+        self.infinity(sign) - 1
+    }
+
+    pub fn negate(self, bits: u64) -> u64 {
+        bits ^ self.sign_bit()
+    }
+
+    fn unpack(self, bits: u64) -> (bool, Value) {
+        let sign = bits & self.sign_bit() != 0;
+        let exp = (bits >> self.frac_bits) & self.max_exp();
+        let frac = bits & self.frac_mask();
+        let value = if exp == self.max_exp() {
+            if frac == 0 {
+                Value::Infinity
+            } else {
+                Value::NaN {
+                    signaling: frac >> (self.frac_bits - 1) == 0,
+                }
+            }
+        } else if exp == 0 {
+            if frac == 0 {
+                Value::Zero
+            } else {
+                Value::Finite {
+                    exp: self.min_lsb_exp(),
+                    sig: u128::from(frac),
+                }
+            }
+        } else {
+            Value::Finite {
+                exp: exp as i32 + self.min_lsb_exp() - 1,
+                sig: u128::from(frac | (1 << self.frac_bits)),
+            }
+        };
+        (sign, value)
+    }
+}
+
+// A finite value here is sig * 2^exp.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Value {
+    NaN { signaling: bool },
+    Infinity,
+    Zero,
+    Finite { exp: i32, sig: u128 },
+}
+
+impl Value {
+    fn is_nan(self) -> bool {
+        matches!(self, Value::NaN { .. })
+    }
+
+    fn is_signaling(self) -> bool {
+        self == Value::NaN { signaling: true }
+    }
+}
+
+fn nan_result(fmt: Format, values: &[Value]) -> Result {
+    let flags = if values.iter().any(|v| v.is_signaling()) {
+        FLAG_NV
+    } else {
+        0
+    };
+    (fmt.canonical_nan(), flags)
+}
+
+fn bit_length(value: u128) -> i32 {
+    128 - value.leading_zeros() as i32
+}
+
+// Shifts sig right, folding all shifted out bits into the sticky bit.
+fn shift_right_sticky(sig: u128, shift: u32) -> (u128, bool) {
+    if shift == 0 {
+        (sig, false)
+    } else if shift >= 128 {
+        (0, sig != 0)
+    } else {
+        (sig >> shift, sig & ((1 << shift) - 1) != 0)
+    }
+}
+
+// Moves sig so its most significant bit lands on bit 125, leaving enough
+// headroom for additions and enough room below for alignment.
+fn normalize(exp: i32, sig: u128) -> (i32, u128) {
+    let shift = 126 - bit_length(sig);
+    debug_assert!(shift >= 0);
+    (exp - shift, sig << shift)
+}
+
+// Rounds (sig + sticky) / 2^shift to an integer, where a set sticky bit
+// stands for a non-zero value smaller than the least significant bit of sig.
+// Returns the rounded value and whether any precision was lost.
+fn shift_round(sig: u128, sticky: bool, shift: i32, rm: u8, sign: bool) -> (u128, bool) {
+    let (quotient, above_half, half) = if shift <= 0 {
+        (sig << -shift, false, false)
+    } else if shift > 128 {
+        (0, false, false)
+    } else if shift == 128 {
+        (0, sig > 1 << 127, sig == 1 << 127)
+    } else {
+        let remainder = sig & ((1 << shift) - 1);
+        let half = 1 << (shift - 1);
+        (sig >> shift, remainder > half, remainder == half)
+    };
+    let exact_below = if shift <= 0 {
+        !sticky
+    } else if shift >= 128 {
+        sig == 0 && !sticky
+    } else {
+        sig & ((1 << shift) - 1) == 0 && !sticky
+    };
+    let inexact = !exact_below;
+    let above_half = above_half || (half && sticky);
+    let half = half && !sticky;
+    let round_up = match rm {
+        RM_RNE => above_half || (half && quotient & 1 == 1),
+        RM_RTZ => false,
+        RM_RDN => inexact && sign,
+        RM_RUP => inexact && !sign,
+        RM_RMM => above_half || half,
+        _ => unreachable!(),
+    };
+    (quotient + u128::from(round_up), inexact)
+}
+
+// Rounds the exact value (-1)^sign * (sig + sticky) * 2^exp into the target
+// format. Tininess is detected after rounding as RISC-V requires.
+fn round_pack(fmt: Format, sign: bool, exp: i32, sig: u128, sticky: bool, rm: u8) -> Result {
+    if sig == 0 && !sticky {
+        return (fmt.zero(sign), 0);
+    }
+    let precision = fmt.frac_bits as i32 + 1;
+    let min_lsb = fmt.min_lsb_exp();
+    let unbounded_lsb = exp + bit_length(sig) - precision;
+    let mut lsb = unbounded_lsb.max(min_lsb);
+    let (mut rounded, inexact) = shift_round(sig, sticky, lsb - exp, rm, sign);
+    if rounded >> precision != 0 {
+        rounded >>= 1;
+        lsb += 1;
+    }
+    let mut flags = 0;
+    if inexact {
+        flags |= FLAG_NX;
+        if unbounded_lsb < min_lsb {
+            // The only way a result below the normal range is not tiny is
+            // when rounding with unbounded exponent carries it up to the
+            // smallest normal number.
+            let (unbounded, _) = shift_round(sig, sticky, unbounded_lsb - exp, rm, sign);
+            let carried = unbounded >> precision != 0;
+            if !(carried && unbounded_lsb + 1 == min_lsb) {
+                flags |= FLAG_UF;
+            }
+        }
+    }
+    if rounded == 0 {
+        return (fmt.zero(sign), flags);
+    }
+    let biased_exp = if rounded >> fmt.frac_bits == 0 {
+        0
+    } else {
+        (lsb - min_lsb + 1) as u64
+    };
+    if biased_exp >= fmt.max_exp() {
+        let overflow_to_infinity = match rm {
+            RM_RTZ => false,
+            RM_RDN => sign,
+            RM_RUP => !sign,
+            _ => true,
+        };
+        let bits = if overflow_to_infinity {
+            fmt.infinity(sign)
+        } else {
+            fmt.max_finite(sign)
+        };
+        return (bits, flags | FLAG_OF | FLAG_NX);
+    }
+    let bits = fmt.sign(sign) | (biased_exp << fmt.frac_bits) | (rounded as u64 & fmt.frac_mask());
+    (bits, flags)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn add_finite(
+    fmt: Format,
+    sign_a: bool,
+    exp_a: i32,
+    sig_a: u128,
+    sign_b: bool,
+    exp_b: i32,
+    sig_b: u128,
+    rm: u8,
+) -> Result {
+    let a = normalize(exp_a, sig_a);
+    let b = normalize(exp_b, sig_b);
+    let ((sign_big, (exp, big)), (sign_small, (exp_small, small))) = if a >= b {
+        ((sign_a, a), (sign_b, b))
+    } else {
+        ((sign_b, b), (sign_a, a))
+    };
+    let (small, sticky) = shift_right_sticky(small, (exp - exp_small) as u32);
+    if sign_big == sign_small {
+        round_pack(fmt, sign_big, exp, big + small, sticky, rm)
+    } else {
+        // big - (small + d) = (big - small - 1) + (1 - d) for 0 < d < 1,
+        // so the sticky bit is still set after the subtraction.
+        let difference = big - small - u128::from(sticky);
+        if difference == 0 && !sticky {
+            return (fmt.zero(rm == RM_RDN), 0);
+        }
+        round_pack(fmt, sign_big, exp, difference, sticky, rm)
+    }
+}
+
+pub fn add(fmt: Format, a: u64, b: u64, rm: u8) -> Result {
+    let (sign_a, value_a) = fmt.unpack(a);
+    let (sign_b, value_b) = fmt.unpack(b);
+    match (value_a, value_b) {
+        _ if value_a.is_nan() || value_b.is_nan() => nan_result(fmt, &[value_a, value_b]),
+        (Value::Infinity, Value::Infinity) if sign_a != sign_b => (fmt.canonical_nan(), FLAG_NV),
+        (Value::Infinity, _) => (fmt.infinity(sign_a), 0),
+        (_, Value::Infinity) => (fmt.infinity(sign_b), 0),
+        (Value::Zero, Value::Zero) if sign_a == sign_b => (fmt.zero(sign_a), 0),
+        (Value::Zero, Value::Zero) => (fmt.zero(rm == RM_RDN), 0),
+        (Value::Zero, _) => (b, 0),
+        (_, Value::Zero) => (a, 0),
+        (
+            Value::Finite {
+                exp: exp_a,
+                sig: sig_a,
+            },
+            Value::Finite {
+                exp: exp_b,
+                sig: sig_b,
+            },
+        ) => add_finite(fmt, sign_a, exp_a, sig_a, sign_b, exp_b, sig_b, rm),
+        _ => unreachable!(),
+    }
+}
+
+pub fn sub(fmt: Format, a: u64, b: u64, rm: u8) -> Result {
+    add(fmt, a, fmt.negate(b), rm)
+}
+
+pub fn mul(fmt: Format, a: u64, b: u64, rm: u8) -> Result {
+    let (sign_a, value_a) = fmt.unpack(a);
+    let (sign_b, value_b) = fmt.unpack(b);
+    let sign = sign_a != sign_b;
+    match (value_a, value_b) {
+        _ if value_a.is_nan() || value_b.is_nan() => nan_result(fmt, &[value_a, value_b]),
+        (Value::Infinity, Value::Zero) | (Value::Zero, Value::Infinity) => {
+            (fmt.canonical_nan(), FLAG_NV)
+        }
+        (Value::Infinity, _) | (_, Value::Infinity) => (fmt.infinity(sign), 0),
+        (Value::Zero, _) | (_, Value::Zero) => (fmt.zero(sign), 0),
+        (
+            Value::Finite {
+                exp: exp_a,
+                sig: sig_a,
+            },
+            Value::Finite {
+                exp: exp_b,
+                sig: sig_b,
+            },
+        ) => round_pack(fmt, sign, exp_a + exp_b, sig_a * sig_b, false, rm),
+        _ => unreachable!(),
+    }
+}
+
+pub fn div(fmt: Format, a: u64, b: u64, rm: u8) -> Result {
+    let (sign_a, value_a) = fmt.unpack(a);
+    let (sign_b, value_b) = fmt.unpack(b);
+    let sign = sign_a != sign_b;
+    match (value_a, value_b) {
+        _ if value_a.is_nan() || value_b.is_nan() => nan_result(fmt, &[value_a, value_b]),
+        (Value::Infinity, Value::Infinity) | (Value::Zero, Value::Zero) => {
+            (fmt.canonical_nan(), FLAG_NV)
+        }
+        (Value::Infinity, _) => (fmt.infinity(sign), 0),
+        (_, Value::Infinity) | (Value::Zero, _) => (fmt.zero(sign), 0),
+        (_, Value::Zero) => (fmt.infinity(sign), FLAG_DZ),
+        (
+            Value::Finite {
+                exp: exp_a,
+                sig: sig_a,
+            },
+            Value::Finite {
+                exp: exp_b,
+                sig: sig_b,
+            },
+        ) => {
+            // Widening the dividend keeps at least 73 bits in the quotient,
+            // more than enough for rounding.
+            let shift = 126 - bit_length(sig_a);
+            let dividend = sig_a << shift;
+            let quotient = dividend / sig_b;
+            let sticky = dividend - quotient * sig_b != 0;
+            round_pack(fmt, sign, exp_a - shift - exp_b, quotient, sticky, rm)
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn isqrt(value: u128) -> (u128, bool) {
+    let mut remainder = value;
+    let mut result = 0;
+    let mut bit = 1 << 126;
+    while bit > value {
+        bit >>= 2;
+    }
+    while bit != 0 {
+        if remainder >= result + bit {
+            remainder -= result + bit;
+            result = (result >> 1) + bit;
+        } else {
+            result >>= 1;
+        }
+        bit >>= 2;
+    }
+    (result, remainder != 0)
+}
+
+pub fn sqrt(fmt: Format, a: u64, rm: u8) -> Result {
+    let (sign, value) = fmt.unpack(a);
+    match value {
+        Value::NaN { .. } => nan_result(fmt, &[value]),
+        Value::Zero => (a, 0),
+        _ if sign => (fmt.canonical_nan(), FLAG_NV),
+        Value::Infinity => (a, 0),
+        Value::Finite { exp, sig } => {
+            // The exponent must be even so it can be halved exactly.
+            let mut shift = 125 - bit_length(sig);
+            if (exp - shift) & 1 != 0 {
+                shift += 1;
+            }
+            let (root, sticky) = isqrt(sig << shift);
+            round_pack(fmt, false, (exp - shift) / 2, root, sticky, rm)
+        }
+    }
+}
+
+// Computes a * b + c with a single rounding.
+pub fn fma(fmt: Format, a: u64, b: u64, c: u64, rm: u8) -> Result {
+    let (sign_a, value_a) = fmt.unpack(a);
+    let (sign_b, value_b) = fmt.unpack(b);
+    let (sign_c, value_c) = fmt.unpack(c);
+    let sign_product = sign_a != sign_b;
+    let invalid_product = matches!(
+        (value_a, value_b),
+        (Value::Infinity, Value::Zero) | (Value::Zero, Value::Infinity)
+    );
+    if value_a.is_nan() || value_b.is_nan() || value_c.is_nan() {
+        let (bits, flags) = nan_result(fmt, &[value_a, value_b, value_c]);
+        let flags = if invalid_product { FLAG_NV } else { flags };
+        return (bits, flags);
+    }
+    if invalid_product {
+        return (fmt.canonical_nan(), FLAG_NV);
+    }
+    match (value_a, value_b, value_c) {
+        (Value::Infinity, _, _) | (_, Value::Infinity, _) => {
+            if value_c == Value::Infinity && sign_c != sign_product {
+                (fmt.canonical_nan(), FLAG_NV)
+            } else {
+                (fmt.infinity(sign_product), 0)
+            }
+        }
+        (_, _, Value::Infinity) => (fmt.infinity(sign_c), 0),
+        (Value::Zero, _, Value::Zero) | (_, Value::Zero, Value::Zero) => {
+            if sign_product == sign_c {
+                (fmt.zero(sign_c), 0)
+            } else {
+                (fmt.zero(rm == RM_RDN), 0)
+            }
+        }
+        (Value::Zero, _, _) | (_, Value::Zero, _) => (c, 0),
+        (
+            Value::Finite {
+                exp: exp_a,
+                sig: sig_a,
+            },
+            Value::Finite {
+                exp: exp_b,
+                sig: sig_b,
+            },
+            _,
+        ) => {
+            let (exp, sig) = (exp_a + exp_b, sig_a * sig_b);
+            match value_c {
+                Value::Zero => round_pack(fmt, sign_product, exp, sig, false, rm),
+                Value::Finite {
+                    exp: exp_c,
+                    sig: sig_c,
+                } => add_finite(fmt, sign_product, exp, sig, sign_c, exp_c, sig_c, rm),
+                _ => unreachable!(),
+            }
+        }
+        _ => unreachable!(),
+    }
+}
+
+pub fn copy_sign(fmt: Format, a: u64, b: u64) -> u64 {
+    (a & !fmt.sign_bit()) | (b & fmt.sign_bit())
+}
+
+pub fn xor_sign(fmt: Format, a: u64, b: u64) -> u64 {
+    a ^ (b & fmt.sign_bit())
+}
+
+// Orders two non NaN values, treating both zeros as equal.
+fn less_than(fmt: Format, a: u64, b: u64) -> bool {
+    let key = |bits: u64| {
+        let magnitude = (bits & !fmt.sign_bit()) as i64;
+        if bits & fmt.sign_bit() != 0 {
+            -magnitude
+        } else {
+            magnitude
+        }
+    };
+    key(a) < key(b)
+}
+
+// Implements minimumNumber / maximumNumber: a NaN operand is ignored unless
+// both operands are NaN, and -0 is considered smaller than +0.
+pub fn min_max(fmt: Format, a: u64, b: u64, max: bool) -> Result {
+    let (sign_a, value_a) = fmt.unpack(a);
+    let (sign_b, value_b) = fmt.unpack(b);
+    let flags = if value_a.is_signaling() || value_b.is_signaling() {
+        FLAG_NV
+    } else {
+        0
+    };
+    let bits = if value_a.is_nan() && value_b.is_nan() {
+        fmt.canonical_nan()
+    } else if value_a.is_nan() {
+        b
+    } else if value_b.is_nan() {
+        a
+    } else if value_a == Value::Zero && value_b == Value::Zero {
+        fmt.zero(if max {
+            sign_a && sign_b
+        } else {
+            sign_a || sign_b
+        })
+    } else if less_than(fmt, a, b) != max {
+        a
+    } else {
+        b
+    };
+    (bits, flags)
+}
+
+// The quiet comparison used by feq.
+pub fn eq(fmt: Format, a: u64, b: u64) -> Result {
+    let (_, value_a) = fmt.unpack(a);
+    let (_, value_b) = fmt.unpack(b);
+    if value_a.is_nan() || value_b.is_nan() {
+        return (0, nan_result(fmt, &[value_a, value_b]).1);
+    }
+    let equal = a == b || (value_a == Value::Zero && value_b == Value::Zero);
+    (u64::from(equal), 0)
+}
+
+// The signaling comparisons used by flt and fle.
+pub fn lt(fmt: Format, a: u64, b: u64) -> Result {
+    let (_, value_a) = fmt.unpack(a);
+    let (_, value_b) = fmt.unpack(b);
+    if value_a.is_nan() || value_b.is_nan() {
+        return (0, FLAG_NV);
+    }
+    (u64::from(less_than(fmt, a, b)), 0)
+}
+
+pub fn le(fmt: Format, a: u64, b: u64) -> Result {
+    let (_, value_a) = fmt.unpack(a);
+    let (_, value_b) = fmt.unpack(b);
+    if value_a.is_nan() || value_b.is_nan() {
+        return (0, FLAG_NV);
+    }
+    (u64::from(!less_than(fmt, b, a)), 0)
+}
+
+pub fn classify(fmt: Format, a: u64) -> u64 {
+    let (sign, value) = fmt.unpack(a);
+    let subnormal = (a >> fmt.frac_bits) & fmt.max_exp() == 0;
+    let bit = match value {
+        Value::Infinity if sign => 0,
+        Value::Finite { .. } if sign && !subnormal => 1,
+        Value::Finite { .. } if sign => 2,
+        Value::Zero if sign => 3,
+        Value::Zero => 4,
+        Value::Finite { .. } if subnormal => 5,
+        Value::Finite { .. } => 6,
+        Value::Infinity => 7,
+        Value::NaN { signaling: true } => 8,
+        Value::NaN { signaling: false } => 9,
+    };
+    1 << bit
+}
+
+// Converts a float to a 32 or 64 bit integer. Out of range values and NaNs
+// saturate and raise the invalid flag. The returned bits hold the two's
+// complement value, callers sign extend 32 bit results as needed.
+pub fn to_int(fmt: Format, a: u64, rm: u8, signed: bool, bits: u32) -> Result {
+    let (sign, value) = fmt.unpack(a);
+    let (max, min) = if signed {
+        ((1u64 << (bits - 1)) - 1, (-1i64 << (bits - 1)) as u64)
+    } else {
+        (u64::MAX >> (64 - bits), 0)
+    };
+    let (magnitude, inexact) = match value {
+        Value::NaN { .. } => return (max, FLAG_NV),
+        Value::Infinity => return (if sign { min } else { max }, FLAG_NV),
+        Value::Zero => return (0, 0),
+        Value::Finite { exp, sig } => {
+            if exp > 64 {
+                (u128::MAX, false)
+            } else {
+                shift_round(sig, false, -exp, rm, sign)
+            }
+        }
+    };
+    let flags = if inexact { FLAG_NX } else { 0 };
+    if signed {
+        if sign && magnitude > u128::from(max) + 1 {
+            (min, FLAG_NV)
+        } else if !sign && magnitude > u128::from(max) {
+            (max, FLAG_NV)
+        } else if sign {
+            ((magnitude as u64).wrapping_neg(), flags)
+        } else {
+            (magnitude as u64, flags)
+        }
+    } else if sign && magnitude != 0 {
+        (min, FLAG_NV)
+    } else if magnitude > u128::from(max) {
+        (max, FLAG_NV)
+    } else {
+        (magnitude as u64, flags)
+    }
+}
+
+// Converts the lower `bits` bits of value, interpreted as a signed or
+// unsigned integer, to a float.
+pub fn from_int(fmt: Format, value: u64, signed: bool, bits: u32, rm: u8) -> Result {
+    let shift = 64 - bits;
+    let (sign, magnitude) = if signed {
+        let value = (value as i64) << shift >> shift;
+        (value < 0, value.unsigned_abs())
+    } else {
+        (false, value << shift >> shift)
+    };
+    round_pack(fmt, sign, 0, u128::from(magnitude), false, rm)
+}
+
+// Converts between formats, this is exact when widening.
+pub fn convert(from: Format, to: Format, a: u64, rm: u8) -> Result {
+    let (sign, value) = from.unpack(a);
+    match value {
+        Value::NaN { .. } => nan_result(to, &[value]),
+        Value::Infinity => (to.infinity(sign), 0),
+        Value::Zero => (to.zero(sign), 0),
+        Value::Finite { exp, sig } => round_pack(to, sign, exp, sig, false, rm),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Host floating point is only used here as a reference, it follows IEEE
+    // 754 with round to nearest, ties to even.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        // Mixes fully random bits with values of close exponents and
+        // subnormals, so cancellation and gradual underflow are covered.
+        fn next_f64(&mut self) -> u64 {
+            let bits = self.next();
+            match bits % 4 {
+                0 => bits,
+                1 => (bits & 0x800F_FFFF_FFFF_FFFF) | 0x3FF0_0000_0000_0000,
+                2 => bits & 0x800F_FFFF_FFFF_FFFF,
+                _ => (bits & 0x801F_FFFF_FFFF_FFFF) | 0x0010_0000_0000_0000,
+            }
+        }
+
+        fn next_f32(&mut self) -> u64 {
+            let bits = self.next() & 0xFFFF_FFFF;
+            match bits % 4 {
+                0 => bits,
+                1 => (bits & 0x807F_FFFF) | 0x3F80_0000,
+                2 => bits & 0x807F_FFFF,
+                _ => (bits & 0x80FF_FFFF) | 0x0080_0000,
+            }
+        }
+    }
+
+    fn check64(expected: f64, (bits, _): Result) {
+        if expected.is_nan() {
+            assert_eq!(bits, F64.canonical_nan());
+        } else {
+            assert_eq!(bits, expected.to_bits());
+        }
+    }
+
+    fn check32(expected: f32, (bits, _): Result) {
+        if expected.is_nan() {
+            assert_eq!(bits, F32.canonical_nan());
+        } else {
+            assert_eq!(bits, u64::from(expected.to_bits()));
+        }
+    }
+
+    #[test]
+    fn test_f64_matches_host() {
+        let mut rng = Rng(0x1234_5678_9ABC_DEF1);
+        for _ in 0..20000 {
+            let (a, b, c) = (rng.next_f64(), rng.next_f64(), rng.next_f64());
+            let (x, y, z) = (f64::from_bits(a), f64::from_bits(b), f64::from_bits(c));
+            check64(x + y, add(F64, a, b, RM_RNE));
+            check64(x - y, sub(F64, a, b, RM_RNE));
+            check64(x * y, mul(F64, a, b, RM_RNE));
+            check64(x / y, div(F64, a, b, RM_RNE));
+            check64(x.sqrt(), sqrt(F64, a, RM_RNE));
+            check64(x.mul_add(y, z), fma(F64, a, b, c, RM_RNE));
+            check32(x as f32, convert(F64, F32, a, RM_RNE));
+        }
+    }
+
+    #[test]
+    fn test_f32_matches_host() {
+        let mut rng = Rng(0x0FED_CBA9_8765_4321);
+        for _ in 0..20000 {
+            let (a, b, c) = (rng.next_f32(), rng.next_f32(), rng.next_f32());
+            let (x, y, z) = (
+                f32::from_bits(a as u32),
+                f32::from_bits(b as u32),
+                f32::from_bits(c as u32),
+            );
+            check32(x + y, add(F32, a, b, RM_RNE));
+            check32(x - y, sub(F32, a, b, RM_RNE));
+            check32(x * y, mul(F32, a, b, RM_RNE));
+            check32(x / y, div(F32, a, b, RM_RNE));
+            check32(x.sqrt(), sqrt(F32, a, RM_RNE));
+            check32(x.mul_add(y, z), fma(F32, a, b, c, RM_RNE));
+            check64(f64::from(x), convert(F32, F64, a, RM_RNE));
+        }
+    }
+
+    #[test]
+    fn test_rounding_modes() {
+        let one = 1.0f64.to_bits();
+        let tiny = 2.0f64.powi(-60).to_bits();
+        let next = f64::from_bits(one + 1);
+        let previous = f64::from_bits(one - 1);
+        check64(1.0, add(F64, one, tiny, RM_RNE));
+        check64(1.0, add(F64, one, tiny, RM_RTZ));
+        check64(1.0, add(F64, one, tiny, RM_RDN));
+        check64(next, add(F64, one, tiny, RM_RUP));
+        check64(previous, sub(F64, one, tiny, RM_RTZ));
+        check64(previous, sub(F64, one, tiny, RM_RDN));
+        check64(1.0, sub(F64, one, tiny, RM_RUP));
+        // Ties
+        let half = 2.0f64.powi(-53).to_bits();
+        check64(1.0, add(F64, one, half, RM_RNE));
+        check64(next, add(F64, one, half, RM_RMM));
+        // Exact zero results are negative only when rounding down
+        assert_eq!(sub(F64, one, one, RM_RNE), (F64.zero(false), 0));
+        assert_eq!(sub(F64, one, one, RM_RDN), (F64.zero(true), 0));
+    }
+
+    #[test]
+    fn test_exception_flags() {
+        let one = 1.0f64.to_bits();
+        let three = 3.0f64.to_bits();
+        assert_eq!(div(F64, one, three, RM_RNE).1, FLAG_NX);
+        assert_eq!(div(F64, one, 0, RM_RNE), (F64.infinity(false), FLAG_DZ));
+        assert_eq!(div(F64, 0, 0, RM_RNE), (F64.canonical_nan(), FLAG_NV));
+        assert_eq!(
+            sqrt(F64, (-1.0f64).to_bits(), RM_RNE),
+            (F64.canonical_nan(), FLAG_NV)
+        );
+        let inf = F64.infinity(false);
+        assert_eq!(sub(F64, inf, inf, RM_RNE), (F64.canonical_nan(), FLAG_NV));
+        let max = f64::MAX.to_bits();
+        assert_eq!(add(F64, max, max, RM_RNE), (inf, FLAG_OF | FLAG_NX));
+        assert_eq!(add(F64, max, max, RM_RTZ), (max, FLAG_OF | FLAG_NX));
+        // The smallest subnormal halved is tiny and inexact
+        assert_eq!(
+            mul(F64, 1, 0x3FE0_0000_0000_0000, RM_RNE),
+            (0, FLAG_UF | FLAG_NX)
+        );
+        // Exact subnormal results do not underflow
+        assert_eq!(mul(F64, 2, 0x3FE0_0000_0000_0000, RM_RNE), (1, 0));
+        // Tininess is detected after rounding, this one rounds up to the
+        // smallest normal number
+        assert_eq!(
+            mul(F64, 0x000F_FFFF_FFFF_FFFF, 0x3FF0_0000_0000_0001, RM_RNE),
+            (0x0010_0000_0000_0000, FLAG_NX)
+        );
+        // Signaling NaNs raise invalid, quiet ones propagate silently
+        let snan = 0x7FF0_0000_0000_0001;
+        let qnan = F64.canonical_nan();
+        assert_eq!(add(F64, snan, one, RM_RNE), (qnan, FLAG_NV));
+        assert_eq!(add(F64, qnan | 1, one, RM_RNE), (qnan, 0));
+        // Invalid products are reported even when the addend is a quiet NaN
+        assert_eq!(fma(F64, inf, 0, qnan, RM_RNE), (qnan, FLAG_NV));
+    }
+
+    #[test]
+    fn test_integer_conversions() {
+        let value = |v: f64| v.to_bits();
+        assert_eq!(to_int(F64, value(2.5), RM_RNE, true, 32), (2, FLAG_NX));
+        assert_eq!(to_int(F64, value(2.5), RM_RMM, true, 32), (3, FLAG_NX));
+        assert_eq!(
+            to_int(F64, value(-2.5), RM_RTZ, true, 32),
+            ((-2i64) as u64, FLAG_NX)
+        );
+        assert_eq!(
+            to_int(F64, value(-2.5), RM_RDN, true, 32),
+            ((-3i64) as u64, FLAG_NX)
+        );
+        assert_eq!(
+            to_int(F64, value(1e10), RM_RNE, true, 32),
+            (0x7FFF_FFFF, FLAG_NV)
+        );
+        assert_eq!(
+            to_int(F64, value(-1e10), RM_RNE, true, 32),
+            ((-1i64 << 31) as u64, FLAG_NV)
+        );
+        assert_eq!(to_int(F64, value(-1.5), RM_RNE, false, 32), (0, FLAG_NV));
+        assert_eq!(to_int(F64, value(-0.25), RM_RTZ, false, 64), (0, FLAG_NX));
+        assert_eq!(
+            to_int(F64, value(1e30), RM_RNE, false, 64),
+            (u64::MAX, FLAG_NV)
+        );
+        assert_eq!(
+            to_int(F64, F64.canonical_nan(), RM_RNE, true, 64),
+            (i64::MAX as u64, FLAG_NV)
+        );
+        assert_eq!(
+            to_int(F64, value(-(2.0f64.powi(63))), RM_RNE, true, 64),
+            (i64::MIN as u64, 0)
+        );
+        assert_eq!(
+            from_int(F32, u64::MAX, false, 64, RM_RNE),
+            (u64::from(1.844_674_4e19f32.to_bits()), FLAG_NX)
+        );
+        assert_eq!(
+            from_int(F64, 0x8000_0000, true, 32, RM_RNE),
+            (value(-2_147_483_648.0), 0)
+        );
+        assert_eq!(
+            from_int(F64, 0x8000_0000, false, 32, RM_RNE),
+            (value(2_147_483_648.0), 0)
+        );
+    }
+
+    #[test]
+    fn test_compare_and_classify() {
+        let negative_zero = F32.zero(true);
+        let one = u64::from(1.0f32.to_bits());
+        let qnan = F32.canonical_nan();
+        let snan = 0x7F80_0001;
+        assert_eq!(min_max(F32, 0, negative_zero, false), (negative_zero, 0));
+        assert_eq!(min_max(F32, 0, negative_zero, true), (0, 0));
+        assert_eq!(min_max(F32, qnan, one, false), (one, 0));
+        assert_eq!(min_max(F32, snan, one, true), (one, FLAG_NV));
+        assert_eq!(min_max(F32, qnan, qnan, true), (qnan, 0));
+        assert_eq!(eq(F32, 0, negative_zero), (1, 0));
+        assert_eq!(eq(F32, qnan, one), (0, 0));
+        assert_eq!(eq(F32, snan, one), (0, FLAG_NV));
+        assert_eq!(lt(F32, qnan, one), (0, FLAG_NV));
+        assert_eq!(lt(F32, negative_zero, 0), (0, 0));
+        assert_eq!(le(F32, negative_zero, 0), (1, 0));
+        assert_eq!(le(F32, F32.infinity(true), one), (1, 0));
+        assert_eq!(classify(F32, F32.infinity(true)), 1 << 0);
+        assert_eq!(classify(F32, F32.negate(one)), 1 << 1);
+        assert_eq!(classify(F32, F32.negate(1)), 1 << 2);
+        assert_eq!(classify(F32, negative_zero), 1 << 3);
+        assert_eq!(classify(F32, 0), 1 << 4);
+        assert_eq!(classify(F32, 1), 1 << 5);
+        assert_eq!(classify(F32, one), 1 << 6);
+        assert_eq!(classify(F32, F32.infinity(false)), 1 << 7);
+        assert_eq!(classify(F32, snan), 1 << 8);
+        assert_eq!(classify(F32, qnan), 1 << 9);
+    }
+}
@@ -0,0 +1,344 @@
+use super::{
+    disassemble, extract_opcode, Immediate, Instruction, InstructionOpcode, Itype, R4type,
+    RegisterIndex, Rtype, Stype, Utype, INSTRUCTION_OPCODE_NAMES,
+};
+use ckb_vm_definitions::instructions as insts;
+use core::fmt;
+
+/// A decoded instruction with its operands unpacked into named fields, the
+/// variant tells which packing the decoder used for the opcode. Blank
+/// instructions such as ecall or fence.i are Rtype instructions with all
+/// registers set to 0.
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub enum TaggedInstruction {
+    Rtype {
+        op: InstructionOpcode,
+        rd: RegisterIndex,
+        rs1: RegisterIndex,
+        rs2: RegisterIndex,
+    },
+    Itype {
+        op: InstructionOpcode,
+        rd: RegisterIndex,
+        rs1: RegisterIndex,
+        imm: Immediate,
+    },
+    Stype {
+        op: InstructionOpcode,
+        rs1: RegisterIndex,
+        rs2: RegisterIndex,
+        imm: Immediate,
+    },
+    Utype {
+        op: InstructionOpcode,
+        rd: RegisterIndex,
+        imm: Immediate,
+    },
+    R4type {
+        op: InstructionOpcode,
+        rd: RegisterIndex,
+        rm: u8,
+        rs1: RegisterIndex,
+        rs2: RegisterIndex,
+        rs3: RegisterIndex,
+    },
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+enum Packing {
+    R,
+    I,
+    S,
+    U,
+    R4,
+}
+
+// Mirrors the way factories in the extension modules pack each opcode.
+// Opcodes not listed here either use Rtype or carry no operands.
+fn packing(op: InstructionOpcode) -> Packing {
+    match op {
+        insts::OP_ADDI
+        | insts::OP_ADDIW
+        | insts::OP_ANDI
+        | insts::OP_ORI
+        | insts::OP_XORI
+        | insts::OP_SLTI
+        | insts::OP_SLTIU
+        | insts::OP_SLLI
+        | insts::OP_SRLI
+        | insts::OP_SRAI
+        | insts::OP_SLLIW
+        | insts::OP_SRLIW
+        | insts::OP_SRAIW
+        | insts::OP_BCLRI
+        | insts::OP_BEXTI
+        | insts::OP_BINVI
+        | insts::OP_BSETI
+        | insts::OP_RORI
+        | insts::OP_RORIW
+        | insts::OP_SLLIUW
+        | insts::OP_LB
+        | insts::OP_LBU
+        | insts::OP_LD
+        | insts::OP_LH
+        | insts::OP_LHU
+        | insts::OP_LW
+        | insts::OP_LWU
+        | insts::OP_JALR
+        | insts::OP_RVC_LW
+        | insts::OP_RVC_LD
+        | insts::OP_RVC_ADDI
+        | insts::OP_RVC_ANDI
+        | insts::OP_RVC_ADDIW
+        | insts::OP_RVC_SLLI
+        | insts::OP_RVC_SRLI
+        | insts::OP_RVC_SRAI
+        | insts::OP_RVC_ADDI16SP
+        | insts::OP_FLW
+        | insts::OP_FLD
+        | insts::OP_RVC_FLD
+        | insts::OP_CSRRW..=insts::OP_CSRRCI
+        | insts::OP_VSETVLI
+        | insts::OP_VSETIVLI
+        | insts::OP_VLE
+        | insts::OP_VSE => Packing::I,
+        insts::OP_SB
+        | insts::OP_SH
+        | insts::OP_SW
+        | insts::OP_SD
+        | insts::OP_RVC_SW
+        | insts::OP_RVC_SD
+        | insts::OP_BEQ
+        | insts::OP_BNE
+        | insts::OP_BLT
+        | insts::OP_BGE
+        | insts::OP_BLTU
+        | insts::OP_BGEU
+        | insts::OP_RVC_SWSP
+        | insts::OP_RVC_SDSP
+        | insts::OP_RVC_BEQZ
+        | insts::OP_RVC_BNEZ
+        | insts::OP_RVC_JR
+        | insts::OP_RVC_JALR
+        | insts::OP_FSW
+        | insts::OP_FSD
+        | insts::OP_RVC_FSD
+        | insts::OP_RVC_FSDSP => Packing::S,
+        insts::OP_LUI
+        | insts::OP_AUIPC
+        | insts::OP_RVC_LUI
+        | insts::OP_JAL
+        | insts::OP_RVC_LI
+        | insts::OP_CUSTOM_LOAD_IMM
+        | insts::OP_CUSTOM
+        | insts::OP_RVC_ADDI4SPN
+        | insts::OP_RVC_LWSP
+        | insts::OP_RVC_LDSP
+        | insts::OP_RVC_J
+        | insts::OP_RVC_JAL
+        | insts::OP_RVC_FLDSP => Packing::U,
+        insts::OP_FMADDS..=insts::OP_FMVDX | insts::OP_VADD..=insts::OP_VMV => Packing::R4,
+        _ => Packing::R,
+    }
+}
+
+// Unpacks the operands of a decoded instruction, see TaggedInstruction.
+pub fn unpack(inst: Instruction) -> TaggedInstruction {
+    let op = extract_opcode(inst);
+    match packing(op) {
+        Packing::R => {
+            let i = Rtype(inst);
+            TaggedInstruction::Rtype {
+                op,
+                rd: i.rd(),
+                rs1: i.rs1(),
+                rs2: i.rs2(),
+            }
+        }
+        Packing::I => {
+            let i = Itype(inst);
+            TaggedInstruction::Itype {
+                op,
+                rd: i.rd(),
+                rs1: i.rs1(),
+                imm: i.immediate_s(),
+            }
+        }
+        Packing::S => {
+            let i = Stype(inst);
+            TaggedInstruction::Stype {
+                op,
+                rs1: i.rs1(),
+                rs2: i.rs2(),
+                imm: i.immediate_s(),
+            }
+        }
+        Packing::U => {
+            let i = Utype(inst);
+            TaggedInstruction::Utype {
+                op,
+                rd: i.rd(),
+                imm: i.immediate_s(),
+            }
+        }
+        Packing::R4 => {
+            let i = R4type(inst);
+            TaggedInstruction::R4type {
+                op,
+                rd: i.rd(),
+                rm: i.rm(),
+                rs1: i.rs1(),
+                rs2: i.rs2(),
+                rs3: i.rs3(),
+            }
+        }
+    }
+}
+
+impl TaggedInstruction {
+    // Packs the instruction back into the form executed by the machine.
+    pub fn pack(self) -> Instruction {
+        match self {
+            TaggedInstruction::Rtype { op, rd, rs1, rs2 } => Rtype::new(op, rd, rs1, rs2).0,
+            TaggedInstruction::Itype { op, rd, rs1, imm } => Itype::new_s(op, rd, rs1, imm).0,
+            TaggedInstruction::Stype { op, rs1, rs2, imm } => Stype::new_s(op, imm, rs1, rs2).0,
+            TaggedInstruction::Utype { op, rd, imm } => Utype::new_s(op, rd, imm).0,
+            TaggedInstruction::R4type {
+                op,
+                rd,
+                rm,
+                rs1,
+                rs2,
+                rs3,
+            } => R4type::new(op, rd, rm, rs1, rs2, rs3).0,
+        }
+    }
+
+    pub fn op(&self) -> InstructionOpcode {
+        match *self {
+            TaggedInstruction::Rtype { op, .. }
+            | TaggedInstruction::Itype { op, .. }
+            | TaggedInstruction::Stype { op, .. }
+            | TaggedInstruction::Utype { op, .. }
+            | TaggedInstruction::R4type { op, .. } => op,
+        }
+    }
+
+    // Opcode name as listed in INSTRUCTION_OPCODE_NAMES, such as "ADDI" or
+    // "RVC_LWSP".
+    pub fn name(&self) -> &'static str {
+        INSTRUCTION_OPCODE_NAMES
+            .get(self.op() as usize)
+            .copied()
+            .unwrap_or("UNKNOWN")
+    }
+
+    pub fn rd(&self) -> Option<RegisterIndex> {
+        match *self {
+            TaggedInstruction::Rtype { rd, .. }
+            | TaggedInstruction::Itype { rd, .. }
+            | TaggedInstruction::Utype { rd, .. }
+            | TaggedInstruction::R4type { rd, .. } => Some(rd),
+            TaggedInstruction::Stype { .. } => None,
+        }
+    }
+
+    pub fn rs1(&self) -> Option<RegisterIndex> {
+        match *self {
+            TaggedInstruction::Rtype { rs1, .. }
+            | TaggedInstruction::Itype { rs1, .. }
+            | TaggedInstruction::Stype { rs1, .. }
+            | TaggedInstruction::R4type { rs1, .. } => Some(rs1),
+            TaggedInstruction::Utype { .. } => None,
+        }
+    }
+
+    pub fn rs2(&self) -> Option<RegisterIndex> {
+        match *self {
+            TaggedInstruction::Rtype { rs2, .. }
+            | TaggedInstruction::Stype { rs2, .. }
+            | TaggedInstruction::R4type { rs2, .. } => Some(rs2),
+            TaggedInstruction::Itype { .. } | TaggedInstruction::Utype { .. } => None,
+        }
+    }
+
+    pub fn imm(&self) -> Option<Immediate> {
+        match *self {
+            TaggedInstruction::Itype { imm, .. }
+            | TaggedInstruction::Stype { imm, .. }
+            | TaggedInstruction::Utype { imm, .. } => Some(imm),
+            TaggedInstruction::Rtype { .. } | TaggedInstruction::R4type { .. } => None,
+        }
+    }
+}
+
+impl From<TaggedInstruction> for Instruction {
+    fn from(tagged: TaggedInstruction) -> Self {
+        tagged.pack()
+    }
+}
+
+impl From<Instruction> for TaggedInstruction {
+    fn from(inst: Instruction) -> Self {
+        unpack(inst)
+    }
+}
+
+// Formats the instruction as assembly text, the same as disassemble.
+impl fmt::Display for TaggedInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", disassemble(self.pack()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{i, rvc, InstructionFactory};
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn test_unpack_fields() {
+        let inst = i::factory::<u64>(0xff81_2503).unwrap();
+        let tagged = unpack(inst);
+        assert_eq!(
+            tagged,
+            TaggedInstruction::Itype {
+                op: insts::OP_LW,
+                rd: 10,
+                rs1: 2,
+                imm: -8
+            }
+        );
+        assert_eq!(tagged.name(), "LW");
+        assert_eq!(tagged.rs2(), None);
+        assert_eq!(tagged.to_string(), "lw a0, -8(sp)");
+
+        let tagged = unpack(i::factory::<u64>(0x0011_3423).unwrap());
+        assert_eq!(tagged.rd(), None);
+        assert_eq!(tagged.rs1(), Some(2));
+        assert_eq!(tagged.rs2(), Some(1));
+        assert_eq!(tagged.imm(), Some(8));
+        assert_eq!(tagged.to_string(), "sd ra, 8(sp)");
+    }
+
+    #[test]
+    fn test_unpack_pack_round_trip() {
+        let words: [(InstructionFactory, u32); 8] = [
+            (i::factory::<u64>, 0x0045_8513),
+            (i::factory::<u64>, 0xfe05_0ee3),
+            (i::factory::<u64>, 0x1234_5537),
+            (i::factory::<u64>, 0x0000_0073),
+            (i::factory::<u64>, 0x0ff0_000f),
+            (rvc::factory::<u64>, 0x852e),
+            (rvc::factory::<u64>, 0xe406),
+            (rvc::factory::<u64>, 0x4501),
+        ];
+        for (factory, bits) in words.iter() {
+            let inst = factory(*bits).unwrap();
+            let tagged = unpack(inst);
+            assert_eq!(tagged.pack(), inst);
+            assert_eq!(tagged.to_string(), disassemble(inst));
+        }
+    }
+}
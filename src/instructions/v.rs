@@ -0,0 +1,472 @@
+use super::super::{machine::Machine, memory::Memory, Error};
+use super::register::Register;
+use super::utils::{funct3, opcode, rd, rs1, rs2, update_register, x, xs};
+use super::{extract_opcode, Instruction, Itype, R4type, Rtype};
+use alloc::{vec, vec::Vec};
+use ckb_vm_definitions::instructions as insts;
+use serde::{Deserialize, Serialize};
+
+pub const VLEN: usize = 128;
+pub const VLENB: usize = VLEN / 8;
+
+// vtype value of an unsupported configuration, vill is the top bit of an
+// RV64 vtype CSR.
+pub const VILL: u64 = 1 << 63;
+
+// Arithmetic instructions are packed as R4type, with rd holding vd, rs2
+// holding vs2 and rm telling how the rs1 slot is used: a vector register,
+// an integer register, or a sign extended 5 bit immediate.
+pub const FORM_VV: u8 = 0;
+pub const FORM_VX: u8 = 1;
+pub const FORM_VI: u8 = 2;
+
+const OPIVV: u32 = 0b_000;
+const OPMVV: u32 = 0b_010;
+const OPIVI: u32 = 0b_011;
+const OPIVX: u32 = 0b_100;
+const OPMVX: u32 = 0b_110;
+const OPCFG: u32 = 0b_111;
+
+// Vector register file of the V extension with VLEN fixed at 128 bits.
+// Registers are stored back to back, so a register group of LMUL registers
+// is simply a contiguous range of bytes.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VectorRegisters {
+    data: Vec<u8>,
+    vl: u64,
+    vtype: u64,
+}
+
+impl Default for VectorRegisters {
+    fn default() -> Self {
+        Self {
+            data: vec![0; 32 * VLENB],
+            vl: 0,
+            vtype: VILL,
+        }
+    }
+}
+
+impl VectorRegisters {
+    pub fn register(&self, idx: usize) -> &[u8] {
+        &self.data[idx * VLENB..(idx + 1) * VLENB]
+    }
+
+    pub fn register_mut(&mut self, idx: usize) -> &mut [u8] {
+        &mut self.data[idx * VLENB..(idx + 1) * VLENB]
+    }
+
+    pub fn vl(&self) -> u64 {
+        self.vl
+    }
+
+    pub fn vtype(&self) -> u64 {
+        self.vtype
+    }
+
+    // Applies a new vtype, returning the new vl. avl is None when both rd
+    // and rs1 are x0, which keeps the current vl.
+    pub fn configure(&mut self, vtype: u64, avl: Option<u64>) -> u64 {
+        match config(vtype) {
+            Some((sew, lmul)) => {
+                let vlmax = (VLENB * lmul / sew) as u64;
+                self.vtype = vtype;
+                self.vl = avl.unwrap_or(self.vl).min(vlmax);
+            }
+            None => {
+                self.vtype = VILL;
+                self.vl = 0;
+            }
+        }
+        self.vl
+    }
+
+    // Element width in bytes, LMUL and vl of current configuration, vector
+    // instructions other than vsetvl are invalid while vill is set.
+    fn active(&self, op: u8) -> Result<(usize, usize, usize), Error> {
+        let (sew, lmul) = config(self.vtype).ok_or(Error::InvalidOp(op))?;
+        let vlmax = VLENB * lmul / sew;
+        Ok((sew, lmul, (self.vl as usize).min(vlmax)))
+    }
+
+    fn element(&self, reg: usize, index: usize, width: usize) -> u64 {
+        let start = reg * VLENB + index * width;
+        let mut bytes = [0u8; 8];
+        bytes[..width].copy_from_slice(&self.data[start..start + width]);
+        u64::from_le_bytes(bytes)
+    }
+
+    fn set_element(&mut self, reg: usize, index: usize, width: usize, value: u64) {
+        let start = reg * VLENB + index * width;
+        self.data[start..start + width].copy_from_slice(&value.to_le_bytes()[..width]);
+    }
+}
+
+// Decodes vtype into SEW in bytes and LMUL. Fractional LMUL and the tail and
+// mask agnostic policies are not supported, since tail and inactive
+// elements are always left undisturbed, setting vta or vma is harmless.
+fn config(vtype: u64) -> Option<(usize, usize)> {
+    if vtype >> 8 != 0 {
+        return None;
+    }
+    let vsew = (vtype >> 3) & 0b_111;
+    let vlmul = vtype & 0b_111;
+    if vsew > 3 || vlmul > 3 {
+        return None;
+    }
+    Some((1 << vsew, 1 << vlmul))
+}
+
+// Decodes a subset of the V extension: vsetvli, vsetivli and vsetvl,
+// unit-stride loads and stores, and vadd, vsub, vmul, vand, vor, vxor and
+// vmv.v. Masked instructions are not supported.
+pub fn factory<R: Register>(instruction_bits: u32) -> Option<Instruction> {
+    let bit_length = R::BITS;
+    if bit_length != 32 && bit_length != 64 && bit_length != 128 {
+        return None;
+    }
+    let funct3_value = funct3(instruction_bits);
+    match opcode(instruction_bits) {
+        0b_1010111 if funct3_value == OPCFG => config_factory(instruction_bits),
+        0b_1010111 => arithmetic_factory(instruction_bits, funct3_value),
+        0b_0000111 | 0b_0100111 => {
+            let width = match funct3_value {
+                0b_000 => 8,
+                0b_101 => 16,
+                0b_110 => 32,
+                0b_111 => 64,
+                _ => return None,
+            };
+            // nf, mew, mop and lumop or sumop are all zero for unit-stride
+            // accesses, and vm is set.
+            if instruction_bits >> 20 != 0b_0000_0010_0000 {
+                return None;
+            }
+            let inst = if opcode(instruction_bits) == 0b_0000111 {
+                insts::OP_VLE
+            } else {
+                insts::OP_VSE
+            };
+            Some(Itype::new(inst, rd(instruction_bits), rs1(instruction_bits), width).0)
+        }
+        _ => None,
+    }
+}
+
+fn config_factory(instruction_bits: u32) -> Option<Instruction> {
+    let (rd, rs1) = (rd(instruction_bits), rs1(instruction_bits));
+    if instruction_bits >> 31 == 0 {
+        Some(Itype::new(insts::OP_VSETVLI, rd, rs1, x(instruction_bits, 20, 11, 0)).0)
+    } else if instruction_bits >> 30 == 0b_11 {
+        // The rs1 slot holds the 5 bit AVL immediate
+        Some(Itype::new(insts::OP_VSETIVLI, rd, rs1, x(instruction_bits, 20, 10, 0)).0)
+    } else if instruction_bits >> 25 == 0b_1000000 {
+        Some(Rtype::new(insts::OP_VSETVL, rd, rs1, rs2(instruction_bits)).0)
+    } else {
+        None
+    }
+}
+
+fn arithmetic_factory(instruction_bits: u32, funct3_value: u32) -> Option<Instruction> {
+    if instruction_bits & (1 << 25) == 0 {
+        return None;
+    }
+    let funct6 = instruction_bits >> 26;
+    let vs2 = rs2(instruction_bits);
+    let integer = matches!(funct3_value, OPIVV | OPIVI | OPIVX);
+    let inst = match funct6 {
+        0b_000000 if integer => insts::OP_VADD,
+        0b_000010 if funct3_value == OPIVV || funct3_value == OPIVX => insts::OP_VSUB,
+        0b_001001 if integer => insts::OP_VAND,
+        0b_001010 if integer => insts::OP_VOR,
+        0b_001011 if integer => insts::OP_VXOR,
+        // vmv.v is the unmasked form of vmerge, with vs2 fixed to v0
+        0b_010111 if integer && vs2 == 0 => insts::OP_VMV,
+        0b_100101 if funct3_value == OPMVV || funct3_value == OPMVX => insts::OP_VMUL,
+        _ => return None,
+    };
+    let (form, operand) = match funct3_value {
+        OPIVV | OPMVV => (FORM_VV, rs1(instruction_bits)),
+        OPIVX | OPMVX => (FORM_VX, rs1(instruction_bits)),
+        _ => (FORM_VI, xs(instruction_bits, 15, 5, 0) as u8 as usize),
+    };
+    Some(R4type::new(inst, rd(instruction_bits), form, operand, vs2, 0).0)
+}
+
+fn vector_registers<Mac: Machine>(
+    machine: &mut Mac,
+    op: u8,
+) -> Result<&mut VectorRegisters, Error> {
+    machine.vector_registers_mut().ok_or(Error::InvalidOp(op))
+}
+
+fn load<Mac: Machine>(machine: &mut Mac, address: &Mac::REG, width: usize) -> Result<u64, Error> {
+    let memory = machine.memory_mut();
+    match width {
+        1 => Ok(memory.load8(address)?.to_u64()),
+        2 => Ok(memory.load16(address)?.to_u64()),
+        4 => Ok(memory.load32(address)?.to_u64()),
+        _ if Mac::REG::BITS >= 64 => Ok(memory.load64(address)?.to_u64()),
+        // 64 bit elements are split into 2 halves on RV32
+        _ => {
+            let high_address = address.overflowing_add(&Mac::REG::from_u8(4));
+            let low = memory.load32(address)?.to_u64();
+            let high = memory.load32(&high_address)?.to_u64();
+            Ok(low | (high << 32))
+        }
+    }
+}
+
+fn store<Mac: Machine>(
+    machine: &mut Mac,
+    address: &Mac::REG,
+    width: usize,
+    value: u64,
+) -> Result<(), Error> {
+    let memory = machine.memory_mut();
+    match width {
+        1 => memory.store8(address, &Mac::REG::from_u64(value)),
+        2 => memory.store16(address, &Mac::REG::from_u64(value)),
+        4 => memory.store32(address, &Mac::REG::from_u64(value)),
+        _ if Mac::REG::BITS >= 64 => memory.store64(address, &Mac::REG::from_u64(value)),
+        _ => {
+            let high_address = address.overflowing_add(&Mac::REG::from_u8(4));
+            memory.store32(address, &Mac::REG::from_u64(value & 0xFFFF_FFFF))?;
+            memory.store32(&high_address, &Mac::REG::from_u64(value >> 32))
+        }
+    }
+}
+
+fn execute_config<Mac: Machine>(inst: Instruction, machine: &mut Mac) -> Result<(), Error> {
+    let op = extract_opcode(inst);
+    let (rd, rs1, vtype) = if op == insts::OP_VSETVL {
+        let i = Rtype(inst);
+        (i.rd(), i.rs1(), machine.registers()[i.rs2()].to_u64())
+    } else {
+        let i = Itype(inst);
+        (i.rd(), i.rs1(), u64::from(i.immediate()))
+    };
+    let avl = if op == insts::OP_VSETIVLI {
+        Some(rs1 as u64)
+    } else if rs1 != 0 {
+        Some(machine.registers()[rs1].to_u64())
+    } else if rd != 0 {
+        Some(u64::MAX)
+    } else {
+        None
+    };
+    let vl = vector_registers(machine, op)?.configure(vtype, avl);
+    update_register(machine, rd, Mac::REG::from_u64(vl));
+    Ok(())
+}
+
+// Unit-stride accesses use the element width encoded in the instruction,
+// the register group then spans EEW / SEW * LMUL registers.
+fn execute_load_store<Mac: Machine>(inst: Instruction, machine: &mut Mac) -> Result<(), Error> {
+    let op = extract_opcode(inst);
+    let i = Itype(inst);
+    let (vd, width) = (i.rd(), i.immediate() as usize / 8);
+    let base = machine.registers()[i.rs1()].clone();
+    let (sew, lmul, vl) = vector_registers(machine, op)?.active(op)?;
+    let group = (width * lmul / sew).max(1);
+    if width * lmul > 8 * sew || vd % group != 0 {
+        return Err(Error::InvalidOp(op));
+    }
+    let address = |index: usize| base.overflowing_add(&Mac::REG::from_u64((index * width) as u64));
+    if op == insts::OP_VLE {
+        // Elements are only written back after all loads succeed
+        let mut values = Vec::with_capacity(vl);
+        for index in 0..vl {
+            values.push(load(machine, &address(index), width)?);
+        }
+        let registers = vector_registers(machine, op)?;
+        for (index, value) in values.into_iter().enumerate() {
+            registers.set_element(vd, index, width, value);
+        }
+    } else {
+        let registers = vector_registers(machine, op)?;
+        let values: Vec<u64> = (0..vl)
+            .map(|index| registers.element(vd, index, width))
+            .collect();
+        for (index, value) in values.into_iter().enumerate() {
+            store(machine, &address(index), width, value)?;
+        }
+    }
+    Ok(())
+}
+
+fn execute_arithmetic<Mac: Machine>(inst: Instruction, machine: &mut Mac) -> Result<(), Error> {
+    let op = extract_opcode(inst);
+    let i = R4type(inst);
+    let (vd, vs1, vs2, form) = (i.rd(), i.rs1(), i.rs2(), i.rm());
+    let scalar = match form {
+        FORM_VX => machine.registers()[vs1].to_i64() as u64,
+        FORM_VI => i64::from(vs1 as u8 as i8) as u64,
+        _ => 0,
+    };
+    let registers = vector_registers(machine, op)?;
+    let (sew, lmul, vl) = registers.active(op)?;
+    if vd % lmul != 0 || vs2 % lmul != 0 || (form == FORM_VV && vs1 % lmul != 0) {
+        return Err(Error::InvalidOp(op));
+    }
+    for index in 0..vl {
+        let a = registers.element(vs2, index, sew);
+        let b = if form == FORM_VV {
+            registers.element(vs1, index, sew)
+        } else {
+            scalar
+        };
+        // Results are truncated to SEW when written back
+        let value = match op {
+            insts::OP_VADD => a.wrapping_add(b),
+            insts::OP_VSUB => a.wrapping_sub(b),
+            insts::OP_VMUL => a.wrapping_mul(b),
+            insts::OP_VAND => a & b,
+            insts::OP_VOR => a | b,
+            insts::OP_VXOR => a ^ b,
+            _ => b,
+        };
+        registers.set_element(vd, index, sew, value);
+    }
+    Ok(())
+}
+
+pub fn execute<Mac: Machine>(inst: Instruction, machine: &mut Mac) -> Result<(), Error> {
+    match extract_opcode(inst) {
+        insts::OP_VSETVLI | insts::OP_VSETIVLI | insts::OP_VSETVL => execute_config(inst, machine),
+        insts::OP_VLE | insts::OP_VSE => execute_load_store(inst, machine),
+        _ => execute_arithmetic(inst, machine),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::execute;
+    use super::*;
+    use crate::machine::{CoreMachine, DefaultCoreMachine, DefaultMachine};
+    use crate::SparseMemory;
+
+    type TestMachine<'a, R> = DefaultMachine<'a, DefaultCoreMachine<R, SparseMemory<R>>>;
+
+    fn run<R: Register>(machine: &mut TestMachine<R>, bits: u32) -> Result<(), Error> {
+        let instruction = factory::<R>(bits).expect("decoding");
+        execute(instruction, machine)
+    }
+
+    // OP-V instruction with vm set
+    fn encode(funct6: u32, vs2: u32, rs1: u32, funct3: u32, vd: u32) -> u32 {
+        (funct6 << 26)
+            | (1 << 25)
+            | (vs2 << 20)
+            | (rs1 << 15)
+            | (funct3 << 12)
+            | (vd << 7)
+            | 0b_1010111
+    }
+
+    #[test]
+    fn test_decode() {
+        // vsetvli a0, a1, e32, m1, ta, ma
+        assert!(factory::<u64>(0x0D05_F557).is_some());
+        // vsetivli a0, 4, e32, m1, ta, ma
+        assert!(factory::<u64>(0xCD02_7557).is_some());
+        // vle32.v v1, (a0) and vse32.v v1, (a0)
+        assert!(factory::<u64>(0x0205_6087).is_some());
+        assert!(factory::<u64>(0x0205_60A7).is_some());
+        // Masked, strided and segment accesses are rejected
+        assert!(factory::<u64>(0x0005_6087).is_none());
+        assert!(factory::<u64>(0x0AB5_6087).is_none());
+        assert!(factory::<u64>(0x2205_6087).is_none());
+        // flw and fld are left to the fd factory
+        assert!(factory::<u64>(0x0005_2087).is_none());
+        assert!(factory::<u64>(0x0005_3087).is_none());
+        // vadd.vv v1, v2, v3 and its masked version
+        assert!(factory::<u64>(encode(0b_000000, 2, 3, OPIVV, 1)).is_some());
+        assert!(factory::<u64>(encode(0b_000000, 2, 3, OPIVV, 1) & !(1 << 25)).is_none());
+        // vsub.vi and vmul.vv in the OPI space do not exist
+        assert!(factory::<u64>(encode(0b_000010, 2, 3, OPIVI, 1)).is_none());
+        assert!(factory::<u64>(encode(0b_100101, 2, 3, OPIVV, 1)).is_none());
+        assert!(factory::<u64>(encode(0b_100101, 2, 3, OPMVV, 1)).is_some());
+        // vmerge is not supported
+        assert!(factory::<u64>(encode(0b_010111, 2, 3, OPIVV, 1)).is_none());
+    }
+
+    #[test]
+    fn test_vsetvl() {
+        let mut machine = TestMachine::<u64>::default();
+        assert_eq!(machine.vector_registers().vtype(), VILL);
+        // vsetvli a0, a1, e32, m2 with a1 = 100
+        machine.set_register(11, 100);
+        run(&mut machine, 0x0115_F557).unwrap();
+        assert_eq!(machine.registers()[10], 8);
+        assert_eq!(machine.vector_registers().vl(), 8);
+        // vsetivli a0, 3, e8, m1
+        run(&mut machine, 0xC001_F557).unwrap();
+        assert_eq!(machine.registers()[10], 3);
+        // Fractional LMUL sets vill
+        run(&mut machine, 0x0055_F557).unwrap();
+        assert_eq!(machine.registers()[10], 0);
+        assert_eq!(machine.vector_registers().vtype(), VILL);
+        assert_eq!(
+            run(&mut machine, encode(0b_000000, 2, 3, OPIVV, 1)),
+            Err(Error::InvalidOp(insts::OP_VADD))
+        );
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let mut machine = TestMachine::<u64>::default();
+        for i in 0..4u32 {
+            let registers = machine.vector_registers_mut().unwrap();
+            registers.set_element(2, i as usize, 4, u64::from(i + 1));
+            registers.set_element(3, i as usize, 4, 0xFFFF_FFFF);
+        }
+        // vsetivli zero, 3, e32, m1
+        run(&mut machine, 0xC101_F057).unwrap();
+        // vadd.vv v1, v2, v3 wraps around within SEW, leaving the tail
+        // element undisturbed
+        run(&mut machine, encode(0b_000000, 2, 3, OPIVV, 1)).unwrap();
+        let registers = machine.vector_registers();
+        assert_eq!(
+            registers.register(1),
+            &[0, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0][..]
+        );
+        // vmul.vx v1, v2, a0 with a0 = 3
+        machine.set_register(10, 3);
+        run(&mut machine, encode(0b_100101, 2, 10, OPMVX, 1)).unwrap();
+        assert_eq!(machine.vector_registers().element(1, 2, 4), 9);
+        // vxor.vi v1, v2, -1
+        run(&mut machine, encode(0b_001011, 2, 0b_11111, OPIVI, 1)).unwrap();
+        assert_eq!(machine.vector_registers().element(1, 0, 4), 0xFFFF_FFFE);
+        // vmv.v.i v4, -3
+        run(&mut machine, encode(0b_010111, 0, 0b_11101, OPIVI, 4)).unwrap();
+        assert_eq!(machine.vector_registers().element(4, 1, 4), 0xFFFF_FFFD);
+    }
+
+    #[test]
+    fn test_load_store() {
+        let mut machine = TestMachine::<u32>::default();
+        let data: Vec<u8> = (0..32).collect();
+        machine.memory_mut().store_bytes(0x1000, &data).unwrap();
+        machine.set_register(10, 0x1000);
+        // vsetvli zero, a1, e64, m2 with a1 = 3
+        machine.set_register(11, 3);
+        run(&mut machine, 0x0195_F057).unwrap();
+        // vle64.v v2, (a0)
+        run(&mut machine, 0x0205_7107).unwrap();
+        assert_eq!(machine.vector_registers().register(2), &data[..16]);
+        assert_eq!(machine.vector_registers().register(3)[..8], data[16..24]);
+        // vse64.v v2, (a0) with a0 = 0x2000
+        machine.set_register(10, 0x2000);
+        run(&mut machine, 0x0205_7127).unwrap();
+        assert_eq!(
+            machine.memory_mut().load_bytes(0x2000, 24).unwrap(),
+            data[..24].to_vec()
+        );
+        // The register group of vle64.v v1 is misaligned
+        assert_eq!(
+            run(&mut machine, 0x0205_7087),
+            Err(Error::InvalidOp(insts::OP_VLE))
+        );
+    }
+}
@@ -0,0 +1,119 @@
+use super::super::{machine::Machine, Error};
+use super::register::Register;
+use super::utils::{funct3, opcode, rd, rs1, update_register};
+use super::{extract_opcode, Instruction, Itype};
+use ckb_vm_definitions::instructions as insts;
+
+pub const CSR_CYCLE: u32 = 0xC00;
+pub const CSR_TIME: u32 = 0xC01;
+pub const CSR_INSTRET: u32 = 0xC02;
+// Upper halves of the counters, only available on RV32
+pub const CSR_CYCLEH: u32 = 0xC80;
+pub const CSR_TIMEH: u32 = 0xC81;
+pub const CSR_INSTRETH: u32 = 0xC82;
+
+/// Values of the user level counters read by rdcycle, rdtime and rdinstret.
+/// There is no wall clock in CKB VM, time advances with cycles.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Counters {
+    pub cycle: u64,
+    pub time: u64,
+    pub instret: u64,
+}
+
+impl Counters {
+    fn read(&self, csr: u32) -> u64 {
+        match csr {
+            CSR_CYCLE => self.cycle,
+            CSR_TIME => self.time,
+            CSR_INSTRET => self.instret,
+            CSR_CYCLEH => self.cycle >> 32,
+            CSR_TIMEH => self.time >> 32,
+            _ => self.instret >> 32,
+        }
+    }
+}
+
+pub fn is_counter(csr: u32) -> bool {
+    (CSR_CYCLE..=CSR_INSTRET).contains(&csr) || (CSR_CYCLEH..=CSR_INSTRETH).contains(&csr)
+}
+
+// Decodes CSR instructions reading the counters of the Zicntr extension.
+// Counters are read only, so instructions that would write them are left
+// invalid as the spec requires. Per spec, CSRRS and CSRRC with x0 or a zero
+// immediate as source are reads, CSRRW is always a write.
+pub fn factory<R: Register>(instruction_bits: u32) -> Option<Instruction> {
+    let bit_length = R::BITS;
+    if bit_length != 32 && bit_length != 64 && bit_length != 128 {
+        return None;
+    }
+    if opcode(instruction_bits) != 0b_1110011 {
+        return None;
+    }
+    let csr = instruction_bits >> 20;
+    if !is_counter(csr) || (csr >= CSR_CYCLEH && bit_length != 32) {
+        return None;
+    }
+    if rs1(instruction_bits) != 0 {
+        return None;
+    }
+    let inst = match funct3(instruction_bits) {
+        0b_010 => insts::OP_CSRRS,
+        0b_011 => insts::OP_CSRRC,
+        0b_110 => insts::OP_CSRRSI,
+        0b_111 => insts::OP_CSRRCI,
+        _ => return None,
+    };
+    Some(Itype::new(inst, rd(instruction_bits), 0, csr).0)
+}
+
+// Executes a counter read produced by the factory in this module. Counters
+// are charged before an instruction runs, so values read include the
+// reading instruction itself.
+pub fn execute<Mac: Machine>(inst: Instruction, machine: &mut Mac) -> Result<(), Error> {
+    let i = Itype(inst);
+    let counters = machine
+        .counters()
+        .ok_or(Error::InvalidOp(extract_opcode(inst)))?;
+    let value = counters.read(i.immediate());
+    update_register(machine, i.rd(), Mac::REG::from_u64(value));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_factory() {
+        // rdcycle a0
+        let inst = factory::<u64>(0xc0002573).unwrap();
+        assert_eq!(extract_opcode(inst), insts::OP_CSRRS);
+        assert_eq!(Itype(inst).rd(), 10);
+        assert_eq!(Itype(inst).immediate(), CSR_CYCLE);
+        // rdinstret a1
+        let inst = factory::<u64>(0xc02025f3).unwrap();
+        assert_eq!(Itype(inst).immediate(), CSR_INSTRET);
+        // rdcycleh a0 is only available on RV32
+        assert!(factory::<u32>(0xc8002573).is_some());
+        assert_eq!(factory::<u64>(0xc8002573), None);
+        // csrrw zero, cycle, a0 writes a read only counter
+        assert_eq!(factory::<u64>(0xc0051073), None);
+        // csrrs a0, cycle, a1 sets bits of a read only counter
+        assert_eq!(factory::<u64>(0xc005a573), None);
+        // csrrs a0, fflags, zero is not a counter
+        assert_eq!(factory::<u64>(0x00102573), None);
+    }
+
+    #[test]
+    fn test_counters_read() {
+        let counters = Counters {
+            cycle: 0x1_0000_0002,
+            time: 0x3_0000_0004,
+            instret: 0x5_0000_0006,
+        };
+        assert_eq!(counters.read(CSR_CYCLE), 0x1_0000_0002);
+        assert_eq!(counters.read(CSR_TIMEH), 3);
+        assert_eq!(counters.read(CSR_INSTRETH), 5);
+    }
+}
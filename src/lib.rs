@@ -1,24 +1,68 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
 #[macro_use]
 extern crate derive_more;
 
+pub mod analysis;
+// Threads and clocks are not available on wasm32-unknown-unknown
+#[cfg(all(feature = "std", not(target_family = "wasm")))]
+pub mod batch;
 pub mod bits;
+#[cfg(feature = "conformance")]
+pub mod conformance;
+pub mod cycle_model;
 pub mod debugger;
 pub mod decoder;
+pub mod disassembler;
 pub mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(all(feature = "std", not(target_family = "wasm")))]
+pub mod gdbstub;
 pub mod instructions;
 pub mod machine;
 pub mod memory;
+pub mod snapshot;
 pub mod syscalls;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
+#[cfg(feature = "fd")]
+pub use crate::instructions::fd::FloatRegisters;
+#[cfg(feature = "v-ext")]
+pub use crate::instructions::v::VectorRegisters;
+#[cfg(feature = "mmu")]
+pub use crate::memory::sv39::Sv39Memory;
+#[cfg(feature = "serialize")]
+pub use crate::snapshot::CoreMachineState;
 pub use crate::{
     debugger::Debugger,
     instructions::{Instruction, Register},
     machine::{
-        trace::TraceMachine, CoreMachine, DefaultCoreMachine, DefaultMachine,
-        DefaultMachineBuilder, InstructionCycleFunc, Machine, SupportMachine,
+        commitment::{CommitmentGranularity, StateCommitment},
+        decoded::DecodedProgram,
+        interrupt::InterruptHandle,
+        isa::MachineIsa,
+        overflow::OverflowRecord,
+        pool::MachinePool,
+        recording::InstructionRecording,
+        report::ResourceReport,
+        trace::TraceMachine,
+        view::MachineSnapshotView,
+        CoreMachine, CustomInstructionHandler, CyclesHookFunc, DefaultCoreMachine, DefaultMachine,
+        DefaultMachineBuilder, ExitHandler, ExitReason, InstructionCycleFunc, InstructionHookFunc,
+        Machine, MemoryCycleFunc, ProgramMetadata, ProgramValidator, RunResult, RunState,
+        SupportMachine,
+    },
+    memory::{
+        cow::CowMemory, flat::FlatMemory, lazy::LazyMemory, paged::PagedMemory,
+        sparse::SparseMemory, watchpoint::WatchpointKind, wxorx::WXorXMemory, Memory,
     },
-    memory::{flat::FlatMemory, sparse::SparseMemory, wxorx::WXorXMemory, Memory},
-    syscalls::Syscalls,
+    snapshot::Snapshot,
+    syscalls::{SyscallRecord, SyscallRegistry, Syscalls},
 };
 use bytes::Bytes;
 
@@ -27,7 +71,7 @@ pub use ckb_vm_definitions::{
     RISCV_PAGESIZE,
 };
 
-pub use error::Error;
+pub use error::{ElfSegmentError, Error, Fault};
 
 pub fn run<R: Register, M: Memory<R> + Default>(
     program: &Bytes,
@@ -13,6 +13,7 @@
 #define CKB_VM_ASM_RET_MAX_CYCLES_EXCEEDED 5
 #define CKB_VM_ASM_RET_OUT_OF_BOUND 6
 #define CKB_VM_ASM_RET_INVALID_PERMISSION 7
+#define CKB_VM_ASM_RET_FENCEI 8
 
 #define CKB_VM_ASM_REGISTER_SP 2
 
@@ -143,6 +144,156 @@
 #define CKB_VM_ASM_OP_CUSTOM_LOAD_IMM 103
 #define CKB_VM_ASM_OP_ADDI 104
 #define CKB_VM_ASM_OP_CUSTOM_TRACE_END 105
+#define CKB_VM_ASM_OP_ADDUW 106
+#define CKB_VM_ASM_OP_ANDN 107
+#define CKB_VM_ASM_OP_BCLR 108
+#define CKB_VM_ASM_OP_BCLRI 109
+#define CKB_VM_ASM_OP_BEXT 110
+#define CKB_VM_ASM_OP_BEXTI 111
+#define CKB_VM_ASM_OP_BINV 112
+#define CKB_VM_ASM_OP_BINVI 113
+#define CKB_VM_ASM_OP_BSET 114
+#define CKB_VM_ASM_OP_BSETI 115
+#define CKB_VM_ASM_OP_CLMUL 116
+#define CKB_VM_ASM_OP_CLMULH 117
+#define CKB_VM_ASM_OP_CLMULR 118
+#define CKB_VM_ASM_OP_CLZ 119
+#define CKB_VM_ASM_OP_CLZW 120
+#define CKB_VM_ASM_OP_CPOP 121
+#define CKB_VM_ASM_OP_CPOPW 122
+#define CKB_VM_ASM_OP_CTZ 123
+#define CKB_VM_ASM_OP_CTZW 124
+#define CKB_VM_ASM_OP_MAX 125
+#define CKB_VM_ASM_OP_MAXU 126
+#define CKB_VM_ASM_OP_MIN 127
+#define CKB_VM_ASM_OP_MINU 128
+#define CKB_VM_ASM_OP_ORCB 129
+#define CKB_VM_ASM_OP_ORN 130
+#define CKB_VM_ASM_OP_REV8 131
+#define CKB_VM_ASM_OP_ROL 132
+#define CKB_VM_ASM_OP_ROLW 133
+#define CKB_VM_ASM_OP_ROR 134
+#define CKB_VM_ASM_OP_RORI 135
+#define CKB_VM_ASM_OP_RORIW 136
+#define CKB_VM_ASM_OP_RORW 137
+#define CKB_VM_ASM_OP_SEXTB 138
+#define CKB_VM_ASM_OP_SEXTH 139
+#define CKB_VM_ASM_OP_SH1ADD 140
+#define CKB_VM_ASM_OP_SH1ADDUW 141
+#define CKB_VM_ASM_OP_SH2ADD 142
+#define CKB_VM_ASM_OP_SH2ADDUW 143
+#define CKB_VM_ASM_OP_SH3ADD 144
+#define CKB_VM_ASM_OP_SH3ADDUW 145
+#define CKB_VM_ASM_OP_SLLIUW 146
+#define CKB_VM_ASM_OP_XNOR 147
+#define CKB_VM_ASM_OP_ZEXTH 148
+#define CKB_VM_ASM_OP_FLW 149
+#define CKB_VM_ASM_OP_FSW 150
+#define CKB_VM_ASM_OP_FMADDS 151
+#define CKB_VM_ASM_OP_FMSUBS 152
+#define CKB_VM_ASM_OP_FNMSUBS 153
+#define CKB_VM_ASM_OP_FNMADDS 154
+#define CKB_VM_ASM_OP_FADDS 155
+#define CKB_VM_ASM_OP_FSUBS 156
+#define CKB_VM_ASM_OP_FMULS 157
+#define CKB_VM_ASM_OP_FDIVS 158
+#define CKB_VM_ASM_OP_FSQRTS 159
+#define CKB_VM_ASM_OP_FSGNJS 160
+#define CKB_VM_ASM_OP_FSGNJNS 161
+#define CKB_VM_ASM_OP_FSGNJXS 162
+#define CKB_VM_ASM_OP_FMINS 163
+#define CKB_VM_ASM_OP_FMAXS 164
+#define CKB_VM_ASM_OP_FCVTWS 165
+#define CKB_VM_ASM_OP_FCVTWUS 166
+#define CKB_VM_ASM_OP_FMVXW 167
+#define CKB_VM_ASM_OP_FEQS 168
+#define CKB_VM_ASM_OP_FLTS 169
+#define CKB_VM_ASM_OP_FLES 170
+#define CKB_VM_ASM_OP_FCLASSS 171
+#define CKB_VM_ASM_OP_FCVTSW 172
+#define CKB_VM_ASM_OP_FCVTSWU 173
+#define CKB_VM_ASM_OP_FMVWX 174
+#define CKB_VM_ASM_OP_FCVTLS 175
+#define CKB_VM_ASM_OP_FCVTLUS 176
+#define CKB_VM_ASM_OP_FCVTSL 177
+#define CKB_VM_ASM_OP_FCVTSLU 178
+#define CKB_VM_ASM_OP_FLD 179
+#define CKB_VM_ASM_OP_FSD 180
+#define CKB_VM_ASM_OP_FMADDD 181
+#define CKB_VM_ASM_OP_FMSUBD 182
+#define CKB_VM_ASM_OP_FNMSUBD 183
+#define CKB_VM_ASM_OP_FNMADDD 184
+#define CKB_VM_ASM_OP_FADDD 185
+#define CKB_VM_ASM_OP_FSUBD 186
+#define CKB_VM_ASM_OP_FMULD 187
+#define CKB_VM_ASM_OP_FDIVD 188
+#define CKB_VM_ASM_OP_FSQRTD 189
+#define CKB_VM_ASM_OP_FSGNJD 190
+#define CKB_VM_ASM_OP_FSGNJND 191
+#define CKB_VM_ASM_OP_FSGNJXD 192
+#define CKB_VM_ASM_OP_FMIND 193
+#define CKB_VM_ASM_OP_FMAXD 194
+#define CKB_VM_ASM_OP_FCVTSD 195
+#define CKB_VM_ASM_OP_FCVTDS 196
+#define CKB_VM_ASM_OP_FEQD 197
+#define CKB_VM_ASM_OP_FLTD 198
+#define CKB_VM_ASM_OP_FLED 199
+#define CKB_VM_ASM_OP_FCLASSD 200
+#define CKB_VM_ASM_OP_FCVTWD 201
+#define CKB_VM_ASM_OP_FCVTWUD 202
+#define CKB_VM_ASM_OP_FCVTDW 203
+#define CKB_VM_ASM_OP_FCVTDWU 204
+#define CKB_VM_ASM_OP_FCVTLD 205
+#define CKB_VM_ASM_OP_FCVTLUD 206
+#define CKB_VM_ASM_OP_FMVXD 207
+#define CKB_VM_ASM_OP_FCVTDL 208
+#define CKB_VM_ASM_OP_FCVTDLU 209
+#define CKB_VM_ASM_OP_FMVDX 210
+#define CKB_VM_ASM_OP_CSRRW 211
+#define CKB_VM_ASM_OP_CSRRS 212
+#define CKB_VM_ASM_OP_CSRRC 213
+#define CKB_VM_ASM_OP_CSRRWI 214
+#define CKB_VM_ASM_OP_CSRRSI 215
+#define CKB_VM_ASM_OP_CSRRCI 216
+#define CKB_VM_ASM_OP_RVC_FLD 217
+#define CKB_VM_ASM_OP_RVC_FSD 218
+#define CKB_VM_ASM_OP_RVC_FLDSP 219
+#define CKB_VM_ASM_OP_RVC_FSDSP 220
+#define CKB_VM_ASM_OP_LR_W 221
+#define CKB_VM_ASM_OP_SC_W 222
+#define CKB_VM_ASM_OP_AMOSWAP_W 223
+#define CKB_VM_ASM_OP_AMOADD_W 224
+#define CKB_VM_ASM_OP_AMOXOR_W 225
+#define CKB_VM_ASM_OP_AMOAND_W 226
+#define CKB_VM_ASM_OP_AMOOR_W 227
+#define CKB_VM_ASM_OP_AMOMIN_W 228
+#define CKB_VM_ASM_OP_AMOMAX_W 229
+#define CKB_VM_ASM_OP_AMOMINU_W 230
+#define CKB_VM_ASM_OP_AMOMAXU_W 231
+#define CKB_VM_ASM_OP_LR_D 232
+#define CKB_VM_ASM_OP_SC_D 233
+#define CKB_VM_ASM_OP_AMOSWAP_D 234
+#define CKB_VM_ASM_OP_AMOADD_D 235
+#define CKB_VM_ASM_OP_AMOXOR_D 236
+#define CKB_VM_ASM_OP_AMOAND_D 237
+#define CKB_VM_ASM_OP_AMOOR_D 238
+#define CKB_VM_ASM_OP_AMOMIN_D 239
+#define CKB_VM_ASM_OP_AMOMAX_D 240
+#define CKB_VM_ASM_OP_AMOMINU_D 241
+#define CKB_VM_ASM_OP_AMOMAXU_D 242
+#define CKB_VM_ASM_OP_VSETVLI 243
+#define CKB_VM_ASM_OP_VSETIVLI 244
+#define CKB_VM_ASM_OP_VSETVL 245
+#define CKB_VM_ASM_OP_VLE 246
+#define CKB_VM_ASM_OP_VSE 247
+#define CKB_VM_ASM_OP_VADD 248
+#define CKB_VM_ASM_OP_VSUB 249
+#define CKB_VM_ASM_OP_VMUL 250
+#define CKB_VM_ASM_OP_VAND 251
+#define CKB_VM_ASM_OP_VOR 252
+#define CKB_VM_ASM_OP_VXOR 253
+#define CKB_VM_ASM_OP_VMV 254
+#define CKB_VM_ASM_OP_CUSTOM 255
 
 #ifdef CKB_VM_ASM_GENERATE_LABEL_TABLES
 #ifdef __APPLE__
@@ -259,4 +410,154 @@ ckb_vm_asm_labels:
 	.long	.CKB_VM_ASM_LABEL_OP_CUSTOM_LOAD_IMM - .CKB_VM_ASM_LABEL_TABLE
 	.long	.CKB_VM_ASM_LABEL_OP_ADDI - .CKB_VM_ASM_LABEL_TABLE
 	.long	.CKB_VM_ASM_LABEL_OP_CUSTOM_TRACE_END - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_ADDUW - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_ANDN - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_BCLR - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_BCLRI - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_BEXT - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_BEXTI - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_BINV - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_BINVI - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_BSET - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_BSETI - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_CLMUL - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_CLMULH - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_CLMULR - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_CLZ - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_CLZW - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_CPOP - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_CPOPW - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_CTZ - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_CTZW - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_MAX - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_MAXU - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_MIN - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_MINU - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_ORCB - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_ORN - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_REV8 - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_ROL - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_ROLW - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_ROR - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_RORI - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_RORIW - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_RORW - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SEXTB - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SEXTH - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SH1ADD - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SH1ADDUW - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SH2ADD - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SH2ADDUW - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SH3ADD - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SH3ADDUW - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SLLIUW - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_XNOR - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_ZEXTH - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FLW - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FSW - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FMADDS - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FMSUBS - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FNMSUBS - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FNMADDS - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FADDS - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FSUBS - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FMULS - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FDIVS - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FSQRTS - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FSGNJS - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FSGNJNS - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FSGNJXS - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FMINS - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FMAXS - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FCVTWS - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FCVTWUS - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FMVXW - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FEQS - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FLTS - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FLES - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FCLASSS - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FCVTSW - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FCVTSWU - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FMVWX - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FCVTLS - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FCVTLUS - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FCVTSL - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FCVTSLU - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FLD - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FSD - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FMADDD - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FMSUBD - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FNMSUBD - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FNMADDD - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FADDD - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FSUBD - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FMULD - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FDIVD - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FSQRTD - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FSGNJD - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FSGNJND - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FSGNJXD - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FMIND - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FMAXD - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FCVTSD - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FCVTDS - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FEQD - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FLTD - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FLED - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FCLASSD - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FCVTWD - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FCVTWUD - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FCVTDW - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FCVTDWU - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FCVTLD - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FCVTLUD - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FMVXD - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FCVTDL - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FCVTDLU - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FMVDX - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_CSRRW - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_CSRRS - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_CSRRC - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_CSRRWI - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_CSRRSI - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_CSRRCI - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_RVC_FLD - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_RVC_FSD - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_RVC_FLDSP - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_RVC_FSDSP - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_LR_W - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SC_W - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_AMOSWAP_W - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_AMOADD_W - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_AMOXOR_W - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_AMOAND_W - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_AMOOR_W - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_AMOMIN_W - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_AMOMAX_W - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_AMOMINU_W - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_AMOMAXU_W - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_LR_D - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SC_D - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_AMOSWAP_D - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_AMOADD_D - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_AMOXOR_D - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_AMOAND_D - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_AMOOR_D - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_AMOMIN_D - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_AMOMAX_D - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_AMOMINU_D - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_AMOMAXU_D - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_VSETVLI - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_VSETIVLI - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_VSETVL - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_VLE - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_VSE - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_VADD - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_VSUB - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_VMUL - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_VAND - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_VOR - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_VXOR - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_VMV - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_CUSTOM - .CKB_VM_ASM_LABEL_TABLE
 #endif /* CKB_VM_ASM_GENERATE_LABEL_TABLES */
@@ -8,15 +8,16 @@ use crate::{
         check_permission, fill_page_data, memset, round_page_down, round_page_up, FLAG_EXECUTABLE,
         FLAG_FREEZED, FLAG_WRITABLE,
     },
-    CoreMachine, DefaultMachine, Error, Machine, Memory, SupportMachine, RISCV_MAX_MEMORY,
-    RISCV_PAGES, RISCV_PAGESIZE,
+    CoreMachine, DefaultMachine, Error, Machine, Memory, ProgramMetadata, RunResult,
+    SupportMachine, RISCV_MAX_MEMORY, RISCV_PAGES, RISCV_PAGESIZE,
 };
 use byteorder::{ByteOrder, LittleEndian};
 use bytes::Bytes;
 use ckb_vm_definitions::{
     asm::{
         calculate_slot, Trace, RET_DECODE_TRACE, RET_DYNAMIC_JUMP, RET_EBREAK, RET_ECALL,
-        RET_INVALID_PERMISSION, RET_MAX_CYCLES_EXCEEDED, RET_OUT_OF_BOUND, TRACE_ITEM_LENGTH,
+        RET_FENCEI, RET_INVALID_PERMISSION, RET_MAX_CYCLES_EXCEEDED, RET_OUT_OF_BOUND,
+        TRACE_ITEM_LENGTH,
     },
     instructions::OP_CUSTOM_TRACE_END,
 };
@@ -110,6 +111,13 @@ impl Memory<u64> for Box<AsmCoreMachine> {
         Ok(())
     }
 
+    fn load_bytes(&mut self, addr: u64, size: u64) -> Result<Vec<u8>, Error> {
+        if addr.checked_add(size).ok_or(Error::OutOfBound)? > self.memory.len() as u64 {
+            return Err(Error::OutOfBound);
+        }
+        Ok(self.memory[addr as usize..(addr + size) as usize].to_vec())
+    }
+
     fn store_byte(&mut self, addr: u64, size: u64, value: u8) -> Result<(), Error> {
         check_permission(self, addr, size, FLAG_WRITABLE)?;
         memset(
@@ -119,6 +127,16 @@ impl Memory<u64> for Box<AsmCoreMachine> {
         Ok(())
     }
 
+    // Cached traces are dropped together with memory content
+    fn reset(&mut self) -> Result<(), Error> {
+        memset(&mut self.memory[..], 0);
+        memset(&mut self.flags[..], 0);
+        for trace in self.traces.iter_mut() {
+            *trace = Trace::default();
+        }
+        Ok(())
+    }
+
     fn execute_load16(&mut self, addr: u64) -> Result<u16, Error> {
         check_permission(self, addr, 2, FLAG_EXECUTABLE)?;
         self.load16(&(addr)).map(|v| v as u16)
@@ -210,6 +228,14 @@ impl SupportMachine for Box<AsmCoreMachine> {
         Some(self.max_cycles)
     }
 
+    fn set_max_cycles(&mut self, max_cycles: u64) {
+        self.max_cycles = if max_cycles > 0 {
+            max_cycles
+        } else {
+            u64::max_value()
+        };
+    }
+
     fn running(&self) -> bool {
         self.running == 1
     }
@@ -247,14 +273,43 @@ impl<'a> AsmMachine<'a> {
         }
     }
 
-    pub fn load_program(&mut self, program: &Bytes, args: &[Bytes]) -> Result<u64, Error> {
+    pub fn load_program(
+        &mut self,
+        program: &Bytes,
+        args: &[Bytes],
+    ) -> Result<ProgramMetadata, Error> {
         self.machine.load_program(program, args)
     }
 
+    pub fn load_program_with_env(
+        &mut self,
+        program: &Bytes,
+        args: &[Bytes],
+        envs: &[Bytes],
+        random: &[u8; 16],
+    ) -> Result<ProgramMetadata, Error> {
+        self.machine
+            .load_program_with_env(program, args, envs, random)
+    }
+
+    pub fn load_flat_program(
+        &mut self,
+        addr: u64,
+        binary: &Bytes,
+        entry: u64,
+        args: &[Bytes],
+    ) -> Result<ProgramMetadata, Error> {
+        self.machine.load_flat_program(addr, binary, entry, args)
+    }
+
     pub fn run(&mut self) -> Result<i8, Error> {
         let decoder = build_imac_decoder::<u64>();
         self.machine.set_running(true);
         while self.machine.running() {
+            // Stop requests are looked at whenever the assembly loop returns,
+            // such as on ecalls, dynamic jumps and traces not decoded yet.
+            self.machine.check_interrupt()?;
+            let cycles = self.machine.cycles();
             let result = if let Some(aot_code) = &self.aot_code {
                 if let Some(offset) = aot_code.labels.get(self.machine.pc()) {
                     let base_address = aot_code.base_address();
@@ -269,6 +324,10 @@ impl<'a> AsmMachine<'a> {
             } else {
                 unsafe { ckb_vm_x64_execute(&mut (**self.machine.inner_mut())) }
             };
+            let consumed = self.machine.cycles().saturating_sub(cycles);
+            if consumed > 0 {
+                self.machine.notify_cycles(consumed);
+            }
             match result {
                 RET_DECODE_TRACE => {
                     let pc = *self.machine.pc();
@@ -286,12 +345,7 @@ impl<'a> AsmMachine<'a> {
                         // within assembly loops.
                         instruction |= u64::from((current_pc - pc) as u8) << 24;
                         trace.instructions[i] = instruction;
-                        trace.cycles += self
-                            .machine
-                            .instruction_cycle_func()
-                            .as_ref()
-                            .map(|f| f(instruction))
-                            .unwrap_or(0);
+                        trace.cycles += self.machine.instruction_cycles(instruction);
                         let opcode = extract_opcode(instruction);
                         // Here we are calculating the absolute address used in direct threading
                         // from label offsets.
@@ -317,14 +371,25 @@ impl<'a> AsmMachine<'a> {
                 RET_ECALL => self.machine.ecall()?,
                 RET_EBREAK => self.machine.ebreak()?,
                 RET_DYNAMIC_JUMP => (),
-                RET_MAX_CYCLES_EXCEEDED => return Err(Error::InvalidCycles),
+                // fence.i ends traces, so pc already points past it
+                RET_FENCEI => {
+                    for trace in self.machine.inner_mut().traces.iter_mut() {
+                        *trace = Trace::default();
+                    }
+                }
+                RET_MAX_CYCLES_EXCEEDED => return Err(Error::CyclesExceeded),
                 RET_OUT_OF_BOUND => return Err(Error::OutOfBound),
-                RET_INVALID_PERMISSION => return Err(Error::InvalidPermission),
+                RET_INVALID_PERMISSION => return Err(Error::MemoryProtection),
                 _ => return Err(Error::Asm(result)),
             }
         }
         Ok(self.machine.exit_code())
     }
+
+    pub fn run_with_result(&mut self) -> RunResult {
+        let result = self.run();
+        RunResult::new(&self.machine, result)
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,145 @@
+// Initial stack layout expected by libc implementations on Linux RISC-V,
+// see load_program_with_env. From the stack pointer upwards:
+//
+// argc
+// argv pointers, followed by a null pointer
+// envp pointers, followed by a null pointer
+// auxv (type, value) pairs, terminated by AT_NULL
+// AT_RANDOM bytes and the argument and environment strings
+use super::super::{instructions::Register, memory::Memory, registers::SP, Error, RISCV_PAGESIZE};
+use super::SupportMachine;
+use alloc::{vec, vec::Vec};
+use bytes::Bytes;
+use goblin::elf::header::ET_DYN;
+use goblin::elf::program_header::{PT_LOAD, PT_PHDR};
+use goblin::elf::Elf;
+
+pub const AT_NULL: u64 = 0;
+pub const AT_PHDR: u64 = 3;
+pub const AT_PHENT: u64 = 4;
+pub const AT_PHNUM: u64 = 5;
+pub const AT_PAGESZ: u64 = 6;
+pub const AT_BASE: u64 = 7;
+pub const AT_FLAGS: u64 = 8;
+pub const AT_ENTRY: u64 = 9;
+pub const AT_UID: u64 = 11;
+pub const AT_EUID: u64 = 12;
+pub const AT_GID: u64 = 13;
+pub const AT_EGID: u64 = 14;
+pub const AT_HWCAP: u64 = 16;
+pub const AT_SECURE: u64 = 23;
+pub const AT_RANDOM: u64 = 25;
+
+// One bit per single letter extension as in the Linux kernel, the machine
+// always runs IMC code.
+const HWCAP: u64 = (1 << (b'i' - b'a')) | (1 << (b'm' - b'a')) | (1 << (b'c' - b'a'));
+
+// Auxiliary vector entries describing the loaded program, except
+// AT_RANDOM whose address is only known once the stack is built.
+pub(crate) fn program_auxv(program: &Bytes, load_bias: u64) -> Result<Vec<(u64, u64)>, Error> {
+    let elf = Elf::parse(program).map_err(|_e| Error::ParseError)?;
+    let load_bias = if elf.header.e_type == ET_DYN {
+        load_bias
+    } else {
+        0
+    };
+    let phoff = elf.header.e_phoff;
+    // Program headers are located through PT_PHDR when present, otherwise
+    // through the PT_LOAD segment containing them in the file.
+    let phdr = elf
+        .program_headers
+        .iter()
+        .find(|header| header.p_type == PT_PHDR)
+        .map(|header| header.p_vaddr)
+        .or_else(|| {
+            elf.program_headers
+                .iter()
+                .find(|header| {
+                    header.p_type == PT_LOAD
+                        && phoff >= header.p_offset
+                        && phoff - header.p_offset < header.p_filesz
+                })
+                .map(|header| header.p_vaddr.wrapping_add(phoff - header.p_offset))
+        })
+        .map(|addr| addr.wrapping_add(load_bias))
+        .unwrap_or(0);
+    Ok(vec![
+        (AT_PHDR, phdr),
+        (AT_PHENT, u64::from(elf.header.e_phentsize)),
+        (AT_PHNUM, u64::from(elf.header.e_phnum)),
+        (AT_PAGESZ, RISCV_PAGESIZE as u64),
+        (AT_BASE, 0),
+        (AT_FLAGS, 0),
+        (AT_ENTRY, elf.header.e_entry.wrapping_add(load_bias)),
+        (AT_UID, 0),
+        (AT_EUID, 0),
+        (AT_GID, 0),
+        (AT_EGID, 0),
+        (AT_HWCAP, HWCAP),
+        (AT_SECURE, 0),
+    ])
+}
+
+fn store_word<Mac: SupportMachine>(machine: &mut Mac, addr: u64, value: u64) -> Result<(), Error> {
+    let bytes = value.to_le_bytes();
+    let size = (Mac::REG::BITS / 8) as usize;
+    machine.memory_mut().store_bytes(addr, &bytes[..size])
+}
+
+// Builds the stack described above in [stack_start, stack_start +
+// stack_size), leaving SP 16 bytes aligned and pointing to argc. Returns
+// the number of bytes used.
+pub(crate) fn initialize_linux_stack<Mac: SupportMachine>(
+    machine: &mut Mac,
+    args: &[Bytes],
+    envs: &[Bytes],
+    auxv: &[(u64, u64)],
+    random: &[u8; 16],
+    stack_start: u64,
+    stack_size: u64,
+) -> Result<u64, Error> {
+    let stack_top = stack_start + stack_size;
+    let mut sp = stack_top;
+    let mut pointers = Vec::with_capacity(args.len() + envs.len());
+    for string in args.iter().chain(envs.iter()) {
+        sp = sp
+            .checked_sub(string.len() as u64 + 1)
+            .filter(|sp| *sp >= stack_start)
+            .ok_or(Error::OutOfBound)?;
+        machine.memory_mut().store_bytes(sp, string)?;
+        machine
+            .memory_mut()
+            .store_byte(sp + string.len() as u64, 1, 0)?;
+        pointers.push(sp);
+    }
+    sp = sp
+        .checked_sub(random.len() as u64)
+        .filter(|sp| *sp >= stack_start)
+        .ok_or(Error::OutOfBound)?;
+    machine.memory_mut().store_bytes(sp, random)?;
+    let random_addr = sp;
+
+    let mut words = vec![args.len() as u64];
+    words.extend_from_slice(&pointers[..args.len()]);
+    words.push(0);
+    words.extend_from_slice(&pointers[args.len()..]);
+    words.push(0);
+    for (key, value) in auxv
+        .iter()
+        .chain([(AT_RANDOM, random_addr), (AT_NULL, 0)].iter())
+    {
+        words.push(*key);
+        words.push(*value);
+    }
+    let word_size = u64::from(Mac::REG::BITS / 8);
+    sp = sp
+        .checked_sub(words.len() as u64 * word_size)
+        .map(|sp| sp & !15)
+        .filter(|sp| *sp >= stack_start)
+        .ok_or(Error::OutOfBound)?;
+    for (i, word) in words.iter().enumerate() {
+        store_word(machine, sp + i as u64 * word_size, *word)?;
+    }
+    machine.set_register(SP, Mac::REG::from_u64(sp));
+    Ok(stack_top - sp)
+}
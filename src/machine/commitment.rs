@@ -0,0 +1,151 @@
+use alloc::{boxed::Box, collections::BTreeMap, vec::Vec};
+
+// Hash function state commitments are built with, e.g. blake2b-256 as used
+// by CKB. It is given the previous commitment followed by the encoded
+// transitions being committed.
+pub type StateHashFunc = dyn Fn(&[u8]) -> [u8; 32];
+
+/// How often the state commitment of a machine is updated.
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub enum CommitmentGranularity {
+    // After every instruction
+    Instruction,
+    // After every basic block, see is_basic_block_end_instruction
+    Block,
+}
+
+/// Rolling hash over the state transitions of a run, the building block of
+/// interactive verification games where two parties bisect a run down to a
+/// single disputed step, see DefaultMachineBuilder::state_commitment.
+///
+/// Each executed instruction is encoded as its pc, the general purpose
+/// registers it changed and the memory it wrote:
+///
+/// pc: u64, register count: u8, (index: u8, value: u64)*,
+/// write count: u32, (addr: u64, len: u64, bytes)*
+///
+/// all little endian. Once a step completes, the commitment becomes the
+/// hash of the previous commitment followed by the transitions of the step.
+/// The first commitment starts from 32 zero bytes.
+pub struct StateCommitment {
+    hash: Box<StateHashFunc>,
+    granularity: CommitmentGranularity,
+    commitment: [u8; 32],
+    steps: u64,
+    // Previous commitment followed by the transitions not committed yet
+    pending: Vec<u8>,
+    // Registers, stored range and dirty pages captured before the current
+    // instruction, see DefaultMachine::begin_transition.
+    pub(crate) registers: Vec<u64>,
+    pub(crate) store: Option<(u64, u64)>,
+    pub(crate) dirty: Option<BTreeMap<u64, Vec<u8>>>,
+}
+
+impl StateCommitment {
+    pub fn new(hash: Box<StateHashFunc>, granularity: CommitmentGranularity) -> Self {
+        let mut commitment = Self {
+            hash,
+            granularity,
+            commitment: [0; 32],
+            steps: 0,
+            pending: Vec::new(),
+            registers: Vec::new(),
+            store: None,
+            dirty: None,
+        };
+        commitment.clear();
+        commitment
+    }
+
+    pub fn granularity(&self) -> CommitmentGranularity {
+        self.granularity
+    }
+
+    // Commitment after the last completed step
+    pub fn commitment(&self) -> [u8; 32] {
+        self.commitment
+    }
+
+    // Number of steps committed so far
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    // Starts over from the zero commitment, transitions not committed yet
+    // are dropped.
+    pub fn clear(&mut self) {
+        self.commitment = [0; 32];
+        self.steps = 0;
+        self.pending.clear();
+        self.pending.extend_from_slice(&self.commitment);
+        self.store = None;
+        self.dirty = None;
+    }
+
+    // Appends the transition of the instruction at pc, and commits it
+    // along with the rest of the step when block_end is set or instructions
+    // are committed one by one.
+    pub fn push(
+        &mut self,
+        pc: u64,
+        registers: &[(usize, u64)],
+        writes: &[(u64, Vec<u8>)],
+        block_end: bool,
+    ) {
+        self.pending.extend_from_slice(&pc.to_le_bytes());
+        self.pending.push(registers.len() as u8);
+        for (index, value) in registers {
+            self.pending.push(*index as u8);
+            self.pending.extend_from_slice(&value.to_le_bytes());
+        }
+        self.pending
+            .extend_from_slice(&(writes.len() as u32).to_le_bytes());
+        for (addr, bytes) in writes {
+            self.pending.extend_from_slice(&addr.to_le_bytes());
+            self.pending
+                .extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+            self.pending.extend_from_slice(bytes);
+        }
+        if block_end || self.granularity == CommitmentGranularity::Instruction {
+            self.commitment = (self.hash)(&self.pending);
+            self.steps += 1;
+            self.pending.clear();
+            self.pending.extend_from_slice(&self.commitment);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Not a real hash, but enough to tell what was committed
+    fn sum(data: &[u8]) -> [u8; 32] {
+        let mut result = [0; 32];
+        for (i, b) in data.iter().enumerate() {
+            result[i % 32] ^= b.rotate_left(i as u32 % 8);
+        }
+        result[31] = data.len() as u8;
+        result
+    }
+
+    #[test]
+    fn test_state_commitment() {
+        let mut by_block = StateCommitment::new(Box::new(sum), CommitmentGranularity::Block);
+        let mut by_instruction =
+            StateCommitment::new(Box::new(sum), CommitmentGranularity::Instruction);
+        for commitment in [&mut by_block, &mut by_instruction].iter_mut() {
+            commitment.push(0x1000, &[(10, 1)], &[], false);
+            commitment.push(0x1004, &[], &[(0x2000, vec![1, 2])], true);
+        }
+        assert_eq!(by_block.steps(), 1);
+        assert_eq!(by_instruction.steps(), 2);
+        // 32 bytes of previous commitment, then 8 + 1 + 9 + 4 and
+        // 8 + 1 + 4 + 8 + 8 + 2 bytes of transitions
+        assert_eq!(by_block.commitment()[31], (32 + 22 + 31) as u8);
+        assert_ne!(by_block.commitment(), by_instruction.commitment());
+        by_block.clear();
+        assert_eq!(by_block.commitment(), [0; 32]);
+        assert_eq!(by_block.steps(), 0);
+    }
+}
@@ -0,0 +1,74 @@
+use alloc::{vec, vec::Vec};
+
+/// Bitmap of executed basic blocks by start address. Instructions are at
+/// least 2 bytes aligned, so bit n stands for the block starting at
+/// address 2 * n. The bitmap only grows up to the highest block executed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Coverage {
+    bits: Vec<u64>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Returns true when the block was not covered before, which is what
+    // fuzzers look for to keep an input.
+    #[inline(always)]
+    pub fn mark(&mut self, addr: u64) -> bool {
+        let bit = addr >> 1;
+        let word = (bit / 64) as usize;
+        if word >= self.bits.len() {
+            self.bits.resize(word + 1, 0);
+        }
+        let mask = 1 << (bit % 64);
+        let new = self.bits[word] & mask == 0;
+        self.bits[word] |= mask;
+        new
+    }
+
+    pub fn contains(&self, addr: u64) -> bool {
+        let bit = addr >> 1;
+        self.bits
+            .get((bit / 64) as usize)
+            .map(|bits| bits & (1 << (bit % 64)) != 0)
+            .unwrap_or(false)
+    }
+
+    // Raw bitmap, see Coverage for the layout.
+    pub fn bitmap(&self) -> &[u64] {
+        &self.bits
+    }
+
+    // Start addresses of covered blocks in ascending order.
+    pub fn blocks(&self) -> Vec<u64> {
+        let mut result = Vec::new();
+        for (i, bits) in self.bits.iter().enumerate() {
+            let mut bits = *bits;
+            while bits != 0 {
+                let bit = bits.trailing_zeros() as u64;
+                result.push((i as u64 * 64 + bit) << 1);
+                bits &= bits - 1;
+            }
+        }
+        result
+    }
+
+    // Adds blocks covered by other, returning the number of new blocks.
+    pub fn merge(&mut self, other: &Coverage) -> usize {
+        if other.bits.len() > self.bits.len() {
+            self.bits.resize(other.bits.len(), 0);
+        }
+        let mut new = 0;
+        for (bits, other_bits) in self.bits.iter_mut().zip(other.bits.iter()) {
+            new += (other_bits & !*bits).count_ones() as usize;
+            *bits |= other_bits;
+        }
+        new
+    }
+
+    pub fn clear(&mut self) {
+        self.bits = vec![];
+    }
+}
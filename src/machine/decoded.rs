@@ -0,0 +1,173 @@
+use super::{convert_flags, elf_bits};
+use crate::{
+    decoder::Decoder,
+    instructions::{instruction_length, is_basic_block_end_instruction, Instruction, Register},
+    memory::FLAG_EXECUTABLE,
+    Error,
+};
+use alloc::{collections::BTreeMap, vec::Vec};
+use bytes::Bytes;
+use goblin::elf::program_header::PT_LOAD;
+use goblin::elf::Elf;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct DecodedInstruction {
+    address: u64,
+    // Raw bits the instruction was decoded from, RVC instructions only use
+    // the lower 16 bits as with Decoder::decode_raw.
+    bits: u32,
+    instruction: Instruction,
+}
+
+/// Instructions of a program's executable segments decoded ahead of time,
+/// grouped in basic blocks keyed by start address. A program is decoded
+/// once and shared between machines through Arc, machines then only fetch
+/// instruction bits from memory to check they still match, which keeps
+/// permission checks in place and makes stale entries harmless.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DecodedProgram {
+    blocks: BTreeMap<u64, Vec<DecodedInstruction>>,
+}
+
+impl DecodedProgram {
+    // Decodes the executable segments of program with decoder, which should
+    // be the decoder machines run the program with. Segments are swept
+    // linearly from their start, bytes that do not decode end the current
+    // block, code reached in some other way is left to the machine's own
+    // decoder. Position independent programs are decoded at the addresses
+    // they are linked to, so they only benefit when loaded without a bias.
+    pub fn new<R: Register>(program: &Bytes, decoder: &Decoder) -> Result<Self, Error> {
+        let elf = Elf::parse(program).map_err(|_e| Error::ParseError)?;
+        let bits = elf_bits(&elf.header).ok_or(Error::InvalidElfBits)?;
+        if bits != R::BITS {
+            return Err(Error::InvalidElfBits);
+        }
+        let mut result = Self::default();
+        for program_header in &elf.program_headers {
+            if program_header.p_type != PT_LOAD
+                || convert_flags(program_header.p_flags)? & FLAG_EXECUTABLE == 0
+            {
+                continue;
+            }
+            let start = program_header.p_offset as usize;
+            let end = program_header
+                .p_offset
+                .checked_add(program_header.p_filesz)
+                .ok_or(Error::OutOfBound)? as usize;
+            if start > end || end > program.len() {
+                return Err(Error::OutOfBound);
+            }
+            result.decode_segment(program_header.p_vaddr, &program[start..end], decoder);
+        }
+        Ok(result)
+    }
+
+    fn decode_segment(&mut self, address: u64, code: &[u8], decoder: &Decoder) {
+        let mut block = Vec::new();
+        let mut offset = 0;
+        while offset + 2 <= code.len() {
+            let mut bits = u32::from(u16::from_le_bytes([code[offset], code[offset + 1]]));
+            let mut length = 2;
+            if bits & 0x3 == 0x3 {
+                if offset + 4 > code.len() {
+                    break;
+                }
+                bits |= u32::from(u16::from_le_bytes([code[offset + 2], code[offset + 3]])) << 16;
+                length = 4;
+            }
+            let instruction_address = address.wrapping_add(offset as u64);
+            match decoder.decode_raw(bits) {
+                Ok(instruction) if usize::from(instruction_length(instruction)) == length => {
+                    block.push(DecodedInstruction {
+                        address: instruction_address,
+                        bits,
+                        instruction,
+                    });
+                    if is_basic_block_end_instruction(instruction) {
+                        self.push_block(&mut block);
+                    }
+                    offset += length;
+                }
+                _ => {
+                    self.push_block(&mut block);
+                    offset += 2;
+                }
+            }
+        }
+        self.push_block(&mut block);
+    }
+
+    fn push_block(&mut self, block: &mut Vec<DecodedInstruction>) {
+        if let Some(first) = block.first() {
+            self.blocks.insert(first.address, core::mem::take(block));
+        }
+    }
+
+    // Number of basic blocks decoded
+    pub fn blocks(&self) -> usize {
+        self.blocks.len()
+    }
+
+    // Number of instructions decoded across all blocks
+    pub fn instructions(&self) -> usize {
+        self.blocks.values().map(Vec::len).sum()
+    }
+
+    // Instructions of the basic block starting at address, if any
+    pub fn block(&self, address: u64) -> Option<Vec<Instruction>> {
+        self.blocks
+            .get(&address)
+            .map(|block| block.iter().map(|i| i.instruction).collect())
+    }
+
+    // Looks up the instruction decoded at address together with the bits it
+    // was decoded from. Addresses inside a block are found as well, since
+    // jumps do not always land at the start of one.
+    pub(crate) fn get(&self, address: u64) -> Option<(u32, Instruction)> {
+        let (_, block) = self.blocks.range(..=address).next_back()?;
+        let index = block.binary_search_by_key(&address, |i| i.address).ok()?;
+        Some((block[index].bits, block[index].instruction))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::build_imac_decoder;
+    use crate::instructions::extract_opcode;
+    use ckb_vm_definitions::instructions::{OP_ADDI, OP_ECALL, OP_JAL};
+
+    #[test]
+    fn test_decode_segment_blocks() {
+        let decoder = build_imac_decoder::<u64>();
+        let mut program = DecodedProgram::default();
+        let words: [u32; 5] = [
+            0x00150513, // addi a0, a0, 1
+            0x0000006f, // j 0
+            0xffffffff, // invalid
+            0x00000513, // li a0, 0
+            0x00000073, // ecall
+        ];
+        let code: Vec<u8> = words
+            .iter()
+            .flat_map(|w| w.to_le_bytes().to_vec())
+            .collect();
+        program.decode_segment(0x1000, &code, &decoder);
+        assert_eq!(program.blocks(), 2);
+        assert_eq!(program.instructions(), 4);
+        let opcodes = |address| {
+            program
+                .block(address)
+                .unwrap()
+                .into_iter()
+                .map(extract_opcode)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(opcodes(0x1000), vec![OP_ADDI, OP_JAL]);
+        assert_eq!(opcodes(0x100c), vec![OP_ADDI, OP_ECALL]);
+        assert_eq!(program.block(0x1010), None);
+        assert_eq!(program.get(0x1004).map(|(bits, _)| bits), Some(0x0000006f));
+        assert_eq!(program.get(0x1008), None);
+        assert_eq!(program.get(0x1002), None);
+    }
+}
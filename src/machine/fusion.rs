@@ -0,0 +1,175 @@
+use super::super::{
+    instructions::{extract_opcode, Instruction, Itype, Register, Rtype, Utype},
+    memory::Memory,
+    CoreMachine, Error,
+};
+use ckb_vm_definitions::instructions as insts;
+
+/// Pair of adjacent instructions TraceMachine executes as one, see
+/// TraceMachine::set_fusion. Both instructions write the same register, so
+/// the value in between is never observed and only the final one is
+/// computed.
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub(crate) enum Fused {
+    // rd = value, from lui+addi, lui+addiw and auipc+addi
+    Constant {
+        rd: usize,
+        value: u64,
+    },
+    // rd = 64 bit value at a pc relative address, from auipc+ld
+    Load {
+        rd: usize,
+        address: u64,
+    },
+    // rd = (rs1 << shamt) + rs2, from slli+add
+    ShiftAdd {
+        rd: usize,
+        rs1: usize,
+        shamt: u32,
+        rs2: usize,
+    },
+}
+
+fn sext(imm: i32) -> u64 {
+    imm as i64 as u64
+}
+
+// Recognizes first followed by second at pc as a pair that can be fused.
+// Compressed forms are recognized as well.
+pub(crate) fn fuse(first: Instruction, second: Instruction, pc: u64) -> Option<Fused> {
+    let (rd, value) = match extract_opcode(first) {
+        insts::OP_LUI | insts::OP_RVC_LUI => {
+            let i = Utype(first);
+            (i.rd(), sext(i.immediate_s()))
+        }
+        insts::OP_AUIPC => {
+            let i = Utype(first);
+            (i.rd(), pc.wrapping_add(sext(i.immediate_s())))
+        }
+        insts::OP_SLLI | insts::OP_RVC_SLLI => {
+            let i = Itype(first);
+            let add = Rtype(second);
+            let opcode = extract_opcode(second);
+            if i.rd() == 0
+                || add.rd() != i.rd()
+                || !matches!(opcode, insts::OP_ADD | insts::OP_RVC_ADD)
+            {
+                return None;
+            }
+            // The other operand must not be the intermediate value
+            let rs2 = match (add.rs1() == i.rd(), add.rs2() == i.rd()) {
+                (true, false) => add.rs2(),
+                (false, true) => add.rs1(),
+                _ => return None,
+            };
+            return Some(Fused::ShiftAdd {
+                rd: i.rd(),
+                rs1: i.rs1(),
+                shamt: i.immediate(),
+                rs2,
+            });
+        }
+        _ => return None,
+    };
+    let i = Itype(second);
+    if rd == 0 || i.rd() != rd || i.rs1() != rd {
+        return None;
+    }
+    let auipc = extract_opcode(first) == insts::OP_AUIPC;
+    match extract_opcode(second) {
+        insts::OP_ADDI | insts::OP_RVC_ADDI => Some(Fused::Constant {
+            rd,
+            value: value.wrapping_add(sext(i.immediate_s())),
+        }),
+        insts::OP_ADDIW | insts::OP_RVC_ADDIW if !auipc => Some(Fused::Constant {
+            rd,
+            value: sext(value.wrapping_add(sext(i.immediate_s())) as i32),
+        }),
+        insts::OP_LD if auipc => Some(Fused::Load {
+            rd,
+            address: value.wrapping_add(sext(i.immediate_s())),
+        }),
+        _ => None,
+    }
+}
+
+// Computes the register written by a fused pair and its value, without
+// changing the machine. Errors are left for the instructions to raise one
+// at a time.
+pub(crate) fn evaluate<Mac: CoreMachine>(
+    fused: Fused,
+    machine: &mut Mac,
+) -> Result<(usize, Mac::REG), Error> {
+    match fused {
+        Fused::Constant { rd, value } => Ok((rd, Mac::REG::from_u64(value))),
+        Fused::Load { rd, address } => {
+            let value = machine.memory_mut().load64(&Mac::REG::from_u64(address))?;
+            Ok((rd, value))
+        }
+        Fused::ShiftAdd {
+            rd,
+            rs1,
+            shamt,
+            rs2,
+        } => {
+            let shifted = machine.registers()[rs1].clone() << Mac::REG::from_u32(shamt);
+            Ok((rd, shifted.overflowing_add(&machine.registers()[rs2])))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instructions::{blank_instruction, Itype, Rtype, Utype};
+
+    #[test]
+    fn test_fuse() {
+        let lui = Utype::new(insts::OP_LUI, 10, 0x12345000).0;
+        let addi = Itype::new(insts::OP_ADDI, 10, 10, 0x678).0;
+        let addiw = Itype::new_s(insts::OP_ADDIW, 10, 10, -1).0;
+        assert_eq!(
+            fuse(lui, addi, 0x1000),
+            Some(Fused::Constant {
+                rd: 10,
+                value: 0x12345678
+            })
+        );
+        let negative = Utype::new_s(insts::OP_LUI, 10, -0x1000).0;
+        assert_eq!(
+            fuse(negative, addiw, 0x1000),
+            Some(Fused::Constant {
+                rd: 10,
+                value: (-0x1001i64) as u64
+            })
+        );
+        // The intermediate value would be lost
+        let other = Itype::new(insts::OP_ADDI, 11, 10, 1).0;
+        assert_eq!(fuse(lui, other, 0x1000), None);
+
+        let auipc = Utype::new(insts::OP_AUIPC, 5, 0x2000).0;
+        let ld = Itype::new_s(insts::OP_LD, 5, 5, -8).0;
+        assert_eq!(
+            fuse(auipc, ld, 0x1000),
+            Some(Fused::Load {
+                rd: 5,
+                address: 0x2ff8
+            })
+        );
+
+        let slli = Itype::new(insts::OP_SLLI, 10, 11, 3).0;
+        let add = Rtype::new(insts::OP_ADD, 10, 12, 10).0;
+        assert_eq!(
+            fuse(slli, add, 0x1000),
+            Some(Fused::ShiftAdd {
+                rd: 10,
+                rs1: 11,
+                shamt: 3,
+                rs2: 12
+            })
+        );
+        let double = Rtype::new(insts::OP_ADD, 10, 10, 10).0;
+        assert_eq!(fuse(slli, double, 0x1000), None);
+        assert_eq!(fuse(blank_instruction(insts::OP_ECALL), addi, 0x1000), None);
+    }
+}
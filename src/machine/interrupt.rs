@@ -0,0 +1,35 @@
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Requests a machine to stop from another thread, e.g. when a UI cancels a
+/// run or a watchdog times it out, see DefaultMachine::interrupt_handle.
+/// Handles are cheap to clone and all refer to the same machine. The run
+/// loop notices the request at the end of the current basic block and
+/// fails with Error::Interrupted, the machine is left between two
+/// instructions and can be run again. A request made while the machine is
+/// not running stops the next run right away.
+#[derive(Debug, Clone, Default)]
+pub struct InterruptHandle {
+    requested: Arc<AtomicBool>,
+}
+
+impl InterruptHandle {
+    pub fn stop(&self) {
+        self.requested.store(true, Ordering::Release);
+    }
+
+    // Tells if a stop has been requested and not reported by a run yet
+    pub fn is_stop_requested(&self) -> bool {
+        self.requested.load(Ordering::Acquire)
+    }
+
+    // Consumes a pending request so it is reported only once. The plain
+    // load keeps the check cheap when nothing is pending.
+    pub(crate) fn take(&self) -> bool {
+        self.requested.load(Ordering::Relaxed) && self.requested.swap(false, Ordering::Acquire)
+    }
+
+    pub(crate) fn clear(&self) {
+        self.requested.store(false, Ordering::Release);
+    }
+}
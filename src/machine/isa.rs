@@ -0,0 +1,122 @@
+use super::super::Error;
+use super::elf_bits;
+
+use core::fmt;
+use goblin::elf::header::{EI_DATA, ELFDATA2LSB};
+use goblin::elf::Elf;
+
+// Extension bits follow the misa CSR, one bit per extension letter
+pub const ISA_A: u32 = 1 << 0;
+pub const ISA_B: u32 = 1 << 1;
+pub const ISA_C: u32 = 1 << 2;
+pub const ISA_D: u32 = 1 << 3;
+pub const ISA_F: u32 = 1 << 5;
+pub const ISA_I: u32 = 1 << 8;
+pub const ISA_M: u32 = 1 << 12;
+pub const ISA_Q: u32 = 1 << 16;
+pub const ISA_V: u32 = 1 << 21;
+pub const ISA_IMAC: u32 = ISA_I | ISA_M | ISA_A | ISA_C;
+
+// e_flags of RISC-V ELF files
+pub const EF_RISCV_RVC: u32 = 0x1;
+pub const EF_RISCV_FLOAT_ABI: u32 = 0x6;
+pub const EF_RISCV_FLOAT_ABI_SINGLE: u32 = 0x2;
+pub const EF_RISCV_FLOAT_ABI_DOUBLE: u32 = 0x4;
+pub const EF_RISCV_FLOAT_ABI_QUAD: u32 = 0x6;
+
+/// Instruction set a machine is built for, see
+/// DefaultMachineBuilder::isa. Programs are checked against it when
+/// loaded, so one targeting another ISA fails right away with
+/// Error::UnsupportedIsa instead of hitting an invalid instruction later.
+/// Memory is little endian as RISC-V requires, big endian programs are
+/// rejected whatever the descriptor.
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub struct MachineIsa {
+    // Register width in bits, which should match the machine's registers
+    pub xlen: u8,
+    // ISA_* bits of the extensions the machine's decoder handles
+    pub extensions: u32,
+    // Version of the machine semantics, left to hosts to define and
+    // reported as is, e.g. to tell which programs a deployment accepts
+    pub version: u32,
+}
+
+impl MachineIsa {
+    pub fn new(xlen: u8, extensions: u32, version: u32) -> Self {
+        Self {
+            xlen,
+            extensions,
+            version,
+        }
+    }
+
+    // What program declares it needs in its ELF header: its class gives
+    // the register width, EF_RISCV_RVC requires C and the float ABI F, D
+    // or Q. Instructions themselves are not looked at, see
+    // analysis::scan_program for that.
+    pub fn of_program(program: &[u8]) -> Result<Self, Error> {
+        let elf = Elf::parse(program).map_err(|_e| Error::ParseError)?;
+        let xlen = elf_bits(&elf.header).ok_or(Error::InvalidElfBits)?;
+        if elf.header.e_ident[EI_DATA] != ELFDATA2LSB {
+            return Err(Error::InvalidElfBits);
+        }
+        let flags = elf.header.e_flags;
+        let mut extensions = ISA_I;
+        if flags & EF_RISCV_RVC != 0 {
+            extensions |= ISA_C;
+        }
+        extensions |= match flags & EF_RISCV_FLOAT_ABI {
+            EF_RISCV_FLOAT_ABI_SINGLE => ISA_F,
+            EF_RISCV_FLOAT_ABI_DOUBLE => ISA_F | ISA_D,
+            EF_RISCV_FLOAT_ABI_QUAD => ISA_F | ISA_D | ISA_Q,
+            _ => 0,
+        };
+        Ok(Self::new(xlen, extensions, 0))
+    }
+
+    pub fn supports(&self, extensions: u32) -> bool {
+        self.extensions & extensions == extensions
+    }
+
+    // Fails with Error::UnsupportedIsa carrying what program declares when
+    // the machine has another register width or lacks an extension.
+    pub fn check_program(&self, program: &[u8]) -> Result<(), Error> {
+        let required = Self::of_program(program)?;
+        if required.xlen != self.xlen || !self.supports(required.extensions) {
+            return Err(Error::UnsupportedIsa {
+                xlen: required.xlen,
+                extensions: required.extensions,
+            });
+        }
+        Ok(())
+    }
+}
+
+// Shown as an ISA string such as rv64imac
+impl fmt::Display for MachineIsa {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rv{}", self.xlen)?;
+        for letter in "imafdqcbv".chars() {
+            if self.extensions & (1 << (letter as u32 - 'a' as u32)) != 0 {
+                write!(f, "{}", letter)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_machine_isa_display() {
+        assert_eq!(MachineIsa::new(64, ISA_IMAC, 1).to_string(), "rv64imac");
+        assert_eq!(
+            MachineIsa::new(32, ISA_I | ISA_F | ISA_D | ISA_C, 0).to_string(),
+            "rv32ifdc"
+        );
+        assert!(MachineIsa::new(64, ISA_IMAC, 0).supports(ISA_I | ISA_C));
+        assert!(!MachineIsa::new(64, ISA_IMAC, 0).supports(ISA_F));
+    }
+}
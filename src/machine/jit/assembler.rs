@@ -0,0 +1,192 @@
+// A tiny x86-64 assembler covering the handful of instructions emitted by
+// the JIT tier. Generated code only uses rax and rcx as scratch registers,
+// and reads and writes RISC-V registers in the array pointed to by rdi.
+use alloc::vec::Vec;
+
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub enum AluOp {
+    Add,
+    Sub,
+    And,
+    Or,
+    Xor,
+    Cmp,
+}
+
+impl AluOp {
+    // Opcode of "op rax, [rdi + disp32]"
+    fn memory_opcode(self) -> u8 {
+        match self {
+            AluOp::Add => 0x03,
+            AluOp::Sub => 0x2B,
+            AluOp::And => 0x23,
+            AluOp::Or => 0x0B,
+            AluOp::Xor => 0x33,
+            AluOp::Cmp => 0x3B,
+        }
+    }
+
+    // Opcode of "op rax, imm32", the short form dedicated to rax
+    fn immediate_opcode(self) -> u8 {
+        match self {
+            AluOp::Add => 0x05,
+            AluOp::Sub => 0x2D,
+            AluOp::And => 0x25,
+            AluOp::Or => 0x0D,
+            AluOp::Xor => 0x35,
+            AluOp::Cmp => 0x3D,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub enum ShiftOp {
+    Shl,
+    Shr,
+    Sar,
+}
+
+impl ShiftOp {
+    // ModRM byte selecting the operation with rax as operand
+    fn modrm(self) -> u8 {
+        match self {
+            ShiftOp::Shl => 0xE0,
+            ShiftOp::Shr => 0xE8,
+            ShiftOp::Sar => 0xF8,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub enum Condition {
+    Less,
+    Below,
+}
+
+const REX_W: u8 = 0x48;
+
+#[derive(Default)]
+pub struct Assembler {
+    code: Vec<u8>,
+}
+
+impl Assembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn code(&self) -> &[u8] {
+        &self.code
+    }
+
+    fn emit(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    fn emit_u32(&mut self, value: u32) {
+        self.code.extend_from_slice(&value.to_le_bytes());
+    }
+
+    // Displacement of register idx in the register array
+    fn register_offset(idx: usize) -> u32 {
+        (idx * 8) as u32
+    }
+
+    // mov rax, [rdi + 8 * idx]
+    pub fn load_rax(&mut self, idx: usize) {
+        self.emit(&[REX_W, 0x8B, 0x87]);
+        self.emit_u32(Self::register_offset(idx));
+    }
+
+    // mov rcx, [rdi + 8 * idx]
+    pub fn load_rcx(&mut self, idx: usize) {
+        self.emit(&[REX_W, 0x8B, 0x8F]);
+        self.emit_u32(Self::register_offset(idx));
+    }
+
+    // mov [rdi + 8 * idx], rax
+    pub fn store_rax(&mut self, idx: usize) {
+        self.emit(&[REX_W, 0x89, 0x87]);
+        self.emit_u32(Self::register_offset(idx));
+    }
+
+    // mov rax, imm32, the immediate is sign extended
+    pub fn mov_rax_immediate(&mut self, imm: i32) {
+        self.emit(&[REX_W, 0xC7, 0xC0]);
+        self.emit_u32(imm as u32);
+    }
+
+    // op rax, [rdi + 8 * idx]
+    pub fn alu_register(&mut self, op: AluOp, idx: usize) {
+        self.emit(&[REX_W, op.memory_opcode(), 0x87]);
+        self.emit_u32(Self::register_offset(idx));
+    }
+
+    // op rax, imm32, the immediate is sign extended
+    pub fn alu_immediate(&mut self, op: AluOp, imm: i32) {
+        self.emit(&[REX_W, op.immediate_opcode()]);
+        self.emit_u32(imm as u32);
+    }
+
+    // shift rax (or eax for 32 bit shifts) by an immediate
+    pub fn shift_immediate(&mut self, op: ShiftOp, word: bool, amount: u8) {
+        if !word {
+            self.emit(&[REX_W]);
+        }
+        self.emit(&[0xC1, op.modrm(), amount]);
+    }
+
+    // shift rax (or eax for 32 bit shifts) by cl, the processor masks the
+    // amount to the operand width the same way RISC-V does.
+    pub fn shift_cl(&mut self, op: ShiftOp, word: bool) {
+        if !word {
+            self.emit(&[REX_W]);
+        }
+        self.emit(&[0xD3, op.modrm()]);
+    }
+
+    // setcc al followed by movzx eax, al, which clears the upper bits of rax
+    pub fn set_condition(&mut self, condition: Condition) {
+        let opcode = match condition {
+            Condition::Less => 0x9C,
+            Condition::Below => 0x92,
+        };
+        self.emit(&[0x0F, opcode, 0xC0, 0x0F, 0xB6, 0xC0]);
+    }
+
+    // movsxd rax, eax
+    pub fn sign_extend_eax(&mut self) {
+        self.emit(&[REX_W, 0x63, 0xC0]);
+    }
+
+    pub fn ret(&mut self) {
+        self.emit(&[0xC3]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encodings() {
+        let mut a = Assembler::new();
+        a.load_rax(10);
+        a.alu_immediate(AluOp::Add, -1);
+        a.shift_cl(ShiftOp::Sar, true);
+        a.sign_extend_eax();
+        a.store_rax(11);
+        a.ret();
+        assert_eq!(
+            a.code(),
+            &[
+                0x48, 0x8B, 0x87, 0x50, 0x00, 0x00, 0x00, // mov rax, [rdi + 0x50]
+                0x48, 0x05, 0xFF, 0xFF, 0xFF, 0xFF, // add rax, -1
+                0xD3, 0xF8, // sar eax, cl
+                0x48, 0x63, 0xC0, // movsxd rax, eax
+                0x48, 0x89, 0x87, 0x58, 0x00, 0x00, 0x00, // mov [rdi + 0x58], rax
+                0xC3, // ret
+            ][..]
+        );
+    }
+}
@@ -0,0 +1,349 @@
+// JIT tier of TraceMachine. Once a trace item has been executed more than a
+// threshold number of times, the leading run of register to register
+// instructions in it is compiled to x86-64 code. Loads, stores, branches and
+// anything else stop compilation, the rest of the trace item keeps running
+// in the interpreter. Native code only ever sees a copy of the general
+// purpose registers, so memory, cycles and pc are still handled in Rust.
+pub mod assembler;
+
+use super::super::{
+    instructions::{extract_opcode, instruction_length, insts, Instruction, Itype, Rtype, Utype},
+    registers::SP,
+    RISCV_GENERAL_REGISTER_NUMBER,
+};
+use super::InstructionCycleFunc;
+use assembler::{AluOp, Assembler, Condition, ShiftOp};
+use memmap::{Mmap, MmapMut};
+
+// The default number of executions after which a trace item is compiled
+pub const DEFAULT_JIT_THRESHOLD: u32 = 64;
+
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+enum Source {
+    Register(usize),
+    Immediate(i32),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+enum Kind {
+    Move,
+    Alu(AluOp),
+    Shift(ShiftOp),
+    Set(Condition),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+struct Operation {
+    kind: Kind,
+    rd: usize,
+    rs1: usize,
+    source: Source,
+    // Operates on the low 32 bits and sign extends the result, as the *W
+    // instructions of RV64 do.
+    word: bool,
+}
+
+fn rtype(kind: Kind, inst: Instruction, word: bool) -> Operation {
+    let i = Rtype(inst);
+    Operation {
+        kind,
+        rd: i.rd(),
+        rs1: i.rs1(),
+        source: Source::Register(i.rs2()),
+        word,
+    }
+}
+
+fn itype(kind: Kind, inst: Instruction, word: bool) -> Operation {
+    let i = Itype(inst);
+    Operation {
+        kind,
+        rd: i.rd(),
+        rs1: i.rs1(),
+        source: Source::Immediate(i.immediate_s()),
+        word,
+    }
+}
+
+// Shift amounts are unsigned, only the bits used by RV64 are kept
+fn shift_itype(shift: ShiftOp, inst: Instruction, word: bool) -> Operation {
+    let i = Itype(inst);
+    Operation {
+        kind: Kind::Shift(shift),
+        rd: i.rd(),
+        rs1: i.rs1(),
+        source: Source::Immediate((i.immediate() & 0x3F) as i32),
+        word,
+    }
+}
+
+fn load_immediate(rd: usize, imm: i32) -> Operation {
+    Operation {
+        kind: Kind::Move,
+        rd,
+        rs1: 0,
+        source: Source::Immediate(imm),
+        word: false,
+    }
+}
+
+// Translates an instruction to an operation of the JIT, following the
+// semantics in instructions::execute for RV64. Ok(None) means the
+// instruction has no effect on registers, while Err(()) means it cannot be
+// compiled.
+fn lower(inst: Instruction) -> Result<Option<Operation>, ()> {
+    let operation = match extract_opcode(inst) {
+        insts::OP_ADD | insts::OP_RVC_ADD => rtype(Kind::Alu(AluOp::Add), inst, false),
+        insts::OP_ADDW | insts::OP_RVC_ADDW => rtype(Kind::Alu(AluOp::Add), inst, true),
+        insts::OP_SUB | insts::OP_RVC_SUB => rtype(Kind::Alu(AluOp::Sub), inst, false),
+        insts::OP_SUBW | insts::OP_RVC_SUBW => rtype(Kind::Alu(AluOp::Sub), inst, true),
+        insts::OP_AND | insts::OP_RVC_AND => rtype(Kind::Alu(AluOp::And), inst, false),
+        insts::OP_OR | insts::OP_RVC_OR => rtype(Kind::Alu(AluOp::Or), inst, false),
+        insts::OP_XOR | insts::OP_RVC_XOR => rtype(Kind::Alu(AluOp::Xor), inst, false),
+        insts::OP_SLL => rtype(Kind::Shift(ShiftOp::Shl), inst, false),
+        insts::OP_SLLW => rtype(Kind::Shift(ShiftOp::Shl), inst, true),
+        insts::OP_SRL => rtype(Kind::Shift(ShiftOp::Shr), inst, false),
+        insts::OP_SRLW => rtype(Kind::Shift(ShiftOp::Shr), inst, true),
+        insts::OP_SRA => rtype(Kind::Shift(ShiftOp::Sar), inst, false),
+        insts::OP_SRAW => rtype(Kind::Shift(ShiftOp::Sar), inst, true),
+        insts::OP_SLT => rtype(Kind::Set(Condition::Less), inst, false),
+        insts::OP_SLTU => rtype(Kind::Set(Condition::Below), inst, false),
+        insts::OP_ADDI | insts::OP_RVC_ADDI => itype(Kind::Alu(AluOp::Add), inst, false),
+        insts::OP_ADDIW | insts::OP_RVC_ADDIW => itype(Kind::Alu(AluOp::Add), inst, true),
+        insts::OP_ANDI | insts::OP_RVC_ANDI => itype(Kind::Alu(AluOp::And), inst, false),
+        insts::OP_ORI => itype(Kind::Alu(AluOp::Or), inst, false),
+        insts::OP_XORI => itype(Kind::Alu(AluOp::Xor), inst, false),
+        insts::OP_SLTI => itype(Kind::Set(Condition::Less), inst, false),
+        insts::OP_SLTIU => itype(Kind::Set(Condition::Below), inst, false),
+        insts::OP_SLLI | insts::OP_RVC_SLLI => shift_itype(ShiftOp::Shl, inst, false),
+        insts::OP_SLLIW => shift_itype(ShiftOp::Shl, inst, true),
+        insts::OP_SRLI | insts::OP_RVC_SRLI => shift_itype(ShiftOp::Shr, inst, false),
+        insts::OP_SRLIW => shift_itype(ShiftOp::Shr, inst, true),
+        insts::OP_SRAI | insts::OP_RVC_SRAI => shift_itype(ShiftOp::Sar, inst, false),
+        insts::OP_SRAIW => shift_itype(ShiftOp::Sar, inst, true),
+        insts::OP_LUI | insts::OP_RVC_LUI | insts::OP_RVC_LI => {
+            let i = Utype(inst);
+            load_immediate(i.rd(), i.immediate_s())
+        }
+        insts::OP_RVC_MV => {
+            let i = Rtype(inst);
+            Operation {
+                kind: Kind::Move,
+                rd: i.rd(),
+                rs1: 0,
+                source: Source::Register(i.rs2()),
+                word: false,
+            }
+        }
+        insts::OP_RVC_ADDI4SPN => {
+            let i = Utype(inst);
+            Operation {
+                kind: Kind::Alu(AluOp::Add),
+                rd: i.rd(),
+                rs1: SP,
+                source: Source::Immediate(i.immediate() as i32),
+                word: false,
+            }
+        }
+        insts::OP_RVC_ADDI16SP => {
+            let i = Itype(inst);
+            Operation {
+                kind: Kind::Alu(AluOp::Add),
+                rd: SP,
+                rs1: SP,
+                source: Source::Immediate(i.immediate_s()),
+                word: false,
+            }
+        }
+        insts::OP_RVC_NOP | insts::OP_RVC_SRLI64 | insts::OP_RVC_SRAI64 | insts::OP_RVC_SLLI64 => {
+            return Ok(None)
+        }
+        _ => return Err(()),
+    };
+    // Writes to zero are discarded, such instructions are hints or nops
+    if operation.rd == 0 {
+        Ok(None)
+    } else {
+        Ok(Some(operation))
+    }
+}
+
+fn emit(a: &mut Assembler, operation: &Operation) {
+    match operation.kind {
+        Kind::Move => match operation.source {
+            Source::Register(rs) => a.load_rax(rs),
+            Source::Immediate(imm) => a.mov_rax_immediate(imm),
+        },
+        Kind::Alu(op) => {
+            a.load_rax(operation.rs1);
+            match operation.source {
+                Source::Register(rs) => a.alu_register(op, rs),
+                Source::Immediate(imm) => a.alu_immediate(op, imm),
+            }
+        }
+        Kind::Shift(op) => {
+            a.load_rax(operation.rs1);
+            match operation.source {
+                Source::Register(rs) => {
+                    a.load_rcx(rs);
+                    a.shift_cl(op, operation.word);
+                }
+                Source::Immediate(imm) => a.shift_immediate(op, operation.word, imm as u8),
+            }
+        }
+        Kind::Set(condition) => {
+            a.load_rax(operation.rs1);
+            match operation.source {
+                Source::Register(rs) => a.alu_register(AluOp::Cmp, rs),
+                Source::Immediate(imm) => a.alu_immediate(AluOp::Cmp, imm),
+            }
+            a.set_condition(condition);
+        }
+    }
+    if operation.word {
+        a.sign_extend_eax();
+    }
+    a.store_rax(operation.rd);
+}
+
+/// Native code compiled from the leading instructions of a trace item. A
+/// block compiled from a trace starting with an unsupported instruction is
+/// empty, which saves trying to compile it again.
+pub struct NativeBlock {
+    code: Option<Mmap>,
+    instruction_count: u8,
+    length: u64,
+    cycles: u64,
+    written: u32,
+}
+
+impl NativeBlock {
+    pub fn compile(
+        instructions: &[Instruction],
+        instruction_cycle_func: Option<&InstructionCycleFunc>,
+    ) -> Self {
+        let mut a = Assembler::new();
+        let mut block = NativeBlock {
+            code: None,
+            instruction_count: 0,
+            length: 0,
+            cycles: 0,
+            written: 0,
+        };
+        for inst in instructions {
+            let operation = match lower(*inst) {
+                Ok(operation) => operation,
+                Err(()) => break,
+            };
+            // Costs overflowing the block total are left to the
+            // interpreter, which charges them one at a time and fails
+            // properly.
+            let cycles = match block
+                .cycles
+                .checked_add(instruction_cycle_func.map(|f| f(*inst)).unwrap_or(0))
+            {
+                Some(cycles) => cycles,
+                None => break,
+            };
+            if let Some(operation) = operation {
+                emit(&mut a, &operation);
+                block.written |= 1 << operation.rd;
+            }
+            block.instruction_count += 1;
+            block.length += u64::from(instruction_length(*inst));
+            block.cycles = cycles;
+        }
+        if block.instruction_count == 0 {
+            return block;
+        }
+        a.ret();
+        match map_executable(a.code()) {
+            Some(code) => block.code = Some(code),
+            // Falls back to the interpreter when executable memory cannot
+            // be allocated.
+            None => block.instruction_count = 0,
+        }
+        block
+    }
+
+    // Number of instructions covered by native code, they always come first
+    // in the trace item.
+    pub fn instruction_count(&self) -> u8 {
+        self.instruction_count
+    }
+
+    // Total length in bytes of the compiled instructions
+    pub fn length(&self) -> u64 {
+        self.length
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    // Bit mask of the registers modified by the block
+    pub fn written(&self) -> u32 {
+        self.written
+    }
+
+    pub fn call(&self, registers: &mut [u64; RISCV_GENERAL_REGISTER_NUMBER]) {
+        if let Some(code) = &self.code {
+            // The code was generated by compile above, it only accesses the
+            // register array passed in rdi and clobbers rax and rcx, both of
+            // which are caller saved in the System V ABI.
+            unsafe {
+                let f: extern "C" fn(*mut u64) = core::mem::transmute(code.as_ptr());
+                f(registers.as_mut_ptr());
+            }
+        }
+    }
+}
+
+fn map_executable(code: &[u8]) -> Option<Mmap> {
+    let mut map = MmapMut::map_anon(code.len()).ok()?;
+    map.copy_from_slice(code);
+    map.make_exec().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::instructions::{i, rvc};
+    use super::*;
+
+    #[test]
+    fn test_compile_and_call() {
+        let instructions = [
+            // addi a0, a0, -1
+            i::factory::<u64>(0xfff5_0513).unwrap(),
+            // slliw a1, a0, 4
+            i::factory::<u64>(0x0045_159b).unwrap(),
+            // sltu a2, a1, a0
+            i::factory::<u64>(0x00a5_b633).unwrap(),
+            // c.mv a3, a1
+            rvc::factory::<u64>(0x86ae).unwrap(),
+            // ld a4, 0(a0) stops compilation
+            i::factory::<u64>(0x0005_3703).unwrap(),
+        ];
+        let block = NativeBlock::compile(&instructions, Some(&|_| 2));
+        assert_eq!(block.instruction_count(), 4);
+        assert_eq!(block.length(), 14);
+        assert_eq!(block.cycles(), 8);
+        assert_eq!(block.written(), 0b1111 << 10);
+
+        let mut registers = [0; RISCV_GENERAL_REGISTER_NUMBER];
+        registers[10] = 0x1_0000_0000;
+        block.call(&mut registers);
+        assert_eq!(registers[10], 0xFFFF_FFFF);
+        assert_eq!(registers[11], 0xFFFF_FFFF_FFFF_FFF0);
+        assert_eq!(registers[12], 0);
+        assert_eq!(registers[13], 0xFFFF_FFFF_FFFF_FFF0);
+
+        let block = NativeBlock::compile(&instructions[4..], None);
+        assert_eq!(block.instruction_count(), 0);
+
+        // Compilation stops before the total cycles would overflow
+        let block = NativeBlock::compile(&instructions, Some(&|_| u64::MAX / 2));
+        assert_eq!(block.instruction_count(), 2);
+        assert_eq!(block.cycles(), u64::MAX - 1);
+    }
+}
@@ -2,23 +2,91 @@
 pub mod aot;
 #[cfg(has_asm)]
 pub mod asm;
+pub mod auxv;
+pub mod commitment;
+pub mod coverage;
+pub mod decoded;
+mod fusion;
+pub mod interrupt;
+pub mod isa;
+#[cfg(has_jit)]
+pub mod jit;
+pub mod overflow;
+pub mod pool;
+pub mod recording;
+pub mod report;
+#[cfg(feature = "symbols")]
+pub mod symbols;
 pub mod trace;
+pub mod view;
 
 use super::debugger::Debugger;
 use super::decoder::{build_imac_decoder, Decoder};
-use super::instructions::{execute, Instruction, Register};
-use super::memory::{round_page_down, round_page_up, Memory, FLAG_EXECUTABLE, FLAG_FREEZED};
-use super::syscalls::Syscalls;
+#[cfg(feature = "fd")]
+use super::instructions::fd::FloatRegisters;
+#[cfg(feature = "v-ext")]
+use super::instructions::v::VectorRegisters;
+use super::instructions::{
+    a::Reservation, call_depth_change, disassemble, disassemble_block, execute, extract_opcode,
+    instruction_length, is_basic_block_end_instruction, memory_access, memory_op, zicntr::Counters,
+    Instruction, MemoryOp, Register,
+};
+use super::memory::{
+    diff, round_page_down, round_page_up, watchpoint::WatchpointKind, wxorx::WXorXMemory, Memory,
+    FLAG_EXECUTABLE, FLAG_FREEZED,
+};
+#[cfg(feature = "serialize")]
+use super::snapshot::CoreMachineState;
+use super::snapshot::{resume_memory, snapshot_memory, Snapshot};
+#[cfg(feature = "async")]
+use super::syscalls::AsyncSyscalls;
+#[cfg(feature = "std")]
+use super::syscalls::DebugSyscall;
+use super::syscalls::{
+    HeapSyscalls, HostFn, MprotectSyscall, RandomSyscall, SyscallFallback, SyscallHandler,
+    SyscallRecord, SyscallRegistry, Syscalls,
+};
 use super::{
-    registers::{A0, A7, REGISTER_ABI_NAMES, SP},
-    Error, DEFAULT_STACK_SIZE, RISCV_GENERAL_REGISTER_NUMBER, RISCV_MAX_MEMORY,
+    registers::{A0, A1, A2, A3, A4, A5, A7, RA, REGISTER_ABI_NAMES, SP},
+    ElfSegmentError, Error, Fault, DEFAULT_STACK_SIZE, RISCV_GENERAL_REGISTER_NUMBER,
+    RISCV_PAGESIZE,
+};
+use alloc::{
+    boxed::Box,
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec,
+    vec::Vec,
 };
+use auxv::{initialize_linux_stack, program_auxv};
 use bytes::Bytes;
+use ckb_vm_definitions::instructions::{MAXIMUM_OPCODE, OP_CUSTOM, OP_EBREAK, OP_ECALL};
+use commitment::{CommitmentGranularity, StateCommitment, StateHashFunc};
+use core::fmt::{self, Display};
+use decoded::DecodedProgram;
+use goblin::elf::header::ET_DYN;
 use goblin::elf::program_header::{PF_R, PF_W, PF_X, PT_LOAD};
+use goblin::elf::reloc::{R_RISCV_NONE, R_RISCV_RELATIVE};
 use goblin::elf::{Elf, Header};
-use std::fmt::{self, Display};
+use interrupt::InterruptHandle;
+use isa::MachineIsa;
+use overflow::{signed_overflow, OverflowRecord};
+use recording::InstructionRecording;
+use report::ResourceReport;
+#[cfg(feature = "serialize")]
+use serde::{de::Error as DeError, Deserialize, Deserializer};
+#[cfg(all(feature = "std", not(target_family = "wasm")))]
+use std::time::Instant;
+#[cfg(feature = "symbols")]
+use symbols::{Symbol, Symbols};
+use view::MachineSnapshotView;
 
-fn elf_bits(header: &Header) -> Option<u8> {
+// Basic blocks run_with_deadline executes between two reads of the clock
+pub const DEADLINE_CHECK_INTERVAL: u64 = 64;
+
+pub(crate) fn elf_bits(header: &Header) -> Option<u8> {
     // This is documented in ELF specification, we are exacting ELF file
     // class part here.
     // Right now we are only supporting 32 and 64 bits, in the future we
@@ -31,7 +99,7 @@ fn elf_bits(header: &Header) -> Option<u8> {
 }
 
 // Converts goblin's ELF flags into RISC-V flags
-fn convert_flags(p_flags: u32) -> Result<u8, Error> {
+pub(crate) fn convert_flags(p_flags: u32) -> Result<u8, Error> {
     let readable = p_flags & PF_R != 0;
     let writable = p_flags & PF_W != 0;
     let executable = p_flags & PF_X != 0;
@@ -45,6 +113,119 @@ fn convert_flags(p_flags: u32) -> Result<u8, Error> {
     }
 }
 
+// Checks PT_LOAD segments before any of them is loaded, so a rejected
+// program leaves memory untouched. Returns the page aligned range each
+// segment occupies, indexed like the program headers.
+fn check_segments(elf: &Elf, load_bias: u64) -> Result<Vec<Option<(u64, u64)>>, Error> {
+    let mut ranges: Vec<Option<(u64, u64)>> = vec![];
+    for (index, program_header) in elf.program_headers.iter().enumerate() {
+        if program_header.p_type != PT_LOAD {
+            ranges.push(None);
+            continue;
+        }
+        let segment_error = |reason| Error::ElfSegment { index, reason };
+        if program_header.p_filesz > program_header.p_memsz {
+            return Err(segment_error(ElfSegmentError::FileSizeExceedsMemorySize));
+        }
+        let align = program_header.p_align;
+        if align > 1 {
+            if !align.is_power_of_two() {
+                return Err(segment_error(ElfSegmentError::InvalidAlignment));
+            }
+            if program_header.p_vaddr % align != program_header.p_offset % align {
+                return Err(segment_error(ElfSegmentError::Misaligned));
+            }
+        }
+        let vaddr = program_header
+            .p_vaddr
+            .checked_add(load_bias)
+            .ok_or(Error::OutOfBound)?;
+        let end = vaddr
+            .checked_add(program_header.p_memsz)
+            .and_then(|end| end.checked_add(RISCV_PAGESIZE as u64 - 1))
+            .ok_or(Error::OutOfBound)?;
+        let range = (round_page_down(vaddr), round_page_down(end));
+        for (other, other_range) in ranges.iter().enumerate() {
+            if let Some((start, end)) = other_range {
+                if range.0 < *end && *start < range.1 {
+                    return Err(segment_error(ElfSegmentError::Overlap(other)));
+                }
+            }
+        }
+        ranges.push(Some(range));
+    }
+    Ok(ranges)
+}
+
+// Exact address ranges of PT_LOAD segments once loaded, as opposed to the
+// pages they occupy.
+pub(crate) fn segment_ranges(program: &Bytes, load_bias: u64) -> Result<Vec<(u64, u64)>, Error> {
+    let elf = Elf::parse(program).map_err(|_e| Error::ParseError)?;
+    let load_bias = if elf.header.e_type == ET_DYN {
+        load_bias
+    } else {
+        0
+    };
+    elf.program_headers
+        .iter()
+        .filter(|header| header.p_type == PT_LOAD)
+        .map(|header| {
+            let start = header.p_vaddr.wrapping_add(load_bias);
+            let end = start.checked_add(header.p_memsz).ok_or(Error::OutOfBound)?;
+            Ok((start, end))
+        })
+        .collect()
+}
+
+// Collects dynamic relocations as (address, addend) pairs, addend is None
+// for relocations without an explicit addend, in which case the value
+// stored at the address is used. There is no dynamic linker to resolve
+// symbols, so only R_RISCV_RELATIVE relocations are supported.
+fn relative_relocations(elf: &Elf) -> Result<Vec<(u64, Option<i64>)>, Error> {
+    let mut result = vec![];
+    for reloc in elf
+        .dynrelas
+        .iter()
+        .chain(elf.dynrels.iter())
+        .chain(elf.pltrelocs.iter())
+    {
+        match reloc.r_type {
+            R_RISCV_NONE => (),
+            R_RISCV_RELATIVE => result.push((reloc.r_offset, reloc.r_addend)),
+            _ => return Err(Error::Unimplemented),
+        }
+    }
+    Ok(result)
+}
+
+// Applies a relative relocation to a word at offset of segment data
+fn relocate(
+    data: &mut [u8],
+    offset: usize,
+    addend: Option<i64>,
+    load_bias: u64,
+    bits: u8,
+) -> Result<(), Error> {
+    let width = usize::from(bits / 8);
+    let end = offset.checked_add(width).ok_or(Error::OutOfBound)?;
+    if end > data.len() {
+        return Err(Error::Unimplemented);
+    }
+    let word = &mut data[offset..end];
+    let addend = match addend {
+        Some(addend) => addend as u64,
+        None => word
+            .iter()
+            .rev()
+            .fold(0, |value, byte| (value << 8) | u64::from(*byte)),
+    };
+    let value = addend.wrapping_add(load_bias);
+    for (i, byte) in word.iter_mut().enumerate() {
+        *byte = (value >> (i * 8)) as u8;
+    }
+    Ok(())
+}
+
 /// This is the core part of RISC-V that only deals with data part, it
 /// is extracted from Machine so we can handle lifetime logic in dynamic
 /// syscall support.
@@ -65,6 +246,42 @@ pub trait CoreMachine {
 pub trait Machine: CoreMachine {
     fn ecall(&mut self) -> Result<(), Error>;
     fn ebreak(&mut self) -> Result<(), Error>;
+
+    // Machines that do not track reservations reject LR and SC instructions,
+    // other A extension instructions work on any machine.
+    fn reservation_mut(&mut self) -> Option<&mut Reservation> {
+        None
+    }
+
+    // Machines without a floating point register file reject all F and D
+    // instructions.
+    #[cfg(feature = "fd")]
+    fn float_registers_mut(&mut self) -> Option<&mut FloatRegisters> {
+        None
+    }
+
+    // Likewise, machines without a vector register file reject all V
+    // instructions.
+    #[cfg(feature = "v-ext")]
+    fn vector_registers_mut(&mut self) -> Option<&mut VectorRegisters> {
+        None
+    }
+
+    // Machines without counters reject instructions reading them
+    fn counters(&self) -> Option<Counters> {
+        None
+    }
+
+    // Called by fence.i, machines caching decoded code drop it so code
+    // stored before the fence is fetched again.
+    fn fence_i(&mut self) {}
+
+    // Executes a custom instruction decoded by a factory supplied by the
+    // embedder, see instructions::custom. Returns the next pc when the
+    // instruction jumps. Machines without handlers reject them.
+    fn execute_custom(&mut self, _id: u8, bits: u32) -> Result<Option<Self::REG>, Error> {
+        Err(Error::InvalidInstruction(bits))
+    }
 }
 
 /// This traits extend on top of CoreMachine by adding additional support
@@ -78,6 +295,9 @@ pub trait SupportMachine: CoreMachine {
     fn cycles(&self) -> u64;
     fn set_cycles(&mut self, cycles: u64);
     fn max_cycles(&self) -> Option<u64>;
+    // A max cycles value of 0 means there is no limit on execution cycles.
+    // Implementations with a limit fixed at construction ignore it.
+    fn set_max_cycles(&mut self, _max_cycles: u64) {}
 
     fn running(&self) -> bool;
     fn set_running(&mut self, running: bool);
@@ -89,7 +309,7 @@ pub trait SupportMachine: CoreMachine {
             .ok_or(Error::InvalidCycles)?;
         if let Some(max_cycles) = self.max_cycles() {
             if new_cycles > max_cycles {
-                return Err(Error::InvalidCycles);
+                return Err(Error::CyclesExceeded);
             }
         }
         self.set_cycles(new_cycles);
@@ -97,17 +317,45 @@ pub trait SupportMachine: CoreMachine {
     }
 
     fn load_elf(&mut self, program: &Bytes, update_pc: bool) -> Result<u64, Error> {
+        self.load_elf_with_bias(program, update_pc, 0)
+    }
+
+    // Position independent executables(ET_DYN) are loaded with their
+    // addresses shifted by load_bias, and their relative relocations are
+    // applied. Other ELF files are always loaded at the addresses they are
+    // linked to, load_bias is ignored for them.
+    fn load_elf_with_bias(
+        &mut self,
+        program: &Bytes,
+        update_pc: bool,
+        load_bias: u64,
+    ) -> Result<u64, Error> {
         let elf = Elf::parse(program).map_err(|_e| Error::ParseError)?;
         let bits = elf_bits(&elf.header).ok_or(Error::InvalidElfBits)?;
         if bits != Self::REG::BITS {
             return Err(Error::InvalidElfBits);
         }
+        let load_bias = if elf.header.e_type == ET_DYN {
+            load_bias
+        } else {
+            0
+        };
+        if round_page_down(load_bias) != load_bias {
+            return Err(Error::Unaligned);
+        }
+        let ranges = check_segments(&elf, load_bias)?;
+        let relocations = relative_relocations(&elf)?;
+        let mut applied_relocations = 0;
         let mut bytes: u64 = 0;
-        for program_header in &elf.program_headers {
-            if program_header.p_type == PT_LOAD {
-                let aligned_start = round_page_down(program_header.p_vaddr);
-                let padding_start = program_header.p_vaddr.wrapping_sub(aligned_start);
-                let size = round_page_up(program_header.p_memsz.wrapping_add(padding_start));
+        for (program_header, range) in elf.program_headers.iter().zip(ranges) {
+            if let Some((aligned_start, end)) = range {
+                let vaddr = program_header.p_vaddr.wrapping_add(load_bias);
+                let padding_start = vaddr - aligned_start;
+                let size = end - aligned_start;
+                let memory_size = self.memory().memory_size() as u64;
+                if end > memory_size {
+                    return Err(Error::MemorySizeExceeded(end, memory_size));
+                }
                 let slice_start = program_header.p_offset;
                 let slice_end = program_header
                     .p_offset
@@ -115,26 +363,77 @@ pub trait SupportMachine: CoreMachine {
                 if slice_start > slice_end || slice_end > program.len() as u64 {
                     return Err(Error::OutOfBound);
                 }
+                let mut source = program.slice(slice_start as usize, slice_end as usize);
+                let segment_end = program_header.p_vaddr.wrapping_add(program_header.p_filesz);
+                let patches: Vec<&(u64, Option<i64>)> = relocations
+                    .iter()
+                    .filter(|(address, _)| {
+                        *address >= program_header.p_vaddr && *address < segment_end
+                    })
+                    .collect();
+                if !patches.is_empty() {
+                    applied_relocations += patches.len();
+                    let mut data = source.to_vec();
+                    for (address, addend) in patches {
+                        let offset = (address - program_header.p_vaddr) as usize;
+                        relocate(&mut data, offset, *addend, load_bias, bits)?;
+                    }
+                    source = Bytes::from(data);
+                }
+                // Pages are filled with zeros around the file data, which
+                // covers both the padding before vaddr and bss.
                 self.memory_mut().init_pages(
                     aligned_start,
                     size,
                     convert_flags(program_header.p_flags)?,
-                    Some(program.slice(slice_start as usize, slice_end as usize)),
+                    Some(source),
                     padding_start,
                 )?;
-                self.memory_mut()
-                    .store_byte(aligned_start, padding_start, 0)?;
                 bytes = bytes
                     .checked_add(slice_end - slice_start)
                     .ok_or(Error::Unexpected)?;
             }
         }
+        // Relocations targeting memory not backed by file data, such as
+        // bss, are not supported.
+        if applied_relocations != relocations.len() {
+            return Err(Error::Unimplemented);
+        }
         if update_pc {
-            self.set_pc(Self::REG::from_u64(elf.header.e_entry));
+            let entry = elf
+                .header
+                .e_entry
+                .checked_add(load_bias)
+                .ok_or(Error::OutOfBound)?;
+            self.set_pc(Self::REG::from_u64(entry));
         }
         Ok(bytes)
     }
 
+    // Loads a raw binary without ELF headers, such as a hand written test
+    // kernel or fuzzer input, at addr and jumps to entry. The pages covering
+    // the binary are executable and frozen like ELF code, so the binary
+    // cannot write to itself. Returns the number of bytes loaded.
+    fn load_flat_binary(&mut self, addr: u64, binary: &Bytes, entry: u64) -> Result<u64, Error> {
+        let end = addr
+            .checked_add(binary.len() as u64)
+            .ok_or(Error::OutOfBound)?;
+        let memory_size = self.memory().memory_size() as u64;
+        if end > memory_size {
+            return Err(Error::MemorySizeExceeded(end, memory_size));
+        }
+        let aligned_start = round_page_down(addr);
+        self.memory_mut().init_pages(
+            aligned_start,
+            round_page_up(end) - aligned_start,
+            FLAG_EXECUTABLE | FLAG_FREEZED,
+            Some(binary.clone()),
+            addr - aligned_start,
+        )?;
+        self.set_pc(Self::REG::from_u64(entry));
+        Ok(binary.len() as u64)
+    }
+
     fn initialize_stack(
         &mut self,
         args: &[Bytes],
@@ -226,6 +525,14 @@ impl<R: Register, M: Memory<R>> SupportMachine for DefaultCoreMachine<R, M> {
         self.max_cycles
     }
 
+    fn set_max_cycles(&mut self, max_cycles: u64) {
+        self.max_cycles = if max_cycles > 0 {
+            Some(max_cycles)
+        } else {
+            None
+        };
+    }
+
     fn running(&self) -> bool {
         self.running
     }
@@ -235,20 +542,191 @@ impl<R: Register, M: Memory<R>> SupportMachine for DefaultCoreMachine<R, M> {
     }
 }
 
+impl<R: Register, M: Memory<R>> DefaultCoreMachine<R, M> {
+    // Creates a machine on top of an existing memory, e.g. a CowMemory
+    // sharing its program image with other machines.
+    pub fn new_with_memory(memory: M, max_cycles: u64) -> Self {
+        let mut machine = Self {
+            registers: Default::default(),
+            pc: R::default(),
+            memory,
+            cycles: 0,
+            max_cycles: None,
+            running: false,
+        };
+        machine.set_max_cycles(max_cycles);
+        machine
+    }
+
+    #[cfg(feature = "serialize")]
+    pub fn state(&mut self) -> Result<CoreMachineState, Error> {
+        Ok(CoreMachineState {
+            bits: R::BITS,
+            pc: self.pc.to_u64(),
+            registers: self.registers.iter().map(|r| r.to_u64()).collect(),
+            cycles: self.cycles,
+            max_cycles: self.max_cycles,
+            memory_size: self.memory.memory_size() as u64,
+            pages: snapshot_memory(&mut self.memory)?,
+        })
+    }
+
+    // Restores a state taken by state, memory is resized first when the
+    // sizes differ, which discards its content. As with Snapshot, frozen
+    // pages must match the state exactly.
+    #[cfg(feature = "serialize")]
+    pub fn restore_state(&mut self, state: &CoreMachineState) -> Result<(), Error> {
+        if state.bits != R::BITS || state.registers.len() != self.registers.len() {
+            return Err(Error::InvalidSnapshot);
+        }
+        if state.memory_size != self.memory.memory_size() as u64 {
+            self.memory.set_memory_size(state.memory_size as usize)?;
+        }
+        resume_memory(&mut self.memory, &state.pages)?;
+        for (register, value) in self.registers.iter_mut().zip(state.registers.iter()) {
+            *register = R::from_u64(*value);
+        }
+        self.pc = R::from_u64(state.pc);
+        self.cycles = state.cycles;
+        self.max_cycles = state.max_cycles;
+        Ok(())
+    }
+}
+
 impl<R: Register, M: Memory<R> + Default> DefaultCoreMachine<R, M> {
     pub fn new_with_max_cycles(max_cycles: u64) -> Self {
-        Self {
-            max_cycles: Some(max_cycles),
-            ..Default::default()
-        }
+        let mut machine = Self::default();
+        machine.set_max_cycles(max_cycles);
+        machine
     }
 
     pub fn take_memory(self) -> M {
         self.memory
     }
+
+    #[cfg(feature = "serialize")]
+    pub fn from_state(state: &CoreMachineState) -> Result<Self, Error> {
+        let mut machine = Self::default();
+        machine.restore_state(state)?;
+        Ok(machine)
+    }
+}
+
+#[cfg(feature = "serialize")]
+impl<'de, R: Register, M: Memory<R> + Default> Deserialize<'de> for DefaultCoreMachine<R, M> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let state = CoreMachineState::deserialize(deserializer)?;
+        Self::from_state(&state).map_err(DeError::custom)
+    }
+}
+
+// Why a run stopped
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub enum ExitReason {
+    // The program stopped the machine, normally through the exit syscall
+    Exit,
+    CyclesExceeded,
+    // Any other error raised while running, such as an invalid instruction
+    Trap(Error),
+}
+
+/// Detailed outcome of running a machine, see `DefaultMachine::run_with_result`.
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub struct RunResult {
+    // Exit code set by the program, only meaningful with ExitReason::Exit
+    pub exit_code: i8,
+    pub cycles: u64,
+    // See Memory::touched_memory, since memory is never released this is
+    // also the maximum reached while running.
+    pub touched_memory: usize,
+    pub reason: ExitReason,
+    // Context of the error that stopped the run, for machines reporting
+    // it, see DefaultMachine::last_fault.
+    pub fault: Option<Fault>,
+}
+
+impl RunResult {
+    pub(crate) fn new<M: SupportMachine>(machine: &M, result: Result<i8, Error>) -> Self {
+        let (exit_code, reason) = match result {
+            Ok(exit_code) => (exit_code, ExitReason::Exit),
+            Err(Error::CyclesExceeded) => (0, ExitReason::CyclesExceeded),
+            Err(e) => (0, ExitReason::Trap(e)),
+        };
+        Self {
+            exit_code,
+            cycles: machine.cycles(),
+            touched_memory: machine.memory().touched_memory(),
+            reason,
+            fault: None,
+        }
+    }
+
+    // Converts to the plain result returned by run
+    pub fn into_result(self) -> Result<i8, Error> {
+        match self.reason {
+            ExitReason::Exit => Ok(self.exit_code),
+            ExitReason::CyclesExceeded => Err(Error::CyclesExceeded),
+            ExitReason::Trap(e) => Err(e),
+        }
+    }
+}
+
+// Outcome of DefaultMachine::run_resumable
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub enum RunState {
+    // The program stopped the machine with this exit code
+    Exited(i8),
+    // Max cycles was reached right before an instruction, the host can raise
+    // it and call run_resumable again to continue from there.
+    Paused,
+    // The instruction quantum was used up, calling run_resumable again
+    // continues with the next instruction. See
+    // DefaultMachineBuilder::yield_every. Also returned when a syscall would
+    // block, the ecall is then executed again.
+    Yielded,
+}
+
+/// Where load_program placed a program, returned by it and kept on the
+/// machine afterwards, see DefaultMachine::program_metadata.
+#[derive(Debug, PartialEq, Clone, Eq, Default)]
+pub struct ProgramMetadata {
+    // Address execution starts at, load bias included
+    pub entry: u64,
+    // Memory ranges of PT_LOAD segments as (start, end), bss included
+    pub segments: Vec<(u64, u64)>,
+    // First page boundary after all segments, where a heap managed by brk
+    // would start
+    pub program_break: u64,
+    // End of the stack, the stack grows down from here
+    pub stack_top: u64,
+    // Bytes written by the loader, file data of segments plus the initial
+    // stack, which hosts may charge cycles for
+    pub bytes: u64,
 }
 
 pub type InstructionCycleFunc = dyn Fn(Instruction) -> u64;
+// Cycles charged for the memory accessed by an instruction, on top of the
+// instruction cycles. Receives the kind of access and the bytes touched.
+pub type MemoryCycleFunc = dyn Fn(MemoryOp, u64) -> u64;
+// Hook invoked right before each instruction is executed, with the pc the
+// instruction is located at, the decoded instruction and the machine state.
+pub type InstructionHookFunc<'a, Mac> = dyn FnMut(u64, Instruction, &Mac) + 'a;
+// Hook invoked each time cycles are added, with the cycles just consumed
+// and the total cycles so far.
+pub type CyclesHookFunc<'a> = dyn FnMut(u64, u64) + 'a;
+// Handler invoked when the program exits through the exit syscall, with
+// the exit code from A0. The code returned becomes the exit code of the
+// machine, an error stops the machine with that error instead.
+pub type ExitHandler<'a, Mac> = dyn FnMut(&mut Mac, i8) -> Result<i8, Error> + 'a;
+// Handler executing custom instructions of one id, receiving the raw
+// instruction bits. Returning a pc makes the machine jump there, otherwise
+// execution continues with the next instruction.
+pub type CustomInstructionHandler<'a, Mac> =
+    dyn FnMut(&mut Mac, u32) -> Result<Option<u64>, Error> + 'a;
+// Validator invoked by load_program once a program is in memory, with the
+// program as given and where it was placed. An error fails the load
+// before any instruction of the program runs.
+pub type ProgramValidator<'a> = dyn FnMut(&[u8], &ProgramMetadata) -> Result<(), Error> + 'a;
 
 #[derive(Default)]
 pub struct DefaultMachine<'a, Inner> {
@@ -259,8 +737,65 @@ pub struct DefaultMachine<'a, Inner> {
     // with Box solution for simplicity now. Later if this becomes an issue,
     // we can change to static dispatch.
     instruction_cycle_func: Option<Box<InstructionCycleFunc>>,
+    memory_cycle_func: Option<Box<MemoryCycleFunc>>,
     debugger: Option<Box<dyn Debugger<Inner> + 'a>>,
     syscalls: Vec<Box<dyn Syscalls<Inner> + 'a>>,
+    syscall_registry: SyscallRegistry<'a, Inner>,
+    on_instruction: Option<Box<InstructionHookFunc<'a, Inner>>>,
+    on_cycles: Option<Box<CyclesHookFunc<'a>>>,
+    exit_handler: Option<Box<ExitHandler<'a, Inner>>>,
+    program_validator: Option<Box<ProgramValidator<'a>>>,
+    custom_instructions: BTreeMap<u8, Box<CustomInstructionHandler<'a, Inner>>>,
+    // Ecalls made so far, only recorded when the syscall log is enabled
+    syscall_log: Option<Vec<SyscallRecord>>,
+    // Completed ecalls per syscall number, for resource reports
+    syscall_counts: BTreeMap<u64, u64>,
+    // Arithmetic overflows so far, only recorded with checked arithmetic
+    overflow_log: Option<Vec<OverflowRecord>>,
+    // Instructions executed per opcode, only counted with opcode stats
+    opcode_stats: Option<Vec<u64>>,
+    // Executed instruction stream, only recorded when enabled
+    instruction_recording: Option<InstructionRecording>,
+    // Rolling hash of executed transitions, only computed when enabled
+    state_commitment: Option<StateCommitment>,
+    #[cfg(feature = "fd")]
+    float_registers: FloatRegisters,
+    #[cfg(feature = "v-ext")]
+    vector_registers: VectorRegisters,
+    reservation: Reservation,
+    load_bias: u64,
+    pause_on_limit: bool,
+    yield_every: Option<u64>,
+    // Inverted so derived Default keeps RVC enabled
+    reject_rvc: bool,
+    decoded_program: Option<Arc<DecodedProgram>>,
+    fixed_counters: bool,
+    instructions: u64,
+    max_instructions: Option<u64>,
+    #[cfg(feature = "async")]
+    async_ecall: AsyncEcall,
+    // Calls minus returns so far, only tracked with a maximum call depth
+    call_depth: u64,
+    max_call_depth: Option<u64>,
+    // Pc of the instruction paused or blocked last, which runs again on
+    // resume without invoking the instruction hook a second time
+    retried_pc: Option<u64>,
+    // Shared with the handles given out by interrupt_handle
+    interrupt: InterruptHandle,
+    stack_guard_size: u64,
+    // Range watched by the stack guard, set up by load_program
+    stack_guard: Option<(u64, u64)>,
+    preloads: Vec<(u64, Bytes)>,
+    // Applied by load_program once the program and its stack are set up
+    initial_registers: Option<[u64; RISCV_GENERAL_REGISTER_NUMBER]>,
+    entry_override: Option<u64>,
+    // Programs are checked against it by load_program when set
+    isa: Option<MachineIsa>,
+    // Symbols of the program loaded by load_program
+    #[cfg(feature = "symbols")]
+    symbols: Symbols,
+    program_metadata: Option<ProgramMetadata>,
+    last_fault: Option<Fault>,
     exit_code: i8,
 }
 
@@ -306,6 +841,10 @@ impl<Inner: SupportMachine> SupportMachine for DefaultMachine<'_, Inner> {
         self.inner.max_cycles()
     }
 
+    fn set_max_cycles(&mut self, max_cycles: u64) {
+        self.inner.set_max_cycles(max_cycles)
+    }
+
     fn running(&self) -> bool {
         self.inner.running()
     }
@@ -313,44 +852,176 @@ impl<Inner: SupportMachine> SupportMachine for DefaultMachine<'_, Inner> {
     fn set_running(&mut self, running: bool) {
         self.inner.set_running(running);
     }
+
+    fn add_cycles(&mut self, cycles: u64) -> Result<(), Error> {
+        self.inner.add_cycles(cycles)?;
+        self.notify_cycles(cycles);
+        Ok(())
+    }
+}
+
+// Progress of an ecall left to async syscalls by run_async. The ecall
+// fails with Error::WouldBlock until run_async stores the result of the
+// future, it is then executed again and completes with that result.
+#[cfg(feature = "async")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+enum AsyncEcall {
+    // Not running run_async
+    #[default]
+    Disabled,
+    Idle,
+    Waiting,
+    Done(Result<bool, Error>),
 }
 
 impl<Inner: SupportMachine> Machine for DefaultMachine<'_, Inner> {
     fn ecall(&mut self) -> Result<(), Error> {
+        // Syscalls might modify memory behind the program's back, the
+        // reservation is conservatively released here.
+        self.reservation.clear();
         let code = self.registers()[A7].to_u64();
+        let record = self.syscall_log.as_ref().map(|_| SyscallRecord {
+            number: code,
+            args: [A0, A1, A2, A3, A4, A5].map(|i| self.registers()[i].to_u64()),
+            result: Ok(0),
+            cycles: self.cycles(),
+        });
+        let mut result = self.add_cycles(self.syscall_registry.cycles(code));
+        if result.is_ok() {
+            // Syscalls charge their cycles on the inner machine, those are
+            // reported once the syscall returns.
+            let cycles = self.cycles();
+            result = self.dispatch_ecall(code);
+            let consumed = self.cycles().saturating_sub(cycles);
+            if consumed > 0 {
+                self.notify_cycles(consumed);
+            }
+        }
+        if result.is_ok() {
+            *self.syscall_counts.entry(code).or_insert(0) += 1;
+        }
+        if let Some(mut record) = record {
+            record.result = result.map(|_| self.registers()[A0].to_u64());
+            if let Some(log) = &mut self.syscall_log {
+                log.push(record);
+            }
+        }
+        result
+    }
+
+    fn ebreak(&mut self) -> Result<(), Error> {
+        if let Some(debugger) = &mut self.debugger {
+            debugger.ebreak(&mut self.inner)
+        } else {
+            // Unlike ecall, the default behavior of an EBREAK operation is
+            // a dummy one.
+            Ok(())
+        }
+    }
+
+    fn reservation_mut(&mut self) -> Option<&mut Reservation> {
+        Some(&mut self.reservation)
+    }
+
+    #[cfg(feature = "fd")]
+    fn float_registers_mut(&mut self) -> Option<&mut FloatRegisters> {
+        Some(&mut self.float_registers)
+    }
+
+    #[cfg(feature = "v-ext")]
+    fn vector_registers_mut(&mut self) -> Option<&mut VectorRegisters> {
+        Some(&mut self.vector_registers)
+    }
+
+    fn execute_custom(&mut self, id: u8, bits: u32) -> Result<Option<Self::REG>, Error> {
+        match self.custom_instructions.get_mut(&id) {
+            Some(handler) => Ok(handler(&mut self.inner, bits)?.map(Inner::REG::from_u64)),
+            None => Err(Error::InvalidInstruction(bits)),
+        }
+    }
+
+    fn counters(&self) -> Option<Counters> {
+        if self.fixed_counters {
+            return Some(Counters::default());
+        }
+        Some(Counters {
+            cycle: self.cycles(),
+            time: self.cycles(),
+            instret: self.instructions,
+        })
+    }
+}
+
+impl<Inner: SupportMachine> DefaultMachine<'_, Inner> {
+    fn dispatch_ecall(&mut self, code: u64) -> Result<(), Error> {
         match code {
             93 => {
                 // exit
-                self.exit_code = self.registers()[A0].to_i8();
                 self.set_running(false);
+                let exit_code = self.registers()[A0].to_i8();
+                self.exit_code = match &mut self.exit_handler {
+                    Some(exit_handler) => exit_handler(&mut self.inner, exit_code)?,
+                    None => exit_code,
+                };
                 Ok(())
             }
             _ => {
+                // Handlers registered by number take precedence over
+                // Syscalls modules, the fallback is only consulted when
+                // neither of them processes the syscall.
+                if self.syscall_registry.dispatch(code, &mut self.inner)? {
+                    return Ok(());
+                }
                 for syscall in &mut self.syscalls {
                     let processed = syscall.ecall(&mut self.inner)?;
                     if processed {
                         return Ok(());
                     }
                 }
+                #[cfg(feature = "async")]
+                match self.async_ecall {
+                    AsyncEcall::Disabled => (),
+                    AsyncEcall::Idle => {
+                        self.async_ecall = AsyncEcall::Waiting;
+                        return Err(Error::WouldBlock);
+                    }
+                    AsyncEcall::Waiting => return Err(Error::WouldBlock),
+                    AsyncEcall::Done(result) => {
+                        self.async_ecall = AsyncEcall::Idle;
+                        if result? {
+                            return Ok(());
+                        }
+                    }
+                }
+                if self
+                    .syscall_registry
+                    .dispatch_fallback(code, &mut self.inner)?
+                {
+                    return Ok(());
+                }
                 Err(Error::InvalidEcall(code))
             }
         }
     }
-
-    fn ebreak(&mut self) -> Result<(), Error> {
-        if let Some(debugger) = &mut self.debugger {
-            debugger.ebreak(&mut self.inner)
-        } else {
-            // Unlike ecall, the default behavior of an EBREAK operation is
-            // a dummy one.
-            Ok(())
-        }
-    }
 }
 
-impl<Inner: CoreMachine> Display for DefaultMachine<'_, Inner> {
+// Instructions shown when describing the basic block at pc
+const DESCRIBED_INSTRUCTIONS: usize = 32;
+
+// Registers with their ABI names, followed by counters, the last fault and
+// the basic block at pc when the machine has a decoded program covering
+// it, see DefaultMachine::describe to read it from memory instead.
+impl<Inner: SupportMachine> Display for DefaultMachine<'_, Inner> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        writeln!(f, "pc  : 0x{:16X}", self.pc().to_u64())?;
+        let pc = self.pc().to_u64();
+        write!(f, "pc  : 0x{:16X}", pc)?;
+        #[cfg(feature = "symbols")]
+        {
+            if let Some(symbol) = self.symbols.lookup(pc) {
+                write!(f, " <{}>", symbol)?;
+            }
+        }
+        writeln!(f)?;
         for (i, name) in REGISTER_ABI_NAMES.iter().enumerate() {
             write!(f, "{:4}: 0x{:16X}", name, self.registers()[i].to_u64())?;
             if (i + 1) % 4 == 0 {
@@ -359,117 +1030,1800 @@ impl<Inner: CoreMachine> Display for DefaultMachine<'_, Inner> {
                 write!(f, " ")?;
             }
         }
+        write!(
+            f,
+            "cycles: {}, instructions: {}, ",
+            self.cycles(),
+            self.instructions
+        )?;
+        if self.running() {
+            writeln!(f, "running")?;
+        } else {
+            writeln!(f, "exit code: {}", self.exit_code)?;
+        }
+        if let Some(fault) = &self.last_fault {
+            writeln!(f, "fault: {}", fault)?;
+        }
+        if let Some(program) = &self.decoded_program {
+            let mut current_pc = pc;
+            for _ in 0..DESCRIBED_INSTRUCTIONS {
+                let instruction = match program.get(current_pc) {
+                    Some((_, instruction)) => instruction,
+                    None => break,
+                };
+                writeln!(f, "{:#x}: {}", current_pc, disassemble(instruction))?;
+                if is_basic_block_end_instruction(instruction) {
+                    break;
+                }
+                current_pc += u64::from(instruction_length(instruction));
+            }
+        }
         Ok(())
     }
 }
 
+impl<Inner: SupportMachine> fmt::Debug for DefaultMachine<'_, Inner> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
 impl<'a, Inner: SupportMachine> DefaultMachine<'a, Inner> {
-    pub fn load_program(&mut self, program: &Bytes, args: &[Bytes]) -> Result<u64, Error> {
-        let elf_bytes = self.load_elf(program, true)?;
+    pub fn load_program(
+        &mut self,
+        program: &Bytes,
+        args: &[Bytes],
+    ) -> Result<ProgramMetadata, Error> {
+        self.load_program_with_stack(program, |machine, stack_start| {
+            machine.initialize_stack(args, stack_start, DEFAULT_STACK_SIZE as u64)
+        })
+    }
+
+    // Same as load_program, but the initial stack follows the Linux RISC-V
+    // ABI expected by newlib or musl programs: argv and envp are null
+    // terminated and followed by the auxiliary vector, see machine::auxv.
+    // The 16 bytes referenced by AT_RANDOM are provided by the host so
+    // execution stays deterministic.
+    pub fn load_program_with_env(
+        &mut self,
+        program: &Bytes,
+        args: &[Bytes],
+        envs: &[Bytes],
+        random: &[u8; 16],
+    ) -> Result<ProgramMetadata, Error> {
+        let auxv = program_auxv(program, self.load_bias)?;
+        self.load_program_with_stack(program, |machine, stack_start| {
+            initialize_linux_stack(
+                &mut machine.inner,
+                args,
+                envs,
+                &auxv,
+                random,
+                stack_start,
+                DEFAULT_STACK_SIZE as u64,
+            )
+        })
+    }
+
+    fn load_program_with_stack<F>(
+        &mut self,
+        program: &Bytes,
+        initialize_stack: F,
+    ) -> Result<ProgramMetadata, Error>
+    where
+        F: FnOnce(&mut Self, u64) -> Result<u64, Error>,
+    {
+        // Checked before anything is loaded, a rejected program leaves
+        // memory untouched.
+        if let Some(isa) = &self.isa {
+            isa.check_program(program)?;
+        }
+        let elf_bytes = self.load_elf_with_bias(program, true, self.load_bias)?;
+        #[cfg(feature = "symbols")]
+        {
+            self.symbols = Symbols::from_elf(program, self.load_bias)?;
+        }
+        let segments = segment_ranges(program, self.load_bias)?;
+        self.initialize_program(program, elf_bytes, segments, initialize_stack)
+    }
+
+    // Same as load_program for a raw binary loaded at addr, see
+    // SupportMachine::load_flat_binary. The stack is set up with args as
+    // usual and the binary is reported as a single segment.
+    pub fn load_flat_program(
+        &mut self,
+        addr: u64,
+        binary: &Bytes,
+        entry: u64,
+        args: &[Bytes],
+    ) -> Result<ProgramMetadata, Error> {
+        let binary_bytes = self.load_flat_binary(addr, binary, entry)?;
+        #[cfg(feature = "symbols")]
+        {
+            self.symbols = Symbols::default();
+        }
+        let segments = vec![(addr, addr + binary.len() as u64)];
+        self.initialize_program(binary, binary_bytes, segments, |machine, stack_start| {
+            machine.initialize_stack(args, stack_start, DEFAULT_STACK_SIZE as u64)
+        })
+    }
+
+    // Initializes syscalls, the debugger, the stack and preloaded data once
+    // the program occupies segments, loaded_bytes is what loading the
+    // program itself wrote. The program is validated last, a rejected
+    // program leaves no metadata behind.
+    fn initialize_program<F>(
+        &mut self,
+        program: &Bytes,
+        loaded_bytes: u64,
+        segments: Vec<(u64, u64)>,
+        initialize_stack: F,
+    ) -> Result<ProgramMetadata, Error>
+    where
+        F: FnOnce(&mut Self, u64) -> Result<u64, Error>,
+    {
+        let memory_size = self.memory().memory_size();
         for syscall in &mut self.syscalls {
             syscall.initialize(&mut self.inner)?;
         }
         if let Some(debugger) = &mut self.debugger {
             debugger.initialize(&mut self.inner)?;
         }
-        let stack_bytes = self.initialize_stack(
-            args,
-            (RISCV_MAX_MEMORY - DEFAULT_STACK_SIZE) as u64,
-            DEFAULT_STACK_SIZE as u64,
-        )?;
-        let bytes = elf_bytes
+        let stack_start = (memory_size - DEFAULT_STACK_SIZE) as u64;
+        let stack_bytes = initialize_stack(self, stack_start)?;
+        self.install_stack_guard(stack_start)?;
+        self.apply_preloads(&segments)?;
+        self.apply_initial_state();
+        let bytes = loaded_bytes
             .checked_add(stack_bytes)
             .ok_or(Error::Unexpected)?;
-        Ok(bytes)
+        let program_break = round_page_up(segments.iter().map(|(_, end)| *end).max().unwrap_or(0));
+        let metadata = ProgramMetadata {
+            entry: self.pc().to_u64(),
+            segments,
+            program_break,
+            stack_top: memory_size as u64,
+            bytes,
+        };
+        self.program_metadata = None;
+        if let Some(validator) = &mut self.program_validator {
+            validator(program, &metadata)?;
+        }
+        self.program_metadata = Some(metadata.clone());
+        Ok(metadata)
     }
 
-    pub fn take_inner(self) -> Inner {
-        self.inner
+    fn apply_initial_state(&mut self) {
+        if let Some(registers) = self.initial_registers {
+            for (index, value) in registers.iter().enumerate().skip(1) {
+                // A zero sp keeps the stack set up by load_program
+                if index == SP && *value == 0 {
+                    continue;
+                }
+                self.set_register(index, Inner::REG::from_u64(*value));
+            }
+        }
+        if let Some(entry) = self.entry_override {
+            self.set_pc(Inner::REG::from_u64(entry));
+        }
     }
 
-    pub fn exit_code(&self) -> i8 {
-        self.exit_code
+    // Copies registers and memory into a view other threads can inspect,
+    // pages unchanged since previous are shared with it. Reading memory
+    // does not trigger watchpoints.
+    pub fn view(
+        &mut self,
+        previous: Option<&MachineSnapshotView>,
+    ) -> Result<MachineSnapshotView, Error> {
+        MachineSnapshotView::new(self, previous)
     }
 
-    pub fn instruction_cycle_func(&self) -> &Option<Box<InstructionCycleFunc>> {
-        &self.instruction_cycle_func
+    // Summary of the resources used since the machine was built or reset,
+    // see ResourceReport.
+    pub fn resource_report(&self) -> ResourceReport {
+        ResourceReport {
+            cycles: self.cycles(),
+            instructions: self.instructions,
+            syscalls: self.syscall_counts.clone(),
+            pages_touched: (self.max_memory_used() / RISCV_PAGESIZE) as u64,
+            exit_code: self.exit_code,
+        }
     }
 
-    pub fn inner_mut(&mut self) -> &mut Inner {
-        &mut self.inner
+    // Metadata of the program loaded last, None before any program is
+    // loaded and after reset.
+    pub fn program_metadata(&self) -> Option<&ProgramMetadata> {
+        self.program_metadata.as_ref()
     }
 
-    // This is the most naive way of running the VM, it only decodes each
-    // instruction and run it, no optimization is performed here. It might
-    // not be practical in production, but it serves as a baseline and
-    // reference implementation
-    pub fn run(&mut self) -> Result<i8, Error> {
-        let decoder = build_imac_decoder::<Inner::REG>();
-        self.set_running(true);
-        while self.running() {
-            self.step(&decoder)?;
+    // Watches writes to the stack_guard_size bytes right below the stack,
+    // any previous guard is dropped first so programs can be reloaded.
+    fn install_stack_guard(&mut self, stack_start: u64) -> Result<(), Error> {
+        if let Some((addr, len)) = self.stack_guard.take() {
+            self.memory_mut()
+                .remove_watchpoint(addr, len, WatchpointKind::Write)?;
         }
-        Ok(self.exit_code())
+        let len = self.stack_guard_size.min(stack_start);
+        if len > 0 {
+            let addr = stack_start - len;
+            self.memory_mut()
+                .add_watchpoint(addr, len, WatchpointKind::Write)?;
+            self.stack_guard = Some((addr, len));
+        }
+        Ok(())
     }
 
-    pub fn step(&mut self, decoder: &Decoder) -> Result<(), Error> {
-        let instruction = {
-            let pc = self.pc().to_u64();
-            let memory = self.memory_mut();
-            decoder.decode(memory, pc)?
-        };
-        execute(instruction, self)?;
-        let cycles = self
-            .instruction_cycle_func()
-            .as_ref()
-            .map(|f| f(instruction))
-            .unwrap_or(0);
-        self.add_cycles(cycles)
+    // Writes data registered with DefaultMachineBuilder::preload, ranges
+    // are checked against program segments and each other first so nothing
+    // is written when they overlap.
+    fn apply_preloads(&mut self, segments: &[(u64, u64)]) -> Result<(), Error> {
+        if self.preloads.is_empty() {
+            return Ok(());
+        }
+        let mut ranges = segments.to_vec();
+        for (addr, data) in &self.preloads {
+            let end = addr
+                .checked_add(data.len() as u64)
+                .ok_or(Error::OutOfBound)?;
+            if ranges
+                .iter()
+                .any(|(start, range_end)| *addr < *range_end && *start < end)
+            {
+                return Err(Error::PreloadOverlap(*addr));
+            }
+            ranges.push((*addr, end));
+        }
+        for (addr, data) in &self.preloads {
+            self.inner.memory_mut().store_bytes(*addr, data)?;
+        }
+        Ok(())
     }
-}
 
-#[derive(Default)]
-pub struct DefaultMachineBuilder<'a, Inner> {
-    inner: Inner,
-    instruction_cycle_func: Option<Box<InstructionCycleFunc>>,
-    debugger: Option<Box<dyn Debugger<Inner> + 'a>>,
-    syscalls: Vec<Box<dyn Syscalls<Inner> + 'a>>,
-}
+    pub fn stack_guard_size(&self) -> u64 {
+        self.stack_guard_size
+    }
 
-impl<'a, Inner> DefaultMachineBuilder<'a, Inner> {
-    pub fn new(inner: Inner) -> Self {
-        Self {
-            inner,
-            instruction_cycle_func: None,
-            debugger: None,
-            syscalls: vec![],
-        }
+    // Instruction set given to the builder, see DefaultMachineBuilder::isa.
+    pub fn isa(&self) -> Option<MachineIsa> {
+        self.isa
     }
 
-    pub fn instruction_cycle_func(
-        mut self,
-        instruction_cycle_func: Box<InstructionCycleFunc>,
-    ) -> Self {
-        self.instruction_cycle_func = Some(instruction_cycle_func);
-        self
+    // Range of the active stack guard as (address, length).
+    pub fn stack_guard(&self) -> Option<(u64, u64)> {
+        self.stack_guard
     }
 
-    pub fn syscall(mut self, syscall: Box<dyn Syscalls<Inner> + 'a>) -> Self {
-        self.syscalls.push(syscall);
-        self
+    // Turns the watchpoint triggered by a write into the stack guard into
+    // Error::StackOverflow, other errors are returned unchanged.
+    pub(crate) fn map_stack_guard(&self, error: Error, instruction: Instruction) -> Error {
+        if let (Error::Watchpoint { .. }, Some((addr, len))) = (error, self.stack_guard) {
+            if let Some((access, size)) = memory_access(instruction, self.registers()) {
+                if access < addr + len && addr < access.saturating_add(size) {
+                    return Error::StackOverflow;
+                }
+            }
+        }
+        error
     }
 
-    pub fn debugger(mut self, debugger: Box<dyn Debugger<Inner> + 'a>) -> Self {
-        self.debugger = Some(debugger);
-        self
+    pub fn take_inner(self) -> Inner {
+        self.inner
     }
 
-    pub fn build(self) -> DefaultMachine<'a, Inner> {
-        DefaultMachine {
+    pub fn exit_code(&self) -> i8 {
+        self.exit_code
+    }
+
+    // Bytes of memory accessed by the program and the host since the
+    // machine was built or reset, see Memory::high_watermark. Hosts can
+    // price memory usage with it.
+    pub fn max_memory_used(&self) -> usize {
+        self.memory().high_watermark()
+    }
+
+    pub fn instruction_cycle_func(&self) -> &Option<Box<InstructionCycleFunc>> {
+        &self.instruction_cycle_func
+    }
+
+    pub fn memory_cycle_func(&self) -> &Option<Box<MemoryCycleFunc>> {
+        &self.memory_cycle_func
+    }
+
+    // Cycles charged for executing instruction, including its memory
+    // access. The cost only depends on the instruction, so it can be
+    // computed ahead of time for whole traces.
+    pub(crate) fn instruction_cycles(&self, instruction: Instruction) -> u64 {
+        let cycles = self
+            .instruction_cycle_func
+            .as_ref()
+            .map(|f| f(instruction))
+            .unwrap_or(0);
+        match (&self.memory_cycle_func, memory_op(instruction)) {
+            (Some(f), Some((op, size))) => cycles.saturating_add(f(op, size)),
+            _ => cycles,
+        }
+    }
+
+    pub fn inner_mut(&mut self) -> &mut Inner {
+        &mut self.inner
+    }
+
+    // Syscalls are consulted in the order they are added, after the ones
+    // provided via the builder. Like those, a syscall added here gets
+    // initialized when a program is loaded.
+    pub fn add_syscall(&mut self, syscall: Box<dyn Syscalls<Inner> + 'a>) {
+        self.syscalls.push(syscall);
+    }
+
+    pub fn set_on_instruction(&mut self, on_instruction: Box<InstructionHookFunc<'a, Inner>>) {
+        self.on_instruction = Some(on_instruction);
+    }
+
+    pub fn set_on_cycles(&mut self, on_cycles: Box<CyclesHookFunc<'a>>) {
+        self.on_cycles = Some(on_cycles);
+    }
+
+    pub fn set_exit_handler(&mut self, exit_handler: Box<ExitHandler<'a, Inner>>) {
+        self.exit_handler = Some(exit_handler);
+    }
+
+    // Replaces the handler of custom instructions with id, if any
+    pub fn set_custom_instruction(
+        &mut self,
+        id: u8,
+        handler: Box<CustomInstructionHandler<'a, Inner>>,
+    ) {
+        self.custom_instructions.insert(id, handler);
+    }
+
+    // Enabling the syscall log starts with an empty log, disabling it drops
+    // the recorded syscalls.
+    pub fn set_syscall_log(&mut self, enabled: bool) {
+        self.syscall_log = if enabled { Some(Vec::new()) } else { None };
+    }
+
+    // Ecalls in the order they were made, None when the log is disabled
+    pub fn syscall_log(&self) -> Option<&[SyscallRecord]> {
+        self.syscall_log.as_deref()
+    }
+
+    // Returns the recorded syscalls, leaving an empty log in place so the
+    // next run is recorded separately.
+    pub fn take_syscall_log(&mut self) -> Option<Vec<SyscallRecord>> {
+        self.syscall_log.as_mut().map(core::mem::take)
+    }
+
+    // Enabling checked arithmetic starts with an empty overflow log,
+    // disabling it drops the recorded overflows.
+    pub fn set_checked_arithmetic(&mut self, enabled: bool) {
+        self.overflow_log = if enabled { Some(Vec::new()) } else { None };
+    }
+
+    pub fn checked_arithmetic(&self) -> bool {
+        self.overflow_log.is_some()
+    }
+
+    // Overflows in the order they happened, None without checked arithmetic
+    pub fn overflow_log(&self) -> Option<&[OverflowRecord]> {
+        self.overflow_log.as_deref()
+    }
+
+    pub fn take_overflow_log(&mut self) -> Option<Vec<OverflowRecord>> {
+        self.overflow_log.as_mut().map(core::mem::take)
+    }
+
+    // Enabling opcode stats starts counting from zero, disabling them drops
+    // the counts.
+    pub fn set_opcode_stats(&mut self, enabled: bool) {
+        self.opcode_stats = if enabled {
+            Some(vec![0; MAXIMUM_OPCODE as usize + 1])
+        } else {
+            None
+        };
+    }
+
+    // Instructions executed so far indexed by opcode, names are found in
+    // INSTRUCTION_OPCODE_NAMES. None unless opcode stats are enabled.
+    pub fn opcode_stats(&self) -> Option<&[u64]> {
+        self.opcode_stats.as_deref()
+    }
+
+    // Enabling instruction recording starts with an empty recording,
+    // disabling it drops the recorded instructions.
+    pub fn set_instruction_recording(&mut self, enabled: bool) {
+        self.instruction_recording = if enabled {
+            Some(InstructionRecording::new())
+        } else {
+            None
+        };
+    }
+
+    // Instructions executed so far, None unless instruction recording is
+    // enabled. Instructions are recorded once executed, including the one
+    // a run failed on, see replay.
+    pub fn instruction_recording(&self) -> Option<&InstructionRecording> {
+        self.instruction_recording.as_ref()
+    }
+
+    // Returns the recorded instructions, leaving an empty recording in
+    // place so the next run is recorded separately.
+    pub fn take_instruction_recording(&mut self) -> Option<InstructionRecording> {
+        self.instruction_recording.as_mut().map(core::mem::take)
+    }
+
+    // Raw bits of instruction at pc when instructions are recorded, fetched
+    // before it runs since it might change memory permissions.
+    pub(crate) fn recorded_bits(&mut self, pc: u64, instruction: Instruction) -> Option<u32> {
+        self.instruction_recording.as_ref()?;
+        let low = self.memory_mut().execute_load16(pc).ok()?;
+        if instruction_length(instruction) == 2 {
+            return Some(u32::from(low));
+        }
+        let high = self.memory_mut().execute_load16(pc + 2).ok()?;
+        Some(u32::from(low) | (u32::from(high) << 16))
+    }
+
+    pub(crate) fn record_instruction(&mut self, pc: u64, bits: Option<u32>) {
+        if let (Some(recording), Some(bits)) = (&mut self.instruction_recording, bits) {
+            recording.push(pc, bits);
+        }
+    }
+
+    // Commitment to the transitions executed since the machine was built or
+    // reset, as of the last completed instruction or basic block depending
+    // on the granularity. None unless enabled, see
+    // DefaultMachineBuilder::state_commitment.
+    pub fn state_commitment(&self) -> Option<[u8; 32]> {
+        self.state_commitment
+            .as_ref()
+            .map(|commitment| commitment.commitment())
+    }
+
+    // Number of instructions or basic blocks committed so far
+    pub fn state_commitment_steps(&self) -> Option<u64> {
+        self.state_commitment
+            .as_ref()
+            .map(|commitment| commitment.steps())
+    }
+
+    // Captures what instruction might change before it runs: registers,
+    // the range stored to, and for ecalls, ebreaks and custom instructions,
+    // whose handlers can write anywhere, the dirty pages of memory.
+    pub(crate) fn begin_transition(&mut self, instruction: Instruction) {
+        let mut commitment = match self.state_commitment.take() {
+            Some(commitment) => commitment,
+            None => return,
+        };
+        commitment.registers.clear();
+        commitment
+            .registers
+            .extend(self.registers().iter().map(|r| r.to_u64()));
+        commitment.store = match memory_op(instruction) {
+            Some((MemoryOp::Store, _)) | Some((MemoryOp::Atomic, _)) => {
+                memory_access(instruction, self.registers())
+            }
+            _ => None,
+        };
+        commitment.dirty = match extract_opcode(instruction) {
+            OP_ECALL | OP_EBREAK | OP_CUSTOM => {
+                let mut dirty = BTreeMap::new();
+                for page in self.memory().dirty_pages().unwrap_or_default() {
+                    let addr = page * RISCV_PAGESIZE as u64;
+                    if let Ok(content) = self.memory_mut().dump_range(addr, RISCV_PAGESIZE as u64) {
+                        dirty.insert(page, content);
+                    }
+                }
+                Some(dirty)
+            }
+            _ => None,
+        };
+        self.state_commitment = Some(commitment);
+    }
+
+    // Adds the transition of the instruction at pc, which just completed,
+    // to the state commitment. Pages already dirty before an ecall are
+    // compared to find the bytes it changed, other pages it dirtied are
+    // committed whole.
+    pub(crate) fn end_transition(&mut self, pc: u64, instruction: Instruction) {
+        let mut commitment = match self.state_commitment.take() {
+            Some(commitment) => commitment,
+            None => return,
+        };
+        let registers: Vec<(usize, u64)> = self
+            .registers()
+            .iter()
+            .enumerate()
+            .map(|(index, r)| (index, r.to_u64()))
+            .filter(|(index, value)| commitment.registers.get(*index) != Some(value))
+            .collect();
+        let mut writes = Vec::new();
+        if let Some((addr, size)) = commitment.store.take() {
+            if let Ok(content) = self.memory_mut().dump_range(addr, size) {
+                writes.push((addr, content));
+            }
+        }
+        if let Some(dirty) = commitment.dirty.take() {
+            for page in self.memory().dirty_pages().unwrap_or_default() {
+                let addr = page * RISCV_PAGESIZE as u64;
+                let content = match self.memory_mut().dump_range(addr, RISCV_PAGESIZE as u64) {
+                    Ok(content) => content,
+                    Err(_) => continue,
+                };
+                match dirty.get(&page) {
+                    Some(before) => {
+                        for range in diff(addr, before, &content).ranges {
+                            writes.push((range.addr, range.after));
+                        }
+                    }
+                    None => writes.push((addr, content)),
+                }
+            }
+        }
+        commitment.push(
+            pc,
+            &registers,
+            &writes,
+            is_basic_block_end_instruction(instruction),
+        );
+        self.state_commitment = Some(commitment);
+    }
+
+    pub(crate) fn count_opcode(&mut self, instruction: Instruction) {
+        if let Some(stats) = &mut self.opcode_stats {
+            stats[extract_opcode(instruction) as usize] += 1;
+        }
+    }
+
+    // Overflow instruction is about to cause, only looked for with checked
+    // arithmetic. It is logged once the instruction completes.
+    fn arithmetic_overflow(&self, instruction: Instruction) -> Option<OverflowRecord> {
+        self.overflow_log.as_ref()?;
+        let (lhs, rhs) = signed_overflow(instruction, self.inner.registers())?;
+        Some(OverflowRecord {
+            pc: self.inner.pc().to_u64(),
+            instruction,
+            lhs,
+            rhs,
+        })
+    }
+
+    pub(crate) fn notify_cycles(&mut self, consumed: u64) {
+        if let Some(on_cycles) = &mut self.on_cycles {
+            on_cycles(consumed, self.inner.cycles());
+        }
+    }
+
+    // Tells if anything has to see every instruction run one by one:
+    // instruction hooks, opcode stats, instruction recording, state
+    // commitments, call depth limits and checked arithmetic. Native code
+    // and fused instructions are only used otherwise.
+    pub(crate) fn inspects_instructions(&self) -> bool {
+        self.on_instruction.is_some()
+            || self.checked_arithmetic()
+            || self.opcode_stats().is_some()
+            || self.instruction_recording().is_some()
+            || self.state_commitment().is_some()
+            || self.max_call_depth().is_some()
+    }
+
+    fn notify_instruction(&mut self, instruction: Instruction) {
+        if let Some(on_instruction) = &mut self.on_instruction {
+            on_instruction(self.inner.pc().to_u64(), instruction, &self.inner);
+        }
+    }
+
+    pub fn reservation(&self) -> &Reservation {
+        &self.reservation
+    }
+
+    #[cfg(feature = "fd")]
+    pub fn float_registers(&self) -> &FloatRegisters {
+        &self.float_registers
+    }
+
+    #[cfg(feature = "v-ext")]
+    pub fn vector_registers(&self) -> &VectorRegisters {
+        &self.vector_registers
+    }
+
+    pub fn syscall_registry(&self) -> &SyscallRegistry<'a, Inner> {
+        &self.syscall_registry
+    }
+
+    pub fn syscall_registry_mut(&mut self) -> &mut SyscallRegistry<'a, Inner> {
+        &mut self.syscall_registry
+    }
+
+    pub fn snapshot(&mut self) -> Result<Snapshot, Error> {
+        Ok(Snapshot {
+            bits: Inner::REG::BITS,
+            pc: self.pc().to_u64(),
+            registers: self.registers().iter().map(|r| r.to_u64()).collect(),
+            exit_code: self.exit_code,
+            cycles: self.cycles(),
+            pages: snapshot_memory(self.memory_mut())?,
+            #[cfg(feature = "fd")]
+            float_registers: self.float_registers.clone(),
+            #[cfg(feature = "v-ext")]
+            vector_registers: self.vector_registers.clone(),
+        })
+    }
+
+    // Restores a snapshot on this machine, the snapshot is rejected when it
+    // is created by a machine of different register width or with a
+    // different program loaded in frozen memory.
+    pub fn resume(&mut self, snapshot: &Snapshot) -> Result<(), Error> {
+        if snapshot.bits != Inner::REG::BITS || snapshot.registers.len() != self.registers().len() {
+            return Err(Error::InvalidSnapshot);
+        }
+        resume_memory(self.memory_mut(), &snapshot.pages)?;
+        for (i, value) in snapshot.registers.iter().enumerate() {
+            self.set_register(i, Inner::REG::from_u64(*value));
+        }
+        self.set_pc(Inner::REG::from_u64(snapshot.pc));
+        self.exit_code = snapshot.exit_code;
+        self.set_cycles(snapshot.cycles);
+        self.reservation.clear();
+        #[cfg(feature = "fd")]
+        {
+            self.float_registers = snapshot.float_registers.clone();
+        }
+        #[cfg(feature = "v-ext")]
+        {
+            self.vector_registers = snapshot.vector_registers.clone();
+        }
+        Ok(())
+    }
+
+    // Brings the machine back to the state it was built in, so the same
+    // instance can run another program without allocating memory again.
+    // Registers, pc, cycles, instruction count, exit code and memory are
+    // cleared, while syscalls, cycle functions, limits and other settings
+    // given to the builder are kept. Syscalls and the debugger are
+    // initialized again when the next program is loaded.
+    pub fn reset(&mut self) -> Result<(), Error> {
+        self.memory_mut().reset()?;
+        for i in 0..self.registers().len() {
+            self.set_register(i, Inner::REG::zero());
+        }
+        self.set_pc(Inner::REG::zero());
+        self.set_cycles(0);
+        self.set_running(false);
+        self.instructions = 0;
+        self.call_depth = 0;
+        self.retried_pc = None;
+        self.interrupt.clear();
+        self.exit_code = 0;
+        self.last_fault = None;
+        if let Some(log) = &mut self.syscall_log {
+            log.clear();
+        }
+        self.syscall_counts.clear();
+        if let Some(log) = &mut self.overflow_log {
+            log.clear();
+        }
+        if let Some(stats) = &mut self.opcode_stats {
+            stats.iter_mut().for_each(|count| *count = 0);
+        }
+        if let Some(recording) = &mut self.instruction_recording {
+            recording.clear();
+        }
+        if let Some(commitment) = &mut self.state_commitment {
+            commitment.clear();
+        }
+        // Watchpoints are gone with memory reset
+        self.stack_guard = None;
+        #[cfg(feature = "symbols")]
+        {
+            self.symbols = Symbols::default();
+        }
+        self.program_metadata = None;
+        self.reservation.clear();
+        #[cfg(feature = "fd")]
+        {
+            self.float_registers = FloatRegisters::default();
+        }
+        #[cfg(feature = "v-ext")]
+        {
+            self.vector_registers = VectorRegisters::default();
+        }
+        Ok(())
+    }
+
+    // This is the most naive way of running the VM, it only decodes each
+    // instruction and run it, no optimization is performed here. It might
+    // not be practical in production, but it serves as a baseline and
+    // reference implementation
+    pub fn run(&mut self) -> Result<i8, Error> {
+        self.run_with_result().into_result()
+    }
+
+    // Like run, but also reports cycles and memory consumed, which are
+    // available even when the run fails.
+    pub fn run_with_result(&mut self) -> RunResult {
+        let decoder = build_imac_decoder::<Inner::REG>();
+        self.run_with_decoder_result(&decoder)
+    }
+
+    // Runs the program with a custom decoder, hosts can use this to enable
+    // extra extensions such as B for a single machine.
+    pub fn run_with_decoder(&mut self, decoder: &Decoder) -> Result<i8, Error> {
+        self.run_with_decoder_result(decoder).into_result()
+    }
+
+    pub fn run_with_decoder_result(&mut self, decoder: &Decoder) -> RunResult {
+        self.set_running(true);
+        self.clear_fault();
+        let mut result = Ok(());
+        while self.running() && result.is_ok() {
+            result = self.step(decoder).map(|_| ());
+        }
+        let result = result.map(|_| self.exit_code());
+        let mut run_result = RunResult::new(self, result);
+        run_result.fault = self.last_fault;
+        run_result
+    }
+
+    // Like run, but fails with Error::Deadline once deadline has passed,
+    // for hosts such as simulators that limit wall-clock time rather than
+    // cycles. The clock is read every DEADLINE_CHECK_INTERVAL basic blocks,
+    // so the deadline is only enforced that coarsely. The machine stops
+    // between two instructions and can be run again afterwards.
+    #[cfg(all(feature = "std", not(target_family = "wasm")))]
+    pub fn run_with_deadline(&mut self, deadline: Instant) -> Result<i8, Error> {
+        let decoder = build_imac_decoder::<Inner::REG>();
+        self.run_with_decoder_and_deadline(&decoder, deadline)
+    }
+
+    #[cfg(all(feature = "std", not(target_family = "wasm")))]
+    pub fn run_with_decoder_and_deadline(
+        &mut self,
+        decoder: &Decoder,
+        deadline: Instant,
+    ) -> Result<i8, Error> {
+        self.set_running(true);
+        self.clear_fault();
+        if Instant::now() >= deadline {
+            return Err(Error::Deadline);
+        }
+        let mut blocks = 0;
+        while self.running() {
+            if !is_basic_block_end_instruction(self.step(decoder)?) {
+                continue;
+            }
+            blocks += 1;
+            if blocks % DEADLINE_CHECK_INTERVAL == 0 && Instant::now() >= deadline {
+                return Err(Error::Deadline);
+            }
+        }
+        Ok(self.exit_code())
+    }
+
+    // Display output, followed by the disassembly of the basic block at pc
+    // read from memory unless the decoded program already covered it.
+    // Meant for bug reports, e.g. once a run failed. Fetching counts
+    // towards the memory high watermark like executing would.
+    pub fn describe(&mut self) -> String {
+        let mut text = self.to_string();
+        let pc = self.pc().to_u64();
+        let decoded = self
+            .decoded_program
+            .as_ref()
+            .is_some_and(|program| program.get(pc).is_some());
+        if !decoded {
+            let decoder = build_imac_decoder::<Inner::REG>();
+            match disassemble_block(&decoder, self.memory_mut(), pc, DESCRIBED_INSTRUCTIONS) {
+                Ok(block) => {
+                    for (addr, instruction) in block {
+                        text += &format!("{:#x}: {}\n", addr, instruction);
+                    }
+                }
+                Err(e) => text += &format!("{:#x}: {}\n", pc, e),
+            }
+        }
+        text
+    }
+
+    // Context of the last error raised by step, such as the instruction
+    // and the memory it accessed. This is cleared when a run starts.
+    pub fn last_fault(&self) -> Option<Fault> {
+        self.last_fault
+    }
+
+    #[cfg(feature = "symbols")]
+    pub fn symbols(&self) -> &Symbols {
+        &self.symbols
+    }
+
+    // Symbol of the loaded program containing pc, displayed as name+offset
+    #[cfg(feature = "symbols")]
+    pub fn symbolicate(&self, pc: u64) -> Option<Symbol<'_>> {
+        self.symbols.lookup(pc)
+    }
+
+    // Describes the last fault like its Display implementation, followed by
+    // the function it happened in and the one ra points to, which is
+    // usually the caller.
+    #[cfg(feature = "symbols")]
+    pub fn last_fault_message(&self) -> Option<String> {
+        let fault = self.last_fault?;
+        let mut message = fault.to_string();
+        if let Some(symbol) = self.symbolicate(fault.pc) {
+            message.push_str(&format!(" in {}", symbol));
+        }
+        if let Some(symbol) = self.symbolicate(self.registers()[super::registers::RA].to_u64()) {
+            message.push_str(&format!(" called from {}", symbol));
+        }
+        Some(message)
+    }
+
+    // Calls the guest function at addr with args passed per the RISC-V
+    // calling convention: the first 8 in a0-a7, the others on the stack.
+    // ra points to the end of memory, which can never be executed, and the
+    // call returns a0 once the function returns there. The program must be
+    // loaded first, the stack set up by load_program is used and sp is
+    // restored afterwards, so functions can be called repeatedly. Cycles
+    // are charged and limits apply as in run. A function exiting the
+    // program fails with Error::CallExited.
+    pub fn call(&mut self, addr: u64, args: &[u64]) -> Result<u64, Error> {
+        let decoder = build_imac_decoder::<Inner::REG>();
+        let return_address = self.memory().memory_size() as u64;
+        let sp = self.registers()[SP].clone();
+        let xlen = u64::from(Inner::REG::BITS / 8);
+        let stack_args = args.len().saturating_sub(8) as u64;
+        // The stack stays 16 byte aligned as the ABI requires
+        let frame_start = sp
+            .to_u64()
+            .checked_sub(stack_args * xlen)
+            .ok_or(Error::OutOfBound)?
+            & !15;
+        for (i, value) in args.iter().enumerate() {
+            let value = Inner::REG::from_u64(*value);
+            if i < 8 {
+                self.set_register(A0 + i, value);
+            } else {
+                let addr = Inner::REG::from_u64(frame_start + (i as u64 - 8) * xlen);
+                match Inner::REG::BITS {
+                    32 => self.memory_mut().store32(&addr, &value)?,
+                    _ => self.memory_mut().store64(&addr, &value)?,
+                }
+            }
+        }
+        self.set_register(SP, Inner::REG::from_u64(frame_start));
+        self.set_register(RA, Inner::REG::from_u64(return_address));
+        self.set_pc(Inner::REG::from_u64(addr));
+        self.set_running(true);
+        self.clear_fault();
+        let result = loop {
+            if self.pc().to_u64() == return_address {
+                break Ok(self.registers()[A0].to_u64());
+            }
+            if !self.running() {
+                break Err(Error::CallExited(self.exit_code()));
+            }
+            if let Err(e) = self.step(&decoder) {
+                break Err(e);
+            }
+        };
+        self.set_register(SP, sp);
+        result
+    }
+
+    // Same as call for a function of the loaded program found by name,
+    // fails with Error::UnknownSymbol when there is none.
+    #[cfg(feature = "symbols")]
+    pub fn call_symbol(&mut self, name: &str, args: &[u64]) -> Result<u64, Error> {
+        let addr = self.symbols.address(name).ok_or(Error::UnknownSymbol)?;
+        self.call(addr, args)
+    }
+
+    pub(crate) fn clear_fault(&mut self) {
+        self.last_fault = None;
+    }
+
+    pub(crate) fn record_fault(&mut self, error: Error, pc: u64, instruction: Option<Instruction>) {
+        let access = match instruction {
+            Some(instruction) => memory_access(instruction, self.registers()),
+            None => Some((pc, 0)),
+        };
+        self.last_fault = Some(Fault {
+            error,
+            pc,
+            instruction,
+            addr: access.map(|(addr, _)| addr),
+            size: access.and_then(|(_, size)| if size > 0 { Some(size) } else { None }),
+            cycles: self.cycles(),
+        });
+    }
+
+    // Like run, but reaching max cycles pauses the machine instead of
+    // failing when pause on limit is enabled, see
+    // DefaultMachineBuilder::pause_on_limit. Without it, this returns
+    // Error::CyclesExceeded like run.
+    pub fn run_resumable(&mut self) -> Result<RunState, Error> {
+        let decoder = build_imac_decoder::<Inner::REG>();
+        self.run_resumable_with_decoder(&decoder)
+    }
+
+    pub fn run_resumable_with_decoder(&mut self, decoder: &Decoder) -> Result<RunState, Error> {
+        self.set_running(true);
+        self.clear_fault();
+        let mut executed = 0;
+        while self.running() {
+            if Some(executed) == self.yield_every {
+                return Ok(RunState::Yielded);
+            }
+            match self.step(decoder) {
+                Ok(_) => executed += 1,
+                Err(Error::CyclesExceeded) if self.pause_on_limit => return Ok(RunState::Paused),
+                Err(Error::WouldBlock) => return Ok(RunState::Yielded),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(RunState::Exited(self.exit_code()))
+    }
+
+    // Like run, but ecalls not processed by the syscalls of the machine are
+    // given to syscalls, whose futures are awaited with the machine paused
+    // at the ecall. Execution itself stays synchronous, only ecalls can
+    // suspend the run. Synchronous syscalls that would block fail with
+    // Error::WouldBlock since nothing else runs meanwhile.
+    #[cfg(feature = "async")]
+    pub async fn run_async<S>(&mut self, syscalls: &mut S) -> Result<i8, Error>
+    where
+        S: AsyncSyscalls<Inner> + ?Sized,
+    {
+        let decoder = build_imac_decoder::<Inner::REG>();
+        self.set_running(true);
+        self.clear_fault();
+        self.async_ecall = AsyncEcall::Idle;
+        let mut result = Ok(());
+        while self.running() && result.is_ok() {
+            result = match self.step(&decoder) {
+                Err(Error::WouldBlock) if self.async_ecall == AsyncEcall::Waiting => {
+                    let processed = syscalls.ecall(&mut self.inner).await;
+                    self.async_ecall = AsyncEcall::Done(processed);
+                    Ok(())
+                }
+                result => result.map(|_| ()),
+            };
+        }
+        self.async_ecall = AsyncEcall::Disabled;
+        result.map(|_| self.exit_code())
+    }
+
+    pub fn pause_on_limit(&self) -> bool {
+        self.pause_on_limit
+    }
+
+    pub fn yield_every(&self) -> Option<u64> {
+        self.yield_every
+    }
+
+    // 0 disables yielding
+    pub fn set_yield_every(&mut self, instructions: u64) {
+        self.yield_every = if instructions > 0 {
+            Some(instructions)
+        } else {
+            None
+        };
+    }
+
+    pub fn set_pause_on_limit(&mut self, pause_on_limit: bool) {
+        self.pause_on_limit = pause_on_limit;
+    }
+
+    pub fn rvc(&self) -> bool {
+        !self.reject_rvc
+    }
+
+    pub fn set_rvc(&mut self, rvc: bool) {
+        self.reject_rvc = !rvc;
+    }
+
+    pub fn fixed_counters(&self) -> bool {
+        self.fixed_counters
+    }
+
+    pub fn set_fixed_counters(&mut self, fixed_counters: bool) {
+        self.fixed_counters = fixed_counters;
+    }
+
+    pub fn decoded_program(&self) -> Option<&Arc<DecodedProgram>> {
+        self.decoded_program.as_ref()
+    }
+
+    pub fn set_decoded_program(&mut self, decoded_program: Option<Arc<DecodedProgram>>) {
+        self.decoded_program = decoded_program;
+    }
+
+    // Decodes the instruction at pc, compressed instructions are rejected
+    // as invalid when RVC is disabled. Instructions found in the decoded
+    // program are reused when memory still holds the bits they were
+    // decoded from.
+    pub(crate) fn decode_instruction(
+        &mut self,
+        decoder: &Decoder,
+        pc: u64,
+    ) -> Result<Instruction, Error> {
+        let decoded = self
+            .decoded_program
+            .as_ref()
+            .and_then(|program| program.get(pc));
+        let instruction = match decoded {
+            Some((bits, instruction)) => {
+                let instruction_bits = decoder.decode_bits(self.memory_mut(), pc)?;
+                if instruction_bits == bits {
+                    instruction
+                } else {
+                    decoder.decode_raw(instruction_bits)?
+                }
+            }
+            None => decoder.decode(self.memory_mut(), pc)?,
+        };
+        if self.reject_rvc && instruction_length(instruction) == 2 {
+            let instruction_bits = self.memory_mut().execute_load16(pc)?;
+            return Err(Error::InvalidInstruction(u32::from(instruction_bits)));
+        }
+        Ok(instruction)
+    }
+
+    // Number of instructions executed so far, including those that failed
+    // after being charged.
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    pub fn set_instructions(&mut self, instructions: u64) {
+        self.instructions = instructions;
+    }
+
+    pub fn max_instructions(&self) -> Option<u64> {
+        self.max_instructions
+    }
+
+    // A value of 0 means there is no limit, as with max cycles.
+    pub fn set_max_instructions(&mut self, max_instructions: u64) {
+        self.max_instructions = if max_instructions > 0 {
+            Some(max_instructions)
+        } else {
+            None
+        };
+    }
+
+    // Current call depth, see DefaultMachineBuilder::max_call_depth. It is
+    // only tracked with a maximum set and stays 0 otherwise.
+    pub fn call_depth(&self) -> u64 {
+        self.call_depth
+    }
+
+    pub fn max_call_depth(&self) -> Option<u64> {
+        self.max_call_depth
+    }
+
+    // A value of 0 means there is no limit. The current depth is kept, so
+    // the limit can be changed between runs.
+    pub fn set_max_call_depth(&mut self, max_call_depth: u64) {
+        self.max_call_depth = if max_call_depth > 0 {
+            Some(max_call_depth)
+        } else {
+            None
+        };
+    }
+
+    // Tells if instruction can be executed without exceeding the maximum
+    // call depth, the depth itself is only updated by update_call_depth.
+    pub(crate) fn check_call_depth(&self, instruction: Instruction) -> Result<(), Error> {
+        match self.max_call_depth {
+            Some(max_call_depth)
+                if call_depth_change(instruction) > 0 && self.call_depth >= max_call_depth =>
+            {
+                Err(Error::CallDepthExceeded)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Returns below the initial depth, e.g. from longjmp, are ignored
+    pub(crate) fn update_call_depth(&mut self, instruction: Instruction) {
+        if self.max_call_depth.is_some() {
+            match call_depth_change(instruction) {
+                1 => self.call_depth += 1,
+                -1 => self.call_depth = self.call_depth.saturating_sub(1),
+                _ => (),
+            }
+        }
+    }
+
+    // Handle to stop this machine from another thread, see InterruptHandle.
+    // It can be obtained before the run starts and be moved elsewhere.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    // Fails with Error::Interrupted when a stop has been requested, which is
+    // consumed so the machine can be run again.
+    pub(crate) fn check_interrupt(&self) -> Result<(), Error> {
+        if self.interrupt.take() {
+            return Err(Error::Interrupted);
+        }
+        Ok(())
+    }
+
+    // Tells if count more instructions can be executed, the counter itself
+    // is only updated once they are charged, see add_instructions.
+    pub(crate) fn check_instructions(&self, count: u64) -> Result<(), Error> {
+        match self.max_instructions {
+            Some(max_instructions)
+                if self.instructions.saturating_add(count) > max_instructions =>
+            {
+                Err(Error::InstructionLimitExceeded)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn add_instructions(&mut self, count: u64) {
+        self.instructions = self.instructions.saturating_add(count);
+    }
+
+    // Decodes and executes exactly the instruction at pc, charging its
+    // cycles and applying limits, hooks and the other settings run applies.
+    // Returns the instruction executed, so debuggers and property tests can
+    // drive the machine one instruction at a time. Unlike run, whether the
+    // machine is running is neither checked nor changed.
+    pub fn step(&mut self, decoder: &Decoder) -> Result<Instruction, Error> {
+        let pc = self.pc().to_u64();
+        let instruction = match self.decode_instruction(decoder, pc) {
+            Ok(instruction) => instruction,
+            Err(e) => {
+                self.record_fault(e, pc, None);
+                return Err(e);
+            }
+        };
+        self.execute_instruction(pc, instruction)?;
+        // Stop requests are only looked at between basic blocks
+        if is_basic_block_end_instruction(instruction) {
+            self.check_interrupt()?;
+        }
+        Ok(instruction)
+    }
+
+    // Re-executes a recorded instruction stream on this machine, which
+    // should be set up like the recorded one was, e.g. by loading the same
+    // program with the same arguments and syscalls. Instructions are
+    // decoded from the recording instead of memory and charged as step
+    // would, so cycles match the recorded run. Fails with
+    // Error::ReplayDivergence when the machine is not at the recorded pc,
+    // such as when a syscall returned different data, or with the error
+    // the last recorded instruction failed with, which reproduces the
+    // recorded fault.
+    pub fn replay(
+        &mut self,
+        recording: &InstructionRecording,
+        decoder: &Decoder,
+    ) -> Result<(), Error> {
+        self.set_running(true);
+        self.clear_fault();
+        for (index, recorded) in recording.iter().enumerate() {
+            if !self.running() || self.pc().to_u64() != recorded.pc {
+                return Err(Error::ReplayDivergence {
+                    index: index as u64,
+                    pc: recorded.pc,
+                });
+            }
+            let instruction = match decoder.decode_raw(recorded.bits) {
+                Ok(instruction) => instruction,
+                Err(e) => {
+                    self.record_fault(e, recorded.pc, None);
+                    return Err(e);
+                }
+            };
+            self.execute_instruction(recorded.pc, instruction)?;
+        }
+        Ok(())
+    }
+
+    fn execute_instruction(&mut self, pc: u64, instruction: Instruction) -> Result<(), Error> {
+        let pending = self.start_instruction(pc, instruction)?;
+        let result = execute(instruction, self);
+        self.finish_instruction(pending, result)
+    }
+
+    // Charges the instruction at pc and invokes the hooks that look at the
+    // machine right before it runs. Cycles are charged before executing,
+    // so when the budget is exhausted the machine stops right before the
+    // instruction and can be snapshotted and resumed later.
+    pub(crate) fn start_instruction(
+        &mut self,
+        pc: u64,
+        instruction: Instruction,
+    ) -> Result<PendingInstruction, Error> {
+        let cycles = self.instruction_cycles(instruction);
+        let previous_cycles = self.cycles();
+        if let Err(e) = self
+            .check_instructions(1)
+            .and_then(|_| self.check_call_depth(instruction))
+            .and_then(|_| self.add_cycles(cycles))
+        {
+            self.record_fault(e, pc, Some(instruction));
+            return Err(e);
+        }
+        self.add_instructions(1);
+        if self.retried_pc.take() != Some(pc) {
+            self.notify_instruction(instruction);
+        }
+        let overflow = self.arithmetic_overflow(instruction);
+        let bits = self.recorded_bits(pc, instruction);
+        self.begin_transition(instruction);
+        Ok(PendingInstruction {
+            pc,
+            instruction,
+            cycles,
+            previous_cycles,
+            bits,
+            overflow,
+        })
+    }
+
+    // Completes an instruction started by start_instruction with the result
+    // of executing it. Instructions retried on resume are rolled back
+    // instead, so they are charged, counted and logged once.
+    pub(crate) fn finish_instruction(
+        &mut self,
+        pending: PendingInstruction,
+        result: Result<(), Error>,
+    ) -> Result<(), Error> {
+        let PendingInstruction {
+            pc, instruction, ..
+        } = pending;
+        let result = match result {
+            Ok(()) => {
+                self.end_transition(pc, instruction);
+                Ok(())
+            }
+            // Syscall cycles are charged before the syscall runs, the
+            // instruction is retried on resume.
+            Err(Error::CyclesExceeded) if self.pause_on_limit => {
+                self.retry_instruction(&pending);
+                self.record_fault(Error::CyclesExceeded, pc, Some(instruction));
+                return Err(Error::CyclesExceeded);
+            }
+            // Likewise for syscalls that would block, which are not recorded
+            // as faults so waiting machines can be told apart.
+            Err(Error::WouldBlock) => {
+                self.retry_instruction(&pending);
+                return Err(Error::WouldBlock);
+            }
+            Err(e) => Err(self.map_stack_guard(e.with_pc(pc), instruction)),
+        };
+        self.update_call_depth(instruction);
+        self.count_opcode(instruction);
+        if let (Some(log), Some(overflow)) = (&mut self.overflow_log, pending.overflow) {
+            log.push(overflow);
+        }
+        self.record_instruction(pc, pending.bits);
+        if let Err(e) = result {
+            self.record_fault(e, pc, Some(instruction));
+        }
+        result
+    }
+
+    fn retry_instruction(&mut self, pending: &PendingInstruction) {
+        self.set_cycles(pending.previous_cycles);
+        self.instructions -= 1;
+        self.retried_pc = Some(pending.pc);
+    }
+}
+
+// Instruction charged by DefaultMachine::start_instruction, waiting for
+// finish_instruction.
+pub(crate) struct PendingInstruction {
+    pc: u64,
+    instruction: Instruction,
+    pub(crate) cycles: u64,
+    previous_cycles: u64,
+    bits: Option<u32>,
+    overflow: Option<OverflowRecord>,
+}
+
+#[derive(Default)]
+pub struct DefaultMachineBuilder<'a, Inner> {
+    inner: Inner,
+    instruction_cycle_func: Option<Box<InstructionCycleFunc>>,
+    memory_cycle_func: Option<Box<MemoryCycleFunc>>,
+    debugger: Option<Box<dyn Debugger<Inner> + 'a>>,
+    syscalls: Vec<Box<dyn Syscalls<Inner> + 'a>>,
+    syscall_registry: SyscallRegistry<'a, Inner>,
+    on_instruction: Option<Box<InstructionHookFunc<'a, Inner>>>,
+    on_cycles: Option<Box<CyclesHookFunc<'a>>>,
+    exit_handler: Option<Box<ExitHandler<'a, Inner>>>,
+    program_validator: Option<Box<ProgramValidator<'a>>>,
+    custom_instructions: BTreeMap<u8, Box<CustomInstructionHandler<'a, Inner>>>,
+    syscall_log: bool,
+    checked_arithmetic: bool,
+    opcode_stats: bool,
+    record_instructions: bool,
+    state_commitment: Option<StateCommitment>,
+    load_bias: u64,
+    pause_on_limit: bool,
+    yield_every: Option<u64>,
+    reject_rvc: bool,
+    decoded_program: Option<Arc<DecodedProgram>>,
+    fixed_counters: bool,
+    max_instructions: Option<u64>,
+    max_call_depth: Option<u64>,
+    stack_guard_size: u64,
+    preloads: Vec<(u64, Bytes)>,
+    initial_registers: Option<[u64; RISCV_GENERAL_REGISTER_NUMBER]>,
+    entry_override: Option<u64>,
+    isa: Option<MachineIsa>,
+}
+
+impl<'a, Inner> DefaultMachineBuilder<'a, Inner> {
+    pub fn new(inner: Inner) -> Self {
+        Self {
+            inner,
+            instruction_cycle_func: None,
+            memory_cycle_func: None,
+            debugger: None,
+            syscalls: vec![],
+            syscall_registry: SyscallRegistry::default(),
+            on_instruction: None,
+            on_cycles: None,
+            exit_handler: None,
+            program_validator: None,
+            custom_instructions: BTreeMap::new(),
+            syscall_log: false,
+            checked_arithmetic: false,
+            opcode_stats: false,
+            record_instructions: false,
+            state_commitment: None,
+            load_bias: 0,
+            pause_on_limit: false,
+            yield_every: None,
+            reject_rvc: false,
+            decoded_program: None,
+            fixed_counters: false,
+            max_instructions: None,
+            max_call_depth: None,
+            stack_guard_size: 0,
+            preloads: vec![],
+            initial_registers: None,
+            entry_override: None,
+            isa: None,
+        }
+    }
+
+    pub fn instruction_cycle_func(
+        mut self,
+        instruction_cycle_func: Box<InstructionCycleFunc>,
+    ) -> Self {
+        self.instruction_cycle_func = Some(instruction_cycle_func);
+        self
+    }
+
+    // Charges memory accesses by kind and bytes touched on top of the
+    // instruction cycles, for cost models weighting loads and stores by
+    // size. This applies to all machine kinds.
+    pub fn memory_cycle_func(mut self, memory_cycle_func: Box<MemoryCycleFunc>) -> Self {
+        self.memory_cycle_func = Some(memory_cycle_func);
+        self
+    }
+
+    pub fn syscall(mut self, syscall: Box<dyn Syscalls<Inner> + 'a>) -> Self {
+        self.syscalls.push(syscall);
+        self
+    }
+
+    // Adds a RandomSyscall seeded with seed, programs can then request
+    // reproducible pseudo-random bytes. It is off by default so consensus
+    // machines never expose it.
+    pub fn random_seed(mut self, seed: u64) -> Self
+    where
+        Inner: SupportMachine,
+    {
+        self.syscalls.push(Box::new(RandomSyscall::new(seed)));
+        self
+    }
+
+    // Adds a MprotectSyscall, so programs can change page permissions at
+    // runtime. Only memories tracking permissions such as WXorXMemory
+    // support it.
+    pub fn mprotect_syscall(mut self) -> Self
+    where
+        Inner: SupportMachine,
+    {
+        self.syscalls.push(Box::new(MprotectSyscall::new()));
+        self
+    }
+
+    // Adds a DebugSyscall served under number, writes of programs to stdout
+    // and stderr go to the given sinks, so printf debugging works in tests
+    // and simulators. See syscalls::debug::DEBUG_WRITE_SYSCALL_NUMBER for
+    // the usual number.
+    #[cfg(feature = "std")]
+    pub fn debug_output(
+        mut self,
+        number: u64,
+        stdout: Box<dyn std::io::Write + 'a>,
+        stderr: Box<dyn std::io::Write + 'a>,
+    ) -> Self
+    where
+        Inner: SupportMachine,
+    {
+        self.syscalls.push(Box::new(
+            DebugSyscall::new(number).stdout(stdout).stderr(stderr),
+        ));
+        self
+    }
+
+    // Adds a HeapSyscalls with the heap starting at heap_start, see
+    // HeapSyscalls::for_program to start it right after a program.
+    pub fn heap_syscalls(mut self, heap_start: u64) -> Self
+    where
+        Inner: SupportMachine,
+    {
+        self.syscalls.push(Box::new(HeapSyscalls::new(heap_start)));
+        self
+    }
+
+    pub fn syscall_handler(mut self, number: u64, handler: Box<SyscallHandler<'a, Inner>>) -> Self
+    where
+        Inner: SupportMachine,
+    {
+        self.syscall_registry.register(number, handler);
+        self
+    }
+
+    // Registers a Rust function with typed arguments as the handler of a
+    // syscall number, see HostFn.
+    pub fn host_fn<Args, F: HostFn<'a, Inner, Args>>(mut self, number: u64, f: F) -> Self
+    where
+        Inner: SupportMachine,
+    {
+        self.syscall_registry.register_fn(number, f);
+        self
+    }
+
+    pub fn syscall_fallback(mut self, fallback: Box<SyscallFallback<'a, Inner>>) -> Self
+    where
+        Inner: SupportMachine,
+    {
+        self.syscall_registry.set_fallback(fallback);
+        self
+    }
+
+    // Charges cycles on each invocation of the syscall, see
+    // SyscallRegistry::set_cycles.
+    pub fn syscall_cycles(mut self, number: u64, cycles: u64) -> Self
+    where
+        Inner: SupportMachine,
+    {
+        self.syscall_registry.set_cycles(number, cycles);
+        self
+    }
+
+    pub fn on_instruction(mut self, on_instruction: Box<InstructionHookFunc<'a, Inner>>) -> Self {
+        self.on_instruction = Some(on_instruction);
+        self
+    }
+
+    // Reports cycles as they are consumed, e.g. to warn about programs
+    // getting close to the limit or to schedule machines adaptively. The
+    // asm machine charges cycles in native code, so there the hook is
+    // called in batches whenever native code returns to Rust.
+    pub fn on_cycles(mut self, on_cycles: Box<CyclesHookFunc<'a>>) -> Self {
+        self.on_cycles = Some(on_cycles);
+        self
+    }
+
+    // Lets the host check the exit code against the return conventions of
+    // its programs, or collect final state before the machine is torn
+    // down. See ExitHandler.
+    pub fn exit_handler(mut self, exit_handler: Box<ExitHandler<'a, Inner>>) -> Self {
+        self.exit_handler = Some(exit_handler);
+        self
+    }
+
+    // Checks programs before they can run, e.g. to enforce code signing,
+    // scan for forbidden instructions or limit sizes. Runs on every
+    // load_program, load_program_with_env and load_flat_program, see
+    // ProgramValidator.
+    pub fn program_validator(mut self, program_validator: Box<ProgramValidator<'a>>) -> Self {
+        self.program_validator = Some(program_validator);
+        self
+    }
+
+    // Executes custom instructions with id through handler, programs using
+    // them must be run with a decoder including a factory producing them,
+    // see instructions::custom. Only interpreted machines support them.
+    pub fn custom_instruction(
+        mut self,
+        id: u8,
+        handler: Box<CustomInstructionHandler<'a, Inner>>,
+    ) -> Self {
+        self.custom_instructions.insert(id, handler);
+        self
+    }
+
+    // Records every ecall with its arguments, result and the cycles at the
+    // time of the call, see DefaultMachine::syscall_log. Useful to debug
+    // mismatches between what programs pass and what syscalls expect.
+    pub fn syscall_log(mut self, enabled: bool) -> Self {
+        self.syscall_log = enabled;
+        self
+    }
+
+    // Records add, sub and mul instructions overflowing as signed
+    // operations together with their pc, see DefaultMachine::overflow_log.
+    // Results are not changed, this only helps finding arithmetic bugs in
+    // programs. Only interpreted machines check arithmetic.
+    pub fn checked_arithmetic(mut self, enabled: bool) -> Self {
+        self.checked_arithmetic = enabled;
+        self
+    }
+
+    // Counts the instructions executed per opcode, see
+    // DefaultMachine::opcode_stats. Useful to tune cycle models and to find
+    // out which extensions programs rely on. Only interpreted machines
+    // count opcodes.
+    pub fn opcode_stats(mut self, enabled: bool) -> Self {
+        self.opcode_stats = enabled;
+        self
+    }
+
+    // Records every executed instruction, see
+    // DefaultMachine::instruction_recording and DefaultMachine::replay.
+    // Only interpreted machines record instructions.
+    pub fn record_instructions(mut self, enabled: bool) -> Self {
+        self.record_instructions = enabled;
+        self
+    }
+
+    // Maintains a rolling hash of the executed state transitions computed
+    // with hash, updated after each instruction or basic block, see
+    // StateCommitment and DefaultMachine::state_commitment. Only
+    // interpreted machines compute it, and memory written by vector stores
+    // is not covered.
+    pub fn state_commitment(
+        mut self,
+        hash: Box<StateHashFunc>,
+        granularity: CommitmentGranularity,
+    ) -> Self {
+        self.state_commitment = Some(StateCommitment::new(hash, granularity));
+        self
+    }
+
+    pub fn debugger(mut self, debugger: Box<dyn Debugger<Inner> + 'a>) -> Self {
+        self.debugger = Some(debugger);
+        self
+    }
+
+    // Address position independent executables are loaded at, it must be
+    // page aligned. Programs linked to fixed addresses are not affected.
+    pub fn load_bias(mut self, load_bias: u64) -> Self {
+        self.load_bias = load_bias;
+        self
+    }
+
+    // Limits the address space of the machine, memory_size must be a power
+    // of 2 that holds at least the default stack. Fails with
+    // Error::InvalidMemorySize when the memory used by the machine does not
+    // support the given size.
+    pub fn memory_size(mut self, memory_size: usize) -> Result<Self, Error>
+    where
+        Inner: SupportMachine,
+    {
+        self.inner.memory_mut().set_memory_size(memory_size)?;
+        Ok(self)
+    }
+
+    pub fn max_cycles(mut self, max_cycles: u64) -> Self
+    where
+        Inner: SupportMachine,
+    {
+        self.inner.set_max_cycles(max_cycles);
+        self
+    }
+
+    // Rejects writes to code that has been executed, see
+    // WXorXMemory::set_forbid_self_modifying_code.
+    pub fn forbid_self_modifying_code<R, M>(mut self, forbid: bool) -> Self
+    where
+        R: Register,
+        M: Memory<R>,
+        Inner: SupportMachine<REG = R, MEM = WXorXMemory<R, M>>,
+    {
+        self.inner
+            .memory_mut()
+            .set_forbid_self_modifying_code(forbid);
+        self
+    }
+
+    // Makes run_resumable return RunState::Paused when max cycles is
+    // reached, leaving the machine at the instruction that could not be
+    // paid for. Syscalls charging cycles themselves should do so before
+    // any other side effect, since the ecall is executed again on resume.
+    pub fn pause_on_limit(mut self, pause_on_limit: bool) -> Self {
+        self.pause_on_limit = pause_on_limit;
+        self
+    }
+
+    // Makes run_resumable return RunState::Yielded each time the given
+    // number of instructions has been executed, so async hosts can
+    // interleave many machines on one thread. 0 disables yielding, which is
+    // the default.
+    pub fn yield_every(mut self, instructions: u64) -> Self {
+        self.yield_every = if instructions > 0 {
+            Some(instructions)
+        } else {
+            None
+        };
+        self
+    }
+
+    // Limits the number of instructions executed regardless of their
+    // cycles, exceeding it stops the machine with
+    // Error::InstructionLimitExceeded. The asm machine does not enforce it.
+    pub fn max_instructions(mut self, max_instructions: u64) -> Self {
+        self.max_instructions = if max_instructions > 0 {
+            Some(max_instructions)
+        } else {
+            None
+        };
+        self
+    }
+
+    // Limits how deeply calls can nest, calling deeper stops the machine
+    // with Error::CallDepthExceeded before the call. Calls and returns are
+    // recognized from jal and jalr using ra or t0 as link register, as
+    // return address predictors do, so a limit bounds recursion even when
+    // a program keeps its frames small enough to stay within the stack
+    // guard. A value of 0 means there is no limit. Only interpreted
+    // machines enforce it.
+    pub fn max_call_depth(mut self, max_call_depth: u64) -> Self {
+        self.max_call_depth = if max_call_depth > 0 {
+            Some(max_call_depth)
+        } else {
+            None
+        };
+        self
+    }
+
+    // Machines accept compressed instructions by default, disabling RVC
+    // makes them fail with Error::InvalidInstruction as on an ISA without
+    // the C extension. This only applies to interpreted machines.
+    pub fn rvc(mut self, rvc: bool) -> Self {
+        self.reject_rvc = !rvc;
+        self
+    }
+
+    // Makes rdcycle, rdtime and rdinstret always read 0, so results of
+    // programs do not depend on the cycle model or on how instructions are
+    // counted, as required under consensus. See build_imac_counter_decoder.
+    pub fn fixed_counters(mut self, fixed_counters: bool) -> Self {
+        self.fixed_counters = fixed_counters;
+        self
+    }
+
+    // Shares instructions decoded ahead of time with DecodedProgram::new,
+    // so machines running the same program skip most of the decoding work.
+    // Code missing from the decoded program is decoded as usual.
+    pub fn decoded_program(mut self, decoded_program: Arc<DecodedProgram>) -> Self {
+        self.decoded_program = Some(decoded_program);
+        self
+    }
+
+    // Reserves size bytes below the stack set up by load_program, writes
+    // into them stop the machine with Error::StackOverflow instead of
+    // silently corrupting the heap. The memory must support watchpoints,
+    // and the asm machine does not enforce the guard.
+    pub fn stack_guard_size(mut self, size: u64) -> Self {
+        self.stack_guard_size = size;
+        self
+    }
+
+    // Writes data at addr each time a program is loaded, after the ELF
+    // segments and the stack, so hosts can inject input at a known address.
+    // Loading fails with Error::PreloadOverlap when data overlaps a program
+    // segment or other preloaded data.
+    pub fn preload(mut self, addr: u64, data: Bytes) -> Self {
+        self.preloads.push((addr, data));
+        self
+    }
+
+    // Registers set each time a program is loaded, after the stack and
+    // preloads, e.g. arguments of a single guest function under test. x0
+    // is ignored and a zero sp keeps the stack set up by load_program.
+    pub fn initial_registers(mut self, registers: [u64; RISCV_GENERAL_REGISTER_NUMBER]) -> Self {
+        self.initial_registers = Some(registers);
+        self
+    }
+
+    // Starts execution at pc instead of the entry point of loaded programs,
+    // ProgramMetadata::entry reports pc as well.
+    pub fn entry_override(mut self, pc: u64) -> Self {
+        self.entry_override = Some(pc);
+        self
+    }
+
+    // Declares the instruction set the machine runs, which should match its
+    // registers and decoder. load_program and load_program_with_env then
+    // fail with Error::UnsupportedIsa when the ELF header of a program
+    // requires another register width or extensions isa lacks, e.g. the
+    // compressed instructions implied by EF_RISCV_RVC or the floating point
+    // ones implied by its float ABI. Flat binaries carry no header and are
+    // not checked.
+    pub fn isa(mut self, isa: MachineIsa) -> Self {
+        self.isa = Some(isa);
+        self
+    }
+
+    pub fn build(self) -> DefaultMachine<'a, Inner> {
+        DefaultMachine {
             inner: self.inner,
             instruction_cycle_func: self.instruction_cycle_func,
+            memory_cycle_func: self.memory_cycle_func,
             debugger: self.debugger,
             syscalls: self.syscalls,
+            syscall_registry: self.syscall_registry,
+            on_instruction: self.on_instruction,
+            on_cycles: self.on_cycles,
+            exit_handler: self.exit_handler,
+            program_validator: self.program_validator,
+            custom_instructions: self.custom_instructions,
+            syscall_log: if self.syscall_log {
+                Some(Vec::new())
+            } else {
+                None
+            },
+            syscall_counts: BTreeMap::new(),
+            overflow_log: if self.checked_arithmetic {
+                Some(Vec::new())
+            } else {
+                None
+            },
+            opcode_stats: if self.opcode_stats {
+                Some(vec![0; MAXIMUM_OPCODE as usize + 1])
+            } else {
+                None
+            },
+            instruction_recording: if self.record_instructions {
+                Some(InstructionRecording::new())
+            } else {
+                None
+            },
+            state_commitment: self.state_commitment,
+            #[cfg(feature = "fd")]
+            float_registers: FloatRegisters::default(),
+            #[cfg(feature = "v-ext")]
+            vector_registers: VectorRegisters::default(),
+            reservation: Reservation::default(),
+            load_bias: self.load_bias,
+            pause_on_limit: self.pause_on_limit,
+            yield_every: self.yield_every,
+            reject_rvc: self.reject_rvc,
+            decoded_program: self.decoded_program,
+            fixed_counters: self.fixed_counters,
+            instructions: 0,
+            max_instructions: self.max_instructions,
+            call_depth: 0,
+            retried_pc: None,
+            max_call_depth: self.max_call_depth,
+            interrupt: InterruptHandle::default(),
+            #[cfg(feature = "async")]
+            async_ecall: AsyncEcall::Disabled,
+            stack_guard_size: self.stack_guard_size,
+            stack_guard: None,
+            preloads: self.preloads,
+            initial_registers: self.initial_registers,
+            entry_override: self.entry_override,
+            isa: self.isa,
+            #[cfg(feature = "symbols")]
+            symbols: Symbols::default(),
+            program_metadata: None,
+            last_fault: None,
             exit_code: 0,
         }
     }
@@ -0,0 +1,128 @@
+use crate::instructions::{extract_opcode, insts, Instruction, Itype, Register, Rtype};
+
+/// Signed overflow of an add, sub or mul instruction, recorded by machines
+/// with checked arithmetic enabled, see
+/// DefaultMachineBuilder::checked_arithmetic. The instruction itself still
+/// wraps around as RISC-V specifies.
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub struct OverflowRecord {
+    pub pc: u64,
+    pub instruction: Instruction,
+    // Operands right before the instruction ran, immediates are sign
+    // extended.
+    pub lhs: u64,
+    pub rhs: u64,
+}
+
+#[derive(Clone, Copy)]
+enum ArithmeticOp {
+    Add,
+    Sub,
+    Mul,
+}
+
+// Returns the operands of instruction when it is an add, sub or mul that
+// overflows as a signed operation of its width. Unsigned wrap around is not
+// reported, since adding negative offsets to addresses relies on it.
+pub fn signed_overflow<R: Register>(
+    instruction: Instruction,
+    registers: &[R],
+) -> Option<(u64, u64)> {
+    let rtype = |op, word| {
+        let i = Rtype(instruction);
+        (op, word, i.rs1(), Some(i.rs2()), 0)
+    };
+    let itype = |op, word| {
+        let i = Itype(instruction);
+        (op, word, i.rs1(), None, i.immediate_s() as i64 as u64)
+    };
+    let (op, word, rs1, rs2, imm) = match extract_opcode(instruction) {
+        insts::OP_ADD | insts::OP_RVC_ADD => rtype(ArithmeticOp::Add, false),
+        insts::OP_ADDW | insts::OP_RVC_ADDW => rtype(ArithmeticOp::Add, true),
+        insts::OP_SUB | insts::OP_RVC_SUB => rtype(ArithmeticOp::Sub, false),
+        insts::OP_SUBW | insts::OP_RVC_SUBW => rtype(ArithmeticOp::Sub, true),
+        insts::OP_MUL => rtype(ArithmeticOp::Mul, false),
+        insts::OP_MULW => rtype(ArithmeticOp::Mul, true),
+        insts::OP_ADDI | insts::OP_RVC_ADDI => itype(ArithmeticOp::Add, false),
+        insts::OP_ADDIW | insts::OP_RVC_ADDIW => itype(ArithmeticOp::Add, true),
+        _ => return None,
+    };
+    let lhs = registers.get(rs1)?.to_u64();
+    let rhs = match rs2 {
+        Some(rs2) => registers.get(rs2)?.to_u64(),
+        None => imm,
+    };
+    let overflow = if word || R::BITS == 32 {
+        let (a, b) = (lhs as i32, rhs as i32);
+        match op {
+            ArithmeticOp::Add => a.checked_add(b),
+            ArithmeticOp::Sub => a.checked_sub(b),
+            ArithmeticOp::Mul => a.checked_mul(b),
+        }
+        .is_none()
+    } else {
+        let (a, b) = (lhs as i64, rhs as i64);
+        match op {
+            ArithmeticOp::Add => a.checked_add(b),
+            ArithmeticOp::Sub => a.checked_sub(b),
+            ArithmeticOp::Mul => a.checked_mul(b),
+        }
+        .is_none()
+    };
+    if overflow {
+        Some((lhs, rhs))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RISCV_GENERAL_REGISTER_NUMBER;
+
+    fn registers(a1: u64, a2: u64) -> Vec<u64> {
+        let mut registers = vec![0; RISCV_GENERAL_REGISTER_NUMBER];
+        registers[11] = a1;
+        registers[12] = a2;
+        registers
+    }
+
+    #[test]
+    fn test_signed_overflow() {
+        let add = Rtype::new(insts::OP_ADD, 10, 11, 12).0;
+        let addw = Rtype::new(insts::OP_ADDW, 10, 11, 12).0;
+        let sub = Rtype::new(insts::OP_SUB, 10, 11, 12).0;
+        let mul = Rtype::new(insts::OP_MUL, 10, 11, 12).0;
+        let addi = Itype::new_s(insts::OP_ADDI, 10, 11, -16).0;
+        let xor = Rtype::new(insts::OP_XOR, 10, 11, 12).0;
+
+        let max = i64::MAX as u64;
+        assert_eq!(signed_overflow(add, &registers(max, 1)), Some((max, 1)));
+        assert_eq!(signed_overflow(add, &registers(max, u64::MAX)), None);
+        assert_eq!(signed_overflow(addw, &registers(max, 1)), None);
+        assert_eq!(
+            signed_overflow(addw, &registers(0x7fff_ffff, 1)),
+            Some((0x7fff_ffff, 1))
+        );
+        assert_eq!(
+            signed_overflow(sub, &registers(1 << 63, 1)),
+            Some((1 << 63, 1))
+        );
+        assert_eq!(
+            signed_overflow(mul, &registers(1 << 32, 1 << 32)),
+            Some((1 << 32, 1 << 32))
+        );
+        assert_eq!(signed_overflow(mul, &registers(u64::MAX, u64::MAX)), None);
+        // Negative offsets on addresses wrap around unsigned only
+        assert_eq!(signed_overflow(addi, &registers(0x1000, 0)), None);
+        assert_eq!(
+            signed_overflow(addi, &registers(1 << 63, 0)),
+            Some((1 << 63, -16i64 as u64))
+        );
+        assert_eq!(signed_overflow(xor, &registers(max, 1)), None);
+
+        let registers32: Vec<u32> = vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x7fff_ffff, 1];
+        assert_eq!(signed_overflow(add, &registers32), Some((0x7fff_ffff, 1)));
+    }
+}
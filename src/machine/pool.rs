@@ -0,0 +1,57 @@
+use super::{DefaultMachine, SupportMachine};
+use crate::Error;
+use alloc::{boxed::Box, vec::Vec};
+
+// Builds a fresh machine when the pool has no idle one to hand out.
+pub type MachineFactory<'a, Inner> = dyn Fn() -> DefaultMachine<'a, Inner> + 'a;
+
+/// Keeps machines around between runs so hosts running many small programs
+/// do not pay for allocating memory each time. Machines are reset when they
+/// are given back, so a machine taken from the pool never carries state
+/// from a previous run.
+pub struct MachinePool<'a, Inner> {
+    factory: Box<MachineFactory<'a, Inner>>,
+    idle: Vec<DefaultMachine<'a, Inner>>,
+    max_idle: usize,
+}
+
+impl<'a, Inner: SupportMachine> MachinePool<'a, Inner> {
+    // At most max_idle machines are kept, machines given back beyond that
+    // are dropped.
+    pub fn new(max_idle: usize, factory: Box<MachineFactory<'a, Inner>>) -> Self {
+        Self {
+            factory,
+            idle: Vec::new(),
+            max_idle,
+        }
+    }
+
+    // Number of machines ready to be taken without building a new one
+    pub fn idle(&self) -> usize {
+        self.idle.len()
+    }
+
+    pub fn max_idle(&self) -> usize {
+        self.max_idle
+    }
+
+    pub fn take(&mut self) -> DefaultMachine<'a, Inner> {
+        self.idle.pop().unwrap_or_else(|| (self.factory)())
+    }
+
+    // Resets the machine and keeps it for later runs. Machines whose memory
+    // cannot be reset are dropped, the error is returned so hosts know
+    // pooling does not help with their memory implementation.
+    pub fn give_back(&mut self, mut machine: DefaultMachine<'a, Inner>) -> Result<(), Error> {
+        machine.reset()?;
+        if self.idle.len() < self.max_idle {
+            self.idle.push(machine);
+        }
+        Ok(())
+    }
+
+    // Drops all idle machines, releasing their memory
+    pub fn clear(&mut self) {
+        self.idle.clear();
+    }
+}
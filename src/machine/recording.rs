@@ -0,0 +1,182 @@
+use crate::Error;
+use alloc::vec::Vec;
+
+/// An instruction executed during a recorded run.
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub struct RecordedInstruction {
+    pub pc: u64,
+    // Raw instruction word, compressed instructions only use the lower 16
+    // bits as with Decoder::decode_raw.
+    pub bits: u32,
+}
+
+/// Instruction stream of a run, recorded by DefaultMachine when instruction
+/// recording is enabled and re-executed by DefaultMachine::replay. Entries
+/// are compressed: the pc is stored as a zigzag LEB128 delta from the pc
+/// following the previous instruction, which is a single byte for
+/// straight-line code, followed by the instruction word in 2 or 4 little
+/// endian bytes depending on its length. The encoding is returned by
+/// as_bytes, so recordings taken in production can be stored and loaded
+/// back with from_bytes.
+#[derive(Debug, PartialEq, Clone, Eq, Default)]
+pub struct InstructionRecording {
+    data: Vec<u8>,
+    len: u64,
+    next_pc: u64,
+}
+
+fn instruction_length(bits: u32) -> u64 {
+    if bits & 0x3 == 0x3 {
+        4
+    } else {
+        2
+    }
+}
+
+impl InstructionRecording {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, pc: u64, bits: u32) {
+        let length = instruction_length(bits);
+        let delta = pc.wrapping_sub(self.next_pc) as i64;
+        let mut zigzag = ((delta << 1) ^ (delta >> 63)) as u64;
+        loop {
+            let byte = (zigzag & 0x7f) as u8;
+            zigzag >>= 7;
+            if zigzag == 0 {
+                self.data.push(byte);
+                break;
+            }
+            self.data.push(byte | 0x80);
+        }
+        self.data
+            .extend_from_slice(&bits.to_le_bytes()[..length as usize]);
+        self.len += 1;
+        self.next_pc = pc.wrapping_add(length);
+    }
+
+    // Number of recorded instructions
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    // Loads a recording from the encoding returned by as_bytes, failing
+    // with Error::ParseError when it is truncated or malformed.
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self, Error> {
+        let mut len = 0;
+        let mut entries = Entries::new(&data);
+        while entries.offset < data.len() {
+            entries.next().ok_or(Error::ParseError)?;
+            len += 1;
+        }
+        let next_pc = entries.next_pc;
+        Ok(Self { data, len, next_pc })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = RecordedInstruction> + '_ {
+        Entries::new(&self.data)
+    }
+}
+
+struct Entries<'a> {
+    data: &'a [u8],
+    offset: usize,
+    next_pc: u64,
+}
+
+impl<'a> Entries<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            offset: 0,
+            next_pc: 0,
+        }
+    }
+}
+
+impl Iterator for Entries<'_> {
+    type Item = RecordedInstruction;
+
+    fn next(&mut self) -> Option<RecordedInstruction> {
+        let mut zigzag: u64 = 0;
+        let mut shift = 0;
+        loop {
+            let byte = *self.data.get(self.offset)?;
+            self.offset += 1;
+            if shift > 63 {
+                return None;
+            }
+            zigzag |= u64::from(byte & 0x7f) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        let delta = ((zigzag >> 1) as i64) ^ -((zigzag & 1) as i64);
+        let pc = self.next_pc.wrapping_add(delta as u64);
+        let low = self.data.get(self.offset..self.offset + 2)?;
+        let mut bits = u32::from(u16::from_le_bytes([low[0], low[1]]));
+        if instruction_length(bits) == 4 {
+            let high = self.data.get(self.offset + 2..self.offset + 4)?;
+            bits |= u32::from(u16::from_le_bytes([high[0], high[1]])) << 16;
+        }
+        self.offset += instruction_length(bits) as usize;
+        self.next_pc = pc.wrapping_add(instruction_length(bits));
+        Some(RecordedInstruction { pc, bits })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encoding() {
+        let mut recording = InstructionRecording::new();
+        recording.push(0x1000, 0x00000513);
+        recording.push(0x1004, 0x4501);
+        recording.push(0x0ff0, 0xfe059ae3);
+        // Straight-line code costs a single byte per pc
+        assert_eq!(recording.as_bytes().len(), 6 + 3 + 5);
+        let instructions: Vec<_> = recording.iter().collect();
+        assert_eq!(
+            instructions,
+            [
+                RecordedInstruction {
+                    pc: 0x1000,
+                    bits: 0x00000513
+                },
+                RecordedInstruction {
+                    pc: 0x1004,
+                    bits: 0x4501
+                },
+                RecordedInstruction {
+                    pc: 0x0ff0,
+                    bits: 0xfe059ae3
+                },
+            ]
+        );
+        let loaded = InstructionRecording::from_bytes(recording.as_bytes().to_vec()).unwrap();
+        assert_eq!(loaded, recording);
+        let mut truncated = recording.as_bytes().to_vec();
+        truncated.pop();
+        assert_eq!(
+            InstructionRecording::from_bytes(truncated),
+            Err(Error::ParseError)
+        );
+    }
+}
@@ -0,0 +1,62 @@
+use alloc::{collections::BTreeMap, vec::Vec};
+
+/// Resources a run consumed, see DefaultMachine::resource_report. Every
+/// field only depends on the program, its inputs and the machine settings,
+/// so machines agreeing on a run produce identical reports that chains can
+/// commit to, e.g. by hashing to_bytes.
+#[derive(Debug, PartialEq, Clone, Eq, Hash, Default)]
+pub struct ResourceReport {
+    pub cycles: u64,
+    pub instructions: u64,
+    // Completed ecalls per syscall number
+    pub syscalls: BTreeMap<u64, u64>,
+    // Pages accessed by the program, see Memory::high_watermark
+    pub pages_touched: u64,
+    pub exit_code: i8,
+}
+
+impl ResourceReport {
+    // Canonical encoding, all integers are little-endian and syscalls are
+    // ordered by number:
+    //
+    //     cycles: u64, instructions: u64, pages_touched: u64, exit_code: i8,
+    //     syscall count: u64, then (number: u64, calls: u64) per syscall
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(33 + self.syscalls.len() * 16);
+        bytes.extend_from_slice(&self.cycles.to_le_bytes());
+        bytes.extend_from_slice(&self.instructions.to_le_bytes());
+        bytes.extend_from_slice(&self.pages_touched.to_le_bytes());
+        bytes.extend_from_slice(&self.exit_code.to_le_bytes());
+        bytes.extend_from_slice(&(self.syscalls.len() as u64).to_le_bytes());
+        for (number, calls) in &self.syscalls {
+            bytes.extend_from_slice(&number.to_le_bytes());
+            bytes.extend_from_slice(&calls.to_le_bytes());
+        }
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_bytes() {
+        let mut report = ResourceReport {
+            cycles: 0x0102,
+            instructions: 3,
+            syscalls: BTreeMap::new(),
+            pages_touched: 4,
+            exit_code: -1,
+        };
+        report.syscalls.insert(93, 1);
+        report.syscalls.insert(64, 2);
+        let bytes = report.to_bytes();
+        assert_eq!(bytes.len(), 33 + 32);
+        assert_eq!(&bytes[..2], &[2, 1]);
+        assert_eq!(bytes[24], 0xff);
+        // Syscalls are ordered by number whatever the insertion order
+        assert_eq!(&bytes[33..41], &64u64.to_le_bytes());
+        assert_eq!(&bytes[49..57], &93u64.to_le_bytes());
+    }
+}
@@ -0,0 +1,119 @@
+use crate::Error;
+use alloc::{collections::BTreeMap, string::String};
+use bytes::Bytes;
+use core::fmt;
+use goblin::elf::header::ET_DYN;
+use goblin::elf::section_header::SHF_EXECINSTR;
+use goblin::elf::sym::{STT_FUNC, STT_NOTYPE};
+use goblin::elf::Elf;
+
+/// Code symbols of a program from its .symtab and .strtab sections, used to
+/// show function names for pcs. Symbols of size 0, such as labels in
+/// assembly files, cover the code up to the next symbol.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Symbols {
+    // Start address -> (size, name)
+    entries: BTreeMap<u64, (u64, String)>,
+}
+
+/// A pc resolved to the symbol containing it, displayed as name+offset.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Symbol<'a> {
+    pub name: &'a str,
+    pub offset: u64,
+}
+
+impl fmt::Display for Symbol<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.offset == 0 {
+            write!(f, "{}", self.name)
+        } else {
+            write!(f, "{}+{:#x}", self.name, self.offset)
+        }
+    }
+}
+
+impl Symbols {
+    // Reads symbols the same way load_program maps the program, so
+    // position independent programs are shifted by load_bias. Stripped
+    // programs simply have no symbols.
+    pub fn from_elf(program: &Bytes, load_bias: u64) -> Result<Self, Error> {
+        let elf = Elf::parse(program).map_err(|_e| Error::ParseError)?;
+        let bias = if elf.header.e_type == ET_DYN {
+            load_bias
+        } else {
+            0
+        };
+        let mut result = Self::default();
+        for sym in elf.syms.iter() {
+            if sym.st_type() != STT_FUNC && sym.st_type() != STT_NOTYPE {
+                continue;
+            }
+            let executable = elf
+                .section_headers
+                .get(sym.st_shndx)
+                .map(|section| section.sh_flags & u64::from(SHF_EXECINSTR) != 0)
+                .unwrap_or(false);
+            let name = match elf.strtab.get_unsafe(sym.st_name) {
+                Some(name) if executable && !name.is_empty() => name,
+                _ => continue,
+            };
+            let start = sym.st_value.wrapping_add(bias);
+            // Functions win over labels sharing their address
+            match result.entries.get(&start) {
+                Some((size, _)) if *size > 0 && sym.st_size == 0 => (),
+                _ => {
+                    result.entries.insert(start, (sym.st_size, name.into()));
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Symbol containing addr, if any
+    pub fn lookup(&self, addr: u64) -> Option<Symbol<'_>> {
+        let (start, (size, name)) = self.entries.range(..=addr).next_back()?;
+        let offset = addr - start;
+        if *size > 0 && offset >= *size {
+            return None;
+        }
+        Some(Symbol {
+            name: name.as_str(),
+            offset,
+        })
+    }
+
+    // Start address of the symbol called name, if any
+    pub fn address(&self, name: &str) -> Option<u64> {
+        self.entries
+            .iter()
+            .find(|(_, (_, n))| n == name)
+            .map(|(start, _)| *start)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        let mut symbols = Symbols::default();
+        symbols.entries.insert(0x100, (0x10, "sized".into()));
+        symbols.entries.insert(0x200, (0, "label".into()));
+        assert_eq!(symbols.lookup(0xff), None);
+        assert_eq!(symbols.lookup(0x100).unwrap().to_string(), "sized");
+        assert_eq!(symbols.lookup(0x10e).unwrap().to_string(), "sized+0xe");
+        assert_eq!(symbols.lookup(0x110), None);
+        assert_eq!(symbols.lookup(0x1234).unwrap().to_string(), "label+0x1034");
+        assert_eq!(symbols.address("label"), Some(0x200));
+    }
+}
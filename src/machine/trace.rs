@@ -1,41 +1,130 @@
+#[cfg(feature = "fd")]
+use super::super::instructions::fd::FloatRegisters;
+#[cfg(feature = "v-ext")]
+use super::super::instructions::v::VectorRegisters;
+#[cfg(all(feature = "std", not(target_family = "wasm")))]
+use super::DEADLINE_CHECK_INTERVAL;
+#[cfg(has_jit)]
+use super::{
+    super::RISCV_GENERAL_REGISTER_NUMBER,
+    jit::{NativeBlock, DEFAULT_JIT_THRESHOLD},
+};
 use super::{
     super::{
-        decoder::build_imac_decoder,
+        decoder::{build_imac_decoder, Decoder},
         instructions::{
-            execute, instruction_length, is_basic_block_end_instruction, Instruction, Register,
+            a::Reservation, execute, instruction_length, is_basic_block_end_instruction,
+            zicntr::Counters, Instruction, Register,
         },
         memory::{wxorx::WXorXMemory, Memory},
-        Error,
+        snapshot::Snapshot,
+        Error, Fault,
     },
-    CoreMachine, DefaultMachine, Machine, SupportMachine,
+    coverage::Coverage,
+    decoded::DecodedProgram,
+    fusion::{evaluate, fuse, Fused},
+    CoreMachine, CyclesHookFunc, DefaultMachine, InstructionHookFunc, Machine, ProgramMetadata,
+    RunResult, SupportMachine,
 };
+use alloc::collections::BTreeMap;
+use alloc::{boxed::Box, sync::Arc, vec, vec::Vec};
 use bytes::Bytes;
+#[cfg(all(feature = "std", not(target_family = "wasm")))]
+use std::time::Instant;
 
-// The number of trace items to keep
-const TRACE_SIZE: usize = 8192;
-// Quick bit-mask to truncate a value in trace size range
-const TRACE_MASK: usize = (TRACE_SIZE - 1);
-// The maximum number of instructions to cache in a trace item
-const TRACE_ITEM_LENGTH: usize = 16;
-// Shifts to truncate a value so 2 traces has the minimal chance of sharing code.
+// The default number of trace items to keep
+pub const TRACE_SIZE: usize = 8192;
+// The default maximum number of instructions to cache in a trace item
+pub const TRACE_ITEM_LENGTH: usize = 16;
+// The largest number of ways a set associative trace cache can have, see
+// TraceMachine::set_trace_ways
+pub const MAXIMUM_TRACE_WAYS: usize = 16;
 
 #[derive(Default)]
 struct Trace {
     address: u64,
     length: usize,
     instruction_count: u8,
-    instructions: [Instruction; TRACE_ITEM_LENGTH],
+    instructions: Vec<Instruction>,
+    // Pairs starting at each instruction that can be fused, see fusion
+    fused: Vec<Option<Fused>>,
+    // Executions since the trace item was filled, until it gets compiled
+    #[cfg(has_jit)]
+    hits: u32,
+    #[cfg(has_jit)]
+    native: Option<NativeBlock>,
+}
+
+// Shifts to truncate a value so 2 traces has the minimal chance of sharing code.
+// The mask here is a quick bit-mask to truncate a value in trace size range.
+#[inline(always)]
+fn calculate_slot(addr: u64, trace_mask: usize) -> usize {
+    (((addr >> 9).wrapping_add(addr) >> 1) & (trace_mask as u64)) as usize
+}
+
+// Tree pseudo-LRU state of a set, bit n tells which half of the subtree
+// rooted at node n, numbered from 1 as in a binary heap, holds the next
+// victim.
+#[inline(always)]
+fn plru_touch(bits: &mut u16, way: usize, ways: usize) {
+    let mut node = 1;
+    let mut half = ways >> 1;
+    while half > 0 {
+        let right = way & half != 0;
+        // The victim is looked for in the other half from now on
+        if right {
+            *bits &= !(1 << node);
+        } else {
+            *bits |= 1 << node;
+        }
+        node = node * 2 + right as usize;
+        half >>= 1;
+    }
 }
 
 #[inline(always)]
-fn calculate_slot(addr: u64) -> usize {
-    (((addr >> 9).wrapping_add(addr) >> 1) & (TRACE_MASK as u64)) as usize
+fn plru_victim(bits: u16, ways: usize) -> usize {
+    let mut node = 1;
+    let mut way = 0;
+    let mut half = ways >> 1;
+    while half > 0 {
+        let right = bits & (1 << node) != 0;
+        if right {
+            way |= half;
+        }
+        node = node * 2 + right as usize;
+        half >>= 1;
+    }
+    way
 }
 
 pub struct TraceMachine<'a, Inner> {
     pub machine: DefaultMachine<'a, Inner>,
 
     traces: Vec<Trace>,
+    trace_size: usize,
+    trace_mask: usize,
+    trace_item_length: usize,
+    // Trace items per set, 1 for a direct mapped cache. Sets are selected
+    // by set_mask and replace their items in pseudo-LRU order.
+    ways: usize,
+    set_mask: usize,
+    plru: Vec<u16>,
+    // Executions and cycles per trace item start address, only collected
+    // when profiling is enabled.
+    profile: Option<BTreeMap<u64, (u64, u64)>>,
+    // Executed trace item start addresses, only collected when coverage is
+    // enabled.
+    coverage: Option<Coverage>,
+    #[cfg(has_jit)]
+    jit_threshold: Option<u32>,
+    // Protection and code generations of memory when traces were last
+    // validated
+    protection_generation: u64,
+    code_generation: u64,
+    // Set by fence.i, traces are dropped before the next trace item runs
+    fenced: bool,
+    fusion: bool,
 }
 
 impl<R: Register, M: Memory<R>, Inner: SupportMachine<REG = R, MEM = WXorXMemory<R, M>>> CoreMachine
@@ -79,63 +168,543 @@ impl<R: Register, M: Memory<R>, Inner: SupportMachine<REG = R, MEM = WXorXMemory
     fn ebreak(&mut self) -> Result<(), Error> {
         self.machine.ebreak()
     }
+
+    fn reservation_mut(&mut self) -> Option<&mut Reservation> {
+        self.machine.reservation_mut()
+    }
+
+    #[cfg(feature = "fd")]
+    fn float_registers_mut(&mut self) -> Option<&mut FloatRegisters> {
+        self.machine.float_registers_mut()
+    }
+
+    #[cfg(feature = "v-ext")]
+    fn vector_registers_mut(&mut self) -> Option<&mut VectorRegisters> {
+        self.machine.vector_registers_mut()
+    }
+
+    fn counters(&self) -> Option<Counters> {
+        self.machine.counters()
+    }
+
+    fn execute_custom(&mut self, id: u8, bits: u32) -> Result<Option<Self::REG>, Error> {
+        self.machine.execute_custom(id, bits)
+    }
+
+    // fence.i ends trace items, so the current one is never dropped while
+    // it runs.
+    fn fence_i(&mut self) {
+        self.fenced = true;
+    }
 }
 
 impl<'a, R: Register, M: Memory<R>, Inner: SupportMachine<REG = R, MEM = WXorXMemory<R, M>>>
     TraceMachine<'a, Inner>
 {
     pub fn new(machine: DefaultMachine<'a, Inner>) -> Self {
+        Self::with_capacity(machine, TRACE_SIZE, TRACE_ITEM_LENGTH)
+    }
+
+    // Trace size must be a power of 2 so slots can be calculated with a
+    // bit-mask, while trace item length must be a power of 2 that fits in
+    // the u8 instruction counter of each trace item.
+    pub fn with_capacity(
+        machine: DefaultMachine<'a, Inner>,
+        trace_size: usize,
+        trace_item_length: usize,
+    ) -> Self {
+        assert!(trace_size.is_power_of_two());
+        assert!(trace_item_length.is_power_of_two());
+        assert!(trace_item_length <= 255);
         Self {
             machine,
             traces: vec![],
+            trace_size,
+            trace_mask: trace_size - 1,
+            trace_item_length,
+            ways: 1,
+            set_mask: trace_size - 1,
+            plru: vec![],
+            profile: None,
+            coverage: None,
+            #[cfg(has_jit)]
+            jit_threshold: Some(DEFAULT_JIT_THRESHOLD),
+            protection_generation: 0,
+            code_generation: 0,
+            fenced: false,
+            fusion: true,
+        }
+    }
+
+    pub fn trace_size(&self) -> usize {
+        self.trace_size
+    }
+
+    pub fn trace_item_length(&self) -> usize {
+        self.trace_item_length
+    }
+
+    pub fn trace_ways(&self) -> usize {
+        self.ways
+    }
+
+    // Turns the trace cache into a set associative one with ways trace
+    // items per set, so hot blocks mapping to the same slot stop evicting
+    // each other. Items are replaced in pseudo-LRU order within a set, at
+    // the cost of looking up all ways of the set for each trace item run.
+    // ways must be a power of 2 no larger than MAXIMUM_TRACE_WAYS or the
+    // trace size, 1 goes back to the direct mapped cache. Cached traces are
+    // dropped.
+    pub fn set_trace_ways(&mut self, ways: usize) {
+        assert!(ways.is_power_of_two());
+        assert!(ways <= MAXIMUM_TRACE_WAYS && ways <= self.trace_size);
+        let sets = self.trace_size / ways;
+        self.ways = ways;
+        self.set_mask = sets - 1;
+        self.plru = if ways > 1 { vec![0; sets] } else { vec![] };
+        self.traces.clear();
+    }
+
+    // Slot of the trace item starting at pc, or of the item to replace
+    // with it.
+    #[inline(always)]
+    fn find_slot(&mut self, pc: u64) -> usize {
+        if self.ways == 1 {
+            return calculate_slot(pc, self.trace_mask);
         }
+        let set = calculate_slot(pc, self.set_mask);
+        let first = set * self.ways;
+        let items = &self.traces[first..first + self.ways];
+        let way = items
+            .iter()
+            .position(|t| t.address == pc && t.instruction_count > 0)
+            .or_else(|| items.iter().position(|t| t.instruction_count == 0))
+            .unwrap_or_else(|| plru_victim(self.plru[set], self.ways));
+        plru_touch(&mut self.plru[set], way, self.ways);
+        first + way
     }
 
-    pub fn load_program(&mut self, program: &Bytes, args: &[Bytes]) -> Result<u64, Error> {
+    // Common pairs of adjacent instructions such as lui+addi, auipc+ld or
+    // slli+add are executed as one, cycles charged stay the sum of both
+    // instructions. Pairs are run one instruction at a time whenever
+    // something inspects every instruction, see
+    // DefaultMachine::inspects_instructions. Enabled by default.
+    pub fn set_fusion(&mut self, enabled: bool) {
+        self.fusion = enabled;
+    }
+
+    pub fn fusion(&self) -> bool {
+        self.fusion
+    }
+
+    // Enabling profiling starts with empty statistics, disabling it drops
+    // collected statistics.
+    pub fn set_profiling(&mut self, enabled: bool) {
+        self.profile = if enabled { Some(BTreeMap::new()) } else { None };
+    }
+
+    pub fn profiling(&self) -> bool {
+        self.profile.is_some()
+    }
+
+    // Returns (address, hits, cycles) for each executed block sorted by
+    // address. Blocks are trace items, so a basic block longer than the
+    // trace item length shows up as several consecutive entries.
+    pub fn profile(&self) -> Vec<(u64, u64, u64)> {
+        self.profile
+            .iter()
+            .flatten()
+            .map(|(address, (hits, cycles))| (*address, *hits, *cycles))
+            .collect()
+    }
+
+    // Enabling coverage starts with an empty bitmap, disabling it drops the
+    // collected one. Blocks are trace items as with profiling, collecting
+    // coverage is much cheaper though.
+    pub fn set_coverage(&mut self, enabled: bool) {
+        self.coverage = if enabled { Some(Coverage::new()) } else { None };
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    // Returns the collected coverage, leaving an empty bitmap in place so
+    // the next run is recorded separately.
+    pub fn take_coverage(&mut self) -> Option<Coverage> {
+        self.coverage.as_mut().map(core::mem::take)
+    }
+
+    pub fn last_fault(&self) -> Option<Fault> {
+        self.machine.last_fault()
+    }
+
+    // Trace items executed more than threshold times get compiled to native
+    // code, None keeps all code interpreted.
+    #[cfg(has_jit)]
+    pub fn set_jit_threshold(&mut self, threshold: Option<u32>) {
+        self.jit_threshold = threshold;
+    }
+
+    #[cfg(has_jit)]
+    pub fn jit_threshold(&self) -> Option<u32> {
+        self.jit_threshold
+    }
+
+    // Number of cached trace items currently running native code
+    #[cfg(has_jit)]
+    pub fn compiled_traces(&self) -> usize {
+        self.traces
+            .iter()
+            .filter(|trace| {
+                trace
+                    .native
+                    .as_ref()
+                    .map(|block| block.instruction_count() > 0)
+                    .unwrap_or(false)
+            })
+            .count()
+    }
+
+    pub fn set_on_instruction(&mut self, on_instruction: Box<InstructionHookFunc<'a, Inner>>) {
+        self.machine.set_on_instruction(on_instruction);
+    }
+
+    pub fn set_decoded_program(&mut self, decoded_program: Option<Arc<DecodedProgram>>) {
+        self.machine.set_decoded_program(decoded_program);
+    }
+
+    pub fn set_on_cycles(&mut self, on_cycles: Box<CyclesHookFunc<'a>>) {
+        self.machine.set_on_cycles(on_cycles);
+    }
+
+    pub fn load_program(
+        &mut self,
+        program: &Bytes,
+        args: &[Bytes],
+    ) -> Result<ProgramMetadata, Error> {
         self.machine.load_program(program, args)
     }
 
+    pub fn load_program_with_env(
+        &mut self,
+        program: &Bytes,
+        args: &[Bytes],
+        envs: &[Bytes],
+        random: &[u8; 16],
+    ) -> Result<ProgramMetadata, Error> {
+        self.machine
+            .load_program_with_env(program, args, envs, random)
+    }
+
+    pub fn load_flat_program(
+        &mut self,
+        addr: u64,
+        binary: &Bytes,
+        entry: u64,
+        args: &[Bytes],
+    ) -> Result<ProgramMetadata, Error> {
+        self.machine.load_flat_program(addr, binary, entry, args)
+    }
+
+    pub fn snapshot(&mut self) -> Result<Snapshot, Error> {
+        self.machine.snapshot()
+    }
+
+    // Cached traces are dropped after resuming, since they might no longer
+    // match the code in restored memory.
+    pub fn resume(&mut self, snapshot: &Snapshot) -> Result<(), Error> {
+        self.machine.resume(snapshot)?;
+        self.traces.clear();
+        Ok(())
+    }
+
+    // Resets the underlying machine, see DefaultMachine::reset. Cached
+    // traces are dropped, profiling and coverage restart from empty
+    // statistics when enabled.
+    pub fn reset(&mut self) -> Result<(), Error> {
+        self.machine.reset()?;
+        self.traces.clear();
+        if let Some(profile) = &mut self.profile {
+            profile.clear();
+        }
+        if let Some(coverage) = &mut self.coverage {
+            coverage.clear();
+        }
+        Ok(())
+    }
+
     pub fn run(&mut self) -> Result<i8, Error> {
+        self.run_with_result().into_result()
+    }
+
+    pub fn run_with_result(&mut self) -> RunResult {
+        let decoder = build_imac_decoder::<Inner::REG>();
+        self.run_with_decoder_result(&decoder)
+    }
+
+    // Runs the program with a custom decoder, hosts can use this to enable
+    // extra extensions such as B for a single machine.
+    pub fn run_with_decoder(&mut self, decoder: &Decoder) -> Result<i8, Error> {
+        self.run_with_decoder_result(decoder).into_result()
+    }
+
+    pub fn run_with_decoder_result(&mut self, decoder: &Decoder) -> RunResult {
+        self.machine.set_running(true);
+        self.machine.clear_fault();
+        let mut result = Ok(());
+        while self.machine.running() && result.is_ok() {
+            result = self.step(decoder);
+        }
+        let result = result.map(|_| self.machine.exit_code());
+        let mut run_result = RunResult::new(&self.machine, result);
+        run_result.fault = self.machine.last_fault();
+        run_result
+    }
+
+    // Like DefaultMachine::run_with_deadline, trace items are the basic
+    // blocks counted between reads of the clock.
+    #[cfg(all(feature = "std", not(target_family = "wasm")))]
+    pub fn run_with_deadline(&mut self, deadline: Instant) -> Result<i8, Error> {
         let decoder = build_imac_decoder::<Inner::REG>();
+        self.run_with_decoder_and_deadline(&decoder, deadline)
+    }
+
+    #[cfg(all(feature = "std", not(target_family = "wasm")))]
+    pub fn run_with_decoder_and_deadline(
+        &mut self,
+        decoder: &Decoder,
+        deadline: Instant,
+    ) -> Result<i8, Error> {
         self.machine.set_running(true);
-        // For current trace size this is acceptable, however we might want
-        // to tweak the code here if we choose to use a larger trace size or
-        // larger trace item length.
-        self.traces.resize_with(TRACE_SIZE, Trace::default);
+        self.machine.clear_fault();
+        if Instant::now() >= deadline {
+            return Err(Error::Deadline);
+        }
+        let mut blocks = 0;
         while self.machine.running() {
-            let pc = self.machine.pc().to_u64();
-            let slot = calculate_slot(pc);
-            if pc != self.traces[slot].address || self.traces[slot].instruction_count == 0 {
-                self.traces[slot] = Trace::default();
-                let mut current_pc = pc;
-                let mut i = 0;
-                while i < TRACE_ITEM_LENGTH {
-                    let instruction = decoder.decode(self.machine.memory_mut(), current_pc)?;
-                    let end_instruction = is_basic_block_end_instruction(instruction);
-                    current_pc += u64::from(instruction_length(instruction));
-                    self.traces[slot].instructions[i] = instruction;
-                    i += 1;
-                    if end_instruction {
-                        break;
+            self.step(decoder)?;
+            blocks += 1;
+            if blocks % DEADLINE_CHECK_INTERVAL == 0 && Instant::now() >= deadline {
+                return Err(Error::Deadline);
+            }
+        }
+        Ok(self.machine.exit_code())
+    }
+
+    // Executes exactly one trace item starting from current PC, decoding and
+    // caching the trace first if needed. The machine is left in a resumable
+    // state, so this can be used to drive execution from outside.
+    pub fn step(&mut self, decoder: &Decoder) -> Result<(), Error> {
+        // Trace items are allocated lazily so creating a machine stays cheap,
+        // instruction buffers of each item are allocated when first filled.
+        // Code cached from pages that lost execute permission must be
+        // fetched again, so the permission check takes place. Likewise for
+        // code that has been overwritten, or that a fence.i asked for, which
+        // drops compiled code as well.
+        let protection_generation = self.machine.memory().protection_generation();
+        let code_generation = self.machine.memory().code_generation();
+        if protection_generation != self.protection_generation
+            || code_generation != self.code_generation
+            || self.fenced
+        {
+            self.traces.clear();
+            self.protection_generation = protection_generation;
+            self.code_generation = code_generation;
+            self.fenced = false;
+        }
+        if self.traces.len() != self.trace_size {
+            self.traces.resize_with(self.trace_size, Trace::default);
+        }
+        let pc = self.machine.pc().to_u64();
+        let slot = self.find_slot(pc);
+        // When programs are allowed to modify their own code, memory tells
+        // when code that has been fetched is overwritten through the code
+        // generation, the rest of the trace item is then decoded again.
+        // Forbidding writes to code that has been decoded makes this
+        // unnecessary.
+        let self_modifying = self.machine.memory().allow_self_modifying_code()
+            && !self.machine.memory().forbid_self_modifying_code();
+        if pc != self.traces[slot].address || self.traces[slot].instruction_count == 0 {
+            self.traces[slot].instruction_count = 0;
+            self.traces[slot].instructions.clear();
+            self.traces[slot].fused.clear();
+            let mut current_pc = pc;
+            let mut i = 0;
+            // Previous instruction and its pc, unless it is part of a pair
+            let mut previous: Option<(Instruction, u64)> = None;
+            while i < self.trace_item_length {
+                let instruction = match self.machine.decode_instruction(decoder, current_pc) {
+                    Ok(instruction) => instruction,
+                    Err(e) => {
+                        self.machine.record_fault(e, current_pc, None);
+                        return Err(e);
+                    }
+                };
+                let end_instruction = is_basic_block_end_instruction(instruction);
+                let instruction_pc = current_pc;
+                current_pc += u64::from(instruction_length(instruction));
+                self.traces[slot].instructions.push(instruction);
+                self.traces[slot].fused.push(None);
+                i += 1;
+                // auipc ends basic blocks, the trace item still goes on
+                // with the next instruction when both can be fused.
+                if end_instruction && i < self.trace_item_length {
+                    if let Ok(next) = self.machine.decode_instruction(decoder, current_pc) {
+                        if let Some(fused) = fuse(instruction, next, instruction_pc) {
+                            current_pc += u64::from(instruction_length(next));
+                            self.traces[slot].instructions.push(next);
+                            self.traces[slot].fused[i - 1] = Some(fused);
+                            self.traces[slot].fused.push(None);
+                            i += 1;
+                        }
                     }
                 }
-                self.traces[slot].address = pc;
-                self.traces[slot].length = (current_pc - pc) as usize;
-                self.traces[slot].instruction_count = i as u8;
+                if end_instruction {
+                    break;
+                }
+                let fused = previous
+                    .and_then(|(previous, previous_pc)| fuse(previous, instruction, previous_pc));
+                if fused.is_some() {
+                    self.traces[slot].fused[i - 2] = fused;
+                    previous = None;
+                } else {
+                    previous = Some((instruction, instruction_pc));
+                }
             }
-            for i in 0..self.traces[slot].instruction_count {
-                let i = self.traces[slot].instructions[i as usize];
-                execute(i, self)?;
-                let cycles = self
-                    .machine
-                    .instruction_cycle_func()
-                    .as_ref()
-                    .map(|f| f(i))
-                    .unwrap_or(0);
-                self.machine.add_cycles(cycles)?;
+            self.traces[slot].address = pc;
+            self.traces[slot].length = (current_pc - pc) as usize;
+            self.traces[slot].instruction_count = i as u8;
+            #[cfg(has_jit)]
+            {
+                self.traces[slot].hits = 0;
+                self.traces[slot].native = None;
             }
         }
-        Ok(self.machine.exit_code())
+        if let Some(coverage) = &mut self.coverage {
+            coverage.mark(pc);
+        }
+        let (native_count, mut block_cycles) = self.run_native(slot, self_modifying);
+        let fusion = self.fusion && !self.machine.inspects_instructions();
+        let mut result = Ok(());
+        let mut index = native_count as usize;
+        while index < self.traces[slot].instruction_count as usize {
+            if fusion {
+                if let Some(cycles) = self.run_fused(slot, index) {
+                    block_cycles += cycles;
+                    index += 2;
+                    continue;
+                }
+            }
+            let i = self.traces[slot].instructions[index];
+            index += 1;
+            let current_pc = self.machine.pc().to_u64();
+            let pending = match self.machine.start_instruction(current_pc, i) {
+                Ok(pending) => pending,
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            };
+            block_cycles += pending.cycles;
+            result = execute(i, self);
+            result = self.machine.finish_instruction(pending, result);
+            if result.is_err() {
+                break;
+            }
+            if self_modifying && self.machine.memory().code_generation() != code_generation {
+                break;
+            }
+        }
+        if let Some(profile) = &mut self.profile {
+            let entry = profile.entry(pc).or_insert((0, 0));
+            entry.0 += 1;
+            entry.1 += block_cycles;
+        }
+        // Trace items end with basic blocks, stop requests are looked at
+        // in between
+        result.and_then(|_| self.machine.check_interrupt())
+    }
+
+    // Runs the pair of instructions at index of the trace item in slot as
+    // one when they were fused, returning the cycles charged. When the pair
+    // would fail or exceed a limit, it is left to the interpreter so the
+    // error is raised by the exact instruction.
+    fn run_fused(&mut self, slot: usize, index: usize) -> Option<u64> {
+        let fused = self.traces[slot].fused[index]?;
+        let first = self.traces[slot].instructions[index];
+        let second = self.traces[slot].instructions[index + 1];
+        let (rd, value) = evaluate(fused, &mut self.machine).ok()?;
+        let cycles = self
+            .machine
+            .instruction_cycles(first)
+            .checked_add(self.machine.instruction_cycles(second))?;
+        self.machine.check_instructions(2).ok()?;
+        self.machine.add_cycles(cycles).ok()?;
+        self.machine.add_instructions(2);
+        self.machine.set_register(rd, value);
+        let length = instruction_length(first) + instruction_length(second);
+        let next_pc = self.machine.pc().to_u64().wrapping_add(u64::from(length));
+        self.machine.set_pc(R::from_u64(next_pc));
+        Some(cycles)
+    }
+
+    // Runs the compiled part of the trace item in slot, compiling it first
+    // when it just became hot. Returns the number of instructions executed
+    // and the cycles charged for them, the interpreter takes over from there.
+    #[cfg(has_jit)]
+    fn run_native(&mut self, slot: usize, self_modifying: bool) -> (u8, u64) {
+        // Native code follows RV64 semantics only
+        let threshold = match self.jit_threshold {
+            Some(threshold)
+                if !self_modifying && R::BITS == 64 && !self.machine.inspects_instructions() =>
+            {
+                threshold
+            }
+            _ => return (0, 0),
+        };
+        let trace = &mut self.traces[slot];
+        if trace.native.is_none() {
+            trace.hits = trace.hits.saturating_add(1);
+            if trace.hits <= threshold {
+                return (0, 0);
+            }
+            let instructions = &trace.instructions[..trace.instruction_count as usize];
+            let instruction_cycle_func = self.machine.instruction_cycle_func().as_deref();
+            trace.native = Some(NativeBlock::compile(instructions, instruction_cycle_func));
+        }
+        let block = match &trace.native {
+            Some(block) if block.instruction_count() > 0 => block,
+            _ => return (0, 0),
+        };
+        // When a limit would be exceeded, the interpreter runs the trace item
+        // instead so the error is raised by the exact instruction.
+        let count = u64::from(block.instruction_count());
+        if self.machine.check_instructions(count).is_err()
+            || self.machine.add_cycles(block.cycles()).is_err()
+        {
+            return (0, 0);
+        }
+        self.machine.add_instructions(count);
+        let mut registers = [0; RISCV_GENERAL_REGISTER_NUMBER];
+        for (value, register) in registers.iter_mut().zip(self.machine.registers()) {
+            *value = register.to_u64();
+        }
+        block.call(&mut registers);
+        for (i, value) in registers.iter().enumerate() {
+            if block.written() & (1 << i) != 0 {
+                self.machine.set_register(i, R::from_u64(*value));
+            }
+        }
+        let next_pc = self.machine.pc().to_u64().wrapping_add(block.length());
+        self.machine.set_pc(R::from_u64(next_pc));
+        (block.instruction_count(), block.cycles())
+    }
+
+    #[cfg(not(has_jit))]
+    #[inline(always)]
+    fn run_native(&mut self, _slot: usize, _self_modifying: bool) -> (u8, u64) {
+        (0, 0)
     }
 }
 
@@ -143,11 +712,144 @@ impl<'a, R: Register, M: Memory<R>, Inner: SupportMachine<REG = R, MEM = WXorXMe
 mod tests {
     use super::*;
 
+    use crate::{
+        memory::{FLAG_EXECUTABLE, FLAG_WRITABLE},
+        registers::{A0, A1, A2, T0},
+        DefaultCoreMachine, DefaultMachineBuilder, SparseMemory,
+    };
+
+    type TestMachine<'a> =
+        TraceMachine<'a, DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>>;
+
     #[test]
     fn test_trace_constant_rules() {
         assert!(TRACE_SIZE.is_power_of_two());
-        assert_eq!(TRACE_MASK, TRACE_SIZE - 1);
         assert!(TRACE_ITEM_LENGTH.is_power_of_two());
         assert!(TRACE_ITEM_LENGTH <= 255);
+
+        let machine = TestMachine::new(DefaultMachine::default());
+        assert_eq!(machine.trace_mask, TRACE_SIZE - 1);
+        assert_eq!(machine.trace_item_length, TRACE_ITEM_LENGTH);
+    }
+
+    #[test]
+    fn test_trace_with_capacity() {
+        let machine = TestMachine::with_capacity(DefaultMachine::default(), 64, 4);
+        assert_eq!(machine.trace_size(), 64);
+        assert_eq!(machine.trace_mask, 63);
+        assert_eq!(machine.trace_item_length(), 4);
+    }
+
+    #[test]
+    fn test_plru() {
+        let mut bits = 0;
+        for way in 0..4 {
+            plru_touch(&mut bits, way, 4);
+        }
+        assert_eq!(plru_victim(bits, 4), 0);
+        plru_touch(&mut bits, 0, 4);
+        assert_eq!(plru_victim(bits, 4), 2);
+        plru_touch(&mut bits, 2, 4);
+        assert_eq!(plru_victim(bits, 4), 1);
+        // A single way set always replaces its only item
+        assert_eq!(plru_victim(0, 1), 0);
+    }
+
+    #[test]
+    fn test_set_trace_ways() {
+        let mut machine = TestMachine::with_capacity(DefaultMachine::default(), 64, 4);
+        machine.set_trace_ways(4);
+        assert_eq!(machine.trace_ways(), 4);
+        assert_eq!(machine.set_mask, 15);
+        assert_eq!(machine.plru.len(), 16);
+        machine.set_trace_ways(1);
+        assert_eq!(machine.set_mask, 63);
+    }
+
+    // lui a0, 0x12345; addi a0, a0, 0x678; slli a1, a2, 3; add a1, a1, a0;
+    // auipc t0, 1; ld t0, -16(t0)
+    fn fusion_machine<'a>(max_cycles: u64) -> TestMachine<'a> {
+        let code: Vec<u8> = [
+            0x12345537u32,
+            0x67850513,
+            0x00361593,
+            0x00a585b3,
+            0x00001297,
+            0xff02b283,
+        ]
+        .iter()
+        .flat_map(|i| i.to_le_bytes())
+        .collect();
+        let core = DefaultCoreMachine::new_with_max_cycles(max_cycles);
+        let mut machine = TestMachine::new(
+            DefaultMachineBuilder::new(core)
+                .instruction_cycle_func(Box::new(|_| 1))
+                .build(),
+        );
+        let memory = machine.memory_mut();
+        memory
+            .init_pages(0x1000, 0x1000, FLAG_EXECUTABLE, Some(code.into()), 0)
+            .unwrap();
+        memory
+            .init_pages(0x2000, 0x1000, FLAG_WRITABLE, None, 0)
+            .unwrap();
+        memory.store64(&0x2000, &0x1122334455667788).unwrap();
+        machine.set_pc(0x1000);
+        machine.set_register(A2, 5);
+        machine.machine.set_running(true);
+        machine
+    }
+
+    #[test]
+    fn test_trace_fusion() {
+        let decoder = build_imac_decoder::<u64>();
+        let mut results = vec![];
+        for fusion in [true, false] {
+            let mut machine = fusion_machine(100);
+            machine.set_fusion(fusion);
+            // auipc ends the basic block, the trace item goes on with ld
+            machine.step(&decoder).unwrap();
+            let slot = machine.find_slot(0x1000);
+            assert_eq!(machine.traces[slot].instruction_count, 6);
+            assert_eq!(
+                machine.traces[slot]
+                    .fused
+                    .iter()
+                    .map(Option::is_some)
+                    .collect::<Vec<_>>(),
+                [true, false, true, false, true, false]
+            );
+            assert_eq!(machine.registers()[A0], 0x12345678);
+            assert_eq!(machine.registers()[A1], 0x12345678 + (5 << 3));
+            assert_eq!(machine.registers()[T0], 0x1122334455667788);
+            assert_eq!(*machine.pc(), 0x1018);
+            results.push((machine.machine.cycles(), machine.machine.instructions()));
+        }
+        assert_eq!(results, [(6, 6), (6, 6)]);
+
+        // Pairs exceeding a limit run one instruction at a time, so the
+        // error comes from the exact instruction
+        let mut machine = fusion_machine(3);
+        assert_eq!(machine.step(&decoder), Err(Error::CyclesExceeded));
+        assert_eq!(machine.registers()[A1], 5 << 3);
+        assert_eq!(*machine.pc(), 0x100c);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_trace_ways_must_be_power_of_two() {
+        TestMachine::new(DefaultMachine::default()).set_trace_ways(3);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_trace_size_must_be_power_of_two() {
+        TestMachine::with_capacity(DefaultMachine::default(), 100, 16);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_trace_item_length_must_fit_in_byte() {
+        TestMachine::with_capacity(DefaultMachine::default(), 8192, 256);
     }
 }
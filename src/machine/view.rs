@@ -0,0 +1,126 @@
+use super::{CoreMachine, DefaultMachine, SupportMachine};
+use crate::{
+    memory::{Memory, Page},
+    Error, Register, RISCV_PAGESIZE,
+};
+use alloc::{collections::BTreeMap, sync::Arc, vec, vec::Vec};
+
+/// Read-only copy of the state of a machine, taken with DefaultMachine::view
+/// between runs, e.g. each time run_resumable yields. Registers and pages
+/// are kept behind Arc, so views are cheap to clone and can be handed to
+/// other threads for dashboards or sampling profilers while the machine
+/// keeps running. Pages unchanged since the previous view are shared with
+/// it instead of being copied again.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MachineSnapshotView {
+    pc: u64,
+    registers: Arc<[u64]>,
+    cycles: u64,
+    instructions: u64,
+    memory_size: u64,
+    // Page index -> content, pages missing here only hold zeros
+    pages: Arc<BTreeMap<u64, Arc<Page>>>,
+}
+
+impl MachineSnapshotView {
+    pub(crate) fn new<Inner: SupportMachine>(
+        machine: &mut DefaultMachine<'_, Inner>,
+        previous: Option<&MachineSnapshotView>,
+    ) -> Result<Self, Error> {
+        let memory_size = machine.memory().memory_size() as u64;
+        let mut pages = BTreeMap::new();
+        for page in 0..memory_size / RISCV_PAGESIZE as u64 {
+            let content = machine
+                .memory_mut()
+                .dump_range(page * RISCV_PAGESIZE as u64, RISCV_PAGESIZE as u64)?;
+            if content.iter().all(|b| *b == 0) {
+                continue;
+            }
+            let shared = previous
+                .and_then(|previous| previous.pages.get(&page))
+                .filter(|previous| previous[..] == content[..]);
+            let page_content = match shared {
+                Some(shared) => Arc::clone(shared),
+                None => {
+                    let mut data = [0; RISCV_PAGESIZE];
+                    data.copy_from_slice(&content);
+                    Arc::new(data)
+                }
+            };
+            pages.insert(page, page_content);
+        }
+        Ok(Self {
+            pc: machine.pc().to_u64(),
+            registers: machine.registers().iter().map(|r| r.to_u64()).collect(),
+            cycles: machine.cycles(),
+            instructions: machine.instructions(),
+            memory_size,
+            pages: Arc::new(pages),
+        })
+    }
+
+    pub fn pc(&self) -> u64 {
+        self.pc
+    }
+
+    pub fn registers(&self) -> &[u64] {
+        &self.registers
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    pub fn memory_size(&self) -> u64 {
+        self.memory_size
+    }
+
+    // Number of pages holding non zero bytes
+    pub fn pages(&self) -> usize {
+        self.pages.len()
+    }
+
+    // Number of pages whose content is shared with other, rather than
+    // copied when this view was taken.
+    pub fn shared_pages(&self, other: &MachineSnapshotView) -> usize {
+        self.pages
+            .iter()
+            .filter(|(page, content)| {
+                other
+                    .pages
+                    .get(page)
+                    .is_some_and(|other| Arc::ptr_eq(content, other))
+            })
+            .count()
+    }
+
+    pub fn load_bytes(&self, addr: u64, size: u64) -> Result<Vec<u8>, Error> {
+        let end = addr.checked_add(size).ok_or(Error::OutOfBound)?;
+        if end > self.memory_size {
+            return Err(Error::OutOfBound);
+        }
+        let mut result = vec![0; size as usize];
+        let mut current = addr;
+        while current < end {
+            let page = current / RISCV_PAGESIZE as u64;
+            let offset = (current % RISCV_PAGESIZE as u64) as usize;
+            let len = (RISCV_PAGESIZE - offset).min((end - current) as usize);
+            if let Some(content) = self.pages.get(&page) {
+                let start = (current - addr) as usize;
+                result[start..start + len].copy_from_slice(&content[offset..offset + len]);
+            }
+            current += len as u64;
+        }
+        Ok(result)
+    }
+
+    pub fn load64(&self, addr: u64) -> Result<u64, Error> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&self.load_bytes(addr, 8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+}
@@ -0,0 +1,338 @@
+use super::super::{
+    machine::{DefaultCoreMachine, SupportMachine},
+    Error, Register, RISCV_MAX_MEMORY, RISCV_PAGES, RISCV_PAGESIZE,
+};
+use super::{
+    dirty::DirtyPages,
+    fill_page_data,
+    flat::FlatMemory,
+    mappings::{Mapping, MappingTable},
+    memset, round_page_down, round_page_up,
+    watchpoint::{WatchpointKind, Watchpoints},
+    Memory, Page,
+};
+
+use alloc::sync::Arc;
+use alloc::{vec, vec::Vec};
+use bytes::Bytes;
+use core::cmp::min;
+use core::marker::PhantomData;
+
+const INVALID_PAGE_INDEX: u16 = 0xFFFF;
+
+// Loads the segments of an ELF program into a memory image starting from
+// address 0, trailing zero pages are trimmed. The image only needs to be
+// built once, it can then be shared by all CowMemory instances running the
+// same program.
+pub fn build_image<R: Register>(program: &Bytes) -> Result<Arc<[u8]>, Error> {
+    let mut machine = DefaultCoreMachine::<R, FlatMemory<R>>::default();
+    machine.load_elf(program, false)?;
+    let memory = machine.take_memory();
+    let end = memory
+        .iter()
+        .rposition(|b| *b != 0)
+        .map(|p| round_page_up(p as u64 + 1) as usize)
+        .unwrap_or(0);
+    Ok(Arc::from(&memory[..end]))
+}
+
+/// A copy-on-write memory implementation, reads are served from a shared
+/// read-only image until a page is first modified, at which point a private
+/// copy of that page is allocated. Like SparseMemory, it does no permission
+/// checking. Writes that leave a shared page unchanged, such as loading the
+/// same program the image is built from, do not allocate private pages.
+pub struct CowMemory<R> {
+    image: Arc<[u8]>,
+    // Indices of private pages in pages data structure, pages that are still
+    // shared are filled with INVALID_PAGE_INDEX.
+    indices: [u16; RISCV_PAGES],
+    pages: Vec<Page>,
+    watchpoints: Watchpoints,
+    dirty: DirtyPages,
+    mappings: MappingTable,
+    _inner: PhantomData<R>,
+}
+
+impl<R> CowMemory<R> {
+    pub fn new(image: Arc<[u8]>) -> Self {
+        debug_assert!(RISCV_PAGES < INVALID_PAGE_INDEX as usize);
+        Self {
+            image,
+            indices: [INVALID_PAGE_INDEX; RISCV_PAGES],
+            pages: Vec::new(),
+            watchpoints: Watchpoints::default(),
+            dirty: DirtyPages::new(RISCV_PAGES),
+            mappings: MappingTable::default(),
+            _inner: PhantomData,
+        }
+    }
+
+    pub fn image(&self) -> &Arc<[u8]> {
+        &self.image
+    }
+
+    // Number of pages copied out of the shared image or allocated because
+    // they are beyond it.
+    pub fn private_pages(&self) -> usize {
+        self.pages.len()
+    }
+
+    // Returns the part of the shared image within the range, bytes beyond
+    // the image are implicitly zero.
+    fn shared_bytes(&self, addr: u64, size: u64) -> &[u8] {
+        let len = self.image.len() as u64;
+        let start = min(addr, len) as usize;
+        let end = min(addr + size, len) as usize;
+        &self.image[start..end]
+    }
+
+    fn fetch_page(&mut self, aligned_addr: u64) -> Result<&mut Page, Error> {
+        let page = aligned_addr / RISCV_PAGESIZE as u64;
+        if page >= RISCV_PAGES as u64 {
+            return Err(Error::OutOfBound);
+        }
+        let mut index = self.indices[page as usize];
+        if index == INVALID_PAGE_INDEX {
+            let mut data = [0; RISCV_PAGESIZE];
+            let shared = self.shared_bytes(aligned_addr, RISCV_PAGESIZE as u64);
+            data[..shared.len()].copy_from_slice(shared);
+            self.pages.push(data);
+            index = (self.pages.len() - 1) as u16;
+            self.indices[page as usize] = index;
+        }
+        Ok(&mut self.pages[index as usize])
+    }
+
+    // Writes value to memory one page chunk at a time, pages that are still
+    // shared and already contain the expected content are left untouched.
+    fn write<F, G>(&mut self, addr: u64, size: u64, unchanged: F, mut write: G) -> Result<(), Error>
+    where
+        F: Fn(u64, &[u8], u64) -> bool,
+        G: FnMut(u64, &mut [u8]),
+    {
+        if addr.checked_add(size).ok_or(Error::OutOfBound)? > RISCV_MAX_MEMORY as u64 {
+            return Err(Error::OutOfBound);
+        }
+        self.dirty.mark(addr, size);
+        let mut current_page_addr = round_page_down(addr);
+        let mut current_page_offset = addr - current_page_addr;
+        let mut written = 0;
+        while written < size {
+            let bytes = min(RISCV_PAGESIZE as u64 - current_page_offset, size - written);
+            let start = current_page_addr + current_page_offset;
+            let shared_page =
+                self.indices[current_page_addr as usize / RISCV_PAGESIZE] == INVALID_PAGE_INDEX;
+            if !shared_page || !unchanged(written, self.shared_bytes(start, bytes), bytes) {
+                let page = self.fetch_page(current_page_addr)?;
+                write(
+                    written,
+                    &mut page[current_page_offset as usize..(current_page_offset + bytes) as usize],
+                );
+            }
+            written += bytes;
+            current_page_addr += RISCV_PAGESIZE as u64;
+            current_page_offset = 0;
+        }
+        Ok(())
+    }
+
+    fn read(&self, addr: u64, buffer: &mut [u8]) -> Result<(), Error> {
+        let size = buffer.len() as u64;
+        if addr.checked_add(size).ok_or(Error::OutOfBound)? > RISCV_MAX_MEMORY as u64 {
+            return Err(Error::OutOfBound);
+        }
+        let mut current_page_addr = round_page_down(addr);
+        let mut current_page_offset = addr - current_page_addr;
+        let mut read = 0;
+        while read < size {
+            let bytes = min(RISCV_PAGESIZE as u64 - current_page_offset, size - read);
+            let target = &mut buffer[read as usize..(read + bytes) as usize];
+            let index = self.indices[current_page_addr as usize / RISCV_PAGESIZE];
+            if index == INVALID_PAGE_INDEX {
+                let shared = self.shared_bytes(current_page_addr + current_page_offset, bytes);
+                target[..shared.len()].copy_from_slice(shared);
+                memset(&mut target[shared.len()..], 0);
+            } else {
+                let page = &self.pages[index as usize];
+                target.copy_from_slice(
+                    &page[current_page_offset as usize..(current_page_offset + bytes) as usize],
+                );
+            }
+            read += bytes;
+            current_page_addr += RISCV_PAGESIZE as u64;
+            current_page_offset = 0;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Register> Memory<R> for CowMemory<R> {
+    fn init_pages(
+        &mut self,
+        addr: u64,
+        size: u64,
+        _flags: u8,
+        source: Option<Bytes>,
+        offset_from_addr: u64,
+    ) -> Result<(), Error> {
+        fill_page_data(self, addr, size, source, offset_from_addr)
+    }
+
+    fn fetch_flag(&mut self, page: u64) -> Result<u8, Error> {
+        if page < RISCV_PAGES as u64 {
+            Ok(0)
+        } else {
+            Err(Error::OutOfBound)
+        }
+    }
+
+    // Shared image pages are not counted since they are not owned by this
+    // memory.
+    fn touched_memory(&self) -> usize {
+        self.pages.len() * RISCV_PAGESIZE
+    }
+
+    fn add_watchpoint(&mut self, addr: u64, len: u64, kind: WatchpointKind) -> Result<(), Error> {
+        self.watchpoints.add(addr, len, kind)
+    }
+
+    fn remove_watchpoint(
+        &mut self,
+        addr: u64,
+        len: u64,
+        kind: WatchpointKind,
+    ) -> Result<(), Error> {
+        self.watchpoints.remove(addr, len, kind);
+        Ok(())
+    }
+
+    fn dirty_pages(&self) -> Result<Vec<u64>, Error> {
+        Ok(self.dirty.pages())
+    }
+
+    fn clear_dirty(&mut self) -> Result<(), Error> {
+        self.dirty.clear();
+        Ok(())
+    }
+
+    // Private pages are dropped, all pages are shared with the image again
+    fn reset(&mut self) -> Result<(), Error> {
+        for index in self.indices.iter_mut() {
+            *index = INVALID_PAGE_INDEX;
+        }
+        self.pages.clear();
+        self.watchpoints.clear();
+        self.dirty.clear();
+        self.mappings.clear();
+        Ok(())
+    }
+
+    fn map_region(&mut self, addr: u64, len: u64, prot: u64, fixed: bool) -> Result<(), Error> {
+        let memory_size = Memory::<R>::memory_size(self) as u64;
+        self.mappings.map(addr, len, prot, fixed, memory_size)
+    }
+
+    fn unmap_region(&mut self, addr: u64, len: u64) -> Result<(), Error> {
+        let memory_size = Memory::<R>::memory_size(self) as u64;
+        self.mappings.unmap(addr, len, memory_size).map(|_| ())
+    }
+
+    fn mappings(&self) -> Vec<Mapping> {
+        self.mappings.mappings()
+    }
+
+    fn load8(&mut self, addr: &R) -> Result<R, Error> {
+        self.watchpoints.check(addr.to_u64(), 1, false)?;
+        let mut buffer = [0; 1];
+        self.read(addr.to_u64(), &mut buffer)?;
+        Ok(R::from_u8(buffer[0]))
+    }
+
+    fn load16(&mut self, addr: &R) -> Result<R, Error> {
+        self.watchpoints.check(addr.to_u64(), 2, false)?;
+        let v = self.execute_load16(addr.to_u64())?;
+        Ok(R::from_u16(v))
+    }
+
+    fn load32(&mut self, addr: &R) -> Result<R, Error> {
+        self.watchpoints.check(addr.to_u64(), 4, false)?;
+        let mut buffer = [0; 4];
+        self.read(addr.to_u64(), &mut buffer)?;
+        Ok(R::from_u32(u32::from_le_bytes(buffer)))
+    }
+
+    fn load64(&mut self, addr: &R) -> Result<R, Error> {
+        self.watchpoints.check(addr.to_u64(), 8, false)?;
+        let mut buffer = [0; 8];
+        self.read(addr.to_u64(), &mut buffer)?;
+        Ok(R::from_u64(u64::from_le_bytes(buffer)))
+    }
+
+    fn execute_load16(&mut self, addr: u64) -> Result<u16, Error> {
+        let mut buffer = [0; 2];
+        self.read(addr, &mut buffer)?;
+        Ok(u16::from_le_bytes(buffer))
+    }
+
+    fn store_bytes(&mut self, addr: u64, value: &[u8]) -> Result<(), Error> {
+        self.write(
+            addr,
+            value.len() as u64,
+            |offset, shared, size| {
+                let expected = &value[offset as usize..(offset + size) as usize];
+                let (head, tail) = expected.split_at(shared.len());
+                head == shared && tail.iter().all(|b| *b == 0)
+            },
+            |offset, target| {
+                target.copy_from_slice(&value[offset as usize..offset as usize + target.len()])
+            },
+        )
+    }
+
+    fn load_bytes(&mut self, addr: u64, size: u64) -> Result<Vec<u8>, Error> {
+        if addr.checked_add(size).ok_or(Error::OutOfBound)? > RISCV_MAX_MEMORY as u64 {
+            return Err(Error::OutOfBound);
+        }
+        let mut result = vec![0; size as usize];
+        self.read(addr, &mut result)?;
+        Ok(result)
+    }
+
+    fn store_byte(&mut self, addr: u64, size: u64, value: u8) -> Result<(), Error> {
+        self.write(
+            addr,
+            size,
+            |_, shared, size| {
+                shared.iter().all(|b| *b == value) && (value == 0 || shared.len() as u64 == size)
+            },
+            |_, target| memset(target, value),
+        )
+    }
+
+    fn store8(&mut self, addr: &R, value: &R) -> Result<(), Error> {
+        self.watchpoints.check(addr.to_u64(), 1, true)?;
+        self.store_bytes(addr.to_u64(), &[value.to_u8()])
+    }
+
+    fn store16(&mut self, addr: &R, value: &R) -> Result<(), Error> {
+        self.watchpoints.check(addr.to_u64(), 2, true)?;
+        // RISC-V is little-endian by specification
+        self.store_bytes(addr.to_u64(), &value.to_u16().to_le_bytes())
+    }
+
+    fn store32(&mut self, addr: &R, value: &R) -> Result<(), Error> {
+        self.watchpoints.check(addr.to_u64(), 4, true)?;
+        self.store_bytes(addr.to_u64(), &value.to_u32().to_le_bytes())
+    }
+
+    fn store64(&mut self, addr: &R, value: &R) -> Result<(), Error> {
+        self.watchpoints.check(addr.to_u64(), 8, true)?;
+        self.store_bytes(addr.to_u64(), &value.to_u64().to_le_bytes())
+    }
+}
+
+impl<R> Default for CowMemory<R> {
+    fn default() -> Self {
+        Self::new(Arc::from(Vec::new()))
+    }
+}
@@ -0,0 +1,93 @@
+use super::super::RISCV_PAGESIZE;
+
+use alloc::{vec, vec::Vec};
+
+// Bitmap of the pages written since the last clear, shared by memory
+// implementations to back Memory::dirty_pages. All writes are recorded,
+// including those done by the host through store_bytes or init_pages, even
+// when they leave the content unchanged.
+#[derive(Debug, Clone, Default)]
+pub struct DirtyPages {
+    bits: Vec<u64>,
+}
+
+impl DirtyPages {
+    pub fn new(pages: usize) -> Self {
+        Self {
+            bits: vec![0; pages.div_ceil(64)],
+        }
+    }
+
+    // Marks the pages overlapping [addr, addr + len), callers are expected
+    // to have checked the range is within memory.
+    #[inline(always)]
+    pub fn mark(&mut self, addr: u64, len: u64) {
+        if len == 0 {
+            return;
+        }
+        let first = addr / RISCV_PAGESIZE as u64;
+        let last = (addr + len - 1) / RISCV_PAGESIZE as u64;
+        for page in first..=last {
+            if let Some(bits) = self.bits.get_mut(page as usize / 64) {
+                *bits |= 1 << (page % 64);
+            }
+        }
+    }
+
+    pub fn unmark(&mut self, addr: u64, len: u64) {
+        if len == 0 {
+            return;
+        }
+        let first = addr / RISCV_PAGESIZE as u64;
+        let last = (addr + len - 1) / RISCV_PAGESIZE as u64;
+        for page in first..=last {
+            if let Some(bits) = self.bits.get_mut(page as usize / 64) {
+                *bits &= !(1 << (page % 64));
+            }
+        }
+    }
+
+    // Tells if any page overlapping [addr, addr + len) is marked.
+    #[inline(always)]
+    pub fn contains(&self, addr: u64, len: u64) -> bool {
+        if len == 0 {
+            return false;
+        }
+        let first = addr / RISCV_PAGESIZE as u64;
+        let last = addr.saturating_add(len - 1) / RISCV_PAGESIZE as u64;
+        (first..=last).any(|page| {
+            self.bits
+                .get(page as usize / 64)
+                .map(|bits| bits & (1 << (page % 64)) != 0)
+                .unwrap_or(false)
+        })
+    }
+
+    // Indices of dirty pages in ascending order.
+    pub fn pages(&self) -> Vec<u64> {
+        let mut result = Vec::new();
+        for (i, bits) in self.bits.iter().enumerate() {
+            let mut bits = *bits;
+            while bits != 0 {
+                let bit = bits.trailing_zeros() as u64;
+                result.push(i as u64 * 64 + bit);
+                bits &= bits - 1;
+            }
+        }
+        result
+    }
+
+    // Number of dirty pages
+    pub fn count(&self) -> usize {
+        self.bits
+            .iter()
+            .map(|bits| bits.count_ones() as usize)
+            .sum()
+    }
+
+    pub fn clear(&mut self) {
+        for bits in self.bits.iter_mut() {
+            *bits = 0;
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use super::super::RISCV_PAGESIZE;
+
+use alloc::vec::Vec;
+
+/// Consecutive bytes that differ between two memory dumps.
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct ChangedRange {
+    pub addr: u64,
+    pub before: Vec<u8>,
+    pub after: Vec<u8>,
+}
+
+/// Differences between two dumps of the same memory range, see `diff`.
+#[derive(Debug, PartialEq, Clone, Eq, Default)]
+pub struct MemoryDiff {
+    // Indices of pages containing at least one changed byte, in ascending
+    // order.
+    pub pages: Vec<u64>,
+    pub ranges: Vec<ChangedRange>,
+}
+
+impl MemoryDiff {
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    pub fn changed_bytes(&self) -> usize {
+        self.ranges.iter().map(|range| range.after.len()).sum()
+    }
+}
+
+// Compares two dumps of the memory range starting at addr, typically taken
+// with Memory::dump_range before and after running a program. This panics
+// when the dumps differ in length.
+pub fn diff(addr: u64, before: &[u8], after: &[u8]) -> MemoryDiff {
+    assert_eq!(before.len(), after.len(), "dumps of different ranges");
+    let mut result = MemoryDiff::default();
+    let mut i = 0;
+    while i < before.len() {
+        if before[i] == after[i] {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < before.len() && before[i] != after[i] {
+            i += 1;
+        }
+        let first_page = (addr + start as u64) / RISCV_PAGESIZE as u64;
+        let last_page = (addr + i as u64 - 1) / RISCV_PAGESIZE as u64;
+        for page in first_page..=last_page {
+            if result.pages.last() != Some(&page) {
+                result.pages.push(page);
+            }
+        }
+        result.ranges.push(ChangedRange {
+            addr: addr + start as u64,
+            before: before[start..i].to_vec(),
+            after: after[start..i].to_vec(),
+        });
+    }
+    result
+}
@@ -1,36 +1,142 @@
-use super::super::{Error, Register, RISCV_MAX_MEMORY, RISCV_PAGES};
-use super::{fill_page_data, memset, Memory};
+use super::super::{Error, Register, RISCV_MAX_MEMORY, RISCV_PAGESIZE};
+#[cfg(all(feature = "std", unix))]
+use super::guarded::GuardedRegion;
+use super::{
+    check_memory_size,
+    dirty::DirtyPages,
+    fill_page_data,
+    mappings::{Mapping, MappingTable},
+    memset,
+    watchpoint::{WatchpointKind, Watchpoints},
+    Memory,
+};
 
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use alloc::{vec, vec::Vec};
+use byteorder::{ByteOrder, LittleEndian};
 use bytes::Bytes;
-use std::io::{Cursor, Seek, SeekFrom};
-use std::marker::PhantomData;
-use std::ops::{Deref, DerefMut};
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+
+// Backing store of FlatMemory
+enum Storage {
+    Heap(Vec<u8>),
+    #[cfg(all(feature = "std", unix))]
+    Guarded(GuardedRegion),
+}
+
+impl Deref for Storage {
+    type Target = [u8];
+
+    #[inline(always)]
+    fn deref(&self) -> &[u8] {
+        match self {
+            Storage::Heap(data) => data,
+            #[cfg(all(feature = "std", unix))]
+            Storage::Guarded(region) => region,
+        }
+    }
+}
+
+impl DerefMut for Storage {
+    #[inline(always)]
+    fn deref_mut(&mut self) -> &mut [u8] {
+        match self {
+            Storage::Heap(data) => data,
+            #[cfg(all(feature = "std", unix))]
+            Storage::Guarded(region) => region,
+        }
+    }
+}
 
 pub struct FlatMemory<R> {
-    data: Vec<u8>,
+    data: Storage,
+    watchpoints: Watchpoints,
+    dirty: DirtyPages,
+    // Pages loaded from or stored to since creation or reset
+    accessed: DirtyPages,
+    mappings: MappingTable,
     _inner: PhantomData<R>,
 }
 
-impl<R> Default for FlatMemory<R> {
-    fn default() -> Self {
+impl<R> FlatMemory<R> {
+    pub fn new_with_memory_size(memory_size: usize) -> Result<Self, Error> {
+        check_memory_size(memory_size)?;
+        Ok(Self::with_storage(Storage::Heap(vec![0; memory_size])))
+    }
+
+    // Backs memory with its own host mapping surrounded by inaccessible
+    // guard pages, see GuardedRegion. Meant for untrusted programs: should
+    // a bounds check ever be wrong, the host faults instead of handing out
+    // or overwriting host memory. Accesses are still bounds checked exactly
+    // like heap backed memory, so this is no faster, eliding checks would
+    // turn bad guest addresses into host crashes rather than
+    // Error::OutOfBound. Resetting also gives touched pages back to the
+    // host.
+    #[cfg(all(feature = "std", unix))]
+    pub fn new_guarded(memory_size: usize) -> Result<Self, Error> {
+        check_memory_size(memory_size)?;
+        Ok(Self::with_storage(Storage::Guarded(GuardedRegion::new(
+            memory_size,
+        )?)))
+    }
+
+    pub fn is_guarded(&self) -> bool {
+        match self.data {
+            Storage::Heap(_) => false,
+            #[cfg(all(feature = "std", unix))]
+            Storage::Guarded(_) => true,
+        }
+    }
+
+    fn with_storage(data: Storage) -> Self {
+        let pages = data.len() / RISCV_PAGESIZE;
         Self {
-            data: vec![0; RISCV_MAX_MEMORY],
+            data,
+            watchpoints: Watchpoints::default(),
+            dirty: DirtyPages::new(pages),
+            accessed: DirtyPages::new(pages),
+            mappings: MappingTable::default(),
             _inner: PhantomData,
         }
     }
+
+    // Checked in u64 before casting, so addresses beyond 4GB cannot wrap
+    // around on 32 bit hosts.
+    #[inline(always)]
+    fn bytes(&self, addr: u64, size: u64) -> Result<&[u8], Error> {
+        let end = addr.checked_add(size).ok_or(Error::OutOfBound)?;
+        if end > self.data.len() as u64 {
+            return Err(Error::OutOfBound);
+        }
+        Ok(&self.data[addr as usize..end as usize])
+    }
+
+    #[inline(always)]
+    fn bytes_mut(&mut self, addr: u64, size: u64) -> Result<&mut [u8], Error> {
+        let end = addr.checked_add(size).ok_or(Error::OutOfBound)?;
+        if end > self.data.len() as u64 {
+            return Err(Error::OutOfBound);
+        }
+        Ok(&mut self.data[addr as usize..end as usize])
+    }
+}
+
+impl<R> Default for FlatMemory<R> {
+    fn default() -> Self {
+        Self::new_with_memory_size(RISCV_MAX_MEMORY).expect("default memory size")
+    }
 }
 
 impl<R> Deref for FlatMemory<R> {
-    type Target = Vec<u8>;
+    type Target = [u8];
 
-    fn deref(&self) -> &Self::Target {
+    fn deref(&self) -> &[u8] {
         &self.data
     }
 }
 
 impl<R> DerefMut for FlatMemory<R> {
-    fn deref_mut(&mut self) -> &mut Vec<u8> {
+    fn deref_mut(&mut self) -> &mut [u8] {
         &mut self.data
     }
 }
@@ -50,123 +156,182 @@ impl<R: Register> Memory<R> for FlatMemory<R> {
     }
 
     fn fetch_flag(&mut self, page: u64) -> Result<u8, Error> {
-        if page < RISCV_PAGES as u64 {
+        if page < (self.len() / RISCV_PAGESIZE) as u64 {
             Ok(0)
         } else {
             Err(Error::OutOfBound)
         }
     }
 
+    fn memory_size(&self) -> usize {
+        self.len()
+    }
+
+    fn set_memory_size(&mut self, size: usize) -> Result<(), Error> {
+        *self = match self.data {
+            Storage::Heap(_) => Self::new_with_memory_size(size)?,
+            #[cfg(all(feature = "std", unix))]
+            Storage::Guarded(_) => Self::new_guarded(size)?,
+        };
+        Ok(())
+    }
+
+    fn high_watermark(&self) -> usize {
+        self.accessed.count() * RISCV_PAGESIZE
+    }
+
+    fn add_watchpoint(&mut self, addr: u64, len: u64, kind: WatchpointKind) -> Result<(), Error> {
+        self.watchpoints.add(addr, len, kind)
+    }
+
+    fn remove_watchpoint(
+        &mut self,
+        addr: u64,
+        len: u64,
+        kind: WatchpointKind,
+    ) -> Result<(), Error> {
+        self.watchpoints.remove(addr, len, kind);
+        Ok(())
+    }
+
+    // Instruction fetches are not data accesses, so unlike load16 this does
+    // not check watchpoints.
     fn execute_load16(&mut self, addr: u64) -> Result<u16, Error> {
-        self.load16(&R::from_u64(addr)).map(|v| v.to_u16())
+        let v = LittleEndian::read_u16(self.bytes(addr, 2)?);
+        self.accessed.mark(addr, 2);
+        Ok(v)
+    }
+
+    fn dirty_pages(&self) -> Result<Vec<u64>, Error> {
+        Ok(self.dirty.pages())
+    }
+
+    fn clear_dirty(&mut self) -> Result<(), Error> {
+        self.dirty.clear();
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Result<(), Error> {
+        match &mut self.data {
+            Storage::Heap(data) => memset(data, 0),
+            #[cfg(all(feature = "std", unix))]
+            Storage::Guarded(region) => region.zero()?,
+        }
+        self.watchpoints.clear();
+        self.dirty.clear();
+        self.accessed.clear();
+        self.mappings.clear();
+        Ok(())
+    }
+
+    fn map_region(&mut self, addr: u64, len: u64, prot: u64, fixed: bool) -> Result<(), Error> {
+        let memory_size = Memory::<R>::memory_size(self) as u64;
+        self.mappings.map(addr, len, prot, fixed, memory_size)
+    }
+
+    fn unmap_region(&mut self, addr: u64, len: u64) -> Result<(), Error> {
+        let memory_size = Memory::<R>::memory_size(self) as u64;
+        self.mappings.unmap(addr, len, memory_size).map(|_| ())
+    }
+
+    fn mappings(&self) -> Vec<Mapping> {
+        self.mappings.mappings()
     }
 
     fn load8(&mut self, addr: &R) -> Result<R, Error> {
+        self.watchpoints.check(addr.to_u64(), 1, false)?;
         let addr = addr.to_u64();
-        if addr.checked_add(1).ok_or(Error::OutOfBound)? > self.len() as u64 {
-            return Err(Error::OutOfBound);
-        }
-        let mut reader = Cursor::new(&self.data);
-        reader.seek(SeekFrom::Start(addr as u64))?;
-        let v = reader.read_u8()?;
+        let v = self.bytes(addr, 1)?[0];
+        self.accessed.mark(addr, 1);
         Ok(R::from_u8(v))
     }
 
     fn load16(&mut self, addr: &R) -> Result<R, Error> {
+        self.watchpoints.check(addr.to_u64(), 2, false)?;
         let addr = addr.to_u64();
-        if addr.checked_add(2).ok_or(Error::OutOfBound)? > self.len() as u64 {
-            return Err(Error::OutOfBound);
-        }
-        let mut reader = Cursor::new(&self.data);
-        reader.seek(SeekFrom::Start(addr as u64))?;
         // NOTE: Base RISC-V ISA is defined as a little-endian memory system.
-        let v = reader.read_u16::<LittleEndian>()?;
+        let v = LittleEndian::read_u16(self.bytes(addr, 2)?);
+        self.accessed.mark(addr, 2);
         Ok(R::from_u16(v))
     }
 
     fn load32(&mut self, addr: &R) -> Result<R, Error> {
+        self.watchpoints.check(addr.to_u64(), 4, false)?;
         let addr = addr.to_u64();
-        if addr.checked_add(4).ok_or(Error::OutOfBound)? > self.len() as u64 {
-            return Err(Error::OutOfBound);
-        }
-        let mut reader = Cursor::new(&self.data);
-        reader.seek(SeekFrom::Start(addr as u64))?;
         // NOTE: Base RISC-V ISA is defined as a little-endian memory system.
-        let v = reader.read_u32::<LittleEndian>()?;
+        let v = LittleEndian::read_u32(self.bytes(addr, 4)?);
+        self.accessed.mark(addr, 4);
         Ok(R::from_u32(v))
     }
 
     fn load64(&mut self, addr: &R) -> Result<R, Error> {
+        self.watchpoints.check(addr.to_u64(), 8, false)?;
         let addr = addr.to_u64();
-        if addr.checked_add(8).ok_or(Error::OutOfBound)? > self.len() as u64 {
-            return Err(Error::OutOfBound);
-        }
-        let mut reader = Cursor::new(&self.data);
-        reader.seek(SeekFrom::Start(addr as u64))?;
         // NOTE: Base RISC-V ISA is defined as a little-endian memory system.
-        let v = reader.read_u64::<LittleEndian>()?;
+        let v = LittleEndian::read_u64(self.bytes(addr, 8)?);
+        self.accessed.mark(addr, 8);
         Ok(R::from_u64(v))
     }
 
     fn store8(&mut self, addr: &R, value: &R) -> Result<(), Error> {
+        self.watchpoints.check(addr.to_u64(), 1, true)?;
         let addr = addr.to_u64();
-        if addr.checked_add(1).ok_or(Error::OutOfBound)? > self.len() as u64 {
-            return Err(Error::OutOfBound);
-        }
-        let mut writer = Cursor::new(&mut self.data);
-        writer.seek(SeekFrom::Start(addr as u64))?;
-        writer.write_u8(value.to_u8())?;
+        self.bytes_mut(addr, 1)?[0] = value.to_u8();
+        self.dirty.mark(addr, 1);
+        self.accessed.mark(addr, 1);
         Ok(())
     }
 
     fn store16(&mut self, addr: &R, value: &R) -> Result<(), Error> {
+        self.watchpoints.check(addr.to_u64(), 2, true)?;
         let addr = addr.to_u64();
-        if addr.checked_add(2).ok_or(Error::OutOfBound)? > self.len() as u64 {
-            return Err(Error::OutOfBound);
-        }
-        let mut writer = Cursor::new(&mut self.data);
-        writer.seek(SeekFrom::Start(addr as u64))?;
-        writer.write_u16::<LittleEndian>(value.to_u16())?;
+        LittleEndian::write_u16(self.bytes_mut(addr, 2)?, value.to_u16());
+        self.dirty.mark(addr, 2);
+        self.accessed.mark(addr, 2);
         Ok(())
     }
 
     fn store32(&mut self, addr: &R, value: &R) -> Result<(), Error> {
+        self.watchpoints.check(addr.to_u64(), 4, true)?;
         let addr = addr.to_u64();
-        if addr.checked_add(4).ok_or(Error::OutOfBound)? > self.len() as u64 {
-            return Err(Error::OutOfBound);
-        }
-        let mut writer = Cursor::new(&mut self.data);
-        writer.seek(SeekFrom::Start(addr as u64))?;
-        writer.write_u32::<LittleEndian>(value.to_u32())?;
+        LittleEndian::write_u32(self.bytes_mut(addr, 4)?, value.to_u32());
+        self.dirty.mark(addr, 4);
+        self.accessed.mark(addr, 4);
         Ok(())
     }
 
     fn store64(&mut self, addr: &R, value: &R) -> Result<(), Error> {
+        self.watchpoints.check(addr.to_u64(), 8, true)?;
         let addr = addr.to_u64();
-        if addr.checked_add(8).ok_or(Error::OutOfBound)? > self.len() as u64 {
-            return Err(Error::OutOfBound);
-        }
-        let mut writer = Cursor::new(&mut self.data);
-        writer.seek(SeekFrom::Start(addr as u64))?;
-        writer.write_u64::<LittleEndian>(value.to_u64())?;
+        LittleEndian::write_u64(self.bytes_mut(addr, 8)?, value.to_u64());
+        self.dirty.mark(addr, 8);
+        self.accessed.mark(addr, 8);
         Ok(())
     }
 
     fn store_bytes(&mut self, addr: u64, value: &[u8]) -> Result<(), Error> {
         let size = value.len() as u64;
-        if addr.checked_add(size).ok_or(Error::OutOfBound)? > self.len() as u64 {
-            return Err(Error::OutOfBound);
-        }
-        let slice = &mut self[addr as usize..(addr + size) as usize];
-        slice.copy_from_slice(value);
+        self.bytes_mut(addr, size)?.copy_from_slice(value);
+        self.dirty.mark(addr, size);
+        self.accessed.mark(addr, size);
         Ok(())
     }
 
+    fn load_bytes(&mut self, addr: u64, size: u64) -> Result<Vec<u8>, Error> {
+        let bytes = self.bytes(addr, size)?.to_vec();
+        self.accessed.mark(addr, size);
+        Ok(bytes)
+    }
+
+    // Inspecting memory does not count towards the high watermark
+    fn dump_range(&mut self, addr: u64, len: u64) -> Result<Vec<u8>, Error> {
+        Ok(self.bytes(addr, len)?.to_vec())
+    }
+
     fn store_byte(&mut self, addr: u64, size: u64, value: u8) -> Result<(), Error> {
-        if addr.checked_add(size).ok_or(Error::OutOfBound)? > self.len() as u64 {
-            return Err(Error::OutOfBound);
-        }
-        memset(&mut self[addr as usize..(addr + size) as usize], value);
+        memset(self.bytes_mut(addr, size)?, value);
+        self.dirty.mark(addr, size);
+        self.accessed.mark(addr, size);
         Ok(())
     }
 }
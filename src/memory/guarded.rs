@@ -0,0 +1,136 @@
+use super::super::Error;
+
+use core::ops::{Deref, DerefMut};
+use core::ptr;
+use core::slice;
+use libc::{
+    c_void, mmap, mprotect, munmap, sysconf, _SC_PAGESIZE, MAP_ANONYMOUS, MAP_FAILED, MAP_FIXED,
+    MAP_PRIVATE, PROT_NONE, PROT_READ, PROT_WRITE,
+};
+use std::io::Error as IOError;
+
+// Host memory mapped for a guest, surrounded by PROT_NONE guard pages so an
+// access running past either end of it faults on the host instead of
+// reading or corrupting neighbouring host memory. Pages are only committed
+// by the host once touched, and zero returns them to the host.
+pub struct GuardedRegion {
+    // Start of the guest accessible part, the mapping starts guard bytes
+    // before it
+    data: *mut u8,
+    len: usize,
+    guard: usize,
+}
+
+// The region is exclusively owned like a Vec<u8>
+unsafe impl Send for GuardedRegion {}
+unsafe impl Sync for GuardedRegion {}
+
+fn host_page_size() -> usize {
+    unsafe { sysconf(_SC_PAGESIZE) as usize }
+}
+
+impl GuardedRegion {
+    pub fn new(len: usize) -> Result<Self, Error> {
+        let guard = host_page_size();
+        let mapped_len = round_up(len, guard);
+        let total = mapped_len
+            .checked_add(2 * guard)
+            .ok_or(Error::InvalidMemorySize(len as u64))?;
+        unsafe {
+            let base = mmap(
+                ptr::null_mut(),
+                total,
+                PROT_NONE,
+                MAP_PRIVATE | MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            if base == MAP_FAILED {
+                return Err(IOError::last_os_error().into());
+            }
+            let data = (base as *mut u8).add(guard);
+            if mapped_len > 0
+                && mprotect(data as *mut c_void, mapped_len, PROT_READ | PROT_WRITE) != 0
+            {
+                let error = IOError::last_os_error();
+                munmap(base, total);
+                return Err(error.into());
+            }
+            Ok(Self { data, len, guard })
+        }
+    }
+
+    // Size of the PROT_NONE area on each side of the region
+    pub fn guard_size(&self) -> usize {
+        self.guard
+    }
+
+    // Zeroes the region by replacing it with a fresh anonymous mapping,
+    // which also releases the pages the guest touched.
+    pub fn zero(&mut self) -> Result<(), Error> {
+        let mapped_len = round_up(self.len, self.guard);
+        if mapped_len == 0 {
+            return Ok(());
+        }
+        let result = unsafe {
+            mmap(
+                self.data as *mut c_void,
+                mapped_len,
+                PROT_READ | PROT_WRITE,
+                MAP_PRIVATE | MAP_ANONYMOUS | MAP_FIXED,
+                -1,
+                0,
+            )
+        };
+        if result == MAP_FAILED {
+            return Err(IOError::last_os_error().into());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for GuardedRegion {
+    fn drop(&mut self) {
+        let total = round_up(self.len, self.guard) + 2 * self.guard;
+        unsafe {
+            munmap(self.data.sub(self.guard) as *mut c_void, total);
+        }
+    }
+}
+
+impl Deref for GuardedRegion {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.data, self.len) }
+    }
+}
+
+impl DerefMut for GuardedRegion {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.data, self.len) }
+    }
+}
+
+fn round_up(len: usize, page: usize) -> usize {
+    len.div_ceil(page) * page
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_guarded_region() {
+        let mut region = GuardedRegion::new(1 << 16).unwrap();
+        assert_eq!(region.len(), 1 << 16);
+        assert!(region.iter().all(|b| *b == 0));
+        region[0] = 1;
+        region[(1 << 16) - 1] = 2;
+        region.zero().unwrap();
+        assert_eq!(region[0], 0);
+        assert_eq!(region[(1 << 16) - 1], 0);
+        // Guard pages are mapped but inaccessible
+        assert!(region.guard_size() > 0);
+    }
+}
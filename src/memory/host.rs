@@ -0,0 +1,105 @@
+use super::super::{Error, RISCV_PAGESIZE};
+use super::{round_page_down, round_page_up};
+
+use alloc::collections::BTreeMap;
+use bytes::Bytes;
+
+// Read-only windows over host buffers, shared by memory implementations to
+// back Memory::map_host_slice. Buffers are kept as Bytes, so mapping never
+// copies them and they are released once unmapped and dropped by the host.
+// Windows cover whole pages, bytes past the end of a buffer read as zeros.
+#[derive(Debug, Clone, Default)]
+pub struct HostRegions {
+    // Start address -> buffer
+    regions: BTreeMap<u64, Bytes>,
+}
+
+impl HostRegions {
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+    }
+
+    pub fn map(&mut self, addr: u64, data: Bytes, memory_size: u64) -> Result<(), Error> {
+        if round_page_down(addr) != addr {
+            return Err(Error::Unaligned);
+        }
+        let end = addr
+            .checked_add(round_page_up(data.len() as u64))
+            .ok_or(Error::OutOfBound)?;
+        if data.is_empty() || end > memory_size {
+            return Err(Error::OutOfBound);
+        }
+        if self.overlaps(addr, end - addr) {
+            return Err(Error::InvalidPermission);
+        }
+        self.regions.insert(addr, data);
+        Ok(())
+    }
+
+    // Unmaps the window starting at addr, returning its buffer
+    pub fn unmap(&mut self, addr: u64) -> Option<Bytes> {
+        self.regions.remove(&addr)
+    }
+
+    pub fn clear(&mut self) {
+        self.regions.clear();
+    }
+
+    // Tells if any window overlaps [addr, addr + len)
+    #[inline(always)]
+    pub fn overlaps(&self, addr: u64, len: u64) -> bool {
+        if self.regions.is_empty() || len == 0 {
+            return false;
+        }
+        let end = addr.saturating_add(len);
+        self.regions
+            .range(..end)
+            .next_back()
+            .is_some_and(|(start, data)| start + round_page_up(data.len() as u64) > addr)
+    }
+
+    // Content of the page starting at page_addr when a window covers it,
+    // the slice is shorter than a page at the end of a buffer.
+    #[inline(always)]
+    pub fn page(&self, page_addr: u64) -> Option<&[u8]> {
+        if !self.overlaps(page_addr, 1) {
+            return None;
+        }
+        let (start, data) = self.regions.range(..=page_addr).next_back()?;
+        let offset = (page_addr - start) as usize;
+        let end = (offset + RISCV_PAGESIZE).min(data.len());
+        Some(&data[offset.min(end)..end])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_host_regions() {
+        let mut regions = HostRegions::default();
+        let data: Bytes = vec![1; RISCV_PAGESIZE + 16].into();
+        assert_eq!(
+            regions.map(0x1001, data.clone(), 0x10000),
+            Err(Error::Unaligned)
+        );
+        assert_eq!(
+            regions.map(0xf000, data.clone(), 0x10000),
+            Err(Error::OutOfBound)
+        );
+        regions.map(0x1000, data.clone(), 0x10000).unwrap();
+        assert_eq!(
+            regions.map(0x2000, data.clone(), 0x10000),
+            Err(Error::InvalidPermission)
+        );
+        assert!(regions.overlaps(0xff8, 16));
+        assert!(regions.overlaps(0x2fff, 1));
+        assert!(!regions.overlaps(0x3000, 8));
+        assert_eq!(regions.page(0x1000).unwrap().len(), RISCV_PAGESIZE);
+        assert_eq!(regions.page(0x2000).unwrap().len(), 16);
+        assert_eq!(regions.page(0x3000), None);
+        assert_eq!(regions.unmap(0x1000), Some(data));
+        assert!(regions.is_empty());
+    }
+}
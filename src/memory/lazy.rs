@@ -0,0 +1,376 @@
+use super::super::{Error, Register, RISCV_MAX_MEMORY, RISCV_PAGESIZE};
+use super::{
+    check_memory_size,
+    dirty::DirtyPages,
+    mappings::{Mapping, MappingTable},
+    memset, round_page_down,
+    watchpoint::{WatchpointKind, Watchpoints},
+    Memory, Page,
+};
+
+use alloc::{boxed::Box, vec, vec::Vec};
+use bytes::Bytes;
+use core::cmp::{max, min};
+use core::marker::PhantomData;
+
+const INVALID_PAGE_INDEX: u16 = 0xFFFF;
+
+// Provides the initial content of memory pages, such as a program image
+// stored in a file, a database or behind the network.
+pub trait PageSource {
+    // Fills page, which is zero filled, with the initial content of the page
+    // starting at addr. This is called at most once per page, when the page
+    // is first accessed.
+    fn load_page(&mut self, addr: u64, page: &mut Page) -> Result<(), Error>;
+}
+
+impl<F: FnMut(u64, &mut Page) -> Result<(), Error>> PageSource for F {
+    fn load_page(&mut self, addr: u64, page: &mut Page) -> Result<(), Error> {
+        self(addr, page)
+    }
+}
+
+// Content passed to init_pages, kept around until the pages are accessed.
+struct Segment {
+    addr: u64,
+    size: u64,
+    source: Option<Bytes>,
+    offset_from_addr: u64,
+}
+
+impl Segment {
+    // Writes the part of the segment within the page starting at page_addr.
+    fn fill(&self, page_addr: u64, page: &mut Page) {
+        let start = max(self.addr, page_addr);
+        let end = min(self.addr + self.size, page_addr + RISCV_PAGESIZE as u64);
+        if start >= end {
+            return;
+        }
+        let target = &mut page[(start - page_addr) as usize..(end - page_addr) as usize];
+        memset(target, 0);
+        if let Some(source) = &self.source {
+            let data_start = self.addr + self.offset_from_addr;
+            let data_end = data_start + source.len() as u64;
+            let copy_start = max(start, data_start);
+            let copy_end = min(end, data_end);
+            if copy_start < copy_end {
+                target[(copy_start - start) as usize..(copy_end - start) as usize].copy_from_slice(
+                    &source[(copy_start - data_start) as usize..(copy_end - data_start) as usize],
+                );
+            }
+        }
+    }
+}
+
+/// A memory implementation that populates pages on first access, from an
+/// optional page source and from the data passed to init_pages, which is
+/// not copied until the pages are used. Like SparseMemory, it does no
+/// permission checking.
+pub struct LazyMemory<R> {
+    indices: Vec<u16>,
+    pages: Vec<Page>,
+    source: Option<Box<dyn PageSource>>,
+    segments: Vec<Segment>,
+    watchpoints: Watchpoints,
+    dirty: DirtyPages,
+    mappings: MappingTable,
+    _inner: PhantomData<R>,
+}
+
+impl<R> LazyMemory<R> {
+    pub fn new() -> Self {
+        Self::new_with_memory_size(RISCV_MAX_MEMORY).expect("default memory size")
+    }
+
+    pub fn new_with_memory_size(memory_size: usize) -> Result<Self, Error> {
+        check_memory_size(memory_size)?;
+        let pages = memory_size / RISCV_PAGESIZE;
+        if pages >= INVALID_PAGE_INDEX as usize {
+            return Err(Error::InvalidMemorySize(memory_size as u64));
+        }
+        Ok(Self {
+            indices: vec![INVALID_PAGE_INDEX; pages],
+            pages: Vec::new(),
+            source: None,
+            segments: Vec::new(),
+            watchpoints: Watchpoints::default(),
+            dirty: DirtyPages::new(pages),
+            mappings: MappingTable::default(),
+            _inner: PhantomData,
+        })
+    }
+
+    pub fn new_with_source(source: Box<dyn PageSource>) -> Self {
+        let mut memory = Self::new();
+        memory.source = Some(source);
+        memory
+    }
+
+    // Number of pages populated so far.
+    pub fn loaded_pages(&self) -> usize {
+        self.pages.len()
+    }
+
+    fn fetch_page(&mut self, aligned_addr: u64) -> Result<&mut Page, Error> {
+        let page = aligned_addr / RISCV_PAGESIZE as u64;
+        if page >= self.indices.len() as u64 {
+            return Err(Error::OutOfBound);
+        }
+        let mut index = self.indices[page as usize];
+        if index == INVALID_PAGE_INDEX {
+            let mut data = [0; RISCV_PAGESIZE];
+            if let Some(source) = &mut self.source {
+                source.load_page(aligned_addr, &mut data)?;
+            }
+            for segment in &self.segments {
+                segment.fill(aligned_addr, &mut data);
+            }
+            self.pages.push(data);
+            index = (self.pages.len() - 1) as u16;
+            self.indices[page as usize] = index;
+        }
+        Ok(&mut self.pages[index as usize])
+    }
+
+    fn read(&mut self, addr: u64, buffer: &mut [u8]) -> Result<(), Error> {
+        let size = buffer.len() as u64;
+        if addr.checked_add(size).ok_or(Error::OutOfBound)?
+            > self.indices.len() as u64 * RISCV_PAGESIZE as u64
+        {
+            return Err(Error::OutOfBound);
+        }
+        let mut current_page_addr = round_page_down(addr);
+        let mut current_page_offset = addr - current_page_addr;
+        let mut read = 0;
+        while read < size {
+            let bytes = min(RISCV_PAGESIZE as u64 - current_page_offset, size - read);
+            let page = self.fetch_page(current_page_addr)?;
+            buffer[read as usize..(read + bytes) as usize].copy_from_slice(
+                &page[current_page_offset as usize..(current_page_offset + bytes) as usize],
+            );
+            read += bytes;
+            current_page_addr += RISCV_PAGESIZE as u64;
+            current_page_offset = 0;
+        }
+        Ok(())
+    }
+
+    fn write<F: FnMut(u64, &mut [u8])>(
+        &mut self,
+        addr: u64,
+        size: u64,
+        mut write: F,
+    ) -> Result<(), Error> {
+        if addr.checked_add(size).ok_or(Error::OutOfBound)?
+            > self.indices.len() as u64 * RISCV_PAGESIZE as u64
+        {
+            return Err(Error::OutOfBound);
+        }
+        self.dirty.mark(addr, size);
+        let mut current_page_addr = round_page_down(addr);
+        let mut current_page_offset = addr - current_page_addr;
+        let mut written = 0;
+        while written < size {
+            let bytes = min(RISCV_PAGESIZE as u64 - current_page_offset, size - written);
+            let page = self.fetch_page(current_page_addr)?;
+            write(
+                written,
+                &mut page[current_page_offset as usize..(current_page_offset + bytes) as usize],
+            );
+            written += bytes;
+            current_page_addr += RISCV_PAGESIZE as u64;
+            current_page_offset = 0;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Register> Memory<R> for LazyMemory<R> {
+    // Pages already populated are updated right away, the others only
+    // record the segment so it can be applied once they are accessed.
+    fn init_pages(
+        &mut self,
+        addr: u64,
+        size: u64,
+        _flags: u8,
+        source: Option<Bytes>,
+        offset_from_addr: u64,
+    ) -> Result<(), Error> {
+        let end = addr.checked_add(size).ok_or(Error::OutOfBound)?;
+        if end > Memory::<R>::memory_size(self) as u64 {
+            return Err(Error::OutOfBound);
+        }
+        self.dirty.mark(addr, size);
+        let segment = Segment {
+            addr,
+            size,
+            source,
+            offset_from_addr,
+        };
+        let mut page_addr = round_page_down(addr);
+        while page_addr < end {
+            let index = self.indices[page_addr as usize / RISCV_PAGESIZE];
+            if index != INVALID_PAGE_INDEX {
+                segment.fill(page_addr, &mut self.pages[index as usize]);
+            }
+            page_addr += RISCV_PAGESIZE as u64;
+        }
+        self.segments.push(segment);
+        Ok(())
+    }
+
+    fn fetch_flag(&mut self, page: u64) -> Result<u8, Error> {
+        if page < self.indices.len() as u64 {
+            Ok(0)
+        } else {
+            Err(Error::OutOfBound)
+        }
+    }
+
+    fn memory_size(&self) -> usize {
+        self.indices.len() * RISCV_PAGESIZE
+    }
+
+    // The page source is kept, while recorded segments are discarded
+    // together with memory content.
+    fn set_memory_size(&mut self, size: usize) -> Result<(), Error> {
+        let source = self.source.take();
+        *self = Self::new_with_memory_size(size)?;
+        self.source = source;
+        Ok(())
+    }
+
+    fn touched_memory(&self) -> usize {
+        self.pages.len() * RISCV_PAGESIZE
+    }
+
+    fn add_watchpoint(&mut self, addr: u64, len: u64, kind: WatchpointKind) -> Result<(), Error> {
+        self.watchpoints.add(addr, len, kind)
+    }
+
+    fn remove_watchpoint(
+        &mut self,
+        addr: u64,
+        len: u64,
+        kind: WatchpointKind,
+    ) -> Result<(), Error> {
+        self.watchpoints.remove(addr, len, kind);
+        Ok(())
+    }
+
+    fn dirty_pages(&self) -> Result<Vec<u64>, Error> {
+        Ok(self.dirty.pages())
+    }
+
+    fn clear_dirty(&mut self) -> Result<(), Error> {
+        self.dirty.clear();
+        Ok(())
+    }
+
+    // Content passed to init_pages is dropped, the page source is kept
+    fn reset(&mut self) -> Result<(), Error> {
+        for index in self.indices.iter_mut() {
+            *index = INVALID_PAGE_INDEX;
+        }
+        self.pages.clear();
+        self.segments.clear();
+        self.watchpoints.clear();
+        self.dirty.clear();
+        self.mappings.clear();
+        Ok(())
+    }
+
+    fn map_region(&mut self, addr: u64, len: u64, prot: u64, fixed: bool) -> Result<(), Error> {
+        let memory_size = Memory::<R>::memory_size(self) as u64;
+        self.mappings.map(addr, len, prot, fixed, memory_size)
+    }
+
+    fn unmap_region(&mut self, addr: u64, len: u64) -> Result<(), Error> {
+        let memory_size = Memory::<R>::memory_size(self) as u64;
+        self.mappings.unmap(addr, len, memory_size).map(|_| ())
+    }
+
+    fn mappings(&self) -> Vec<Mapping> {
+        self.mappings.mappings()
+    }
+
+    fn load8(&mut self, addr: &R) -> Result<R, Error> {
+        self.watchpoints.check(addr.to_u64(), 1, false)?;
+        let mut buffer = [0; 1];
+        self.read(addr.to_u64(), &mut buffer)?;
+        Ok(R::from_u8(buffer[0]))
+    }
+
+    fn load16(&mut self, addr: &R) -> Result<R, Error> {
+        self.watchpoints.check(addr.to_u64(), 2, false)?;
+        let v = self.execute_load16(addr.to_u64())?;
+        Ok(R::from_u16(v))
+    }
+
+    fn load32(&mut self, addr: &R) -> Result<R, Error> {
+        self.watchpoints.check(addr.to_u64(), 4, false)?;
+        let mut buffer = [0; 4];
+        self.read(addr.to_u64(), &mut buffer)?;
+        Ok(R::from_u32(u32::from_le_bytes(buffer)))
+    }
+
+    fn load64(&mut self, addr: &R) -> Result<R, Error> {
+        self.watchpoints.check(addr.to_u64(), 8, false)?;
+        let mut buffer = [0; 8];
+        self.read(addr.to_u64(), &mut buffer)?;
+        Ok(R::from_u64(u64::from_le_bytes(buffer)))
+    }
+
+    fn execute_load16(&mut self, addr: u64) -> Result<u16, Error> {
+        let mut buffer = [0; 2];
+        self.read(addr, &mut buffer)?;
+        Ok(u16::from_le_bytes(buffer))
+    }
+
+    fn store_bytes(&mut self, addr: u64, value: &[u8]) -> Result<(), Error> {
+        self.write(addr, value.len() as u64, |offset, target| {
+            target.copy_from_slice(&value[offset as usize..offset as usize + target.len()])
+        })
+    }
+
+    fn load_bytes(&mut self, addr: u64, size: u64) -> Result<Vec<u8>, Error> {
+        if addr.checked_add(size).ok_or(Error::OutOfBound)? > Memory::<R>::memory_size(self) as u64
+        {
+            return Err(Error::OutOfBound);
+        }
+        let mut result = vec![0; size as usize];
+        self.read(addr, &mut result)?;
+        Ok(result)
+    }
+
+    fn store_byte(&mut self, addr: u64, size: u64, value: u8) -> Result<(), Error> {
+        self.write(addr, size, |_, target| memset(target, value))
+    }
+
+    fn store8(&mut self, addr: &R, value: &R) -> Result<(), Error> {
+        self.watchpoints.check(addr.to_u64(), 1, true)?;
+        self.store_bytes(addr.to_u64(), &[value.to_u8()])
+    }
+
+    fn store16(&mut self, addr: &R, value: &R) -> Result<(), Error> {
+        self.watchpoints.check(addr.to_u64(), 2, true)?;
+        // RISC-V is little-endian by specification
+        self.store_bytes(addr.to_u64(), &value.to_u16().to_le_bytes())
+    }
+
+    fn store32(&mut self, addr: &R, value: &R) -> Result<(), Error> {
+        self.watchpoints.check(addr.to_u64(), 4, true)?;
+        self.store_bytes(addr.to_u64(), &value.to_u32().to_le_bytes())
+    }
+
+    fn store64(&mut self, addr: &R, value: &R) -> Result<(), Error> {
+        self.watchpoints.check(addr.to_u64(), 8, true)?;
+        self.store_bytes(addr.to_u64(), &value.to_u64().to_le_bytes())
+    }
+}
+
+impl<R> Default for LazyMemory<R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,180 @@
+use super::super::Error;
+use super::{round_page_down, round_page_up};
+
+use alloc::{collections::BTreeMap, vec::Vec};
+
+/// A range of memory mapped by a program or the host, see Memory::mappings.
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub struct Mapping {
+    pub start: u64,
+    pub end: u64,
+    // PROT_* bits the range was mapped with
+    pub prot: u64,
+}
+
+// Page aligned mappings of a memory, shared by memory implementations to
+// back Memory::map_region and Memory::unmap_region. The table only keeps
+// track of ranges, page content and flags are left to the memory.
+#[derive(Debug, Clone, Default)]
+pub struct MappingTable {
+    // Start address -> (end, prot)
+    regions: BTreeMap<u64, (u64, u64)>,
+}
+
+impl MappingTable {
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.regions.clear();
+    }
+
+    pub fn mappings(&self) -> Vec<Mapping> {
+        self.regions
+            .iter()
+            .map(|(start, (end, prot))| Mapping {
+                start: *start,
+                end: *end,
+                prot: *prot,
+            })
+            .collect()
+    }
+
+    // Page aligned [addr, end) covering len bytes from addr
+    fn range(addr: u64, len: u64, memory_size: u64) -> Result<(u64, u64), Error> {
+        if round_page_down(addr) != addr || len == 0 {
+            return Err(Error::Unaligned);
+        }
+        let end = addr
+            .checked_add(round_page_up(len))
+            .ok_or(Error::OutOfBound)?;
+        if end > memory_size {
+            return Err(Error::OutOfBound);
+        }
+        Ok((addr, end))
+    }
+
+    // Tells if any mapping overlaps [addr, end)
+    pub fn overlaps(&self, addr: u64, end: u64) -> bool {
+        self.regions
+            .range(..end)
+            .next_back()
+            .is_some_and(|(_, (mapping_end, _))| *mapping_end > addr)
+    }
+
+    // Maps len bytes rounded up to whole pages at the page aligned addr.
+    // Overlapping existing mappings fails with Error::InvalidPermission
+    // unless fixed is set, in which case they are replaced as with
+    // MAP_FIXED.
+    pub fn map(
+        &mut self,
+        addr: u64,
+        len: u64,
+        prot: u64,
+        fixed: bool,
+        memory_size: u64,
+    ) -> Result<(), Error> {
+        let (start, end) = Self::range(addr, len, memory_size)?;
+        if self.overlaps(start, end) {
+            if !fixed {
+                return Err(Error::InvalidPermission);
+            }
+            self.remove(start, end);
+        }
+        self.regions.insert(start, (end, prot));
+        Ok(())
+    }
+
+    // Unmaps len bytes rounded up to whole pages at the page aligned addr,
+    // mappings partially covered are split. Pages that are not mapped are
+    // skipped as on Linux, the ranges actually unmapped are returned.
+    pub fn unmap(
+        &mut self,
+        addr: u64,
+        len: u64,
+        memory_size: u64,
+    ) -> Result<Vec<(u64, u64)>, Error> {
+        let (start, end) = Self::range(addr, len, memory_size)?;
+        Ok(self.remove(start, end))
+    }
+
+    fn remove(&mut self, start: u64, end: u64) -> Vec<(u64, u64)> {
+        let overlapping: Vec<(u64, u64, u64)> = self
+            .regions
+            .range(..end)
+            .filter(|(_, (mapping_end, _))| *mapping_end > start)
+            .map(|(mapping_start, (mapping_end, prot))| (*mapping_start, *mapping_end, *prot))
+            .collect();
+        let mut removed = Vec::with_capacity(overlapping.len());
+        for (mapping_start, mapping_end, prot) in overlapping {
+            self.regions.remove(&mapping_start);
+            if mapping_start < start {
+                self.regions.insert(mapping_start, (start, prot));
+            }
+            if mapping_end > end {
+                self.regions.insert(end, (mapping_end, prot));
+            }
+            removed.push((mapping_start.max(start), mapping_end.min(end)));
+        }
+        removed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mapping_table() {
+        let mut table = MappingTable::default();
+        assert_eq!(
+            table.map(0x1001, 16, 3, false, 0x10000),
+            Err(Error::Unaligned)
+        );
+        assert_eq!(
+            table.map(0x1000, 0, 3, false, 0x10000),
+            Err(Error::Unaligned)
+        );
+        assert_eq!(
+            table.map(0xf000, 0x2000, 3, false, 0x10000),
+            Err(Error::OutOfBound)
+        );
+        table.map(0x1000, 0x3000, 3, false, 0x10000).unwrap();
+        assert_eq!(
+            table.map(0x3000, 16, 1, false, 0x10000),
+            Err(Error::InvalidPermission)
+        );
+        // Replacing the middle page splits the mapping
+        table.map(0x2000, 16, 1, true, 0x10000).unwrap();
+        assert_eq!(
+            table.mappings(),
+            [
+                Mapping {
+                    start: 0x1000,
+                    end: 0x2000,
+                    prot: 3
+                },
+                Mapping {
+                    start: 0x2000,
+                    end: 0x3000,
+                    prot: 1
+                },
+                Mapping {
+                    start: 0x3000,
+                    end: 0x4000,
+                    prot: 3
+                },
+            ]
+        );
+        assert_eq!(table.unmap(0x1800, 16, 0x10000), Err(Error::Unaligned));
+        assert_eq!(
+            table.unmap(0x0000, 0x2800, 0x10000).unwrap(),
+            [(0x1000, 0x2000), (0x2000, 0x3000)]
+        );
+        assert_eq!(table.unmap(0x8000, 16, 0x10000).unwrap(), []);
+        assert_eq!(table.mappings().len(), 1);
+        table.clear();
+        assert!(table.is_empty());
+    }
+}
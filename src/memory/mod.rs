@@ -1,15 +1,32 @@
 use super::{
     bits::{rounddown, roundup},
-    Error, Register, RISCV_PAGESIZE,
+    Error, Register, DEFAULT_STACK_SIZE, RISCV_MAX_MEMORY, RISCV_PAGESIZE,
 };
+use alloc::vec::Vec;
 use bytes::Bytes;
-use std::cmp::min;
-use std::ptr;
+use core::cmp::min;
+use core::ptr;
+use watchpoint::WatchpointKind;
 
+pub mod cow;
+pub mod dirty;
+pub mod dump;
 pub mod flat;
+#[cfg(all(feature = "std", unix))]
+pub mod guarded;
+pub mod host;
+pub mod lazy;
+pub mod mappings;
+pub mod paged;
 pub mod sparse;
+#[cfg(feature = "mmu")]
+pub mod sv39;
+pub mod watchpoint;
 pub mod wxorx;
 
+pub use dump::{diff, ChangedRange, MemoryDiff};
+pub use mappings::Mapping;
+
 pub use ckb_vm_definitions::memory::{
     FLAG_EXECUTABLE, FLAG_FREEZED, FLAG_WRITABLE, FLAG_WXORX_BIT,
 };
@@ -26,6 +43,16 @@ pub fn round_page_up(x: u64) -> u64 {
 
 pub type Page = [u8; RISCV_PAGESIZE];
 
+// Memory size must be a power of 2 that is large enough to hold the default
+// stack.
+pub fn check_memory_size(size: usize) -> Result<(), Error> {
+    if size.is_power_of_two() && size >= DEFAULT_STACK_SIZE {
+        Ok(())
+    } else {
+        Err(Error::InvalidMemorySize(size as u64))
+    }
+}
+
 pub trait Memory<R: Register> {
     fn init_pages(
         &mut self,
@@ -36,9 +63,137 @@ pub trait Memory<R: Register> {
         offset_from_addr: u64,
     ) -> Result<(), Error>;
     fn fetch_flag(&mut self, page: u64) -> Result<u8, Error>;
+    // Size of the address space in bytes, memory created by default always
+    // has RISCV_MAX_MEMORY bytes.
+    fn memory_size(&self) -> usize {
+        RISCV_MAX_MEMORY
+    }
+    // Changes the size of the address space, existing memory content is
+    // discarded so this is meant to be done before loading programs. Memory
+    // implementations with a fixed size only accept their current size.
+    fn set_memory_size(&mut self, size: usize) -> Result<(), Error> {
+        if size == self.memory_size() {
+            Ok(())
+        } else {
+            Err(Error::InvalidMemorySize(size as u64))
+        }
+    }
+    // Bytes of memory allocated to back the address space so far, memory
+    // that allocates pages lazily reports the pages touched by reads or
+    // writes, while memory allocated upfront reports its whole size.
+    fn touched_memory(&self) -> usize {
+        self.memory_size()
+    }
+    // Bytes of the pages accessed since the memory was created or reset,
+    // which is the most memory a program used whatever its layout. Memory
+    // allocating pages lazily never releases them, so this is the same as
+    // touched_memory there, while FlatMemory tracks the pages accessed.
+    fn high_watermark(&self) -> usize {
+        self.touched_memory()
+    }
+    // Watches the range [addr, addr + len), an instruction accessing it in a
+    // way matching kind stops execution with Error::Watchpoint before the
+    // access takes place. Memory implementations not supporting watchpoints
+    // return Error::Unimplemented.
+    fn add_watchpoint(
+        &mut self,
+        _addr: u64,
+        _len: u64,
+        _kind: WatchpointKind,
+    ) -> Result<(), Error> {
+        Err(Error::Unimplemented)
+    }
+    fn remove_watchpoint(
+        &mut self,
+        _addr: u64,
+        _len: u64,
+        _kind: WatchpointKind,
+    ) -> Result<(), Error> {
+        Err(Error::Unimplemented)
+    }
     // This is in fact just memset
     fn store_byte(&mut self, addr: u64, size: u64, value: u8) -> Result<(), Error>;
     fn store_bytes(&mut self, addr: u64, value: &[u8]) -> Result<(), Error>;
+    // Reads a range of bytes, this serves as the counterpart of store_bytes.
+    // The default implementation works byte by byte, memory implementations
+    // are encouraged to override it with a faster version.
+    fn load_bytes(&mut self, addr: u64, size: u64) -> Result<Vec<u8>, Error> {
+        addr.checked_add(size).ok_or(Error::OutOfBound)?;
+        // Programs control size, so never reserve more than the memory
+        // holds, the first failing load8 stops oversized reads.
+        let mut result = Vec::with_capacity(size.min(self.memory_size() as u64) as usize);
+        for current_addr in addr..addr + size {
+            result.push(self.load8(&R::from_u64(current_addr))?.to_u8());
+        }
+        Ok(result)
+    }
+    // Copies len bytes starting at addr for inspection, e.g. to compare
+    // memory before and after a run with diff. Like load_bytes, it does not
+    // trigger watchpoints in memory implementations overriding load_bytes.
+    fn dump_range(&mut self, addr: u64, len: u64) -> Result<Vec<u8>, Error> {
+        self.load_bytes(addr, len)
+    }
+    // Indices of pages written since the last call to clear_dirty, or since
+    // the memory was created, in ascending order. This is meant for
+    // incremental snapshots and copying out results after a run. Memory
+    // implementations not tracking writes return Error::Unimplemented.
+    fn dirty_pages(&self) -> Result<Vec<u64>, Error> {
+        Err(Error::Unimplemented)
+    }
+    fn clear_dirty(&mut self) -> Result<(), Error> {
+        Err(Error::Unimplemented)
+    }
+    // Changes the flags of pages in [addr, addr + size) while keeping their
+    // content, addr and size must be page aligned and frozen pages cannot be
+    // changed. Memory implementations not tracking permissions return
+    // Error::Unimplemented.
+    fn mprotect(&mut self, _addr: u64, _size: u64, _flags: u8) -> Result<(), Error> {
+        Err(Error::Unimplemented)
+    }
+    // Exposes data read-only at addr without copying it, so large buffers
+    // such as witnesses or cell data can be handed to programs without
+    // doubling memory use. addr must be page aligned, the window covers
+    // whole pages and bytes past the end of data read as zeros. Writes to
+    // the window fail with Error::MemoryProtection, and data is kept alive
+    // until the window is unmapped or the memory is reset. Memory
+    // implementations not supporting this return Error::Unimplemented.
+    fn map_host_slice(&mut self, _addr: u64, _data: Bytes) -> Result<(), Error> {
+        Err(Error::Unimplemented)
+    }
+    // Removes the window mapped at addr, whatever it was hiding shows
+    // again. Error::OutOfBound is returned when no window starts at addr.
+    fn unmap_host_slice(&mut self, _addr: u64) -> Result<(), Error> {
+        Err(Error::Unimplemented)
+    }
+    // Records a mapping of len bytes, rounded up to whole pages, at the page
+    // aligned addr with the given PROT_* bits. Overlapping an existing
+    // mapping fails with Error::InvalidPermission unless fixed is set, which
+    // replaces the overlapped part as MAP_FIXED does. Only bookkeeping is
+    // done, content and page flags are left untouched. Memory
+    // implementations not tracking mappings return Error::Unimplemented.
+    fn map_region(&mut self, _addr: u64, _len: u64, _prot: u64, _fixed: bool) -> Result<(), Error> {
+        Err(Error::Unimplemented)
+    }
+    // Removes mappings within len bytes, rounded up to whole pages, at the
+    // page aligned addr, splitting those partially covered. Pages that are
+    // not mapped are skipped as on Linux.
+    fn unmap_region(&mut self, _addr: u64, _len: u64) -> Result<(), Error> {
+        Err(Error::Unimplemented)
+    }
+    // Current mappings ordered by address
+    fn mappings(&self) -> Vec<Mapping> {
+        Vec::new()
+    }
+    // Brings memory back to the state it was created in while keeping its
+    // allocations, so a machine can be reused for another run. Content is
+    // zeroed, or goes back to the shared image or page source the memory is
+    // built on, and page flags, watchpoints, dirty pages and host windows
+    // are cleared.
+    // Memory implementations not supporting this return
+    // Error::Unimplemented.
+    fn reset(&mut self) -> Result<(), Error> {
+        Err(Error::Unimplemented)
+    }
     fn execute_load16(&mut self, addr: u64) -> Result<u16, Error>;
 
     // Methods below are used to implement RISC-V instructions, to make JIT
@@ -99,7 +254,7 @@ pub fn check_permission<R: Register>(
         let page = current_addr / RISCV_PAGESIZE as u64;
         let page_flag = memory.fetch_flag(page)?;
         if (page_flag & FLAG_WXORX_BIT) != (flag & FLAG_WXORX_BIT) {
-            return Err(Error::InvalidPermission);
+            return Err(Error::MemoryProtection);
         }
         current_addr += RISCV_PAGESIZE as u64;
     }
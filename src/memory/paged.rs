@@ -0,0 +1,385 @@
+use super::super::{Error, Register, RISCV_MAX_MEMORY, RISCV_PAGESIZE};
+use super::{
+    check_memory_size,
+    dirty::DirtyPages,
+    fill_page_data,
+    host::HostRegions,
+    mappings::{Mapping, MappingTable},
+    memset, round_page_down,
+    watchpoint::{WatchpointKind, Watchpoints},
+    Memory, Page,
+};
+
+use alloc::{boxed::Box, vec::Vec};
+use bytes::Bytes;
+use core::cmp::min;
+use core::marker::PhantomData;
+
+// Each page table covers 2MB of address space
+const TABLE_SHIFT: u64 = 9;
+const TABLE_ENTRIES: usize = 1 << TABLE_SHIFT;
+
+// Largest address space supported, 256GB. The directory takes 8 bytes per
+// 2MB and dirty page tracking 1 bit per page, both are allocated up front.
+pub const PAGED_MAX_MEMORY: u64 = 1 << 38;
+
+type PageTable = Box<[Option<Box<Page>>]>;
+
+/// A sparse memory for big programs, pages are found through a two level
+/// page directory instead of a flat index, so the address space can grow
+/// to many gigabytes while only touched pages and the page tables holding
+/// them are allocated. Like SparseMemory, it does no permission checking.
+pub struct PagedMemory<R> {
+    // One entry per TABLE_ENTRIES pages, tables are allocated on the first
+    // write to any of their pages.
+    directory: Vec<Option<PageTable>>,
+    memory_size: usize,
+    touched_pages: usize,
+    watchpoints: Watchpoints,
+    dirty: DirtyPages,
+    host: HostRegions,
+    mappings: MappingTable,
+    _inner: PhantomData<R>,
+}
+
+impl<R> PagedMemory<R> {
+    pub fn new() -> Self {
+        Self::new_with_memory_size(RISCV_MAX_MEMORY).expect("default memory size")
+    }
+
+    pub fn new_with_memory_size(memory_size: usize) -> Result<Self, Error> {
+        check_memory_size(memory_size)?;
+        if memory_size as u64 > PAGED_MAX_MEMORY {
+            return Err(Error::InvalidMemorySize(memory_size as u64));
+        }
+        let pages = memory_size / RISCV_PAGESIZE;
+        let mut directory = Vec::new();
+        directory.resize_with(pages.div_ceil(TABLE_ENTRIES), || None);
+        Ok(Self {
+            directory,
+            memory_size,
+            touched_pages: 0,
+            watchpoints: Watchpoints::default(),
+            dirty: DirtyPages::new(pages),
+            host: HostRegions::default(),
+            mappings: MappingTable::default(),
+            _inner: PhantomData,
+        })
+    }
+
+    // Directory and table index of the page holding addr
+    fn split(&self, addr: u64) -> Result<(usize, usize), Error> {
+        if addr >= self.memory_size as u64 {
+            return Err(Error::OutOfBound);
+        }
+        let page = addr / RISCV_PAGESIZE as u64;
+        Ok((
+            (page >> TABLE_SHIFT) as usize,
+            (page as usize) & (TABLE_ENTRIES - 1),
+        ))
+    }
+
+    // Page holding addr if it has been allocated
+    fn get_page(&self, addr: u64) -> Result<Option<&Page>, Error> {
+        let (dir, index) = self.split(addr)?;
+        Ok(self.directory[dir]
+            .as_ref()
+            .and_then(|table| table[index].as_deref()))
+    }
+
+    fn fetch_page(&mut self, aligned_addr: u64) -> Result<&mut Page, Error> {
+        let (dir, index) = self.split(aligned_addr)?;
+        let table = self.directory[dir].get_or_insert_with(|| {
+            let mut table = Vec::new();
+            table.resize_with(TABLE_ENTRIES, || None);
+            table.into_boxed_slice()
+        });
+        let touched_pages = &mut self.touched_pages;
+        Ok(table[index].get_or_insert_with(|| {
+            *touched_pages += 1;
+            Box::new([0; RISCV_PAGESIZE])
+        }))
+    }
+
+    // Page content for reads, untouched pages read as zeros without being
+    // allocated. Pages under host windows may be shorter than a page, see
+    // HostRegions::page.
+    fn read_page(&self, aligned_addr: u64) -> Result<&[u8], Error> {
+        const ZERO_PAGE: Page = [0; RISCV_PAGESIZE];
+        if self.host.overlaps(aligned_addr, 1) {
+            return Ok(self.host.page(aligned_addr).unwrap_or(&[]));
+        }
+        Ok(self
+            .get_page(aligned_addr)?
+            .map(|page| &page[..])
+            .unwrap_or(&ZERO_PAGE))
+    }
+
+    // Assembles the value byte by byte in little-endian order, whatever the
+    // byte order of the host.
+    fn load(&self, addr: u64, bytes: u64) -> Result<u64, Error> {
+        debug_assert!(bytes == 1 || bytes == 2 || bytes == 4 || bytes == 8);
+        let page_addr = round_page_down(addr);
+        let first_page_bytes = min(bytes, RISCV_PAGESIZE as u64 - (addr - page_addr));
+        let mut value: u64 = 0;
+        let page = self.read_page(page_addr)?;
+        for (i, &byte) in page
+            .iter()
+            .skip((addr - page_addr) as usize)
+            .take(first_page_bytes as usize)
+            .enumerate()
+        {
+            value |= u64::from(byte) << (i * 8);
+        }
+        let second_page_bytes = bytes - first_page_bytes;
+        if second_page_bytes > 0 {
+            let second_page = self.read_page(page_addr + RISCV_PAGESIZE as u64)?;
+            for (i, &byte) in second_page
+                .iter()
+                .take(second_page_bytes as usize)
+                .enumerate()
+            {
+                value |= u64::from(byte) << ((first_page_bytes as usize + i) * 8);
+            }
+        }
+        Ok(value)
+    }
+}
+
+impl<R: Register> Memory<R> for PagedMemory<R> {
+    fn init_pages(
+        &mut self,
+        addr: u64,
+        size: u64,
+        _flags: u8,
+        source: Option<Bytes>,
+        offset_from_addr: u64,
+    ) -> Result<(), Error> {
+        fill_page_data(self, addr, size, source, offset_from_addr)
+    }
+
+    fn fetch_flag(&mut self, page: u64) -> Result<u8, Error> {
+        if page < (self.memory_size / RISCV_PAGESIZE) as u64 {
+            Ok(0)
+        } else {
+            Err(Error::OutOfBound)
+        }
+    }
+
+    fn memory_size(&self) -> usize {
+        self.memory_size
+    }
+
+    fn set_memory_size(&mut self, size: usize) -> Result<(), Error> {
+        *self = Self::new_with_memory_size(size)?;
+        Ok(())
+    }
+
+    fn touched_memory(&self) -> usize {
+        self.touched_pages * RISCV_PAGESIZE
+    }
+
+    fn add_watchpoint(&mut self, addr: u64, len: u64, kind: WatchpointKind) -> Result<(), Error> {
+        self.watchpoints.add(addr, len, kind)
+    }
+
+    fn remove_watchpoint(
+        &mut self,
+        addr: u64,
+        len: u64,
+        kind: WatchpointKind,
+    ) -> Result<(), Error> {
+        self.watchpoints.remove(addr, len, kind);
+        Ok(())
+    }
+
+    fn dirty_pages(&self) -> Result<Vec<u64>, Error> {
+        Ok(self.dirty.pages())
+    }
+
+    fn clear_dirty(&mut self) -> Result<(), Error> {
+        self.dirty.clear();
+        Ok(())
+    }
+
+    fn map_host_slice(&mut self, addr: u64, data: Bytes) -> Result<(), Error> {
+        self.host.map(addr, data, self.memory_size as u64)
+    }
+
+    fn unmap_host_slice(&mut self, addr: u64) -> Result<(), Error> {
+        self.host.unmap(addr).map(|_| ()).ok_or(Error::OutOfBound)
+    }
+
+    // Page tables are released along with their pages, the directory keeps
+    // its size.
+    fn reset(&mut self) -> Result<(), Error> {
+        for table in self.directory.iter_mut() {
+            *table = None;
+        }
+        self.touched_pages = 0;
+        self.watchpoints.clear();
+        self.dirty.clear();
+        self.host.clear();
+        self.mappings.clear();
+        Ok(())
+    }
+
+    fn map_region(&mut self, addr: u64, len: u64, prot: u64, fixed: bool) -> Result<(), Error> {
+        self.mappings
+            .map(addr, len, prot, fixed, self.memory_size as u64)
+    }
+
+    fn unmap_region(&mut self, addr: u64, len: u64) -> Result<(), Error> {
+        self.mappings
+            .unmap(addr, len, self.memory_size as u64)
+            .map(|_| ())
+    }
+
+    fn mappings(&self) -> Vec<Mapping> {
+        self.mappings.mappings()
+    }
+
+    fn load8(&mut self, addr: &R) -> Result<R, Error> {
+        self.watchpoints.check(addr.to_u64(), 1, false)?;
+        let v = self.load(addr.to_u64(), 1).map(|v| v as u8)?;
+        Ok(R::from_u8(v))
+    }
+
+    fn load16(&mut self, addr: &R) -> Result<R, Error> {
+        self.watchpoints.check(addr.to_u64(), 2, false)?;
+        let v = self.load(addr.to_u64(), 2).map(|v| v as u16)?;
+        Ok(R::from_u16(v))
+    }
+
+    fn load32(&mut self, addr: &R) -> Result<R, Error> {
+        self.watchpoints.check(addr.to_u64(), 4, false)?;
+        let v = self.load(addr.to_u64(), 4).map(|v| v as u32)?;
+        Ok(R::from_u32(v))
+    }
+
+    fn load64(&mut self, addr: &R) -> Result<R, Error> {
+        self.watchpoints.check(addr.to_u64(), 8, false)?;
+        let v = self.load(addr.to_u64(), 8)?;
+        Ok(R::from_u64(v))
+    }
+
+    fn execute_load16(&mut self, addr: u64) -> Result<u16, Error> {
+        self.load(addr, 2).map(|v| v as u16)
+    }
+
+    fn store_bytes(&mut self, addr: u64, value: &[u8]) -> Result<(), Error> {
+        if addr
+            .checked_add(value.len() as u64)
+            .ok_or(Error::OutOfBound)?
+            > self.memory_size as u64
+        {
+            return Err(Error::OutOfBound);
+        }
+        if self.host.overlaps(addr, value.len() as u64) {
+            return Err(Error::MemoryProtection);
+        }
+        self.dirty.mark(addr, value.len() as u64);
+        let mut remaining_data = value;
+        let mut current_page_addr = round_page_down(addr);
+        let mut current_page_offset = addr - current_page_addr;
+        while !remaining_data.is_empty() {
+            let page = self.fetch_page(current_page_addr)?;
+            let bytes = min(
+                RISCV_PAGESIZE as u64 - current_page_offset,
+                remaining_data.len() as u64,
+            );
+            let slice =
+                &mut page[current_page_offset as usize..(current_page_offset + bytes) as usize];
+            slice.copy_from_slice(&remaining_data[..bytes as usize]);
+
+            remaining_data = &remaining_data[bytes as usize..];
+            current_page_addr += RISCV_PAGESIZE as u64;
+            current_page_offset = 0;
+        }
+        Ok(())
+    }
+
+    // Unlike typed loads, reading pages that are never written to will not
+    // allocate them here.
+    fn load_bytes(&mut self, addr: u64, size: u64) -> Result<Vec<u8>, Error> {
+        if addr.checked_add(size).ok_or(Error::OutOfBound)? > self.memory_size as u64 {
+            return Err(Error::OutOfBound);
+        }
+        let mut result = Vec::with_capacity(size as usize);
+        let mut current_page_addr = round_page_down(addr);
+        let mut current_page_offset = addr - current_page_addr;
+        let mut remaining_size = size;
+        while remaining_size > 0 {
+            let bytes = min(RISCV_PAGESIZE as u64 - current_page_offset, remaining_size);
+            if let Some(page) = self.host.page(current_page_addr) {
+                let start = min(current_page_offset as usize, page.len());
+                let end = min((current_page_offset + bytes) as usize, page.len());
+                result.extend_from_slice(&page[start..end]);
+                result.resize(result.len() + bytes as usize - (end - start), 0);
+            } else if let Some(page) = self.get_page(current_page_addr)? {
+                result.extend_from_slice(
+                    &page[current_page_offset as usize..(current_page_offset + bytes) as usize],
+                );
+            } else {
+                result.resize(result.len() + bytes as usize, 0);
+            }
+            remaining_size -= bytes;
+            current_page_addr += RISCV_PAGESIZE as u64;
+            current_page_offset = 0;
+        }
+        Ok(result)
+    }
+
+    fn store_byte(&mut self, addr: u64, size: u64, value: u8) -> Result<(), Error> {
+        if addr.checked_add(size).ok_or(Error::OutOfBound)? > self.memory_size as u64 {
+            return Err(Error::OutOfBound);
+        }
+        if self.host.overlaps(addr, size) {
+            return Err(Error::MemoryProtection);
+        }
+        self.dirty.mark(addr, size);
+        let mut current_page_addr = round_page_down(addr);
+        let mut current_page_offset = addr - current_page_addr;
+        let mut remaining_size = size;
+        while remaining_size > 0 {
+            let page = self.fetch_page(current_page_addr)?;
+            let bytes = min(RISCV_PAGESIZE as u64 - current_page_offset, remaining_size);
+            memset(
+                &mut page[current_page_offset as usize..(current_page_offset + bytes) as usize],
+                value,
+            );
+            remaining_size -= bytes;
+            current_page_addr += RISCV_PAGESIZE as u64;
+            current_page_offset = 0;
+        }
+        Ok(())
+    }
+
+    fn store8(&mut self, addr: &R, value: &R) -> Result<(), Error> {
+        self.watchpoints.check(addr.to_u64(), 1, true)?;
+        self.store_bytes(addr.to_u64(), &[value.to_u8()])
+    }
+
+    // RISC-V is little-endian by specification, values are converted
+    // explicitly so memory content does not depend on the host byte order.
+    fn store16(&mut self, addr: &R, value: &R) -> Result<(), Error> {
+        self.watchpoints.check(addr.to_u64(), 2, true)?;
+        self.store_bytes(addr.to_u64(), &value.to_u16().to_le_bytes())
+    }
+
+    fn store32(&mut self, addr: &R, value: &R) -> Result<(), Error> {
+        self.watchpoints.check(addr.to_u64(), 4, true)?;
+        self.store_bytes(addr.to_u64(), &value.to_u32().to_le_bytes())
+    }
+
+    fn store64(&mut self, addr: &R, value: &R) -> Result<(), Error> {
+        self.watchpoints.check(addr.to_u64(), 8, true)?;
+        self.store_bytes(addr.to_u64(), &value.to_u64().to_le_bytes())
+    }
+}
+
+impl<R> Default for PagedMemory<R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
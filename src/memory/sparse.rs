@@ -1,9 +1,19 @@
-use super::super::{Error, Register, RISCV_PAGES, RISCV_PAGESIZE};
-use super::{fill_page_data, memset, round_page_down, Memory, Page};
+use super::super::{Error, Register, RISCV_MAX_MEMORY, RISCV_PAGESIZE};
+use super::{
+    check_memory_size,
+    dirty::DirtyPages,
+    fill_page_data,
+    host::HostRegions,
+    mappings::{Mapping, MappingTable},
+    memset, round_page_down,
+    watchpoint::{WatchpointKind, Watchpoints},
+    Memory, Page,
+};
 
+use alloc::{vec, vec::Vec};
 use bytes::Bytes;
-use std::cmp::min;
-use std::marker::PhantomData;
+use core::cmp::min;
+use core::marker::PhantomData;
 
 const INVALID_PAGE_INDEX: u16 = 0xFFFF;
 
@@ -14,24 +24,42 @@ pub struct SparseMemory<R> {
     // been initialized, the corresponding position will be filled with
     // INVALID_PAGE_INDEX. Considering u16 takes 2 bytes, this add an additional
     // of 64KB extra storage cost assuming we have 128MB memory.
-    indices: [u16; RISCV_PAGES],
+    indices: Vec<u16>,
     pages: Vec<Page>,
+    watchpoints: Watchpoints,
+    dirty: DirtyPages,
+    host: HostRegions,
+    mappings: MappingTable,
     _inner: PhantomData<R>,
 }
 
 impl<R> SparseMemory<R> {
     pub fn new() -> Self {
-        debug_assert!(RISCV_PAGES < INVALID_PAGE_INDEX as usize);
-        Self {
-            indices: [INVALID_PAGE_INDEX; RISCV_PAGES],
+        Self::new_with_memory_size(RISCV_MAX_MEMORY).expect("default memory size")
+    }
+
+    // Page indices are stored as u16, so memory size is also limited by the
+    // number of pages that can be indexed.
+    pub fn new_with_memory_size(memory_size: usize) -> Result<Self, Error> {
+        check_memory_size(memory_size)?;
+        let pages = memory_size / RISCV_PAGESIZE;
+        if pages >= INVALID_PAGE_INDEX as usize {
+            return Err(Error::InvalidMemorySize(memory_size as u64));
+        }
+        Ok(Self {
+            indices: vec![INVALID_PAGE_INDEX; pages],
             pages: Vec::new(),
+            watchpoints: Watchpoints::default(),
+            dirty: DirtyPages::new(pages),
+            host: HostRegions::default(),
+            mappings: MappingTable::default(),
             _inner: PhantomData,
-        }
+        })
     }
 
     fn fetch_page(&mut self, aligned_addr: u64) -> Result<&mut Page, Error> {
         let page = aligned_addr / RISCV_PAGESIZE as u64;
-        if page >= RISCV_PAGES as u64 {
+        if page >= self.indices.len() as u64 {
             return Err(Error::OutOfBound);
         }
         let mut index = self.indices[page as usize];
@@ -43,29 +71,42 @@ impl<R> SparseMemory<R> {
         Ok(&mut self.pages[index as usize])
     }
 
+    // Page content for reads, pages under host windows may be shorter than
+    // a page, see HostRegions::page.
+    fn read_page(&mut self, aligned_addr: u64) -> Result<&[u8], Error> {
+        if self.host.overlaps(aligned_addr, 1) {
+            return Ok(self.host.page(aligned_addr).unwrap_or(&[]));
+        }
+        self.fetch_page(aligned_addr).map(|page| &page[..])
+    }
+
+    // Assembles the value byte by byte in little-endian order, whatever the
+    // byte order of the host.
     fn load(&mut self, addr: u64, bytes: u64) -> Result<u64, Error> {
         debug_assert!(bytes == 1 || bytes == 2 || bytes == 4 || bytes == 8);
         let page_addr = round_page_down(addr);
         let first_page_bytes = min(bytes, RISCV_PAGESIZE as u64 - (addr - page_addr));
-        let mut shift = 0;
         let mut value: u64 = 0;
         {
-            let page = self.fetch_page(page_addr)?;
-            for &byte in page
+            let page = self.read_page(page_addr)?;
+            for (i, &byte) in page
                 .iter()
                 .skip((addr - page_addr) as usize)
                 .take(first_page_bytes as usize)
+                .enumerate()
             {
-                value |= u64::from(byte) << shift;
-                shift += 8;
+                value |= u64::from(byte) << (i * 8);
             }
         }
         let second_page_bytes = bytes - first_page_bytes;
         if second_page_bytes > 0 {
-            let second_page = self.fetch_page(page_addr + RISCV_PAGESIZE as u64)?;
-            for &byte in second_page.iter().take(second_page_bytes as usize) {
-                value |= u64::from(byte) << shift;
-                shift += 8;
+            let second_page = self.read_page(page_addr + RISCV_PAGESIZE as u64)?;
+            for (i, &byte) in second_page
+                .iter()
+                .take(second_page_bytes as usize)
+                .enumerate()
+            {
+                value |= u64::from(byte) << ((first_page_bytes as usize + i) * 8);
             }
         }
         Ok(value)
@@ -85,29 +126,105 @@ impl<R: Register> Memory<R> for SparseMemory<R> {
     }
 
     fn fetch_flag(&mut self, page: u64) -> Result<u8, Error> {
-        if page < RISCV_PAGES as u64 {
+        if page < self.indices.len() as u64 {
             Ok(0)
         } else {
             Err(Error::OutOfBound)
         }
     }
 
+    fn memory_size(&self) -> usize {
+        self.indices.len() * RISCV_PAGESIZE
+    }
+
+    fn set_memory_size(&mut self, size: usize) -> Result<(), Error> {
+        *self = Self::new_with_memory_size(size)?;
+        Ok(())
+    }
+
+    fn touched_memory(&self) -> usize {
+        self.pages.len() * RISCV_PAGESIZE
+    }
+
+    fn add_watchpoint(&mut self, addr: u64, len: u64, kind: WatchpointKind) -> Result<(), Error> {
+        self.watchpoints.add(addr, len, kind)
+    }
+
+    fn remove_watchpoint(
+        &mut self,
+        addr: u64,
+        len: u64,
+        kind: WatchpointKind,
+    ) -> Result<(), Error> {
+        self.watchpoints.remove(addr, len, kind);
+        Ok(())
+    }
+
+    fn dirty_pages(&self) -> Result<Vec<u64>, Error> {
+        Ok(self.dirty.pages())
+    }
+
+    fn clear_dirty(&mut self) -> Result<(), Error> {
+        self.dirty.clear();
+        Ok(())
+    }
+
+    fn map_host_slice(&mut self, addr: u64, data: Bytes) -> Result<(), Error> {
+        let memory_size = Memory::<R>::memory_size(self) as u64;
+        self.host.map(addr, data, memory_size)
+    }
+
+    fn unmap_host_slice(&mut self, addr: u64) -> Result<(), Error> {
+        self.host.unmap(addr).map(|_| ()).ok_or(Error::OutOfBound)
+    }
+
+    // Pages are dropped but the page storage keeps its capacity
+    fn reset(&mut self) -> Result<(), Error> {
+        for index in self.indices.iter_mut() {
+            *index = INVALID_PAGE_INDEX;
+        }
+        self.pages.clear();
+        self.watchpoints.clear();
+        self.dirty.clear();
+        self.host.clear();
+        self.mappings.clear();
+        Ok(())
+    }
+
+    fn map_region(&mut self, addr: u64, len: u64, prot: u64, fixed: bool) -> Result<(), Error> {
+        let memory_size = Memory::<R>::memory_size(self) as u64;
+        self.mappings.map(addr, len, prot, fixed, memory_size)
+    }
+
+    fn unmap_region(&mut self, addr: u64, len: u64) -> Result<(), Error> {
+        let memory_size = Memory::<R>::memory_size(self) as u64;
+        self.mappings.unmap(addr, len, memory_size).map(|_| ())
+    }
+
+    fn mappings(&self) -> Vec<Mapping> {
+        self.mappings.mappings()
+    }
+
     fn load8(&mut self, addr: &R) -> Result<R, Error> {
+        self.watchpoints.check(addr.to_u64(), 1, false)?;
         let v = self.load(addr.to_u64(), 1).map(|v| v as u8)?;
         Ok(R::from_u8(v))
     }
 
     fn load16(&mut self, addr: &R) -> Result<R, Error> {
+        self.watchpoints.check(addr.to_u64(), 2, false)?;
         let v = self.load(addr.to_u64(), 2).map(|v| v as u16)?;
         Ok(R::from_u16(v))
     }
 
     fn load32(&mut self, addr: &R) -> Result<R, Error> {
+        self.watchpoints.check(addr.to_u64(), 4, false)?;
         let v = self.load(addr.to_u64(), 4).map(|v| v as u32)?;
         Ok(R::from_u32(v))
     }
 
     fn load64(&mut self, addr: &R) -> Result<R, Error> {
+        self.watchpoints.check(addr.to_u64(), 8, false)?;
         let v = self.load(addr.to_u64(), 8)?;
         Ok(R::from_u64(v))
     }
@@ -117,6 +234,10 @@ impl<R: Register> Memory<R> for SparseMemory<R> {
     }
 
     fn store_bytes(&mut self, addr: u64, value: &[u8]) -> Result<(), Error> {
+        if self.host.overlaps(addr, value.len() as u64) {
+            return Err(Error::MemoryProtection);
+        }
+        self.dirty.mark(addr, value.len() as u64);
         let mut remaining_data = value;
         let mut current_page_addr = round_page_down(addr);
         let mut current_page_offset = addr - current_page_addr;
@@ -137,7 +258,45 @@ impl<R: Register> Memory<R> for SparseMemory<R> {
         Ok(())
     }
 
+    // Unlike typed loads, reading pages that are never written to will not
+    // allocate them here.
+    fn load_bytes(&mut self, addr: u64, size: u64) -> Result<Vec<u8>, Error> {
+        if addr.checked_add(size).ok_or(Error::OutOfBound)? > Memory::<R>::memory_size(self) as u64
+        {
+            return Err(Error::OutOfBound);
+        }
+        let mut result = Vec::with_capacity(size as usize);
+        let mut current_page_addr = round_page_down(addr);
+        let mut current_page_offset = addr - current_page_addr;
+        let mut remaining_size = size;
+        while remaining_size > 0 {
+            let bytes = min(RISCV_PAGESIZE as u64 - current_page_offset, remaining_size);
+            let index = self.indices[current_page_addr as usize / RISCV_PAGESIZE];
+            if let Some(page) = self.host.page(current_page_addr) {
+                let start = min(current_page_offset as usize, page.len());
+                let end = min((current_page_offset + bytes) as usize, page.len());
+                result.extend_from_slice(&page[start..end]);
+                result.resize(result.len() + bytes as usize - (end - start), 0);
+            } else if index == INVALID_PAGE_INDEX {
+                result.resize(result.len() + bytes as usize, 0);
+            } else {
+                let page = &self.pages[index as usize];
+                result.extend_from_slice(
+                    &page[current_page_offset as usize..(current_page_offset + bytes) as usize],
+                );
+            }
+            remaining_size -= bytes;
+            current_page_addr += RISCV_PAGESIZE as u64;
+            current_page_offset = 0;
+        }
+        Ok(result)
+    }
+
     fn store_byte(&mut self, addr: u64, size: u64, value: u8) -> Result<(), Error> {
+        if self.host.overlaps(addr, size) {
+            return Err(Error::MemoryProtection);
+        }
+        self.dirty.mark(addr, size);
         let mut current_page_addr = round_page_down(addr);
         let mut current_page_offset = addr - current_page_addr;
         let mut remaining_size = size;
@@ -156,45 +315,25 @@ impl<R: Register> Memory<R> for SparseMemory<R> {
     }
 
     fn store8(&mut self, addr: &R, value: &R) -> Result<(), Error> {
+        self.watchpoints.check(addr.to_u64(), 1, true)?;
         self.store_bytes(addr.to_u64(), &[value.to_u8()])
     }
 
+    // RISC-V is little-endian by specification, values are converted
+    // explicitly so memory content does not depend on the host byte order.
     fn store16(&mut self, addr: &R, value: &R) -> Result<(), Error> {
-        let value = value.to_u16();
-        // RISC-V is little-endian by specification
-        self.store_bytes(addr.to_u64(), &[(value & 0xFF) as u8, (value >> 8) as u8])
+        self.watchpoints.check(addr.to_u64(), 2, true)?;
+        self.store_bytes(addr.to_u64(), &value.to_u16().to_le_bytes())
     }
 
     fn store32(&mut self, addr: &R, value: &R) -> Result<(), Error> {
-        let value = value.to_u32();
-        // RISC-V is little-endian by specification
-        self.store_bytes(
-            addr.to_u64(),
-            &[
-                (value & 0xFF) as u8,
-                ((value >> 8) & 0xFF) as u8,
-                ((value >> 16) & 0xFF) as u8,
-                ((value >> 24) & 0xFF) as u8,
-            ],
-        )
+        self.watchpoints.check(addr.to_u64(), 4, true)?;
+        self.store_bytes(addr.to_u64(), &value.to_u32().to_le_bytes())
     }
 
     fn store64(&mut self, addr: &R, value: &R) -> Result<(), Error> {
-        let value = value.to_u64();
-        // RISC-V is little-endian by specification
-        self.store_bytes(
-            addr.to_u64(),
-            &[
-                (value & 0xFF) as u8,
-                ((value >> 8) & 0xFF) as u8,
-                ((value >> 16) & 0xFF) as u8,
-                ((value >> 24) & 0xFF) as u8,
-                ((value >> 32) & 0xFF) as u8,
-                ((value >> 40) & 0xFF) as u8,
-                ((value >> 48) & 0xFF) as u8,
-                ((value >> 56) & 0xFF) as u8,
-            ],
-        )
+        self.watchpoints.check(addr.to_u64(), 8, true)?;
+        self.store_bytes(addr.to_u64(), &value.to_u64().to_le_bytes())
     }
 }
 
@@ -0,0 +1,457 @@
+use super::super::{Error, Register, RISCV_PAGESIZE};
+use super::{watchpoint::WatchpointKind, Mapping, Memory};
+
+use alloc::{vec, vec::Vec};
+use byteorder::{ByteOrder, LittleEndian};
+use bytes::Bytes;
+use core::marker::PhantomData;
+
+// MODE field of satp, in its top 4 bits
+pub const SATP_MODE_BARE: u64 = 0;
+pub const SATP_MODE_SV39: u64 = 8;
+const SATP_MODE_SHIFT: u64 = 60;
+const SATP_PPN_MASK: u64 = (1 << 44) - 1;
+
+// Page table entry bits
+pub const PTE_V: u64 = 1 << 0;
+pub const PTE_R: u64 = 1 << 1;
+pub const PTE_W: u64 = 1 << 2;
+pub const PTE_X: u64 = 1 << 3;
+pub const PTE_U: u64 = 1 << 4;
+pub const PTE_G: u64 = 1 << 5;
+pub const PTE_A: u64 = 1 << 6;
+pub const PTE_D: u64 = 1 << 7;
+const PTE_PPN_SHIFT: u64 = 10;
+
+const LEVELS: u64 = 3;
+const VPN_BITS: u64 = 9;
+const PAGE_SHIFT: u64 = 12;
+const PAGE_OFFSET_MASK: u64 = RISCV_PAGESIZE as u64 - 1;
+// Number of entries of the software TLB, which is direct mapped
+const TLB_ENTRIES: usize = 64;
+
+/// Kind of access a virtual address is translated for.
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub enum Access {
+    Load,
+    Store,
+    Fetch,
+}
+
+impl Access {
+    fn permission(self) -> u64 {
+        match self {
+            Access::Load => PTE_R,
+            Access::Store => PTE_W,
+            Access::Fetch => PTE_X,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct TlbEntry {
+    // Virtual page number, u64::MAX for empty entries
+    vpn: u64,
+    // Physical page number of the 4K page, superpages are cached per 4K page
+    ppn: u64,
+    // R, W and X bits of the leaf, W only once D has been set
+    permissions: u64,
+}
+
+const EMPTY_ENTRY: TlbEntry = TlbEntry {
+    vpn: u64::MAX,
+    ppn: 0,
+    permissions: 0,
+};
+
+/// Sv39 address translation on top of a memory holding physical pages, for
+/// guests such as RTOS images that set up their own page tables. Loads,
+/// stores and instruction fetches, including the ranges accessed by
+/// load_bytes, store_bytes and store_byte, use virtual addresses that are
+/// translated through the page tables rooted at satp, see set_satp. Page
+/// granular operations such as init_pages, mprotect, watchpoints and dirty
+/// pages work on physical addresses. satp starts in bare mode where
+/// virtual and physical addresses are the same, so programs can be loaded
+/// as usual before paging is turned on.
+///
+/// Translations are cached in a software TLB, which like sfence.vma must
+/// be flushed with flush_tlb once the page tables change. Accessed and dirty
+/// bits are set by the translation as on hardware doing so. Accesses that
+/// cannot be translated fail with Error::PageFault, the privilege level is
+/// not modeled so the U bit is ignored. Only DefaultMachine runs on this
+/// memory, TraceMachine caches code by virtual address.
+pub struct Sv39Memory<R: Register, M: Memory<R>> {
+    inner: M,
+    satp: u64,
+    tlb: Vec<TlbEntry>,
+    tlb_hits: u64,
+    tlb_misses: u64,
+    _inner: PhantomData<R>,
+}
+
+impl<R: Register, M: Memory<R> + Default> Default for Sv39Memory<R, M> {
+    fn default() -> Self {
+        Self::new(M::default())
+    }
+}
+
+impl<R: Register, M: Memory<R>> Sv39Memory<R, M> {
+    pub fn new(inner: M) -> Self {
+        Self {
+            inner,
+            satp: 0,
+            tlb: vec![EMPTY_ENTRY; TLB_ENTRIES],
+            tlb_hits: 0,
+            tlb_misses: 0,
+            _inner: PhantomData,
+        }
+    }
+
+    pub fn inner(&self) -> &M {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut dyn Memory<R> {
+        &mut self.inner
+    }
+
+    pub fn satp(&self) -> u64 {
+        self.satp
+    }
+
+    // Sets the MODE and PPN of the root page table like writing satp does,
+    // the ASID is kept but not used to tag translations. Modes other than
+    // bare and Sv39 fail with Error::Unimplemented. The TLB is flushed.
+    pub fn set_satp(&mut self, satp: u64) -> Result<(), Error> {
+        match satp >> SATP_MODE_SHIFT {
+            SATP_MODE_BARE | SATP_MODE_SV39 => (),
+            _ => return Err(Error::Unimplemented),
+        }
+        self.satp = satp;
+        self.flush_tlb();
+        Ok(())
+    }
+
+    pub fn paging_enabled(&self) -> bool {
+        self.satp >> SATP_MODE_SHIFT == SATP_MODE_SV39
+    }
+
+    // Drops all cached translations, the counterpart of sfence.vma
+    pub fn flush_tlb(&mut self) {
+        for entry in self.tlb.iter_mut() {
+            *entry = EMPTY_ENTRY;
+        }
+    }
+
+    // Translations served by the TLB and page table walks so far
+    pub fn tlb_stats(&self) -> (u64, u64) {
+        (self.tlb_hits, self.tlb_misses)
+    }
+
+    // Physical address addr is accessed at, walking the page tables on a
+    // TLB miss.
+    pub fn translate(&mut self, addr: u64, access: Access) -> Result<u64, Error> {
+        if !self.paging_enabled() {
+            return Ok(addr);
+        }
+        // Bits 63 to 39 must all be copies of bit 38
+        if ((addr as i64) << 25 >> 25) as u64 != addr {
+            return Err(Error::PageFault { addr, pc: 0 });
+        }
+        let vpn = (addr >> PAGE_SHIFT) & ((1 << (LEVELS * VPN_BITS)) - 1);
+        let entry = self.tlb[vpn as usize % TLB_ENTRIES];
+        if entry.vpn == vpn && entry.permissions & access.permission() != 0 {
+            self.tlb_hits += 1;
+            return Ok((entry.ppn << PAGE_SHIFT) | (addr & PAGE_OFFSET_MASK));
+        }
+        self.tlb_misses += 1;
+        let ppn = self.walk(addr, vpn, access)?;
+        Ok((ppn << PAGE_SHIFT) | (addr & PAGE_OFFSET_MASK))
+    }
+
+    fn walk(&mut self, addr: u64, vpn: u64, access: Access) -> Result<u64, Error> {
+        let fault = Error::PageFault { addr, pc: 0 };
+        let mut table = (self.satp & SATP_PPN_MASK) << PAGE_SHIFT;
+        for level in (0..LEVELS).rev() {
+            let index = (vpn >> (level * VPN_BITS)) & ((1 << VPN_BITS) - 1);
+            let pte_addr = table + index * 8;
+            let mut pte = LittleEndian::read_u64(&self.inner.load_bytes(pte_addr, 8)?);
+            if pte & PTE_V == 0 || (pte & PTE_R == 0 && pte & PTE_W != 0) {
+                return Err(fault);
+            }
+            let ppn = (pte >> PTE_PPN_SHIFT) & SATP_PPN_MASK;
+            if pte & (PTE_R | PTE_X) == 0 {
+                table = ppn << PAGE_SHIFT;
+                continue;
+            }
+            let superpage_mask = (1 << (level * VPN_BITS)) - 1;
+            if pte & access.permission() == 0 || ppn & superpage_mask != 0 {
+                return Err(fault);
+            }
+            let updated = pte | PTE_A | if access == Access::Store { PTE_D } else { 0 };
+            if updated != pte {
+                pte = updated;
+                self.inner.store_bytes(pte_addr, &pte.to_le_bytes())?;
+            }
+            // Pages not dirty yet are cached without write permission, so
+            // the first store walks again and sets D.
+            let mut permissions = pte & (PTE_R | PTE_W | PTE_X);
+            if pte & PTE_D == 0 {
+                permissions &= !PTE_W;
+            }
+            let ppn = ppn | (vpn & superpage_mask);
+            self.tlb[vpn as usize % TLB_ENTRIES] = TlbEntry {
+                vpn,
+                ppn,
+                permissions,
+            };
+            return Ok(ppn);
+        }
+        Err(fault)
+    }
+
+    // Physical address of an access of size bytes that stays within a page,
+    // or None when it crosses into the next one.
+    fn translate_within_page(
+        &mut self,
+        addr: u64,
+        size: u64,
+        access: Access,
+    ) -> Result<Option<u64>, Error> {
+        if !self.paging_enabled() {
+            return Ok(Some(addr));
+        }
+        let paddr = self.translate(addr, access)?;
+        if (addr & PAGE_OFFSET_MASK) + size > RISCV_PAGESIZE as u64 {
+            return Ok(None);
+        }
+        Ok(Some(paddr))
+    }
+
+    // Calls f with the physical address and length of each page sized piece
+    // of [addr, addr + size), along with the offset of the piece in it.
+    fn for_each_piece<F>(
+        &mut self,
+        addr: u64,
+        size: u64,
+        access: Access,
+        mut f: F,
+    ) -> Result<(), Error>
+    where
+        F: FnMut(&mut M, u64, u64, usize) -> Result<(), Error>,
+    {
+        if !self.paging_enabled() {
+            return f(&mut self.inner, addr, size, 0);
+        }
+        let mut offset = 0;
+        while offset < size {
+            let current = addr.wrapping_add(offset);
+            let len = (RISCV_PAGESIZE as u64 - (current & PAGE_OFFSET_MASK)).min(size - offset);
+            let paddr = self.translate(current, access)?;
+            f(&mut self.inner, paddr, len, offset as usize)?;
+            offset += len;
+        }
+        Ok(())
+    }
+
+    fn load(&mut self, addr: u64, size: u64) -> Result<u64, Error> {
+        let bytes = self.load_bytes(addr, size)?;
+        Ok(LittleEndian::read_uint(&bytes, size as usize))
+    }
+
+    fn store(&mut self, addr: u64, size: u64, value: u64) -> Result<(), Error> {
+        let mut bytes = [0; 8];
+        LittleEndian::write_u64(&mut bytes, value);
+        self.store_bytes(addr, &bytes[..size as usize])
+    }
+}
+
+impl<R: Register, M: Memory<R>> Memory<R> for Sv39Memory<R, M> {
+    fn init_pages(
+        &mut self,
+        addr: u64,
+        size: u64,
+        flags: u8,
+        source: Option<Bytes>,
+        offset_from_addr: u64,
+    ) -> Result<(), Error> {
+        self.inner
+            .init_pages(addr, size, flags, source, offset_from_addr)
+    }
+
+    fn fetch_flag(&mut self, page: u64) -> Result<u8, Error> {
+        self.inner.fetch_flag(page)
+    }
+
+    fn memory_size(&self) -> usize {
+        self.inner.memory_size()
+    }
+
+    fn set_memory_size(&mut self, size: usize) -> Result<(), Error> {
+        self.inner.set_memory_size(size)?;
+        self.flush_tlb();
+        Ok(())
+    }
+
+    fn touched_memory(&self) -> usize {
+        self.inner.touched_memory()
+    }
+
+    fn high_watermark(&self) -> usize {
+        self.inner.high_watermark()
+    }
+
+    fn add_watchpoint(&mut self, addr: u64, len: u64, kind: WatchpointKind) -> Result<(), Error> {
+        self.inner.add_watchpoint(addr, len, kind)
+    }
+
+    fn remove_watchpoint(
+        &mut self,
+        addr: u64,
+        len: u64,
+        kind: WatchpointKind,
+    ) -> Result<(), Error> {
+        self.inner.remove_watchpoint(addr, len, kind)
+    }
+
+    fn store_byte(&mut self, addr: u64, size: u64, value: u8) -> Result<(), Error> {
+        self.for_each_piece(addr, size, Access::Store, |inner, paddr, len, _| {
+            inner.store_byte(paddr, len, value)
+        })
+    }
+
+    fn store_bytes(&mut self, addr: u64, value: &[u8]) -> Result<(), Error> {
+        self.for_each_piece(
+            addr,
+            value.len() as u64,
+            Access::Store,
+            |inner, paddr, len, offset| {
+                inner.store_bytes(paddr, &value[offset..offset + len as usize])
+            },
+        )
+    }
+
+    fn load_bytes(&mut self, addr: u64, size: u64) -> Result<Vec<u8>, Error> {
+        if !self.paging_enabled() {
+            return self.inner.load_bytes(addr, size);
+        }
+        // Grown piece by piece, so a size controlled by the program stops at
+        // the first page that fails to translate instead of being reserved
+        // up front.
+        addr.checked_add(size).ok_or(Error::OutOfBound)?;
+        let mut result = Vec::new();
+        self.for_each_piece(addr, size, Access::Load, |inner, paddr, len, _| {
+            result.extend_from_slice(&inner.load_bytes(paddr, len)?);
+            Ok(())
+        })?;
+        Ok(result)
+    }
+
+    fn dump_range(&mut self, addr: u64, len: u64) -> Result<Vec<u8>, Error> {
+        if !self.paging_enabled() {
+            return self.inner.dump_range(addr, len);
+        }
+        self.load_bytes(addr, len)
+    }
+
+    fn dirty_pages(&self) -> Result<Vec<u64>, Error> {
+        self.inner.dirty_pages()
+    }
+
+    fn clear_dirty(&mut self) -> Result<(), Error> {
+        self.inner.clear_dirty()
+    }
+
+    fn mprotect(&mut self, addr: u64, size: u64, flags: u8) -> Result<(), Error> {
+        self.inner.mprotect(addr, size, flags)
+    }
+
+    fn map_host_slice(&mut self, addr: u64, data: Bytes) -> Result<(), Error> {
+        self.inner.map_host_slice(addr, data)
+    }
+
+    fn unmap_host_slice(&mut self, addr: u64) -> Result<(), Error> {
+        self.inner.unmap_host_slice(addr)
+    }
+
+    fn map_region(&mut self, addr: u64, len: u64, prot: u64, fixed: bool) -> Result<(), Error> {
+        self.inner.map_region(addr, len, prot, fixed)
+    }
+
+    fn unmap_region(&mut self, addr: u64, len: u64) -> Result<(), Error> {
+        self.inner.unmap_region(addr, len)
+    }
+
+    fn mappings(&self) -> Vec<Mapping> {
+        self.inner.mappings()
+    }
+
+    // Paging is turned off again
+    fn reset(&mut self) -> Result<(), Error> {
+        self.inner.reset()?;
+        self.satp = 0;
+        self.flush_tlb();
+        Ok(())
+    }
+
+    fn execute_load16(&mut self, addr: u64) -> Result<u16, Error> {
+        match self.translate_within_page(addr, 2, Access::Fetch)? {
+            Some(paddr) => self.inner.execute_load16(paddr),
+            // Halves of 32-bit instructions are fetched separately, so a
+            // fetch only crosses pages when misaligned.
+            None => Err(Error::PageFault { addr, pc: 0 }),
+        }
+    }
+
+    fn load8(&mut self, addr: &R) -> Result<R, Error> {
+        let paddr = self.translate(addr.to_u64(), Access::Load)?;
+        self.inner.load8(&R::from_u64(paddr))
+    }
+
+    fn load16(&mut self, addr: &R) -> Result<R, Error> {
+        match self.translate_within_page(addr.to_u64(), 2, Access::Load)? {
+            Some(paddr) => self.inner.load16(&R::from_u64(paddr)),
+            None => self.load(addr.to_u64(), 2).map(R::from_u64),
+        }
+    }
+
+    fn load32(&mut self, addr: &R) -> Result<R, Error> {
+        match self.translate_within_page(addr.to_u64(), 4, Access::Load)? {
+            Some(paddr) => self.inner.load32(&R::from_u64(paddr)),
+            None => self.load(addr.to_u64(), 4).map(R::from_u64),
+        }
+    }
+
+    fn load64(&mut self, addr: &R) -> Result<R, Error> {
+        match self.translate_within_page(addr.to_u64(), 8, Access::Load)? {
+            Some(paddr) => self.inner.load64(&R::from_u64(paddr)),
+            None => self.load(addr.to_u64(), 8).map(R::from_u64),
+        }
+    }
+
+    fn store8(&mut self, addr: &R, value: &R) -> Result<(), Error> {
+        let paddr = self.translate(addr.to_u64(), Access::Store)?;
+        self.inner.store8(&R::from_u64(paddr), value)
+    }
+
+    fn store16(&mut self, addr: &R, value: &R) -> Result<(), Error> {
+        match self.translate_within_page(addr.to_u64(), 2, Access::Store)? {
+            Some(paddr) => self.inner.store16(&R::from_u64(paddr), value),
+            None => self.store(addr.to_u64(), 2, value.to_u64()),
+        }
+    }
+
+    fn store32(&mut self, addr: &R, value: &R) -> Result<(), Error> {
+        match self.translate_within_page(addr.to_u64(), 4, Access::Store)? {
+            Some(paddr) => self.inner.store32(&R::from_u64(paddr), value),
+            None => self.store(addr.to_u64(), 4, value.to_u64()),
+        }
+    }
+
+    fn store64(&mut self, addr: &R, value: &R) -> Result<(), Error> {
+        match self.translate_within_page(addr.to_u64(), 8, Access::Store)? {
+            Some(paddr) => self.inner.store64(&R::from_u64(paddr), value),
+            None => self.store(addr.to_u64(), 8, value.to_u64()),
+        }
+    }
+}
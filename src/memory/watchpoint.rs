@@ -0,0 +1,86 @@
+use super::super::Error;
+
+use alloc::vec::Vec;
+
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub enum WatchpointKind {
+    Read,
+    Write,
+    // Triggered by both reads and writes
+    Access,
+    // Like Access, but reported as Error::InvalidHeapAccess. HeapSyscalls
+    // poisons heap memory that is not allocated with it.
+    Poison,
+}
+
+impl WatchpointKind {
+    fn matches(self, write: bool) -> bool {
+        match self {
+            WatchpointKind::Read => !write,
+            WatchpointKind::Write => write,
+            WatchpointKind::Access | WatchpointKind::Poison => true,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+struct Watchpoint {
+    addr: u64,
+    // Exclusive end of the watched range
+    end: u64,
+    kind: WatchpointKind,
+}
+
+// Watched ranges shared by memory implementations. Only typed loads and
+// stores, which are what instructions use, are checked, bulk operations
+// such as load_bytes serve the host and are left alone.
+#[derive(Debug, Default, Clone)]
+pub struct Watchpoints {
+    watchpoints: Vec<Watchpoint>,
+}
+
+impl Watchpoints {
+    pub fn add(&mut self, addr: u64, len: u64, kind: WatchpointKind) -> Result<(), Error> {
+        let end = addr.checked_add(len).ok_or(Error::OutOfBound)?;
+        let watchpoint = Watchpoint { addr, end, kind };
+        if !self.watchpoints.contains(&watchpoint) {
+            self.watchpoints.push(watchpoint);
+        }
+        Ok(())
+    }
+
+    // Removing a watchpoint that was never added is a no-op.
+    pub fn remove(&mut self, addr: u64, len: u64, kind: WatchpointKind) {
+        self.watchpoints
+            .retain(|w| w.addr != addr || w.end != addr.wrapping_add(len) || w.kind != kind);
+    }
+
+    pub fn clear(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.watchpoints.is_empty()
+    }
+
+    // The program counter is not known at the memory level, it is left as 0
+    // here and filled in by the machine executing the instruction.
+    #[inline(always)]
+    pub fn check(&self, addr: u64, size: u64, write: bool) -> Result<(), Error> {
+        if self.watchpoints.is_empty() {
+            return Ok(());
+        }
+        let end = addr.saturating_add(size);
+        let mut triggered = self
+            .watchpoints
+            .iter()
+            .filter(|w| w.kind.matches(write) && addr < w.end && w.addr < end);
+        match triggered.next() {
+            Some(w) if w.kind == WatchpointKind::Poison => {
+                Err(Error::InvalidHeapAccess { addr, pc: 0 })
+            }
+            Some(_) => Err(Error::Watchpoint { addr, pc: 0 }),
+            None => Ok(()),
+        }
+    }
+}
@@ -1,32 +1,146 @@
-use super::super::{Error, Register, RISCV_MAX_MEMORY, RISCV_PAGES, RISCV_PAGESIZE};
+use super::super::{Error, Register, RISCV_PAGESIZE};
 use super::{
-    check_permission, round_page_down, round_page_up, Memory, FLAG_EXECUTABLE, FLAG_FREEZED,
-    FLAG_WRITABLE,
+    check_permission, dirty::DirtyPages, memset, round_page_down, round_page_up,
+    watchpoint::WatchpointKind, Mapping, Memory, FLAG_EXECUTABLE, FLAG_FREEZED, FLAG_WRITABLE,
+    FLAG_WXORX_BIT,
 };
 
+use alloc::{vec, vec::Vec};
 use bytes::Bytes;
-use std::marker::PhantomData;
+use core::marker::PhantomData;
 
 pub struct WXorXMemory<R: Register, M: Memory<R>> {
     inner: M,
     flags: Vec<u8>,
+    allow_self_modifying_code: bool,
+    forbid_self_modifying_code: bool,
+    // Pages instructions have been fetched from, only tracked when self
+    // modifying code is forbidden or allowed.
+    executed: DirtyPages,
+    // Bumped each time mprotect takes execute permission away from a page
+    protection_generation: u64,
+    // Bumped each time code fetched before is overwritten, which is only
+    // possible when self modifying code is allowed.
+    code_generation: u64,
     _inner: PhantomData<R>,
 }
 
 impl<R: Register, M: Memory<R> + Default> Default for WXorXMemory<R, M> {
     fn default() -> Self {
+        Self::new(M::default())
+    }
+}
+
+impl<R: Register, M: Memory<R>> WXorXMemory<R, M> {
+    pub fn new(inner: M) -> Self {
         Self {
-            inner: M::default(),
-            flags: vec![0; RISCV_PAGES],
+            flags: vec![0; inner.memory_size() / RISCV_PAGESIZE],
+            executed: DirtyPages::new(inner.memory_size() / RISCV_PAGESIZE),
+            inner,
+            allow_self_modifying_code: false,
+            forbid_self_modifying_code: false,
+            protection_generation: 0,
+            code_generation: 0,
             _inner: PhantomData,
         }
     }
-}
 
-impl<R: Register, M: Memory<R>> WXorXMemory<R, M> {
+    pub fn inner(&self) -> &M {
+        &self.inner
+    }
+
     pub fn inner_mut(&mut self) -> &mut dyn Memory<R> {
         &mut self.inner
     }
+
+    pub fn allow_self_modifying_code(&self) -> bool {
+        self.allow_self_modifying_code
+    }
+
+    // Escape hatch for legacy programs that write code at runtime. When set,
+    // page flags are still tracked, but writing to executable pages and
+    // executing writable pages are no longer rejected.
+    pub fn set_allow_self_modifying_code(&mut self, allow: bool) {
+        if allow != self.allow_self_modifying_code {
+            // Fetched pages were not tracked until now
+            self.code_generation += 1;
+        }
+        self.allow_self_modifying_code = allow;
+    }
+
+    pub fn forbid_self_modifying_code(&self) -> bool {
+        self.forbid_self_modifying_code
+    }
+
+    // Rejects writes to any page code has been fetched from with
+    // Error::WriteToExecutableMemory, including writes done by syscalls.
+    // This takes precedence over set_allow_self_modifying_code: programs
+    // can still generate code into writable pages, but code that has run
+    // or been decoded in advance by TraceMachine never changes afterwards.
+    pub fn set_forbid_self_modifying_code(&mut self, forbid: bool) {
+        self.forbid_self_modifying_code = forbid;
+    }
+
+    // Code decoded from pages that are no longer executable must not run
+    // anymore, callers caching decoded instructions compare this value to
+    // know when to drop their cache.
+    pub fn protection_generation(&self) -> u64 {
+        self.protection_generation
+    }
+
+    // Likewise, callers caching decoded instructions compare this value to
+    // know when code they decoded might have been overwritten. Only stores
+    // to pages instructions have been fetched from bump it, so most stores
+    // leave cached code alone.
+    pub fn code_generation(&self) -> u64 {
+        self.code_generation
+    }
+
+    // Checks the range [addr, addr + size) can be initialized or protected
+    fn check_pages(&self, addr: u64, size: u64, offset_from_addr: u64) -> Result<(), Error> {
+        if round_page_down(addr) != addr || round_page_up(size) != size {
+            return Err(Error::Unaligned);
+        }
+        let memory_size = self.memory_size() as u64;
+        if addr > memory_size
+            || size > memory_size
+            || addr + size > memory_size
+            || offset_from_addr > size
+        {
+            return Err(Error::OutOfBound);
+        }
+        for page_addr in (addr..addr + size).step_by(RISCV_PAGESIZE) {
+            let page = page_addr as usize / RISCV_PAGESIZE;
+            if self.flags[page] & FLAG_FREEZED != 0 {
+                return Err(Error::InvalidPermission);
+            }
+        }
+        Ok(())
+    }
+
+    fn check_access(&mut self, addr: u64, size: u64, flag: u8) -> Result<(), Error> {
+        if self.forbid_self_modifying_code {
+            if flag == FLAG_EXECUTABLE {
+                self.executed.mark(addr, size);
+            } else if self.executed.contains(addr, size) {
+                return Err(Error::WriteToExecutableMemory);
+            }
+        } else if self.allow_self_modifying_code {
+            if flag == FLAG_EXECUTABLE {
+                self.executed.mark(addr, size);
+            } else if self.executed.contains(addr, size) {
+                // All cached code is dropped, pages are tracked again as
+                // code gets fetched.
+                self.executed.clear();
+                self.code_generation += 1;
+            }
+        }
+        if self.allow_self_modifying_code {
+            Ok(())
+        } else {
+            check_permission(self, addr, size, flag)
+        }
+    }
 }
 
 impl<R: Register, M: Memory<R>> Memory<R> for WXorXMemory<R, M> {
@@ -38,37 +152,96 @@ impl<R: Register, M: Memory<R>> Memory<R> for WXorXMemory<R, M> {
         source: Option<Bytes>,
         offset_from_addr: u64,
     ) -> Result<(), Error> {
-        if round_page_down(addr) != addr || round_page_up(size) != size {
-            return Err(Error::Unaligned);
-        }
-        if addr > RISCV_MAX_MEMORY as u64
-            || size > RISCV_MAX_MEMORY as u64
-            || addr + size > RISCV_MAX_MEMORY as u64
-            || offset_from_addr > size
-        {
-            return Err(Error::OutOfBound);
+        self.check_pages(addr, size, offset_from_addr)?;
+        for page_addr in (addr..addr + size).step_by(RISCV_PAGESIZE) {
+            self.flags[page_addr as usize / RISCV_PAGESIZE] = flags;
         }
+        // Loading new content starts over
+        self.executed.unmark(addr, size);
+        self.inner
+            .init_pages(addr, size, flags, source, offset_from_addr)
+    }
+
+    fn mprotect(&mut self, addr: u64, size: u64, flags: u8) -> Result<(), Error> {
+        self.check_pages(addr, size, 0)?;
+        let mut removed_executable = false;
         for page_addr in (addr..addr + size).step_by(RISCV_PAGESIZE) {
             let page = page_addr as usize / RISCV_PAGESIZE;
-            if self.flags[page] & FLAG_FREEZED != 0 {
-                return Err(Error::InvalidPermission);
+            if self.flags[page] & FLAG_WXORX_BIT == FLAG_EXECUTABLE
+                && flags & FLAG_WXORX_BIT != FLAG_EXECUTABLE
+            {
+                removed_executable = true;
             }
             self.flags[page] = flags;
         }
-        self.inner
-            .init_pages(addr, size, flags, source, offset_from_addr)
+        if removed_executable {
+            // Code on those pages can be rewritten once they are writable
+            self.executed.unmark(addr, size);
+            self.protection_generation += 1;
+        }
+        Ok(())
     }
 
     fn fetch_flag(&mut self, page: u64) -> Result<u8, Error> {
-        if page < RISCV_PAGES as u64 {
+        if page < self.flags.len() as u64 {
             Ok(self.flags[page as usize])
         } else {
             Err(Error::OutOfBound)
         }
     }
 
+    fn memory_size(&self) -> usize {
+        self.inner.memory_size()
+    }
+
+    fn set_memory_size(&mut self, size: usize) -> Result<(), Error> {
+        self.inner.set_memory_size(size)?;
+        self.flags = vec![0; size / RISCV_PAGESIZE];
+        self.executed = DirtyPages::new(size / RISCV_PAGESIZE);
+        Ok(())
+    }
+
+    fn touched_memory(&self) -> usize {
+        self.inner.touched_memory()
+    }
+
+    fn high_watermark(&self) -> usize {
+        self.inner.high_watermark()
+    }
+
+    fn add_watchpoint(&mut self, addr: u64, len: u64, kind: WatchpointKind) -> Result<(), Error> {
+        self.inner.add_watchpoint(addr, len, kind)
+    }
+
+    fn remove_watchpoint(
+        &mut self,
+        addr: u64,
+        len: u64,
+        kind: WatchpointKind,
+    ) -> Result<(), Error> {
+        self.inner.remove_watchpoint(addr, len, kind)
+    }
+
+    fn dirty_pages(&self) -> Result<Vec<u64>, Error> {
+        self.inner.dirty_pages()
+    }
+
+    fn clear_dirty(&mut self) -> Result<(), Error> {
+        self.inner.clear_dirty()
+    }
+
+    // Self modifying code settings are kept, pages lose execute permission
+    // so protection generation is bumped as with mprotect.
+    fn reset(&mut self) -> Result<(), Error> {
+        self.inner.reset()?;
+        memset(&mut self.flags, 0);
+        self.executed.clear();
+        self.protection_generation += 1;
+        Ok(())
+    }
+
     fn execute_load16(&mut self, addr: u64) -> Result<u16, Error> {
-        check_permission(self, addr, 2, FLAG_EXECUTABLE)?;
+        self.check_access(addr, 2, FLAG_EXECUTABLE)?;
         self.inner.execute_load16(addr)
     }
 
@@ -89,32 +262,60 @@ impl<R: Register, M: Memory<R>> Memory<R> for WXorXMemory<R, M> {
     }
 
     fn store8(&mut self, addr: &R, value: &R) -> Result<(), Error> {
-        check_permission(self, addr.to_u64(), 1, FLAG_WRITABLE)?;
+        self.check_access(addr.to_u64(), 1, FLAG_WRITABLE)?;
         self.inner.store8(addr, value)
     }
 
     fn store16(&mut self, addr: &R, value: &R) -> Result<(), Error> {
-        check_permission(self, addr.to_u64(), 2, FLAG_WRITABLE)?;
+        self.check_access(addr.to_u64(), 2, FLAG_WRITABLE)?;
         self.inner.store16(addr, value)
     }
 
     fn store32(&mut self, addr: &R, value: &R) -> Result<(), Error> {
-        check_permission(self, addr.to_u64(), 4, FLAG_WRITABLE)?;
+        self.check_access(addr.to_u64(), 4, FLAG_WRITABLE)?;
         self.inner.store32(addr, value)
     }
 
     fn store64(&mut self, addr: &R, value: &R) -> Result<(), Error> {
-        check_permission(self, addr.to_u64(), 8, FLAG_WRITABLE)?;
+        self.check_access(addr.to_u64(), 8, FLAG_WRITABLE)?;
         self.inner.store64(addr, value)
     }
 
     fn store_bytes(&mut self, addr: u64, value: &[u8]) -> Result<(), Error> {
-        check_permission(self, addr, value.len() as u64, FLAG_WRITABLE)?;
+        self.check_access(addr, value.len() as u64, FLAG_WRITABLE)?;
         self.inner.store_bytes(addr, value)
     }
 
+    fn load_bytes(&mut self, addr: u64, size: u64) -> Result<Vec<u8>, Error> {
+        self.inner.load_bytes(addr, size)
+    }
+
+    fn dump_range(&mut self, addr: u64, len: u64) -> Result<Vec<u8>, Error> {
+        self.inner.dump_range(addr, len)
+    }
+
+    fn map_host_slice(&mut self, addr: u64, data: Bytes) -> Result<(), Error> {
+        self.inner.map_host_slice(addr, data)
+    }
+
+    fn unmap_host_slice(&mut self, addr: u64) -> Result<(), Error> {
+        self.inner.unmap_host_slice(addr)
+    }
+
+    fn map_region(&mut self, addr: u64, len: u64, prot: u64, fixed: bool) -> Result<(), Error> {
+        self.inner.map_region(addr, len, prot, fixed)
+    }
+
+    fn unmap_region(&mut self, addr: u64, len: u64) -> Result<(), Error> {
+        self.inner.unmap_region(addr, len)
+    }
+
+    fn mappings(&self) -> Vec<Mapping> {
+        self.inner.mappings()
+    }
+
     fn store_byte(&mut self, addr: u64, size: u64, value: u8) -> Result<(), Error> {
-        check_permission(self, addr, size, FLAG_WRITABLE)?;
+        self.check_access(addr, size, FLAG_WRITABLE)?;
         self.inner.store_byte(addr, size, value)
     }
 }
@@ -0,0 +1,137 @@
+#[cfg(feature = "fd")]
+use super::instructions::fd::FloatRegisters;
+#[cfg(feature = "v-ext")]
+use super::instructions::v::VectorRegisters;
+use super::{
+    memory::{Memory, FLAG_FREEZED},
+    Error, Register, RISCV_PAGESIZE,
+};
+use alloc::{vec, vec::Vec};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+
+/// A single memory page captured in a snapshot, together with its
+/// protection flag.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PageSnapshot {
+    pub page: u64,
+    pub flag: u8,
+    pub content: Vec<u8>,
+}
+
+/// Full machine state that can be persisted and later resumed, possibly in
+/// a different process. Only pages that carry a flag or non-zero content
+/// are kept, so the size of a snapshot depends on the memory actually used
+/// by the program instead of the whole address space.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub bits: u8,
+    pub pc: u64,
+    pub registers: Vec<u64>,
+    pub exit_code: i8,
+    pub cycles: u64,
+    pub pages: Vec<PageSnapshot>,
+    // Snapshots taken without the fd feature resume with cleared floating
+    // point registers.
+    #[cfg(feature = "fd")]
+    #[serde(default)]
+    pub float_registers: FloatRegisters,
+    #[cfg(feature = "v-ext")]
+    #[serde(default)]
+    pub vector_registers: VectorRegisters,
+}
+
+/// Registers, pc, cycles and memory content of a DefaultCoreMachine. Unlike
+/// Snapshot it leaves out state kept by DefaultMachine, such as the exit code
+/// or floating point registers. DefaultCoreMachine can be deserialized from
+/// it directly, while serializing goes through DefaultCoreMachine::state
+/// since reading memory requires mutable access.
+#[cfg(feature = "serialize")]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CoreMachineState {
+    pub bits: u8,
+    pub pc: u64,
+    pub registers: Vec<u64>,
+    pub cycles: u64,
+    pub max_cycles: Option<u64>,
+    pub memory_size: u64,
+    pub pages: Vec<PageSnapshot>,
+}
+
+fn load_page<R: Register, M: Memory<R>>(memory: &mut M, page: u64) -> Result<Vec<u8>, Error> {
+    memory.load_bytes(page * RISCV_PAGESIZE as u64, RISCV_PAGESIZE as u64)
+}
+
+pub(crate) fn snapshot_memory<R: Register, M: Memory<R>>(
+    memory: &mut M,
+) -> Result<Vec<PageSnapshot>, Error> {
+    let mut pages = Vec::new();
+    for page in 0..(memory.memory_size() / RISCV_PAGESIZE) as u64 {
+        let flag = memory.fetch_flag(page)?;
+        let content = load_page(memory, page)?;
+        if flag != 0 || content.iter().any(|b| *b != 0) {
+            pages.push(PageSnapshot {
+                page,
+                flag,
+                content,
+            });
+        }
+    }
+    Ok(pages)
+}
+
+// Frozen pages cannot be re-initialized, they are only accepted when the
+// snapshot contains exactly the same page, which is the case when the same
+// program has been loaded. All checks are done before touching memory, so a
+// mismatching snapshot leaves the machine unchanged.
+pub(crate) fn resume_memory<R: Register, M: Memory<R>>(
+    memory: &mut M,
+    pages: &[PageSnapshot],
+) -> Result<(), Error> {
+    let memory_pages = memory.memory_size() / RISCV_PAGESIZE;
+    let mut snapshot_pages = vec![None; memory_pages];
+    for page in pages {
+        if page.page >= memory_pages as u64 || page.content.len() != RISCV_PAGESIZE {
+            return Err(Error::InvalidSnapshot);
+        }
+        snapshot_pages[page.page as usize] = Some(page);
+    }
+    for (page, snapshot_page) in snapshot_pages.iter().enumerate() {
+        let flag = memory.fetch_flag(page as u64)?;
+        if flag & FLAG_FREEZED == 0 {
+            continue;
+        }
+        let matched = match snapshot_page {
+            Some(snapshot_page) => {
+                snapshot_page.flag == flag
+                    && load_page(memory, page as u64)? == snapshot_page.content
+            }
+            None => false,
+        };
+        if !matched {
+            return Err(Error::InvalidSnapshot);
+        }
+    }
+    for (page, snapshot_page) in snapshot_pages.iter().enumerate() {
+        let flag = memory.fetch_flag(page as u64)?;
+        if flag & FLAG_FREEZED != 0 {
+            continue;
+        }
+        let addr = (page * RISCV_PAGESIZE) as u64;
+        match snapshot_page {
+            Some(snapshot_page) => memory.init_pages(
+                addr,
+                RISCV_PAGESIZE as u64,
+                snapshot_page.flag,
+                Some(Bytes::from(snapshot_page.content.clone())),
+                0,
+            )?,
+            None => {
+                if flag != 0 || load_page(memory, page as u64)?.iter().any(|b| *b != 0) {
+                    memory.init_pages(addr, RISCV_PAGESIZE as u64, 0, None, 0)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,17 @@
+use super::super::{machine::SupportMachine, Error};
+
+use alloc::boxed::Box;
+use core::future::Future;
+use core::pin::Pin;
+
+pub type EcallFuture<'a> = Pin<Box<dyn Future<Output = Result<bool, Error>> + 'a>>;
+
+/// Syscalls served by futures, e.g. ones waiting on a database or the
+/// network, see DefaultMachine::run_async. They are consulted after the
+/// synchronous syscalls and before the fallback of the syscall registry,
+/// with the same contract as Syscalls::ecall: the future resolves to true
+/// once the ecall is processed. The machine stays paused right at the ecall
+/// until it resolves, so any executor can drive it.
+pub trait AsyncSyscalls<Mac: SupportMachine> {
+    fn ecall<'a>(&'a mut self, machine: &'a mut Mac) -> EcallFuture<'a>;
+}
@@ -0,0 +1,171 @@
+use super::super::{
+    machine::SupportMachine,
+    memory::{diff, Memory},
+    registers::{A0, A7},
+    Error, Register, RISCV_PAGESIZE,
+};
+use super::Syscalls;
+
+use alloc::{collections::BTreeMap, rc::Rc, vec::Vec};
+use core::cell::RefCell;
+
+/// Effects of one ecall handled by the syscalls wrapped in RecordingSyscalls,
+/// enough for ReplaySyscalls to reproduce it without the host.
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct SyscallCapture {
+    // Syscall number from A7
+    pub number: u64,
+    // A0 to A5 right before the syscall ran
+    pub args: [u64; 6],
+    // Registers changed by the syscall as (index, value)
+    pub registers: Vec<(usize, u64)>,
+    // Memory changed by the syscall as (address, content)
+    pub writes: Vec<(u64, Vec<u8>)>,
+    // Cycles charged by the syscall itself
+    pub cycles: u64,
+    // Ok(true) once handled, or the error the syscall failed with
+    pub result: Result<bool, Error>,
+}
+
+pub type SharedCaptures = Rc<RefCell<Vec<SyscallCapture>>>;
+
+/// Wraps syscalls and captures the effects of every ecall they handle into
+/// a trace shared with the host, see captures. Writes are found through the
+/// dirty pages of memory, so memory implementations without dirty page
+/// tracking cannot be used. Pages already dirty before an ecall are copied
+/// to find the bytes it changed, other pages it writes are captured whole.
+/// The trace restarts each time a program is loaded, changes made while
+/// initializing the wrapped syscalls are not captured.
+pub struct RecordingSyscalls<S> {
+    inner: S,
+    captures: SharedCaptures,
+}
+
+impl<S> RecordingSyscalls<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            captures: SharedCaptures::default(),
+        }
+    }
+
+    // Trace of the current run, it stays available once the machine is
+    // dropped.
+    pub fn captures(&self) -> SharedCaptures {
+        Rc::clone(&self.captures)
+    }
+}
+
+impl<Mac: SupportMachine, S: Syscalls<Mac>> Syscalls<Mac> for RecordingSyscalls<S> {
+    fn initialize(&mut self, machine: &mut Mac) -> Result<(), Error> {
+        self.captures.borrow_mut().clear();
+        self.inner.initialize(machine)
+    }
+
+    fn ecall(&mut self, machine: &mut Mac) -> Result<bool, Error> {
+        let registers: Vec<u64> = machine.registers().iter().map(|r| r.to_u64()).collect();
+        let cycles = machine.cycles();
+        let mut dirty = BTreeMap::new();
+        for page in machine.memory().dirty_pages()? {
+            let content = machine
+                .memory_mut()
+                .dump_range(page * RISCV_PAGESIZE as u64, RISCV_PAGESIZE as u64)?;
+            dirty.insert(page, content);
+        }
+        let result = self.inner.ecall(machine);
+        // Not handled, or retried once the machine is resumed
+        if result == Ok(false) || result == Err(Error::WouldBlock) {
+            return result;
+        }
+        let mut args = [0; 6];
+        args.copy_from_slice(&registers[A0..A0 + 6]);
+        let mut capture = SyscallCapture {
+            number: registers[A7],
+            args,
+            registers: Vec::new(),
+            writes: Vec::new(),
+            cycles: machine.cycles().saturating_sub(cycles),
+            result,
+        };
+        for (index, before) in registers.iter().enumerate() {
+            let after = machine.registers()[index].to_u64();
+            if after != *before {
+                capture.registers.push((index, after));
+            }
+        }
+        for page in machine.memory().dirty_pages()? {
+            let addr = page * RISCV_PAGESIZE as u64;
+            let content = machine
+                .memory_mut()
+                .dump_range(addr, RISCV_PAGESIZE as u64)?;
+            match dirty.get(&page) {
+                Some(before) => {
+                    for range in diff(addr, before, &content).ranges {
+                        capture.writes.push((range.addr, range.after));
+                    }
+                }
+                None => capture.writes.push((addr, content)),
+            }
+        }
+        self.captures.borrow_mut().push(capture);
+        result
+    }
+}
+
+/// Replays ecalls captured by RecordingSyscalls in order, applying their
+/// effects instead of running them. Ecalls with another number than the
+/// next capture are left to other syscalls, while the same number with
+/// different arguments fails with Error::ReplayDivergence, index being the
+/// position of the capture. Replay restarts each time a program is loaded.
+pub struct ReplaySyscalls {
+    captures: Vec<SyscallCapture>,
+    position: usize,
+}
+
+impl ReplaySyscalls {
+    pub fn new(captures: Vec<SyscallCapture>) -> Self {
+        Self {
+            captures,
+            position: 0,
+        }
+    }
+
+    // Number of captures replayed so far
+    pub fn position(&self) -> usize {
+        self.position
+    }
+}
+
+impl<Mac: SupportMachine> Syscalls<Mac> for ReplaySyscalls {
+    fn initialize(&mut self, _machine: &mut Mac) -> Result<(), Error> {
+        self.position = 0;
+        Ok(())
+    }
+
+    fn ecall(&mut self, machine: &mut Mac) -> Result<bool, Error> {
+        let capture = match self.captures.get(self.position) {
+            Some(capture) if capture.number == machine.registers()[A7].to_u64() => capture,
+            _ => return Ok(false),
+        };
+        let args = &machine.registers()[A0..A0 + 6];
+        if args
+            .iter()
+            .zip(&capture.args)
+            .any(|(a, b)| a.to_u64() != *b)
+        {
+            return Err(Error::ReplayDivergence {
+                index: self.position as u64,
+                pc: machine.pc().to_u64(),
+            });
+        }
+        machine.add_cycles(capture.cycles)?;
+        for (addr, content) in &capture.writes {
+            machine.memory_mut().store_bytes(*addr, content)?;
+        }
+        for (index, value) in &capture.registers {
+            machine.set_register(*index, Mac::REG::from_u64(*value));
+        }
+        self.position += 1;
+        capture.result
+    }
+}
@@ -0,0 +1,153 @@
+use super::super::{
+    machine::{DefaultMachine, RunState, SupportMachine},
+    registers::{A0, A1, A7},
+    Error, Register,
+};
+use super::{read_arg_bytes, write_return_slice, Syscalls};
+
+use alloc::{collections::VecDeque, rc::Rc, vec, vec::Vec};
+use core::cell::RefCell;
+
+// Numbers outside the range used by Linux. Send takes the message address
+// in A0 and its length in A1, and returns 0. Receive takes a buffer address
+// in A0 and its capacity in A1, and returns the length of the message,
+// which is truncated when the buffer is too small.
+pub const CHANNEL_SEND_SYSCALL_NUMBER: u64 = 3001;
+pub const CHANNEL_RECV_SYSCALL_NUMBER: u64 = 3002;
+
+const EMSGSIZE: i64 = 90;
+
+type SharedQueue = Rc<RefCell<VecDeque<Vec<u8>>>>;
+
+/// One end of a bounded message channel between two machines, created by
+/// ChannelBroker::pair. Sending to a full queue or receiving from an empty
+/// one fails with Error::WouldBlock without side effects, so
+/// DefaultMachine::run_resumable yields and retries the ecall once the
+/// machine is resumed.
+pub struct ChannelSyscalls {
+    outgoing: SharedQueue,
+    incoming: SharedQueue,
+    capacity: usize,
+    max_message_size: u64,
+}
+
+impl ChannelSyscalls {
+    fn send<Mac: SupportMachine>(&mut self, machine: &mut Mac) -> Result<i64, Error> {
+        if machine.registers()[A1].to_u64() > self.max_message_size {
+            return Ok(-EMSGSIZE);
+        }
+        if self.outgoing.borrow().len() >= self.capacity {
+            return Err(Error::WouldBlock);
+        }
+        let message = read_arg_bytes(machine, 0)?;
+        self.outgoing.borrow_mut().push_back(message);
+        Ok(0)
+    }
+
+    fn recv<Mac: SupportMachine>(&mut self, machine: &mut Mac) -> Result<(), Error> {
+        let message = match self.incoming.borrow().front() {
+            Some(message) => message.clone(),
+            None => return Err(Error::WouldBlock),
+        };
+        // The message is only taken once it has been written, a fault
+        // leaves it in the queue.
+        write_return_slice(machine, 0, &message)?;
+        self.incoming.borrow_mut().pop_front();
+        Ok(())
+    }
+}
+
+impl<Mac: SupportMachine> Syscalls<Mac> for ChannelSyscalls {
+    fn initialize(&mut self, _machine: &mut Mac) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn ecall(&mut self, machine: &mut Mac) -> Result<bool, Error> {
+        match machine.registers()[A7].to_u64() {
+            CHANNEL_SEND_SYSCALL_NUMBER => {
+                let result = self.send(machine)?;
+                machine.set_register(A0, Mac::REG::from_i64(result));
+            }
+            CHANNEL_RECV_SYSCALL_NUMBER => self.recv(machine)?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+/// Host side of the channels between machines running on one thread. The
+/// broker hands out connected ChannelSyscalls pairs, keeps track of the
+/// messages in flight and drives the machines until they all exit.
+pub struct ChannelBroker {
+    capacity: usize,
+    max_message_size: u64,
+    queues: Vec<SharedQueue>,
+}
+
+impl ChannelBroker {
+    // Each direction of a channel holds at most capacity messages of at
+    // most max_message_size bytes, larger messages are rejected with
+    // -EMSGSIZE.
+    pub fn new(capacity: usize, max_message_size: u64) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            max_message_size,
+            queues: Vec::new(),
+        }
+    }
+
+    // Creates a channel, messages sent through one end are received by the
+    // other one.
+    pub fn pair(&mut self) -> (ChannelSyscalls, ChannelSyscalls) {
+        let a_to_b = SharedQueue::default();
+        let b_to_a = SharedQueue::default();
+        self.queues.push(a_to_b.clone());
+        self.queues.push(b_to_a.clone());
+        let end = |outgoing, incoming| ChannelSyscalls {
+            outgoing,
+            incoming,
+            capacity: self.capacity,
+            max_message_size: self.max_message_size,
+        };
+        (end(a_to_b.clone(), b_to_a.clone()), end(b_to_a, a_to_b))
+    }
+
+    // Messages sent but not received yet, across all channels
+    pub fn pending(&self) -> usize {
+        self.queues.iter().map(|q| q.borrow().len()).sum()
+    }
+
+    // Runs machines round robin with run_resumable until all of them have
+    // exited, returning their exit codes. Programs must already be loaded.
+    // Machines should yield every so many instructions, otherwise one
+    // machine runs until it blocks. When every machine is blocked right
+    // away the machines are deadlocked and Error::WouldBlock is returned,
+    // blocked ecalls do not count as executed instructions. Machines can
+    // still be inspected or resumed afterwards. Pausing on max cycles is
+    // reported as Error::CyclesExceeded.
+    pub fn run<Inner: SupportMachine>(
+        &self,
+        machines: &mut [&mut DefaultMachine<'_, Inner>],
+    ) -> Result<Vec<i8>, Error> {
+        let mut exit_codes = vec![None; machines.len()];
+        while exit_codes.iter().any(Option::is_none) {
+            let mut progressed = false;
+            for (machine, exit_code) in machines.iter_mut().zip(exit_codes.iter_mut()) {
+                if exit_code.is_some() {
+                    continue;
+                }
+                let instructions = machine.instructions();
+                match machine.run_resumable()? {
+                    RunState::Exited(code) => *exit_code = Some(code),
+                    RunState::Yielded => (),
+                    RunState::Paused => return Err(Error::CyclesExceeded),
+                }
+                progressed |= exit_code.is_some() || machine.instructions() != instructions;
+            }
+            if !progressed {
+                return Err(Error::WouldBlock);
+            }
+        }
+        Ok(exit_codes.into_iter().flatten().collect())
+    }
+}
@@ -0,0 +1,92 @@
+use super::super::{
+    machine::SupportMachine,
+    memory::Memory,
+    registers::{A0, A1, A2, A7},
+    Error, Register,
+};
+use super::Syscalls;
+
+use std::io::Write;
+
+// Same number and arguments as write on Linux RISC-V, so printf from
+// newlib or musl works unchanged: A0 holds the file descriptor, A1 the
+// buffer address and A2 its length. The number of bytes written is
+// returned in A0, or a negated errno value.
+pub const DEBUG_WRITE_SYSCALL_NUMBER: u64 = 64;
+
+pub const STDOUT_FILENO: u64 = 1;
+pub const STDERR_FILENO: u64 = 2;
+
+const EIO: i64 = 5;
+const EBADF: i64 = 9;
+
+/// Forwards what programs write to stdout and stderr into host sinks, e.g.
+/// a Vec<u8> captured by a test or the terminal of a simulator, see
+/// DefaultMachineBuilder::debug_output. Descriptors without a sink fail
+/// with EBADF, and failing sinks with EIO, the program keeps running in
+/// both cases.
+pub struct DebugSyscall<'a> {
+    number: u64,
+    stdout: Option<Box<dyn Write + 'a>>,
+    stderr: Option<Box<dyn Write + 'a>>,
+}
+
+impl<'a> DebugSyscall<'a> {
+    pub fn new(number: u64) -> Self {
+        Self {
+            number,
+            stdout: None,
+            stderr: None,
+        }
+    }
+
+    pub fn stdout(mut self, sink: Box<dyn Write + 'a>) -> Self {
+        self.stdout = Some(sink);
+        self
+    }
+
+    pub fn stderr(mut self, sink: Box<dyn Write + 'a>) -> Self {
+        self.stderr = Some(sink);
+        self
+    }
+}
+
+impl<'a> Default for DebugSyscall<'a> {
+    fn default() -> Self {
+        Self::new(DEBUG_WRITE_SYSCALL_NUMBER)
+    }
+}
+
+impl<'a, Mac: SupportMachine> Syscalls<Mac> for DebugSyscall<'a> {
+    fn initialize(&mut self, _machine: &mut Mac) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn ecall(&mut self, machine: &mut Mac) -> Result<bool, Error> {
+        if machine.registers()[A7].to_u64() != self.number {
+            return Ok(false);
+        }
+        let fd = machine.registers()[A0].to_u64();
+        let addr = machine.registers()[A1].to_u64();
+        let size = machine.registers()[A2].to_u64();
+        let sink = match fd {
+            STDOUT_FILENO => self.stdout.as_mut(),
+            STDERR_FILENO => self.stderr.as_mut(),
+            _ => None,
+        };
+        let result = match sink {
+            Some(sink) => {
+                let data = machine.memory_mut().load_bytes(addr, size)?;
+                // Flushed right away so output is not lost when the
+                // program fails later on.
+                match sink.write_all(&data).and_then(|_| sink.flush()) {
+                    Ok(()) => size as i64,
+                    Err(_) => -EIO,
+                }
+            }
+            None => -EBADF,
+        };
+        machine.set_register(A0, Mac::REG::from_i64(result));
+        Ok(true)
+    }
+}
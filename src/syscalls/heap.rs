@@ -0,0 +1,289 @@
+use super::super::{
+    machine::{segment_ranges, SupportMachine},
+    memory::{round_page_down, round_page_up, watchpoint::WatchpointKind, Memory},
+    registers::{A0, A1, A2, A3, A7},
+    Error, Register, DEFAULT_STACK_SIZE,
+};
+use super::{mprotect::PROT_EXEC, Syscalls};
+
+use alloc::{collections::BTreeMap, vec::Vec};
+use bytes::Bytes;
+
+// Same numbers and arguments as brk, munmap and mmap on Linux RISC-V.
+pub const BRK_SYSCALL_NUMBER: u64 = 214;
+pub const MUNMAP_SYSCALL_NUMBER: u64 = 215;
+pub const MMAP_SYSCALL_NUMBER: u64 = 222;
+
+pub const MAP_SHARED: u64 = 0x01;
+pub const MAP_PRIVATE: u64 = 0x02;
+pub const MAP_FIXED: u64 = 0x10;
+pub const MAP_ANONYMOUS: u64 = 0x20;
+
+const EINVAL: i64 = 22;
+const ENOMEM: i64 = 12;
+const EEXIST: i64 = 17;
+
+/// Implements brk, anonymous mmap and munmap so programs using malloc run
+/// without host glue. The heap lies between the program and the stack:
+/// brk grows up from heap_start while mappings are handed out top down
+/// from the stack, always at the same addresses for the same calls. Memory
+/// is zeroed when it is released, so memory handed out again is zeroed as
+/// Linux guarantees.
+///
+/// With the sanitizer enabled, heap memory that is not allocated, above the
+/// break or outside mappings, is poisoned: instructions accessing it stop
+/// the machine with Error::InvalidHeapAccess, which catches use after free
+/// and out of bound accesses in tests. The memory must support watchpoints,
+/// and the asm machine does not enforce them.
+pub struct HeapSyscalls {
+    heap_start: u64,
+    brk: u64,
+    // Start of the stack, set up when a program is loaded
+    limit: u64,
+    // Page aligned anonymous mappings as start -> end
+    mappings: BTreeMap<u64, u64>,
+    sanitize: bool,
+    // Ranges currently poisoned in memory as (start, len)
+    poisoned: Vec<(u64, u64)>,
+}
+
+impl HeapSyscalls {
+    pub fn new(heap_start: u64) -> Self {
+        let heap_start = round_page_up(heap_start);
+        Self {
+            heap_start,
+            brk: heap_start,
+            limit: heap_start,
+            mappings: BTreeMap::new(),
+            sanitize: false,
+            poisoned: Vec::new(),
+        }
+    }
+
+    pub fn with_sanitizer(mut self, sanitize: bool) -> Self {
+        self.sanitize = sanitize;
+        self
+    }
+
+    pub fn sanitize(&self) -> bool {
+        self.sanitize
+    }
+
+    // Heap memory not handed out by brk or mmap, byte exact at the break
+    // and page granular around mappings.
+    fn unallocated(&self) -> Vec<(u64, u64)> {
+        let mut ranges = Vec::new();
+        let mut start = self.brk;
+        for (mapping_start, mapping_end) in &self.mappings {
+            if *mapping_start > start {
+                ranges.push((start, *mapping_start - start));
+            }
+            start = start.max(*mapping_end);
+        }
+        if self.limit > start {
+            ranges.push((start, self.limit - start));
+        }
+        ranges
+    }
+
+    // Poisons unallocated heap memory again after allocations changed
+    fn update_poison<Mac: SupportMachine>(&mut self, machine: &mut Mac) -> Result<(), Error> {
+        if !self.sanitize {
+            return Ok(());
+        }
+        for (addr, len) in self.poisoned.drain(..) {
+            machine
+                .memory_mut()
+                .remove_watchpoint(addr, len, WatchpointKind::Poison)?;
+        }
+        for (addr, len) in self.unallocated() {
+            machine
+                .memory_mut()
+                .add_watchpoint(addr, len, WatchpointKind::Poison)?;
+            self.poisoned.push((addr, len));
+        }
+        Ok(())
+    }
+
+    // Starts the heap at the page following the last segment of program,
+    // load_bias is the one given to DefaultMachineBuilder::load_bias.
+    pub fn for_program(program: &Bytes, load_bias: u64) -> Result<Self, Error> {
+        let end = segment_ranges(program, load_bias)?
+            .iter()
+            .map(|(_, end)| *end)
+            .max()
+            .unwrap_or(0);
+        Ok(Self::new(end))
+    }
+
+    // The break cannot grow into mappings
+    fn brk_limit(&self) -> u64 {
+        self.mappings.keys().next().copied().unwrap_or(self.limit)
+    }
+
+    // Linux returns the new break on success and the current one on failure,
+    // an address below the heap only queries the current break.
+    fn set_brk<Mac: SupportMachine>(&mut self, machine: &mut Mac, addr: u64) -> Result<u64, Error> {
+        if addr < self.heap_start || addr > self.brk_limit() {
+            return Ok(self.brk);
+        }
+        if addr < self.brk {
+            machine.memory_mut().store_byte(addr, self.brk - addr, 0)?;
+        }
+        self.brk = addr;
+        Ok(self.brk)
+    }
+
+    // Only private or shared anonymous mappings are supported. Without
+    // MAP_FIXED the address hint is ignored, with it the mapping must lie
+    // between the break and the stack and replaces the mappings it overlaps.
+    // Pages are always readable and writable, executable mappings are
+    // rejected since code could only be written through mprotect
+    // afterwards. Mappings are mirrored into memory, see
+    // Memory::map_region, ranges memory already tracks as mapped by someone
+    // else fail with EEXIST.
+    fn mmap<Mac: SupportMachine>(
+        &mut self,
+        machine: &mut Mac,
+        addr: u64,
+        len: u64,
+        prot: u64,
+        flags: u64,
+    ) -> Result<i64, Error> {
+        let fixed = flags & MAP_FIXED != 0;
+        if len == 0
+            || flags & MAP_ANONYMOUS == 0
+            || flags & (MAP_SHARED | MAP_PRIVATE) == 0
+            || prot & PROT_EXEC != 0
+            || (fixed && round_page_down(addr) != addr)
+        {
+            return Ok(-EINVAL);
+        }
+        if len > self.limit {
+            return Ok(-ENOMEM);
+        }
+        let len = round_page_up(len);
+        let floor = round_page_up(self.brk);
+        let start = if fixed {
+            if addr < floor || addr.checked_add(len).is_none_or(|end| end > self.limit) {
+                return Ok(-ENOMEM);
+            }
+            addr
+        } else {
+            let mut end = self.limit;
+            for (start, mapping_end) in self.mappings.iter().rev() {
+                if end.saturating_sub(*mapping_end) >= len {
+                    break;
+                }
+                end = *start;
+            }
+            if end < floor || end - floor < len {
+                return Ok(-ENOMEM);
+            }
+            end - len
+        };
+        let replaced = self.remove(start, start + len);
+        match machine.memory_mut().map_region(start, len, prot, fixed) {
+            Ok(()) | Err(Error::Unimplemented) => (),
+            Err(Error::InvalidPermission) => {
+                for (replaced_start, replaced_end) in replaced {
+                    self.mappings.insert(replaced_start, replaced_end);
+                }
+                return Ok(-EEXIST);
+            }
+            Err(e) => return Err(e),
+        }
+        if !replaced.is_empty() {
+            machine.memory_mut().store_byte(start, len, 0)?;
+        }
+        self.mappings.insert(start, start + len);
+        Ok(start as i64)
+    }
+
+    // Removes heap mappings within [start, end), mappings only partially
+    // covered are split. Returns the ranges removed.
+    fn remove(&mut self, start: u64, end: u64) -> Vec<(u64, u64)> {
+        let overlapping: Vec<(u64, u64)> = self
+            .mappings
+            .range(..end)
+            .filter(|(_, mapping_end)| **mapping_end > start)
+            .map(|(mapping_start, mapping_end)| (*mapping_start, *mapping_end))
+            .collect();
+        let mut removed = Vec::with_capacity(overlapping.len());
+        for (mapping_start, mapping_end) in overlapping {
+            self.mappings.remove(&mapping_start);
+            if mapping_start < start {
+                self.mappings.insert(mapping_start, start);
+            }
+            if mapping_end > end {
+                self.mappings.insert(end, mapping_end);
+            }
+            removed.push((mapping_start.max(start), mapping_end.min(end)));
+        }
+        removed
+    }
+
+    // Unmapping pages that are not mapped succeeds as on Linux, mappings
+    // only partially covered are split. Ranges memory rejects, see
+    // Memory::unmap_region, fail with EINVAL.
+    fn munmap<Mac: SupportMachine>(
+        &mut self,
+        machine: &mut Mac,
+        addr: u64,
+        len: u64,
+    ) -> Result<i64, Error> {
+        if round_page_down(addr) != addr || len == 0 {
+            return Ok(-EINVAL);
+        }
+        let end = match addr.checked_add(round_page_up(len)) {
+            Some(end) => end,
+            None => return Ok(-EINVAL),
+        };
+        match machine.memory_mut().unmap_region(addr, len) {
+            Ok(()) | Err(Error::Unimplemented) => (),
+            Err(Error::Unaligned) | Err(Error::OutOfBound) => return Ok(-EINVAL),
+            Err(e) => return Err(e),
+        }
+        for (start, end) in self.remove(addr, end) {
+            machine.memory_mut().store_byte(start, end - start, 0)?;
+        }
+        Ok(0)
+    }
+}
+
+impl<Mac: SupportMachine> Syscalls<Mac> for HeapSyscalls {
+    fn initialize(&mut self, machine: &mut Mac) -> Result<(), Error> {
+        let stack_start =
+            (machine.memory().memory_size() as u64).saturating_sub(DEFAULT_STACK_SIZE as u64);
+        self.limit = stack_start.max(self.heap_start);
+        self.brk = self.heap_start;
+        // Mappings of a previous program are gone if memory was reset,
+        // unmapping them again is harmless.
+        for (start, end) in core::mem::take(&mut self.mappings) {
+            match machine.memory_mut().unmap_region(start, end - start) {
+                Ok(()) | Err(Error::Unimplemented) => (),
+                Err(e) => return Err(e),
+            }
+        }
+        // Likewise for watchpoints
+        self.update_poison(machine)
+    }
+
+    fn ecall(&mut self, machine: &mut Mac) -> Result<bool, Error> {
+        let a0 = machine.registers()[A0].to_u64();
+        let a1 = machine.registers()[A1].to_u64();
+        let result = match machine.registers()[A7].to_u64() {
+            BRK_SYSCALL_NUMBER => Mac::REG::from_u64(self.set_brk(machine, a0)?),
+            MMAP_SYSCALL_NUMBER => {
+                let prot = machine.registers()[A2].to_u64();
+                let flags = machine.registers()[A3].to_u64();
+                Mac::REG::from_i64(self.mmap(machine, a0, a1, prot, flags)?)
+            }
+            MUNMAP_SYSCALL_NUMBER => Mac::REG::from_i64(self.munmap(machine, a0, a1)?),
+            _ => return Ok(false),
+        };
+        self.update_poison(machine)?;
+        machine.set_register(A0, result);
+        Ok(true)
+    }
+}
@@ -0,0 +1,169 @@
+// Helpers for writing syscalls as plain Rust functions. Arguments are read
+// from A0 to A5 following the RISC-V calling convention, and results are
+// returned in A0. Byte buffers are passed as an address followed by a
+// length in the next argument.
+use super::super::{machine::SupportMachine, memory::Memory, registers::A0, Error, Register};
+use super::registry::SyscallHandler;
+
+use alloc::{boxed::Box, vec::Vec};
+use core::cmp::min;
+
+// Number of argument registers available to syscalls, A0 to A5.
+pub const MAX_ARGS: usize = 6;
+
+// Reads the raw value of argument idx. This panics when idx is not below
+// MAX_ARGS.
+pub fn read_arg<Mac: SupportMachine>(machine: &Mac, idx: usize) -> Mac::REG {
+    assert!(idx < MAX_ARGS, "syscall argument index out of range");
+    machine.registers()[A0 + idx].clone()
+}
+
+// Reads the buffer whose address is in argument idx and whose length is in
+// argument idx + 1.
+pub fn read_arg_bytes<Mac: SupportMachine>(
+    machine: &mut Mac,
+    idx: usize,
+) -> Result<Vec<u8>, Error> {
+    let addr = read_arg(machine, idx).to_u64();
+    let len = read_arg(machine, idx + 1).to_u64();
+    machine.memory_mut().load_bytes(addr, len)
+}
+
+// Reads a NUL terminated string starting at the address in argument idx,
+// at most max_len bytes are read before giving up with Error::OutOfBound.
+// The terminating NUL is not included.
+pub fn read_arg_cstr<Mac: SupportMachine>(
+    machine: &mut Mac,
+    idx: usize,
+    max_len: u64,
+) -> Result<Vec<u8>, Error> {
+    let mut addr = read_arg(machine, idx).to_u64();
+    let mut result = Vec::new();
+    while (result.len() as u64) < max_len {
+        let byte = machine
+            .memory_mut()
+            .load8(&Mac::REG::from_u64(addr))?
+            .to_u8();
+        if byte == 0 {
+            return Ok(result);
+        }
+        result.push(byte);
+        addr = addr.checked_add(1).ok_or(Error::OutOfBound)?;
+    }
+    Err(Error::OutOfBound)
+}
+
+// Copies data into the buffer whose address is in argument idx and whose
+// capacity is in argument idx + 1, data exceeding the capacity is dropped.
+// The full length of data is returned in A0, so programs can detect a
+// truncated result and retry with a larger buffer.
+pub fn write_return_slice<Mac: SupportMachine>(
+    machine: &mut Mac,
+    idx: usize,
+    data: &[u8],
+) -> Result<(), Error> {
+    let addr = read_arg(machine, idx).to_u64();
+    let capacity = read_arg(machine, idx + 1).to_u64();
+    let size = min(capacity, data.len() as u64) as usize;
+    machine.memory_mut().store_bytes(addr, &data[..size])?;
+    machine.set_register(A0, Mac::REG::from_u64(data.len() as u64));
+    Ok(())
+}
+
+/// Types a syscall argument register can be converted to.
+pub trait FromArg: Sized {
+    fn from_arg<R: Register>(value: &R) -> Self;
+}
+
+/// Types that can be returned to the program in A0. Returning `()` leaves
+/// A0 untouched, which lets functions use write_return_slice.
+pub trait IntoReturn {
+    fn into_return<R: Register>(self) -> Option<R>;
+}
+
+macro_rules! impl_arg {
+    ($t:ty, $to:ident, $from:ident) => {
+        impl FromArg for $t {
+            fn from_arg<R: Register>(value: &R) -> Self {
+                value.$to()
+            }
+        }
+
+        impl IntoReturn for $t {
+            fn into_return<R: Register>(self) -> Option<R> {
+                Some(R::$from(self))
+            }
+        }
+    };
+}
+
+impl_arg!(u8, to_u8, from_u8);
+impl_arg!(u16, to_u16, from_u16);
+impl_arg!(u32, to_u32, from_u32);
+impl_arg!(u64, to_u64, from_u64);
+impl_arg!(i8, to_i8, from_i8);
+impl_arg!(i16, to_i16, from_i16);
+impl_arg!(i32, to_i32, from_i32);
+impl_arg!(i64, to_i64, from_i64);
+
+impl FromArg for bool {
+    fn from_arg<R: Register>(value: &R) -> Self {
+        value.to_u64() != 0
+    }
+}
+
+impl IntoReturn for bool {
+    fn into_return<R: Register>(self) -> Option<R> {
+        Some(R::from_u8(self as u8))
+    }
+}
+
+impl IntoReturn for () {
+    fn into_return<R: Register>(self) -> Option<R> {
+        None
+    }
+}
+
+/// Rust functions that can serve as syscall handlers. This is implemented
+/// for closures taking the machine followed by up to MAX_ARGS arguments
+/// implementing `FromArg`, and returning a `Result` of a type implementing
+/// `IntoReturn`. Args is the tuple of argument types, it only exists to
+/// tell implementations apart.
+pub trait HostFn<'a, Mac, Args> {
+    fn into_handler(self) -> Box<SyscallHandler<'a, Mac>>;
+}
+
+macro_rules! impl_host_fn {
+    ($($t:ident $arg:ident),*) => {
+        impl<'a, Mac, F, Ret, $($t),*> HostFn<'a, Mac, ($($t,)*)> for F
+        where
+            Mac: SupportMachine,
+            F: FnMut(&mut Mac, $($t),*) -> Result<Ret, Error> + 'a,
+            Ret: IntoReturn,
+            $($t: FromArg,)*
+        {
+            #[allow(unused_mut, unused_variables, unused_assignments)]
+            fn into_handler(mut self) -> Box<SyscallHandler<'a, Mac>> {
+                Box::new(move |machine: &mut Mac| {
+                    let mut idx = 0;
+                    $(
+                        let $arg = $t::from_arg(&read_arg(machine, idx));
+                        idx += 1;
+                    )*
+                    if let Some(value) = self(machine, $($arg),*)?.into_return() {
+                        machine.set_register(A0, value);
+                    }
+                    Ok(())
+                })
+            }
+        }
+    };
+}
+
+impl_host_fn!();
+impl_host_fn!(T1 a1);
+impl_host_fn!(T1 a1, T2 a2);
+impl_host_fn!(T1 a1, T2 a2, T3 a3);
+impl_host_fn!(T1 a1, T2 a2, T3 a3, T4 a4);
+impl_host_fn!(T1 a1, T2 a2, T3 a3, T4 a4, T5 a5);
+impl_host_fn!(T1 a1, T2 a2, T3 a3, T4 a4, T5 a5, T6 a6);
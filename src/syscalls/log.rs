@@ -0,0 +1,16 @@
+use super::super::Error;
+
+/// One ecall recorded by a machine with the syscall log enabled, see
+/// DefaultMachineBuilder::syscall_log.
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub struct SyscallRecord {
+    // Syscall number from A7
+    pub number: u64,
+    // A0 to A5 right before the syscall ran
+    pub args: [u64; 6],
+    // A0 once the syscall returned, or the error it stopped the machine
+    // with, such as Error::InvalidEcall for unhandled syscalls.
+    pub result: Result<u64, Error>,
+    // Cycles consumed before the syscall, its own cost not included
+    pub cycles: u64,
+}
@@ -1,6 +1,32 @@
+#[cfg(feature = "async")]
+pub mod asynchronous;
+pub mod capture;
+pub mod channel;
+#[cfg(feature = "std")]
+pub mod debug;
+pub mod heap;
+pub mod host;
+pub mod log;
+pub mod mprotect;
+pub mod random;
+pub mod registry;
+
 use super::Error;
 use crate::machine::SupportMachine;
 
+#[cfg(feature = "async")]
+pub use self::asynchronous::{AsyncSyscalls, EcallFuture};
+pub use self::capture::{RecordingSyscalls, ReplaySyscalls, SharedCaptures, SyscallCapture};
+pub use self::channel::{ChannelBroker, ChannelSyscalls};
+#[cfg(feature = "std")]
+pub use self::debug::DebugSyscall;
+pub use self::heap::HeapSyscalls;
+pub use self::host::{read_arg, read_arg_bytes, read_arg_cstr, write_return_slice, HostFn};
+pub use self::log::SyscallRecord;
+pub use self::mprotect::MprotectSyscall;
+pub use self::random::RandomSyscall;
+pub use self::registry::{SyscallFallback, SyscallHandler, SyscallRegistry};
+
 pub trait Syscalls<Mac: SupportMachine> {
     fn initialize(&mut self, machine: &mut Mac) -> Result<(), Error>;
     // Returned bool means if the syscall has been processed, if
@@ -0,0 +1,72 @@
+use super::super::{
+    machine::SupportMachine,
+    memory::{round_page_up, Memory, FLAG_EXECUTABLE, FLAG_WRITABLE},
+    registers::{A0, A1, A2, A7},
+    Error, Register,
+};
+use super::Syscalls;
+
+// Same number and arguments as mprotect on Linux RISC-V: A0 holds the page
+// aligned address, A1 the length and A2 the PROT_* bits. 0 is returned in
+// A0 on success, or a negated errno value.
+pub const MPROTECT_SYSCALL_NUMBER: u64 = 226;
+
+pub const PROT_READ: u64 = 0x1;
+pub const PROT_WRITE: u64 = 0x2;
+pub const PROT_EXEC: u64 = 0x4;
+
+const EACCES: i64 = 13;
+const EINVAL: i64 = 22;
+const ENOMEM: i64 = 12;
+
+/// Lets programs change page permissions at runtime through
+/// Memory::mprotect. Pages are either writable or executable, so
+/// PROT_WRITE | PROT_EXEC is rejected, and pages without PROT_EXEC are
+/// writable since reads are never restricted.
+#[derive(Default)]
+pub struct MprotectSyscall {}
+
+impl MprotectSyscall {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<Mac: SupportMachine> Syscalls<Mac> for MprotectSyscall {
+    fn initialize(&mut self, _machine: &mut Mac) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn ecall(&mut self, machine: &mut Mac) -> Result<bool, Error> {
+        if machine.registers()[A7].to_u64() != MPROTECT_SYSCALL_NUMBER {
+            return Ok(false);
+        }
+        let addr = machine.registers()[A0].to_u64();
+        // Lengths are rounded up to whole pages as Linux does
+        let size = machine.registers()[A1].to_u64();
+        let size = if size > machine.memory().memory_size() as u64 {
+            size
+        } else {
+            round_page_up(size)
+        };
+        let prot = machine.registers()[A2].to_u64();
+        let result = if prot & PROT_WRITE != 0 && prot & PROT_EXEC != 0 {
+            -EINVAL
+        } else {
+            let flags = if prot & PROT_EXEC != 0 {
+                FLAG_EXECUTABLE
+            } else {
+                FLAG_WRITABLE
+            };
+            match machine.memory_mut().mprotect(addr, size, flags) {
+                Ok(()) => 0,
+                Err(Error::Unaligned) => -EINVAL,
+                Err(Error::OutOfBound) => -ENOMEM,
+                Err(Error::InvalidPermission) => -EACCES,
+                Err(e) => return Err(e),
+            }
+        };
+        machine.set_register(A0, Mac::REG::from_i64(result));
+        Ok(true)
+    }
+}
@@ -0,0 +1,70 @@
+use super::super::{
+    machine::SupportMachine,
+    memory::Memory,
+    registers::{A0, A1, A7},
+    Error, Register,
+};
+use super::Syscalls;
+
+use alloc::{vec, vec::Vec};
+
+// Same number and arguments as getrandom on Linux RISC-V: A0 holds the
+// buffer address, A1 the number of bytes to fill, and the number of bytes
+// filled is returned in A0. Flags in A2 are ignored.
+pub const RANDOM_BYTES_SYSCALL_NUMBER: u64 = 278;
+
+/// Fills guest buffers with pseudo-random bytes generated from a seed
+/// chosen by the host, so programs see the same bytes on every run. The
+/// generator is reset each time a program is loaded. This is meant for
+/// tests, scripts relying on it are not deterministic across hosts using
+/// different seeds.
+pub struct RandomSyscall {
+    seed: u64,
+    state: u64,
+}
+
+impl RandomSyscall {
+    pub fn new(seed: u64) -> Self {
+        Self { seed, state: seed }
+    }
+
+    // SplitMix64, small and good enough for test inputs.
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    pub fn fill_bytes(&mut self, buffer: &mut [u8]) {
+        for chunk in buffer.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+}
+
+impl<Mac: SupportMachine> Syscalls<Mac> for RandomSyscall {
+    fn initialize(&mut self, _machine: &mut Mac) -> Result<(), Error> {
+        self.state = self.seed;
+        Ok(())
+    }
+
+    fn ecall(&mut self, machine: &mut Mac) -> Result<bool, Error> {
+        if machine.registers()[A7].to_u64() != RANDOM_BYTES_SYSCALL_NUMBER {
+            return Ok(false);
+        }
+        let addr = machine.registers()[A0].to_u64();
+        let size = machine.registers()[A1].to_u64();
+        if addr.checked_add(size).ok_or(Error::OutOfBound)? > machine.memory().memory_size() as u64
+        {
+            return Err(Error::OutOfBound);
+        }
+        let mut buffer: Vec<u8> = vec![0; size as usize];
+        self.fill_bytes(&mut buffer);
+        machine.memory_mut().store_bytes(addr, &buffer)?;
+        machine.set_register(A0, Mac::REG::from_u64(size));
+        Ok(true)
+    }
+}
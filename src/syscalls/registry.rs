@@ -0,0 +1,101 @@
+use super::super::Error;
+use super::host::HostFn;
+use crate::machine::SupportMachine;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+
+pub type SyscallHandler<'a, Mac> = dyn FnMut(&mut Mac) -> Result<(), Error> + 'a;
+// Fallback handlers also receive the syscall number, returning false means
+// the syscall is not processed.
+pub type SyscallFallback<'a, Mac> = dyn FnMut(&mut Mac, u64) -> Result<bool, Error> + 'a;
+
+// Syscall handlers keyed by syscall number, unlike Syscalls modules which
+// are probed one after another, a handler here is located directly via the
+// number stored in A7. Handlers can be replaced or removed at any time, and
+// an optional fallback is consulted for numbers without a handler.
+//
+// The registry also keeps a cost table, the machine charges the cycles
+// listed for a syscall number on each invocation before dispatching it, no
+// matter whether a handler, a Syscalls module or the fallback processes it.
+pub struct SyscallRegistry<'a, Mac> {
+    handlers: BTreeMap<u64, Box<SyscallHandler<'a, Mac>>>,
+    fallback: Option<Box<SyscallFallback<'a, Mac>>>,
+    cycles: BTreeMap<u64, u64>,
+}
+
+impl<Mac> Default for SyscallRegistry<'_, Mac> {
+    fn default() -> Self {
+        Self {
+            handlers: BTreeMap::new(),
+            fallback: None,
+            cycles: BTreeMap::new(),
+        }
+    }
+}
+
+impl<'a, Mac: SupportMachine> SyscallRegistry<'a, Mac> {
+    // Returns the handler previously registered for the same number, if any.
+    pub fn register(
+        &mut self,
+        number: u64,
+        handler: Box<SyscallHandler<'a, Mac>>,
+    ) -> Option<Box<SyscallHandler<'a, Mac>>> {
+        self.handlers.insert(number, handler)
+    }
+
+    // Registers a Rust function with typed arguments, see HostFn. Returns
+    // the handler previously registered for the same number, if any.
+    pub fn register_fn<Args, F: HostFn<'a, Mac, Args>>(
+        &mut self,
+        number: u64,
+        f: F,
+    ) -> Option<Box<SyscallHandler<'a, Mac>>> {
+        self.register(number, f.into_handler())
+    }
+
+    pub fn remove(&mut self, number: u64) -> Option<Box<SyscallHandler<'a, Mac>>> {
+        self.handlers.remove(&number)
+    }
+
+    pub fn contains(&self, number: u64) -> bool {
+        self.handlers.contains_key(&number)
+    }
+
+    pub fn set_fallback(&mut self, fallback: Box<SyscallFallback<'a, Mac>>) {
+        self.fallback = Some(fallback);
+    }
+
+    pub fn remove_fallback(&mut self) -> Option<Box<SyscallFallback<'a, Mac>>> {
+        self.fallback.take()
+    }
+
+    // Returns the cost previously set for the same number, if any.
+    pub fn set_cycles(&mut self, number: u64, cycles: u64) -> Option<u64> {
+        self.cycles.insert(number, cycles)
+    }
+
+    pub fn remove_cycles(&mut self, number: u64) -> Option<u64> {
+        self.cycles.remove(&number)
+    }
+
+    // Cycles charged for each invocation of a syscall, numbers without an
+    // entry in the cost table are free.
+    pub fn cycles(&self, number: u64) -> u64 {
+        self.cycles.get(&number).cloned().unwrap_or(0)
+    }
+
+    // Returned bool means if a handler has been found for the number.
+    pub fn dispatch(&mut self, number: u64, machine: &mut Mac) -> Result<bool, Error> {
+        match self.handlers.get_mut(&number) {
+            Some(handler) => handler(machine).map(|_| true),
+            None => Ok(false),
+        }
+    }
+
+    pub fn dispatch_fallback(&mut self, number: u64, machine: &mut Mac) -> Result<bool, Error> {
+        match &mut self.fallback {
+            Some(fallback) => fallback(machine, number),
+            None => Ok(false),
+        }
+    }
+}
@@ -0,0 +1,143 @@
+// Differential harness running one program on two machines that only
+// differ in their memory implementation, FlatMemory and SparseMemory by
+// default. Machines are stepped in lockstep and their pc and registers are
+// compared at the end of every basic block, so a memory backend returning
+// different data is caught right after the block that read it instead of
+// by a mismatching exit code much later, if at all. Downstream crates can
+// run it over their own programs in CI.
+use super::{
+    decoder::{build_imac_decoder, Decoder},
+    instructions::is_basic_block_end_instruction,
+    machine::{CoreMachine, DefaultCoreMachine, DefaultMachine, DefaultMachineBuilder},
+    memory::{flat::FlatMemory, sparse::SparseMemory, Memory},
+    Error, Register, SupportMachine,
+};
+use alloc::{boxed::Box, vec::Vec};
+use bytes::Bytes;
+
+// Instructions a program can execute before the harness gives up on it.
+pub const DEFAULT_MAX_INSTRUCTIONS: u64 = 10_000_000;
+
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct MachineState {
+    pub pc: u64,
+    pub registers: Vec<u64>,
+    // Error the machine stopped with, None while it runs or after it exited
+    pub error: Option<Error>,
+}
+
+/// First point at which the two machines of run_lockstep disagreed.
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct Divergence {
+    // Start of the basic block after which the states were compared, 0 when
+    // loading the program already differed
+    pub block: u64,
+    // Instructions executed by the left machine so far
+    pub instructions: u64,
+    pub left: MachineState,
+    pub right: MachineState,
+}
+
+fn state<Inner: SupportMachine>(
+    machine: &DefaultMachine<'_, Inner>,
+    result: &Result<(), Error>,
+) -> MachineState {
+    MachineState {
+        pc: machine.pc().to_u64(),
+        registers: machine.registers().iter().map(|r| r.to_u64()).collect(),
+        error: result.err(),
+    }
+}
+
+// Runs program on FlatMemory and SparseMemory machines, see run_lockstep.
+pub fn run_flat_vs_sparse<R: Register>(
+    program: &Bytes,
+    args: &[Bytes],
+    max_instructions: u64,
+) -> Result<Result<i8, Error>, Box<Divergence>> {
+    run_lockstep::<R, FlatMemory<R>, SparseMemory<R>>(program, args, max_instructions)
+}
+
+// Loads program on a machine backed by memory A and one backed by memory B,
+// then steps both until they exit or fail. Returns the result both
+// machines agree on, or the first divergence. Running out of
+// max_instructions fails both with Error::InstructionLimitExceeded.
+pub fn run_lockstep<R, A, B>(
+    program: &Bytes,
+    args: &[Bytes],
+    max_instructions: u64,
+) -> Result<Result<i8, Error>, Box<Divergence>>
+where
+    R: Register,
+    A: Memory<R> + Default,
+    B: Memory<R> + Default,
+{
+    let mut left = DefaultMachineBuilder::new(DefaultCoreMachine::<R, A>::default())
+        .max_instructions(max_instructions)
+        .build();
+    let mut right = DefaultMachineBuilder::new(DefaultCoreMachine::<R, B>::default())
+        .max_instructions(max_instructions)
+        .build();
+    let decoder = build_imac_decoder::<R>();
+    let left_result = left.load_program(program, args).map(|_| ());
+    let right_result = right.load_program(program, args).map(|_| ());
+    compare(&left, &left_result, &right, &right_result, 0)?;
+    if let Err(e) = left_result {
+        return Ok(Err(e));
+    }
+    left.set_running(true);
+    right.set_running(true);
+    loop {
+        let block = left.pc().to_u64();
+        let left_result = step_block(&mut left, &decoder);
+        let right_result = step_block(&mut right, &decoder);
+        compare(&left, &left_result, &right, &right_result, block)?;
+        if let Err(e) = left_result {
+            return Ok(Err(e));
+        }
+        if !left.running() {
+            return Ok(Ok(left.exit_code()));
+        }
+    }
+}
+
+// Steps machine up to and including the next instruction ending a basic
+// block, or until it exits or fails.
+fn step_block<Inner: SupportMachine>(
+    machine: &mut DefaultMachine<'_, Inner>,
+    decoder: &Decoder,
+) -> Result<(), Error> {
+    while machine.running() {
+        let pc = machine.pc().to_u64();
+        let instruction = decoder.decode(machine.memory_mut(), pc)?;
+        machine.step(decoder)?;
+        if is_basic_block_end_instruction(instruction) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn compare<L: SupportMachine, R: SupportMachine>(
+    left: &DefaultMachine<'_, L>,
+    left_result: &Result<(), Error>,
+    right: &DefaultMachine<'_, R>,
+    right_result: &Result<(), Error>,
+    block: u64,
+) -> Result<(), Box<Divergence>> {
+    let left_state = state(left, left_result);
+    let right_state = state(right, right_result);
+    if left_state == right_state
+        && left.running() == right.running()
+        && left.exit_code() == right.exit_code()
+        && left.instructions() == right.instructions()
+    {
+        return Ok(());
+    }
+    Err(Box::new(Divergence {
+        block,
+        instructions: left.instructions(),
+        left: left_state,
+        right: right_state,
+    }))
+}
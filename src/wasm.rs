@@ -0,0 +1,94 @@
+use crate::{
+    machine::{trace::TraceMachine, DefaultCoreMachine, DefaultMachineBuilder, SupportMachine},
+    SparseMemory, WXorXMemory,
+};
+use alloc::{format, string::String, vec::Vec};
+use bytes::Bytes;
+use wasm_bindgen::prelude::*;
+
+type WasmMachine<'a> =
+    TraceMachine<'a, DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>>;
+
+/// Result of running a program from JavaScript. Errors raised by the VM are
+/// thrown as strings instead, so hosts only see this on a clean exit.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RunResult {
+    exit_code: i8,
+    cycles: u64,
+}
+
+#[wasm_bindgen]
+impl RunResult {
+    #[wasm_bindgen(getter)]
+    pub fn exit_code(&self) -> i8 {
+        self.exit_code
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+}
+
+// Runs a RV64 ELF program with the interpreter. Every instruction costs one
+// cycle, a max_cycles of 0 means no limit. Sparse memory is used so pages
+// are only allocated on wasm's linear memory when the program touches them.
+#[wasm_bindgen]
+pub fn run(program: &[u8], args: Vec<String>, max_cycles: u64) -> Result<RunResult, JsValue> {
+    let mut machine: WasmMachine = TraceMachine::new(
+        DefaultMachineBuilder::default()
+            .instruction_cycle_func(alloc::boxed::Box::new(|_| 1))
+            .max_cycles(max_cycles)
+            .build(),
+    );
+    run_machine(&mut machine, program, &args).map_err(|e| JsValue::from_str(&e))
+}
+
+fn run_machine(
+    machine: &mut WasmMachine,
+    program: &[u8],
+    args: &[String],
+) -> Result<RunResult, String> {
+    let args: Vec<Bytes> = args.iter().map(|arg| Bytes::from(arg.as_bytes())).collect();
+    machine
+        .load_program(&Bytes::from(program), &args)
+        .map_err(|e| format!("{:?}", e))?;
+    let exit_code = machine.run().map_err(|e| format!("{:?}", e))?;
+    Ok(RunResult {
+        exit_code,
+        cycles: SupportMachine::cycles(&machine.machine),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_run_simple() {
+        let program = fs::read("tests/programs/simple64").unwrap();
+        let mut machine: WasmMachine = TraceMachine::new(
+            DefaultMachineBuilder::default()
+                .instruction_cycle_func(alloc::boxed::Box::new(|_| 1))
+                .build(),
+        );
+        let result = run_machine(&mut machine, &program, &["simple".into()]).unwrap();
+        assert_eq!(result.exit_code(), 0);
+        assert!(result.cycles() > 0);
+    }
+
+    #[test]
+    fn test_run_cycles_exceeded() {
+        let program = fs::read("tests/programs/simple64").unwrap();
+        let mut machine: WasmMachine = TraceMachine::new(
+            DefaultMachineBuilder::default()
+                .instruction_cycle_func(alloc::boxed::Box::new(|_| 1))
+                .max_cycles(10)
+                .build(),
+        );
+        let result = run_machine(&mut machine, &program, &["simple".into()]);
+        assert_eq!(result, Err("CyclesExceeded".into()));
+    }
+}
@@ -7,7 +7,8 @@ use ckb_vm::{
         asm::{AsmCoreMachine, AsmMachine},
     },
     registers::{A0, A1, A2, A3, A4, A5, A7},
-    Debugger, DefaultMachineBuilder, Error, Instruction, Register, SupportMachine, Syscalls,
+    Debugger, DefaultMachineBuilder, ElfSegmentError, Error, Instruction, Register, SupportMachine,
+    Syscalls,
 };
 use std::fs::File;
 use std::io::Read;
@@ -32,6 +33,28 @@ pub fn test_aot_simple64() {
     assert_eq!(result.unwrap(), 0);
 }
 
+#[test]
+pub fn test_aot_code_reused_across_machines() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    // A program only needs to be compiled once, the generated code can be
+    // shared by as many machines as needed.
+    let mut aot_machine = AotCompilingMachine::load(&buffer, None).unwrap();
+    let code = aot_machine.compile().unwrap();
+    for _ in 0..3 {
+        let mut machine = AsmMachine::default_with_aot_code(&code);
+        machine
+            .load_program(&buffer, &vec!["simple".into()])
+            .unwrap();
+        let result = machine.run();
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+    }
+}
+
 pub struct CustomSyscall {}
 
 impl<Mac: SupportMachine> Syscalls<Mac> for CustomSyscall {
@@ -167,7 +190,7 @@ pub fn test_aot_simple_max_cycles_reached() {
         .unwrap();
     let result = machine.run();
     assert!(result.is_err());
-    assert_eq!(result.unwrap_err(), Error::InvalidCycles);
+    assert_eq!(result.unwrap_err(), Error::CyclesExceeded);
 }
 
 #[test]
@@ -185,7 +208,7 @@ pub fn test_aot_trace() {
         .unwrap();
     let result = machine.run();
     assert!(result.is_err());
-    assert_eq!(result.err(), Some(Error::InvalidPermission));
+    assert_eq!(result.err(), Some(Error::MemoryProtection));
 }
 
 #[test]
@@ -203,7 +226,7 @@ pub fn test_aot_jump0() {
         .unwrap();
     let result = machine.run();
     assert!(result.is_err());
-    assert_eq!(result.err(), Some(Error::InvalidPermission));
+    assert_eq!(result.err(), Some(Error::MemoryProtection));
 }
 
 #[test]
@@ -284,14 +307,8 @@ pub fn test_aot_load_elf_crash_64() {
     file.read_to_end(&mut buffer).unwrap();
     let buffer: Bytes = buffer.into();
 
-    let mut aot_machine = AotCompilingMachine::load(&buffer, None).unwrap();
-    let code = aot_machine.compile().unwrap();
-    let mut machine = AsmMachine::default_with_aot_code(&code);
-    machine
-        .load_program(&buffer, &vec!["load_elf_crash_64".into()])
-        .unwrap();
-    let result = machine.run();
-    assert_eq!(result.err(), Some(Error::InvalidPermission));
+    let result = AotCompilingMachine::load(&buffer, None);
+    assert_eq!(result.err(), Some(Error::OutOfBound));
 }
 
 #[test]
@@ -341,5 +358,11 @@ pub fn test_aot_flat_crash_64() {
     let buffer: Bytes = buffer.into();
 
     let result = AotCompilingMachine::load(&buffer, None);
-    assert_eq!(result.err(), Some(Error::OutOfBound));
+    assert_eq!(
+        result.err(),
+        Some(Error::ElfSegment {
+            index: 0,
+            reason: ElfSegmentError::InvalidAlignment
+        })
+    );
 }
@@ -3,12 +3,14 @@
 use bytes::Bytes;
 use ckb_vm::{
     machine::asm::{AsmCoreMachine, AsmMachine},
+    memory::FLAG_EXECUTABLE,
     registers::{A0, A1, A2, A3, A4, A5, A7},
-    Debugger, DefaultMachineBuilder, Error, Instruction, Register, SupportMachine, Syscalls,
+    CoreMachine, Debugger, DefaultMachineBuilder, Error, ExitReason, Instruction, Memory, Register,
+    SupportMachine, Syscalls, RISCV_MAX_MEMORY, RISCV_PAGESIZE,
 };
 use std::fs::File;
 use std::io::Read;
-use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
 use std::sync::Arc;
 
 #[test]
@@ -27,6 +29,21 @@ pub fn test_asm_simple64() {
     assert_eq!(result.unwrap(), 0);
 }
 
+#[test]
+pub fn test_asm_run_with_result() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let mut machine = AsmMachine::default();
+    machine.load_program(&buffer, &["simple".into()]).unwrap();
+    let result = machine.run_with_result();
+    assert_eq!(result.reason, ExitReason::Exit);
+    assert_eq!(result.exit_code, 0);
+    assert_eq!(result.cycles, SupportMachine::cycles(&machine.machine));
+}
+
 pub struct CustomSyscall {}
 
 impl<Mac: SupportMachine> Syscalls<Mac> for CustomSyscall {
@@ -152,7 +169,7 @@ pub fn test_asm_simple_max_cycles_reached() {
         .unwrap();
     let result = machine.run();
     assert!(result.is_err());
-    assert_eq!(result.unwrap_err(), Error::InvalidCycles);
+    assert_eq!(result.unwrap_err(), Error::CyclesExceeded);
 }
 
 #[test]
@@ -168,7 +185,7 @@ pub fn test_asm_trace() {
         .unwrap();
     let result = machine.run();
     assert!(result.is_err());
-    assert_eq!(result.err(), Some(Error::InvalidPermission));
+    assert_eq!(result.err(), Some(Error::MemoryProtection));
 }
 
 #[test]
@@ -184,7 +201,7 @@ pub fn test_asm_jump0() {
         .unwrap();
     let result = machine.run();
     assert!(result.is_err());
-    assert_eq!(result.err(), Some(Error::InvalidPermission));
+    assert_eq!(result.err(), Some(Error::MemoryProtection));
 }
 
 #[test]
@@ -258,11 +275,8 @@ pub fn test_asm_load_elf_crash_64() {
     let buffer: Bytes = buffer.into();
 
     let mut machine = AsmMachine::default();
-    machine
-        .load_program(&buffer, &vec!["load_elf_crash_64".into()])
-        .unwrap();
-    let result = machine.run();
-    assert_eq!(result.err(), Some(Error::InvalidPermission));
+    let result = machine.load_program(&buffer, &vec!["load_elf_crash_64".into()]);
+    assert_eq!(result.err(), Some(Error::OutOfBound));
 }
 
 #[test]
@@ -279,3 +293,125 @@ pub fn test_asm_wxorx_crash_64() {
     let result = machine.run();
     assert_eq!(result.err(), Some(Error::OutOfBound));
 }
+
+#[test]
+pub fn test_asm_on_cycles() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let consumed = Arc::new(AtomicU64::new(0));
+    let hook_consumed = Arc::clone(&consumed);
+    let core = DefaultMachineBuilder::<Box<AsmCoreMachine>>::new(
+        AsmCoreMachine::new_with_max_cycles(u64::MAX),
+    )
+    .instruction_cycle_func(Box::new(|_| 1))
+    .on_cycles(Box::new(move |cycles, _| {
+        hook_consumed.fetch_add(cycles, Ordering::SeqCst);
+    }))
+    .build();
+    let mut machine = AsmMachine::new(core, None);
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    assert_eq!(machine.run(), Ok(0));
+    let cycles = SupportMachine::cycles(&machine.machine);
+    assert!(cycles > 0);
+    assert_eq!(consumed.load(Ordering::SeqCst), cycles);
+}
+
+pub struct RemapSyscall {
+    code: Bytes,
+}
+
+// Asm memory has no way to make executable pages writable again, the host
+// rewrites the code at 0x2000 directly instead.
+impl Syscalls<Box<AsmCoreMachine>> for RemapSyscall {
+    fn initialize(&mut self, _machine: &mut Box<AsmCoreMachine>) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn ecall(&mut self, machine: &mut Box<AsmCoreMachine>) -> Result<bool, Error> {
+        if machine.registers[A7] != 1000 {
+            return Ok(false);
+        }
+        machine.memory[0x2000..0x2000 + self.code.len()].copy_from_slice(&self.code);
+        Ok(true)
+    }
+}
+
+#[test]
+pub fn test_asm_fence_i_invalidates_traces() {
+    let to_bytes = |code: &[u32]| -> Bytes {
+        code.iter()
+            .flat_map(|word| word.to_le_bytes().to_vec())
+            .collect::<Vec<u8>>()
+            .into()
+    };
+    let main: [u32; 7] = [
+        0x000010ef, // jal ra, 0x2000
+        0x3e800893, // li a7, 1000
+        0x00000073, // ecall
+        0x0000100f, // fence.i
+        0x7f1000ef, // jal ra, 0x2000
+        0x05d00893, // li a7, 93
+        0x00000073, // ecall
+    ];
+    let core = DefaultMachineBuilder::<Box<AsmCoreMachine>>::new(
+        AsmCoreMachine::new_with_max_cycles(u64::MAX),
+    )
+    .syscall(Box::new(RemapSyscall {
+        code: to_bytes(&[0x00200513, 0x00008067]), // li a0, 2; ret
+    }))
+    .build();
+    let mut machine = AsmMachine::new(core, None);
+    let memory = machine.machine.memory_mut();
+    memory
+        .init_pages(0x1000, 0x1000, FLAG_EXECUTABLE, Some(to_bytes(&main)), 0)
+        .unwrap();
+    memory
+        .init_pages(
+            0x2000,
+            0x1000,
+            FLAG_EXECUTABLE,
+            Some(to_bytes(&[0x00100513, 0x00008067])), // li a0, 1; ret
+            0,
+        )
+        .unwrap();
+    machine.machine.set_pc(0x1000);
+    assert_eq!(machine.run(), Ok(2));
+}
+
+#[test]
+pub fn test_asm_interrupt_handle() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let mut machine = AsmMachine::default();
+    machine.load_program(&buffer, &["simple".into()]).unwrap();
+    let handle = machine.machine.interrupt_handle();
+    handle.stop();
+    assert_eq!(machine.run(), Err(Error::Interrupted));
+    assert!(!handle.is_stop_requested());
+    assert_eq!(machine.run(), Ok(0));
+}
+
+#[test]
+pub fn test_asm_memory_load_bytes() {
+    let mut machine = AsmCoreMachine::new_with_max_cycles(0);
+    let data: Vec<u8> = (0..32).collect();
+    // Crosses a page boundary
+    let addr = RISCV_PAGESIZE as u64 - 10;
+    machine.store_bytes(addr, &data).unwrap();
+    assert_eq!(machine.load_bytes(addr, 32).unwrap(), data);
+    assert_eq!(machine.load_bytes(addr + 4, 8).unwrap(), &data[4..12]);
+
+    let end = RISCV_MAX_MEMORY as u64;
+    assert_eq!(machine.load_bytes(end - 4, 4).unwrap(), vec![0; 4]);
+    assert_eq!(machine.load_bytes(end - 4, 8), Err(Error::OutOfBound));
+    assert_eq!(machine.load_bytes(u64::MAX, 2), Err(Error::OutOfBound));
+    assert_eq!(machine.load_bytes(0, 1 << 40), Err(Error::OutOfBound));
+}
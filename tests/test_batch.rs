@@ -0,0 +1,101 @@
+extern crate ckb_vm;
+
+use bytes::Bytes;
+use ckb_vm::{
+    batch::{run_all, BatchConfig, MachineConfig},
+    Error, ExitReason, Instruction, SparseMemory, WXorXMemory,
+};
+use std::fs::File;
+use std::io::Read;
+use std::time::Duration;
+
+type Memory64 = WXorXMemory<u64, SparseMemory<u64>>;
+
+fn dummy_cycle_func(_i: Instruction) -> u64 {
+    1
+}
+
+fn load_program(name: &str) -> Bytes {
+    let mut file = File::open(name).unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    buffer.into()
+}
+
+fn config() -> MachineConfig {
+    MachineConfig {
+        args: vec!["batch".into()],
+        max_cycles: 0,
+        instruction_cycle_func: Some(dummy_cycle_func),
+    }
+}
+
+#[test]
+pub fn test_batch_run_all() {
+    let simple = load_program("tests/programs/simple64");
+    let trace = load_program("tests/programs/trace64");
+    let programs = vec![simple.clone(), trace, simple.clone(), simple];
+    let mut limited = config();
+    limited.max_cycles = 100;
+    let configs = vec![config(), config(), limited, config()];
+    let batch = BatchConfig {
+        threads: 2,
+        ..BatchConfig::default()
+    };
+
+    let results = run_all::<u64, Memory64>(&programs, &configs, &batch);
+    assert_eq!(results.len(), 4);
+    assert_eq!(results[0].reason, ExitReason::Exit);
+    assert_eq!(results[0].exit_code, 0);
+    assert!(results[0].cycles > 100);
+    assert_eq!(results[1].reason, ExitReason::Trap(Error::MemoryProtection));
+    assert_eq!(results[2].reason, ExitReason::CyclesExceeded);
+    assert_eq!(results[2].cycles, 100);
+    assert_eq!(results[3], results[0]);
+
+    // A single config applies to all programs
+    let results = run_all::<u64, Memory64>(&programs[..1], &[config()], &batch);
+    assert_eq!(results[0].reason, ExitReason::Exit);
+}
+
+#[test]
+pub fn test_batch_cycle_budget() {
+    let simple = load_program("tests/programs/simple64");
+    let single = run_all::<u64, Memory64>(&[simple.clone()], &[config()], &BatchConfig::default());
+    let cycles = single[0].cycles;
+
+    // The budget covers two and a half runs
+    let programs = vec![simple; 4];
+    let batch = BatchConfig {
+        threads: 1,
+        max_cycles: cycles * 5 / 2,
+        ..BatchConfig::default()
+    };
+    let results = run_all::<u64, Memory64>(&programs, &[config()], &batch);
+    let reasons: Vec<ExitReason> = results.iter().map(|r| r.reason).collect();
+    assert_eq!(
+        reasons,
+        vec![
+            ExitReason::Exit,
+            ExitReason::Exit,
+            ExitReason::CyclesExceeded,
+            ExitReason::CyclesExceeded
+        ]
+    );
+    let total: u64 = results.iter().map(|r| r.cycles).sum();
+    assert_eq!(total, batch.max_cycles);
+    assert_eq!(results[3].cycles, 0);
+}
+
+#[test]
+pub fn test_batch_timeout() {
+    let simple = load_program("tests/programs/simple64");
+    let batch = BatchConfig {
+        timeout: Some(Duration::from_secs(0)),
+        ..BatchConfig::default()
+    };
+    let results = run_all::<u64, Memory64>(&[simple.clone(), simple], &[config()], &batch);
+    for result in results {
+        assert_eq!(result.reason, ExitReason::Trap(Error::Timeout));
+    }
+}
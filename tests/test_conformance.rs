@@ -0,0 +1,41 @@
+#![cfg(feature = "conformance")]
+
+use bytes::Bytes;
+use ckb_vm::conformance::{ConformanceRunner, TestOutcome};
+use ckb_vm::decoder::build_imac_decoder;
+use ckb_vm::Error;
+use std::fs::File;
+use std::io::Read;
+
+fn load_program(name: &str) -> Bytes {
+    let mut file = File::open(name).unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    buffer.into()
+}
+
+#[test]
+pub fn test_conformance_exit_protocol() {
+    let program = load_program("tests/programs/conformance_pass64");
+    let runner = ConformanceRunner::new(build_imac_decoder::<u64>());
+    assert_eq!(runner.run::<u64>(&program), Ok(TestOutcome::Pass));
+    assert_eq!(runner.run::<u32>(&program), Err(Error::InvalidElfBits));
+}
+
+#[test]
+pub fn test_conformance_tohost_protocol() {
+    let program = load_program("tests/programs/conformance_tohost_fail64");
+    let runner = ConformanceRunner::new(build_imac_decoder::<u64>());
+    assert_eq!(runner.run::<u64>(&program), Ok(TestOutcome::Fail(3)));
+}
+
+#[test]
+pub fn test_conformance_base() {
+    let program = load_program("tests/programs/conformance_pass64");
+    // Not shifted, the test does not fit in memory
+    let runner = ConformanceRunner::new(build_imac_decoder::<u64>()).base(0);
+    assert!(runner.run::<u64>(&program).is_err());
+    // Not enough steps to reach the result
+    let runner = ConformanceRunner::new(build_imac_decoder::<u64>()).max_steps(5);
+    assert_eq!(runner.run::<u64>(&program), Err(Error::CyclesExceeded));
+}
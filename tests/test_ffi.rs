@@ -0,0 +1,169 @@
+#![cfg(feature = "ffi")]
+
+use ckb_vm::ffi::*;
+use ckb_vm::registers::{A0, A5, SP};
+use std::fs::File;
+use std::io::Read;
+use std::os::raw::c_void;
+use std::ptr;
+
+fn load_syscall_program(machine: *mut FfiMachine) -> i32 {
+    let mut file = File::open("tests/programs/syscall64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let arg = b"syscall";
+    let args = [arg.as_ptr()];
+    let arg_lens = [arg.len()];
+    unsafe {
+        ckb_vm_load_program(
+            machine,
+            buffer.as_ptr(),
+            buffer.len(),
+            1,
+            args.as_ptr(),
+            arg_lens.as_ptr(),
+        )
+    }
+}
+
+// Sums A0 to A5 into A0, and counts invocations in data.
+extern "C" fn sum_syscall(data: *mut c_void, core: *mut FfiCoreMachine) -> i32 {
+    unsafe {
+        *(data as *mut u32) += 1;
+        let mut sum = 0u64;
+        for i in A0..=A5 {
+            let mut value = 0;
+            assert_eq!(ckb_vm_get_register(core, i, &mut value), CKB_VM_OK);
+            sum = sum.wrapping_add(value);
+        }
+        ckb_vm_set_register(core, A0, sum)
+    }
+}
+
+extern "C" fn failing_syscall(_data: *mut c_void, _core: *mut FfiCoreMachine) -> i32 {
+    1
+}
+
+// Writes below the stack pointer and reads the data back.
+extern "C" fn memory_syscall(_data: *mut c_void, core: *mut FfiCoreMachine) -> i32 {
+    unsafe {
+        let mut sp = 0;
+        assert_eq!(ckb_vm_get_register(core, SP, &mut sp), CKB_VM_OK);
+        let data = [1u8, 2, 3, 4];
+        assert_eq!(
+            ckb_vm_store_memory(core, sp - 16, data.as_ptr(), data.len()),
+            CKB_VM_OK
+        );
+        let mut loaded = [0u8; 4];
+        assert_eq!(
+            ckb_vm_load_memory(core, sp - 16, loaded.as_mut_ptr(), loaded.len()),
+            CKB_VM_OK
+        );
+        assert_eq!(loaded, data);
+        assert_eq!(
+            ckb_vm_load_memory(core, u64::MAX, loaded.as_mut_ptr(), loaded.len()),
+            CKB_VM_ERROR_OUT_OF_BOUND
+        );
+        ckb_vm_set_register(core, A0, 7)
+    }
+}
+
+#[test]
+pub fn test_ffi_lifecycle() {
+    let machine = ckb_vm_create(0);
+    assert!(!machine.is_null());
+    assert_eq!(load_syscall_program(machine), CKB_VM_OK);
+    let mut calls = 0u32;
+    unsafe {
+        assert_eq!(
+            ckb_vm_register_syscall(
+                machine,
+                1111,
+                Some(sum_syscall),
+                &mut calls as *mut u32 as *mut c_void
+            ),
+            CKB_VM_OK
+        );
+        let mut exit_code = 0;
+        assert_eq!(ckb_vm_run(machine, &mut exit_code), CKB_VM_OK);
+        assert_eq!(exit_code, 39);
+        assert!(ckb_vm_cycles(machine) > 0);
+        ckb_vm_destroy(machine);
+    }
+    assert_eq!(calls, 1);
+}
+
+#[test]
+pub fn test_ffi_memory_access() {
+    let machine = ckb_vm_create(0);
+    assert_eq!(load_syscall_program(machine), CKB_VM_OK);
+    unsafe {
+        ckb_vm_register_syscall(machine, 1111, Some(memory_syscall), ptr::null_mut());
+        let mut exit_code = 0;
+        assert_eq!(ckb_vm_run(machine, &mut exit_code), CKB_VM_OK);
+        assert_eq!(exit_code, 7);
+        ckb_vm_destroy(machine);
+    }
+}
+
+#[test]
+pub fn test_ffi_errors() {
+    unsafe {
+        assert_eq!(
+            ckb_vm_run(ptr::null_mut(), ptr::null_mut()),
+            CKB_VM_ERROR_INVALID_ARGUMENT
+        );
+        assert_eq!(ckb_vm_cycles(ptr::null()), 0);
+        ckb_vm_destroy(ptr::null_mut());
+
+        let machine = ckb_vm_create(0);
+        let garbage = [0u8; 16];
+        assert_eq!(
+            ckb_vm_load_program(
+                machine,
+                garbage.as_ptr(),
+                garbage.len(),
+                0,
+                ptr::null(),
+                ptr::null()
+            ),
+            CKB_VM_ERROR_PARSE
+        );
+        assert_eq!(
+            ckb_vm_register_syscall(machine, 93, Some(failing_syscall), ptr::null_mut()),
+            CKB_VM_ERROR_INVALID_ARGUMENT
+        );
+        assert_eq!(
+            ckb_vm_register_syscall(machine, 1111, None, ptr::null_mut()),
+            CKB_VM_ERROR_INVALID_ARGUMENT
+        );
+        ckb_vm_destroy(machine);
+
+        // Syscalls without a callback are rejected
+        let machine = ckb_vm_create(0);
+        assert_eq!(load_syscall_program(machine), CKB_VM_OK);
+        assert_eq!(
+            ckb_vm_run(machine, ptr::null_mut()),
+            CKB_VM_ERROR_INVALID_ECALL
+        );
+        ckb_vm_destroy(machine);
+
+        let machine = ckb_vm_create(0);
+        assert_eq!(load_syscall_program(machine), CKB_VM_OK);
+        ckb_vm_register_syscall(machine, 1111, Some(failing_syscall), ptr::null_mut());
+        assert_eq!(
+            ckb_vm_run(machine, ptr::null_mut()),
+            CKB_VM_ERROR_INVALID_ECALL
+        );
+        ckb_vm_destroy(machine);
+
+        let machine = ckb_vm_create(5);
+        assert_eq!(load_syscall_program(machine), CKB_VM_OK);
+        ckb_vm_register_syscall(machine, 1111, Some(failing_syscall), ptr::null_mut());
+        assert_eq!(
+            ckb_vm_run(machine, ptr::null_mut()),
+            CKB_VM_ERROR_CYCLES_EXCEEDED
+        );
+        ckb_vm_destroy(machine);
+    }
+}
@@ -0,0 +1,94 @@
+#![cfg(has_jit)]
+
+use bytes::Bytes;
+use ckb_vm::{
+    machine::trace::TraceMachine, memory::FLAG_EXECUTABLE, CoreMachine, DefaultCoreMachine,
+    DefaultMachineBuilder, Error, Memory, SparseMemory, SupportMachine, WXorXMemory,
+};
+
+// A loop of register to register instructions running 1000 times, which
+// exits with the exit syscall afterwards.
+const LOOP_PROGRAM: [u32; 12] = [
+    0x00000513, // li a0, 0
+    0x3e800593, // li a1, 1000
+    0x00350513, // addi a0, a0, 3
+    0x00251613, // slli a2, a0, 2
+    0x00a646b3, // xor a3, a2, a0
+    0x4036d71b, // sraiw a4, a3, 3
+    0x40e787b3, // sub a5, a5, a4
+    0x00d7b833, // sltu a6, a5, a3
+    0xfff58593, // addi a1, a1, -1
+    0xfe0592e3, // bnez a1, -28
+    0x05d00893, // li a7, 93
+    0x00000073, // ecall
+];
+
+fn build_machine<'a>(
+    jit_threshold: Option<u32>,
+    max_cycles: u64,
+    max_instructions: u64,
+) -> TraceMachine<'a, DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>> {
+    let mut machine = TraceMachine::new(
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>>::default()
+            .instruction_cycle_func(Box::new(|_| 1))
+            .max_cycles(max_cycles)
+            .max_instructions(max_instructions)
+            .build(),
+    );
+    machine.set_jit_threshold(jit_threshold);
+    let code: Vec<u8> = LOOP_PROGRAM
+        .iter()
+        .flat_map(|word| word.to_le_bytes().to_vec())
+        .collect();
+    machine
+        .memory_mut()
+        .init_pages(0x1000, 0x1000, FLAG_EXECUTABLE, Some(Bytes::from(code)), 0)
+        .unwrap();
+    machine.set_pc(0x1000);
+    machine
+}
+
+#[test]
+pub fn test_jit_matches_interpreter() {
+    let mut interpreted = build_machine(None, 100_000, 0);
+    let interpreted_result = interpreted.run();
+    assert_eq!(interpreted.compiled_traces(), 0);
+
+    let mut compiled = build_machine(Some(2), 100_000, 0);
+    let compiled_result = compiled.run();
+    assert_eq!(compiled.compiled_traces(), 1);
+
+    assert_eq!(compiled_result, interpreted_result);
+    assert_eq!(compiled.registers(), interpreted.registers());
+    assert_eq!(compiled.pc(), interpreted.pc());
+    assert_eq!(
+        SupportMachine::cycles(&compiled.machine),
+        SupportMachine::cycles(&interpreted.machine)
+    );
+}
+
+#[test]
+pub fn test_jit_cycles_exceeded() {
+    // The limit is hit halfway through a compiled trace item
+    let mut interpreted = build_machine(None, 3003, 0);
+    let mut compiled = build_machine(Some(2), 3003, 0);
+    assert_eq!(interpreted.run(), Err(Error::CyclesExceeded));
+    assert_eq!(compiled.run(), Err(Error::CyclesExceeded));
+    assert_eq!(compiled.registers(), interpreted.registers());
+    assert_eq!(compiled.pc(), interpreted.pc());
+    assert_eq!(
+        SupportMachine::cycles(&compiled.machine),
+        SupportMachine::cycles(&interpreted.machine)
+    );
+}
+
+#[test]
+pub fn test_jit_instruction_limit() {
+    let mut interpreted = build_machine(None, 0, 3003);
+    let mut compiled = build_machine(Some(2), 0, 3003);
+    assert_eq!(interpreted.run(), Err(Error::InstructionLimitExceeded));
+    assert_eq!(compiled.run(), Err(Error::InstructionLimitExceeded));
+    assert_eq!(compiled.machine.instructions(), 3003);
+    assert_eq!(compiled.registers(), interpreted.registers());
+    assert_eq!(compiled.pc(), interpreted.pc());
+}
@@ -0,0 +1,863 @@
+extern crate ckb_vm;
+
+use bytes::Bytes;
+use ckb_vm::{
+    memory::{
+        cow::build_image, diff, ChangedRange, Page, FLAG_EXECUTABLE, FLAG_FREEZED, FLAG_WRITABLE,
+    },
+    CoreMachine, CowMemory, DefaultCoreMachine, DefaultMachineBuilder, Error, FlatMemory,
+    LazyMemory, Memory, PagedMemory, SparseMemory, WXorXMemory, WatchpointKind, DEFAULT_STACK_SIZE,
+    RISCV_MAX_MEMORY, RISCV_PAGESIZE,
+};
+use std::fs::File;
+use std::io::Read;
+use std::sync::Arc;
+
+fn check_load_bytes<M: Memory<u64>>(memory: &mut M) {
+    let data: Vec<u8> = (0..32).collect();
+    // Crosses a page boundary
+    let addr = RISCV_PAGESIZE as u64 - 10;
+    memory.store_bytes(addr, &data).unwrap();
+
+    assert_eq!(memory.load_bytes(addr, 32).unwrap(), data);
+    assert_eq!(memory.load_bytes(addr + 4, 8).unwrap(), &data[4..12]);
+    assert_eq!(memory.load_bytes(addr, 0).unwrap(), Vec::<u8>::new());
+    let bytes: Vec<u8> = (addr - 4..addr + 36)
+        .map(|a| memory.load8(&a).unwrap() as u8)
+        .collect();
+    assert_eq!(memory.load_bytes(addr - 4, 40).unwrap(), bytes);
+
+    let end = RISCV_MAX_MEMORY as u64;
+    assert_eq!(memory.load_bytes(end - 4, 4).unwrap(), vec![0; 4]);
+    assert_eq!(memory.load_bytes(end - 4, 8), Err(Error::OutOfBound));
+    assert_eq!(memory.load_bytes(end, 1), Err(Error::OutOfBound));
+    assert_eq!(
+        memory.load_bytes(u64::max_value(), 2),
+        Err(Error::OutOfBound)
+    );
+    // Sizes far beyond the memory fail instead of exhausting the host
+    assert_eq!(memory.load_bytes(0, 1 << 40), Err(Error::OutOfBound));
+}
+
+#[test]
+pub fn test_flat_memory_load_bytes() {
+    check_load_bytes(&mut FlatMemory::<u64>::default());
+
+    // Addresses beyond 4GB must not alias low memory on 32 bit hosts
+    let mut memory = FlatMemory::<u64>::default();
+    memory.store64(&0x10, &0x0102030405060708).unwrap();
+    let high = 0x1_0000_0010u64;
+    assert_eq!(memory.load8(&high), Err(Error::OutOfBound));
+    assert_eq!(memory.load64(&high), Err(Error::OutOfBound));
+    assert_eq!(memory.store64(&high, &0), Err(Error::OutOfBound));
+    assert_eq!(memory.store_bytes(high, &[1, 2]), Err(Error::OutOfBound));
+    assert_eq!(memory.load_bytes(high, 8), Err(Error::OutOfBound));
+    assert_eq!(memory.load64(&0x10).unwrap(), 0x0102030405060708);
+}
+
+#[test]
+pub fn test_sparse_memory_load_bytes() {
+    let mut memory = SparseMemory::<u64>::default();
+    // Reading untouched pages yields zeros
+    assert_eq!(
+        memory.load_bytes(RISCV_PAGESIZE as u64 * 3 - 2, 4).unwrap(),
+        vec![0; 4]
+    );
+    check_load_bytes(&mut memory);
+}
+
+#[test]
+pub fn test_wxorx_memory_load_bytes() {
+    check_load_bytes(&mut WXorXMemory::<u64, SparseMemory<u64>>::default());
+    check_load_bytes(&mut WXorXMemory::<u64, FlatMemory<u64>>::default());
+}
+
+#[test]
+pub fn test_cow_memory_load_bytes() {
+    check_load_bytes(&mut CowMemory::<u64>::default());
+    check_load_bytes(&mut CowMemory::<u64>::new(Arc::from(vec![0xAA; 100])));
+}
+
+fn check_watchpoints<M: Memory<u64>>(memory: &mut M) {
+    let addr = 0x1000;
+    let triggered = Err(Error::Watchpoint { addr: 0xffe, pc: 0 });
+    memory
+        .add_watchpoint(addr, 4, WatchpointKind::Write)
+        .unwrap();
+    // Reads and accesses outside the range are not watched
+    assert!(memory.load32(&addr).is_ok());
+    assert!(memory.store32(&(addr + 4), &1).is_ok());
+    assert!(memory.store16(&(addr - 2), &1).is_ok());
+    // Partially overlapping accesses trigger
+    assert_eq!(memory.store32(&0xffe, &1), triggered);
+    // The watchpoint fires before memory is modified
+    assert_eq!(memory.load16(&0xffe).unwrap(), 1);
+    // Bulk operations are meant for the host and are not watched
+    assert!(memory.store_bytes(addr, &[1, 2, 3, 4]).is_ok());
+    assert!(memory.load_bytes(addr, 4).is_ok());
+
+    memory
+        .add_watchpoint(addr, 4, WatchpointKind::Read)
+        .unwrap();
+    assert_eq!(
+        memory.load8(&(addr + 3)),
+        Err(Error::Watchpoint {
+            addr: addr + 3,
+            pc: 0
+        })
+    );
+    memory
+        .remove_watchpoint(addr, 4, WatchpointKind::Write)
+        .unwrap();
+    assert!(memory.store64(&addr, &0).is_ok());
+    memory
+        .remove_watchpoint(addr, 4, WatchpointKind::Read)
+        .unwrap();
+    assert!(memory.load64(&addr).is_ok());
+
+    memory
+        .add_watchpoint(addr, 1, WatchpointKind::Access)
+        .unwrap();
+    assert!(memory.load8(&addr).is_err());
+    assert!(memory.store8(&addr, &0).is_err());
+    assert_eq!(
+        memory.add_watchpoint(u64::MAX, 2, WatchpointKind::Access),
+        Err(Error::OutOfBound)
+    );
+}
+
+#[test]
+pub fn test_memory_watchpoints() {
+    check_watchpoints(&mut FlatMemory::<u64>::default());
+    check_watchpoints(&mut SparseMemory::<u64>::default());
+    check_watchpoints(&mut PagedMemory::<u64>::default());
+    check_watchpoints(&mut CowMemory::<u64>::default());
+    check_watchpoints(&mut WXorXMemory::<u64, SparseMemory<u64>>::default());
+
+    // Instruction fetches are not data reads
+    let mut memory = FlatMemory::<u64>::default();
+    memory.store16(&0x1000, &0x0201).unwrap();
+    memory
+        .add_watchpoint(0x1000, 2, WatchpointKind::Read)
+        .unwrap();
+    assert_eq!(memory.execute_load16(0x1000).unwrap(), 0x0201);
+    assert!(memory.load16(&0x1000).is_err());
+}
+
+#[test]
+pub fn test_cow_memory_shares_image() {
+    let mut image = vec![0; RISCV_PAGESIZE * 2];
+    image[0] = 1;
+    image[RISCV_PAGESIZE] = 2;
+    let image: Arc<[u8]> = Arc::from(image);
+    let mut first = CowMemory::<u64>::new(Arc::clone(&image));
+    let mut second = CowMemory::<u64>::new(Arc::clone(&image));
+
+    assert_eq!(first.load8(&0).unwrap(), 1);
+    assert_eq!(first.load8(&(RISCV_PAGESIZE as u64)).unwrap(), 2);
+    assert_eq!(first.private_pages(), 0);
+
+    // Only the written page is copied, other instances keep seeing the
+    // shared image.
+    first.store8(&1, &3).unwrap();
+    assert_eq!(first.private_pages(), 1);
+    assert_eq!(first.load16(&0).unwrap(), 0x0301);
+    assert_eq!(second.load16(&0).unwrap(), 0x0001);
+    assert_eq!(first.load8(&(RISCV_PAGESIZE as u64)).unwrap(), 2);
+    assert_eq!(image[1], 0);
+
+    // Writing data identical to the image does not allocate
+    second.store_bytes(0, &[1, 0, 0, 0]).unwrap();
+    second.store_byte(8, 16, 0).unwrap();
+    second.store_byte(RISCV_PAGESIZE as u64 * 3, 16, 0).unwrap();
+    assert_eq!(second.private_pages(), 0);
+
+    // Writes crossing into pages beyond the image
+    second
+        .store64(&(RISCV_PAGESIZE as u64 * 2 - 4), &0x1122_3344_5566_7788)
+        .unwrap();
+    assert_eq!(second.private_pages(), 2);
+    assert_eq!(
+        second.load64(&(RISCV_PAGESIZE as u64 * 2 - 4)).unwrap(),
+        0x1122_3344_5566_7788
+    );
+    assert_eq!(second.load8(&(RISCV_PAGESIZE as u64)).unwrap(), 2);
+    assert_eq!(
+        second.store8(&(RISCV_MAX_MEMORY as u64), &1),
+        Err(Error::OutOfBound)
+    );
+}
+
+#[test]
+pub fn test_cow_memory_run_program() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+    let image = build_image::<u64>(&buffer).unwrap();
+
+    for _ in 0..2 {
+        let memory = WXorXMemory::new(CowMemory::<u64>::new(Arc::clone(&image)));
+        let core_machine = DefaultCoreMachine::new_with_memory(memory, 0);
+        let mut machine =
+            DefaultMachineBuilder::<DefaultCoreMachine<u64, _>>::new(core_machine).build();
+        machine
+            .load_program(&buffer, &vec!["simple".into()])
+            .unwrap();
+        // Loading the program the image is built from leaves all program
+        // pages shared, only the stack page holding arguments is private.
+        let private_pages = machine.memory().inner().private_pages();
+        assert_eq!(private_pages, 1);
+        assert_eq!(machine.run().unwrap(), 0);
+    }
+    assert_eq!(Arc::strong_count(&image), 1);
+}
+
+#[test]
+pub fn test_memory_size() {
+    let size = RISCV_MAX_MEMORY * 2;
+    let mut flat = FlatMemory::<u64>::new_with_memory_size(size).unwrap();
+    let mut sparse = SparseMemory::<u64>::new_with_memory_size(size).unwrap();
+    assert_eq!(Memory::<u64>::memory_size(&flat), size);
+    assert_eq!(Memory::<u64>::memory_size(&sparse), size);
+    for memory in &mut [&mut flat as &mut dyn Memory<u64>, &mut sparse] {
+        memory.store64(&(size as u64 - 8), &1).unwrap();
+        assert_eq!(memory.load64(&(size as u64 - 8)).unwrap(), 1);
+        assert_eq!(memory.store8(&(size as u64), &1), Err(Error::OutOfBound));
+        assert_eq!(memory.fetch_flag((size / RISCV_PAGESIZE) as u64 - 1), Ok(0));
+        assert_eq!(
+            memory.fetch_flag((size / RISCV_PAGESIZE) as u64),
+            Err(Error::OutOfBound)
+        );
+    }
+
+    let mut wxorx = WXorXMemory::new(SparseMemory::<u64>::default());
+    assert_eq!(wxorx.memory_size(), RISCV_MAX_MEMORY);
+    wxorx.set_memory_size(size).unwrap();
+    assert_eq!(wxorx.memory_size(), size);
+    assert_eq!(wxorx.fetch_flag((size / RISCV_PAGESIZE) as u64 - 1), Ok(0));
+
+    for invalid in &[0, RISCV_MAX_MEMORY + RISCV_PAGESIZE, RISCV_PAGESIZE] {
+        assert_eq!(
+            SparseMemory::<u64>::new_with_memory_size(*invalid).err(),
+            Some(Error::InvalidMemorySize(*invalid as u64))
+        );
+        assert_eq!(
+            FlatMemory::<u64>::new_with_memory_size(*invalid).err(),
+            Some(Error::InvalidMemorySize(*invalid as u64))
+        );
+    }
+    // Exceeds the number of pages that can be indexed
+    assert!(SparseMemory::<u64>::new_with_memory_size(1 << 28).is_err());
+    // Fixed size memory
+    let mut cow = CowMemory::<u64>::default();
+    assert!(Memory::<u64>::set_memory_size(&mut cow, RISCV_MAX_MEMORY).is_ok());
+    assert!(Memory::<u64>::set_memory_size(&mut cow, size).is_err());
+}
+
+#[test]
+pub fn test_paged_memory() {
+    check_load_bytes(&mut PagedMemory::<u64>::default());
+
+    let size = 1 << 33;
+    let mut memory = PagedMemory::<u64>::new_with_memory_size(size).unwrap();
+    assert_eq!(Memory::<u64>::memory_size(&memory), size);
+    // Reads do not allocate anything
+    assert_eq!(memory.load64(&(size as u64 - 8)).unwrap(), 0);
+    assert_eq!(memory.load_bytes(0x1_0000_0000, 16).unwrap(), vec![0; 16]);
+    assert_eq!(memory.touched_memory(), 0);
+
+    // Crosses both a page and a page table boundary
+    let addr = 0x1_4020_0000 - 4;
+    memory.store64(&addr, &0x0807060504030201).unwrap();
+    assert_eq!(memory.load64(&addr).unwrap(), 0x0807060504030201);
+    assert_eq!(
+        memory.load_bytes(addr, 8).unwrap(),
+        vec![1, 2, 3, 4, 5, 6, 7, 8]
+    );
+    memory.store_byte(size as u64 - 16, 16, 0xff).unwrap();
+    assert_eq!(memory.load8(&(size as u64 - 1)).unwrap(), 0xff);
+    assert_eq!(memory.touched_memory(), 3 * RISCV_PAGESIZE);
+    assert_eq!(
+        memory.dirty_pages().unwrap(),
+        vec![
+            (addr / RISCV_PAGESIZE as u64),
+            (addr / RISCV_PAGESIZE as u64) + 1,
+            (size / RISCV_PAGESIZE) as u64 - 1
+        ]
+    );
+
+    assert_eq!(memory.store8(&(size as u64), &1), Err(Error::OutOfBound));
+    assert_eq!(
+        memory.store_bytes(size as u64 - 2, &[1, 2, 3]),
+        Err(Error::OutOfBound)
+    );
+    assert_eq!(memory.load8(&(size as u64)), Err(Error::OutOfBound));
+    assert_eq!(memory.fetch_flag((size / RISCV_PAGESIZE) as u64 - 1), Ok(0));
+
+    memory.reset().unwrap();
+    assert_eq!(memory.touched_memory(), 0);
+    assert_eq!(memory.load64(&addr).unwrap(), 0);
+
+    assert!(PagedMemory::<u64>::new_with_memory_size(3 << 30).is_err());
+    assert!(PagedMemory::<u64>::new_with_memory_size(1 << 40).is_err());
+}
+
+#[test]
+pub fn test_paged_memory_program_run() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    // The stack ends up at the top of a 4GB address space
+    let memory = WXorXMemory::new(PagedMemory::<u64>::new_with_memory_size(1 << 32).unwrap());
+    let core_machine = DefaultCoreMachine::new_with_memory(memory, 0);
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, _>>::new(core_machine).build();
+    machine.load_program(&buffer, &["simple".into()]).unwrap();
+    assert_eq!(machine.run().unwrap(), 0);
+}
+
+#[test]
+pub fn test_lazy_memory_load_bytes() {
+    check_load_bytes(&mut LazyMemory::<u64>::default());
+}
+
+#[test]
+pub fn test_lazy_memory_page_source() {
+    let source = |addr: u64, page: &mut Page| {
+        if addr == RISCV_PAGESIZE as u64 * 2 {
+            return Err(Error::Unexpected);
+        }
+        page[0] = (addr / RISCV_PAGESIZE as u64) as u8 + 1;
+        Ok(())
+    };
+    let mut memory = LazyMemory::<u64>::new_with_source(Box::new(source));
+    assert_eq!(memory.loaded_pages(), 0);
+    // Segments are applied on top of the page source once pages are used
+    memory
+        .init_pages(
+            RISCV_PAGESIZE as u64 + 8,
+            8,
+            0,
+            Some(Bytes::from(vec![0xAA; 4])),
+            2,
+        )
+        .unwrap();
+    assert_eq!(memory.loaded_pages(), 0);
+    assert_eq!(memory.load8(&0).unwrap(), 1);
+    assert_eq!(
+        memory.load_bytes(RISCV_PAGESIZE as u64, 16).unwrap(),
+        vec![2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xAA, 0xAA, 0xAA, 0xAA, 0, 0]
+    );
+    assert_eq!(memory.loaded_pages(), 2);
+    assert_eq!(
+        memory.load8(&(RISCV_PAGESIZE as u64 * 2)),
+        Err(Error::Unexpected)
+    );
+    // Writes to loaded pages are kept, pages are only loaded once
+    memory.store8(&0, &7).unwrap();
+    assert_eq!(memory.load8(&0).unwrap(), 7);
+    assert_eq!(memory.loaded_pages(), 2);
+    assert_eq!(Memory::<u64>::touched_memory(&memory), RISCV_PAGESIZE * 2);
+}
+
+#[test]
+pub fn test_lazy_memory_run_program() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let memory = WXorXMemory::new(LazyMemory::<u64>::default());
+    let core_machine = DefaultCoreMachine::new_with_memory(memory, 0);
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, _>>::new(core_machine).build();
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    assert_eq!(machine.run().unwrap(), 0);
+    assert!(machine.memory().inner().loaded_pages() < RISCV_MAX_MEMORY / RISCV_PAGESIZE);
+}
+
+#[test]
+pub fn test_memory_dump_and_diff() {
+    let mut memory = SparseMemory::<u64>::default();
+    let addr = RISCV_PAGESIZE as u64 - 4;
+    let before = memory.dump_range(addr, 16).unwrap();
+    assert_eq!(before, vec![0; 16]);
+    assert!(diff(addr, &before, &before).is_empty());
+
+    memory.store32(&(addr + 2), &0x0000_1122).unwrap();
+    memory.store8(&(addr + 12), &0x33).unwrap();
+    let after = memory.dump_range(addr, 16).unwrap();
+    let changes = diff(addr, &before, &after);
+    assert_eq!(changes.pages, vec![0, 1]);
+    assert_eq!(
+        changes.ranges,
+        vec![
+            ChangedRange {
+                addr: addr + 2,
+                before: vec![0, 0],
+                after: vec![0x22, 0x11],
+            },
+            ChangedRange {
+                addr: addr + 12,
+                before: vec![0],
+                after: vec![0x33],
+            },
+        ]
+    );
+    assert_eq!(changes.changed_bytes(), 3);
+    assert_eq!(
+        memory.dump_range(RISCV_MAX_MEMORY as u64 - 4, 8),
+        Err(Error::OutOfBound)
+    );
+}
+
+#[test]
+pub fn test_memory_diff_program_run() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default().build();
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    let size = RISCV_MAX_MEMORY as u64;
+    let before = machine.memory_mut().dump_range(0, size).unwrap();
+    assert_eq!(machine.run().unwrap(), 0);
+    let after = machine.memory_mut().dump_range(0, size).unwrap();
+    // Locals of the program live on the stack
+    let changes = diff(0, &before, &after);
+    let stack_top_page = (RISCV_MAX_MEMORY / RISCV_PAGESIZE) as u64 - 1;
+    assert_eq!(changes.pages.last(), Some(&stack_top_page));
+    assert!(changes.changed_bytes() > 0);
+    assert!(changes.changed_bytes() < DEFAULT_STACK_SIZE);
+}
+
+fn check_dirty_pages<M: Memory<u64>>(memory: &mut M) {
+    memory.clear_dirty().unwrap();
+    assert_eq!(memory.dirty_pages().unwrap(), Vec::<u64>::new());
+    // Crosses a page boundary
+    memory
+        .store32(&(RISCV_PAGESIZE as u64 * 2 - 2), &0x1122_3344)
+        .unwrap();
+    memory.store8(&(RISCV_PAGESIZE as u64 * 70), &1).unwrap();
+    memory.load64(&(RISCV_PAGESIZE as u64 * 5)).unwrap();
+    assert_eq!(memory.dirty_pages().unwrap(), vec![1, 2, 70]);
+    // Failed writes leave no trace
+    assert!(memory.store8(&(RISCV_MAX_MEMORY as u64), &1).is_err());
+    memory.store_byte(0, 16, 0).unwrap();
+    assert_eq!(memory.dirty_pages().unwrap(), vec![0, 1, 2, 70]);
+
+    memory.clear_dirty().unwrap();
+    assert_eq!(memory.dirty_pages().unwrap(), Vec::<u64>::new());
+    memory
+        .store_bytes(RISCV_PAGESIZE as u64 * 3, &[1, 2])
+        .unwrap();
+    assert_eq!(memory.dirty_pages().unwrap(), vec![3]);
+}
+
+#[test]
+pub fn test_memory_dirty_pages() {
+    check_dirty_pages(&mut SparseMemory::<u64>::default());
+    check_dirty_pages(&mut PagedMemory::<u64>::default());
+    check_dirty_pages(&mut FlatMemory::<u64>::default());
+    check_dirty_pages(&mut LazyMemory::<u64>::default());
+    check_dirty_pages(&mut CowMemory::<u64>::default());
+}
+
+#[test]
+pub fn test_memory_dirty_pages_program_run() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let mut machine = DefaultMachineBuilder::<
+        DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>,
+    >::default()
+    .build();
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    // Loading the program writes its segments and the stack
+    assert!(!machine.memory().dirty_pages().unwrap().is_empty());
+    machine.memory_mut().clear_dirty().unwrap();
+    let before = machine
+        .memory_mut()
+        .dump_range(0, RISCV_MAX_MEMORY as u64)
+        .unwrap();
+    assert_eq!(machine.run().unwrap(), 0);
+    let after = machine
+        .memory_mut()
+        .dump_range(0, RISCV_MAX_MEMORY as u64)
+        .unwrap();
+    // Every page with changed content is reported as dirty
+    let dirty = machine.memory().dirty_pages().unwrap();
+    let changes = diff(0, &before, &after);
+    assert!(changes.pages.iter().all(|page| dirty.contains(page)));
+}
+
+#[test]
+pub fn test_memory_mprotect() {
+    let mut memory = WXorXMemory::<u64, SparseMemory<u64>>::default();
+    let page = RISCV_PAGESIZE as u64;
+    memory
+        .init_pages(page, page * 2, FLAG_WRITABLE, None, 0)
+        .unwrap();
+    memory.store_bytes(page, b"data").unwrap();
+
+    memory.mprotect(page, page, FLAG_EXECUTABLE).unwrap();
+    assert_eq!(memory.fetch_flag(1).unwrap(), FLAG_EXECUTABLE);
+    assert_eq!(memory.fetch_flag(2).unwrap(), FLAG_WRITABLE);
+    assert_eq!(
+        memory.store_bytes(page, b"code"),
+        Err(Error::MemoryProtection)
+    );
+    // Content is kept
+    assert_eq!(memory.load_bytes(page, 4).unwrap(), b"data");
+    assert_eq!(memory.protection_generation(), 0);
+
+    memory.mprotect(page, page, FLAG_WRITABLE).unwrap();
+    assert_eq!(memory.protection_generation(), 1);
+    memory.store_bytes(page, b"code").unwrap();
+
+    assert_eq!(memory.mprotect(page + 1, page, 0), Err(Error::Unaligned));
+    assert_eq!(
+        memory.mprotect(RISCV_MAX_MEMORY as u64, page, 0),
+        Err(Error::OutOfBound)
+    );
+    memory
+        .init_pages(page * 3, page, FLAG_EXECUTABLE | FLAG_FREEZED, None, 0)
+        .unwrap();
+    assert_eq!(
+        memory.mprotect(page * 3, page, FLAG_WRITABLE),
+        Err(Error::InvalidPermission)
+    );
+
+    let mut memory = SparseMemory::<u64>::new();
+    assert_eq!(
+        memory.mprotect(page, page, FLAG_EXECUTABLE),
+        Err(Error::Unimplemented)
+    );
+}
+
+fn check_reset<M: Memory<u64>>(memory: &mut M, initial: u8) {
+    memory
+        .init_pages(0x1000, 0x1000, FLAG_EXECUTABLE | FLAG_FREEZED, None, 0)
+        .unwrap();
+    memory.store_bytes(0x3000, &[1, 2, 3, 4]).unwrap();
+    memory
+        .add_watchpoint(0x3000, 4, WatchpointKind::Write)
+        .unwrap();
+    memory.reset().unwrap();
+    assert_eq!(memory.load_bytes(0x3000, 4).unwrap(), vec![initial; 4]);
+    assert_eq!(memory.fetch_flag(1).unwrap(), 0);
+    assert!(memory.store32(&0x3000, &0).is_ok());
+    memory.reset().unwrap();
+    assert_eq!(memory.dirty_pages().unwrap(), Vec::<u64>::new());
+    // Frozen pages are gone, so a program can be loaded again
+    memory
+        .init_pages(0x1000, 0x1000, FLAG_EXECUTABLE | FLAG_FREEZED, None, 0)
+        .unwrap();
+}
+
+#[test]
+pub fn test_memory_reset() {
+    check_reset(&mut FlatMemory::<u64>::default(), 0);
+    check_reset(&mut SparseMemory::<u64>::default(), 0);
+    check_reset(&mut LazyMemory::<u64>::default(), 0);
+    check_reset(&mut WXorXMemory::<u64, SparseMemory<u64>>::default(), 0);
+    // Copy-on-write memory goes back to its image
+    check_reset(
+        &mut CowMemory::<u64>::new(Arc::from(vec![0xAA; 0x4000])),
+        0xAA,
+    );
+}
+
+#[test]
+pub fn test_wxorx_code_generation() {
+    let mut memory = WXorXMemory::<u64, SparseMemory<u64>>::default();
+    memory
+        .init_pages(
+            0x1000,
+            0x1000,
+            FLAG_EXECUTABLE,
+            Some(Bytes::from(vec![0x13; 8])),
+            0,
+        )
+        .unwrap();
+    memory.set_allow_self_modifying_code(true);
+    let generation = memory.code_generation();
+    memory.execute_load16(0x1000).unwrap();
+
+    // Stores to pages no code has been fetched from keep cached code
+    memory.store64(&0x2000, &1).unwrap();
+    assert_eq!(memory.code_generation(), generation);
+    memory.store64(&0x1004, &1).unwrap();
+    assert_eq!(memory.code_generation(), generation + 1);
+    // Nothing is cached anymore until code is fetched again
+    memory.store64(&0x1004, &2).unwrap();
+    assert_eq!(memory.code_generation(), generation + 1);
+    memory.execute_load16(0x1000).unwrap();
+    memory.store_bytes(0x1000, &[0; 4]).unwrap();
+    assert_eq!(memory.code_generation(), generation + 2);
+
+    // Stores to code are rejected otherwise, so there is nothing to track
+    memory.set_allow_self_modifying_code(false);
+    let generation = memory.code_generation();
+    memory.execute_load16(0x1000).unwrap();
+    memory.store64(&0x2000, &1).unwrap();
+    assert_eq!(memory.code_generation(), generation);
+}
+
+#[test]
+pub fn test_flat_memory_high_watermark() {
+    let mut memory = FlatMemory::<u64>::new_with_memory_size(DEFAULT_STACK_SIZE).unwrap();
+    assert_eq!(memory.high_watermark(), 0);
+    memory.load8(&0x10).unwrap();
+    // Accesses crossing a page boundary count both pages
+    memory
+        .store64(&(RISCV_PAGESIZE as u64 * 3 - 4), &1)
+        .unwrap();
+    memory.store8(&0x20, &1).unwrap();
+    assert_eq!(memory.high_watermark(), RISCV_PAGESIZE * 3);
+    // Unlike dirty pages, the watermark survives clear_dirty
+    memory.clear_dirty().unwrap();
+    assert_eq!(memory.high_watermark(), RISCV_PAGESIZE * 3);
+    memory.reset().unwrap();
+    assert_eq!(memory.high_watermark(), 0);
+    assert_eq!(memory.touched_memory(), DEFAULT_STACK_SIZE);
+}
+
+fn check_byte_order<M: Memory<u64>>(memory: &mut M) {
+    // Guest memory is little-endian whatever the host byte order is
+    memory.store64(&0x1000, &0x0102_0304_0506_0708).unwrap();
+    assert_eq!(
+        memory.load_bytes(0x1000, 8).unwrap(),
+        vec![8, 7, 6, 5, 4, 3, 2, 1]
+    );
+    memory.store32(&0x1010, &0x0a0b_0c0d).unwrap();
+    memory.store16(&0x1014, &0x0e0f).unwrap();
+    assert_eq!(
+        memory.load_bytes(0x1010, 6).unwrap(),
+        vec![0x0d, 0x0c, 0x0b, 0x0a, 0x0f, 0x0e]
+    );
+
+    let bytes: Vec<u8> = (0x11..0x19).collect();
+    let mut le = [0; 8];
+    le.copy_from_slice(&bytes);
+    memory.store_bytes(0x1020, &bytes).unwrap();
+    assert_eq!(memory.load64(&0x1020).unwrap(), u64::from_le_bytes(le));
+    assert_eq!(memory.load32(&0x1020).unwrap(), 0x1413_1211);
+    assert_eq!(memory.load16(&0x1020).unwrap(), 0x1211);
+    // Data laid out by a big-endian producer reads back byte swapped
+    memory
+        .store_bytes(0x1030, &u64::from_le_bytes(le).to_be_bytes())
+        .unwrap();
+    assert_eq!(
+        memory.load64(&0x1030).unwrap(),
+        u64::from_le_bytes(le).swap_bytes()
+    );
+}
+
+#[test]
+pub fn test_memory_byte_order() {
+    check_byte_order(&mut FlatMemory::<u64>::default());
+    check_byte_order(&mut SparseMemory::<u64>::default());
+    check_byte_order(&mut LazyMemory::<u64>::default());
+    check_byte_order(&mut WXorXMemory::<u64, SparseMemory<u64>>::default());
+    check_byte_order(&mut CowMemory::<u64>::new(Arc::from(vec![0; 0x4000])));
+}
+
+#[test]
+pub fn test_map_host_slice() {
+    let data: Vec<u8> = (0..RISCV_PAGESIZE + 8).map(|i| i as u8).collect();
+    let data: Bytes = data.into();
+    let mut memory = WXorXMemory::new(SparseMemory::<u64>::new());
+    memory.store32(&0x0ffc, &0x11223344).unwrap();
+    memory.map_host_slice(0x1000, data.clone()).unwrap();
+    // The buffer is shared, not copied
+    assert_eq!(memory.inner_mut().touched_memory(), RISCV_PAGESIZE);
+
+    assert_eq!(memory.load64(&0x1008).unwrap(), 0x0f0e0d0c0b0a0908);
+    // Crosses from a regular page into the window
+    assert_eq!(memory.load64(&0x0ffc).unwrap(), 0x0302010011223344);
+    // Bytes past the buffer read as zeros up to the end of the page
+    assert_eq!(memory.load32(&0x2006).unwrap(), 0x0706);
+    assert_eq!(memory.load8(&0x2fff).unwrap(), 0);
+    assert_eq!(
+        memory.load_bytes(0x1ffe, 12).unwrap(),
+        [0xfe, 0xff, 0, 1, 2, 3, 4, 5, 6, 7, 0, 0]
+    );
+    assert_eq!(memory.store8(&0x1000, &0), Err(Error::MemoryProtection));
+    assert_eq!(memory.store64(&0x2ffc, &0), Err(Error::MemoryProtection));
+    assert_eq!(
+        memory.init_pages(0x2000, 0x1000, FLAG_WRITABLE, None, 0),
+        Err(Error::MemoryProtection)
+    );
+    assert_eq!(
+        memory.map_host_slice(0x2000, data.clone()),
+        Err(Error::InvalidPermission)
+    );
+
+    memory.unmap_host_slice(0x1000).unwrap();
+    assert_eq!(memory.unmap_host_slice(0x1000), Err(Error::OutOfBound));
+    assert_eq!(memory.load8(&0x1000).unwrap(), 0);
+    memory.store8(&0x1000, &1).unwrap();
+
+    // Other memory implementations do not support windows
+    let mut memory = FlatMemory::<u64>::default();
+    assert_eq!(
+        memory.map_host_slice(0x1000, data),
+        Err(Error::Unimplemented)
+    );
+}
+
+#[test]
+pub fn test_guarded_flat_memory() {
+    let guarded = || FlatMemory::<u64>::new_guarded(RISCV_MAX_MEMORY).unwrap();
+    assert!(guarded().is_guarded());
+    assert!(!FlatMemory::<u64>::default().is_guarded());
+    check_load_bytes(&mut guarded());
+    check_watchpoints(&mut guarded());
+    check_dirty_pages(&mut guarded());
+    check_reset(&mut guarded(), 0);
+    check_byte_order(&mut guarded());
+
+    let mut memory = guarded();
+    let end = RISCV_MAX_MEMORY as u64;
+    memory.store64(&(end - 8), &0x0102030405060708).unwrap();
+    assert_eq!(memory.load64(&(end - 8)).unwrap(), 0x0102030405060708);
+    // Accesses past the end are rejected before reaching the guard pages
+    assert_eq!(memory.load64(&(end - 4)), Err(Error::OutOfBound));
+    assert_eq!(memory.store32(&end, &1), Err(Error::OutOfBound));
+    assert_eq!(memory.load_bytes(u64::MAX, 2), Err(Error::OutOfBound));
+    assert_eq!(
+        FlatMemory::<u64>::new_guarded(3 << 20).err(),
+        Some(Error::InvalidMemorySize(3 << 20))
+    );
+    // Resizing keeps the memory guarded
+    memory.set_memory_size(RISCV_MAX_MEMORY / 2).unwrap();
+    assert!(memory.is_guarded());
+    assert_eq!(memory.memory_size(), RISCV_MAX_MEMORY / 2);
+
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+    let core = DefaultCoreMachine::<u64, FlatMemory<u64>>::new_with_memory(guarded(), u64::MAX);
+    let mut machine = DefaultMachineBuilder::new(core).build();
+    machine.load_program(&buffer, &["simple".into()]).unwrap();
+    assert_eq!(machine.run(), Ok(0));
+}
+
+#[cfg(feature = "mmu")]
+#[test]
+pub fn test_sv39_memory() {
+    use ckb_vm::memory::sv39::{Access, PTE_A, PTE_D, PTE_R, PTE_V, PTE_W, PTE_X, SATP_MODE_SV39};
+    use ckb_vm::{
+        decoder::build_imac_decoder,
+        registers::{A0, A1},
+        DefaultMachine, Sv39Memory,
+    };
+
+    let pte = |paddr: u64, flags: u64| ((paddr >> 12) << 10) | flags;
+    let mut machine =
+        DefaultMachine::<DefaultCoreMachine<u64, Sv39Memory<u64, SparseMemory<u64>>>>::default();
+    let memory = machine.memory_mut();
+    // Root table at 0x1000, VA 0x40000000 is handled by the table at
+    // 0x2000, whose first entry points to the leaf table at 0x3000 while
+    // the second one maps a 2MB superpage.
+    let entries = [
+        (0x1008, pte(0x2000, PTE_V)),
+        (0x2000, pte(0x3000, PTE_V)),
+        (0x2008, pte(0x200000, PTE_V | PTE_R | PTE_W)),
+        (0x3000, pte(0x10000, PTE_V | PTE_R | PTE_W)),
+        (0x3008, pte(0x11000, PTE_V | PTE_R)),
+        (0x3010, pte(0x12000, PTE_V | PTE_X)),
+    ];
+    for (addr, entry) in entries.iter() {
+        memory.store64(addr, entry).unwrap();
+    }
+    // addi a0, zero, 42; sd a0, 0(a1)
+    memory
+        .store_bytes(0x12000, &[0x13, 0x05, 0xa0, 0x02, 0x23, 0xb0, 0xa5, 0x00])
+        .unwrap();
+    memory.store_bytes(0x11000, &[5, 6, 7, 8]).unwrap();
+    assert_eq!(memory.set_satp(1 << 60), Err(Error::Unimplemented));
+    memory.set_satp((SATP_MODE_SV39 << 60) | 1).unwrap();
+
+    memory.store64(&0x4000_0008, &0x1122).unwrap();
+    assert_eq!(memory.inner_mut().load64(&0x10008).unwrap(), 0x1122);
+    let leaf = memory.inner_mut().load64(&0x3000).unwrap();
+    assert_eq!(leaf & (PTE_A | PTE_D), PTE_A | PTE_D);
+    assert_eq!(memory.translate(0x4023_4567, Access::Store), Ok(0x23_4567));
+    assert_eq!(
+        memory.store8(&0x4000_1000, &1),
+        Err(Error::PageFault {
+            addr: 0x4000_1000,
+            pc: 0
+        })
+    );
+    assert_eq!(
+        memory.load8(&0x4000_3000),
+        Err(Error::PageFault {
+            addr: 0x4000_3000,
+            pc: 0
+        })
+    );
+    assert!(memory.load8(&0x80_0000_0000).is_err());
+    // Accesses crossing pages are translated page by page
+    memory.store32(&0x4000_0ffc, &0x0403_0201).unwrap();
+    assert_eq!(memory.load64(&0x4000_0ffc).unwrap(), 0x0807_0605_0403_0201);
+    assert_eq!(
+        memory.load_bytes(0x4000_0ffc, 8).unwrap(),
+        [1, 2, 3, 4, 5, 6, 7, 8]
+    );
+    assert!(memory.store64(&0x4000_0ffc, &0).is_err());
+    // Oversized reads fail at the first page that cannot be read
+    assert_eq!(
+        memory.load_bytes(0x4000_0000, 1 << 40),
+        Err(Error::PageFault {
+            addr: 0x4000_2000,
+            pc: 0
+        })
+    );
+    assert_eq!(memory.load_bytes(u64::MAX, 2), Err(Error::OutOfBound));
+    let (hits, misses) = memory.tlb_stats();
+    assert!(hits > 0 && misses > 0);
+
+    // Translations stay cached until the TLB is flushed
+    memory
+        .inner_mut()
+        .store64(&0x3000, &pte(0x13000, PTE_V | PTE_R | PTE_W))
+        .unwrap();
+    assert_eq!(memory.load64(&0x4000_0008).unwrap(), 0x1122);
+    memory.flush_tlb();
+    assert_eq!(memory.load64(&0x4000_0008).unwrap(), 0);
+
+    let decoder = build_imac_decoder::<u64>();
+    machine.set_pc(0x4000_2000);
+    machine.set_register(A1, 0x4000_1000);
+    machine.step(&decoder).unwrap();
+    assert_eq!(machine.registers()[A0], 42);
+    assert_eq!(
+        machine.step(&decoder),
+        Err(Error::PageFault {
+            addr: 0x4000_1000,
+            pc: 0x4000_2004
+        })
+    );
+    // Code can only be fetched from executable pages
+    machine.set_pc(0x4000_0000);
+    assert!(machine.step(&decoder).is_err());
+    machine.reset().unwrap();
+    assert_eq!(machine.memory().satp(), 0);
+}
@@ -2,14 +2,35 @@ extern crate ckb_vm;
 
 use bytes::Bytes;
 use ckb_vm::{
-    registers::{A0, A1, A2, A3, A4, A5, A7},
-    run, Debugger, DefaultCoreMachine, DefaultMachine, DefaultMachineBuilder, Error, FlatMemory,
-    Register, SparseMemory, SupportMachine, Syscalls,
+    analysis::scan_program,
+    decoder::{build_imac_counter_decoder, build_imac_custom_decoder, build_imac_decoder, Decoder},
+    instructions::{custom, decode_rvc, disassemble, i, m, INSTRUCTION_OPCODE_NAMES},
+    memory::{Mapping, FLAG_EXECUTABLE, FLAG_WRITABLE},
+    registers::{A0, A1, A2, A3, A4, A5, A6, A7, SP},
+    run,
+    syscalls::{
+        debug::{DEBUG_WRITE_SYSCALL_NUMBER, STDERR_FILENO, STDOUT_FILENO},
+        heap::{
+            BRK_SYSCALL_NUMBER, MAP_ANONYMOUS, MAP_FIXED, MAP_PRIVATE, MMAP_SYSCALL_NUMBER,
+            MUNMAP_SYSCALL_NUMBER,
+        },
+        random::RANDOM_BYTES_SYSCALL_NUMBER,
+        read_arg_bytes, write_return_slice, ChannelBroker, ChannelSyscalls, HeapSyscalls,
+        RandomSyscall, RecordingSyscalls, ReplaySyscalls,
+    },
+    CommitmentGranularity, CoreMachine, Debugger, DecodedProgram, DefaultCoreMachine,
+    DefaultMachine, DefaultMachineBuilder, ElfSegmentError, Error, ExitReason, FlatMemory,
+    Instruction, InstructionRecording, InterruptHandle, Machine, MachinePool, Memory, Register,
+    RunState, SparseMemory, SupportMachine, SyscallRecord, Syscalls, TraceMachine, WXorXMemory,
 };
+use std::collections::hash_map::DefaultHasher;
 use std::fs::File;
+use std::hash::Hasher;
 use std::io::Read;
-use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
 use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
 #[test]
 pub fn test_andi() {
@@ -77,165 +98,2859 @@ pub fn test_custom_syscall() {
     assert_eq!(result.unwrap(), 39);
 }
 
-pub struct CustomDebugger {
-    pub value: Arc<AtomicU8>,
+#[test]
+pub fn test_syscall_log() {
+    let mut file = File::open("tests/programs/syscall64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default()
+            .instruction_cycle_func(Box::new(|_| 1))
+            .syscall(Box::new(CustomSyscall {}))
+            .syscall_log(true)
+            .build();
+    machine
+        .load_program(&buffer, &vec!["syscall".into()])
+        .unwrap();
+    assert_eq!(machine.run(), Ok(39));
+    assert_eq!(
+        machine.syscall_log().unwrap(),
+        &[
+            SyscallRecord {
+                number: 1111,
+                args: [4, 5, 6, 7, 8, 9],
+                result: Ok(39),
+                cycles: 8,
+            },
+            SyscallRecord {
+                number: 93,
+                args: [39, 5, 6, 7, 8, 9],
+                result: Ok(39),
+                cycles: 10,
+            },
+        ]
+    );
+    assert_eq!(machine.take_syscall_log().unwrap().len(), 2);
+    assert_eq!(machine.syscall_log().unwrap().len(), 0);
+
+    // Failed syscalls are recorded with the error
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default()
+            .syscall_log(true)
+            .build();
+    machine
+        .load_program(&buffer, &vec!["syscall".into()])
+        .unwrap();
+    assert_eq!(machine.run(), Err(Error::InvalidEcall(1111)));
+    let log = machine.syscall_log().unwrap();
+    assert_eq!(log.len(), 1);
+    assert_eq!(log[0].result, Err(Error::InvalidEcall(1111)));
+
+    machine.set_syscall_log(false);
+    assert!(machine.syscall_log().is_none());
 }
 
-impl<Mac: SupportMachine> Debugger<Mac> for CustomDebugger {
+pub struct ConstantSyscall {
+    pub value: u64,
+}
+
+impl<Mac: SupportMachine> Syscalls<Mac> for ConstantSyscall {
     fn initialize(&mut self, _machine: &mut Mac) -> Result<(), Error> {
-        self.value.store(1, Ordering::Relaxed);
         Ok(())
     }
 
-    fn ebreak(&mut self, _machine: &mut Mac) -> Result<(), Error> {
-        self.value.store(2, Ordering::Relaxed);
-        Ok(())
+    fn ecall(&mut self, machine: &mut Mac) -> Result<bool, Error> {
+        if machine.registers()[A7].to_i32() != 1111 {
+            return Ok(false);
+        }
+        machine.set_register(A0, Mac::REG::from_u64(self.value));
+        Ok(true)
     }
 }
 
 #[test]
-pub fn test_ebreak() {
-    let mut file = File::open("tests/programs/ebreak64").unwrap();
+pub fn test_add_syscall() {
+    let mut file = File::open("tests/programs/syscall64").unwrap();
     let mut buffer = Vec::new();
     file.read_to_end(&mut buffer).unwrap();
     let buffer: Bytes = buffer.into();
 
-    let value = Arc::new(AtomicU8::new(0));
-    let mut machine =
-        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default()
-            .debugger(Box::new(CustomDebugger {
-                value: Arc::clone(&value),
-            }))
-            .build();
+    let mut machine = TraceMachine::new(DefaultMachine::<
+        DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>,
+    >::default());
     machine
-        .load_program(&buffer, &vec!["ebreak".into()])
+        .machine
+        .add_syscall(Box::new(ConstantSyscall { value: 42 }));
+    machine.machine.add_syscall(Box::new(CustomSyscall {}));
+    machine
+        .load_program(&buffer, &vec!["syscall".into()])
         .unwrap();
-    assert_eq!(value.load(Ordering::Relaxed), 1);
     let result = machine.run();
     assert!(result.is_ok());
-    assert_eq!(value.load(Ordering::Relaxed), 2);
+    assert_eq!(result.unwrap(), 42);
 }
 
 #[test]
-pub fn test_trace() {
-    let mut file = File::open("tests/programs/trace64").unwrap();
+pub fn test_add_syscall_after_builder_syscalls() {
+    let mut file = File::open("tests/programs/syscall64").unwrap();
     let mut buffer = Vec::new();
     file.read_to_end(&mut buffer).unwrap();
     let buffer: Bytes = buffer.into();
 
-    let result = run::<u64, SparseMemory<u64>>(&buffer, &vec!["trace64".into()]);
-    assert!(result.is_err());
-    assert_eq!(result.err(), Some(Error::InvalidPermission));
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default()
+            .syscall(Box::new(CustomSyscall {}))
+            .build();
+    machine.add_syscall(Box::new(ConstantSyscall { value: 42 }));
+    machine
+        .load_program(&buffer, &vec!["syscall".into()])
+        .unwrap();
+    let result = machine.run();
+    assert!(result.is_ok());
+    assert_eq!(result.unwrap(), 39);
 }
 
 #[test]
-pub fn test_jump0() {
-    let mut file = File::open("tests/programs/jump0_64").unwrap();
+pub fn test_unhandled_syscall() {
+    let mut file = File::open("tests/programs/syscall64").unwrap();
     let mut buffer = Vec::new();
     file.read_to_end(&mut buffer).unwrap();
     let buffer: Bytes = buffer.into();
 
-    let result = run::<u64, SparseMemory<u64>>(&buffer, &vec!["jump0_64".into()]);
-    assert!(result.is_err());
-    assert_eq!(result.err(), Some(Error::InvalidPermission));
+    let mut machine = DefaultMachine::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default();
+    machine
+        .load_program(&buffer, &vec!["syscall".into()])
+        .unwrap();
+    let result = machine.run();
+    assert_eq!(result.err(), Some(Error::InvalidEcall(1111)));
 }
 
 #[test]
-pub fn test_misaligned_jump64() {
-    let mut file = File::open("tests/programs/misaligned_jump64").unwrap();
+pub fn test_syscall_registry() {
+    let mut file = File::open("tests/programs/syscall64").unwrap();
     let mut buffer = Vec::new();
     file.read_to_end(&mut buffer).unwrap();
     let buffer: Bytes = buffer.into();
 
-    let result = run::<u64, SparseMemory<u64>>(&buffer, &vec!["misaligned_jump64".into()]);
-    assert!(result.is_ok());
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default()
+            .syscall(Box::new(CustomSyscall {}))
+            .syscall_handler(
+                1111,
+                Box::new(|machine: &mut DefaultCoreMachine<u64, SparseMemory<u64>>| {
+                    machine.set_register(A0, 7);
+                    Ok(())
+                }),
+            )
+            .build();
+    machine
+        .load_program(&buffer, &vec!["syscall".into()])
+        .unwrap();
+    // Handlers registered by number are consulted before Syscalls modules
+    assert_eq!(machine.run().unwrap(), 7);
+
+    let previous = machine.syscall_registry_mut().register(
+        1111,
+        Box::new(|machine: &mut DefaultCoreMachine<u64, SparseMemory<u64>>| {
+            machine.set_register(A0, 8);
+            Ok(())
+        }),
+    );
+    assert!(previous.is_some());
+    machine
+        .load_program(&buffer, &vec!["syscall".into()])
+        .unwrap();
+    assert_eq!(machine.run().unwrap(), 8);
+
+    assert!(machine.syscall_registry_mut().remove(1111).is_some());
+    assert!(!machine.syscall_registry().contains(1111));
+    machine
+        .load_program(&buffer, &vec!["syscall".into()])
+        .unwrap();
+    assert_eq!(machine.run().unwrap(), 39);
 }
 
 #[test]
-pub fn test_mulw64() {
-    let mut file = File::open("tests/programs/mulw64").unwrap();
+pub fn test_syscall_registry_fallback() {
+    let mut file = File::open("tests/programs/syscall64").unwrap();
     let mut buffer = Vec::new();
     file.read_to_end(&mut buffer).unwrap();
     let buffer: Bytes = buffer.into();
 
-    let result = run::<u64, SparseMemory<u64>>(&buffer, &vec!["mulw64".into()]);
-    assert!(result.is_ok());
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default()
+            .syscall_fallback(Box::new(
+                |machine: &mut DefaultCoreMachine<u64, SparseMemory<u64>>, number| {
+                    machine.set_register(A0, number - 1000);
+                    Ok(number == 1111)
+                },
+            ))
+            .build();
+    machine
+        .load_program(&buffer, &vec!["syscall".into()])
+        .unwrap();
+    assert_eq!(machine.run().unwrap(), 111);
+
+    assert!(machine.syscall_registry_mut().remove_fallback().is_some());
+    machine
+        .load_program(&buffer, &vec!["syscall".into()])
+        .unwrap();
+    assert_eq!(machine.run().err(), Some(Error::InvalidEcall(1111)));
 }
 
 #[test]
-pub fn test_invalid_file_offset64() {
-    let mut file = File::open("tests/programs/invalid_file_offset64").unwrap();
+pub fn test_syscall_cycles() {
+    let mut file = File::open("tests/programs/syscall64").unwrap();
     let mut buffer = Vec::new();
     file.read_to_end(&mut buffer).unwrap();
     let buffer: Bytes = buffer.into();
 
-    let result = run::<u64, SparseMemory<u64>>(&buffer, &vec!["invalid_file_offset64".into()]);
-    assert_eq!(result.err(), Some(Error::OutOfBound));
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default()
+            .syscall(Box::new(CustomSyscall {}))
+            .syscall_cycles(1111, 500)
+            .syscall_cycles(93, 1)
+            .build();
+    machine
+        .load_program(&buffer, &vec!["syscall".into()])
+        .unwrap();
+    // Syscalls are charged no matter how they are processed
+    assert_eq!(machine.run().unwrap(), 39);
+    assert_eq!(machine.cycles(), 501);
+    assert_eq!(machine.syscall_registry().cycles(1111), 500);
+
+    assert_eq!(machine.syscall_registry_mut().remove_cycles(93), Some(1));
+    assert_eq!(
+        machine.syscall_registry_mut().set_cycles(1111, 1000),
+        Some(500)
+    );
+    machine.set_cycles(0);
+    machine.set_max_cycles(999);
+    machine
+        .load_program(&buffer, &vec!["syscall".into()])
+        .unwrap();
+    assert_eq!(machine.run().err(), Some(Error::CyclesExceeded));
+    // The syscall is not processed when cycles are exhausted
+    assert_eq!(machine.registers()[A0], 4);
 }
 
 #[test]
-pub fn test_op_rvc_srli_crash_32() {
-    let mut file = File::open("tests/programs/op_rvc_srli_crash_32").unwrap();
+pub fn test_pause_on_limit() {
+    let mut file = File::open("tests/programs/syscall64").unwrap();
     let mut buffer = Vec::new();
     file.read_to_end(&mut buffer).unwrap();
     let buffer: Bytes = buffer.into();
 
-    let result = run::<u32, SparseMemory<u32>>(&buffer, &vec!["op_rvc_srli_crash_32".into()]);
-    assert_eq!(result.err(), Some(Error::InvalidPermission));
+    let build = |hooked: Arc<AtomicU64>| {
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default()
+            .instruction_cycle_func(Box::new(|_| 1))
+            .syscall(Box::new(CustomSyscall {}))
+            .syscall_cycles(1111, 500)
+            .pause_on_limit(true)
+            .opcode_stats(true)
+            .on_instruction(Box::new(move |_, _, _| {
+                hooked.fetch_add(1, Ordering::SeqCst);
+            }))
+            .build()
+    };
+    let hooked = Arc::new(AtomicU64::new(0));
+    let mut machine = build(Arc::clone(&hooked));
+    machine
+        .load_program(&buffer, &vec!["syscall".into()])
+        .unwrap();
+    assert_eq!(machine.run_resumable().unwrap(), RunState::Exited(39));
+    let total_cycles = machine.cycles();
+    let total_instructions = machine.instructions();
+    let total_stats = machine.opcode_stats().unwrap().to_vec();
+    assert_eq!(hooked.load(Ordering::SeqCst), total_instructions);
+
+    let paused_hooked = Arc::new(AtomicU64::new(0));
+    let mut machine = build(Arc::clone(&paused_hooked));
+    machine
+        .load_program(&buffer, &vec!["syscall".into()])
+        .unwrap();
+    let mut pauses = 0;
+    let mut max_cycles = 0;
+    let exit_code = loop {
+        // Small top ups pause the machine on the syscall several times
+        max_cycles += 100;
+        machine.set_max_cycles(max_cycles);
+        match machine.run_resumable().unwrap() {
+            RunState::Paused => {
+                assert!(machine.cycles() <= max_cycles);
+                pauses += 1;
+            }
+            RunState::Exited(exit_code) => break exit_code,
+            RunState::Yielded => unreachable!(),
+        }
+    };
+    assert_eq!(exit_code, 39);
+    assert!(pauses >= 5);
+    assert_eq!(machine.cycles(), total_cycles);
+    // The paused ecall is counted and hooked once
+    assert_eq!(machine.instructions(), total_instructions);
+    assert_eq!(machine.opcode_stats().unwrap(), &total_stats[..]);
+    assert_eq!(paused_hooked.load(Ordering::SeqCst), total_instructions);
+
+    // Without pausing, the limit is an error
+    let mut machine = build(Arc::new(AtomicU64::new(0)));
+    machine.set_pause_on_limit(false);
+    machine.set_max_cycles(100);
+    machine
+        .load_program(&buffer, &vec!["syscall".into()])
+        .unwrap();
+    assert_eq!(machine.run_resumable(), Err(Error::CyclesExceeded));
+}
+
+fn heap_syscall(
+    machine: &mut DefaultMachine<DefaultCoreMachine<u64, SparseMemory<u64>>>,
+    number: u64,
+    args: &[u64],
+) -> u64 {
+    for (i, arg) in args.iter().enumerate() {
+        machine.set_register(A0 + i, *arg);
+    }
+    machine.set_register(A7, number);
+    machine.ecall().unwrap();
+    machine.registers()[A0]
 }
 
 #[test]
-pub fn test_op_rvc_srai_crash_32() {
-    let mut file = File::open("tests/programs/op_rvc_srai_crash_32").unwrap();
+pub fn test_heap_syscalls() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
     let mut buffer = Vec::new();
     file.read_to_end(&mut buffer).unwrap();
     let buffer: Bytes = buffer.into();
 
-    let result = run::<u32, SparseMemory<u32>>(&buffer, &vec!["op_rvc_srai_crash_32".into()]);
-    assert!(result.is_ok());
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default()
+            .syscall(Box::new(HeapSyscalls::for_program(&buffer, 0).unwrap()))
+            .build();
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+
+    let heap_start = heap_syscall(&mut machine, BRK_SYSCALL_NUMBER, &[0]);
+    assert!(heap_start > 0);
+    assert_eq!(heap_start % 4096, 0);
+    assert_eq!(
+        heap_syscall(&mut machine, BRK_SYSCALL_NUMBER, &[heap_start + 0x2000]),
+        heap_start + 0x2000
+    );
+    machine
+        .memory_mut()
+        .store_bytes(heap_start, &[1, 2, 3])
+        .unwrap();
+    // Released memory is zeroed
+    assert_eq!(
+        heap_syscall(&mut machine, BRK_SYSCALL_NUMBER, &[heap_start]),
+        heap_start
+    );
+    assert_eq!(
+        heap_syscall(&mut machine, BRK_SYSCALL_NUMBER, &[heap_start + 0x10]),
+        heap_start + 0x10
+    );
+    assert_eq!(
+        machine.memory_mut().load_bytes(heap_start, 3).unwrap(),
+        vec![0, 0, 0]
+    );
+
+    let stack_start = (ckb_vm::RISCV_MAX_MEMORY - ckb_vm::DEFAULT_STACK_SIZE) as u64;
+    let anonymous = MAP_PRIVATE | MAP_ANONYMOUS;
+    let first = heap_syscall(
+        &mut machine,
+        MMAP_SYSCALL_NUMBER,
+        &[0, 0x1800, 3, anonymous],
+    );
+    assert_eq!(first, stack_start - 0x2000);
+    let second = heap_syscall(
+        &mut machine,
+        MMAP_SYSCALL_NUMBER,
+        &[0, 0x1000, 3, anonymous],
+    );
+    assert_eq!(second, first - 0x1000);
+    machine.memory_mut().store_bytes(first, &[4, 5]).unwrap();
+    assert_eq!(
+        heap_syscall(&mut machine, MUNMAP_SYSCALL_NUMBER, &[first, 0x2000]),
+        0
+    );
+    assert_eq!(
+        heap_syscall(
+            &mut machine,
+            MMAP_SYSCALL_NUMBER,
+            &[0, 0x2000, 3, anonymous]
+        ),
+        first
+    );
+    assert_eq!(
+        machine.memory_mut().load_bytes(first, 2).unwrap(),
+        vec![0, 0]
+    );
+
+    // The break cannot grow into mappings
+    assert_eq!(
+        heap_syscall(&mut machine, BRK_SYSCALL_NUMBER, &[second + 1]),
+        heap_start + 0x10
+    );
+    assert_eq!(
+        heap_syscall(
+            &mut machine,
+            MMAP_SYSCALL_NUMBER,
+            &[0, 0x1000, 3, MAP_PRIVATE]
+        ) as i64,
+        -22
+    );
+    assert_eq!(
+        heap_syscall(&mut machine, MUNMAP_SYSCALL_NUMBER, &[first + 1, 0x1000]) as i64,
+        -22
+    );
+    assert_eq!(
+        heap_syscall(
+            &mut machine,
+            MMAP_SYSCALL_NUMBER,
+            &[0, 0x40_0000, 3, anonymous]
+        ) as i64,
+        -12
+    );
 }
 
 #[test]
-pub fn test_op_rvc_slli_crash_32() {
-    let mut file = File::open("tests/programs/op_rvc_slli_crash_32").unwrap();
+pub fn test_heap_mappings() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
     let mut buffer = Vec::new();
     file.read_to_end(&mut buffer).unwrap();
     let buffer: Bytes = buffer.into();
 
-    let result = run::<u32, SparseMemory<u32>>(&buffer, &vec!["op_rvc_slli_crash_32".into()]);
-    assert!(result.is_ok());
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default()
+            .syscall(Box::new(HeapSyscalls::for_program(&buffer, 0).unwrap()))
+            .build();
+    machine.load_program(&buffer, &["simple".into()]).unwrap();
+
+    let anonymous = MAP_PRIVATE | MAP_ANONYMOUS;
+    let first = heap_syscall(
+        &mut machine,
+        MMAP_SYSCALL_NUMBER,
+        &[0, 0x2000, 3, anonymous],
+    );
+    assert_eq!(
+        machine.memory().mappings(),
+        [Mapping {
+            start: first,
+            end: first + 0x2000,
+            prot: 3
+        }]
+    );
+
+    // MAP_FIXED replaces the pages it overlaps with zeroed ones
+    machine
+        .memory_mut()
+        .store_bytes(first + 0x1000, &[1, 2])
+        .unwrap();
+    assert_eq!(
+        heap_syscall(
+            &mut machine,
+            MMAP_SYSCALL_NUMBER,
+            &[first + 0x1000, 0x1000, 1, anonymous | MAP_FIXED]
+        ),
+        first + 0x1000
+    );
+    assert_eq!(
+        machine.memory_mut().load_bytes(first + 0x1000, 2).unwrap(),
+        [0, 0]
+    );
+    assert_eq!(
+        machine.memory().mappings(),
+        [
+            Mapping {
+                start: first,
+                end: first + 0x1000,
+                prot: 3
+            },
+            Mapping {
+                start: first + 0x1000,
+                end: first + 0x2000,
+                prot: 1
+            },
+        ]
+    );
+    assert_eq!(
+        heap_syscall(
+            &mut machine,
+            MMAP_SYSCALL_NUMBER,
+            &[first + 1, 0x1000, 3, anonymous | MAP_FIXED]
+        ) as i64,
+        -22
+    );
+
+    // Ranges mapped in memory by the host are not handed out
+    let hint = first - 0x1000;
+    machine
+        .memory_mut()
+        .map_region(hint, 0x1000, 1, false)
+        .unwrap();
+    assert_eq!(
+        heap_syscall(
+            &mut machine,
+            MMAP_SYSCALL_NUMBER,
+            &[0, 0x1000, 3, anonymous]
+        ) as i64,
+        -17
+    );
+    assert_eq!(machine.memory().mappings().len(), 3);
+
+    assert_eq!(
+        heap_syscall(&mut machine, MUNMAP_SYSCALL_NUMBER, &[hint, 0x2000]),
+        0
+    );
+    assert_eq!(
+        machine.memory().mappings(),
+        [Mapping {
+            start: first + 0x1000,
+            end: first + 0x2000,
+            prot: 1
+        }]
+    );
+    let memory_size = machine.memory().memory_size() as u64;
+    assert_eq!(
+        heap_syscall(&mut machine, MUNMAP_SYSCALL_NUMBER, &[memory_size, 0x1000]) as i64,
+        -22
+    );
+
+    // Loading a program again drops the mappings of the previous one
+    machine.load_program(&buffer, &["simple".into()]).unwrap();
+    assert!(machine.memory().mappings().is_empty());
 }
 
 #[test]
-pub fn test_load_elf_crash_64() {
-    let mut file = File::open("tests/programs/load_elf_crash_64").unwrap();
+pub fn test_heap_sanitizer() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
     let mut buffer = Vec::new();
     file.read_to_end(&mut buffer).unwrap();
     let buffer: Bytes = buffer.into();
 
-    let result = run::<u64, SparseMemory<u64>>(&buffer, &vec!["load_elf_crash_64".into()]);
-    assert_eq!(result.err(), Some(Error::InvalidPermission));
+    let heap = HeapSyscalls::for_program(&buffer, 0)
+        .unwrap()
+        .with_sanitizer(true);
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default()
+            .syscall(Box::new(heap))
+            .build();
+    machine.load_program(&buffer, &["simple".into()]).unwrap();
+
+    let heap_start = heap_syscall(&mut machine, BRK_SYSCALL_NUMBER, &[0]);
+    assert_eq!(
+        machine.memory_mut().load8(&heap_start),
+        Err(Error::InvalidHeapAccess {
+            addr: heap_start,
+            pc: 0
+        })
+    );
+    heap_syscall(&mut machine, BRK_SYSCALL_NUMBER, &[heap_start + 0x10]);
+    machine.memory_mut().store64(&(heap_start + 8), &1).unwrap();
+    assert!(machine.memory_mut().load8(&(heap_start + 0x10)).is_err());
+
+    let anonymous = MAP_PRIVATE | MAP_ANONYMOUS;
+    let mapping = heap_syscall(
+        &mut machine,
+        MMAP_SYSCALL_NUMBER,
+        &[0, 0x1000, 3, anonymous],
+    );
+    machine.memory_mut().store64(&mapping, &1).unwrap();
+    assert!(machine.memory_mut().load8(&(mapping - 1)).is_err());
+    assert_eq!(
+        heap_syscall(&mut machine, MUNMAP_SYSCALL_NUMBER, &[mapping, 0x1000]),
+        0
+    );
+
+    // Use after free is reported with the pc of the instruction
+    let code = 0x00053583u32.to_le_bytes(); // ld a1, 0(a0)
+    machine
+        .memory_mut()
+        .init_pages(
+            0x300000,
+            0x1000,
+            FLAG_EXECUTABLE,
+            Some(Bytes::from(code.to_vec())),
+            0,
+        )
+        .unwrap();
+    machine.set_register(A0, mapping);
+    machine.set_pc(0x300000);
+    assert_eq!(
+        machine.step(&build_imac_decoder::<u64>()),
+        Err(Error::InvalidHeapAccess {
+            addr: mapping,
+            pc: 0x300000
+        })
+    );
 }
 
 #[test]
-pub fn test_wxorx_crash_64() {
-    let mut file = File::open("tests/programs/wxorx_crash_64").unwrap();
+pub fn test_channel_syscalls() {
+    let ping: Vec<u32> = vec![
+        0x00002537, // lui a0, 0x2
+        0x00400593, // li a1, 4
+        0x000018b7, // lui a7, 0x1
+        0xbb988893, // addi a7, a7, -1095 (send)
+        0x00000073, // ecall
+        0x00002537, // lui a0, 0x2
+        0x10050513, // addi a0, a0, 0x100
+        0x01000593, // li a1, 16
+        0x00188893, // addi a7, a7, 1 (recv)
+        0x00000073, // ecall
+        0x05d00893, // li a7, 93
+        0x00000073, // ecall
+    ];
+    let echo: Vec<u32> = vec![
+        0x00002537, // lui a0, 0x2
+        0x01000593, // li a1, 16
+        0x000018b7, // lui a7, 0x1
+        0xbba88893, // addi a7, a7, -1094 (recv)
+        0x00000073, // ecall
+        0x00050593, // mv a1, a0
+        0x00002537, // lui a0, 0x2
+        0xfff88893, // addi a7, a7, -1 (send)
+        0x00000073, // ecall
+        0x05d00893, // li a7, 93
+        0x00000073, // ecall
+    ];
+    let build = |words: &[u32], channel: ChannelSyscalls| {
+        let code: Vec<u8> = words
+            .iter()
+            .flat_map(|w| w.to_le_bytes().to_vec())
+            .collect();
+        let mut machine =
+            DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default()
+                .syscall(Box::new(channel))
+                .yield_every(2)
+                .build();
+        machine
+            .memory_mut()
+            .init_pages(0x1000, 0x1000, FLAG_EXECUTABLE, Some(code.into()), 0)
+            .unwrap();
+        machine
+            .memory_mut()
+            .init_pages(0x2000, 0x1000, FLAG_WRITABLE, Some(Bytes::from("ping")), 0)
+            .unwrap();
+        machine.set_pc(0x1000);
+        machine
+    };
+
+    let mut broker = ChannelBroker::new(1, 16);
+    let (a, b) = broker.pair();
+    let mut pinger = build(&ping, a);
+    let mut echoer = build(&echo, b);
+    // The echoer blocks on its first receive until the ping is sent
+    assert_eq!(broker.run(&mut [&mut echoer, &mut pinger]), Ok(vec![0, 4]));
+    assert_eq!(
+        pinger.memory_mut().load_bytes(0x2100, 4).unwrap(),
+        b"ping".to_vec()
+    );
+    assert_eq!(pinger.instructions(), ping.len() as u64);
+    assert_eq!(broker.pending(), 0);
+
+    // Both ends waiting for the other one are reported as a deadlock
+    let (a, b) = broker.pair();
+    let mut first = build(&echo, a);
+    let mut second = build(&echo, b);
+    assert_eq!(
+        broker.run(&mut [&mut first, &mut second]),
+        Err(Error::WouldBlock)
+    );
+    assert_eq!(first.pc(), &0x1010);
+    assert_eq!(first.instructions(), 4);
+}
+
+fn random_bytes(
+    machine: &mut DefaultMachine<DefaultCoreMachine<u64, SparseMemory<u64>>>,
+) -> Vec<u8> {
+    machine.set_register(A0, 0x1000);
+    machine.set_register(A1, 13);
+    machine.set_register(A7, RANDOM_BYTES_SYSCALL_NUMBER);
+    machine.ecall().unwrap();
+    assert_eq!(machine.registers()[A0], 13);
+    machine.memory_mut().load_bytes(0x1000, 13).unwrap()
+}
+
+#[test]
+pub fn test_random_syscall() {
+    let mut file = File::open("tests/programs/syscall64").unwrap();
     let mut buffer = Vec::new();
     file.read_to_end(&mut buffer).unwrap();
     let buffer: Bytes = buffer.into();
 
-    let result = run::<u64, SparseMemory<u64>>(&buffer, &vec!["wxorx_crash_64".into()]);
-    assert_eq!(result.err(), Some(Error::OutOfBound));
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default()
+            .random_seed(42)
+            .build();
+    machine
+        .load_program(&buffer, &vec!["syscall".into()])
+        .unwrap();
+    let first = random_bytes(&mut machine);
+    let second = random_bytes(&mut machine);
+    assert_ne!(first, second);
+    assert_ne!(first, vec![0; 13]);
+
+    // Loading a program resets the generator
+    machine
+        .load_program(&buffer, &vec!["syscall".into()])
+        .unwrap();
+    assert_eq!(random_bytes(&mut machine), first);
+    let mut other = DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default()
+        .random_seed(43)
+        .build();
+    assert_ne!(random_bytes(&mut other), first);
+
+    // Not available unless enabled
+    let mut machine = DefaultMachine::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default();
+    machine.set_register(A7, RANDOM_BYTES_SYSCALL_NUMBER);
+    assert_eq!(
+        machine.ecall(),
+        Err(Error::InvalidEcall(RANDOM_BYTES_SYSCALL_NUMBER))
+    );
 }
 
 #[test]
-pub fn test_flat_crash_64() {
-    let mut file = File::open("tests/programs/flat_crash_64").unwrap();
+pub fn test_scan_program() {
+    let simple: Bytes = std::fs::read("tests/programs/simple64").unwrap().into();
+    let report = scan_program::<u64>(&simple, &build_imac_decoder::<u64>()).unwrap();
+    assert_eq!(report.entry, 0x100b0);
+    assert!(report.is_supported());
+    assert!(report.instructions > 0);
+    assert!(report.unsupported_indirect.is_empty());
+
+    // The first compressed instruction on the way is reported
+    let mut decoder = Decoder::default();
+    decoder.add_instruction_factory(i::factory::<u64>);
+    decoder.add_instruction_factory(m::factory::<u64>);
+    let report = scan_program::<u64>(&simple, &decoder).unwrap();
+    assert!(!report.is_supported());
+    assert_eq!(report.unsupported, [(0x100c0, 0x8e09)]);
+    assert!(report
+        .to_string()
+        .contains("unsupported instruction 0x8e09 at 0x100c0"));
+
+    // Nothing jumps to the code after an infinite loop
+    let jump0: Bytes = std::fs::read("tests/programs/jump0_64").unwrap().into();
+    let report = scan_program::<u64>(&jump0, &build_imac_decoder::<u64>()).unwrap();
+    assert_eq!(report.instructions, 1);
+    assert_eq!(report.indirect_only, [(0x1007c, 0x10086)]);
+
+    let simple32: Bytes = std::fs::read("tests/programs/simple").unwrap().into();
+    assert_eq!(
+        scan_program::<u64>(&simple32, &build_imac_decoder::<u64>()),
+        Err(Error::InvalidElfBits)
+    );
+    assert!(scan_program::<u32>(&simple32, &build_imac_decoder::<u32>())
+        .unwrap()
+        .is_supported());
+}
+
+#[test]
+pub fn test_debug_output_syscall() {
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+    {
+        let mut machine =
+            DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default()
+                .debug_output(
+                    DEBUG_WRITE_SYSCALL_NUMBER,
+                    Box::new(&mut stdout),
+                    Box::new(&mut stderr),
+                )
+                .build();
+        machine
+            .memory_mut()
+            .store_bytes(0x1000, b"hello\nworld\n")
+            .unwrap();
+        let mut write = |fd: u64, addr: u64, len: u64| {
+            machine.set_register(A0, fd);
+            machine.set_register(A1, addr);
+            machine.set_register(A2, len);
+            machine.set_register(A7, DEBUG_WRITE_SYSCALL_NUMBER);
+            machine.ecall().map(|_| machine.registers()[A0] as i64)
+        };
+        assert_eq!(write(STDOUT_FILENO, 0x1000, 6), Ok(6));
+        assert_eq!(write(STDERR_FILENO, 0x1006, 6), Ok(6));
+        assert_eq!(write(STDOUT_FILENO, 0x1000, 0), Ok(0));
+        // Only stdout and stderr are served
+        assert_eq!(write(3, 0x1000, 6), Ok(-9));
+        assert_eq!(
+            write(STDOUT_FILENO, ckb_vm::RISCV_MAX_MEMORY as u64 - 2, 4),
+            Err(Error::OutOfBound)
+        );
+    }
+    assert_eq!(stdout, b"hello\n");
+    assert_eq!(stderr, b"world\n");
+}
+
+pub struct CustomDebugger {
+    pub value: Arc<AtomicU8>,
+}
+
+impl<Mac: SupportMachine> Debugger<Mac> for CustomDebugger {
+    fn initialize(&mut self, _machine: &mut Mac) -> Result<(), Error> {
+        self.value.store(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn ebreak(&mut self, _machine: &mut Mac) -> Result<(), Error> {
+        self.value.store(2, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+#[test]
+pub fn test_ebreak() {
+    let mut file = File::open("tests/programs/ebreak64").unwrap();
     let mut buffer = Vec::new();
     file.read_to_end(&mut buffer).unwrap();
     let buffer: Bytes = buffer.into();
 
-    let mut machine = DefaultMachine::<DefaultCoreMachine<u64, FlatMemory<u64>>>::default();
-    let result = machine.load_program(&buffer, &vec!["flat_crash_64".into()]);
-    assert_eq!(result.err(), Some(Error::OutOfBound));
+    let value = Arc::new(AtomicU8::new(0));
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default()
+            .debugger(Box::new(CustomDebugger {
+                value: Arc::clone(&value),
+            }))
+            .build();
+    machine
+        .load_program(&buffer, &vec!["ebreak".into()])
+        .unwrap();
+    assert_eq!(value.load(Ordering::Relaxed), 1);
+    let result = machine.run();
+    assert!(result.is_ok());
+    assert_eq!(value.load(Ordering::Relaxed), 2);
+}
+
+#[test]
+pub fn test_trace() {
+    let mut file = File::open("tests/programs/trace64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let result = run::<u64, SparseMemory<u64>>(&buffer, &vec!["trace64".into()]);
+    assert!(result.is_err());
+    assert_eq!(result.err(), Some(Error::MemoryProtection));
+}
+
+#[test]
+pub fn test_trace_ways() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let mut results = vec![];
+    for ways in [1, 2, 4, 16] {
+        // A tiny cache makes traces compete for slots
+        let mut machine = TraceMachine::with_capacity(
+            DefaultMachineBuilder::<DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>>::default()
+                .build(),
+            16,
+            16,
+        );
+        machine.set_trace_ways(ways);
+        machine.load_program(&buffer, &["simple".into()]).unwrap();
+        assert_eq!(machine.run(), Ok(0));
+        results.push((machine.machine.cycles(), machine.machine.instructions()));
+    }
+    assert!(results.iter().all(|r| *r == results[0]));
+}
+
+#[test]
+pub fn test_jump0() {
+    let mut file = File::open("tests/programs/jump0_64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let result = run::<u64, SparseMemory<u64>>(&buffer, &vec!["jump0_64".into()]);
+    assert!(result.is_err());
+    assert_eq!(result.err(), Some(Error::MemoryProtection));
+}
+
+#[test]
+pub fn test_misaligned_jump64() {
+    let mut file = File::open("tests/programs/misaligned_jump64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let result = run::<u64, SparseMemory<u64>>(&buffer, &vec!["misaligned_jump64".into()]);
+    assert!(result.is_ok());
+}
+
+#[test]
+pub fn test_mulw64() {
+    let mut file = File::open("tests/programs/mulw64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let result = run::<u64, SparseMemory<u64>>(&buffer, &vec!["mulw64".into()]);
+    assert!(result.is_ok());
+}
+
+#[test]
+pub fn test_invalid_file_offset64() {
+    let mut file = File::open("tests/programs/invalid_file_offset64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let result = run::<u64, SparseMemory<u64>>(&buffer, &vec!["invalid_file_offset64".into()]);
+    assert_eq!(result.err(), Some(Error::OutOfBound));
+}
+
+#[test]
+pub fn test_op_rvc_srli_crash_32() {
+    let mut file = File::open("tests/programs/op_rvc_srli_crash_32").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let result = run::<u32, SparseMemory<u32>>(&buffer, &vec!["op_rvc_srli_crash_32".into()]);
+    assert_eq!(result.err(), Some(Error::MemoryProtection));
+}
+
+#[test]
+pub fn test_op_rvc_srai_crash_32() {
+    let mut file = File::open("tests/programs/op_rvc_srai_crash_32").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let result = run::<u32, SparseMemory<u32>>(&buffer, &vec!["op_rvc_srai_crash_32".into()]);
+    assert!(result.is_ok());
+}
+
+#[test]
+pub fn test_op_rvc_slli_crash_32() {
+    let mut file = File::open("tests/programs/op_rvc_slli_crash_32").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let result = run::<u32, SparseMemory<u32>>(&buffer, &vec!["op_rvc_slli_crash_32".into()]);
+    assert!(result.is_ok());
+}
+
+#[test]
+pub fn test_load_elf_crash_64() {
+    let mut file = File::open("tests/programs/load_elf_crash_64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    // The segment ends past the address space, which used to wrap around
+    // and only fail once the program ran.
+    let result = run::<u64, SparseMemory<u64>>(&buffer, &vec!["load_elf_crash_64".into()]);
+    assert_eq!(result.err(), Some(Error::OutOfBound));
+}
+
+#[test]
+pub fn test_wxorx_crash_64() {
+    let mut file = File::open("tests/programs/wxorx_crash_64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let result = run::<u64, SparseMemory<u64>>(&buffer, &vec!["wxorx_crash_64".into()]);
+    assert_eq!(result.err(), Some(Error::OutOfBound));
+}
+
+#[test]
+pub fn test_flat_crash_64() {
+    let mut file = File::open("tests/programs/flat_crash_64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let mut machine = DefaultMachine::<DefaultCoreMachine<u64, FlatMemory<u64>>>::default();
+    let result = machine.load_program(&buffer, &vec!["flat_crash_64".into()]);
+    assert_eq!(
+        result.err(),
+        Some(Error::ElfSegment {
+            index: 0,
+            reason: ElfSegmentError::InvalidAlignment
+        })
+    );
+}
+
+#[test]
+pub fn test_trace_with_self_modifying_code() {
+    let mut file = File::open("tests/programs/trace64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let mut machine = TraceMachine::new(DefaultMachine::<
+        DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>,
+    >::default());
+    machine.memory_mut().set_allow_self_modifying_code(true);
+    machine
+        .load_program(&buffer, &vec!["trace64".into()])
+        .unwrap();
+    // trace64 overwrites `li a0, 11` with `li a0, 7` in the same basic block
+    let result = machine.run();
+    assert_eq!(result.unwrap(), 7);
+}
+
+#[test]
+pub fn test_forbid_self_modifying_code() {
+    let mut file = File::open("tests/programs/trace64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let mut machine = TraceMachine::new(
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>>::default()
+            .forbid_self_modifying_code(true)
+            .build(),
+    );
+    machine.memory_mut().set_allow_self_modifying_code(true);
+    machine
+        .load_program(&buffer, &vec!["trace64".into()])
+        .unwrap();
+    assert_eq!(machine.run(), Err(Error::WriteToExecutableMemory));
+
+    // Takes precedence over the W^X check, and applies to the host too
+    let mut machine = DefaultMachineBuilder::<
+        DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>,
+    >::default()
+    .forbid_self_modifying_code(true)
+    .build();
+    machine
+        .load_program(&buffer, &vec!["trace64".into()])
+        .unwrap();
+    assert_eq!(machine.run(), Err(Error::WriteToExecutableMemory));
+    let pc = *machine.pc();
+    assert_eq!(
+        machine.memory_mut().store_bytes(pc, &[0; 4]),
+        Err(Error::WriteToExecutableMemory)
+    );
+}
+
+// Builds a minimal RV64 position independent executable. The code segment
+// loads a pointer from the data segment, which is only correct after the
+// relocation of the given type is applied, and exits with 0 if the pointed
+// value is 42.
+fn build_pie(relocation_type: u64) -> Bytes {
+    fn push_u16(buffer: &mut Vec<u8>, value: u16) {
+        buffer.extend_from_slice(&value.to_le_bytes());
+    }
+    fn push_u32(buffer: &mut Vec<u8>, value: u32) {
+        buffer.extend_from_slice(&value.to_le_bytes());
+    }
+    fn push_u64(buffer: &mut Vec<u8>, value: u64) {
+        buffer.extend_from_slice(&value.to_le_bytes());
+    }
+    fn push_program_header(buffer: &mut Vec<u8>, p_type: u32, p_flags: u32, at: u64, size: u64) {
+        push_u32(buffer, p_type);
+        push_u32(buffer, p_flags);
+        push_u64(buffer, at);
+        push_u64(buffer, at);
+        push_u64(buffer, at);
+        push_u64(buffer, size);
+        push_u64(buffer, size);
+        push_u64(buffer, 0x1000);
+    }
+
+    let mut elf = vec![0x7f, b'E', b'L', b'F', 2, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    push_u16(&mut elf, 3); // ET_DYN
+    push_u16(&mut elf, 243); // EM_RISCV
+    push_u32(&mut elf, 1);
+    push_u64(&mut elf, 0x100); // entry
+    push_u64(&mut elf, 64); // program headers
+    push_u64(&mut elf, 0); // no section headers
+    push_u32(&mut elf, 0);
+    push_u16(&mut elf, 64);
+    push_u16(&mut elf, 56);
+    push_u16(&mut elf, 3);
+    push_u16(&mut elf, 64);
+    push_u16(&mut elf, 0);
+    push_u16(&mut elf, 0);
+    // PT_LOAD R+X, PT_LOAD R+W, PT_DYNAMIC
+    push_program_header(&mut elf, 1, 5, 0, 0x118);
+    push_program_header(&mut elf, 1, 6, 0x1000, 0x68);
+    push_program_header(&mut elf, 2, 6, 0x1028, 0x40);
+
+    elf.resize(0x100, 0);
+    for instruction in &[
+        0x0000_1597, // auipc a1, 1
+        0xf005_b583, // ld a1, -256(a1)
+        0x0005_b503, // ld a0, 0(a1)
+        0xfd65_0513, // addi a0, a0, -42
+        0x05d0_0893, // li a7, 93
+        0x0000_0073, // ecall
+    ] {
+        push_u32(&mut elf, *instruction);
+    }
+
+    elf.resize(0x1000, 0);
+    // Pointer to the value below, relocated at load time
+    push_u64(&mut elf, 0);
+    push_u64(&mut elf, 42);
+    // .rela.dyn
+    push_u64(&mut elf, 0x1000);
+    push_u64(&mut elf, relocation_type);
+    push_u64(&mut elf, 0x1008);
+    // .dynamic: DT_RELA, DT_RELASZ, DT_RELAENT, DT_NULL
+    for (tag, value) in &[(7, 0x1010), (8, 24), (9, 24), (0, 0)] {
+        push_u64(&mut elf, *tag);
+        push_u64(&mut elf, *value);
+    }
+    elf.into()
+}
+
+fn run_pie(program: &Bytes, load_bias: u64) -> Result<i8, Error> {
+    let mut machine = DefaultMachineBuilder::<
+        DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>,
+    >::default()
+    .load_bias(load_bias)
+    .build();
+    machine.load_program(program, &vec!["pie".into()])?;
+    assert_eq!(machine.pc().to_u64(), load_bias + 0x100);
+    machine.run()
+}
+
+#[test]
+pub fn test_pie_relative_relocation() {
+    // R_RISCV_RELATIVE
+    let program = build_pie(3);
+    assert_eq!(run_pie(&program, 0), Ok(0));
+    assert_eq!(run_pie(&program, 0x10000), Ok(0));
+    assert_eq!(run_pie(&program, 0x200000), Ok(0));
+    assert_eq!(run_pie(&program, 0x10010), Err(Error::Unaligned));
+}
+
+#[test]
+pub fn test_pie_unsupported_relocation() {
+    // R_RISCV_64 needs a symbol lookup
+    let program = build_pie(2);
+    assert_eq!(run_pie(&program, 0x10000), Err(Error::Unimplemented));
+}
+
+#[test]
+pub fn test_load_bias_ignored_for_static_executables() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let mut machine = DefaultMachineBuilder::<
+        DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>,
+    >::default()
+    .load_bias(0x10000)
+    .build();
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    assert_eq!(machine.run(), Ok(0));
+}
+
+#[test]
+pub fn test_memory_size_limits_program() {
+    let program = build_pie(3);
+    let mut machine = DefaultMachineBuilder::<
+        DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>,
+    >::default()
+    .memory_size(2 << 20)
+    .unwrap()
+    .load_bias(0x100000)
+    .build();
+    machine.load_program(&program, &vec!["pie".into()]).unwrap();
+    assert_eq!(machine.run(), Ok(0));
+    // Stack is placed at the end of the smaller address space
+    assert!(machine.registers()[ckb_vm::registers::SP] < 2 << 20);
+
+    let mut machine = DefaultMachineBuilder::<
+        DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>,
+    >::default()
+    .memory_size(2 << 20)
+    .unwrap()
+    .load_bias(0x200000)
+    .build();
+    assert_eq!(
+        machine.load_program(&program, &vec!["pie".into()]),
+        Err(Error::MemorySizeExceeded(0x201000, 0x200000))
+    );
+}
+
+#[test]
+pub fn test_invalid_memory_size() {
+    let builder = DefaultMachineBuilder::<
+        DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>,
+    >::default();
+    assert_eq!(
+        builder.memory_size(3 << 20).err(),
+        Some(Error::InvalidMemorySize(3 << 20))
+    );
+}
+
+#[test]
+pub fn test_host_fn() {
+    let mut file = File::open("tests/programs/syscall64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default()
+            .host_fn(
+                1111,
+                |_: &mut DefaultCoreMachine<u64, SparseMemory<u64>>,
+                 a: u64,
+                 b: u64,
+                 c: u64,
+                 d: u64,
+                 e: u64,
+                 f: u64| Ok(a + b + c + d + e + f),
+            )
+            .build();
+    machine
+        .load_program(&buffer, &vec!["syscall".into()])
+        .unwrap();
+    assert_eq!(machine.run().unwrap(), 39);
+
+    // Byte buffers and typed arguments
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default()
+            .host_fn(
+                2000,
+                |machine: &mut DefaultCoreMachine<u64, SparseMemory<u64>>, upper: bool| {
+                    let mut data = read_arg_bytes(machine, 1)?;
+                    if upper {
+                        data.make_ascii_uppercase();
+                    }
+                    write_return_slice(machine, 3, &data)
+                },
+            )
+            .host_fn(
+                2001,
+                |_: &mut DefaultCoreMachine<u64, SparseMemory<u64>>, value: i32| Ok(-value),
+            )
+            .build();
+    machine.memory_mut().store_bytes(0x1000, b"hello").unwrap();
+    machine.set_register(A0, 1);
+    machine.set_register(A1, 0x1000);
+    machine.set_register(A2, 5);
+    machine.set_register(A3, 0x2000);
+    machine.set_register(A4, 3);
+    machine.set_register(A7, 2000);
+    machine.ecall().unwrap();
+    // The result is truncated to the buffer, its full length is returned
+    assert_eq!(machine.registers()[A0], 5);
+    assert_eq!(
+        machine.memory_mut().load_bytes(0x2000, 4).unwrap(),
+        b"HEL\0"
+    );
+
+    machine.set_register(A0, 7);
+    machine.set_register(A7, 2001);
+    machine.ecall().unwrap();
+    assert_eq!(machine.registers()[A0] as i64, -7);
+}
+
+#[test]
+pub fn test_fault_context() {
+    let mut file = File::open("tests/programs/write_large_address64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default()
+            .instruction_cycle_func(Box::new(|_| 1))
+            .build();
+    machine
+        .load_program(&buffer, &vec!["write_large_address64".into()])
+        .unwrap();
+    let result = machine.run_with_result();
+    assert_eq!(result.reason, ExitReason::Trap(Error::OutOfBound));
+    let fault = result.fault.unwrap();
+    assert_eq!(Some(fault), machine.last_fault());
+    assert_eq!(fault.error, Error::OutOfBound);
+    assert_eq!(fault.addr, Some(0xFFFFFF00));
+    assert_eq!(fault.size, Some(8));
+    assert_eq!(fault.cycles, result.cycles);
+    assert_eq!(
+        fault.to_string(),
+        format!(
+            "out of bound access at pc {:#x} (c.sd a3, 0(a2)) accessing 8 bytes at 0xffffff00 after {} cycles",
+            fault.pc, fault.cycles
+        )
+    );
+}
+
+#[test]
+pub fn test_rvc_disabled() {
+    assert_eq!(
+        decode_rvc::<u64>(0x4501).map(disassemble),
+        Some("c.li a0, 0".to_string())
+    );
+    // Lower half of addi a0, zero, 0
+    assert_eq!(decode_rvc::<u64>(0x0513), None);
+
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default()
+            .rvc(false)
+            .build();
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    let result = machine.run();
+    match result {
+        Err(Error::InvalidInstruction(bits)) => {
+            assert_ne!(bits & 0x3, 0x3);
+            assert!(decode_rvc::<u64>(bits as u16).is_some());
+        }
+        _ => panic!("unexpected result: {:?}", result),
+    }
+
+    machine.set_rvc(true);
+    assert_eq!(machine.run(), Ok(0));
+}
+
+#[test]
+pub fn test_max_instructions() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default()
+            .max_instructions(10)
+            .build();
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    assert_eq!(machine.run(), Err(Error::InstructionLimitExceeded));
+    assert_eq!(machine.instructions(), 10);
+    let executed = machine.instructions();
+
+    let mut machine = TraceMachine::new(
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>>::default()
+            .max_instructions(10)
+            .build(),
+    );
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    assert_eq!(machine.run(), Err(Error::InstructionLimitExceeded));
+    assert_eq!(machine.machine.instructions(), executed);
+
+    // Lifting the limit lets the program finish
+    machine.machine.set_max_instructions(0);
+    assert_eq!(machine.run(), Ok(0));
+    assert!(machine.machine.instructions() > executed);
+}
+
+#[test]
+pub fn test_mprotect_invalidates_traces() {
+    let main: [u32; 9] = [
+        0x000010ef, // jal ra, 0x2000
+        0x00002537, // lui a0, 0x2
+        0x000015b7, // lui a1, 0x1
+        0x00100613, // li a2, 1
+        0x0e200893, // li a7, 226
+        0x00000073, // ecall
+        0x7e9000ef, // jal ra, 0x2000
+        0x05d00893, // li a7, 93
+        0x00000073, // ecall
+    ];
+    let function: [u32; 2] = [
+        0x00168693, // addi a3, a3, 1
+        0x00008067, // ret
+    ];
+    let to_bytes = |code: &[u32]| -> Bytes {
+        code.iter()
+            .flat_map(|word| word.to_le_bytes().to_vec())
+            .collect::<Vec<u8>>()
+            .into()
+    };
+
+    let mut machine = TraceMachine::new(
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>>::default()
+            .mprotect_syscall()
+            .build(),
+    );
+    machine
+        .memory_mut()
+        .init_pages(0x1000, 0x1000, FLAG_EXECUTABLE, Some(to_bytes(&main)), 0)
+        .unwrap();
+    machine
+        .memory_mut()
+        .init_pages(
+            0x2000,
+            0x1000,
+            FLAG_EXECUTABLE,
+            Some(to_bytes(&function)),
+            0,
+        )
+        .unwrap();
+    machine.set_pc(0x1000);
+    // The second call fails although the function has been cached
+    assert_eq!(machine.run(), Err(Error::MemoryProtection));
+    assert_eq!(machine.registers()[A0], 0);
+    assert_eq!(machine.registers()[A3], 1);
+    assert_eq!(*machine.pc(), 0x2000);
+}
+
+pub struct RemapSyscall {
+    code: Bytes,
+}
+
+impl<Mac: SupportMachine> Syscalls<Mac> for RemapSyscall {
+    fn initialize(&mut self, _machine: &mut Mac) -> Result<(), Error> {
+        Ok(())
+    }
+
+    // Replaces the code at 0x2000 without changing page flags, as a loader
+    // mapping new code over old code would.
+    fn ecall(&mut self, machine: &mut Mac) -> Result<bool, Error> {
+        if machine.registers()[A7].to_u64() != 1000 {
+            return Ok(false);
+        }
+        machine.memory_mut().init_pages(
+            0x2000,
+            0x1000,
+            FLAG_EXECUTABLE,
+            Some(self.code.clone()),
+            0,
+        )?;
+        Ok(true)
+    }
+}
+
+#[test]
+pub fn test_fence_i_invalidates_traces() {
+    let to_bytes = |code: &[u32]| -> Bytes {
+        code.iter()
+            .flat_map(|word| word.to_le_bytes().to_vec())
+            .collect::<Vec<u8>>()
+            .into()
+    };
+    let run = |fence: u32| {
+        let main: [u32; 8] = [
+            0x000010ef, // jal ra, 0x2000
+            0x3e800893, // li a7, 1000
+            0x00000073, // ecall
+            fence, 0x7f1000ef, // jal ra, 0x2000
+            0x05d00893, // li a7, 93
+            0x00000073, // ecall
+            0x00000013, // nop
+        ];
+        let old_function: [u32; 2] = [
+            0x00100513, // li a0, 1
+            0x00008067, // ret
+        ];
+        let new_function: [u32; 2] = [
+            0x00200513, // li a0, 2
+            0x00008067, // ret
+        ];
+        let mut machine = TraceMachine::new(
+            DefaultMachineBuilder::<DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>>::default()
+                .syscall(Box::new(RemapSyscall {
+                    code: to_bytes(&new_function),
+                }))
+                .build(),
+        );
+        machine
+            .memory_mut()
+            .init_pages(0x1000, 0x1000, FLAG_EXECUTABLE, Some(to_bytes(&main)), 0)
+            .unwrap();
+        machine
+            .memory_mut()
+            .init_pages(
+                0x2000,
+                0x1000,
+                FLAG_EXECUTABLE,
+                Some(to_bytes(&old_function)),
+                0,
+            )
+            .unwrap();
+        machine.set_pc(0x1000);
+        machine.run()
+    };
+    // Without fence.i the cached function keeps running
+    assert_eq!(run(0x00000013), Ok(1));
+    assert_eq!(run(0x0000100f), Ok(2));
+}
+
+#[test]
+pub fn test_stack_guard() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+    // Pushes to the stack forever
+    let code: Vec<u8> = [
+        0xff010113u32, // addi sp, sp, -16
+        0x00113423,    // sd ra, 8(sp)
+        0xff9ff06f,    // j -8
+    ]
+    .iter()
+    .flat_map(|word| word.to_le_bytes().to_vec())
+    .collect();
+    let code = Bytes::from(code);
+    let stack_start = (ckb_vm::RISCV_MAX_MEMORY - ckb_vm::DEFAULT_STACK_SIZE) as u64;
+
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default()
+            .stack_guard_size(4096)
+            .build();
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    assert_eq!(machine.stack_guard(), Some((stack_start - 4096, 4096)));
+    machine
+        .memory_mut()
+        .init_pages(0x200000, 0x1000, FLAG_EXECUTABLE, Some(code.clone()), 0)
+        .unwrap();
+    machine.set_pc(0x200000);
+    assert_eq!(machine.run(), Err(Error::StackOverflow));
+    // Stopped at the first push reaching below the stack
+    let sp = machine.registers()[SP];
+    assert!(sp + 8 < stack_start && sp + 24 >= stack_start);
+
+    let mut machine = TraceMachine::new(
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>>::default()
+            .stack_guard_size(4096)
+            .build(),
+    );
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    machine
+        .memory_mut()
+        .init_pages(0x200000, 0x1000, FLAG_EXECUTABLE, Some(code), 0)
+        .unwrap();
+    machine.set_pc(0x200000);
+    assert_eq!(machine.run(), Err(Error::StackOverflow));
+    // Stopped at the first push reaching below the stack
+    let sp = machine.registers()[SP];
+    assert!(sp + 8 < stack_start && sp + 24 >= stack_start);
+}
+
+#[test]
+pub fn test_load_elf_segment_errors() {
+    use std::convert::TryInto;
+
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+
+    // Patches a u64 field of the program header at index
+    let patch = |index: usize, field: usize, value: u64| {
+        let mut data = buffer.clone();
+        let phoff = u64::from_le_bytes(data[0x20..0x28].try_into().unwrap()) as usize;
+        let offset = phoff + 56 * index + field;
+        data[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+        Bytes::from(data)
+    };
+    let load = |program: Bytes| {
+        let mut machine = DefaultMachine::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default();
+        machine.load_program(&program, &vec!["simple".into()]).err()
+    };
+    let segment_error = |index, reason| Some(Error::ElfSegment { index, reason });
+
+    // p_filesz larger than p_memsz
+    assert_eq!(
+        load(patch(1, 32, 4096)),
+        segment_error(1, ElfSegmentError::FileSizeExceedsMemorySize)
+    );
+    // p_align
+    assert_eq!(
+        load(patch(1, 48, 3000)),
+        segment_error(1, ElfSegmentError::InvalidAlignment)
+    );
+    // p_vaddr not matching p_offset modulo p_align
+    assert_eq!(
+        load(patch(1, 16, 0x11000)),
+        segment_error(1, ElfSegmentError::Misaligned)
+    );
+    // Second segment moved into the pages of the first one
+    assert_eq!(
+        load(patch(1, 16, 0x1084c)),
+        segment_error(1, ElfSegmentError::Overlap(0))
+    );
+    assert_eq!(load(Bytes::from(buffer.clone())), None);
+}
+
+#[test]
+pub fn test_preload() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let mut machine = TraceMachine::new(
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>>::default()
+            .preload(0x200000, Bytes::from_static(b"input"))
+            .preload(0x200005, Bytes::from_static(b"data"))
+            .build(),
+    );
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    assert_eq!(
+        machine.memory_mut().load_bytes(0x200000, 9).unwrap(),
+        b"inputdata".to_vec()
+    );
+    assert_eq!(machine.run(), Ok(0));
+
+    // The first segment covers [0x10000, 0x1084c)
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default()
+            .preload(0x200000, Bytes::from_static(b"input"))
+            .preload(0x10840, Bytes::from_static(b"overlap"))
+            .build();
+    let result = machine.load_program(&buffer, &vec!["simple".into()]);
+    assert_eq!(result, Err(Error::PreloadOverlap(0x10840)));
+    assert_eq!(
+        machine.memory_mut().load_bytes(0x200000, 5).unwrap(),
+        vec![0; 5]
+    );
+
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default()
+            .preload(0x200000, Bytes::from_static(b"input"))
+            .preload(0x200004, Bytes::from_static(b"data"))
+            .build();
+    let result = machine.load_program(&buffer, &vec!["simple".into()]);
+    assert_eq!(result, Err(Error::PreloadOverlap(0x200004)));
+}
+
+#[test]
+pub fn test_u128_registers() {
+    let code: Vec<u8> = [
+        0xfff00513u32, // li a0, -1
+        0x00155513,    // srli a0, a0, 1
+        0x00a505b3,    // add a1, a0, a0
+        0x02a53633,    // mulhu a2, a0, a0
+        0xfff00693,    // li a3, -1
+        0x02a69733,    // mulh a4, a3, a0
+        0x06400293,    // li t0, 100
+        0x005697b3,    // sll a5, a3, t0
+        0x0006883b,    // addw a6, a3, zero
+    ]
+    .iter()
+    .flat_map(|word| word.to_le_bytes().to_vec())
+    .collect();
+    let mut machine = DefaultMachine::<DefaultCoreMachine<u128, SparseMemory<u128>>>::default();
+    machine
+        .memory_mut()
+        .init_pages(0x1000, 0x1000, FLAG_EXECUTABLE, Some(Bytes::from(code)), 0)
+        .unwrap();
+    machine.set_pc(0x1000);
+    let decoder = build_imac_decoder::<u128>();
+    for _ in 0..9 {
+        machine.step(&decoder).unwrap();
+    }
+    assert_eq!(machine.registers()[A0], u128::MAX >> 1);
+    assert_eq!(machine.registers()[A1], u128::MAX - 1);
+    assert_eq!(machine.registers()[A2], u128::MAX >> 2);
+    assert_eq!(machine.registers()[A4], u128::MAX);
+    assert_eq!(machine.registers()[A5], u128::MAX << 100);
+    assert_eq!(machine.registers()[A6], u128::MAX);
+}
+
+#[test]
+pub fn test_machine_reset() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let mut machine = DefaultMachineBuilder::new(DefaultCoreMachine::<
+        u64,
+        WXorXMemory<u64, SparseMemory<u64>>,
+    >::new_with_max_cycles(1000))
+    .instruction_cycle_func(Box::new(|_| 1))
+    .build();
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    assert_eq!(machine.run(), Ok(0));
+    let cycles = machine.cycles();
+    assert!(cycles > 0);
+    // Code pages are frozen, loading again requires a reset
+    assert_eq!(
+        machine.load_program(&buffer, &vec!["simple".into()]),
+        Err(Error::InvalidPermission)
+    );
+
+    machine.reset().unwrap();
+    assert_eq!(machine.cycles(), 0);
+    assert_eq!(machine.max_cycles(), Some(1000));
+    assert_eq!(machine.instructions(), 0);
+    assert_eq!(*machine.pc(), 0);
+    assert!(machine.registers().iter().all(|r| *r == 0));
+    assert_eq!(machine.memory().touched_memory(), 0);
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    assert_eq!(machine.run(), Ok(0));
+    assert_eq!(machine.cycles(), cycles);
+}
+
+#[test]
+pub fn test_machine_pool() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let mut pool = MachinePool::new(
+        1,
+        Box::new(|| {
+            DefaultMachineBuilder::<DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>>::default()
+                .build()
+        }),
+    );
+    assert_eq!(pool.idle(), 0);
+    let mut first = pool.take();
+    let mut second = pool.take();
+    for machine in [&mut first, &mut second].iter_mut() {
+        machine
+            .load_program(&buffer, &vec!["simple".into()])
+            .unwrap();
+        assert_eq!(machine.run(), Ok(0));
+    }
+    pool.give_back(first).unwrap();
+    pool.give_back(second).unwrap();
+    assert_eq!(pool.idle(), 1);
+
+    let mut machine = pool.take();
+    assert_eq!(pool.idle(), 0);
+    assert_eq!(machine.cycles(), 0);
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    assert_eq!(machine.run(), Ok(0));
+}
+
+#[test]
+pub fn test_decoded_program() {
+    let load = |name| {
+        let mut file = File::open(name).unwrap();
+        let mut buffer = Vec::new();
+        file.read_to_end(&mut buffer).unwrap();
+        Bytes::from(buffer)
+    };
+    let simple = load("tests/programs/simple64");
+    let decoded =
+        Arc::new(DecodedProgram::new::<u64>(&simple, &build_imac_decoder::<u64>()).unwrap());
+    assert!(decoded.blocks() > 0);
+    assert!(decoded.instructions() >= decoded.blocks());
+    assert_eq!(
+        DecodedProgram::new::<u32>(&simple, &build_imac_decoder::<u32>()),
+        Err(Error::InvalidElfBits)
+    );
+
+    let build = |decoded: Option<Arc<DecodedProgram>>| {
+        let mut builder = DefaultMachineBuilder::<
+            DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>,
+        >::default()
+        .instruction_cycle_func(Box::new(|_| 1));
+        if let Some(decoded) = decoded {
+            builder = builder.decoded_program(decoded);
+        }
+        let mut machine = builder.build();
+        machine
+            .load_program(&simple, &vec!["simple".into()])
+            .unwrap();
+        machine
+    };
+    let mut expected = build(None);
+    assert_eq!(expected.run(), Ok(0));
+
+    let mut machine = build(Some(Arc::clone(&decoded)));
+    assert_eq!(machine.run(), Ok(0));
+    assert_eq!(machine.cycles(), expected.cycles());
+
+    let mut machine = TraceMachine::new(build(None));
+    machine.set_decoded_program(Some(Arc::clone(&decoded)));
+    assert_eq!(machine.run(), Ok(0));
+    assert_eq!(SupportMachine::cycles(&machine.machine), expected.cycles());
+
+    // Instructions decoded from another program never match memory
+    let other = load("tests/programs/mulw64");
+    let other = Arc::new(DecodedProgram::new::<u64>(&other, &build_imac_decoder::<u64>()).unwrap());
+    let mut machine = build(Some(other));
+    assert_eq!(machine.run(), Ok(0));
+    assert_eq!(machine.cycles(), expected.cycles());
+}
+
+#[test]
+pub fn test_counter_csrs() {
+    let code: Vec<u8> = [
+        0xc0002573u32, // rdcycle a0
+        0xc02025f3,    // rdinstret a1
+        0xc0102673,    // rdtime a2
+        0x05d00893,    // li a7, 93
+        0x00000073,    // ecall
+    ]
+    .iter()
+    .flat_map(|word| word.to_le_bytes().to_vec())
+    .collect();
+    let build = |fixed_counters| {
+        let mut machine = DefaultMachineBuilder::<
+            DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>,
+        >::default()
+        .instruction_cycle_func(Box::new(|_| 1))
+        .fixed_counters(fixed_counters)
+        .build();
+        machine
+            .memory_mut()
+            .init_pages(
+                0x1000,
+                0x1000,
+                FLAG_EXECUTABLE,
+                Some(code.clone().into()),
+                0,
+            )
+            .unwrap();
+        machine.set_pc(0x1000);
+        machine
+    };
+
+    let mut machine = build(false);
+    let decoder = build_imac_counter_decoder::<u64>();
+    assert_eq!(machine.run_with_decoder(&decoder), Ok(1));
+    assert_eq!(machine.registers()[A1], 2);
+    assert_eq!(machine.registers()[A2], 3);
+
+    let mut machine = build(true);
+    assert_eq!(machine.run_with_decoder(&decoder), Ok(0));
+    assert_eq!(machine.registers()[A1], 0);
+    assert_eq!(machine.registers()[A2], 0);
+
+    // Counters are rejected by the IMAC decoder
+    let mut machine = build(false);
+    assert_eq!(machine.run(), Err(Error::InvalidInstruction(0xc0002573)));
+}
+
+#[test]
+pub fn test_checked_arithmetic() {
+    let code: Vec<u8> = [
+        0x80000537u32, // lui a0, 0x80000
+        0xfff5059b,    // addiw a1, a0, -1
+        0xfff50613,    // addi a2, a0, -1
+        0x05d00893,    // li a7, 93
+        0x00000073,    // ecall
+    ]
+    .iter()
+    .flat_map(|word| word.to_le_bytes().to_vec())
+    .collect();
+    let build = |checked_arithmetic| {
+        let mut machine = DefaultMachineBuilder::<
+            DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>,
+        >::default()
+        .checked_arithmetic(checked_arithmetic)
+        .build();
+        machine
+            .memory_mut()
+            .init_pages(
+                0x1000,
+                0x1000,
+                FLAG_EXECUTABLE,
+                Some(code.clone().into()),
+                0,
+            )
+            .unwrap();
+        machine.set_pc(0x1000);
+        machine
+    };
+
+    let mut machine = build(true);
+    assert_eq!(machine.run(), Ok(0));
+    // Results still wrap around
+    assert_eq!(machine.registers()[A1], 0x7fff_ffff);
+    let log = machine.overflow_log().unwrap();
+    assert_eq!(log.len(), 1);
+    assert_eq!(log[0].pc, 0x1004);
+    assert_eq!(log[0].lhs, 0xffff_ffff_8000_0000);
+    assert_eq!(log[0].rhs, -1i64 as u64);
+    let log = machine.take_overflow_log().unwrap();
+
+    let mut machine = TraceMachine::new(build(true));
+    assert_eq!(machine.run(), Ok(0));
+    assert_eq!(machine.machine.overflow_log().unwrap(), &log[..]);
+
+    let mut machine = build(false);
+    assert_eq!(machine.run(), Ok(0));
+    assert!(machine.overflow_log().is_none());
+}
+
+#[test]
+pub fn test_opcode_stats() {
+    let code: Vec<u8> = [
+        0x00300513u32, // li a0, 3
+        0xfff50513,    // addi a0, a0, -1
+        0xfe051ee3,    // bnez a0, -4
+        0x05d00893,    // li a7, 93
+        0x00000073,    // ecall
+    ]
+    .iter()
+    .flat_map(|word| word.to_le_bytes().to_vec())
+    .collect();
+    let build = |opcode_stats| {
+        let mut machine = DefaultMachineBuilder::<
+            DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>,
+        >::default()
+        .opcode_stats(opcode_stats)
+        .build();
+        machine
+            .memory_mut()
+            .init_pages(
+                0x1000,
+                0x1000,
+                FLAG_EXECUTABLE,
+                Some(code.clone().into()),
+                0,
+            )
+            .unwrap();
+        machine.set_pc(0x1000);
+        machine
+    };
+    let named = |stats: &[u64]| {
+        stats
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(opcode, count)| (INSTRUCTION_OPCODE_NAMES[opcode], *count))
+            .collect::<Vec<_>>()
+    };
+
+    let mut machine = build(true);
+    assert_eq!(machine.run(), Ok(0));
+    let stats = named(machine.opcode_stats().unwrap());
+    assert_eq!(stats, vec![("BNE", 3), ("ECALL", 1), ("ADDI", 5)]);
+
+    let mut machine = TraceMachine::new(build(true));
+    assert_eq!(machine.run(), Ok(0));
+    assert_eq!(named(machine.machine.opcode_stats().unwrap()), stats);
+    machine.machine.reset().unwrap();
+    assert!(machine
+        .machine
+        .opcode_stats()
+        .unwrap()
+        .iter()
+        .all(|c| *c == 0));
+
+    let mut machine = build(false);
+    assert_eq!(machine.run(), Ok(0));
+    assert!(machine.opcode_stats().is_none());
+}
+
+#[test]
+pub fn test_exit_handler() {
+    let code: Vec<u8> = [
+        0x00300513u32, // li a0, 3
+        0x05d00893,    // li a7, 93
+        0x00000073,    // ecall
+    ]
+    .iter()
+    .flat_map(|word| word.to_le_bytes().to_vec())
+    .collect();
+    let code: Bytes = code.into();
+    let load = |machine: &mut DefaultMachine<DefaultCoreMachine<u64, SparseMemory<u64>>>| {
+        machine
+            .memory_mut()
+            .init_pages(0x1000, 0x1000, FLAG_EXECUTABLE, Some(code.clone()), 0)
+            .unwrap();
+        machine.set_pc(0x1000);
+    };
+
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default()
+            .exit_handler(Box::new(|machine, code| {
+                assert_eq!(machine.registers()[A7], 93);
+                Ok(code * 2)
+            }))
+            .build();
+    load(&mut machine);
+    assert_eq!(machine.run(), Ok(6));
+    assert!(!machine.running());
+
+    // Hosts can reject exit codes breaking their conventions
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default()
+            .exit_handler(Box::new(|_, code| {
+                if code == 0 {
+                    Ok(0)
+                } else {
+                    Err(Error::Unexpected)
+                }
+            }))
+            .build();
+    load(&mut machine);
+    assert_eq!(machine.run(), Err(Error::Unexpected));
+}
+
+// Decodes the custom-0 opcode space as custom instructions with id 1
+fn custom0_factory(bits: u32) -> Option<Instruction> {
+    if bits & 0x7f == 0x0b {
+        Some(custom::pack(1, bits))
+    } else {
+        None
+    }
+}
+
+#[test]
+pub fn test_custom_instructions() {
+    let code: Vec<u8> = [
+        0x00500513u32, // li a0, 5
+        0x00700593,    // li a1, 7
+        0x00b5060b,    // custom-0 a2, a0, a1
+        0x00060513,    // mv a0, a2
+        0x05d00893,    // li a7, 93
+        0x00000073,    // ecall
+    ]
+    .iter()
+    .flat_map(|word| word.to_le_bytes().to_vec())
+    .collect();
+    let code: Bytes = code.into();
+    let mut decoder = build_imac_decoder::<u64>();
+    decoder.add_instruction_factory(custom0_factory);
+    assert_eq!(
+        disassemble(decoder.decode_raw(0x00b5060b).unwrap()),
+        "custom.1 0x00b5060b"
+    );
+
+    let build = |handled: bool| {
+        let mut builder = DefaultMachineBuilder::<
+            DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>,
+        >::default();
+        if handled {
+            // rd = rs1 * rs2 + 1
+            builder = builder.custom_instruction(
+                1,
+                Box::new(|machine, bits| {
+                    let rd = ((bits >> 7) & 0x1f) as usize;
+                    let rs1 = ((bits >> 15) & 0x1f) as usize;
+                    let rs2 = ((bits >> 20) & 0x1f) as usize;
+                    let value = machine.registers()[rs1] * machine.registers()[rs2] + 1;
+                    machine.set_register(rd, value);
+                    Ok(None)
+                }),
+            );
+        }
+        let mut machine = builder.build();
+        machine
+            .memory_mut()
+            .init_pages(0x1000, 0x1000, FLAG_EXECUTABLE, Some(code.clone()), 0)
+            .unwrap();
+        machine.set_pc(0x1000);
+        machine
+    };
+
+    let mut machine = build(true);
+    assert_eq!(machine.run_with_decoder(&decoder), Ok(36));
+    let mut machine = TraceMachine::new(build(true));
+    assert_eq!(machine.run_with_decoder(&decoder), Ok(36));
+
+    // Custom instructions need both a factory and a handler
+    let mut machine = build(true);
+    assert_eq!(machine.run(), Err(Error::InvalidInstruction(0x00b5060b)));
+    let mut decoder = build_imac_decoder::<u64>();
+    decoder.add_instruction_factory(custom0_factory);
+    let mut machine = build(false);
+    assert_eq!(
+        machine.run_with_decoder(&decoder),
+        Err(Error::InvalidInstruction(0x00b5060b))
+    );
+}
+
+#[test]
+pub fn test_custom_opcode_spaces() {
+    let code: Vec<u8> = [
+        0x00500513u32, // li a0, 5
+        0x0000002b,    // custom-1, skips the next instruction
+        0x00100513,    // li a0, 1
+        0x0000000b,    // custom-0, adds 10 to a0
+        0x05d00893,    // li a7, 93
+        0x00000073,    // ecall
+    ]
+    .iter()
+    .flat_map(|word| word.to_le_bytes().to_vec())
+    .collect();
+    let mut machine = DefaultMachineBuilder::<
+        DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>,
+    >::default()
+    .custom_instruction(
+        custom::CUSTOM_0_ID,
+        Box::new(|machine, _| {
+            machine.set_register(A0, machine.registers()[A0] + 10);
+            Ok(None)
+        }),
+    )
+    .custom_instruction(
+        custom::CUSTOM_1_ID,
+        Box::new(|machine, _| Ok(Some(machine.pc() + 8))),
+    )
+    .build();
+    machine
+        .memory_mut()
+        .init_pages(0x1000, 0x1000, FLAG_EXECUTABLE, Some(code.into()), 0)
+        .unwrap();
+    machine.set_pc(0x1000);
+    let decoder = build_imac_custom_decoder::<u64>();
+    assert_eq!(machine.run_with_decoder(&decoder), Ok(15));
+}
+
+#[cfg(feature = "symbols")]
+#[test]
+pub fn test_symbolicate() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default().build();
+    machine.load_program(&buffer, &["simple".into()]).unwrap();
+    assert_eq!(machine.symbols().address("main"), Some(0x1019c));
+    assert_eq!(machine.symbolicate(0x100b0).unwrap().to_string(), "_start");
+    assert_eq!(
+        machine.symbolicate(0x101a0).unwrap().to_string(),
+        "main+0x4"
+    );
+    assert!(machine.symbolicate(0x100).is_none());
+
+    let mut file = File::open("tests/programs/invalid_read64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default().build();
+    machine
+        .load_program(&buffer, &["invalid_read".into()])
+        .unwrap();
+    assert!(machine.run().is_err());
+    let message = machine.last_fault_message().unwrap();
+    assert!(message.starts_with(&machine.last_fault().unwrap().to_string()));
+    assert!(message.ends_with(" in _start+0x2"), "{}", message);
+}
+
+#[test]
+pub fn test_yield_every() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let build = |yield_every| {
+        let mut machine =
+            DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default()
+                .instruction_cycle_func(Box::new(|_| 1))
+                .yield_every(yield_every)
+                .build();
+        machine.load_program(&buffer, &["simple".into()]).unwrap();
+        machine
+    };
+    let mut machine = build(0);
+    assert_eq!(machine.run_resumable().unwrap(), RunState::Exited(0));
+    let total_cycles = machine.cycles();
+
+    // Machines take turns on one thread
+    let mut machines = vec![build(50), build(70)];
+    let mut yields = vec![0; machines.len()];
+    let mut exited = vec![false; machines.len()];
+    while exited.iter().any(|exited| !exited) {
+        for (i, machine) in machines.iter_mut().enumerate() {
+            if exited[i] {
+                continue;
+            }
+            match machine.run_resumable().unwrap() {
+                RunState::Yielded => yields[i] += 1,
+                RunState::Exited(exit_code) => {
+                    assert_eq!(exit_code, 0);
+                    exited[i] = true;
+                }
+                RunState::Paused => unreachable!(),
+            }
+        }
+    }
+    assert_eq!(yields[0], total_cycles / 50);
+    assert_eq!(yields[1], total_cycles / 70);
+    for machine in &machines {
+        assert_eq!(machine.cycles(), total_cycles);
+    }
+}
+
+#[test]
+pub fn test_load_flat_program() {
+    let binary: Vec<u8> = [
+        0x00013503u32, // ld a0, 0(sp)
+        0x05d00893,    // li a7, 93
+        0x00000073,    // ecall
+    ]
+    .iter()
+    .flat_map(|word| word.to_le_bytes().to_vec())
+    .collect();
+    let binary: Bytes = binary.into();
+    let args: Vec<Bytes> = vec!["a".into(), "b".into(), "c".into()];
+
+    let mut machine = TraceMachine::new(
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>>::default()
+            .build(),
+    );
+    let metadata = machine
+        .load_flat_program(0x10010, &binary, 0x10010, &args)
+        .unwrap();
+    assert_eq!(metadata.entry, 0x10010);
+    assert_eq!(metadata.segments, [(0x10010, 0x1001c)]);
+    assert_eq!(metadata.program_break, 0x11000);
+    // argc is on top of the stack as with ELF programs
+    assert_eq!(machine.run(), Ok(3));
+    // The binary cannot be written to
+    assert_eq!(
+        machine.machine.memory_mut().store8(&0x10010, &0),
+        Err(Error::MemoryProtection)
+    );
+
+    let mut machine = DefaultMachine::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default();
+    let end = machine.memory().memory_size() as u64;
+    assert_eq!(
+        machine.load_flat_program(end - 4, &binary, end - 4, &args),
+        Err(Error::MemorySizeExceeded(end + 8, end))
+    );
+}
+
+#[test]
+pub fn test_run_with_deadline() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+    let spin: Bytes = 0x0000006fu32.to_le_bytes().to_vec().into(); // j .
+
+    let mut machine = DefaultMachine::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default();
+    machine.load_program(&buffer, &["simple".into()]).unwrap();
+    let far = Instant::now() + Duration::from_secs(3600);
+    assert_eq!(machine.run_with_deadline(far), Ok(0));
+
+    let mut machine = DefaultMachine::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default();
+    machine
+        .load_flat_program(0x1000, &spin, 0x1000, &["spin".into()])
+        .unwrap();
+    // A deadline already passed stops the machine before it starts
+    assert_eq!(
+        machine.run_with_deadline(Instant::now()),
+        Err(Error::Deadline)
+    );
+    assert_eq!(machine.instructions(), 0);
+    let deadline = Instant::now() + Duration::from_millis(20);
+    assert_eq!(machine.run_with_deadline(deadline), Err(Error::Deadline));
+    assert!(Instant::now() >= deadline);
+    assert!(machine.instructions() > 0);
+    assert_eq!(*machine.pc(), 0x1000);
+
+    let mut machine = TraceMachine::new(
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>>::default()
+            .build(),
+    );
+    machine
+        .load_flat_program(0x1000, &spin, 0x1000, &["spin".into()])
+        .unwrap();
+    let deadline = Instant::now() + Duration::from_millis(20);
+    assert_eq!(machine.run_with_deadline(deadline), Err(Error::Deadline));
+    assert!(Instant::now() >= deadline);
+}
+
+#[test]
+pub fn test_replay_recording() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+    let decoder = build_imac_decoder::<u64>();
+    let new_machine = || {
+        let mut machine =
+            DefaultMachineBuilder::new(DefaultCoreMachine::<u64, SparseMemory<u64>>::default())
+                .instruction_cycle_func(Box::new(|_| 2))
+                .build();
+        machine.load_program(&buffer, &["simple".into()]).unwrap();
+        machine
+    };
+
+    let mut machine = new_machine();
+    machine.set_instruction_recording(true);
+    assert_eq!(machine.run(), Ok(0));
+    let recording = machine.take_instruction_recording().unwrap();
+    assert_eq!(recording.len(), machine.instructions());
+    assert!(machine.instruction_recording().unwrap().is_empty());
+
+    let mut replayed = new_machine();
+    assert_eq!(replayed.replay(&recording, &decoder), Ok(()));
+    assert!(!replayed.running());
+    assert_eq!(replayed.exit_code(), 0);
+    assert_eq!(replayed.cycles(), machine.cycles());
+    assert_eq!(replayed.instructions(), machine.instructions());
+
+    // Trace machines record the same stream
+    let mut trace = TraceMachine::new(
+        DefaultMachineBuilder::new(
+            DefaultCoreMachine::<u64, WXorXMemory<u64, SparseMemory<u64>>>::default(),
+        )
+        .instruction_cycle_func(Box::new(|_| 2))
+        .record_instructions(true)
+        .build(),
+    );
+    trace.load_program(&buffer, &["simple".into()]).unwrap();
+    assert_eq!(trace.run(), Ok(0));
+    assert_eq!(trace.machine.instruction_recording(), Some(&recording));
+
+    let loaded = InstructionRecording::from_bytes(recording.as_bytes().to_vec()).unwrap();
+    assert_eq!(loaded, recording);
+
+    // A recording taken elsewhere that jumps to another pc diverges
+    let mut tampered = InstructionRecording::new();
+    for (index, recorded) in recording.iter().enumerate() {
+        let pc = if index == 5 {
+            recorded.pc + 4
+        } else {
+            recorded.pc
+        };
+        tampered.push(pc, recorded.bits);
+    }
+    let mut replayed = new_machine();
+    assert_eq!(
+        replayed.replay(&tampered, &decoder),
+        Err(Error::ReplayDivergence {
+            index: 5,
+            pc: recording.iter().nth(5).unwrap().pc + 4,
+        })
+    );
+    assert_eq!(replayed.instructions(), 5);
+}
+
+#[test]
+pub fn test_initial_registers_and_entry_override() {
+    // Spins at the entry point, the function at 0x1008 returns a0 + a1 as
+    // exit code
+    let binary: Bytes = [
+        0x0000006fu32, // j .
+        0x00000013,    // nop
+        0x00b50533,    // add a0, a0, a1
+        0x05d00893,    // li a7, 93
+        0x00000073,    // ecall
+    ]
+    .iter()
+    .flat_map(|word| word.to_le_bytes())
+    .collect::<Vec<u8>>()
+    .into();
+    let mut registers = [0; 32];
+    registers[A0] = 20;
+    registers[A1] = 22;
+    registers[0] = 1;
+    let mut machine = DefaultMachineBuilder::new(
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new_with_max_cycles(1000),
+    )
+    .instruction_cycle_func(Box::new(|_| 1))
+    .initial_registers(registers)
+    .entry_override(0x1008)
+    .build();
+    let metadata = machine
+        .load_flat_program(0x1000, &binary, 0x1000, &["test".into()])
+        .unwrap();
+    assert_eq!(metadata.entry, 0x1008);
+    assert_eq!(*machine.pc(), 0x1008);
+    assert_eq!(machine.registers()[0], 0);
+    // A zero sp keeps the stack set up by load_program
+    assert_ne!(machine.registers()[SP], 0);
+    assert_eq!(machine.run(), Ok(42));
+
+    // Both are applied again on every load
+    machine.reset().unwrap();
+    machine
+        .load_flat_program(0x1000, &binary, 0x1000, &["test".into()])
+        .unwrap();
+    assert_eq!(machine.registers()[A1], 22);
+    assert_eq!(machine.run(), Ok(42));
+}
+
+#[test]
+pub fn test_call() {
+    let binary: Bytes = [
+        // 0x1000: a0 + a1
+        0x00b50533u32, // add a0, a0, a1
+        0x00008067,    // ret
+        // 0x1008: a0 + 9th and 10th arguments
+        0x00013283, // ld t0, 0(sp)
+        0x00550533, // add a0, a0, t0
+        0x00813303, // ld t1, 8(sp)
+        0x00650533, // add a0, a0, t1
+        0x00008067, // ret
+        // 0x101c: exits with a0
+        0x05d00893, // li a7, 93
+        0x00000073, // ecall
+    ]
+    .iter()
+    .flat_map(|word| word.to_le_bytes())
+    .collect::<Vec<u8>>()
+    .into();
+    let mut machine = DefaultMachineBuilder::new(
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new_with_max_cycles(1000),
+    )
+    .instruction_cycle_func(Box::new(|_| 1))
+    .build();
+    machine
+        .load_flat_program(0x1000, &binary, 0x1000, &["call".into()])
+        .unwrap();
+    let sp = machine.registers()[SP];
+    assert_eq!(machine.call(0x1000, &[20, 22]), Ok(42));
+    assert_eq!(
+        machine.call(0x1008, &[1, 0, 0, 0, 0, 0, 0, 0, 10, 100]),
+        Ok(111)
+    );
+    assert_eq!(machine.registers()[SP], sp);
+    assert_eq!(machine.cycles(), 2 + 5);
+    assert_eq!(machine.call(0x101c, &[3]), Err(Error::CallExited(3)));
+    assert_eq!(machine.registers()[SP], sp);
+    assert_eq!(machine.call(0x2000, &[]), Err(Error::InvalidInstruction(0)));
+}
+
+#[cfg(feature = "symbols")]
+#[test]
+pub fn test_call_symbol() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default().build();
+    machine.load_program(&buffer, &["simple".into()]).unwrap();
+    assert_eq!(machine.call_symbol("main", &[]), Ok(0));
+    assert_eq!(machine.call_symbol("main", &[]), Ok(0));
+    assert_eq!(
+        machine.call_symbol("missing", &[]),
+        Err(Error::UnknownSymbol)
+    );
+}
+
+#[test]
+pub fn test_max_call_depth() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+    // Calls itself forever without touching the stack
+    let recurse: Bytes = 0x000000efu32.to_le_bytes().to_vec().into(); // jal ra, .
+
+    let mut machine = DefaultMachineBuilder::new(
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new_with_max_cycles(u64::MAX),
+    )
+    .instruction_cycle_func(Box::new(|_| 1))
+    .max_call_depth(100)
+    .build();
+    machine.load_program(&buffer, &["simple".into()]).unwrap();
+    assert_eq!(machine.run(), Ok(0));
+    assert!(machine.call_depth() < 100);
+
+    machine.reset().unwrap();
+    machine
+        .load_flat_program(0x1000, &recurse, 0x1000, &["recurse".into()])
+        .unwrap();
+    assert_eq!(machine.run(), Err(Error::CallDepthExceeded));
+    assert_eq!(machine.call_depth(), 100);
+    // The machine stops right before the call exceeding the limit
+    assert_eq!(machine.instructions(), 100);
+    assert_eq!(machine.cycles(), 100);
+    machine.set_max_call_depth(0);
+    machine.set_cycles(0);
+    machine.set_max_instructions(200);
+    assert_eq!(machine.run(), Err(Error::InstructionLimitExceeded));
+
+    let mut machine = TraceMachine::new(
+        DefaultMachineBuilder::new(
+            DefaultCoreMachine::<u64, WXorXMemory<u64, SparseMemory<u64>>>::new_with_max_cycles(
+                u64::MAX,
+            ),
+        )
+        .instruction_cycle_func(Box::new(|_| 1))
+        .max_call_depth(100)
+        .build(),
+    );
+    machine
+        .load_flat_program(0x1000, &recurse, 0x1000, &["recurse".into()])
+        .unwrap();
+    assert_eq!(machine.run(), Err(Error::CallDepthExceeded));
+    assert_eq!(machine.machine.call_depth(), 100);
+}
+
+#[test]
+pub fn test_display_machine() {
+    let mut file = File::open("tests/programs/invalid_read64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default().build();
+    machine
+        .load_program(&buffer, &["invalid_read".into()])
+        .unwrap();
+    assert!(machine.run().is_err());
+    let fault = machine.last_fault().unwrap();
+    let text = machine.to_string();
+    assert!(text.contains("a0  : 0x"));
+    assert!(text.contains(&format!("fault: {}\n", fault)));
+    assert_eq!(format!("{:?}", machine), text);
+    // The faulting block is read from memory
+    let description = machine.describe();
+    assert!(description.starts_with(&text));
+    assert!(description.contains(&format!(
+        "{:#x}: {}\n",
+        fault.pc,
+        disassemble(fault.instruction.unwrap())
+    )));
+
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+    let program =
+        Arc::new(DecodedProgram::new::<u64>(&buffer, &build_imac_decoder::<u64>()).unwrap());
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default().build();
+    machine.load_program(&buffer, &["simple".into()]).unwrap();
+    machine.set_decoded_program(Some(program));
+    // The entry block comes from the decoded program, describe adds nothing
+    let text = machine.to_string();
+    assert!(text.contains(&format!("0x{:x}: ", *machine.pc())));
+    assert_eq!(machine.describe(), text);
+    assert_eq!(machine.run(), Ok(0));
+    assert!(machine.to_string().contains("exit code: 0\n"));
+}
+
+#[test]
+pub fn test_recording_syscalls() {
+    let binary: Bytes = [
+        0x00002537u32, // lui a0, 0x2
+        0x01000593,    // li a1, 16
+        0x11600893,    // li a7, 278
+        0x00000073,    // ecall
+        0x000022b7,    // lui t0, 0x2
+        0x0052c503,    // lbu a0, 5(t0)
+        0x05d00893,    // li a7, 93
+        0x00000073,    // ecall
+    ]
+    .iter()
+    .flat_map(|word| word.to_le_bytes())
+    .collect::<Vec<u8>>()
+    .into();
+    let mut random = RandomSyscall::new(7);
+    let mut bytes = [0; 16];
+    random.fill_bytes(&mut bytes);
+
+    let recording = RecordingSyscalls::new(RandomSyscall::new(7));
+    let captures = recording.captures();
+    let mut machine = DefaultMachineBuilder::new(
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new_with_max_cycles(1000),
+    )
+    .instruction_cycle_func(Box::new(|_| 1))
+    .syscall(Box::new(recording))
+    .build();
+    machine
+        .load_flat_program(0x1000, &binary, 0x1000, &["random".into()])
+        .unwrap();
+    assert_eq!(machine.run(), Ok(bytes[5] as i8));
+    let cycles = machine.cycles();
+    drop(machine);
+    let captures = captures.borrow().clone();
+    assert_eq!(captures.len(), 1);
+    assert_eq!(captures[0].number, 278);
+    assert_eq!(captures[0].args[..2], [0x2000, 16]);
+    assert_eq!(captures[0].registers, [(A0, 16)]);
+    // The page was clean before the ecall, so it is captured whole
+    assert_eq!(captures[0].writes.len(), 1);
+    assert_eq!(captures[0].writes[0].0, 0x2000);
+    assert_eq!(captures[0].writes[0].1[..16], bytes);
+    assert_eq!(captures[0].result, Ok(true));
+
+    // Replaying needs no random generator
+    let mut machine = DefaultMachineBuilder::new(
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new_with_max_cycles(1000),
+    )
+    .instruction_cycle_func(Box::new(|_| 1))
+    .syscall(Box::new(ReplaySyscalls::new(captures.clone())))
+    .build();
+    machine
+        .load_flat_program(0x1000, &binary, 0x1000, &["random".into()])
+        .unwrap();
+    assert_eq!(machine.run(), Ok(bytes[5] as i8));
+    assert_eq!(machine.cycles(), cycles);
+
+    let mut diverged = captures;
+    diverged[0].args[1] = 8;
+    let mut machine = DefaultMachineBuilder::new(
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new_with_max_cycles(1000),
+    )
+    .syscall(Box::new(ReplaySyscalls::new(diverged)))
+    .build();
+    machine
+        .load_flat_program(0x1000, &binary, 0x1000, &["random".into()])
+        .unwrap();
+    assert_eq!(
+        machine.run(),
+        Err(Error::ReplayDivergence {
+            index: 0,
+            pc: 0x100c
+        })
+    );
+}
+
+#[cfg(feature = "async")]
+#[test]
+pub fn test_run_async() {
+    use ckb_vm::syscalls::{AsyncSyscalls, EcallFuture};
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::task::{Context, Poll, Wake, Waker};
+
+    struct NoopWaker;
+
+    impl Wake for NoopWaker {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let waker = Waker::from(Arc::new(NoopWaker));
+        let mut context = Context::from_waker(&waker);
+        let mut future = Box::pin(future);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+                return output;
+            }
+        }
+    }
+
+    // Resolves on the second poll, as if waiting on I/O
+    struct Delay(bool);
+
+    impl Future for Delay {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+            if self.0 {
+                return Poll::Ready(());
+            }
+            self.0 = true;
+            context.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+
+    struct DoubleSyscall {
+        calls: u64,
+    }
+
+    impl<Mac: SupportMachine> AsyncSyscalls<Mac> for DoubleSyscall {
+        fn ecall<'a>(&'a mut self, machine: &'a mut Mac) -> EcallFuture<'a> {
+            Box::pin(async move {
+                if machine.registers()[A7].to_u64() != 1000 {
+                    return Ok(false);
+                }
+                Delay(false).await;
+                self.calls += 1;
+                let value = machine.registers()[A0].to_u64() * 2;
+                machine.set_register(A0, Mac::REG::from_u64(value));
+                Ok(true)
+            })
+        }
+    }
+
+    let program = |number: u32| -> Bytes {
+        [
+            0x01500513u32,               // li a0, 21
+            (number << 20) | 0x00000893, // li a7, number
+            0x00000073,                  // ecall
+            0x05d00893,                  // li a7, 93
+            0x00000073,                  // ecall
+        ]
+        .iter()
+        .flat_map(|word| word.to_le_bytes())
+        .collect::<Vec<u8>>()
+        .into()
+    };
+    let mut syscalls = DoubleSyscall { calls: 0 };
+    let hooked = Arc::new(AtomicU64::new(0));
+    let machine_hooked = Arc::clone(&hooked);
+    let mut machine = DefaultMachineBuilder::new(
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new_with_max_cycles(1000),
+    )
+    .instruction_cycle_func(Box::new(|_| 1))
+    .opcode_stats(true)
+    .on_instruction(Box::new(move |_, _, _| {
+        machine_hooked.fetch_add(1, Ordering::SeqCst);
+    }))
+    .build();
+    machine
+        .load_flat_program(0x1000, &program(1000), 0x1000, &["async".into()])
+        .unwrap();
+    assert_eq!(block_on(machine.run_async(&mut syscalls)), Ok(42));
+    assert_eq!(syscalls.calls, 1);
+    // The ecall is charged, counted and hooked once although it blocked
+    assert_eq!(machine.instructions(), 5);
+    assert_eq!(machine.cycles(), 5);
+    assert_eq!(hooked.load(Ordering::SeqCst), 5);
+    let ecalls = INSTRUCTION_OPCODE_NAMES
+        .iter()
+        .position(|name| *name == "ECALL")
+        .unwrap();
+    let stats = machine.opcode_stats().unwrap();
+    assert_eq!(stats.iter().sum::<u64>(), 5);
+    assert_eq!(stats[ecalls], 2);
+
+    machine.reset().unwrap();
+    machine
+        .load_flat_program(0x1000, &program(1001), 0x1000, &["async".into()])
+        .unwrap();
+    assert_eq!(
+        block_on(machine.run_async(&mut syscalls)),
+        Err(Error::InvalidEcall(1001))
+    );
+    // Outside of run_async, the syscall is unknown
+    machine.reset().unwrap();
+    machine
+        .load_flat_program(0x1000, &program(1000), 0x1000, &["async".into()])
+        .unwrap();
+    assert_eq!(machine.run(), Err(Error::InvalidEcall(1000)));
+}
+
+#[test]
+pub fn test_interrupt_handle() {
+    let spin: Bytes = 0x0000006fu32.to_le_bytes().to_vec().into(); // j .
+    let stop_later = |handle: InterruptHandle| {
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            handle.stop();
+        })
+    };
+
+    let mut machine = DefaultMachine::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default();
+    machine
+        .load_flat_program(0x1000, &spin, 0x1000, &["spin".into()])
+        .unwrap();
+    let handle = machine.interrupt_handle();
+    let stopper = stop_later(handle.clone());
+    assert_eq!(machine.run(), Err(Error::Interrupted));
+    stopper.join().unwrap();
+    assert!(!handle.is_stop_requested());
+    assert!(machine.instructions() > 0);
+    assert_eq!(*machine.pc(), 0x1000);
+
+    // A request made beforehand stops the next run after one block
+    handle.stop();
+    let instructions = machine.instructions();
+    assert_eq!(machine.run(), Err(Error::Interrupted));
+    assert_eq!(machine.instructions(), instructions + 1);
+    // Reset drops pending requests
+    handle.stop();
+    machine.reset().unwrap();
+    assert!(!handle.is_stop_requested());
+
+    let mut machine = TraceMachine::new(
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>>::default()
+            .build(),
+    );
+    machine
+        .load_flat_program(0x1000, &spin, 0x1000, &["spin".into()])
+        .unwrap();
+    let stopper = stop_later(machine.machine.interrupt_handle());
+    assert_eq!(machine.run(), Err(Error::Interrupted));
+    stopper.join().unwrap();
+}
+
+// Stands in for a cryptographic hash such as blake2b
+fn commitment_hash(data: &[u8]) -> [u8; 32] {
+    let mut result = [0; 32];
+    for (i, chunk) in result.chunks_mut(8).enumerate() {
+        let mut hasher = DefaultHasher::new();
+        hasher.write_usize(i);
+        hasher.write(data);
+        chunk.copy_from_slice(&hasher.finish().to_le_bytes());
+    }
+    result
+}
+
+#[test]
+pub fn test_state_commitment() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+    let builder = |granularity| {
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>>::default()
+            .state_commitment(Box::new(commitment_hash), granularity)
+    };
+    let run = |granularity, args: &[Bytes]| {
+        let mut machine = builder(granularity).build();
+        machine.load_program(&buffer, args).unwrap();
+        assert_eq!(machine.run(), Ok(0));
+        (
+            machine.state_commitment().unwrap(),
+            machine.state_commitment_steps().unwrap(),
+            machine.instructions(),
+        )
+    };
+
+    let (commitment, blocks, instructions) = run(CommitmentGranularity::Block, &["simple".into()]);
+    assert_ne!(commitment, [0; 32]);
+    assert!(blocks > 0 && blocks < instructions);
+    assert_eq!(
+        run(CommitmentGranularity::Block, &["simple".into()]),
+        (commitment, blocks, instructions)
+    );
+    // Arguments end up on the stack, which the program reads
+    assert_ne!(
+        run(CommitmentGranularity::Block, &["simple2".into()]).0,
+        commitment
+    );
+    let (by_instruction, steps, _) = run(CommitmentGranularity::Instruction, &["simple".into()]);
+    assert_eq!(steps, instructions);
+    assert_ne!(by_instruction, commitment);
+
+    // Trace machines commit the same transitions
+    let mut machine = TraceMachine::new(builder(CommitmentGranularity::Block).build());
+    machine.load_program(&buffer, &["simple".into()]).unwrap();
+    assert_eq!(machine.run(), Ok(0));
+    assert_eq!(machine.machine.state_commitment(), Some(commitment));
+
+    // The commitment only moves at the end of basic blocks
+    let decoder = build_imac_decoder::<u64>();
+    let mut machine = builder(CommitmentGranularity::Block).build();
+    machine.load_program(&buffer, &["simple".into()]).unwrap();
+    let mut previous = machine.state_commitment();
+    machine.set_running(true);
+    while machine.running() {
+        let instruction = machine.step(&decoder).unwrap();
+        let current = machine.state_commitment();
+        assert_eq!(
+            current != previous,
+            ckb_vm::instructions::is_basic_block_end_instruction(instruction)
+        );
+        previous = current;
+    }
+    assert_eq!(previous, Some(commitment));
+    machine.reset().unwrap();
+    assert_eq!(machine.state_commitment(), Some([0; 32]));
+    assert_eq!(machine.state_commitment_steps(), Some(0));
 }
@@ -0,0 +1,43 @@
+// Runs programs with the std feature disabled, see `make check-no-std`.
+#![cfg(not(feature = "std"))]
+
+use bytes::Bytes;
+use ckb_vm::{
+    run, DefaultCoreMachine, DefaultMachineBuilder, Error, FlatMemory, SparseMemory, WXorXMemory,
+};
+use std::fs::File;
+use std::io::Read;
+
+fn load(path: &str) -> Bytes {
+    let mut file = File::open(path).unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    buffer.into()
+}
+
+#[test]
+pub fn test_no_std_run() {
+    let buffer = load("tests/programs/simple");
+    assert_eq!(
+        run::<u32, SparseMemory<u32>>(&buffer, &["simple".into()]),
+        Ok(0)
+    );
+    let buffer = load("tests/programs/simple64");
+    assert_eq!(
+        run::<u64, FlatMemory<u64>>(&buffer, &["simple".into()]),
+        Ok(0)
+    );
+}
+
+#[test]
+pub fn test_no_std_machine() {
+    let buffer = load("tests/programs/simple64");
+    let mut machine = DefaultMachineBuilder::new(DefaultCoreMachine::<
+        u64,
+        WXorXMemory<u64, SparseMemory<u64>>,
+    >::new_with_max_cycles(10))
+    .instruction_cycle_func(Box::new(|_| 1))
+    .build();
+    machine.load_program(&buffer, &["simple".into()]).unwrap();
+    assert_eq!(machine.run(), Err(Error::CyclesExceeded));
+}
@@ -0,0 +1,132 @@
+extern crate ckb_vm;
+
+use ckb_vm::{
+    decoder::build_imac_decoder,
+    instructions::{execute, i, m, rvc},
+    CoreMachine, DefaultCoreMachine, DefaultMachine, Memory, SparseMemory,
+};
+
+type Machine32<'a> = DefaultMachine<'a, DefaultCoreMachine<u32, SparseMemory<u32>>>;
+
+// Encodes an R-type instruction with rd = a0, rs1 = a1 and rs2 = a2
+fn rtype(funct7: u32, funct3: u32, opcode: u32) -> u32 {
+    (funct7 << 25) | (12 << 20) | (11 << 15) | (funct3 << 12) | (10 << 7) | opcode
+}
+
+// Encodes an I-type instruction with rd = a0 and rs1 = a1
+fn itype(imm: u32, funct3: u32, opcode: u32) -> u32 {
+    ((imm & 0xFFF) << 20) | (11 << 15) | (funct3 << 12) | (10 << 7) | opcode
+}
+
+fn run(bits: u32, a1: u32, a2: u32) -> u32 {
+    let mut machine = Machine32::default();
+    machine.set_register(11, a1);
+    machine.set_register(12, a2);
+    let mut memory = SparseMemory::<u32>::default();
+    memory.store32(&0, &bits).unwrap();
+    let instruction = build_imac_decoder::<u32>()
+        .decode(&mut memory, 0)
+        .expect("decoding");
+    execute(instruction, &mut machine).unwrap();
+    machine.registers()[10]
+}
+
+// Test vectors below are taken from the rv32ui and rv32um suites of
+// riscv-tests.
+#[test]
+pub fn test_rv32ui() {
+    let add = rtype(0, 0b_000, 0b_0110011);
+    assert_eq!(run(add, 0x7fff_ffff, 0x0000_0001), 0x8000_0000);
+    assert_eq!(run(add, 0xffff_ffff, 0x0000_0001), 0);
+    let sub = rtype(0b_0100000, 0b_000, 0b_0110011);
+    assert_eq!(run(sub, 0x0000_0000, 0xffff_8000), 0x0000_8000);
+    let sll = rtype(0, 0b_001, 0b_0110011);
+    assert_eq!(run(sll, 0x2122_2222, 0xffff_ffe0), 0x2122_2222);
+    assert_eq!(run(sll, 0x2122_2222, 0xffff_ffff), 0x0000_0000);
+    let srl = rtype(0, 0b_101, 0b_0110011);
+    assert_eq!(run(srl, 0x8000_0000, 0x0000_001f), 0x0000_0001);
+    assert_eq!(run(srl, 0x8000_0000, 0xffff_ffe1), 0x4000_0000);
+    let sra = rtype(0b_0100000, 0b_101, 0b_0110011);
+    assert_eq!(run(sra, 0x8000_0000, 0x0000_0001), 0xc000_0000);
+    assert_eq!(run(sra, 0x8182_8384, 0xffff_ffff), 0xffff_ffff);
+    let slt = rtype(0, 0b_010, 0b_0110011);
+    assert_eq!(run(slt, 0x8000_0000, 0x7fff_ffff), 1);
+    let sltu = rtype(0, 0b_011, 0b_0110011);
+    assert_eq!(run(sltu, 0x8000_0000, 0x7fff_ffff), 0);
+
+    let addi = itype(0x800, 0b_000, 0b_0010011);
+    assert_eq!(run(addi, 0x0000_0000, 0), 0xffff_f800);
+    let srai = itype(0b_0100000_11111, 0b_101, 0b_0010011);
+    assert_eq!(run(srai, 0x8000_0000, 0), 0xffff_ffff);
+    let slli = itype(31, 0b_001, 0b_0010011);
+    assert_eq!(run(slli, 0x0000_0001, 0), 0x8000_0000);
+    let sltiu = itype(0xfff, 0b_011, 0b_0010011);
+    assert_eq!(run(sltiu, 0x0000_0000, 0), 1);
+
+    // lui a0, 0xfffff
+    assert_eq!(run(0xfffff537, 0, 0), 0xffff_f000);
+}
+
+#[test]
+pub fn test_rv32um() {
+    let mul = rtype(1, 0b_000, 0b_0110011);
+    assert_eq!(run(mul, 0x0000_7e00, 0xb6db_6db7), 0x0000_1200);
+    let mulh = rtype(1, 0b_001, 0b_0110011);
+    assert_eq!(run(mulh, 0x8000_0000, 0xffff_8000), 0x0000_4000);
+    assert_eq!(run(mulh, 0xaaaa_aaab, 0x0002_fe7d), 0xffff_0081);
+    let mulhsu = rtype(1, 0b_010, 0b_0110011);
+    assert_eq!(run(mulhsu, 0x8000_0000, 0xffff_8000), 0x8000_4000);
+    assert_eq!(run(mulhsu, 0xaaaa_aaab, 0x0002_fe7d), 0xffff_0081);
+    let mulhu = rtype(1, 0b_011, 0b_0110011);
+    assert_eq!(run(mulhu, 0xaaaa_aaab, 0x0002_fe7d), 0x0001_fefe);
+    assert_eq!(run(mulhu, 0xff00_0000, 0xff00_0000), 0xfe01_0000);
+
+    let div = rtype(1, 0b_100, 0b_0110011);
+    assert_eq!(run(div, -20i32 as u32, 6), -3i32 as u32);
+    assert_eq!(run(div, 0x8000_0000, 0xffff_ffff), 0x8000_0000);
+    assert_eq!(run(div, 1, 0), 0xffff_ffff);
+    let divu = rtype(1, 0b_101, 0b_0110011);
+    assert_eq!(run(divu, -20i32 as u32, 6), 715_827_879);
+    assert_eq!(run(divu, 1, 0), 0xffff_ffff);
+    let rem = rtype(1, 0b_110, 0b_0110011);
+    assert_eq!(run(rem, -20i32 as u32, 6), -2i32 as u32);
+    assert_eq!(run(rem, 0x8000_0000, 0xffff_ffff), 0);
+    assert_eq!(run(rem, -20i32 as u32, 0), -20i32 as u32);
+    let remu = rtype(1, 0b_111, 0b_0110011);
+    assert_eq!(run(remu, -20i32 as u32, 6), 2);
+    assert_eq!(run(remu, -20i32 as u32, 0), -20i32 as u32);
+}
+
+#[test]
+pub fn test_rv32_rejects_rv64_only_instructions() {
+    let rv64_only = [
+        // ld, sd, lwu
+        itype(0, 0b_011, 0b_0000011),
+        (12 << 20) | (11 << 15) | (0b_011 << 12) | 0b_0100011,
+        itype(0, 0b_110, 0b_0000011),
+        // addiw, slliw, addw, subw
+        itype(1, 0b_000, 0b_0011011),
+        itype(1, 0b_001, 0b_0011011),
+        rtype(0, 0b_000, 0b_0111011),
+        rtype(0b_0100000, 0b_000, 0b_0111011),
+        // slli, srli and srai with shamt[5] set
+        itype(32, 0b_001, 0b_0010011),
+        itype(32, 0b_101, 0b_0010011),
+        itype(0b_010000_100000, 0b_101, 0b_0010011),
+    ];
+    for bits in &rv64_only {
+        assert!(i::factory::<u32>(*bits).is_none(), "{:08x}", bits);
+        assert!(i::factory::<u64>(*bits).is_some(), "{:08x}", bits);
+    }
+    // mulw, divw, remuw
+    for funct3 in &[0b_000, 0b_100, 0b_111] {
+        let bits = rtype(1, *funct3, 0b_0111011);
+        assert!(m::factory::<u32>(bits).is_none());
+        assert!(m::factory::<u64>(bits).is_some());
+    }
+    // c.ld, c.sd, c.ldsp, c.sdsp, c.subw, c.addw
+    for bits in &[0x6198, 0xe198, 0x6502, 0xe02a, 0x9d1d, 0x9d3d] {
+        assert!(rvc::factory::<u32>(*bits).is_none(), "{:04x}", bits);
+        assert!(rvc::factory::<u64>(*bits).is_some(), "{:04x}", bits);
+    }
+}
@@ -2,11 +2,19 @@ extern crate ckb_vm;
 
 use bytes::Bytes;
 use ckb_vm::{
-    run, DefaultCoreMachine, DefaultMachine, DefaultMachineBuilder, Error, FlatMemory, Instruction,
-    SparseMemory, SupportMachine,
+    cycle_model,
+    decoder::build_imac_decoder,
+    instructions::{memory_op, MemoryOp},
+    machine::{auxv, isa},
+    registers::SP,
+    run, CoreMachine, DefaultCoreMachine, DefaultMachine, DefaultMachineBuilder, Error, ExitReason,
+    FlatMemory, Instruction, MachineIsa, Memory, RunState, SparseMemory, SupportMachine,
+    TraceMachine, WXorXMemory, WatchpointKind, DEFAULT_STACK_SIZE, RISCV_MAX_MEMORY,
 };
+use std::cell::RefCell;
 use std::fs::File;
 use std::io::Read;
+use std::rc::Rc;
 
 #[test]
 pub fn test_simple_instructions() {
@@ -88,7 +96,7 @@ pub fn test_simple_max_cycles_reached() {
         .unwrap();
     let result = machine.run();
     assert!(result.is_err());
-    assert_eq!(result.unwrap_err(), Error::InvalidCycles);
+    assert_eq!(result.unwrap_err(), Error::CyclesExceeded);
 }
 
 #[test]
@@ -111,8 +119,874 @@ pub fn test_simple_loaded_bytes() {
     let buffer: Bytes = buffer.into();
 
     let mut machine = DefaultMachine::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default();
-    let bytes = machine
+    let metadata = machine
         .load_program(&buffer, &vec!["simple".into()])
         .unwrap();
-    assert_eq!(bytes, 4055);
+    assert_eq!(metadata.bytes, 4055);
+}
+
+#[test]
+pub fn test_simple_program_metadata() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let mut machine = DefaultMachine::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default();
+    assert_eq!(machine.program_metadata(), None);
+    let metadata = machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    assert_eq!(metadata.entry, 0x100b0);
+    assert_eq!(machine.pc(), &metadata.entry);
+    assert_eq!(metadata.segments, [(0x10000, 0x1084c), (0x1184c, 0x11ff8)]);
+    assert_eq!(metadata.program_break, 0x12000);
+    assert_eq!(metadata.stack_top, RISCV_MAX_MEMORY as u64);
+    assert!(machine.registers()[SP] < metadata.stack_top);
+    assert_eq!(machine.program_metadata(), Some(&metadata));
+    machine.reset().unwrap();
+    assert_eq!(machine.program_metadata(), None);
+}
+
+#[test]
+pub fn test_simple_program_validator() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let validated = Rc::new(RefCell::new(Vec::new()));
+    let validator_validated = Rc::clone(&validated);
+    let expected = buffer.clone();
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default()
+            .program_validator(Box::new(move |program, metadata| {
+                assert_eq!(program, &expected[..]);
+                validator_validated.borrow_mut().push(metadata.entry);
+                // Only programs fitting in a single page of code are accepted
+                if metadata.segments[0].1 - metadata.segments[0].0 > 0x1000 {
+                    return Err(Error::Unexpected);
+                }
+                Ok(())
+            }))
+            .build();
+    let metadata = machine.load_program(&buffer, &["simple".into()]).unwrap();
+    assert_eq!(*validated.borrow(), [metadata.entry]);
+    assert_eq!(machine.run().unwrap(), 0);
+
+    // A rejected program fails to load and leaves no metadata behind
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default()
+            .program_validator(Box::new(|_, metadata| {
+                if metadata.bytes > 1024 {
+                    Err(Error::Unexpected)
+                } else {
+                    Ok(())
+                }
+            }))
+            .build();
+    assert_eq!(
+        machine.load_program(&buffer, &["simple".into()]),
+        Err(Error::Unexpected)
+    );
+    assert_eq!(machine.program_metadata(), None);
+}
+
+#[test]
+pub fn test_simple_machine_isa() {
+    let simple: Bytes = std::fs::read("tests/programs/simple64").unwrap().into();
+    // Built with RVC and the double float ABI
+    let trace: Bytes = std::fs::read("tests/programs/trace64").unwrap().into();
+    assert_eq!(
+        MachineIsa::of_program(&simple).unwrap(),
+        MachineIsa::new(64, isa::ISA_I | isa::ISA_C, 0)
+    );
+
+    let imac = MachineIsa::new(64, isa::ISA_IMAC, 1);
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default()
+            .isa(imac)
+            .build();
+    assert_eq!(machine.isa(), Some(imac));
+    machine.load_program(&simple, &["simple".into()]).unwrap();
+    assert_eq!(machine.run().unwrap(), 0);
+
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default()
+            .isa(imac)
+            .build();
+    assert_eq!(
+        machine.load_program(&trace, &["trace".into()]),
+        Err(Error::UnsupportedIsa {
+            xlen: 64,
+            extensions: isa::ISA_I | isa::ISA_C | isa::ISA_F | isa::ISA_D
+        })
+    );
+    assert_eq!(machine.program_metadata(), None);
+
+    // Neither compressed instructions nor a 32 bit machine
+    let rv32 = MachineIsa::new(32, isa::ISA_IMAC, 0);
+    assert!(rv32.check_program(&simple).is_err());
+    let uncompressed = MachineIsa::new(64, isa::ISA_I | isa::ISA_M, 0);
+    assert_eq!(
+        uncompressed.check_program(&simple),
+        Err(Error::UnsupportedIsa {
+            xlen: 64,
+            extensions: isa::ISA_I | isa::ISA_C
+        })
+    );
+}
+
+#[test]
+pub fn test_simple_trace_machine_on_instruction() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let executed = Rc::new(RefCell::new(Vec::new()));
+    let hook_executed = Rc::clone(&executed);
+    let mut machine = TraceMachine::new(
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>>::default()
+            .instruction_cycle_func(Box::new(dummy_cycle_func))
+            .build(),
+    );
+    machine.set_on_instruction(Box::new(move |pc, _instruction, _machine| {
+        hook_executed.borrow_mut().push(pc);
+    }));
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    let entry = *machine.machine.pc();
+    let result = machine.run();
+    assert!(result.is_ok());
+    assert_eq!(result.unwrap(), 0);
+
+    let executed = executed.borrow();
+    assert_eq!(
+        executed.len() as u64,
+        SupportMachine::cycles(&machine.machine)
+    );
+    assert_eq!(executed[0], entry);
+}
+
+#[test]
+pub fn test_simple_default_machine_on_instruction() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let executed = Rc::new(RefCell::new(Vec::new()));
+    let hook_executed = Rc::clone(&executed);
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default()
+            .instruction_cycle_func(Box::new(dummy_cycle_func))
+            .on_instruction(Box::new(
+                move |pc, instruction, machine: &DefaultCoreMachine<u64, SparseMemory<u64>>| {
+                    // Hook is invoked before the instruction is executed
+                    assert_eq!(*machine.pc(), pc);
+                    hook_executed.borrow_mut().push((
+                        pc,
+                        instruction,
+                        SupportMachine::cycles(machine),
+                    ));
+                },
+            ))
+            .build();
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    let entry = *machine.pc();
+    let result = machine.run();
+    assert!(result.is_ok());
+    assert_eq!(result.unwrap(), 0);
+
+    let executed = executed.borrow();
+    assert_eq!(executed.len() as u64, SupportMachine::cycles(&machine));
+    assert_eq!(executed[0].0, entry);
+    for (i, (_, _, cycles)) in executed.iter().enumerate() {
+        assert_eq!(*cycles, i as u64 + 1);
+    }
+}
+
+#[test]
+pub fn test_simple_step() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default()
+            .instruction_cycle_func(Box::new(dummy_cycle_func))
+            .build();
+    machine.load_program(&buffer, &["simple".into()]).unwrap();
+    let decoder = build_imac_decoder::<u64>();
+    machine.set_running(true);
+    while machine.running() {
+        let pc = *machine.pc();
+        let expected = decoder.decode(machine.memory_mut(), pc).unwrap();
+        let cycles = SupportMachine::cycles(&machine);
+        let instruction = machine.step(&decoder).unwrap();
+        assert_eq!(instruction, expected);
+        assert_eq!(
+            SupportMachine::cycles(&machine),
+            cycles + dummy_cycle_func(instruction)
+        );
+    }
+    assert_eq!(machine.exit_code(), 0);
+    assert_eq!(SupportMachine::cycles(&machine), 517);
+    assert_eq!(machine.instructions(), 517);
+}
+
+#[test]
+pub fn test_simple_trace_machine_step() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let mut machine = TraceMachine::new(
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>>::default()
+            .instruction_cycle_func(Box::new(dummy_cycle_func))
+            .build(),
+    );
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    let decoder = build_imac_decoder::<u64>();
+    machine.machine.set_running(true);
+    let mut steps = 0;
+    while machine.machine.running() {
+        let cycles = SupportMachine::cycles(&machine.machine);
+        machine.step(&decoder).unwrap();
+        assert!(SupportMachine::cycles(&machine.machine) > cycles);
+        steps += 1;
+    }
+    assert!(steps > 1);
+    assert_eq!(machine.machine.exit_code(), 0);
+    assert_eq!(SupportMachine::cycles(&machine.machine), 517);
+}
+
+#[test]
+pub fn test_simple_trace_machine_with_capacity() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let mut machine = TraceMachine::with_capacity(
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>>::default()
+            .instruction_cycle_func(Box::new(dummy_cycle_func))
+            .build(),
+        4,
+        2,
+    );
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    let result = machine.run();
+    assert!(result.is_ok());
+    assert_eq!(result.unwrap(), 0);
+    assert_eq!(SupportMachine::cycles(&machine.machine), 517);
+}
+
+#[test]
+pub fn test_simple_trace_machine_max_cycles_reached() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let mut machine = TraceMachine::new(
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>>::default()
+            .instruction_cycle_func(Box::new(dummy_cycle_func))
+            .max_cycles(500)
+            .build(),
+    );
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    let result = machine.run();
+    assert_eq!(result.unwrap_err(), Error::CyclesExceeded);
+    // Cycles are charged per instruction, so execution stops in the middle
+    // of a trace right at the limit.
+    assert_eq!(SupportMachine::cycles(&machine.machine), 500);
+}
+
+#[test]
+pub fn test_simple_zero_max_cycles_means_unlimited() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let core_machine = DefaultCoreMachine::<u64, SparseMemory<u64>>::new_with_max_cycles(0);
+    assert_eq!(core_machine.max_cycles(), None);
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::new(core_machine)
+            .instruction_cycle_func(Box::new(dummy_cycle_func))
+            .build();
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    let result = machine.run();
+    assert_eq!(result.unwrap(), 0);
+    assert_eq!(SupportMachine::cycles(&machine), 517);
+}
+
+#[test]
+pub fn test_simple_cycle_model() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let core_machine = DefaultCoreMachine::<u64, SparseMemory<u64>>::new_with_max_cycles(0);
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::new(core_machine)
+            .instruction_cycle_func(Box::new(cycle_model::ckb2019))
+            .build();
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    let result = machine.run();
+    assert_eq!(result.unwrap(), 0);
+    assert_eq!(SupportMachine::cycles(&machine), 1695);
+}
+
+#[test]
+pub fn test_simple_trace_machine_profile() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let mut machine = TraceMachine::new(
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>>::default()
+            .instruction_cycle_func(Box::new(dummy_cycle_func))
+            .build(),
+    );
+    assert!(!machine.profiling());
+    machine.set_profiling(true);
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    let entry = *machine.machine.pc();
+    let result = machine.run();
+    assert_eq!(result.unwrap(), 0);
+
+    let profile = machine.profile();
+    assert!(!profile.is_empty());
+    assert!(profile.windows(2).all(|w| w[0].0 < w[1].0));
+    assert!(profile
+        .iter()
+        .any(|(address, hits, _)| *address == entry && *hits == 1));
+    assert!(profile
+        .iter()
+        .all(|(_, hits, cycles)| *hits > 0 && *cycles >= *hits));
+    let total: u64 = profile.iter().map(|(_, _, cycles)| cycles).sum();
+    assert_eq!(total, 517);
+
+    machine.set_profiling(false);
+    assert!(machine.profile().is_empty());
+}
+
+#[test]
+pub fn test_simple_watchpoint() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let mut machine = TraceMachine::new(
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>>::default()
+            .build(),
+    );
+    machine.load_program(&buffer, &["simple".into()]).unwrap();
+    let stack_start = (RISCV_MAX_MEMORY - DEFAULT_STACK_SIZE) as u64;
+    let stack_size = DEFAULT_STACK_SIZE as u64;
+    machine
+        .memory_mut()
+        .add_watchpoint(stack_start, stack_size, WatchpointKind::Write)
+        .unwrap();
+    let result = machine.run();
+    let (addr, pc) = match result {
+        Err(Error::Watchpoint { addr, pc }) => (addr, pc),
+        _ => panic!("unexpected result {:?}", result),
+    };
+    assert!(addr >= stack_start);
+    // Execution stops right at the instruction making the access
+    assert_eq!(pc, *machine.machine.pc());
+
+    machine
+        .memory_mut()
+        .remove_watchpoint(stack_start, stack_size, WatchpointKind::Write)
+        .unwrap();
+    let result = machine.run();
+    assert_eq!(result.unwrap(), 0);
+}
+
+#[test]
+pub fn test_simple_run_with_result() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default()
+            .instruction_cycle_func(Box::new(dummy_cycle_func))
+            .build();
+    machine.load_program(&buffer, &["simple".into()]).unwrap();
+    let result = machine.run_with_result();
+    assert_eq!(result.reason, ExitReason::Exit);
+    assert_eq!(result.exit_code, 0);
+    assert_eq!(result.cycles, 517);
+    assert!(result.touched_memory > 0);
+    assert_eq!(result.touched_memory, machine.memory().touched_memory());
+    assert_eq!(result.into_result(), Ok(0));
+
+    let core_machine = DefaultCoreMachine::<u64, SparseMemory<u64>>::new_with_max_cycles(500);
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::new(core_machine)
+            .instruction_cycle_func(Box::new(dummy_cycle_func))
+            .build();
+    machine.load_program(&buffer, &["simple".into()]).unwrap();
+    let result = machine.run_with_result();
+    assert_eq!(result.reason, ExitReason::CyclesExceeded);
+    assert_eq!(result.cycles, 500);
+    assert_eq!(result.into_result(), Err(Error::CyclesExceeded));
+}
+
+#[test]
+pub fn test_simple_trace_machine_run_with_result() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let mut machine = TraceMachine::new(
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>>::default()
+            .instruction_cycle_func(Box::new(dummy_cycle_func))
+            .build(),
+    );
+    machine.load_program(&buffer, &["simple".into()]).unwrap();
+    machine
+        .memory_mut()
+        .add_watchpoint(0, u64::MAX, WatchpointKind::Write)
+        .unwrap();
+    let result = machine.run_with_result();
+    let error = match result.reason {
+        ExitReason::Trap(e @ Error::Watchpoint { .. }) => e,
+        _ => panic!("unexpected result {:?}", result),
+    };
+    assert!(result.cycles > 0 && result.cycles < 517);
+    assert_eq!(result.into_result(), Err(error));
+}
+
+#[test]
+pub fn test_simple_trace_machine_coverage() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let run_with_coverage = |profiling: bool| {
+        let mut machine = TraceMachine::new(
+            DefaultMachineBuilder::<DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>>::default()
+                .build(),
+        );
+        assert!(machine.coverage().is_none());
+        machine.set_coverage(true);
+        machine.set_profiling(profiling);
+        machine
+            .load_program(&buffer, &vec!["simple".into()])
+            .unwrap();
+        let entry = *machine.machine.pc();
+        assert_eq!(machine.run().unwrap(), 0);
+        let coverage = machine.take_coverage().unwrap();
+        assert!(coverage.contains(entry));
+        assert!(machine.coverage().unwrap().blocks().is_empty());
+        (coverage, machine.profile())
+    };
+
+    let (coverage, profile) = run_with_coverage(true);
+    // Covered blocks are exactly the profiled ones
+    let profiled: Vec<u64> = profile.iter().map(|(address, _, _)| *address).collect();
+    assert_eq!(coverage.blocks(), profiled);
+
+    // Running the same program again covers nothing new
+    let (other, _) = run_with_coverage(false);
+    let mut merged = coverage.clone();
+    assert_eq!(merged.merge(&other), 0);
+    assert_eq!(merged, coverage);
+}
+
+#[test]
+pub fn test_simple_load_program_with_env() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let mut machine = TraceMachine::new(
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>>::default()
+            .build(),
+    );
+    let random = [7u8; 16];
+    machine
+        .load_program_with_env(
+            &buffer,
+            &vec!["simple".into()],
+            &vec!["HOME=/".into(), "TERM=dumb".into()],
+            &random,
+        )
+        .unwrap();
+    let sp = machine.registers()[SP];
+    assert_eq!(sp % 16, 0);
+
+    let mut words = vec![];
+    let mut addr = sp;
+    // argc, argv, envp and the auxiliary vector up to AT_NULL
+    loop {
+        let word = machine.memory_mut().load64(&addr).unwrap();
+        words.push(word);
+        addr += 8;
+        if words.len() >= 8 && words.len() % 2 == 0 && words[words.len() - 2] == 0 {
+            break;
+        }
+    }
+    let read_string = |memory: &mut WXorXMemory<u64, SparseMemory<u64>>, addr: u64| {
+        let len = (RISCV_MAX_MEMORY as u64 - addr).min(16);
+        let bytes = memory.load_bytes(addr, len).unwrap();
+        let end = bytes.iter().position(|b| *b == 0).unwrap();
+        String::from_utf8(bytes[..end].to_vec()).unwrap()
+    };
+    assert_eq!(words[0], 1);
+    assert_eq!(read_string(machine.memory_mut(), words[1]), "simple");
+    assert_eq!(words[2], 0);
+    assert_eq!(read_string(machine.memory_mut(), words[3]), "HOME=/");
+    assert_eq!(read_string(machine.memory_mut(), words[4]), "TERM=dumb");
+    assert_eq!(words[5], 0);
+
+    let auxv: Vec<(u64, u64)> = words[6..].chunks(2).map(|w| (w[0], w[1])).collect();
+    let entry = |key| auxv.iter().find(|(k, _)| *k == key).unwrap().1;
+    assert_eq!(auxv.last(), Some(&(auxv::AT_NULL, 0)));
+    assert_eq!(entry(auxv::AT_PAGESZ), 4096);
+    assert_eq!(entry(auxv::AT_ENTRY), *machine.pc());
+    assert_eq!(entry(auxv::AT_PHNUM), 2);
+    let random_addr = entry(auxv::AT_RANDOM);
+    assert_eq!(
+        machine.memory_mut().load_bytes(random_addr, 16).unwrap(),
+        random.to_vec()
+    );
+    // AT_PHDR points to the program headers as found in the file
+    let phdr = machine
+        .memory_mut()
+        .load_bytes(entry(auxv::AT_PHDR), 56)
+        .unwrap();
+    assert_eq!(phdr[..], buffer[64..120]);
+
+    assert_eq!(machine.run().unwrap(), 0);
+}
+
+#[test]
+pub fn test_simple_memory_cycles() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    // Stores cost twice as much as loads per byte
+    let memory_cycle_func = |op, size| match op {
+        MemoryOp::Store => size * 2,
+        _ => size,
+    };
+    let memory_cycles = Rc::new(RefCell::new(0));
+    let hook_cycles = Rc::clone(&memory_cycles);
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default()
+            .instruction_cycle_func(Box::new(dummy_cycle_func))
+            .on_instruction(Box::new(move |_, instruction, _| {
+                if let Some((op, size)) = memory_op(instruction) {
+                    *hook_cycles.borrow_mut() += memory_cycle_func(op, size);
+                }
+            }))
+            .build();
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    assert_eq!(machine.run(), Ok(0));
+    let instruction_cycles = SupportMachine::cycles(&machine);
+    assert!(*memory_cycles.borrow() > 0);
+
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default()
+            .instruction_cycle_func(Box::new(dummy_cycle_func))
+            .memory_cycle_func(Box::new(memory_cycle_func))
+            .build();
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    assert_eq!(machine.run(), Ok(0));
+    let cycles = SupportMachine::cycles(&machine);
+    assert_eq!(cycles, instruction_cycles + *memory_cycles.borrow());
+
+    let mut machine = TraceMachine::new(
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>>::default()
+            .instruction_cycle_func(Box::new(dummy_cycle_func))
+            .memory_cycle_func(Box::new(memory_cycle_func))
+            .build(),
+    );
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    assert_eq!(machine.run(), Ok(0));
+    assert_eq!(SupportMachine::cycles(&machine.machine), cycles);
+}
+
+#[test]
+pub fn test_simple_on_cycles() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let reports = Rc::new(RefCell::new(Vec::new()));
+    let hook_reports = Rc::clone(&reports);
+    let mut machine = TraceMachine::new(
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>>::default()
+            .instruction_cycle_func(Box::new(dummy_cycle_func))
+            .syscall_cycles(93, 500)
+            .on_cycles(Box::new(move |consumed, total| {
+                hook_reports.borrow_mut().push((consumed, total));
+            }))
+            .build(),
+    );
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    assert_eq!(machine.run(), Ok(0));
+    let reports = reports.borrow();
+    let cycles = SupportMachine::cycles(&machine.machine);
+    assert_eq!(reports.last(), Some(&(500, cycles)));
+    assert_eq!(
+        reports.iter().map(|(consumed, _)| consumed).sum::<u64>(),
+        cycles
+    );
+    let mut total = 0;
+    for (consumed, reported_total) in reports.iter() {
+        total += consumed;
+        assert_eq!(*reported_total, total);
+    }
+}
+
+#[test]
+pub fn test_simple_max_memory_used() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default().build();
+    machine.load_program(&buffer, &["simple".into()]).unwrap();
+    assert_eq!(machine.run(), Ok(0));
+    let used = machine.max_memory_used();
+    assert_eq!(used, machine.memory().touched_memory());
+
+    // Flat memory reports the same pages instead of its whole size
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, FlatMemory<u64>>>::default().build();
+    machine.load_program(&buffer, &["simple".into()]).unwrap();
+    assert_eq!(machine.run(), Ok(0));
+    assert_eq!(machine.max_memory_used(), used);
+    assert!(machine.max_memory_used() < machine.memory().memory_size());
+}
+
+#[test]
+pub fn test_simple_machine_view() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let mut machine = DefaultMachineBuilder::<DefaultCoreMachine<u64, FlatMemory<u64>>>::default()
+        .yield_every(5)
+        .build();
+    machine.load_program(&buffer, &["simple".into()]).unwrap();
+    assert_eq!(machine.run_resumable(), Ok(RunState::Yielded));
+    let used = machine.max_memory_used();
+    let view = machine.view(None).unwrap();
+    assert_eq!(view.pc(), *machine.pc());
+    assert_eq!(view.registers(), machine.registers());
+    assert_eq!(view.instructions(), 5);
+    assert_eq!(
+        view.load_bytes(0x10000, 0x84c).unwrap(),
+        buffer[..0x84c].to_vec()
+    );
+    let sp = machine.registers()[SP];
+    assert_eq!(view.load64(sp).unwrap(), 1);
+    // Inspecting memory is not counted as memory used by the program
+    assert_eq!(machine.max_memory_used(), used);
+
+    let inspector = {
+        let view = view.clone();
+        std::thread::spawn(move || (view.pc(), view.load_bytes(0x10000, 4).unwrap()))
+    };
+    assert_eq!(machine.run_resumable(), Ok(RunState::Yielded));
+    let (pc, code) = inspector.join().unwrap();
+    assert_eq!(pc, view.pc());
+    assert_eq!(code, buffer[..4].to_vec());
+
+    let data = view.load64(0x11900).unwrap();
+    machine.memory_mut().store64(&0x11900, &!data).unwrap();
+    let next = machine.view(Some(&view)).unwrap();
+    assert_eq!(next.instructions(), 10);
+    assert_eq!(next.pages(), view.pages());
+    // Only the modified page is copied again
+    assert_eq!(next.shared_pages(&view), next.pages() - 1);
+    assert_eq!(next.load64(0x11900).unwrap(), !data);
+    assert_eq!(view.load64(0x11900).unwrap(), data);
+    assert_eq!(view.instructions(), 5);
+    assert_eq!(
+        view.load_bytes(machine.memory().memory_size() as u64, 1),
+        Err(Error::OutOfBound)
+    );
+}
+
+#[test]
+pub fn test_simple_resource_report() {
+    let mut file = File::open("tests/programs/simple64").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    let buffer: Bytes = buffer.into();
+
+    let build = || {
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>>::default()
+            .instruction_cycle_func(Box::new(cycle_model::ckb2019))
+            .build()
+    };
+    let mut machine = build();
+    machine.load_program(&buffer, &["simple".into()]).unwrap();
+    assert_eq!(machine.run(), Ok(0));
+    let report = machine.resource_report();
+    assert_eq!(report.cycles, SupportMachine::cycles(&machine));
+    assert_eq!(report.instructions, machine.instructions());
+    assert_eq!(report.syscalls.get(&93), Some(&1));
+    assert_eq!(report.syscalls.len(), 1);
+    assert!(report.pages_touched > 0);
+
+    // Interpreters agree on the report
+    let mut machine = TraceMachine::new(build());
+    machine.load_program(&buffer, &["simple".into()]).unwrap();
+    assert_eq!(machine.run(), Ok(0));
+    assert_eq!(
+        machine.machine.resource_report().to_bytes(),
+        report.to_bytes()
+    );
+
+    machine.machine.reset().unwrap();
+    assert_eq!(machine.machine.resource_report().syscalls.len(), 0);
+}
+
+// Registers, pc, cycles, instructions and fault of program run by a trace
+// machine with fusion enabled or not
+fn run_trace_fusion(
+    program: &Bytes,
+    flat: bool,
+    fusion: bool,
+) -> (
+    Result<i8, Error>,
+    Vec<u64>,
+    u64,
+    u64,
+    u64,
+    Option<ckb_vm::Fault>,
+) {
+    let mut machine = TraceMachine::new(
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>>::default()
+            .instruction_cycle_func(Box::new(dummy_cycle_func))
+            .build(),
+    );
+    machine.set_fusion(fusion);
+    assert_eq!(machine.fusion(), fusion);
+    if flat {
+        machine
+            .machine
+            .load_flat_program(0x1000, program, 0x1000, &["fusion".into()])
+            .unwrap();
+    } else {
+        machine.load_program(program, &["fusion".into()]).unwrap();
+    }
+    let result = machine.run();
+    (
+        result,
+        machine.registers().to_vec(),
+        *machine.pc(),
+        machine.machine.cycles(),
+        machine.machine.instructions(),
+        machine.machine.last_fault(),
+    )
+}
+
+#[test]
+pub fn test_simple_trace_machine_fusion() {
+    for path in ["tests/programs/simple64", "tests/programs/trace64"].iter() {
+        let buffer: Bytes = std::fs::read(path).unwrap().into();
+        assert_eq!(
+            run_trace_fusion(&buffer, false, true),
+            run_trace_fusion(&buffer, false, false)
+        );
+    }
+
+    let flat = |words: &[u64]| -> Bytes {
+        words
+            .iter()
+            .flat_map(|word| {
+                if *word > u64::from(u32::MAX) {
+                    word.to_le_bytes().to_vec()
+                } else {
+                    (*word as u32).to_le_bytes().to_vec()
+                }
+            })
+            .collect::<Vec<u8>>()
+            .into()
+    };
+    // Every fused pair: lui+addi, slli+add and auipc+ld
+    let program = flat(&[
+        0x12345537,         // lui a0, 0x12345
+        0x67850513,         // addi a0, a0, 0x678
+        0x00361593,         // slli a1, a2, 3
+        0x00a585b3,         // add a1, a1, a0
+        0x00000297,         // auipc t0, 0
+        0x0182b283,         // ld t0, 24(t0)
+        0x00550533,         // add a0, a0, t0
+        0x05d00893,         // li a7, 93
+        0x00000073,         // ecall
+        0x00000013,         // nop
+        0x1122334455667788, // loaded by ld
+    ]);
+    let fused = run_trace_fusion(&program, true, true);
+    assert_eq!(fused, run_trace_fusion(&program, true, false));
+    assert_eq!(fused.0, Ok(0));
+    assert_eq!(fused.1[ckb_vm::registers::T0], 0x1122334455667788);
+    assert_eq!(fused.4, 9);
+
+    // The load of a fused auipc+ld fails on its own, after auipc completed
+    let program = flat(&[
+        0x01000297, // auipc t0, 0x1000
+        0x0002b283, // ld t0, 0(t0)
+        0x05d00893, // li a7, 93
+        0x00000073, // ecall
+    ]);
+    let fused = run_trace_fusion(&program, true, true);
+    assert_eq!(fused, run_trace_fusion(&program, true, false));
+    assert_eq!(fused.0, Err(Error::OutOfBound));
+    assert_eq!(fused.1[ckb_vm::registers::T0], 0x1001000);
+    assert_eq!(fused.2, 0x1004);
+    assert_eq!(fused.4, 2);
+    assert_eq!(fused.5.unwrap().pc, 0x1004);
 }
@@ -0,0 +1,217 @@
+extern crate ckb_vm;
+
+use bytes::Bytes;
+use ckb_vm::{
+    decoder::build_imac_decoder, CoreMachine, DefaultCoreMachine, DefaultMachine,
+    DefaultMachineBuilder, Error, Instruction, Snapshot, SparseMemory, SupportMachine,
+    TraceMachine, WXorXMemory, RISCV_PAGES,
+};
+use std::fs::File;
+use std::io::Read;
+
+fn dummy_cycle_func(_i: Instruction) -> u64 {
+    1
+}
+
+fn load_program(name: &str) -> Bytes {
+    let mut file = File::open(name).unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    buffer.into()
+}
+
+fn build_machine<'a>(
+) -> TraceMachine<'a, DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>> {
+    TraceMachine::new(
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, WXorXMemory<u64, SparseMemory<u64>>>>::default()
+            .instruction_cycle_func(Box::new(dummy_cycle_func))
+            .build(),
+    )
+}
+
+// Runs simple64 for a few trace items, then takes a snapshot of the
+// suspended machine.
+fn suspended_snapshot() -> Snapshot {
+    let buffer = load_program("tests/programs/simple64");
+    let mut machine = build_machine();
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    let decoder = build_imac_decoder::<u64>();
+    machine.machine.set_running(true);
+    for _ in 0..5 {
+        machine.step(&decoder).unwrap();
+    }
+    assert!(machine.machine.running());
+    machine.snapshot().unwrap()
+}
+
+#[test]
+pub fn test_snapshot_resume_in_fresh_machine() {
+    let snapshot = suspended_snapshot();
+    assert!(snapshot.cycles > 0 && snapshot.cycles < 517);
+    assert!(snapshot.pages.len() < RISCV_PAGES / 4);
+
+    let serialized = serde_json::to_string(&snapshot).unwrap();
+    let deserialized: Snapshot = serde_json::from_str(&serialized).unwrap();
+    assert_eq!(deserialized, snapshot);
+
+    let mut machine = build_machine();
+    machine.resume(&deserialized).unwrap();
+    assert_eq!(machine.machine.pc(), &snapshot.pc);
+    assert_eq!(SupportMachine::cycles(&machine.machine), snapshot.cycles);
+    let result = machine.run();
+    assert_eq!(result.unwrap(), 0);
+    assert_eq!(SupportMachine::cycles(&machine.machine), 517);
+}
+
+#[test]
+pub fn test_snapshot_resume_with_same_program() {
+    let snapshot = suspended_snapshot();
+    let buffer = load_program("tests/programs/simple64");
+    let mut machine = build_machine();
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    machine.resume(&snapshot).unwrap();
+    let result = machine.run();
+    assert_eq!(result.unwrap(), 0);
+    assert_eq!(SupportMachine::cycles(&machine.machine), 517);
+    assert_eq!(machine.snapshot().unwrap().exit_code, 0);
+}
+
+#[test]
+pub fn test_snapshot_resume_with_different_program() {
+    let snapshot = suspended_snapshot();
+    let buffer = load_program("tests/programs/mulw64");
+    let mut machine = build_machine();
+    machine
+        .load_program(&buffer, &vec!["mulw64".into()])
+        .unwrap();
+    let pc = *machine.machine.pc();
+    let result = machine.resume(&snapshot);
+    assert_eq!(result.unwrap_err(), Error::InvalidSnapshot);
+    assert_eq!(*machine.machine.pc(), pc);
+    assert_eq!(machine.run().unwrap(), 0);
+}
+
+#[test]
+pub fn test_snapshot_resume_with_different_bits() {
+    let snapshot = suspended_snapshot();
+    let mut machine = DefaultMachine::<DefaultCoreMachine<u32, SparseMemory<u32>>>::default();
+    let result = machine.resume(&snapshot);
+    assert_eq!(result.unwrap_err(), Error::InvalidSnapshot);
+}
+
+#[test]
+pub fn test_snapshot_resume_after_cycles_exceeded() {
+    let buffer = load_program("tests/programs/simple64");
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default()
+            .instruction_cycle_func(Box::new(dummy_cycle_func))
+            .max_cycles(100)
+            .build();
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    assert_eq!(machine.run().unwrap_err(), Error::CyclesExceeded);
+    let snapshot = machine.snapshot().unwrap();
+    assert_eq!(snapshot.cycles, 100);
+
+    let serialized = serde_json::to_string(&snapshot).unwrap();
+    let deserialized: Snapshot = serde_json::from_str(&serialized).unwrap();
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::default()
+            .instruction_cycle_func(Box::new(dummy_cycle_func))
+            .max_cycles(1000)
+            .build();
+    machine.resume(&deserialized).unwrap();
+    assert_eq!(machine.run().unwrap(), 0);
+    assert_eq!(SupportMachine::cycles(&machine), 517);
+}
+
+#[test]
+pub fn test_trace_snapshot_resume_after_cycles_exceeded() {
+    let buffer = load_program("tests/programs/simple64");
+    let mut machine = build_machine();
+    machine.machine.set_max_cycles(100);
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    assert_eq!(machine.run().unwrap_err(), Error::CyclesExceeded);
+    let snapshot = machine.snapshot().unwrap();
+    assert_eq!(snapshot.cycles, 100);
+
+    // Resumes several times with a small budget each time
+    let mut snapshot = snapshot;
+    loop {
+        let mut machine = build_machine();
+        machine.resume(&snapshot).unwrap();
+        machine.machine.set_max_cycles(snapshot.cycles + 100);
+        match machine.run() {
+            Ok(exit_code) => {
+                assert_eq!(exit_code, 0);
+                assert_eq!(SupportMachine::cycles(&machine.machine), 517);
+                break;
+            }
+            Err(Error::CyclesExceeded) => snapshot = machine.snapshot().unwrap(),
+            Err(e) => panic!("unexpected error: {}", e),
+        }
+    }
+}
+
+#[cfg(feature = "fd")]
+#[test]
+pub fn test_snapshot_keeps_float_registers() {
+    use ckb_vm::Machine;
+
+    let mut machine = build_machine();
+    {
+        let registers = machine.float_registers_mut().unwrap();
+        registers.set_register(10, 0x4009_21FB_5444_2D18);
+        registers.set_fcsr(0x21);
+    }
+    let snapshot = machine.snapshot().unwrap();
+    let serialized = serde_json::to_string(&snapshot).unwrap();
+    let deserialized: Snapshot = serde_json::from_str(&serialized).unwrap();
+
+    let mut machine = build_machine();
+    machine.resume(&deserialized).unwrap();
+    let registers = machine.machine.float_registers();
+    assert_eq!(registers.registers()[10], 0x4009_21FB_5444_2D18);
+    assert_eq!(registers.fcsr(), 0x21);
+}
+
+#[cfg(feature = "serialize")]
+#[test]
+pub fn test_core_machine_serde() {
+    use ckb_vm::{registers::A0, CoreMachineState, Memory};
+
+    let mut machine = DefaultCoreMachine::<u64, SparseMemory<u64>>::new_with_max_cycles(1000);
+    machine.set_pc(0x1000);
+    machine.set_register(A0, 42);
+    machine.add_cycles(7).unwrap();
+    machine.memory_mut().store_bytes(0x2000, b"state").unwrap();
+
+    let state = machine.state().unwrap();
+    assert_eq!(state.pages.len(), 1);
+    let serialized = serde_json::to_string(&state).unwrap();
+    let mut restored: DefaultCoreMachine<u64, SparseMemory<u64>> =
+        serde_json::from_str(&serialized).unwrap();
+    assert_eq!(*restored.pc(), 0x1000);
+    assert_eq!(restored.registers()[A0], 42);
+    assert_eq!(restored.cycles(), 7);
+    assert_eq!(restored.max_cycles(), Some(1000));
+    assert_eq!(
+        restored.memory_mut().load_bytes(0x2000, 5).unwrap(),
+        b"state"
+    );
+    assert_eq!(restored.state().unwrap(), state);
+
+    // States of a different register width are rejected
+    let state: CoreMachineState = serde_json::from_str(&serialized).unwrap();
+    assert_eq!(
+        DefaultCoreMachine::<u32, SparseMemory<u32>>::from_state(&state).err(),
+        Some(Error::InvalidSnapshot)
+    );
+}
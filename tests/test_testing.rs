@@ -0,0 +1,142 @@
+#![cfg(feature = "testing")]
+extern crate ckb_vm;
+
+use bytes::Bytes;
+use ckb_vm::{
+    testing::{run_flat_vs_sparse, run_lockstep, DEFAULT_MAX_INSTRUCTIONS},
+    Error, Memory, SparseMemory,
+};
+use std::fs::File;
+use std::io::Read;
+
+fn load(name: &str) -> Bytes {
+    let mut file = File::open(format!("tests/programs/{}", name)).unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    buffer.into()
+}
+
+#[test]
+pub fn test_flat_vs_sparse() {
+    let args = ["program".into()];
+    assert_eq!(
+        run_flat_vs_sparse::<u64>(&load("simple64"), &args, DEFAULT_MAX_INSTRUCTIONS),
+        Ok(Ok(0))
+    );
+    assert_eq!(
+        run_flat_vs_sparse::<u64>(&load("mulw64"), &args, DEFAULT_MAX_INSTRUCTIONS),
+        Ok(Ok(0))
+    );
+    assert_eq!(
+        run_flat_vs_sparse::<u32>(&load("simple"), &args, DEFAULT_MAX_INSTRUCTIONS),
+        Ok(Ok(0))
+    );
+    // Both fail the same way
+    assert_eq!(
+        run_flat_vs_sparse::<u64>(&load("invalid_read64"), &args, DEFAULT_MAX_INSTRUCTIONS),
+        Ok(Err(Error::OutOfBound))
+    );
+    assert_eq!(
+        run_flat_vs_sparse::<u64>(&load("simple64"), &args, 100),
+        Ok(Err(Error::InstructionLimitExceeded))
+    );
+}
+
+// Sparse memory returning the wrong doubleword from one address
+#[derive(Default)]
+struct SkewedMemory {
+    inner: SparseMemory<u64>,
+    addr: u64,
+}
+
+impl Memory<u64> for SkewedMemory {
+    fn init_pages(
+        &mut self,
+        addr: u64,
+        size: u64,
+        flags: u8,
+        source: Option<Bytes>,
+        offset_from_addr: u64,
+    ) -> Result<(), Error> {
+        self.inner
+            .init_pages(addr, size, flags, source, offset_from_addr)
+    }
+
+    fn fetch_flag(&mut self, page: u64) -> Result<u8, Error> {
+        self.inner.fetch_flag(page)
+    }
+
+    fn store_byte(&mut self, addr: u64, size: u64, value: u8) -> Result<(), Error> {
+        self.inner.store_byte(addr, size, value)
+    }
+
+    fn store_bytes(&mut self, addr: u64, value: &[u8]) -> Result<(), Error> {
+        self.inner.store_bytes(addr, value)
+    }
+
+    fn execute_load16(&mut self, addr: u64) -> Result<u16, Error> {
+        self.inner.execute_load16(addr)
+    }
+
+    fn load8(&mut self, addr: &u64) -> Result<u64, Error> {
+        self.inner.load8(addr)
+    }
+
+    fn load16(&mut self, addr: &u64) -> Result<u64, Error> {
+        self.inner.load16(addr)
+    }
+
+    fn load32(&mut self, addr: &u64) -> Result<u64, Error> {
+        self.inner.load32(addr)
+    }
+
+    fn load64(&mut self, addr: &u64) -> Result<u64, Error> {
+        let value = self.inner.load64(addr)?;
+        // The first doubleword loaded is argc at the top of the stack
+        if self.addr == 0 {
+            self.addr = *addr;
+        }
+        if *addr == self.addr {
+            Ok(value ^ 1)
+        } else {
+            Ok(value)
+        }
+    }
+
+    fn store8(&mut self, addr: &u64, value: &u64) -> Result<(), Error> {
+        self.inner.store8(addr, value)
+    }
+
+    fn store16(&mut self, addr: &u64, value: &u64) -> Result<(), Error> {
+        self.inner.store16(addr, value)
+    }
+
+    fn store32(&mut self, addr: &u64, value: &u64) -> Result<(), Error> {
+        self.inner.store32(addr, value)
+    }
+
+    fn store64(&mut self, addr: &u64, value: &u64) -> Result<(), Error> {
+        self.inner.store64(addr, value)
+    }
+}
+
+#[test]
+pub fn test_lockstep_divergence() {
+    let divergence = run_lockstep::<u64, SparseMemory<u64>, SkewedMemory>(
+        &load("simple64"),
+        &["simple".into()],
+        DEFAULT_MAX_INSTRUCTIONS,
+    )
+    .unwrap_err();
+    let differing: Vec<usize> = (0..divergence.left.registers.len())
+        .filter(|i| divergence.left.registers[*i] != divergence.right.registers[*i])
+        .collect();
+    assert_eq!(differing.len(), 1);
+    let i = differing[0];
+    assert_eq!(
+        divergence.left.registers[i] ^ divergence.right.registers[i],
+        1
+    );
+    assert!(divergence.instructions > 0);
+    assert_eq!(divergence.left.error, None);
+}